@@ -1119,6 +1119,8 @@ GDK_API int GA_validate_mnemonic(const char* mnemonic, uint32_t* valid);
  * If the provided JSON is empty, any existing configuration for
  * the network is removed.
  *
+ * The configuration is checked for consistency before being registered,
+ * see :ref:`network`.
  */
 GDK_API int GA_register_network(const char* name, const GA_json* network_details);
 
@@ -1098,6 +1098,7 @@ namespace green {
 
         // Create/update the transaction
         create_transaction(*m_session, m_result);
+        m_session->check_spend_limits(m_result);
 
         if (!m_net_params.is_liquid()) {
             return state_type::done; // Nothing to do for non-Liquid
@@ -1288,9 +1289,10 @@ namespace green {
     {
         if (m_net_params.is_electrum()) {
             // FIXME: Move rust to ga_session interface
-            auto txs = m_session->get_transactions(m_details);
-            m_session->postprocess_transactions(txs);
-            m_result = { { "transactions", std::move(txs) } };
+            // Rust returns the transactions along with the total and sync_generation
+            auto result = m_session->get_transactions(m_details);
+            m_session->postprocess_transactions(result.at("transactions"));
+            m_result = std::move(result);
             return state_type::done;
         }
 
@@ -420,7 +420,7 @@ namespace green {
         const bool use_discounted_fees = m_net_params.use_discounted_fees();
         auto ret = rust_call("get_transactions", details, m_session);
         // TODO: remove this logic once the discount is enabled in all envs
-        for (auto& tx : ret) {
+        for (auto& tx : ret.at("transactions")) {
             // Convert weight/fee rate according to whether the discount is enabled
             const auto weight_key = use_discounted_fees ? "discount_weight" : "transaction_weight";
             const auto tx_weight = j_uint32ref(tx, weight_key);
@@ -603,6 +603,14 @@ namespace green {
         }
     }
 
+    nlohmann::json ga_rust::get_transaction_details(const std::string& txhash_hex) const
+    {
+        auto ret = rust_call("get_transaction_details", nlohmann::json(txhash_hex), m_session);
+        const Tx tx(j_strref(ret, "transaction"), m_net_params.is_liquid());
+        update_tx_size_info(m_net_params, tx, ret);
+        return ret;
+    }
+
     nlohmann::json ga_rust::get_scriptpubkey_data(byte_span_t scriptpubkey)
     {
         try {
@@ -614,19 +622,43 @@ namespace green {
 
     nlohmann::json ga_rust::send_transaction(const nlohmann::json& details, const nlohmann::json& /*twofactor_data*/)
     {
-        return broadcast_transaction(details);
+        nlohmann::json send_details = { { "transaction", j_strref(details, "transaction") } };
+        if (auto token = j_str_or_empty(details, "confirmation_token"); !token.empty()) {
+            send_details["confirmation_token"] = std::move(token);
+        }
+        if (auto payjoin = details.find("payjoin"); payjoin != details.end() && !payjoin->is_null()) {
+            send_details["payjoin"] = *payjoin;
+        }
+        if (auto id = j_str_or_empty(details, "client_request_id"); !id.empty()) {
+            send_details["client_request_id"] = std::move(id);
+        }
+        if (j_bool_or_false(details, "check_acceptance")) {
+            send_details["check_acceptance"] = true;
+        }
+        return broadcast_transaction(details, "send_transaction", send_details);
     }
 
     nlohmann::json ga_rust::broadcast_transaction(const nlohmann::json& details)
+    {
+        return broadcast_transaction(details, "broadcast_transaction", j_strref(details, "transaction"));
+    }
+
+    nlohmann::json ga_rust::broadcast_transaction(
+        const nlohmann::json& details, const std::string& method, const nlohmann::json& input)
     {
         try {
-            const auto& tx_hex = j_strref(details, "transaction");
-            const auto ret = rust_call("broadcast_transaction", tx_hex, m_session);
-            auto txhash_hex = ret.get<std::string>();
+            const auto ret = rust_call(method, input, m_session);
+            auto result = details;
+            if (ret.is_object()) {
+                // send_transaction reports whether a payjoin was broadcast instead
+                result["payjoin"] = ret.at("payjoin");
+                result["payjoin_error"] = ret.at("payjoin_error");
+                result["broadcast"] = ret.at("broadcast");
+            }
+            auto txhash_hex = ret.is_object() ? j_strref(ret, "txhash") : ret.get<std::string>();
             if (auto memo = j_str_or_empty(details, "memo"); !memo.empty()) {
                 set_transaction_memo(txhash_hex, memo);
             }
-            auto result = details;
             result["txhash"] = std::move(txhash_hex);
             return result;
         } catch (const std::exception& e) {
@@ -639,6 +671,16 @@ namespace green {
         }
     }
 
+    void ga_rust::check_spend_limits(nlohmann::json& details)
+    {
+        const auto tx_hex = j_str_or_empty(details, "transaction");
+        if (tx_hex.empty() || !j_str_or_empty(details, "error").empty()) {
+            return;
+        }
+        const auto ret = rust_call("check_spend_limits", tx_hex, m_session);
+        details["requires_confirmation"] = j_boolref(ret, "requires_confirmation");
+    }
+
     void ga_rust::send_nlocktimes() { throw std::runtime_error("send_nlocktimes not implemented"); }
 
     void ga_rust::set_csvtime(const nlohmann::json& locktime_details, const nlohmann::json& twofactor_data)
@@ -90,10 +90,12 @@ namespace green {
         nlohmann::json get_unspent_outputs(const nlohmann::json& details, unique_pubkeys_and_scripts_t& missing);
         nlohmann::json set_unspent_outputs_status(const nlohmann::json& details, const nlohmann::json& twofactor_data);
         Tx get_raw_transaction_details(const std::string& txhash_hex) const;
+        nlohmann::json get_transaction_details(const std::string& txhash_hex) const;
 
         nlohmann::json get_scriptpubkey_data(byte_span_t scriptpubkey);
         nlohmann::json send_transaction(const nlohmann::json& details, const nlohmann::json& twofactor_data);
         nlohmann::json broadcast_transaction(const nlohmann::json& details);
+        void check_spend_limits(nlohmann::json& details);
 
         void send_nlocktimes();
         void set_csvtime(const nlohmann::json& locktime_details, const nlohmann::json& twofactor_data);
@@ -132,6 +134,8 @@ namespace green {
         void on_post_login();
 
         nlohmann::json get_local_subaccounts_data();
+        nlohmann::json broadcast_transaction(
+            const nlohmann::json& details, const std::string& method, const nlohmann::json& input);
 
         void* m_session;
     };
@@ -2129,6 +2129,7 @@ namespace green {
         const std::string id_transaction_id{ "id_transaction_id" }; // Transaction ID
         const std::string id_transaction_sent{ "id_transaction_sent" }; // Transaction Sent
         const std::string id_transaction_status{ "id_transaction_status" }; // Transaction status
+        const std::string id_transaction_too_large{ "id_transaction_too_large" }; // Transaction too large
         const std::string id_transactions{ "id_transactions" }; // Transactions
         const std::string id_troubleshoot{ "id_troubleshoot" }; // Troubleshoot
         const std::string id_try_again{ "id_try_again" }; // Try Again
@@ -1223,6 +1223,7 @@ namespace green {
         extern const std::string id_transaction_id;
         extern const std::string id_transaction_sent;
         extern const std::string id_transaction_status;
+        extern const std::string id_transaction_too_large;
         extern const std::string id_transactions;
         extern const std::string id_troubleshoot;
         extern const std::string id_try_again;
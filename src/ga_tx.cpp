@@ -550,16 +550,96 @@ namespace green {
             }
         }
 
+        // Fail if the transaction weighs more than nodes relay, telling how
+        // many inputs are needed to pay the addressee and how many fit
+        static void check_tx_weight(const network_parameters& net_params, const Tx& tx, nlohmann::json& result,
+            const nlohmann::json& utxos, size_t next_utxo, const addressee_details_t& addressee,
+            const amount& fee_rate, size_t input_weight)
+        {
+            const size_t max_weight = net_params.get_max_standard_tx_weight();
+            size_t weight = tx.get_adjusted_weight(net_params);
+            if (weight <= max_weight) {
+                return;
+            }
+            const size_t num_inputs = tx.get_num_inputs();
+            if (!input_weight) {
+                input_weight = weight / num_inputs;
+            }
+            // Count the inputs needed as if the transaction had no maximum
+            size_t count = num_inputs;
+            auto utxo_sum = addressee.utxo_sum;
+            auto covered = [&] {
+                const amount fee{ fee_rate.value() * Tx::vsize_from_weight(weight) / 1000 };
+                return utxo_sum >= addressee.required_total + fee;
+            };
+            const bool is_greedy = addressee.greedy_index.has_value();
+            for (size_t i = next_utxo; i < utxos.size() && (is_greedy || !covered()); ++i) {
+                utxo_sum += j_amountref(utxos.at(i));
+                weight += input_weight;
+                ++count;
+            }
+            if (!covered()) {
+                throw user_error(res::id_insufficient_funds);
+            }
+            const size_t tx_weight = tx.get_adjusted_weight(net_params);
+            const size_t inputs_weight = std::min(tx_weight, num_inputs * input_weight);
+            const size_t base_weight = tx_weight - inputs_weight;
+            const size_t max = max_weight > base_weight ? (max_weight - base_weight) / input_weight : 0;
+            result["inputs_needed"] = count;
+            result["max_inputs"] = max;
+            GDK_LOG(info) << count << " inputs are needed, at most " << max << " fit in a standard transaction";
+            throw user_error(res::id_transaction_too_large);
+        }
+
         static void pick_policy_asset_utxos(session_impl& session, Tx& tx, nlohmann::json& result,
             nlohmann::json& utxos, addressee_details_t& addressee, const amount& fee_rate, bool manual_selection)
         {
             const auto& net_params = session.get_network_parameters();
             const auto policy_asset = net_params.get_policy_asset();
             const amount dust_threshold = session.get_dust_threshold(policy_asset);
+            // Without a floor, only change above the dust threshold is created
+            const amount change_floor{ net_params.get_min_change_satoshi() };
+            const bool has_floor = change_floor.value() != 0;
+            const amount min_change = has_floor ? std::max(change_floor, dust_threshold) : dust_threshold + 1;
+            const size_t max_weight = net_params.get_max_standard_tx_weight();
+            // On Liquid the change can't be added to the fee, it absorbs the blinding factors
+            const bool can_fold = !net_params.is_liquid();
             const auto network_fee = j_amount_or_zero(result, "network_fee");
             const ssize_t num_utxos = manual_selection ? 0 : utxos.size();
             const bool is_greedy = addressee.greedy_index.has_value();
             bool added_change = false;
+            bool raising_change = false;
+            size_t input_weight = 0;
+            result.erase("inputs_needed");
+            result.erase("max_inputs");
+            result.erase("change_decision");
+            if (manual_selection) {
+                // The inputs given are all in the transaction already
+                check_tx_weight(net_params, tx, result, utxos, utxos.size(), addressee, fee_rate, 0);
+            }
+
+            // The fee paid adding the inputs from `next` until `change` is
+            // above the floor, if there are enough of them
+            auto&& raised_fee = [&](ssize_t next, amount change) -> std::optional<amount> {
+                if (!input_weight) {
+                    return std::nullopt;
+                }
+                const amount input_fee{ fee_rate.value() * Tx::vsize_from_weight(input_weight) / 1000 };
+                auto fee = addressee.fee;
+                auto weight = tx.get_adjusted_weight(net_params);
+                for (ssize_t j = next; j < num_utxos; ++j) {
+                    weight += input_weight;
+                    if (weight > max_weight) {
+                        break;
+                    }
+                    fee += input_fee;
+                    change += j_amountref(utxos[j]);
+                    if (change >= min_change + (fee - addressee.fee)) {
+                        return fee;
+                    }
+                }
+                return std::nullopt;
+            };
 
             for (ssize_t i = 0; i <= num_utxos; ++i) {
                 const bool no_more_utxos = i == num_utxos;
@@ -572,6 +652,7 @@ namespace green {
                 if ((!is_greedy && addressee.utxo_sum >= required_total) || (is_greedy && no_more_utxos)) {
                     // We have enough to cover the amount to send plus any fee
                     amount::value_type change_amount = 0;
+                    std::string change_decision = "no_change";
                     if (addressee.utxo_sum >= required_total) {
                         change_amount = (addressee.utxo_sum - required_total).value();
                         if (change_amount) {
@@ -590,9 +671,39 @@ namespace green {
                                     --i;
                                     continue; // Loop again to include the change output
                                 }
-                                if (change_amount <= dust_threshold) {
+                                if (change_amount >= min_change) {
+                                    change_decision = raising_change ? "added_input" : "kept";
+                                } else if (raising_change && !no_more_utxos) {
+                                    goto add_more_utxos; // Still below the floor
+                                } else if (!has_floor) {
                                     have_dusty_change = true;
                                     goto add_more_utxos;
+                                } else {
+                                    // The change costs more to spend than it's worth: add inputs to
+                                    // raise it above the floor or add it to the fee, whichever pays
+                                    // the lower fee. On a tie adding inputs keeps it in the wallet
+                                    const auto fold_fee = addressee.utxo_sum - addressee.required_total;
+                                    std::optional<amount> fee;
+                                    if (!raising_change) {
+                                        fee = raised_fee(i, amount(change_amount));
+                                    }
+                                    if (fee && (!can_fold || *fee <= fold_fee)) {
+                                        raising_change = true;
+                                        goto add_more_utxos;
+                                    }
+                                    if (can_fold) {
+                                        // Remove the change output, added last
+                                        tx.remove_output(tx.get_num_outputs() - 1);
+                                        result["change_address"][addressee.asset_id]["satoshi"] = 0u;
+                                        addressee.fee = fold_fee;
+                                        change_amount = 0;
+                                        change_decision = "folded_into_fee";
+                                    } else if (change_amount <= dust_threshold) {
+                                        have_dusty_change = true;
+                                        goto add_more_utxos;
+                                    } else {
+                                        change_decision = "below_floor";
+                                    }
                                 }
                             }
                         }
@@ -600,6 +711,7 @@ namespace green {
                         throw user_error(res::id_insufficient_funds); // Cant cover fee
                     }
                     result["change_amount"][addressee.asset_id] = change_amount;
+                    result["change_decision"] = change_decision;
                     result["fee"] = addressee.fee.value();
                     result["network_fee"] = network_fee.value();
                     return;
@@ -618,7 +730,10 @@ namespace green {
                 }
                 // Add the next input
                 addressee.utxo_indices.push_back(i);
+                const auto weight_before = tx.get_adjusted_weight(net_params);
                 addressee.utxo_sum += add_tx_input(session, result, tx, utxos[i], true);
+                input_weight = tx.get_adjusted_weight(net_params) - weight_before;
+                check_tx_weight(net_params, tx, result, utxos, i + 1, addressee, fee_rate, input_weight);
             }
         }
 
@@ -855,6 +970,92 @@ namespace green {
             }
         }
 
+        static void validate_transaction_impl(session_impl& session, nlohmann::json& result)
+        {
+            // Check the request as create_transaction_impl does, without
+            // building the transaction, collecting every error found instead
+            // of stopping at the first one
+            const auto& net_params = session.get_network_parameters();
+            const bool is_liquid = net_params.is_liquid();
+            std::vector<std::string> errors;
+            std::vector<std::string> addressee_errors;
+
+            auto fee_rate = j_amount(result, "fee_rate");
+            if (!fee_rate) {
+                fee_rate = session.get_default_fee_rate();
+                result["fee_rate"] = fee_rate.value().value();
+            }
+            if (fee_rate < session.get_min_fee_rate()) {
+                errors.emplace_back(res::id_fee_rate_is_below_minimum);
+            }
+
+            std::map<std::string, amount> required_totals;
+            std::set<std::string> greedy_assets;
+            std::set<std::string> spks;
+            auto addressees_p = result.find("addressees");
+            if (addressees_p == result.end() || addressees_p->empty()) {
+                errors.emplace_back(res::id_no_recipients);
+            } else {
+                for (auto& addressee : *addressees_p) {
+                    auto error = validate_tx_addressee(session, net_params, addressee);
+                    if (error.empty()) {
+                        const auto asset_id = j_assetref(is_liquid, addressee);
+                        if (j_bool_or_false(addressee, "is_greedy")) {
+                            if (!greedy_assets.insert(asset_id).second) {
+                                error = "only one output per asset type can be greedy";
+                            }
+                        } else if (j_amountref(addressee) < session.get_dust_threshold(asset_id)) {
+                            error = res::id_amount_below_the_dust_threshold;
+                        } else {
+                            required_totals[asset_id] += j_amountref(addressee);
+                        }
+                        if (error.empty() && !spks.insert(j_strref(addressee, "scriptpubkey")).second) {
+                            error = "multiple outputs share the same address";
+                        }
+                    }
+                    addressee_errors.emplace_back(std::move(error));
+                }
+            }
+
+            // Check the wallet can fund the addressees, fees aside
+            std::map<std::string, amount> available;
+            if (j_str_or_empty(result, "utxo_strategy") == UTXO_SEL_MANUAL) {
+                const auto tx_inputs = j_array(result, "transaction_inputs").value_or(json_array_t());
+                if (tx_inputs.empty()) {
+                    errors.emplace_back(res::id_no_utxos_found);
+                }
+                for (const auto& utxo : tx_inputs) {
+                    available[j_assetref(is_liquid, utxo)] += j_amountref(utxo);
+                }
+            } else if (auto p = result.find("utxos"); p != result.end() && p->is_object()) {
+                for (const auto& it : p->items()) {
+                    if (it.value().is_array()) {
+                        for (const auto& utxo : it.value()) {
+                            available[it.key()] += j_amountref(utxo);
+                        }
+                    }
+                }
+            }
+            for (const auto& [asset_id, required_total] : required_totals) {
+                if (available[asset_id] < required_total) {
+                    errors.emplace_back(res::id_insufficient_funds);
+                    break;
+                }
+            }
+
+            // Report the first error as create_transaction would
+            for (const auto& error : errors) {
+                set_tx_error(result, error);
+            }
+            for (const auto& error : addressee_errors) {
+                if (!error.empty()) {
+                    set_tx_error(result, error);
+                }
+            }
+            result["errors"] = std::move(errors);
+            result["addressee_errors"] = std::move(addressee_errors);
+        }
+
         static void validate_sighash_flags(uint32_t sighash_flags, bool is_p2tr, bool is_liquid)
         {
             if (sighash_flags == WALLY_SIGHASH_ALL || (is_p2tr && sighash_flags == WALLY_SIGHASH_DEFAULT)) {
@@ -1100,6 +1301,8 @@ namespace green {
         GDK_VERIFY(wally_tx_add_raw_output(m_tx.get(), satoshi, script.data(), script.size(), flags));
     }
 
+    void Tx::remove_output(size_t index) { GDK_VERIFY(wally_tx_remove_output(m_tx.get(), index)); }
+
     void Tx::add_elements_output_at(size_t index, byte_span_t script, byte_span_t asset, byte_span_t value,
         byte_span_t nonce, byte_span_t surjectionproof, byte_span_t rangeproof)
     {
@@ -1437,7 +1640,14 @@ namespace green {
             // before returning any error to allow the caller to make iterative changes
             // fixing each error
             details["error"] = std::string(); // Clear any existing error
-            create_transaction_impl(session, details);
+            if (j_bool_or_false(details, "validate")) {
+                validate_transaction_impl(session, details);
+            } else {
+                // Drop the results of any previous validation
+                details.erase("errors");
+                details.erase("addressee_errors");
+                create_transaction_impl(session, details);
+            }
         } catch (const std::exception& e) {
             set_tx_error(details, e.what());
         }
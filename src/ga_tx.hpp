@@ -60,6 +60,7 @@ namespace green {
         auto get_outputs() const { return gsl::make_span(m_tx->outputs, m_tx->num_outputs); }
 
         void add_output(uint64_t satoshi, byte_span_t script);
+        void remove_output(size_t index);
         void add_elements_output_at(size_t index, byte_span_t script, byte_span_t asset, byte_span_t value,
             byte_span_t nonce, byte_span_t surjectionproof, byte_span_t rangeproof);
 
@@ -1,8 +1,10 @@
 #include <boost/algorithm/string/predicate.hpp>
 #include <mutex>
+#include <set>
 
 #include "assertion.hpp"
 #include "exception.hpp"
+#include "ga_wally.hpp"
 #include "json_utils.hpp"
 #include "network_parameters.hpp"
 #include "session.hpp" // TODO: gdk_config() doesn't belong in session
@@ -654,6 +656,7 @@ static std::map<std::string, std::shared_ptr<nlohmann::json>> registered_network
             { "server_type", "electrum" },
             { "service_chain_code", std::string() },
             { "service_pubkey", std::string() },
+            { "signet", true },
             { "spv_multi", false },
             { "spv_servers", nlohmann::json::array() },
             { "spv_enabled", false },
@@ -755,6 +758,16 @@ static std::map<std::string, std::shared_ptr<nlohmann::json>> registered_network
     // clang-format on
 
     static std::mutex registered_networks_mutex;
+
+    // The networks built into GDK, the state of registered networks is kept
+    // in a directory named after them
+    static const std::set<std::string> builtin_networks = [] {
+        std::set<std::string> names;
+        for (const auto& p : registered_networks) {
+            names.insert(p.first);
+        }
+        return names;
+    }();
 } // namespace
 
 namespace green {
@@ -809,7 +822,20 @@ namespace green {
             set_override(defaults, "tx_explorer_url", user_overrides, empty);
             set_override(defaults, "tx_explorer_onion_url", user_overrides, empty);
 
-            defaults["state_dir"] = gdk_config().value("datadir", empty) + "/state";
+            std::string state_dir = gdk_config().value("datadir", empty) + "/state";
+            if (const auto network = defaults.value("network", empty); !builtin_networks.count(network)) {
+                state_dir += "/" + network;
+            }
+            defaults["state_dir"] = std::move(state_dir);
+
+            if (auto min_change = j_uint32(user_overrides, "min_change_satoshi"); min_change) {
+                defaults["min_change_satoshi"] = *min_change;
+            }
+            // Development networks can lower the weight of the transactions relayed
+            auto max_weight = j_uint32(user_overrides, "max_standard_tx_weight");
+            if (max_weight && j_bool_or_false(defaults, "development")) {
+                defaults["max_standard_tx_weight"] = *max_weight;
+            }
 
             // Handle min fee rate specifically; it is null by default
             auto fee_rate = j_uint32(user_overrides, "min_fee_rate");
@@ -820,6 +846,43 @@ namespace green {
             }
             return defaults;
         }
+
+        static void validate_network(const std::string& name, nlohmann::json& details)
+        {
+            if (details.value("network", name) != name) {
+                throw user_error("Network name does not match");
+            }
+            details["network"] = name;
+            const auto server_type = j_str_or_empty(details, "server_type");
+            if (server_type != "green" && server_type != "electrum") {
+                throw user_error("Invalid server type");
+            }
+            if (j_bool_or_false(details, "mainnet") && j_bool_or_false(details, "development")) {
+                throw user_error("A network cannot be both mainnet and development");
+            }
+            if (j_bool_or_false(details, "signet")
+                && (j_bool_or_false(details, "mainnet") || j_bool_or_false(details, "liquid"))) {
+                throw user_error("A signet network cannot be mainnet or liquid");
+            }
+            if (j_str_is_empty(details, "bech32_prefix")) {
+                throw user_error("Missing bech32 prefix");
+            }
+            if (j_bool_or_false(details, "liquid")) {
+                if (!validate_hex(j_str_or_empty(details, "policy_asset"), 32)) {
+                    throw user_error("Invalid policy asset");
+                }
+                if (j_str_is_empty(details, "blech32_prefix")) {
+                    throw user_error("Missing blech32 prefix");
+                }
+            }
+            if (server_type == "electrum" && j_str_is_empty(details, "electrum_url")) {
+                throw user_error("Missing electrum url");
+            }
+            if (const auto genesis = j_str_or_empty(details, "genesis_hash");
+                !genesis.empty() && !validate_hex(genesis, 32)) {
+                throw user_error("Invalid genesis hash");
+            }
+        }
     } // namespace
 
     network_parameters::network_parameters(const nlohmann::json& details)
@@ -847,7 +910,9 @@ namespace green {
             }
         } else {
             // Validate and add, overwriting any existing entry
-            auto np = std::make_shared<nlohmann::json>(network_parameters(details).get_json());
+            auto network_details = details;
+            validate_network(name, network_details);
+            auto np = std::make_shared<nlohmann::json>(network_parameters(network_details).get_json());
             registered_networks[name] = np;
         }
     }
@@ -989,6 +1054,17 @@ namespace green {
     // testing while being unnaffected by normal chain operation.
     uint32_t network_parameters::get_max_reorg_blocks() const { return m_details.at("max_reorg_blocks"); }
     std::optional<uint32_t> network_parameters::get_min_fee_rate() const { return j_uint32(m_details, "min_fee_rate"); }
+    // The weight above which nodes do not relay transactions, overridable by development networks
+    uint32_t network_parameters::get_max_standard_tx_weight() const
+    {
+        return j_uint32(m_details, "max_standard_tx_weight").value_or(400000);
+    }
+    // The change below which created transactions avoid a change output, 3 times the p2wpkh dust limit by
+    // default for singlesig, none by default for multisig
+    uint32_t network_parameters::get_min_change_satoshi() const
+    {
+        return j_uint32(m_details, "min_change_satoshi").value_or(is_electrum() ? 3 * 294 : 0);
+    }
     std::string network_parameters::get_price_url() const
     {
         return get_url(m_details, "price_url", "price_onion_url", use_tor());
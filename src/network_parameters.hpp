@@ -71,6 +71,8 @@ namespace green {
         uint32_t cert_expiry_threshold() const;
         uint32_t get_max_reorg_blocks() const;
         std::optional<uint32_t> get_min_fee_rate() const;
+        uint32_t get_max_standard_tx_weight() const;
+        uint32_t get_min_change_satoshi() const;
         std::string get_price_url() const;
 
     private:
@@ -818,6 +818,11 @@ namespace green {
         return ret;
     }
 
+    void session_impl::check_spend_limits(nlohmann::json& /*details*/)
+    {
+        // Spend limits are enforced by the server for multisig sessions
+    }
+
     void session_impl::save_cache()
     {
         // Refers to the ga_session cache at the moment, so a no-op for rust sessions
@@ -173,13 +173,16 @@ namespace green {
             = 0;
 
         virtual Tx get_raw_transaction_details(const std::string& txhash_hex) const = 0;
-        nlohmann::json get_transaction_details(const std::string& txhash_hex) const;
+        virtual nlohmann::json get_transaction_details(const std::string& txhash_hex) const;
 
         virtual nlohmann::json service_sign_transaction(const nlohmann::json& details,
             const nlohmann::json& twofactor_data, std::vector<std::vector<unsigned char>>& old_scripts);
         virtual nlohmann::json send_transaction(const nlohmann::json& details, const nlohmann::json& twofactor_data)
             = 0;
         virtual nlohmann::json broadcast_transaction(const nlohmann::json& details) = 0;
+        // Mark a created transaction with "requires_confirmation" if it exceeds
+        // the session spend limits
+        virtual void check_spend_limits(nlohmann::json& details);
 
         virtual void send_nlocktimes() = 0;
         virtual void set_csvtime(const nlohmann::json& locktime_details, const nlohmann::json& twofactor_data) = 0;
@@ -1,5 +1,6 @@
 #include <boost/algorithm/string/case_conv.hpp>
 #include <boost/algorithm/string/predicate.hpp>
+#include <boost/algorithm/string/trim.hpp>
 
 #include "assertion.hpp"
 #include "exception.hpp"
@@ -666,10 +667,18 @@ namespace green {
         const auto blech32_prefix = net_params.blech32_prefix();
 
         try {
-            auto address = j_str_or_empty(addressee, "address");
+            if (auto uri = j_str_or_empty(addressee, "uri"); !uri.empty()) {
+                // A BIP21 URI given in place of the address
+                addressee["address"] = std::move(uri);
+                addressee.erase("uri");
+            }
+            // Addresses are often pasted with stray whitespace
+            auto address = boost::trim_copy(j_str_or_empty(addressee, "address"));
+            addressee.erase("error_code");
             if (address.empty()) {
                 throw user_error(res::id_invalid_address);
             }
+            addressee["address"] = address;
             const bool is_blinded = is_liquid && addressee.value("is_blinded", false);
             if (is_blinded && !session.get_nonnull_signer()->supports_external_blinding()) {
                 throw user_error("Signing device does not support externally blinded transactions");
@@ -703,6 +712,18 @@ namespace green {
                 // Existing outputs which are already blinded are OK
                 error.clear();
             }
+            if (error == res::id_invalid_address) {
+                // Report why the address is invalid, as singlesig sessions do
+                try {
+                    const nlohmann::json details = { { "network", net_params.get_json() }, { "address", address } };
+                    const auto validation = rust_call("validate_address", details);
+                    if (auto p = validation.find("error_code"); p != validation.end()) {
+                        addressee["error_code"] = *p;
+                    }
+                } catch (const std::exception&) {
+                    // The generic error is still returned below
+                }
+            }
             if (!error.empty()) {
                 return error;
             }
@@ -726,8 +747,30 @@ namespace green {
             if (!override_network) {
                 try {
                     if (!j_bool_or_false(addressee, "is_greedy")) {
-                        const auto satoshi = j_amountref(session.convert_amount(addressee));
-                        addressee["satoshi"] = satoshi.value();
+                        // Check the amount can be sent: not zero, dust or above the supply
+                        const auto asset_id = j_assetref(is_liquid, addressee);
+                        nlohmann::json check = { { "network", net_params.get_json() },
+                            { "scriptpubkey", addressee.at("scriptpubkey") } };
+                        auto decimal_p = addressee.end();
+                        if (is_liquid) {
+                            check["asset_id"] = asset_id;
+                            if (asset_id != net_params.get_policy_asset()) {
+                                decimal_p = addressee.find(asset_id);
+                            }
+                        }
+                        if (decimal_p != addressee.end()) {
+                            // A decimal amount of the asset, parsed with its registry precision
+                            check["amount"] = *decimal_p;
+                            addressee.erase(decimal_p);
+                        } else {
+                            check["satoshi"] = session.convert_amount(addressee).at("satoshi");
+                        }
+                        const auto checked = rust_call("check_send_amount", check);
+                        if (auto p = checked.find("error_code"); p != checked.end()) {
+                            addressee["error_code"] = *p;
+                            return checked.at("error").get<std::string>();
+                        }
+                        addressee["satoshi"] = checked.at("satoshi");
                     }
                     amount::strip_non_satoshi_keys(addressee);
                 } catch (const user_error& ex) {
@@ -946,9 +989,25 @@ namespace green {
         const bool is_liquid = net_params.is_liquid();
         const auto policy_asset = net_params.get_policy_asset();
 
+        if (auto p = result.find("addressees"); p != result.end()) {
+            // Flag payments to the wallet's own scripts, so that callers can
+            // confirm the user intends to send to themselves
+            bool send_to_self = false;
+            for (const auto& addressee : *p) {
+                const auto spk = j_str_or_empty(addressee, "scriptpubkey");
+                if (!spk.empty()
+                    && (addressee.contains("address_type") || !session.get_scriptpubkey_data(h2b(spk)).empty())) {
+                    send_to_self = true;
+                    break;
+                }
+            }
+            result["send_to_self"] = send_to_self;
+        }
+
         if (!tx.get_num_inputs() || !tx.get_num_outputs() || !j_str_is_empty(result, "error")) {
             // The tx is not valid/is incomplete
             result["transaction_outputs"] = nlohmann::json::array_t();
+            result["change_outputs"] = nlohmann::json::array_t();
             return;
         }
 
@@ -1013,6 +1072,29 @@ namespace green {
             result["transaction_outputs"] = std::move(outputs);
         }
 
+        // List the change outputs by their position in the transaction,
+        // whatever the order of the outputs
+        nlohmann::json::array_t change_outputs;
+        const auto& transaction_outputs = result.at("transaction_outputs");
+        for (size_t i = 0; i < transaction_outputs.size(); ++i) {
+            const auto& output = transaction_outputs.at(i);
+            if (!j_bool_or_false(output, "is_change")) {
+                continue;
+            }
+            nlohmann::json change{ { "vout", i }, { "scriptpubkey", output.at("scriptpubkey") },
+                { "address", j_str_or_empty(output, "address") }, { "satoshi", output.at("satoshi") } };
+            if (is_liquid) {
+                change["asset_id"] = output.at("asset_id");
+            }
+            for (const auto& key : { "subaccount", "pointer", "is_internal", "user_path" }) {
+                if (auto p = output.find(key); p != output.end()) {
+                    change[key] = *p;
+                }
+            }
+            change_outputs.emplace_back(std::move(change));
+        }
+        result["change_outputs"] = std::move(change_outputs);
+
         // Set "satoshi" per-asset elements to the net effect on the wallet
         auto& summary = result["satoshi"];
         summary = { { policy_asset, 0u } };
@@ -0,0 +1,285 @@
+//! Validation and formatting of amounts.
+//!
+//! The calls converting and sending amounts parse and check them here, so
+//! that the same invalid amount fails with the same error whatever the call.
+//! The calls returning amounts format them here, in the `unit` setting.
+
+use crate::error::ErrorCode;
+use crate::model::Balances;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// The maximum amount in satoshi, the 21 million BTC supply. Liquid assets
+/// have the same limit.
+pub const SATOSHI_MAX: u64 = 21_000_000 * 100_000_000;
+
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AmountError {
+    #[error("amount above the 21 million BTC supply")]
+    Overflow,
+
+    #[error("id_amount_below_the_dust_threshold")]
+    BelowDustLimit,
+
+    #[error("zero amount")]
+    Zero,
+
+    #[error("negative amount")]
+    Negative,
+
+    #[error("amount with more decimal places than its precision")]
+    PrecisionLoss,
+
+    #[error("id_invalid_amount")]
+    Malformed,
+}
+
+impl AmountError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AmountError::Overflow => ErrorCode::AmountOverflow,
+            AmountError::BelowDustLimit => ErrorCode::AmountBelowDust,
+            AmountError::Zero => ErrorCode::AmountZero,
+            AmountError::Negative => ErrorCode::AmountNegative,
+            AmountError::PrecisionLoss => ErrorCode::AmountPrecisionLoss,
+            AmountError::Malformed => ErrorCode::InvalidAmount,
+        }
+    }
+}
+
+/// Parse the decimal `amount`, in a unit with `precision` decimal places, as
+/// a number of the smallest unit, eg. "1.5" with 2 decimal places is 150
+///
+/// Zeros after the last decimal place are accepted, other digits would be
+/// lost and fail with [`AmountError::PrecisionLoss`]. The result is not
+/// checked against the supply, see [`check_supply`].
+pub fn parse_signed(amount: &str, precision: u8) -> Result<i64, AmountError> {
+    let (negative, digits) = match amount.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, amount),
+    };
+    let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    let all_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+    if (integer.is_empty() && fraction.is_empty()) || !all_digits(integer) || !all_digits(fraction)
+    {
+        return Err(AmountError::Malformed);
+    }
+    let precision = precision as usize;
+    if fraction.len() > precision && fraction[precision..].chars().any(|c| c != '0') {
+        return Err(AmountError::PrecisionLoss);
+    }
+    let fraction = &fraction[..fraction.len().min(precision)];
+    let units = format!("{}{:0<width$}", integer, fraction, width = precision);
+    let units = units.trim_start_matches('0');
+    let units = match units {
+        "" => 0,
+        units => units.parse::<i64>().map_err(|_| AmountError::Overflow)?,
+    };
+    Ok(if negative {
+        -units
+    } else {
+        units
+    })
+}
+
+/// Parse the decimal `amount` as [`parse_signed`] does, failing for negative
+/// amounts and amounts above the supply
+pub fn parse(amount: &str, precision: u8) -> Result<u64, AmountError> {
+    let units = parse_signed(amount, precision)?;
+    if units < 0 {
+        return Err(AmountError::Negative);
+    }
+    check_supply(units as u64)
+}
+
+/// Check `satoshi` is within the supply
+pub fn check_supply(satoshi: u64) -> Result<u64, AmountError> {
+    if satoshi > SATOSHI_MAX {
+        return Err(AmountError::Overflow);
+    }
+    Ok(satoshi)
+}
+
+/// Check `satoshi` can be sent to an output whose dust limit is `dust`
+pub fn check_send(satoshi: u64, dust: u64) -> Result<u64, AmountError> {
+    if satoshi == 0 {
+        return Err(AmountError::Zero);
+    }
+    check_supply(satoshi)?;
+    if satoshi < dust {
+        return Err(AmountError::BelowDustLimit);
+    }
+    Ok(satoshi)
+}
+
+/// Format `units` of a unit with `precision` decimal places as a decimal
+/// with all the decimal places, eg. 150 with 2 decimal places is "1.50"
+pub fn format(units: i64, precision: u8) -> String {
+    let sign = if units < 0 {
+        "-"
+    } else {
+        ""
+    };
+    let units = units.unsigned_abs();
+    if precision == 0 {
+        return format!("{}{}", sign, units);
+    }
+    let unit = 10u64.pow(precision as u32);
+    format!("{}{}.{:0width$}", sign, units / unit, units % unit, width = precision as usize)
+}
+
+/// The units of the policy asset amounts, the `unit` setting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Unit {
+    #[default]
+    Btc,
+    MilliBtc,
+    MicroBtc,
+    Bits,
+    Sats,
+}
+
+impl Unit {
+    /// The decimal places of the unit, in satoshi
+    pub fn precision(self) -> u8 {
+        match self {
+            Unit::Btc => 8,
+            Unit::MilliBtc => 5,
+            Unit::MicroBtc | Unit::Bits => 2,
+            Unit::Sats => 0,
+        }
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = match self {
+            Unit::Btc => "BTC",
+            Unit::MilliBtc => "mBTC",
+            Unit::MicroBtc => "\u{b5}BTC",
+            Unit::Bits => "bits",
+            Unit::Sats => "sats",
+        };
+        f.write_str(unit)
+    }
+}
+
+impl FromStr for Unit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "btc" => Ok(Unit::Btc),
+            "mbtc" => Ok(Unit::MilliBtc),
+            "\u{b5}btc" | "ubtc" => Ok(Unit::MicroBtc),
+            "bits" => Ok(Unit::Bits),
+            "sats" => Ok(Unit::Sats),
+            _ => Err(format!("unknown unit {}", s)),
+        }
+    }
+}
+
+/// Format the amounts returned by the calls: the policy asset in the `unit`
+/// setting, the other assets with their precision, in their smallest unit
+/// if not known
+#[derive(Debug, Clone)]
+pub struct AmountFormatter {
+    unit: Unit,
+    policy_asset: String,
+    precisions: HashMap<String, u8>,
+}
+
+impl AmountFormatter {
+    /// The formatter in `unit` of the amounts of `policy_asset`, "btc" on
+    /// Bitcoin, and of the assets with the given `precisions`
+    pub fn new(unit: Unit, policy_asset: &str, precisions: HashMap<String, u8>) -> Self {
+        AmountFormatter {
+            unit,
+            policy_asset: policy_asset.to_string(),
+            precisions,
+        }
+    }
+
+    /// Format `satoshi` of `asset_id`
+    pub fn format(&self, satoshi: i64, asset_id: &str) -> String {
+        let precision = match asset_id == self.policy_asset {
+            true => self.unit.precision(),
+            false => self.precisions.get(asset_id).copied().unwrap_or(0),
+        };
+        format(satoshi, precision)
+    }
+
+    /// Format each amount of `balances`
+    pub fn format_balances(&self, balances: &Balances) -> HashMap<String, String> {
+        balances
+            .iter()
+            .map(|(asset_id, satoshi)| (asset_id.clone(), self.format(*satoshi, asset_id)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("1.5", 2), Ok(150));
+        assert_eq!(parse("0.00000001", 8), Ok(1));
+        assert_eq!(parse(".5", 1), Ok(5));
+        assert_eq!(parse("7.", 0), Ok(7));
+        assert_eq!(parse("1.2300", 2), Ok(123));
+        assert_eq!(parse("21000000", 8), Ok(SATOSHI_MAX));
+        assert_eq!(parse("0", 8), Ok(0));
+        assert_eq!(parse_signed("-.00000001", 8), Ok(-1));
+
+        assert_eq!(parse("1.234", 2), Err(AmountError::PrecisionLoss));
+        assert_eq!(parse("21000000.00000001", 8), Err(AmountError::Overflow));
+        assert_eq!(parse("99999999999999999999", 8), Err(AmountError::Overflow));
+        assert_eq!(parse("-1", 8), Err(AmountError::Negative));
+        for malformed in ["", ".", "-", "1e5", "0x10", "1.2.3", " 1", "1,5", "NaN", "+1"] {
+            assert_eq!(parse(malformed, 8), Err(AmountError::Malformed), "{}", malformed);
+        }
+    }
+
+    #[test]
+    fn test_check_send() {
+        assert_eq!(check_send(546, 546), Ok(546));
+        assert_eq!(check_send(545, 546), Err(AmountError::BelowDustLimit));
+        assert_eq!(check_send(0, 546), Err(AmountError::Zero));
+        assert_eq!(check_send(SATOSHI_MAX + 1, 546), Err(AmountError::Overflow));
+        assert_eq!(
+            AmountError::BelowDustLimit.code().gdk_id(),
+            "id_amount_below_the_dust_threshold"
+        );
+        assert_eq!(AmountError::PrecisionLoss.code().gdk_id(), "id_invalid_amount");
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format(150, 2), "1.50");
+        assert_eq!(format(1, 8), "0.00000001");
+        assert_eq!(format(-123_456, 5), "-1.23456");
+        assert_eq!(format(1_000, 0), "1000");
+        assert_eq!(parse(&format(SATOSHI_MAX as i64, 8), 8), Ok(SATOSHI_MAX));
+
+        for unit in [Unit::Btc, Unit::MilliBtc, Unit::MicroBtc, Unit::Bits, Unit::Sats] {
+            assert_eq!(unit.to_string().parse::<Unit>(), Ok(unit));
+        }
+        assert_eq!("ubtc".parse::<Unit>(), Ok(Unit::MicroBtc));
+        assert_eq!("Sats".parse::<Unit>(), Ok(Unit::Sats));
+        assert!("satoshi".parse::<Unit>().is_err());
+
+        let asset = "ab".repeat(32);
+        let policy_asset = "cd".repeat(32);
+        let precisions = [(asset.clone(), 2)].into();
+        let formatter = AmountFormatter::new(Unit::MilliBtc, &policy_asset, precisions);
+        assert_eq!(formatter.format(12_345, &policy_asset), "0.12345");
+        assert_eq!(formatter.format(12_345, &asset), "123.45");
+        assert_eq!(formatter.format(12_345, &"ef".repeat(32)), "12345");
+        let formatter = AmountFormatter::new(Unit::Sats, "btc", HashMap::new());
+        let balances = [("btc".to_string(), -5)].into();
+        assert_eq!(formatter.format_balances(&balances), [("btc".to_string(), "-5".into())].into());
+    }
+}
@@ -1,6 +1,6 @@
 use super::BEScript;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum BEAddress {
     Bitcoin(bitcoin::Address),
     Elements(elements::Address),
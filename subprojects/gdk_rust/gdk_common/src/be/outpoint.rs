@@ -1,6 +1,7 @@
 use super::BETxid;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
 pub enum BEOutPoint {
     Bitcoin(bitcoin::OutPoint),
     Elements(elements::OutPoint),
@@ -46,6 +46,52 @@ impl BEScript {
             Self::Elements(script) => Some(script),
         }
     }
+
+    /// The type of an output with this script: "p2pkh", "p2sh", "p2wpkh",
+    /// "p2wsh", "p2tr", "op_return" for null data, "fee" for the fee
+    /// outputs of Liquid and "unknown" for the non-standard scripts
+    pub fn output_type(&self) -> &'static str {
+        if let Self::Elements(script) = self {
+            if script.is_empty() {
+                return "fee";
+            }
+        }
+        let script = self.clone().into_bitcoin();
+        if script.is_p2pkh() {
+            "p2pkh"
+        } else if script.is_p2sh() {
+            "p2sh"
+        } else if script.is_p2wpkh() {
+            "p2wpkh"
+        } else if script.is_p2wsh() {
+            "p2wsh"
+        } else if script.is_p2tr() {
+            "p2tr"
+        } else if script.is_op_return() {
+            "op_return"
+        } else {
+            "unknown"
+        }
+    }
+
+    /// The data pushed by an OP_RETURN script, concatenated, None for other
+    /// scripts or if it is not all pushes
+    pub fn op_return_data(&self) -> Option<Vec<u8>> {
+        let script = self.clone().into_bitcoin();
+        if !script.is_op_return() {
+            return None;
+        }
+        let mut data = vec![];
+        for instruction in script.instructions().skip(1) {
+            match instruction.ok()? {
+                bitcoin::script::Instruction::PushBytes(bytes) => {
+                    data.extend_from_slice(bytes.as_bytes())
+                }
+                bitcoin::script::Instruction::Op(_) => return None,
+            }
+        }
+        Some(data)
+    }
 }
 
 impl Default for BEScript {
@@ -157,3 +203,51 @@ impl From<&elements::Script> for BEScript {
         script.clone().into()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoin::hashes::Hash;
+
+    #[test]
+    fn test_output_type() {
+        let pubkey_hash = bitcoin::PubkeyHash::all_zeros();
+        let wpubkey_hash = bitcoin::WPubkeyHash::all_zeros();
+        let script_hash = bitcoin::ScriptHash::all_zeros();
+        let wscript_hash = bitcoin::WScriptHash::all_zeros();
+        let cases = [
+            (bitcoin::ScriptBuf::new_p2pkh(&pubkey_hash), "p2pkh"),
+            (bitcoin::ScriptBuf::new_p2sh(&script_hash), "p2sh"),
+            (bitcoin::ScriptBuf::new_p2wpkh(&wpubkey_hash), "p2wpkh"),
+            (bitcoin::ScriptBuf::new_p2wsh(&wscript_hash), "p2wsh"),
+            (bitcoin::ScriptBuf::from_hex(&format!("5120{}", "11".repeat(32))).unwrap(), "p2tr"),
+            (bitcoin::ScriptBuf::new_op_return([0xca, 0xfe]), "op_return"),
+            (bitcoin::ScriptBuf::from_hex("51").unwrap(), "unknown"),
+            (bitcoin::ScriptBuf::new(), "unknown"),
+        ];
+        for (script, expected) in cases {
+            assert_eq!(BEScript::from(script.clone()).output_type(), expected);
+            if !script.is_empty() {
+                assert_eq!(script.into_elements().into_be().output_type(), expected);
+            }
+        }
+        // Only Liquid has fee outputs
+        assert_eq!(BEScript::from(elements::Script::new()).output_type(), "fee");
+    }
+
+    #[test]
+    fn test_op_return_data() {
+        let op_return = bitcoin::ScriptBuf::new_op_return([0xca, 0xfe]).into_be();
+        assert_eq!(op_return.op_return_data(), Some(vec![0xca, 0xfe]));
+        let empty = bitcoin::ScriptBuf::from_hex("6a").unwrap().into_be();
+        assert_eq!(empty.op_return_data(), Some(vec![]));
+        let two_pushes = bitcoin::ScriptBuf::from_hex("6a01ca01fe").unwrap().into_be();
+        assert_eq!(two_pushes.op_return_data(), Some(vec![0xca, 0xfe]));
+        let not_pushes = bitcoin::ScriptBuf::from_hex("6a01ca75").unwrap().into_be();
+        assert_eq!(not_pushes.op_return_data(), None);
+        let elements = elements::Script::new_op_return(&[0xca, 0xfe]).into_be();
+        assert_eq!(elements.op_return_data(), Some(vec![0xca, 0xfe]));
+        let p2wsh = bitcoin::ScriptBuf::new_p2wsh(&bitcoin::WScriptHash::all_zeros()).into_be();
+        assert_eq!(p2wsh.op_return_data(), None);
+    }
+}
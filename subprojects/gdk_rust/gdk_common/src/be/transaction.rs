@@ -15,14 +15,20 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::ops::{Deref, DerefMut};
 
-pub const DUST_VALUE: u64 = 546;
-
 #[derive(Debug, Clone, Serialize, Deserialize, Hash)]
 pub enum BETransaction {
     Bitcoin(bitcoin::Transaction),
     Elements(elements::Transaction),
 }
 
+/// A transaction output, like the previous outputs spent by a transaction to sign
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[allow(clippy::large_enum_variant)]
+pub enum BETxOut {
+    Bitcoin(bitcoin::TxOut),
+    Elements(elements::TxOut),
+}
+
 impl BETransaction {
     pub fn is_elements(&self) -> bool {
         match self {
@@ -56,6 +62,23 @@ impl BETransaction {
         }
     }
 
+    /// The txid of the transaction without its input scripts, which signing
+    /// doesn't change for any script type
+    pub fn unsigned_txid(&self) -> BETxid {
+        match self {
+            Self::Bitcoin(tx) => {
+                let mut tx = tx.clone();
+                tx.input.iter_mut().for_each(|input| input.script_sig = Default::default());
+                tx.compute_txid().into()
+            }
+            Self::Elements(tx) => {
+                let mut tx = tx.clone();
+                tx.input.iter_mut().for_each(|input| input.script_sig = Default::default());
+                tx.txid().into()
+            }
+        }
+    }
+
     pub fn version(&self) -> u32 {
         match self {
             Self::Bitcoin(tx) => tx.version.0 as u32,
@@ -235,6 +258,35 @@ impl BETransaction {
         }
     }
 
+    /// The value of the output if explicit, always for Bitcoin
+    pub fn output_explicit_value(&self, vout: u32) -> Option<u64> {
+        match self {
+            Self::Bitcoin(tx) => Some(tx.output[vout as usize].value.to_sat()),
+            Self::Elements(tx) => tx.output[vout as usize].value.explicit(),
+        }
+    }
+
+    /// The asset of the output if explicit, never for Bitcoin
+    pub fn output_explicit_asset(&self, vout: u32) -> Option<elements::issuance::AssetId> {
+        match self {
+            Self::Bitcoin(_) => None,
+            Self::Elements(tx) => tx.output[vout as usize].asset.explicit(),
+        }
+    }
+
+    /// The asset and value commitments of a confidential output, as hex
+    pub fn output_commitments_hex(&self, vout: u32) -> Option<(String, String)> {
+        match self {
+            Self::Bitcoin(_) => None,
+            Self::Elements(tx) => {
+                let output = &tx.output[vout as usize];
+                let asset = output.asset.commitment()?;
+                let value = output.value.commitment()?;
+                Some((asset.serialize().to_hex(), value.serialize().to_hex()))
+            }
+        }
+    }
+
     pub fn get_weight(&self) -> usize {
         match self {
             Self::Bitcoin(tx) => tx.weight().to_wu() as usize,
@@ -301,6 +353,19 @@ impl BETransaction {
         }
     }
 
+    /// The index of the first input with neither a script sig nor a witness
+    pub fn unsigned_input(&self) -> Option<usize> {
+        match self {
+            Self::Bitcoin(tx) => tx
+                .input
+                .iter()
+                .position(|input| input.script_sig.is_empty() && input.witness.is_empty()),
+            Self::Elements(tx) => tx.input.iter().position(|input| {
+                input.script_sig.is_empty() && input.witness.script_witness.is_empty()
+            }),
+        }
+    }
+
     pub fn is_redeposit(
         &self,
         all_scripts: &HashMap<BEScript, DerivationPath>,
@@ -321,6 +386,7 @@ impl BETransaction {
                     && tx
                         .output
                         .iter()
+                        .filter(|o| !(o.script_pubkey.is_op_return() && o.value.to_sat() == 0))
                         .all(|o| all_scripts.contains_key(&o.script_pubkey.clone().into()))
             }
             Self::Elements(tx) => {
@@ -337,7 +403,7 @@ impl BETransaction {
                     && tx
                         .output
                         .iter()
-                        .filter(|o| !o.is_fee())
+                        .filter(|o| !o.is_fee() && !o.script_pubkey.is_op_return())
                         .all(|o| all_scripts.contains_key(&o.script_pubkey.clone().into()))
             }
         }
@@ -558,3 +624,125 @@ impl From<BETransaction> for BETransactionEntry {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn btc_tx(
+        input: Vec<bitcoin::OutPoint>,
+        output: Vec<(u64, bitcoin::ScriptBuf)>,
+    ) -> BETransaction {
+        BETransaction::Bitcoin(bitcoin::Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: input
+                .into_iter()
+                .map(|previous_output| bitcoin::TxIn {
+                    previous_output,
+                    ..Default::default()
+                })
+                .collect(),
+            output: output
+                .into_iter()
+                .map(|(value, script_pubkey)| bitcoin::TxOut {
+                    value: bitcoin::Amount::from_sat(value),
+                    script_pubkey,
+                })
+                .collect(),
+        })
+    }
+
+    fn elm_tx(input: Vec<elements::OutPoint>, output: Vec<elements::TxOut>) -> BETransaction {
+        BETransaction::Elements(elements::Transaction {
+            version: 2,
+            lock_time: elements::LockTime::ZERO,
+            input: input
+                .into_iter()
+                .map(|previous_output| elements::TxIn {
+                    previous_output,
+                    ..Default::default()
+                })
+                .collect(),
+            output,
+        })
+    }
+
+    fn elm_txout(value: u64, script_pubkey: elements::Script) -> elements::TxOut {
+        elements::TxOut {
+            asset: elements::confidential::Asset::Explicit(elements::AssetId::default()),
+            value: elements::confidential::Value::Explicit(value),
+            nonce: elements::confidential::Nonce::Null,
+            script_pubkey,
+            witness: Default::default(),
+        }
+    }
+
+    fn wallet_scripts(scripts: &[BEScript]) -> HashMap<BEScript, DerivationPath> {
+        scripts.iter().map(|s| (s.clone(), DerivationPath::master())).collect()
+    }
+
+    fn is_redeposit_spending(
+        prev: BETransaction,
+        tx: &BETransaction,
+        scripts: &[BEScript],
+    ) -> bool {
+        let mut all_txs = BETransactions::default();
+        all_txs.insert(prev.txid(), prev.into());
+        tx.is_redeposit(&wallet_scripts(scripts), &all_txs)
+    }
+
+    #[test]
+    fn test_bitcoin_redeposit_with_op_return() {
+        let ours = bitcoin::ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::all_zeros());
+        let change = bitcoin::ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::hash(&[1]));
+        let op_return = bitcoin::ScriptBuf::new_op_return([0xca, 0xfe]);
+        let unknown = bitcoin::ScriptBuf::from_hex("51").unwrap();
+        let scripts = [ours.clone().into_be(), change.clone().into_be()];
+        let prev = btc_tx(vec![], vec![(10_000, ours)]);
+        let outpoint = bitcoin::OutPoint::new(*prev.txid().ref_bitcoin().unwrap(), 0);
+
+        let tx = btc_tx(vec![outpoint], vec![(9_000, change.clone()), (0, op_return.clone())]);
+        assert!(is_redeposit_spending(prev.clone(), &tx, &scripts));
+
+        // OP_RETURN outputs burning funds are payments
+        let tx = btc_tx(vec![outpoint], vec![(8_000, change.clone()), (1_000, op_return)]);
+        assert!(!is_redeposit_spending(prev.clone(), &tx, &scripts));
+
+        let tx = btc_tx(vec![outpoint], vec![(8_000, change), (1_000, unknown.clone())]);
+        assert!(!is_redeposit_spending(prev.clone(), &tx, &scripts));
+        assert_eq!(tx.output_script(1).output_type(), "unknown");
+    }
+
+    #[test]
+    fn test_elements_redeposit_with_null_data_and_fee() {
+        let ours = elements::Script::new_v0_wpkh(&elements::WPubkeyHash::all_zeros());
+        let change = elements::Script::new_v0_wpkh(&elements::WPubkeyHash::hash(&[1]));
+        let external = elements::Script::new_v0_wpkh(&elements::WPubkeyHash::hash(&[2]));
+        let scripts = [ours.clone().into_be(), change.clone().into_be()];
+        let prev = elm_tx(vec![], vec![elm_txout(10_000, ours)]);
+        let outpoint = elements::OutPoint::new(*prev.txid().ref_elements().unwrap(), 0);
+
+        let tx = elm_tx(
+            vec![outpoint],
+            vec![
+                elm_txout(9_000, change.clone()),
+                elm_txout(0, elements::Script::new_op_return(&[0xca, 0xfe])),
+                elm_txout(1_000, elements::Script::new()),
+            ],
+        );
+        assert!(is_redeposit_spending(prev.clone(), &tx, &scripts));
+        assert_eq!(tx.output_script(1).output_type(), "op_return");
+        assert_eq!(tx.output_script(2).output_type(), "fee");
+
+        let tx = elm_tx(
+            vec![outpoint],
+            vec![
+                elm_txout(8_000, change),
+                elm_txout(1_000, external),
+                elm_txout(1_000, elements::Script::new()),
+            ],
+        );
+        assert!(!is_redeposit_spending(prev, &tx, &scripts));
+    }
+}
@@ -1,13 +1,13 @@
 use crate::error::Error;
 use crate::scripts::ScriptType;
 use crate::util::MasterBlindingKey;
-use bitcoin::bip32::{ChildNumber, Fingerprint, Xpub};
+use bitcoin::bip32::{ChildNumber, DerivationPath, Fingerprint, Xpub};
 use elements_miniscript::confidential::Key;
 use elements_miniscript::{
     ConfidentialDescriptor, DescriptorPublicKey as ElementsDescriptorPublicKey,
 };
 use miniscript::descriptor::checksum::desc_checksum;
-use miniscript::descriptor::{Descriptor, DescriptorPublicKey, ShInner};
+use miniscript::descriptor::{Descriptor, DescriptorPublicKey, ShInner, Wildcard, WshInner};
 use std::str::FromStr;
 
 /// Make sure the key origin is in the expected format
@@ -125,6 +125,54 @@ pub fn parse_single_sig_descriptor(
     Err(Error::UnsupportedDescriptor)
 }
 
+/// A key of a multisig descriptor: the master key fingerprint and the path
+/// of its origin, and the extended public key whose `/0/*` and `/1/*`
+/// children are the receive and change keys
+pub type MultisigDescriptorKey = (Fingerprint, DerivationPath, Xpub);
+
+/// Parse a `wsh(sortedmulti(k,...))` descriptor, returning the number of
+/// signatures required and the keys.
+///
+/// Keys must have an origin and be either bare extended keys or multipath
+/// ones ending in `/<0;1>/*`, covering both the receive and change chains.
+pub fn parse_multisig_descriptor(s: &str) -> Result<(usize, Vec<MultisigDescriptorKey>), Error> {
+    let (desc, _) =
+        Descriptor::parse_descriptor(&crate::EC, s).map_err(|_| Error::UnsupportedDescriptor)?;
+    let sortedmulti = match &desc {
+        Descriptor::Wsh(wsh) => match wsh.as_inner() {
+            WshInner::SortedMulti(sortedmulti) => sortedmulti,
+            _ => return Err(Error::UnsupportedDescriptor),
+        },
+        _ => return Err(Error::UnsupportedDescriptor),
+    };
+    let chains: Vec<DerivationPath> =
+        vec![vec![ChildNumber::from(0)].into(), vec![ChildNumber::from(1)].into()];
+    let keys = sortedmulti
+        .pks()
+        .iter()
+        .map(|key| {
+            let (origin, xpub) = match key {
+                DescriptorPublicKey::XPub(xkey)
+                    if xkey.derivation_path.as_ref().is_empty()
+                        && xkey.wildcard == Wildcard::None =>
+                {
+                    (&xkey.origin, xkey.xkey)
+                }
+                DescriptorPublicKey::MultiXPub(xkey)
+                    if *xkey.derivation_paths.paths() == chains
+                        && xkey.wildcard == Wildcard::Unhardened =>
+                {
+                    (&xkey.origin, xkey.xkey)
+                }
+                _ => return Err(Error::UnsupportedDescriptor),
+            };
+            let (fingerprint, path) = origin.clone().ok_or(Error::UnsupportedDescriptor)?;
+            Ok((fingerprint, path, xpub))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((sortedmulti.k(), keys))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -232,4 +280,35 @@ mod test {
         let f = |(s, t)| parse_single_sig_descriptor(s, t, is_liquid).unwrap_err().to_string();
         assert_eq!(f((&shp2wpkh_view, coin_type)), err_str);
     }
+
+    #[test]
+    fn test_multisig_descriptor() {
+        let tpub = "tpubDC2Q4xK4XH72J7Lkp6kAvY2Q5x4cxrKgrevkZKC2FwWZ9A9qA5eY6kvv6QDHb6iJtByzoC5J8KZZ29T45CxFz2Gh6m6PQoFF3DqukrRGtj5";
+        let tpub_1 = "tpubDC2Q4xK4XH72LKPujd1d7X8YzuwWAemRQhcYpNqduZzpvqvR3DP3bEUJWELoPG8EEsmvQzYZ3Pw81oYrcwnJ5rmVRvm2zdyT2h7mMNJArtJ";
+        let key = format!("[00000000/48'/1'/0'/2']{}", tpub);
+        let key_1 = format!("[00000001/48'/1'/0'/2']{}", tpub_1);
+
+        let bare = format!("wsh(sortedmulti(2,{},{}))", key, key_1);
+        let (threshold, keys) = parse_multisig_descriptor(&bare).unwrap();
+        assert_eq!(threshold, 2);
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].0, Fingerprint::default());
+        assert_eq!(keys[0].1, "m/48'/1'/0'/2'".parse::<DerivationPath>().unwrap());
+        assert_eq!(keys[0].2.to_string(), tpub);
+        assert_eq!(keys[1].2.to_string(), tpub_1);
+
+        let multipath = format!("wsh(sortedmulti(1,{}/<0;1>/*,{}/<0;1>/*))", key, key_1);
+        let (threshold, multipath_keys) = parse_multisig_descriptor(&multipath).unwrap();
+        assert_eq!(threshold, 1);
+        assert_eq!(multipath_keys, keys);
+
+        // Invalid cases
+        let err_str = Error::UnsupportedDescriptor.to_string();
+        let f = |s: String| parse_multisig_descriptor(&s).unwrap_err().to_string();
+        assert_eq!(f(format!("wsh(multi(2,{},{}))", key, key_1)), err_str);
+        assert_eq!(f(format!("sh(sortedmulti(2,{},{}))", key, key_1)), err_str);
+        assert_eq!(f(format!("wsh(sortedmulti(2,{}/0/*,{}/0/*))", key, key_1)), err_str);
+        assert_eq!(f(format!("wsh(sortedmulti(2,{},{}))", tpub, key_1)), err_str);
+        assert_eq!(f(format!("wpkh({}/0/*)", key)), err_str);
+    }
 }
@@ -1,6 +1,9 @@
+use std::error::Error as _;
+use std::io::ErrorKind;
 use std::string::ToString;
 
 use bitcoin::sighash::NonStandardSighashTypeError;
+use serde::{Deserialize, Serialize};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -45,6 +48,9 @@ pub enum Error {
     #[error(transparent)]
     FromSliceError(#[from] std::array::TryFromSliceError),
 
+    #[error("Decryption failed")]
+    DecryptionFailed,
+
     #[error("Invalid input")]
     InputValidationFailed,
 
@@ -60,6 +66,9 @@ pub enum Error {
     #[error("Invalid URL: {0}")]
     InvalidUrl(String),
 
+    #[error("Invalid network parameters: {0}")]
+    InvalidNetwork(String),
+
     #[error(transparent)]
     Io(#[from] std::io::Error),
 
@@ -97,6 +106,170 @@ impl From<aes_gcm_siv::aead::Error> for Error {
     }
 }
 
+impl Error {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::BtcAddressError(bitcoin::address::ParseError::NetworkValidation(_)) => {
+                ErrorCode::InvalidAddress {
+                    network_mismatch: true,
+                }
+            }
+            Error::BtcAddressError(_) | Error::ElementsAddressError(_) => {
+                ErrorCode::InvalidAddress {
+                    network_mismatch: false,
+                }
+            }
+            Error::DecryptionFailed => ErrorCode::DecryptionFailed,
+            Error::Io(e) => ErrorCode::from_io(e),
+            _ => ErrorCode::Unknown,
+        }
+    }
+}
+
+/// A machine readable error classification, stable across releases so that
+/// bindings can map errors to user-facing messages.
+///
+/// It serializes as an object with a `code` string and, for some codes,
+/// additional details, eg. `{"code":"invalid_address","network_mismatch":true}`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum ErrorCode {
+    AmountBelowDust,
+    AmountNegative,
+    AmountOverflow,
+    AmountPrecisionLoss,
+    AmountZero,
+    ConfirmationRequired,
+    ConnectionFailed,
+    DecryptionFailed,
+    FeeRateBelowMinimum,
+    InsufficientFunds,
+    InsufficientFundsForFee,
+    InvalidAddress {
+        network_mismatch: bool,
+    },
+    InvalidAddressCase,
+    InvalidAddressChecksum,
+    InvalidAmount,
+    InvalidAssetId,
+    InvalidPin,
+    InvalidWitnessProgramLength,
+    PolicyRejected,
+    ProxyUnreachable,
+    QuickLoginUnavailable,
+    ServerBehind,
+    ServerGenesisMismatch,
+    SessionClosed,
+    SessionPaused,
+    SettingsConflict,
+    StaleFeeEstimates,
+    StoreLocked,
+    Timeout,
+    TransactionNotFound,
+    TransactionRejected,
+    TransactionTooLarge,
+    UnknownWitnessVersion,
+    UnsupportedCurrency,
+    UnsupportedUriParameter,
+    UserNotFoundOrInvalid,
+    WatchOnly,
+    Unknown,
+}
+
+impl ErrorCode {
+    /// The `id_*` string identifying the error in GDK.
+    pub fn gdk_id(&self) -> &'static str {
+        match self {
+            ErrorCode::AmountBelowDust => "id_amount_below_the_dust_threshold",
+            ErrorCode::ConfirmationRequired => "id_confirmation_required",
+            ErrorCode::ConnectionFailed => "id_connection_failed",
+            ErrorCode::DecryptionFailed => "id_decryption_failed",
+            ErrorCode::FeeRateBelowMinimum => "id_fee_rate_is_below_minimum",
+            ErrorCode::InsufficientFunds | ErrorCode::InsufficientFundsForFee => {
+                "id_insufficient_funds"
+            }
+            ErrorCode::InvalidAddress {
+                ..
+            }
+            | ErrorCode::InvalidAddressCase
+            | ErrorCode::InvalidAddressChecksum
+            | ErrorCode::InvalidWitnessProgramLength
+            | ErrorCode::UnknownWitnessVersion => "id_invalid_address",
+            ErrorCode::AmountNegative
+            | ErrorCode::AmountOverflow
+            | ErrorCode::AmountPrecisionLoss
+            | ErrorCode::AmountZero
+            | ErrorCode::InvalidAmount => "id_invalid_amount",
+            ErrorCode::InvalidAssetId => "id_invalid_asset_id",
+            ErrorCode::InvalidPin => "id_invalid_pin",
+            ErrorCode::PolicyRejected => "id_transaction_rejected_by_policy",
+            ErrorCode::ProxyUnreachable => "id_proxy_unreachable",
+            ErrorCode::QuickLoginUnavailable => "id_quick_login_unavailable",
+            ErrorCode::ServerBehind => "id_server_behind",
+            ErrorCode::ServerGenesisMismatch => "id_server_genesis_mismatch",
+            ErrorCode::SessionClosed => "id_session_closed",
+            ErrorCode::SessionPaused => "id_session_paused",
+            ErrorCode::SettingsConflict => "id_settings_conflict",
+            ErrorCode::StaleFeeEstimates => "id_fee_estimates_are_stale",
+            ErrorCode::StoreLocked => "id_store_locked",
+            ErrorCode::Timeout => "id_timeout",
+            ErrorCode::TransactionNotFound => "id_transaction_not_found",
+            ErrorCode::TransactionRejected => "id_transaction_rejected",
+            ErrorCode::TransactionTooLarge => "id_transaction_too_large",
+            ErrorCode::UnsupportedCurrency => "id_unsupported_currency",
+            ErrorCode::UnsupportedUriParameter => "id_unknown_bip21_parameter",
+            ErrorCode::UserNotFoundOrInvalid => "id_user_not_found_or_invalid",
+            ErrorCode::WatchOnly => "id_watch_only",
+            ErrorCode::Unknown => "id_unknown",
+        }
+    }
+
+    pub fn from_io(err: &std::io::Error) -> Self {
+        match err.kind() {
+            ErrorKind::TimedOut | ErrorKind::WouldBlock => ErrorCode::Timeout,
+            ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected => ErrorCode::ConnectionFailed,
+            _ => ErrorCode::Unknown,
+        }
+    }
+
+    pub fn from_electrum(err: &electrum_client::Error) -> Self {
+        use electrum_client::Error::*;
+        match err {
+            IOError(e) => ErrorCode::from_io(e),
+            SharedIOError(e) => ErrorCode::from_io(e),
+            AllAttemptsErrored(errors) => {
+                errors.last().map(ErrorCode::from_electrum).unwrap_or(ErrorCode::ConnectionFailed)
+            }
+            SslHandshakeError(_) | InvalidDNSNameError(_) | MissingDomain => {
+                ErrorCode::ConnectionFailed
+            }
+            _ => ErrorCode::Unknown,
+        }
+    }
+
+    pub fn from_ureq(err: &ureq::Error) -> Self {
+        let transport = match err {
+            ureq::Error::Transport(transport) => transport,
+            ureq::Error::Status(..) => return ErrorCode::Unknown,
+        };
+        match transport.kind() {
+            ureq::ErrorKind::ProxyConnect | ureq::ErrorKind::InvalidProxyUrl => {
+                ErrorCode::ProxyUnreachable
+            }
+            ureq::ErrorKind::Dns | ureq::ErrorKind::ConnectionFailed => ErrorCode::ConnectionFailed,
+            ureq::ErrorKind::Io => transport
+                .source()
+                .and_then(|e| e.downcast_ref::<std::io::Error>())
+                .map(ErrorCode::from_io)
+                .unwrap_or(ErrorCode::ConnectionFailed),
+            _ => ErrorCode::Unknown,
+        }
+    }
+}
+
 pub fn fn_err(str: &str) -> impl Fn() -> Error + '_ {
     move || Error::Generic(str.into())
 }
@@ -136,3 +309,56 @@ macro_rules! impl_error_variant {
         }
     };
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::session::JsonError;
+    use bitcoin::address::NetworkUnchecked;
+    use serde_json::json;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_error_code() {
+        let public_key = bitcoin::CompressedPublicKey::from_str(
+            "03ad1d8e89212f0b92c74d23bb710c00662ad1470198ac48c43f7d6f93a2a26873",
+        )
+        .unwrap();
+        let address = bitcoin::Address::p2wpkh(&public_key, bitcoin::Network::Regtest)
+            .to_string()
+            .parse::<bitcoin::Address<NetworkUnchecked>>()
+            .unwrap();
+        let err: Error = address.require_network(bitcoin::Network::Bitcoin).unwrap_err().into();
+        let code = err.code();
+        assert_eq!(
+            code,
+            ErrorCode::InvalidAddress {
+                network_mismatch: true
+            }
+        );
+        let err: Error = "bcrt1qinvalid".parse::<bitcoin::Address<_>>().unwrap_err().into();
+        assert_eq!(
+            err.code(),
+            ErrorCode::InvalidAddress {
+                network_mismatch: false
+            }
+        );
+        let err = Error::Io(std::io::Error::from(ErrorKind::TimedOut));
+        assert_eq!(err.code(), ErrorCode::Timeout);
+        assert_eq!(Error::Generic("".into()).code(), ErrorCode::Unknown);
+
+        let json_error = JsonError {
+            message: "".into(),
+            error: code.gdk_id().into(),
+            code,
+        };
+        assert_eq!(
+            serde_json::to_value(&json_error).unwrap(),
+            json!({"message": "", "error": "id_invalid_address", "code": "invalid_address", "network_mismatch": true})
+        );
+        assert_eq!(
+            serde_json::to_value(JsonError::new("")).unwrap(),
+            json!({"message": "", "error": "id_unknown", "code": "unknown"})
+        );
+    }
+}
@@ -1,3 +1,4 @@
+pub mod amount;
 pub mod be;
 pub mod descriptor;
 pub mod error;
@@ -6,11 +7,13 @@ pub mod model;
 pub mod network;
 pub mod notification;
 pub mod password;
+pub mod redact;
 pub mod scripts;
 pub mod session;
 pub mod slip132;
 pub mod state;
 pub mod store;
+pub mod trace;
 pub mod util;
 pub mod aes {
     pub use aes::*;
@@ -29,7 +32,9 @@ pub use once_cell;
 pub use rand;
 pub use serde_cbor;
 pub use state::State;
+pub use tracing;
 pub use ureq;
+pub use url;
 
 pub static EC: once_cell::sync::Lazy<bitcoin::secp256k1::Secp256k1<bitcoin::secp256k1::All>> =
     once_cell::sync::Lazy::new(|| {
@@ -1,9 +1,10 @@
+use crate::amount::Unit;
 use crate::be::{BEOutPoint, BEScript};
 use crate::descriptor::parse_single_sig_descriptor;
 use crate::exchange_rates::Currency;
 use crate::slip132::{decode_from_slip132_string, extract_bip32_account};
 use crate::util::is_confidential_txoutsecrets;
-use crate::NetworkParameters;
+use crate::{NetworkParameters, NetworkPolicy};
 use bitcoin::Network;
 use elements::confidential;
 use elements::hex::ToHex;
@@ -11,6 +12,7 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 use crate::error::Error;
+use crate::password::Password;
 use crate::scripts::ScriptType;
 use crate::util::MasterBlindingKey;
 use bitcoin::bip32::{ChildNumber, DerivationPath, Fingerprint, Xpriv, Xpub};
@@ -92,6 +94,231 @@ pub struct LoginData {
     pub xpubs: Vec<AccountXpub>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub master_blinding_key: Option<MasterBlindingKey>,
+    /// Whether the wallet has not synced yet since login, see [`SyncStatus`].
+    #[serde(default)]
+    pub syncing: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SetSpvEnabledOpt {
+    /// False to trust the server, without downloading headers nor verifying
+    /// the transactions
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PauseOpt {
+    /// Serve the calls from the store while paused, only those needing the
+    /// server fail with `id_session_paused`, otherwise all of them fail
+    #[serde(default)]
+    pub serve_cache: bool,
+}
+
+/// The result of `get_network_info`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NetworkInfo {
+    pub name: String,
+    pub liquid: bool,
+    pub mainnet: bool,
+
+    /// The limits the transactions built by the session are checked against
+    #[serde(flatten)]
+    pub policy: NetworkPolicy,
+
+    /// The seconds the local clock is ahead of the time of the network,
+    /// negative if behind, estimated from the server tips since connecting
+    pub clock_skew_seconds: Option<i64>,
+
+    /// The check of the signatures of the federation on the last tip of
+    /// the server, only on liquid and once a tip was received
+    pub signblock: Option<SignblockValidation>,
+}
+
+/// Whether a liquid block is signed by the federation
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SignblockStatus {
+    Valid,
+    Invalid,
+
+    /// The keys of the federation of the network are not known
+    Unchecked,
+}
+
+/// The check of the signblock witness of a liquid tip
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SignblockValidation {
+    pub status: SignblockStatus,
+    pub height: u32,
+    pub block_hash: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SyncStatus {
+    /// Whether the wallet has not synced yet since login, meanwhile read
+    /// calls return the data cached by previous sessions.
+    pub syncing: bool,
+}
+
+/// The counters of the syncing of a session
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SessionMetrics {
+    /// The unix timestamp in seconds of the end of the last successful sync
+    /// with the server, if any since login
+    pub last_sync_timestamp: Option<u64>,
+
+    /// The number of successful syncs since login
+    pub syncs: u64,
+
+    /// The number of script histories fetched by the syncs, only the scripts
+    /// whose status changed since the last sync are fetched
+    pub histories_fetched: u64,
+
+    /// The maximum number of subaccounts synced at once
+    pub max_parallel_syncs: u64,
+
+    /// The number of transactions fetched from the server by the syncs, a
+    /// transaction needed by several subaccounts is fetched once
+    pub txs_fetched: u64,
+
+    /// The number of liquid outputs unblinded by the syncs, the outputs
+    /// unblinded before are kept in the store and not unblinded again
+    pub outputs_unblinded: u64,
+
+    /// The number of block headers downloaded by the SPV headers chain, from
+    /// the checkpoint below the wallet birthday if any
+    pub headers_downloaded: u64,
+}
+
+/// The overall health of a session, from the most severe of its
+/// [`HealthReason`]s
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Healthy,
+    Degraded,
+    Unhealthy,
+}
+
+/// Why a session is not healthy
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthReason {
+    /// The session is not connected or is paused, it doesn't sync
+    NotSyncing,
+
+    /// The last call to the server failed
+    ServerUnreachable,
+
+    /// The server didn't answer for a while
+    ServerSilent,
+
+    /// The server didn't answer for a long time
+    ServerLost,
+
+    /// The wallet was not synced since login
+    NotSynced,
+
+    /// The SPV headers chain is behind the tip reported by the server
+    TipBehind,
+
+    /// The SPV cross-validation found the server on a minority fork
+    CrossValidationFailed,
+
+    /// Many script changes notified by the server are not synced yet
+    SyncBacklog,
+
+    /// The last liquid tip of the server is not signed by the federation,
+    /// transactions are not SPV verified meanwhile
+    SignblockInvalid,
+}
+
+impl HealthReason {
+    /// The status of a session for this reason alone
+    pub fn status(&self) -> HealthStatus {
+        match self {
+            HealthReason::NotSyncing
+            | HealthReason::ServerLost
+            | HealthReason::CrossValidationFailed => HealthStatus::Unhealthy,
+            _ => HealthStatus::Degraded,
+        }
+    }
+}
+
+/// The health of a session, computed without network calls
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SessionHealth {
+    pub status: HealthStatus,
+
+    /// Why the status is not healthy, empty if it is
+    pub reasons: Vec<HealthReason>,
+
+    /// Whether the last call to the server succeeded
+    pub connected: bool,
+
+    /// The seconds since the last answer of the server, if any
+    pub last_message_age: Option<u64>,
+
+    /// The tip height reported by the server, if any
+    pub server_tip_height: Option<u32>,
+
+    /// The height of the SPV headers chain, if verifying transactions with it
+    pub local_tip_height: Option<u32>,
+
+    /// Whether the last SPV cross-validation succeeded, if any
+    pub cross_validation_valid: Option<bool>,
+
+    /// The seconds since the store was last written, if it was since login
+    pub store_flush_age: Option<u64>,
+
+    /// The seconds since the end of the last successful sync, if any
+    pub last_sync_age: Option<u64>,
+
+    /// The number of script changes notified by the server and not synced yet
+    pub sync_backlog: u64,
+}
+
+/// The tip of the server and the progress of the headers verified locally
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlockStatus {
+    /// The height of the SPV headers chain, None if not verifying
+    /// transactions with it or until the headers are loaded
+    pub local_header_height: Option<u32>,
+
+    /// The height of the tip reported by the server
+    pub server_tip_height: u32,
+
+    /// The hash of the tip reported by the server
+    pub server_tip_hash: String,
+
+    /// Whether the headers chain reached the server tip, always true
+    /// without the headers chain
+    pub in_sync: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetWalletIdentifierOpt {
+    /// The network parameters, its name contributes to the wallet identifier
+    pub network: NetworkParameters,
+
+    /// The credentials of the wallet, or its master extended public key
+    #[serde(flatten)]
+    pub credentials: WalletIdentifierCredentials,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum WalletIdentifierCredentials {
+    Mnemonic(Credentials),
+    MasterXpub {
+        master_xpub: Xpub,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WalletIdentifier {
+    pub wallet_hash_id: String,
+    pub xpub_hash_id: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -100,14 +327,40 @@ pub struct GetTransactionsOpt {
     pub count: usize,
     pub subaccount: u32,
     pub num_confs: Option<u32>,
+    /// Only return transactions changing the balance of this asset,
+    /// "btc" is the only valid value for Bitcoin.
+    pub asset_id: Option<String>,
+    /// Only return transactions already known at this sync generation, as
+    /// returned in a previous page, so that new transactions don't shift pages.
+    pub sync_generation: Option<u64>,
+    /// Only return transactions created at or after this timestamp, in microseconds.
+    pub created_at_from: Option<u64>,
+    /// Only return transactions created at or before this timestamp, in microseconds.
+    pub created_at_to: Option<u64>,
+    /// Only return transactions of this type, eg. "incoming".
+    #[serde(rename = "type")]
+    pub type_: Option<TransactionType>,
+    /// Only return transactions changing the balance of `asset_id`, or of the
+    /// policy asset if not set, by at least this amount.
+    pub min_satoshi: Option<u64>,
+    /// The decimal places of the Liquid assets, as in the registry, to format
+    /// their amounts. The assets not listed are formatted in their smallest unit.
+    #[serde(default)]
+    pub asset_precisions: HashMap<String, u8>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct GetBalanceOpt {
     pub subaccount: u32,
-    pub num_confs: u32,
+    /// The subaccount or wallet `required_num_confs` setting if not set
+    pub num_confs: Option<u32>,
     #[serde(rename = "confidential")]
     pub confidential_utxos_only: Option<bool>,
+    /// Only return the balance of this asset, zero if the wallet never received it.
+    pub asset_id: Option<String>,
+    /// The subaccount or wallet `trust_own_unconfirmed` setting if not set
+    #[serde(default)]
+    pub trust_own_unconfirmed: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -117,6 +370,33 @@ pub struct GetUnspentOpt {
     #[serde(rename = "confidential")]
     pub confidential_utxos_only: Option<bool>,
     pub all_coins: Option<bool>, // unused
+    /// Only return the utxos of this asset.
+    pub asset_id: Option<String>,
+    /// The subaccount or wallet `trust_own_unconfirmed` setting if not set
+    #[serde(default)]
+    pub trust_own_unconfirmed: Option<bool>,
+    /// The decimal places of the Liquid assets, as in the registry, to format
+    /// their amounts. The assets not listed are formatted in their smallest unit.
+    #[serde(default)]
+    pub asset_precisions: HashMap<String, u8>,
+    /// Include the provenance of the outputs created by the wallet
+    /// transactions, such as the change
+    #[serde(default)]
+    pub include_provenance: bool,
+}
+
+/// The options of `get_balance_at` and `get_unspent_outputs_at`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct GetAtHeightOpt {
+    pub subaccount: u32,
+    /// The height of the block, the state is the one once it is connected.
+    pub height: u32,
+    /// Only return the balance or the utxos of this asset.
+    pub asset_id: Option<String>,
+    /// The decimal places of the Liquid assets, as in the registry, to format
+    /// their amounts. The assets not listed are formatted in their smallest unit.
+    #[serde(default)]
+    pub asset_precisions: HashMap<String, u8>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -132,6 +412,78 @@ pub struct LoadStoreOpt {
     /// Used for rich watch only sessions.
     pub filename: Option<String>,
     pub encryption_key_hex: Option<String>,
+    /// When the wallet was created, if restoring it, replacing the one
+    /// persisted if any
+    #[serde(default)]
+    pub wallet_birthday: Option<WalletBirthday>,
+}
+
+/// When a wallet was created, so that restoring it doesn't download the
+/// headers from genesis
+///
+/// The SPV headers chain starts at the checkpoint below the birthday. The
+/// transactions confirmed before it are still synced, unless `asserted`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct WalletBirthday {
+    /// The height of the first block the wallet may have transactions in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+
+    /// The unix timestamp in seconds of the creation of the wallet, the
+    /// session resolves it to a height with the server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+
+    /// Whether the user asserts the wallet has no transaction confirmed
+    /// before the birthday, which are then ignored by the sync
+    #[serde(default)]
+    pub asserted: bool,
+}
+
+impl WalletBirthday {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.height.is_none() && self.timestamp.is_none() {
+            return Err(Error::Generic("the wallet birthday needs a height or a timestamp".into()));
+        }
+        Ok(())
+    }
+}
+
+/// The composition of the wallet of a session, from its store
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WalletInfo {
+    pub wallet_hash_id: Option<String>,
+
+    /// The name of the network of the session
+    pub network: String,
+
+    /// Whether the session can't sign, eg. logged in with an xpub
+    pub watch_only: bool,
+
+    pub subaccounts: Vec<WalletInfoSubaccount>,
+
+    /// The sum of the balances of all the subaccounts, by asset, with the
+    /// unconfirmed transactions
+    pub balances: Balances,
+
+    /// The birthday of the wallet, with its height once resolved
+    pub wallet_birthday: Option<WalletBirthday>,
+
+    /// The version of the format of the store of the wallet
+    pub store_version: u32,
+
+    /// Whether the mnemonic of the wallet has a BIP39 passphrase, None if the
+    /// wallet was never logged in with its mnemonic
+    pub bip39_passphrase: Option<bool>,
+}
+
+/// A subaccount in [`WalletInfo`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WalletInfoSubaccount {
+    pub pointer: u32,
+
+    #[serde(rename = "type")]
+    pub script_type: ScriptType,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -153,9 +505,111 @@ pub struct SetMasterBlindingKeyOpt {
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct GetAddressOpt {
     pub subaccount: u32,
-    pub address_type: Option<String>,   // unused
-    pub is_internal: Option<bool>,      // true = get an internal change address
-    pub ignore_gap_limit: Option<bool>, // true = allow to return addresses beyond the gap limit
+    pub address_type: Option<String>,    // unused
+    pub is_internal: Option<bool>,       // true = get an internal change address
+    pub ignore_gap_limit: Option<bool>,  // true = allow to return addresses beyond the gap limit
+    pub confirm_on_device: Option<bool>, // true = display the address on the signer and check it
+    /// Expect a payment of this amount to the address, see [`ExpectedPayment`]
+    #[serde(default)]
+    pub expected_satoshi: Option<u64>,
+    /// The asset of the expected payment, the policy asset if not set
+    #[serde(default)]
+    pub expected_asset: Option<String>,
+    /// The seconds after which the expected payment is late, never if not set
+    #[serde(default)]
+    pub expiry_seconds: Option<u64>,
+}
+
+/// The status of an [`ExpectedPayment`]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentStatus {
+    /// Not paid yet
+    Pending,
+    /// Not paid before its expiry
+    Expired,
+    /// Paid the expected amount
+    Exact,
+    /// Paid less than the expected amount
+    Underpaid,
+    /// Paid more than the expected amount
+    Overpaid,
+    /// Paid after its expiry
+    Late,
+}
+
+/// A payment expected to a receive address, registered by `get_receive_address`
+///
+/// The first transaction paying the address settles it, the amounts of its
+/// outputs to the address are summed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExpectedPayment {
+    pub subaccount: u32,
+    pub address: String,
+    pub pointer: u32,
+    /// None in the notifications of sessions in privacy mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub satoshi: Option<u64>,
+    /// "btc" on Bitcoin
+    pub asset_id: String,
+    /// unix time in seconds of when the payment was registered
+    pub created_at: u64,
+    /// unix time in seconds after which the payment is late, None if never
+    pub expires_at: Option<u64>,
+    pub status: PaymentStatus,
+    /// The transaction that paid the address, None if still pending
+    #[serde(rename = "txhash")]
+    pub txid: Option<String>,
+    /// The amount of the asset paid to the address, None in the
+    /// notifications of sessions in privacy mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub received_satoshi: Option<u64>,
+}
+
+impl ExpectedPayment {
+    /// The status at `now`, pending payments expire
+    pub fn status_at(&self, now: u64) -> PaymentStatus {
+        match self.status {
+            PaymentStatus::Pending if self.expires_at.is_some_and(|at| at <= now) => {
+                PaymentStatus::Expired
+            }
+            status => status,
+        }
+    }
+
+    /// Settle the payment with `received_satoshi` paid by `txid` at `now`
+    pub fn settle(&mut self, txid: String, received_satoshi: u64, now: u64) {
+        let satoshi = self.satoshi.unwrap_or(0);
+        self.status = if self.expires_at.is_some_and(|at| at <= now) {
+            PaymentStatus::Late
+        } else if received_satoshi < satoshi {
+            PaymentStatus::Underpaid
+        } else if received_satoshi > satoshi {
+            PaymentStatus::Overpaid
+        } else {
+            PaymentStatus::Exact
+        };
+        self.txid = Some(txid);
+        self.received_satoshi = Some(received_satoshi);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct GetExpectedPaymentsOpt {
+    /// Only return the payments expected to this subaccount
+    pub subaccount: Option<u32>,
+    /// Also return the payments that expired unpaid
+    #[serde(default)]
+    pub include_expired: bool,
+    /// Also return the payments already settled
+    #[serde(default)]
+    pub include_paid: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExpectedPayments {
+    /// Oldest first
+    pub payments: Vec<ExpectedPayment>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -172,6 +626,84 @@ pub struct CreateAccountOpt {
     pub allow_gaps: bool,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreateMultisigAccountOpt {
+    #[serde(default)]
+    pub name: String,
+    /// The `wsh(sortedmulti(k,...))` descriptor of the subaccount, one of its
+    /// keys being the one returned by `get_multisig_key`
+    pub descriptor: String,
+}
+
+/// The keys of a Green 2of2 or 2of3 subaccount, as exported for its recovery
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GreenMultisigTemplate {
+    /// The Green service key of the subaccount, its keys being at `/pointer`
+    pub service_xpub: Xpub,
+    /// The user key of the subaccount, its keys being at `/1/pointer`
+    pub user_xpub: Xpub,
+    /// The recovery key of 2of3 subaccounts, its keys being at `/1/pointer`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recovery_xpub: Option<Xpub>,
+    /// The CSV blocks of the 2of2 CSV addresses of the subaccount, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub csv_blocks: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreateGreenMultisigAccountOpt {
+    #[serde(default)]
+    pub name: String,
+    #[serde(flatten)]
+    pub template: GreenMultisigTemplate,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreateRecoveryTransactionsOpt {
+    /// A Green subaccount with CSV addresses
+    pub subaccount: u32,
+    /// The address receiving the funds of the recovery transactions
+    pub address: String,
+    /// The extended private key of the user key of the Green subaccount
+    pub xprv: Xpriv,
+    /// The fee rate in satoshi per 1000 vbytes, the default one if None
+    pub fee_rate: Option<u64>,
+}
+
+/// A transaction spending a utxo of a CSV address with the user key alone
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecoveryTransaction {
+    /// The txid of the utxo spent
+    pub txhash: String,
+    /// The vout of the utxo spent
+    pub pt_idx: u32,
+    pub satoshi: u64,
+    /// The height from which the transaction can be mined, None while the
+    /// utxo is unconfirmed
+    pub expiry_height: Option<u32>,
+    /// Whether the transaction can be mined in the next block
+    pub is_expired: bool,
+    /// The signed transaction, in hex
+    pub transaction: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecoveryTransactionsResult {
+    /// The tip height when the transactions were created
+    pub block_height: u32,
+    /// A transaction for each utxo of the CSV addresses, to be created again
+    /// when they change
+    pub transactions: Vec<RecoveryTransaction>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MultisigKeyResult {
+    /// The next multisig subaccount, the one the key is of
+    pub subaccount: u32,
+    /// The extended public key with its origin, `[fingerprint/48'/coin'/n'/2']xpub`
+    pub key: String,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DiscoverAccountOpt {
     #[serde(rename = "type")]
@@ -193,9 +725,10 @@ pub struct GetLastEmptyAccountOpt {
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct GetAvailableCurrenciesParams {
-    /// The url to use to fetch the available currency pairs.
+    /// The url to use to fetch the available currency pairs, if missing the
+    /// price url of the network is used.
     #[serde(rename = "currency_url")]
-    pub url: String,
+    pub url: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -364,18 +897,25 @@ pub struct GetTxInOut {
     /// None for not relevant Liquid inputs (for which the address is the empty string).
     #[serde(rename = "script")]
     pub script_pubkey: String,
+
+    /// The label of the output, for inputs the label of the output spent.
+    ///
+    /// None if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
 }
 
 /// Transaction type
 ///
 /// Note that the follwing types might be inaccurate for complex
 /// transactions such as coinjoins or involving multiple (sub)accounts.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum TransactionType {
     Unknown,
     Incoming,
     Outgoing,
+    /// All the value but the fee goes back to the subaccount
     Redeposit,
     #[serde(rename = "not unblindable")]
     NotUnblindable,
@@ -397,6 +937,229 @@ impl TransactionType {
     }
 }
 
+/// Why a transaction is no longer part of the wallet history returned by the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxRemoval {
+    /// Another transaction of the wallet spends some of its inputs
+    Replaced,
+    /// Evicted from the mempool without a conflicting transaction
+    Dropped,
+}
+
+/// The chain reorganizations that removed the block confirming a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct TxReorgs {
+    /// How many times the transaction lost its confirmation
+    pub count: u32,
+    /// The height of the last block removed that confirmed it
+    pub last_height: u32,
+}
+
+/// A session event delivered to subscribers, see
+/// [`NativeNotif::subscribe`](crate::notification::NativeNotif::subscribe).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Notification {
+    /// A new tip has been found
+    Block {
+        height: u32,
+        hash: bitcoin::BlockHash,
+        previous_hash: bitcoin::BlockHash,
+        timestamp: u32,
+        /// The new tip does not extend the previously notified one
+        reorg: bool,
+        /// Only set on reorgs, if known
+        common_ancestor_height: Option<u32>,
+    },
+
+    /// A transaction affecting the wallet has been seen or updated
+    Transaction {
+        txid: bitcoin::Txid,
+        subaccounts: Vec<u32>,
+        /// None if Liquid or if privacy mode is enabled
+        satoshi: Option<u64>,
+        /// None if Liquid
+        #[serde(rename = "type")]
+        type_: Option<TransactionType>,
+        /// None if unconfirmed
+        block_height: Option<u32>,
+        /// The net amount for each asset, None if privacy mode is enabled
+        satoshi_delta: Option<Balances>,
+        /// The transaction has been evicted from the mempool or replaced
+        removed: bool,
+    },
+
+    /// The connection state changed
+    Network {
+        connected: bool,
+        /// Singlesig sessions never need to login again after a reconnection
+        login_required: bool,
+        next_state: crate::State,
+        /// The server the session switched to, only set when it changes
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        server: Option<String>,
+    },
+
+    /// The wallet settings changed
+    Settings(Settings),
+
+    /// A new subaccount has been created
+    Subaccount {
+        pointer: u32,
+    },
+
+    /// A subaccount completed its first sync
+    SyncProgress {
+        subaccount: u32,
+    },
+
+    /// Transactions have been verified against the headers chain
+    SpvStatus {
+        verified: usize,
+    },
+
+    /// Notifications have been dropped while nobody was listening
+    QueueOverflow {
+        dropped: usize,
+    },
+
+    /// A receive address already paid by other transactions received another
+    /// payment
+    AddressReuse {
+        subaccount: u32,
+        address: String,
+        pointer: u32,
+        /// The transaction of the new payment
+        txid: bitcoin::Txid,
+        /// The transactions that paid the address before
+        previous_txids: Vec<bitcoin::Txid>,
+    },
+
+    /// The local clock is off the time of the network, its timestamps, like
+    /// the ones of the transaction list, are off as well
+    ClockSkew {
+        /// The seconds the local clock is ahead of the network, negative if behind
+        skew_seconds: i64,
+    },
+
+    /// The block confirming a wallet transaction was removed by a chain
+    /// reorganization, the transaction is unconfirmed or in another block
+    TransactionReorged {
+        subaccount: u32,
+        txid: bitcoin::Txid,
+        /// The height of the block removed
+        height: u32,
+        /// How many times the transaction lost its confirmation, this one included
+        reorg_count: u32,
+    },
+
+    /// A payment expected to a receive address has been received
+    ExpectedPayment(ExpectedPayment),
+
+    /// A refresh of the asset registry started in the background downloaded
+    /// some more bytes
+    AssetsRefreshProgress {
+        refresh_id: u64,
+        /// Either "assets" or "icons"
+        what: String,
+        downloaded: u64,
+        /// None if the registry didn't announce the size
+        total: Option<u64>,
+    },
+
+    /// A file downloaded by a refresh of the asset registry was not used,
+    /// eg. because its signature is invalid, the local one is kept
+    AssetsRefreshWarning {
+        refresh_id: u64,
+        message: String,
+    },
+
+    /// A refresh of the asset registry started in the background completed,
+    /// `error` is set if it failed
+    AssetsRefreshCompleted {
+        refresh_id: u64,
+        /// The local registry was already up to date
+        not_modified: bool,
+        error: Option<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetLastNotificationOpt {
+    #[serde(rename = "type")]
+    pub kind: NotificationKind,
+}
+
+/// The variants of [`Notification`], used to filter subscriptions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    Block,
+    Transaction,
+    Network,
+    Settings,
+    Subaccount,
+    SyncProgress,
+    SpvStatus,
+    QueueOverflow,
+    AddressReuse,
+    ClockSkew,
+    TransactionReorged,
+    ExpectedPayment,
+    AssetsRefreshProgress,
+    AssetsRefreshWarning,
+    AssetsRefreshCompleted,
+}
+
+impl Notification {
+    pub fn kind(&self) -> NotificationKind {
+        match self {
+            Notification::Block {
+                ..
+            } => NotificationKind::Block,
+            Notification::Transaction {
+                ..
+            } => NotificationKind::Transaction,
+            Notification::Network {
+                ..
+            } => NotificationKind::Network,
+            Notification::Settings(_) => NotificationKind::Settings,
+            Notification::Subaccount {
+                ..
+            } => NotificationKind::Subaccount,
+            Notification::SyncProgress {
+                ..
+            } => NotificationKind::SyncProgress,
+            Notification::SpvStatus {
+                ..
+            } => NotificationKind::SpvStatus,
+            Notification::QueueOverflow {
+                ..
+            } => NotificationKind::QueueOverflow,
+            Notification::AddressReuse {
+                ..
+            } => NotificationKind::AddressReuse,
+            Notification::ClockSkew {
+                ..
+            } => NotificationKind::ClockSkew,
+            Notification::TransactionReorged {
+                ..
+            } => NotificationKind::TransactionReorged,
+            Notification::ExpectedPayment(_) => NotificationKind::ExpectedPayment,
+            Notification::AssetsRefreshProgress {
+                ..
+            } => NotificationKind::AssetsRefreshProgress,
+            Notification::AssetsRefreshWarning {
+                ..
+            } => NotificationKind::AssetsRefreshWarning,
+            Notification::AssetsRefreshCompleted {
+                ..
+            } => NotificationKind::AssetsRefreshCompleted,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct TxListItem {
     pub block_height: u32,
@@ -406,11 +1169,18 @@ pub struct TxListItem {
     pub memo: String,
     pub txhash: String,
     pub satoshi: Balances,
+    /// The `satoshi` amounts formatted in the `unit` setting, or with the
+    /// precision of their asset
+    #[serde(default)]
+    pub amounts: HashMap<String, String>,
     pub rbf_optin: bool,
     pub can_cpfp: bool,
     pub can_rbf: bool,
     pub spv_verified: String,
     pub fee: u64,
+    /// The fee formatted in the `unit` setting
+    #[serde(default)]
+    pub fee_amount: String,
     pub fee_rate: u64,
     pub inputs: Vec<GetTxInOut>,
     pub outputs: Vec<GetTxInOut>,
@@ -419,37 +1189,191 @@ pub struct TxListItem {
     pub transaction_vsize: usize,
     pub transaction_weight: usize,
     pub discount_weight: usize,
+    /// The policy asset amount valued at the exchange rate of when the
+    /// transaction got confirmed, None if it was not recorded
+    pub fiat_at_confirmation: Option<FiatAtConfirmation>,
+    /// Set if the transaction was replaced or dropped, it is kept in the list
+    /// but no longer counted in the balance
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub removed: Option<TxRemoval>,
+    /// For redeposits, the addresses of the subaccount the transaction pays
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub internal_destinations: Vec<String>,
+    /// How many times chain reorganizations removed the block confirming
+    /// the transaction
+    #[serde(default)]
+    pub reorg_count: u32,
+    /// The height of the last block confirming the transaction removed by a
+    /// reorganization, None if never reorged
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_reorg_height: Option<u32>,
+    /// The hash of the block confirming the transaction, None if unconfirmed
+    /// or if its header is not downloaded yet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_hash: Option<String>,
+    /// The timestamp in seconds of the block confirming the transaction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_time: Option<u32>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct AccountInfo {
-    #[serde(rename = "pointer")]
-    pub account_num: u32,
-    #[serde(rename = "type")]
-    pub script_type: ScriptType,
+/// A fiat value at a past exchange rate
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FiatAtConfirmation {
+    pub fiat: String,
+    pub fiat_currency: String,
+    pub fiat_rate: String,
+}
+
+/// Input and output element for get_transaction_details
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TxDetailsInOut {
     #[serde(flatten)]
-    pub settings: AccountSettings,
-    pub required_ca: u32,     // unused, always 0
-    pub receiving_id: String, // unused, always ""
-    pub bip44_discovered: bool,
-    pub user_path: Vec<ChildNumber>,
-    pub core_descriptors: Vec<String>,
+    pub inout: GetTxInOut,
+
+    /// For inputs, the txid of the spent output.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub slip132_extended_pubkey: Option<String>,
+    pub prevout_txhash: Option<String>,
+
+    /// For inputs, the vout of the spent output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prevout_pt_idx: Option<u32>,
+
+    /// The asset commitment, for Liquid elements that cannot be unblinded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asset_commitment: Option<String>,
+
+    /// The value commitment, for Liquid elements that cannot be unblinded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_commitment: Option<String>,
+
+    /// The type of the output script, "unknown" for non-standard scripts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub script_type: Option<String>,
+
+    /// The hex of the data carried by an OP_RETURN output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub op_return_data: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Credentials {
-    pub mnemonic: String,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetRawTransactionOpt {
+    pub txid: String,
+
+    /// Keep the transaction in the cache if fetched from the server, apart
+    /// from the wallet transactions.
     #[serde(default)]
-    pub bip39_passphrase: String,
+    pub cache: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Where a transaction returned by `get_raw_transaction` comes from
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
-pub enum WatchOnlyCredentials {
-    Slip132ExtendedPubkeys(Vec<String>),
-    CoreDescriptors(Vec<String>),
+pub enum RawTransactionSource {
+    /// The wallet transactions in the cache
+    Wallet,
+
+    /// The transactions not of the wallet kept in the cache
+    Foreign,
+
+    /// Fetched from the server
+    Server,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetRawTransactionResult {
+    pub txhash: String,
+
+    /// The serialized transaction, including the confidential data on
+    /// Liquid.
+    pub transaction: String,
+
+    pub source: RawTransactionSource,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionDetails {
+    pub txhash: String,
+    /// The transaction hex
+    pub transaction: String,
+    /// True if the transaction does not belong to the wallet and was fetched from the server
+    pub not_wallet_tx: bool,
+    /// 0 if unconfirmed
+    pub block_height: u32,
+    pub confirmations: u32,
+    /// None if the value of some inputs is unknown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee: Option<u64>,
+    pub memo: String,
+    pub spv_verified: String,
+    pub inputs: Vec<TxDetailsInOut>,
+    pub outputs: Vec<TxDetailsInOut>,
+    /// The net effect of the transaction on the balance of each subaccount involved
+    pub subaccounts: HashMap<u32, Balances>,
+    pub transaction_vsize: usize,
+    pub transaction_weight: usize,
+    /// How the wallet broadcast the transaction, None if it was not sent by
+    /// the wallet or before the broadcasts were recorded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub broadcast: Option<BroadcastDetails>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AccountInfo {
+    #[serde(rename = "pointer")]
+    pub account_num: u32,
+    #[serde(rename = "type")]
+    pub script_type: ScriptType,
+    #[serde(flatten)]
+    pub settings: AccountSettings,
+    pub required_ca: u32,     // unused, always 0
+    pub receiving_id: String, // unused, always ""
+    pub bip44_discovered: bool,
+    pub user_path: Vec<ChildNumber>,
+    pub core_descriptors: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slip132_extended_pubkey: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Credentials {
+    pub mnemonic: String,
+    #[serde(default)]
+    pub bip39_passphrase: String,
+}
+
+/// The wallet to log in with the credentials kept for quick login
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QuickLoginOpt {
+    pub wallet_hash_id: String,
+}
+
+/// The username and password of the watch-only access to a wallet
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct WatchOnlyUserCredentials {
+    pub username: String,
+    pub password: Password,
+}
+
+impl Credentials {
+    /// The master extended public key of the wallet on `net_params`
+    pub fn master_xpub(&self, net_params: &NetworkParameters) -> Result<Xpub, Error> {
+        let xprv = Xpriv::new_master(net_params.bip32_network(), &self.seed()?)?;
+        Ok(Xpub::from_priv(&crate::EC, &xprv))
+    }
+
+    /// The BIP39 seed of the mnemonic and the passphrase
+    pub fn seed(&self) -> Result<[u8; 64], Error> {
+        let mnemonic = bip39::Mnemonic::parse(&self.mnemonic)
+            .map_err(|e| Error::Generic(format!("invalid mnemonic: {}", e)))?;
+        Ok(mnemonic.to_seed(&self.bip39_passphrase))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchOnlyCredentials {
+    Slip132ExtendedPubkeys(Vec<String>),
+    CoreDescriptors(Vec<String>),
 }
 
 /// An intermediate struct to hold account data
@@ -604,12 +1528,136 @@ pub struct AddressPointer {
     pub is_confidential: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub unconfidential_address: Option<String>,
+    /// Whether the signer displayed the address, computing the same one
+    #[serde(default)]
+    pub device_confirmed: bool,
 }
 
 // This one is simple enough to derive a serializer
 #[derive(Serialize, Debug, Clone, Deserialize)]
 pub struct FeeEstimate(pub u64);
-pub struct TxsResult(pub Vec<TxListItem>);
+
+/// The fee estimates returned by `get_fee_estimates`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FeeEstimates {
+    pub fees: Vec<FeeEstimate>,
+
+    /// Whether the server could not be asked recently, the estimates are
+    /// the last known ones, possibly from a previous session, or the
+    /// minimum fee rate if the wallet never got any
+    pub stale: bool,
+
+    /// Unix time in seconds of when the estimates were got from the server,
+    /// None if never
+    pub fetched_at: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SearchTransactionsOpt {
+    /// Matched against the memos and the output labels ignoring case, the
+    /// empty query matches all the transactions with a memo or a label
+    pub query: String,
+}
+
+/// The label of a transaction output
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OutputLabel {
+    pub txhash: String,
+    pub pt_idx: u32,
+    /// The empty label removes it
+    pub label: String,
+}
+
+/// The memos and the output labels of a wallet, exported by `export_labels`
+/// and imported by `import_labels`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct WalletLabels {
+    /// The memos by txid
+    #[serde(default)]
+    pub memos: HashMap<String, String>,
+    /// Sorted by outpoint
+    #[serde(default)]
+    pub output_labels: Vec<OutputLabel>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SearchTransactionsResult {
+    /// The matching transactions of all the subaccounts, unconfirmed first
+    /// then by descending height
+    pub transactions: Vec<FoundTransaction>,
+}
+
+/// A transaction found by `search_transactions`, listed once per subaccount
+/// it belongs to
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FoundTransaction {
+    pub subaccount: u32,
+
+    #[serde(flatten)]
+    pub transaction: TxListItem,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExportTransactionsOpt {
+    pub format: ExportFormat,
+    pub subaccount: u32,
+    /// Only export transactions created at or after this timestamp, in microseconds.
+    pub created_at_from: Option<u64>,
+    /// Only export transactions created at or before this timestamp, in microseconds.
+    pub created_at_to: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ExportTransactionsResult {
+    /// The CSV text, or the JSON array of [`ExportedTransaction`]
+    pub data: String,
+}
+
+/// A transaction of an export, oldest first
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExportedTransaction {
+    /// Unix time in seconds
+    pub timestamp: u64,
+    pub txid: String,
+    pub direction: TransactionType,
+    /// The balance changes of the subaccount by asset, fee included
+    pub amounts: Balances,
+    pub fee: u64,
+    pub memo: String,
+    /// The recipient for outgoing transactions, otherwise the receiving address
+    pub address: String,
+    /// None if unconfirmed
+    pub height: Option<u32>,
+    /// The value of the policy asset amount at the exchange rate of when the
+    /// transaction got confirmed, None if it was not recorded
+    pub fiat_value: Option<String>,
+    pub fiat_currency: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BackfillFiatResult {
+    /// The number of transactions given a rate
+    pub backfilled: usize,
+    /// The number of confirmed transactions still without a rate
+    pub missing: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TxsResult {
+    /// The requested page of transactions, most recent first
+    pub transactions: Vec<TxListItem>,
+    /// The number of transactions matching the filters, in all pages
+    pub total: usize,
+    /// The sync generation of the listing, to pass when requesting further pages
+    pub sync_generation: u64,
+}
 
 /// Change to the model of Settings and Pricing structs could break old versions.
 /// You can't remove fields, change fields type and if you add a new field, it must be Option<T>
@@ -620,12 +1668,46 @@ pub struct Settings {
     pub altimeout: u32,
     pub pricing: Pricing,
     pub sound: bool,
+    /// Incremented on every change, to detect changes made by other sessions
+    pub revision: Option<u64>,
+    pub spend_limits: Option<SpendLimits>,
+    /// Seconds after which the client request ids of sent transactions are
+    /// forgotten, a week if not set
+    pub client_request_expiry: Option<u64>,
+    /// Confirmations an utxo needs to be counted in the balance and listed as
+    /// unspent, when not given by the caller. None if not set
+    pub required_num_confs: Option<u32>,
+    /// Whether the unconfirmed outputs of transactions spending our own
+    /// coins, such as our change, are counted regardless of
+    /// `required_num_confs`. True if not set
+    pub trust_own_unconfirmed: Option<bool>,
+    /// Seconds after which the fee estimates are too old to pick the fee rate
+    /// of transactions not given one, an hour if not set
+    pub max_fee_estimates_age: Option<u64>,
 }
 
+/// The default of [`Settings::client_request_expiry`]
+pub const CLIENT_REQUEST_EXPIRY_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// The default of [`Settings::max_fee_estimates_age`]
+pub const MAX_FEE_ESTIMATES_AGE_SECS: u64 = 60 * 60;
+
 impl Settings {
+    pub fn revision(&self) -> u64 {
+        self.revision.unwrap_or(0)
+    }
+
+    pub fn client_request_expiry(&self) -> u64 {
+        self.client_request_expiry.unwrap_or(CLIENT_REQUEST_EXPIRY_SECS)
+    }
+
+    pub fn max_fee_estimates_age(&self) -> u64 {
+        self.max_fee_estimates_age.unwrap_or(MAX_FEE_ESTIMATES_AGE_SECS)
+    }
+
     pub fn update(&mut self, json: &serde_json::Value) -> Result<(), Error> {
         if let Some(unit) = json.get("unit").and_then(|v| v.as_str()) {
-            self.unit = unit.to_string();
+            self.unit = Unit::from_str(unit).map_err(Error::Generic)?.to_string();
         }
         if let Some(required_num_blocks) = json.get("required_num_blocks").and_then(|v| v.as_u64())
         {
@@ -650,14 +1732,272 @@ impl Settings {
         if let Some(sound) = json.get("sound").and_then(|v| v.as_bool()) {
             self.sound = sound;
         }
+        if let Some(spend_limits) = json.get("spend_limits") {
+            self.spend_limits = serde_json::from_value(spend_limits.clone())
+                .map_err(|e| Error::Generic(format!("invalid spend limits: {}", e)))?;
+        }
+        if let Some(expiry) = json.get("client_request_expiry").and_then(|v| v.as_u64()) {
+            self.client_request_expiry = Some(expiry);
+        }
+        if let Some(num_confs) = json.get("required_num_confs").and_then(|v| v.as_u64()) {
+            self.required_num_confs = Some(num_confs as u32);
+        }
+        if let Some(trust) = json.get("trust_own_unconfirmed").and_then(|v| v.as_bool()) {
+            self.trust_own_unconfirmed = Some(trust);
+        }
+        if let Some(age) = json.get("max_fee_estimates_age").and_then(|v| v.as_u64()) {
+            self.max_fee_estimates_age = Some(age);
+        }
         Ok(())
     }
 }
 
+/// Limits on the amount sent out of the wallet, fee included. Transactions
+/// exceeding them must be approved before being sent.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct SpendLimits {
+    /// Maximum satoshi sent by a single transaction
+    pub per_transaction: Option<u64>,
+
+    /// Maximum satoshi sent in the last 24 hours, this transaction included
+    pub daily: Option<u64>,
+}
+
+impl SpendLimits {
+    /// Whether sending `satoshi` after `spent_last_day` exceeds the limits
+    pub fn exceeded(&self, satoshi: u64, spent_last_day: u64) -> bool {
+        self.per_transaction.is_some_and(|limit| satoshi > limit)
+            || self.daily.is_some_and(|limit| satoshi.saturating_add(spent_last_day) > limit)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SpendLimitsCheck {
+    /// Satoshi sent out of the wallet by the transaction, fee included
+    pub satoshi: u64,
+
+    /// Satoshi sent out of the wallet in the last 24 hours
+    pub spent_last_day: u64,
+
+    /// The transaction must be approved with `approve_transaction` before sending
+    pub requires_confirmation: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SendTransactionOpt {
+    /// The signed transaction hex
+    pub transaction: String,
+
+    /// Token returned by `approve_transaction`, needed if the transaction
+    /// exceeds the spend limits
+    pub confirmation_token: Option<String>,
+
+    /// Attempt a payjoin if the transaction pays a URI with a payjoin endpoint
+    pub payjoin: Option<PayjoinOpt>,
+
+    /// An id chosen by the caller to send a payment only once: sending again
+    /// with the same id returns the transaction sent the first time, as long
+    /// as the server has it
+    #[serde(default)]
+    pub client_request_id: Option<String>,
+
+    /// Check the transaction with `check_mempool_acceptance` before
+    /// broadcasting it, failing with the reason it would be rejected
+    #[serde(default)]
+    pub check_acceptance: bool,
+}
+
+/// The transaction to check with `check_mempool_acceptance`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CheckMempoolAcceptanceOpt {
+    /// The signed transaction hex
+    pub transaction: String,
+}
+
+/// Why the nodes reject a transaction from their mempool
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RejectReason {
+    /// An output spent by the transaction doesn't exist or is already spent
+    MissingInputs,
+    /// An output spent by the transaction is spent by another one in the mempool
+    TxnMempoolConflict,
+    /// The fee rate is below the minimum relayed
+    MinRelayFeeNotMet,
+    /// The transaction has too many unconfirmed ancestors or descendants
+    TooLongMempoolChain,
+    /// The lock time of the transaction is not reached yet
+    NonFinal,
+    /// An output is below the dust limit
+    Dust,
+    /// The transaction is above the maximum standard weight
+    TxSize,
+    /// An input is not signed
+    MissingWitness,
+}
+
+impl RejectReason {
+    /// The reason of a rejection in an error message of the nodes, as relayed
+    /// by the server, None if not known
+    pub fn from_message(message: &str) -> Option<Self> {
+        let message = message.to_lowercase();
+        let reasons = [
+            ("missingorspent", RejectReason::MissingInputs),
+            ("missing-inputs", RejectReason::MissingInputs),
+            ("missing inputs", RejectReason::MissingInputs),
+            ("txn-mempool-conflict", RejectReason::TxnMempoolConflict),
+            ("min relay fee not met", RejectReason::MinRelayFeeNotMet),
+            ("mempool min fee not met", RejectReason::MinRelayFeeNotMet),
+            ("too-long-mempool-chain", RejectReason::TooLongMempoolChain),
+            ("non-final", RejectReason::NonFinal),
+            ("non-bip68-final", RejectReason::NonFinal),
+            ("dust", RejectReason::Dust),
+            ("tx-size", RejectReason::TxSize),
+            ("empty witness", RejectReason::MissingWitness),
+        ];
+        reasons.iter().find(|(text, _)| message.contains(text)).map(|(_, reason)| *reason)
+    }
+}
+
+impl Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            RejectReason::MissingInputs => "missing-inputs",
+            RejectReason::TxnMempoolConflict => "txn-mempool-conflict",
+            RejectReason::MinRelayFeeNotMet => "min-relay-fee-not-met",
+            RejectReason::TooLongMempoolChain => "too-long-mempool-chain",
+            RejectReason::NonFinal => "non-final",
+            RejectReason::Dust => "dust",
+            RejectReason::TxSize => "tx-size",
+            RejectReason::MissingWitness => "missing-witness",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// Whether the nodes would accept a transaction in their mempool, as
+/// returned by `check_mempool_acceptance`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MempoolAcceptance {
+    pub accepted: bool,
+
+    /// Why the transaction would be rejected, None if accepted
+    pub reason: Option<RejectReason>,
+
+    /// The details of the rejection, None if accepted
+    pub message: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PayjoinOpt {
+    /// The BIP21 URI paid by the transaction
+    pub uri: String,
+
+    /// The subaccount of the transaction inputs, they're signed again in the payjoin
+    pub subaccount: u32,
+
+    /// The extended private key of the subaccount, the session signer is
+    /// used if not given
+    #[serde(default)]
+    pub xprv: Option<Xpriv>,
+
+    /// The maximum fee in satoshi the receiver can deduct from the change,
+    /// defaults to the fee of an input at the fee rate of the transaction
+    #[serde(default)]
+    pub max_additional_fee: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SendTransactionResult {
+    pub txhash: String,
+
+    /// Whether the payjoin transaction has been broadcast instead of the given one
+    pub payjoin: bool,
+
+    /// Why the payjoin attempt failed, if any
+    pub payjoin_error: Option<String>,
+
+    /// How the transaction was broadcast, None if the session is not logged in
+    #[serde(default)]
+    pub broadcast: Option<BroadcastDetails>,
+}
+
+/// The broadcast of a transaction sent by the wallet, kept to reconstruct
+/// what happened to it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BroadcastDetails {
+    /// The transaction hex as broadcast
+    pub transaction: String,
+
+    /// Unix time in seconds of the broadcast
+    pub broadcast_at: u64,
+
+    /// The server that acknowledged the transaction
+    pub server: String,
+
+    /// Whether the server had the transaction in its mempool right after the
+    /// broadcast, None if it could not be asked
+    pub in_mempool: Option<bool>,
+}
+
+/// An electrum server the session connects to, replacing the one of the
+/// network parameters
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ElectrumServer {
+    /// The `host:port` of the server
+    pub url: String,
+
+    #[serde(default)]
+    pub tls: bool,
+
+    /// Whether the TLS certificate must be valid for the domain of `url`
+    #[serde(default)]
+    pub validate_domain: bool,
+}
+
+/// A signed transaction whose broadcast failed because the server could not
+/// be reached, broadcast again when the connection recovers
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PendingBroadcast {
+    pub txhash: String,
+
+    /// The signed transaction in hex
+    pub transaction: String,
+
+    /// Unix time in seconds of when the broadcast failed the first time
+    pub created_at: u64,
+
+    /// Whether it is too old or was rejected by the server and is not
+    /// broadcast again, since its inputs may have been spent
+    pub expired: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetPendingBroadcastsResult {
+    pub pending_broadcasts: Vec<PendingBroadcast>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CancelPendingBroadcastOpt {
+    pub txhash: String,
+}
+
 #[derive(Default, Serialize, Deserialize, Clone, Debug)]
 pub struct AccountSettings {
     pub name: String,
     pub hidden: bool,
+    /// Overrides the wallet `required_num_confs` setting for the subaccount
+    #[serde(default)]
+    pub required_num_confs: Option<u32>,
+    /// Overrides the wallet `trust_own_unconfirmed` setting for the subaccount
+    #[serde(default)]
+    pub trust_own_unconfirmed: Option<bool>,
+    /// The `wsh(sortedmulti(..))` descriptor of multisig subaccounts
+    #[serde(default)]
+    pub multisig_descriptor: Option<String>,
+    /// The keys of watch-only Green multisig subaccounts
+    #[serde(default)]
+    pub green_multisig: Option<GreenMultisigTemplate>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -665,6 +2005,10 @@ pub struct UpdateAccountOpt {
     pub subaccount: u32,
     pub name: Option<String>,
     pub hidden: Option<bool>,
+    #[serde(default)]
+    pub required_num_confs: Option<u32>,
+    #[serde(default)]
+    pub trust_own_unconfirmed: Option<bool>,
 }
 
 /// see comment for struct Settings
@@ -674,6 +2018,16 @@ pub struct Pricing {
     exchange: String,
 }
 
+impl Pricing {
+    pub fn currency(&self) -> &str {
+        &self.currency
+    }
+
+    pub fn exchange(&self) -> &str {
+        &self.exchange
+    }
+}
+
 impl Default for Settings {
     fn default() -> Self {
         let pricing = Pricing {
@@ -686,10 +2040,51 @@ impl Default for Settings {
             altimeout: 5,
             pricing,
             sound: true,
+            revision: None,
+            spend_limits: None,
+            client_request_expiry: None,
+            required_num_confs: None,
+            trust_own_unconfirmed: None,
+            max_fee_estimates_age: None,
         }
     }
 }
 
+/// An amount to convert, exactly one of the amount fields must be set
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ConvertAmountOpt {
+    pub satoshi: Option<i64>,
+    pub btc: Option<String>,
+    pub mbtc: Option<String>,
+    pub ubtc: Option<String>,
+    pub bits: Option<String>,
+    pub fiat: Option<String>,
+
+    /// Overrides the pricing in the settings, needed if not logged in
+    pub pricing: Option<Pricing>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ConvertAmountResult {
+    pub satoshi: i64,
+    pub btc: String,
+    pub mbtc: String,
+    pub ubtc: String,
+    pub bits: String,
+    pub sats: String,
+
+    /// `None` if the exchange rate is not available
+    pub fiat: Option<String>,
+    pub fiat_currency: String,
+    pub fiat_rate: Option<String>,
+
+    /// Unix time in seconds of when the exchange rate was fetched
+    pub fiat_rate_timestamp: Option<u64>,
+
+    /// True if a current exchange rate could not be obtained
+    pub fiat_rate_stale: bool,
+}
+
 impl SPVVerifyTxResult {
     pub fn as_i32(&self) -> i32 {
         match self {
@@ -762,6 +2157,10 @@ pub struct UnspentOutput {
     pub pointer: u32,
     pub pt_idx: u32,
     pub satoshi: u64,
+    /// `satoshi` formatted in the `unit` setting, or with the precision of
+    /// the asset
+    #[serde(default)]
+    pub amount: String,
     pub subaccount: u32,
     pub txhash: String,
     /// `true` iff belongs to internal chain, i.e. is change
@@ -801,6 +2200,16 @@ pub struct UnspentOutput {
     pub value_commitment: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub nonce_commitment: Option<String>,
+
+    /// The label set on the output, None if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+
+    /// The txids of the transactions the output comes from, following the
+    /// wallet inputs of its transaction, nearest first. None if not requested
+    /// or if its transaction doesn't spend from the wallet.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Vec<String>>,
 }
 
 impl TryFrom<Txo> for UnspentOutput {
@@ -834,6 +2243,7 @@ impl TryFrom<Txo> for UnspentOutput {
             is_internal,
             pointer,
             satoshi: txo.satoshi,
+            amount: String::new(),
             sequence: txo.sequence,
             skip_signing: false,
             is_blinded,
@@ -844,6 +2254,8 @@ impl TryFrom<Txo> for UnspentOutput {
             asset_commitment,
             value_commitment,
             nonce_commitment,
+            label: None,
+            provenance: None,
         })
     }
 }
@@ -885,6 +2297,34 @@ pub struct ScriptPubKeyData {
     pub address_type: String,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IsMineOpt {
+    /// An address or a hex script pubkey
+    pub address_or_script: String,
+
+    /// How many scripts to search after the gap window of each chain, to
+    /// find addresses given out beyond the gap limit when recovering
+    #[serde(default)]
+    pub search_depth: u32,
+}
+
+/// A script derived by the wallet
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WalletScript {
+    pub subaccount: u32,
+    pub is_internal: bool,
+    pub pointer: u32,
+    pub user_path: Vec<ChildNumber>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IsMineResult {
+    pub is_mine: bool,
+
+    #[serde(flatten)]
+    pub script: Option<WalletScript>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct GetPreviousAddressesOpt {
     /// The subaccount to get the addresses for.
@@ -963,22 +2403,752 @@ pub struct AddressDataResult {
     pub user_path: Vec<ChildNumber>,
 }
 
-#[cfg(test)]
-mod test {
-    use crate::model::{parse_path, GetUnspentOutputs};
-    use bitcoin::bip32::DerivationPath;
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GetAddressPaymentsOpt {
+    pub subaccount: u32,
 
-    #[test]
-    fn test_path() {
-        let path_external: DerivationPath = "44'/1'/0'/0/0".parse().unwrap();
-        let path_internal: DerivationPath = "44'/1'/0'/1/0".parse().unwrap();
-        assert_eq!(parse_path(&path_external).unwrap(), (false, 0u32));
-        assert_eq!(parse_path(&path_internal).unwrap(), (true, 0u32));
-    }
+    /// The address, or if not given the one at `pointer`
+    pub address: Option<String>,
+    pub pointer: Option<u32>,
+    #[serde(default)]
+    pub is_internal: bool,
+}
 
-    #[test]
-    fn test_unspent() {
-        let json_str = r#"{"btc": [{"address_type": "p2wsh", "block_height": 1806588, "pointer": 3509, "pt_idx": 1, "satoshi": 3650144, "subaccount": 0, "txhash": "08711d45d4867d7834b133a425da065b252eb6a9b206d57e2bbb226a344c5d13", "is_internal": false, "is_blinded": false, "user_path": [2147483692, 2147483649, 2147483648, 0, 1], "prevout_script": "51", "public_key": "020202020202020202020202020202020202020202020202020202020202020202", "asset_id": ""}, {"address_type": "p2wsh", "block_height": 1835681, "pointer": 3510, "pt_idx": 0, "satoshi": 5589415, "subaccount": 0, "txhash": "fbd00e5b9e8152c04214c72c791a78a65fdbab68b5c6164ff0d8b22a006c5221", "is_internal": false, "is_blinded": false, "user_path": [2147483692, 2147483649, 2147483648, 0, 2], "prevout_script": "51", "public_key": "020202020202020202020202020202020202020202020202020202020202020202", "asset_id": ""}, {"address_type": "p2wsh", "block_height": 1835821, "pointer": 3511, "pt_idx": 0, "satoshi": 568158, "subaccount": 0, "txhash": "e5b358fb8366960130b97794062718d7f4fbe721bf274f47493a19326099b811", "is_internal": false, "is_blinded": false, "user_path": [2147483692, 2147483649, 2147483648, 0, 3], "prevout_script": "51", "public_key": "020202020202020202020202020202020202020202020202020202020202020202", "asset_id": ""}]}"#;
-        let _json: GetUnspentOutputs = serde_json::from_str(json_str).unwrap();
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AddressPayments {
+    pub address: String,
+    pub pointer: u32,
+    pub is_internal: bool,
+
+    /// Every output paying the address, oldest first
+    pub payments: Vec<AddressPayment>,
+}
+
+/// An output paying an address of the wallet
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct AddressPayment {
+    pub txhash: String,
+    pub pt_idx: u32,
+    /// Zero if it cannot be unblinded
+    pub satoshi: u64,
+    /// None on Bitcoin or if it cannot be unblinded
+    pub asset_id: Option<String>,
+    /// Zero if unconfirmed
+    pub block_height: u32,
+    pub confirmations: u32,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DiagnoseScriptOpt {
+    pub subaccount: u32,
+
+    /// The address, or if not given the one at `pointer`
+    pub address: Option<String>,
+    pub pointer: Option<u32>,
+    #[serde(default)]
+    pub is_internal: bool,
+}
+
+/// How the server and the store see a script of the wallet, for support
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ScriptDiagnosis {
+    pub address: String,
+    pub pointer: u32,
+    pub is_internal: bool,
+
+    /// The electrum script hash, in hex
+    pub script_hash: String,
+
+    /// Whether the sync loop is subscribed to the script with its current
+    /// connection
+    pub subscribed: bool,
+
+    /// The status of the script history on the server, None if empty
+    pub server_status: Option<String>,
+
+    /// The status last synced, None if never synced
+    pub store_status: Option<String>,
+
+    /// The transactions of the script only known by the server
+    pub only_on_server: Vec<String>,
+
+    /// The transactions of the script only known by the store
+    pub only_in_store: Vec<String>,
+
+    pub height_mismatches: Vec<HeightMismatch>,
+
+    /// The unspent outputs of the script only known by the server, as
+    /// `txid:vout`
+    pub utxos_only_on_server: Vec<String>,
+
+    /// The unspent outputs of the script only known by the store, as
+    /// `txid:vout`
+    pub utxos_only_in_store: Vec<String>,
+}
+
+impl ScriptDiagnosis {
+    /// Whether the store agrees with the server
+    pub fn is_consistent(&self) -> bool {
+        self.only_on_server.is_empty()
+            && self.only_in_store.is_empty()
+            && self.height_mismatches.is_empty()
+            && self.utxos_only_on_server.is_empty()
+            && self.utxos_only_in_store.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VerifyReceiveAddressOpt {
+    pub subaccount: u32,
+    pub pointer: u32,
+    #[serde(default)]
+    pub is_internal: bool,
+
+    /// The address shown to the user, checked against the one at `pointer`
+    pub address: Option<String>,
+}
+
+/// The outcome of a check of [`ReceiveAddressVerification`]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AddressCheckStatus {
+    Passed,
+    Failed,
+    /// The check does not apply or is not configured
+    Skipped,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct AddressCheck {
+    pub status: AddressCheckStatus,
+
+    /// Why the check failed or was skipped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+}
+
+impl AddressCheck {
+    pub fn passed() -> Self {
+        AddressCheck {
+            status: AddressCheckStatus::Passed,
+            details: None,
+        }
+    }
+
+    pub fn failed(details: String) -> Self {
+        AddressCheck {
+            status: AddressCheckStatus::Failed,
+            details: Some(details),
+        }
+    }
+
+    pub fn skipped(details: String) -> Self {
+        AddressCheck {
+            status: AddressCheckStatus::Skipped,
+            details: Some(details),
+        }
+    }
+}
+
+/// The checks of a receive address against a compromised signer or server
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReceiveAddressVerification {
+    pub address: String,
+    pub pointer: u32,
+    pub is_internal: bool,
+
+    /// The address derived again from the account xpub in the store is the
+    /// one of the wallet
+    pub derivation: AddressCheck,
+
+    /// The address given by the caller is the one at `pointer`
+    pub address_match: AddressCheck,
+
+    /// The verification server knows the same history of the address as the
+    /// wallet, empty for a new address
+    pub server_history: AddressCheck,
+
+    /// No check failed
+    pub verified: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct AuditDerivationsOpt {
+    /// Check this many random cached scripts of each subaccount, all of them
+    /// if not given
+    pub sample: Option<usize>,
+
+    /// Replace the mismatching scripts with the derived ones and sync them
+    #[serde(default)]
+    pub repair: bool,
+}
+
+/// A cached script that is not the one derived at its pointer
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct DerivationMismatch {
+    pub subaccount: u32,
+    pub pointer: u32,
+    pub is_internal: bool,
+
+    /// The script hex in the store
+    pub stored_script: String,
+
+    /// The script hex derived from the subaccount xpub
+    pub derived_script: String,
+
+    /// The store has been fixed
+    pub repaired: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct DerivationAudit {
+    /// The number of cached scripts compared
+    pub checked: usize,
+
+    pub mismatches: Vec<DerivationMismatch>,
+}
+
+/// A transaction with a different height on the server and in the store,
+/// zero if unconfirmed
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct HeightMismatch {
+    pub txhash: String,
+    pub server_height: u32,
+    pub store_height: u32,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CreatePegoutOpt {
+    pub subaccount: u32,
+
+    /// The Bitcoin address on the mainchain receiving the pegged-out funds.
+    pub mainchain_address: String,
+
+    /// The L-BTC amount to peg out.
+    pub satoshi: u64,
+
+    /// The fee rate in satoshi per 1000 vbytes, the minimum fee rate if None.
+    pub fee_rate: Option<u64>,
+
+    /// If the utxos needed do not fit in a standard transaction, consolidate
+    /// them first in a chain of transactions instead of failing.
+    #[serde(default)]
+    pub split: bool,
+}
+
+/// A transaction consolidating utxos to the change before a peg-out, each one
+/// spending the change of the previous one.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PegoutConsolidationStep {
+    /// The unsigned blinded transaction.
+    pub transaction: String,
+
+    pub fee: u64,
+
+    /// The estimated virtual size of the transaction once signed.
+    pub transaction_vsize: usize,
+
+    /// The wallet utxos spent by the transaction, in input order.
+    pub transaction_inputs: Vec<UnspentOutput>,
+
+    pub change_address: String,
+    pub change_amount: u64,
+}
+
+/// How coin selection dealt with the change floor, the minimum change of
+/// [`crate::NetworkPolicy::min_change_satoshi`]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeDecision {
+    /// The inputs pay exactly the amount and the fee, or everything is sent
+    NoChange,
+
+    /// The change is above the floor
+    Kept,
+
+    /// Inputs were added to raise the change above the floor, which was
+    /// cheaper than adding it to the fee
+    AddedInput,
+
+    /// The change below the floor was added to the fee, which was cheaper
+    /// than adding inputs
+    FoldedIntoFee,
+
+    /// The change is below the floor, there are no more inputs to raise it
+    /// and it can't be added to the fee
+    BelowFloor,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CreatePegoutResult {
+    /// The unsigned blinded transaction.
+    pub transaction: String,
+
+    /// The mainchain address, echoed back so callers can confirm it.
+    pub mainchain_address: String,
+
+    pub satoshi: u64,
+    pub fee: u64,
+    pub fee_rate: u64,
+
+    /// The estimated virtual size of the transaction once signed.
+    pub transaction_vsize: usize,
+
+    /// The wallet utxos spent by the transaction, in input order.
+    pub transaction_inputs: Vec<UnspentOutput>,
+
+    pub change_address: String,
+    pub change_amount: u64,
+
+    /// How the change was chosen given the change floor of the network
+    /// policy.
+    pub change_decision: ChangeDecision,
+
+    /// The transactions to sign and broadcast in order before the peg-out,
+    /// which spends the change of the last one, if `split` was needed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub consolidation_steps: Vec<PegoutConsolidationStep>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CreateWalletMigrationOpt {
+    /// The SLIP132 extended public key of the destination account, whose
+    /// receive addresses are used from index 0.
+    pub destination_xpub: Option<String>,
+
+    /// On Liquid, the master blinding key of the destination wallet, to
+    /// derive confidential addresses from `destination_xpub`.
+    pub destination_master_blinding_key: Option<MasterBlindingKey>,
+
+    /// The destination addresses, used in order, if `destination_xpub` is
+    /// not given.
+    #[serde(default)]
+    pub destination_addresses: Vec<String>,
+
+    /// The fee rate in satoshi per 1000 vbytes, the minimum fee rate if None.
+    pub fee_rate: Option<u64>,
+}
+
+/// The transactions sending all the funds of the wallet to another wallet
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct WalletMigrationPlan {
+    /// Identifies the plan, to resume its execution
+    pub id: String,
+
+    pub fee_rate: u64,
+
+    /// The total fee of the transactions
+    pub fee: u64,
+
+    pub steps: Vec<WalletMigrationStep>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct WalletMigrationStep {
+    /// The unsigned transaction, blinded on Liquid.
+    pub transaction: String,
+
+    pub fee: u64,
+
+    /// The estimated virtual size of the transaction once signed.
+    pub transaction_vsize: usize,
+
+    /// The wallet utxos spent by the transaction, in input order.
+    pub transaction_inputs: Vec<UnspentOutput>,
+
+    /// The outputs paying the destination, the fee output excluded.
+    pub outputs: Vec<WalletMigrationOutput>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct WalletMigrationOutput {
+    pub address: String,
+    pub satoshi: u64,
+
+    /// None on Bitcoin
+    pub asset_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ExecuteWalletMigrationResult {
+    /// The transaction ids of the steps, in order
+    pub txhashes: Vec<String>,
+
+    /// The number of steps already broadcast by previous executions
+    pub skipped: usize,
+}
+
+/// Message signature format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageFormat {
+    /// BIP137 recoverable signature, also used by Electrum and bitcoin-cli
+    Legacy,
+    /// BIP322 simple signature, for native segwit addresses
+    Bip322,
+}
+
+impl MessageFormat {
+    /// The format used when the caller doesn't specify one
+    pub fn default_for(script_type: ScriptType) -> Self {
+        match script_type {
+            ScriptType::P2tr => MessageFormat::Bip322,
+            _ => MessageFormat::Legacy,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SignMessageOpt {
+    pub subaccount: u32,
+
+    /// The address to sign with, alternative to `path`.
+    pub address: Option<String>,
+
+    /// The path of the signing key relative to the subaccount, eg. `[0, 3]`.
+    pub path: Option<Vec<ChildNumber>>,
+
+    pub message: String,
+
+    /// The signature format, the default depends on the subaccount type.
+    pub format: Option<MessageFormat>,
+
+    /// The extended private key of the subaccount, the session signer is
+    /// used if not given.
+    #[serde(default)]
+    pub xprv: Option<Xpriv>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SignMessageResult {
+    /// The base64 encoded signature.
+    pub signature: String,
+
+    /// The address the message is signed with.
+    pub address: String,
+
+    pub format: MessageFormat,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VerifyMessageOpt {
+    pub address: String,
+    pub message: String,
+
+    /// The base64 encoded signature, in any supported format.
+    pub signature: String,
+}
+
+/// A wallet output, by transaction hash and output index
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub struct UtxoRef {
+    pub txhash: String,
+    pub pt_idx: u32,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CreateOwnershipProofOpt {
+    pub subaccount: u32,
+
+    /// The message the proof commits to, eg. a challenge of the auditor.
+    pub message: String,
+
+    /// The utxos of the subaccount to prove the ownership of, all of them if
+    /// empty.
+    #[serde(default)]
+    pub utxos: Vec<UtxoRef>,
+
+    /// The extended private key of the subaccount, the session signer is
+    /// used if not given.
+    #[serde(default)]
+    pub xprv: Option<Xpriv>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VerifyOwnershipProofOpt {
+    /// The base64 encoded proof.
+    pub proof: String,
+
+    pub message: String,
+}
+
+/// A utxo covered by an ownership proof
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ProvenUtxo {
+    pub txhash: String,
+    pub pt_idx: u32,
+    pub satoshi: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OwnershipProof {
+    /// The base64 encoded proof, a finalized PSBT in the BIP127 format which
+    /// cannot be broadcast.
+    pub proof: String,
+
+    pub utxos: Vec<ProvenUtxo>,
+
+    /// The total amount of the utxos.
+    pub satoshi: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PsbtFromTransactionOpt {
+    /// The unsigned transaction of a create transaction result, on Liquid it
+    /// must be blinded.
+    pub transaction: String,
+
+    /// The inputs of the create transaction result, only used for their
+    /// sighash types. Other elements of the result are ignored.
+    #[serde(default)]
+    pub transaction_inputs: Vec<PsbtFromTransactionInput>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct PsbtFromTransactionInput {
+    /// The sighash type to sign the input with, SIGHASH_ALL if not given.
+    #[serde(default)]
+    pub user_sighash: Option<u32>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PsbtResult {
+    /// The base64 encoded PSBT, or PSET on Liquid.
+    pub psbt: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SignPsbtOpt {
+    /// The base64 encoded PSBT, or PSET on Liquid.
+    pub psbt: String,
+
+    pub subaccount: u32,
+
+    /// The extended private key of the subaccount, the session signer is
+    /// used if not given.
+    #[serde(default)]
+    pub xprv: Option<Xpriv>,
+
+    /// The indexes of the inputs to sign, all the subaccount inputs if not
+    /// given.
+    #[serde(default)]
+    pub inputs: Option<Vec<usize>>,
+
+    /// Whether to sign with SIGHASH_NONE, or SIGHASH_SINGLE without a
+    /// matching output, letting others change the outputs paid.
+    #[serde(default)]
+    pub expert: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SignPsbtResult {
+    /// The base64 encoded PSBT, with the signatures of the subaccount inputs.
+    pub psbt: String,
+
+    /// The indexes of the inputs signed.
+    pub signed_inputs: Vec<usize>,
+
+    /// Whether each input can be finalized.
+    #[serde(default)]
+    pub complete_inputs: Vec<bool>,
+
+    /// Whether all inputs can be finalized.
+    #[serde(default)]
+    pub is_complete: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FinalizePsbtOpt {
+    /// The base64 encoded PSBT, or PSET on Liquid, with all inputs signed.
+    pub psbt: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FinalizePsbtResult {
+    /// The hex of the transaction, ready to be broadcast.
+    pub transaction: String,
+
+    pub txhash: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PsbtGetDetailsOpt {
+    /// The base64 encoded PSBT, or PSET on Liquid, created by another party.
+    pub psbt: String,
+
+    /// The fee rate in satoshi per 1000 vbytes above which a warning is
+    /// returned, defaults to 0.1 BTC/kvB.
+    #[serde(default)]
+    pub max_fee_rate: Option<u64>,
+}
+
+/// An input or output of a PSBT
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PsbtTxoDetails {
+    /// The subaccount of the wallet inputs and outputs.
+    pub subaccount: Option<u32>,
+
+    /// None if it cannot be known, such as blinded foreign outputs.
+    pub satoshi: Option<u64>,
+
+    /// Only set on Liquid.
+    pub asset_id: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PsbtWarning {
+    /// The output claims a derivation path of the wallet, but it is not a
+    /// wallet output with that path.
+    UnknownChangePath {
+        output: usize,
+    },
+
+    /// The fee rate, in satoshi per 1000 vbytes, exceeds the maximum.
+    HighFeeRate {
+        fee_rate: u64,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PsbtDetails {
+    /// The txid of the transaction, to acknowledge when signing.
+    pub txhash: String,
+
+    pub inputs: Vec<PsbtTxoDetails>,
+
+    pub outputs: Vec<PsbtTxoDetails>,
+
+    /// The net effect of the transaction on the wallet, by asset.
+    pub satoshi: Balances,
+
+    /// The net effect of the transaction on each subaccount involved, by asset.
+    pub subaccounts: HashMap<u32, Balances>,
+
+    /// None if the amount of some input is not known.
+    pub fee: Option<u64>,
+
+    /// The fee rate in satoshi per 1000 vbytes, estimated on the signed size.
+    pub fee_rate: Option<u64>,
+
+    pub warnings: Vec<PsbtWarning>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PsbtSignOpt {
+    /// The base64 encoded PSBT, or PSET on Liquid, created by another party.
+    pub psbt: String,
+
+    /// The txhash of the details shown to the user, the PSBT is signed only if
+    /// its transaction is still the same.
+    pub txhash: String,
+
+    pub subaccount: u32,
+
+    /// The extended private key of the subaccount, the session signer is
+    /// used if not given.
+    #[serde(default)]
+    pub xprv: Option<Xpriv>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ParseUriOpt {
+    /// A BIP21 URI, or its Liquid adaptation.
+    pub uri: String,
+
+    /// The decimal places of the asset of the amount, defaults to 8 as for
+    /// BTC and L-BTC.
+    #[serde(default)]
+    pub precision: Option<u8>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ParsedUri {
+    pub address: String,
+
+    /// The amount of the URI, in satoshi or the smallest unit of the asset.
+    pub satoshi: Option<u64>,
+
+    /// Only set on Liquid.
+    pub asset_id: Option<String>,
+
+    pub label: Option<String>,
+
+    pub message: Option<String>,
+
+    /// Only set on Liquid, whether the address has a blinding key.
+    pub is_confidential: Option<bool>,
+
+    /// The payjoin (BIP78) endpoint of the receiver.
+    pub payjoin: Option<String>,
+
+    /// Whether the address is one of the wallet, for callers to confirm
+    /// the user means to send to themselves.
+    #[serde(default)]
+    pub send_to_self: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CreateUriOpt {
+    pub address: String,
+
+    #[serde(default)]
+    pub satoshi: Option<u64>,
+
+    /// Only for Liquid, defaults to the policy asset if `satoshi` is set.
+    #[serde(default)]
+    pub asset_id: Option<String>,
+
+    #[serde(default)]
+    pub label: Option<String>,
+
+    /// The decimal places of the asset, defaults to 8 as for BTC and L-BTC.
+    #[serde(default)]
+    pub precision: Option<u8>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CreateUriResult {
+    pub uri: String,
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::{parse_path, GetUnspentOutputs, RejectReason};
+    use bitcoin::bip32::DerivationPath;
+
+    #[test]
+    fn test_path() {
+        let path_external: DerivationPath = "44'/1'/0'/0/0".parse().unwrap();
+        let path_internal: DerivationPath = "44'/1'/0'/1/0".parse().unwrap();
+        assert_eq!(parse_path(&path_external).unwrap(), (false, 0u32));
+        assert_eq!(parse_path(&path_internal).unwrap(), (true, 0u32));
+    }
+
+    #[test]
+    fn test_unspent() {
+        let json_str = r#"{"btc": [{"address_type": "p2wsh", "block_height": 1806588, "pointer": 3509, "pt_idx": 1, "satoshi": 3650144, "subaccount": 0, "txhash": "08711d45d4867d7834b133a425da065b252eb6a9b206d57e2bbb226a344c5d13", "is_internal": false, "is_blinded": false, "user_path": [2147483692, 2147483649, 2147483648, 0, 1], "prevout_script": "51", "public_key": "020202020202020202020202020202020202020202020202020202020202020202", "asset_id": ""}, {"address_type": "p2wsh", "block_height": 1835681, "pointer": 3510, "pt_idx": 0, "satoshi": 5589415, "subaccount": 0, "txhash": "fbd00e5b9e8152c04214c72c791a78a65fdbab68b5c6164ff0d8b22a006c5221", "is_internal": false, "is_blinded": false, "user_path": [2147483692, 2147483649, 2147483648, 0, 2], "prevout_script": "51", "public_key": "020202020202020202020202020202020202020202020202020202020202020202", "asset_id": ""}, {"address_type": "p2wsh", "block_height": 1835821, "pointer": 3511, "pt_idx": 0, "satoshi": 568158, "subaccount": 0, "txhash": "e5b358fb8366960130b97794062718d7f4fbe721bf274f47493a19326099b811", "is_internal": false, "is_blinded": false, "user_path": [2147483692, 2147483649, 2147483648, 0, 3], "prevout_script": "51", "public_key": "020202020202020202020202020202020202020202020202020202020202020202", "asset_id": ""}]}"#;
+        let _json: GetUnspentOutputs = serde_json::from_str(json_str).unwrap();
+    }
+
+    #[test]
+    fn test_reject_reason() {
+        let reason = |message| RejectReason::from_message(message);
+        let rpc_error = "sendrawtransaction RPC error: {\"code\":-25,\"message\":\"bad-txns-inputs-missingorspent\"}";
+        assert_eq!(reason(rpc_error), Some(RejectReason::MissingInputs));
+        assert_eq!(reason("min relay fee not met, 0 < 110"), Some(RejectReason::MinRelayFeeNotMet));
+        assert_eq!(
+            reason("mempool min fee not met, 5 < 10"),
+            Some(RejectReason::MinRelayFeeNotMet)
+        );
+        assert_eq!(reason("txn-mempool-conflict"), Some(RejectReason::TxnMempoolConflict));
+        let chain = "too-long-mempool-chain, too many unconfirmed ancestors [limit: 25]";
+        assert_eq!(reason(chain), Some(RejectReason::TooLongMempoolChain));
+        assert_eq!(reason("non-BIP68-final"), Some(RejectReason::NonFinal));
+        assert_eq!(reason("transaction rejected"), None);
+
+        assert_eq!(RejectReason::MinRelayFeeNotMet.to_string(), "min-relay-fee-not-met");
+        let json = serde_json::to_value(RejectReason::TooLongMempoolChain).unwrap();
+        assert_eq!(json, "too-long-mempool-chain");
     }
 }
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
 use std::str::FromStr;
 use std::time::Duration;
 
@@ -19,6 +21,18 @@ pub struct NetworkParameters {
     pub development: bool,
     pub liquid: bool,
     pub mainnet: bool,
+    /// A signet chain, the public one or a custom one if also development
+    #[serde(default)]
+    pub signet: bool,
+
+    bech32_prefix: Option<String>,
+    blech32_prefix: Option<String>,
+    p2pkh_version: Option<u32>,
+    p2sh_version: Option<u32>,
+
+    /// The genesis block hash of chains sharing the parameters of another
+    /// one, like signet or custom regtests, checked against the server
+    genesis_hash: Option<String>,
 
     tx_explorer_url: String,
     address_explorer_url: String,
@@ -29,9 +43,20 @@ pub struct NetworkParameters {
     pub validate_domain: Option<bool>,
     pub policy_asset: Option<String>,
     pub sync_interval: Option<u32>,
+    /// The maximum number of subaccounts synced at once
+    pub sync_parallelism: Option<u32>,
     pub spv_enabled: Option<bool>,
     asset_registry_url: Option<String>,
     asset_registry_onion_url: Option<String>,
+    /// The base url of the asset icons, if not served by the registry
+    asset_registry_icons_url: Option<String>,
+    /// The hex public key whose signatures of the registry files are required
+    asset_registry_public_key: Option<String>,
+
+    price_url: Option<String>,
+    price_onion_url: Option<String>,
+    /// The base url of the historical exchange rates endpoint, if any
+    price_history_url: Option<String>,
 
     pin_server_url: String,
     pin_server_onion_url: String,
@@ -39,9 +64,32 @@ pub struct NetworkParameters {
 
     pub spv_multi: Option<bool>,
     pub spv_servers: Option<Vec<String>>,
+    /// An electrum server, independent from the one of the session, asked
+    /// for the history of the addresses by `verify_receive_address`, in the
+    /// `host:port:t|s` format of `spv_servers`
+    pub address_verification_server: Option<String>,
+    /// The hex public keys of the federation signing the blocks of a liquid
+    /// network, by default the known ones of liquid mainnet and none on
+    /// regtest, where the blocks are expected to be signed by `OP_TRUE`
+    pub federation_pubkeys: Option<Vec<String>>,
+    /// The number of the most recent headers of the SPV headers chain kept
+    /// in memory
+    pub headers_window: Option<u32>,
+    /// Checkpoints of the SPV headers chain in addition to the built-in
+    /// ones, by height, only on development networks
+    #[serde(default)]
+    pub headers_checkpoints: HashMap<u32, bitcoin::BlockHash>,
 
     pub proxy: Option<String>,
     pub use_tor: Option<bool>,
+
+    /// Addresses of server hostnames, used instead of resolving them
+    #[serde(default)]
+    pub static_hosts: HashMap<String, Vec<IpAddr>>,
+    /// A DNS-over-HTTPS resolver of the server hostnames, with the JSON API
+    /// of eg. `https://1.1.1.1/dns-query`
+    pub doh_url: Option<String>,
+
     pub max_reorg_blocks: Option<u32>,
 
     /// For electrum sessions is used as root directory for the db cache and for
@@ -56,16 +104,133 @@ pub struct NetworkParameters {
     pub state_dir: String,
 
     pub gap_limit: Option<u32>,
+
+    /// Redact amounts, asset ids and addresses from logs while the session is
+    /// alive and omit amounts from notifications
+    pub privacy_mode: Option<bool>,
+
+    /// Maximum number of notifications kept until a handler is registered
+    pub notification_queue_size: Option<usize>,
+
+    /// The seed of the chain and the wallet fabricated by the sessions of
+    /// the `"simulation"` server type
+    pub simulation_seed: Option<String>,
+    /// The seconds between the blocks of simulated chains, mined only on
+    /// request if not set
+    pub simulation_block_interval: Option<u32>,
+
+    /// The change below which the transactions built avoid a change output,
+    /// see [`NetworkPolicy::min_change_satoshi`]
+    pub min_change_satoshi: Option<u64>,
+
+    /// Overrides of the [`NetworkPolicy`] of development networks
+    dust_limits: Option<DustLimits>,
+    min_fee_rate: Option<u64>,
+    max_standard_tx_weight: Option<u64>,
 }
 
+/// The amounts below which outputs are dust and not relayed, by script type
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct DustLimits {
+    pub p2pkh: u64,
+    pub p2sh: u64,
+    pub p2wpkh: u64,
+    pub p2wsh: u64,
+    pub p2tr: u64,
+}
+
+impl Default for DustLimits {
+    /// The limits of Bitcoin Core with its default dust relay fee
+    fn default() -> Self {
+        DustLimits {
+            p2pkh: 546,
+            p2sh: 540,
+            p2wpkh: 294,
+            p2wsh: 330,
+            p2tr: 330,
+        }
+    }
+}
+
+impl DustLimits {
+    /// The dust limit of an output paying to `script`, zero for unspendable
+    /// ones and the highest limit for nonstandard ones
+    pub fn for_script(&self, script: &bitcoin::Script) -> u64 {
+        if script.is_op_return() {
+            0
+        } else if script.is_p2wpkh() {
+            self.p2wpkh
+        } else if script.is_p2wsh() {
+            self.p2wsh
+        } else if script.is_p2tr() {
+            self.p2tr
+        } else if script.is_p2sh() {
+            self.p2sh
+        } else {
+            self.p2pkh
+        }
+    }
+}
+
+/// The standardness rules the transactions built are checked against,
+/// returned by `get_network_info` so that callers can check amounts and fees
+/// before building
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkPolicy {
+    pub dust_limits: DustLimits,
+
+    /// The minimum fee rate relayed, in satoshi per 1000 vbytes
+    pub min_fee_rate: u64,
+
+    /// The maximum weight of the transactions relayed
+    pub max_standard_tx_weight: u64,
+
+    /// The change floor: a change below it costs more to spend than it's
+    /// worth, the transactions built add an input to raise it or add it to
+    /// the fee instead
+    pub min_change_satoshi: u64,
+}
+
+impl NetworkPolicy {
+    /// The policy of the nodes of the network `id` with their default options
+    pub fn new(id: NetworkId) -> Self {
+        NetworkPolicy {
+            dust_limits: DustLimits::default(),
+            min_fee_rate: id.default_min_fee_rate(),
+            max_standard_tx_weight: 400_000,
+            min_change_satoshi: 3 * DustLimits::default().p2wpkh,
+        }
+    }
+}
+
+/// The prefixes of the addresses of a network, telling them from the
+/// addresses of the other networks
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressPrefixes {
+    /// The human readable part of segwit addresses
+    pub bech32: &'static str,
+
+    /// The human readable part of confidential segwit addresses, Liquid only
+    pub blech32: Option<&'static str>,
+
+    /// The version byte of base58 P2PKH addresses
+    pub p2pkh: u8,
+
+    /// The version byte of base58 P2SH addresses
+    pub p2sh: u8,
+
+    /// The byte preceding the version of confidential base58 addresses, Liquid only
+    pub blinded: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ElementsNetwork {
     Liquid,
     LiquidTestnet,
     ElementsRegtest,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum NetworkId {
     Elements(ElementsNetwork),
     Bitcoin(bitcoin::Network),
@@ -85,6 +250,45 @@ impl NetworkId {
             NetworkId::Bitcoin(_) => 1000,
         }
     }
+
+    /// The address prefixes of the chain
+    pub fn address_prefixes(&self) -> AddressPrefixes {
+        match *self {
+            NetworkId::Bitcoin(network) => {
+                let (bech32, p2pkh, p2sh) = match network {
+                    bitcoin::Network::Bitcoin => ("bc", 0, 5),
+                    bitcoin::Network::Regtest => ("bcrt", 111, 196),
+                    _ => ("tb", 111, 196),
+                };
+                AddressPrefixes {
+                    bech32,
+                    blech32: None,
+                    p2pkh,
+                    p2sh,
+                    blinded: None,
+                }
+            }
+            NetworkId::Elements(network) => {
+                let params = network.address_params();
+                AddressPrefixes {
+                    bech32: params.bech_hrp,
+                    blech32: Some(params.blech_hrp),
+                    p2pkh: params.p2pkh_prefix,
+                    p2sh: params.p2sh_prefix,
+                    blinded: Some(params.blinded_prefix),
+                }
+            }
+        }
+    }
+
+    /// The scheme of BIP21 payment URIs
+    pub fn bip21_prefix(&self) -> &'static str {
+        match self {
+            NetworkId::Elements(ElementsNetwork::LiquidTestnet) => "liquidtestnet",
+            NetworkId::Elements(_) => "liquidnetwork",
+            NetworkId::Bitcoin(_) => "bitcoin",
+        }
+    }
 }
 
 impl ElementsNetwork {
@@ -95,10 +299,75 @@ impl ElementsNetwork {
             ElementsNetwork::ElementsRegtest => &elements::AddressParams::ELEMENTS,
         }
     }
+
+    /// The Bitcoin network this network is pegged to
+    pub fn mainchain_network(self: ElementsNetwork) -> bitcoin::Network {
+        match self {
+            ElementsNetwork::Liquid => bitcoin::Network::Bitcoin,
+            ElementsNetwork::LiquidTestnet => bitcoin::Network::Testnet,
+            ElementsNetwork::ElementsRegtest => bitcoin::Network::Regtest,
+        }
+    }
 }
 
 impl NetworkParameters {
+    /// Check the parameters are consistent, as the networks registered by
+    /// the caller can be built only on the chains of [`NetworkId`]
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |reason: &str| Err(Error::InvalidNetwork(reason.into()));
+        if self.mainnet && self.development {
+            return invalid("mainnet and development are exclusive");
+        }
+        if self.signet && (self.mainnet || self.liquid) {
+            return invalid("signet can't be mainnet or liquid");
+        }
+        if self.liquid && self.policy_asset_id().is_err() {
+            return invalid("missing or invalid policy asset");
+        }
+        let prefixes = self.id().address_prefixes();
+        if self.bech32_prefix.as_ref().is_some_and(|prefix| *prefix != prefixes.bech32) {
+            return invalid("bech32 prefix does not match the chain");
+        }
+        if self.blech32_prefix.is_some() && self.blech32_prefix.as_deref() != prefixes.blech32 {
+            return invalid("blech32 prefix does not match the chain");
+        }
+        if self.p2pkh_version.is_some_and(|version| version != prefixes.p2pkh as u32)
+            || self.p2sh_version.is_some_and(|version| version != prefixes.p2sh as u32)
+        {
+            return invalid("address versions do not match the chain");
+        }
+        if self.genesis_hash().is_some_and(|hash| bitcoin::BlockHash::from_str(hash).is_err()) {
+            return invalid("invalid genesis hash");
+        }
+        let overrides_policy = self.dust_limits.is_some()
+            || self.min_fee_rate.is_some()
+            || self.max_standard_tx_weight.is_some();
+        if overrides_policy && !self.development {
+            return invalid("only development networks can override the policy");
+        }
+        if !self.headers_checkpoints.is_empty() && !self.development {
+            return invalid("only development networks can add headers checkpoints");
+        }
+        if let Some(doh_url) = self.doh_url.as_deref().filter(|url| !url.is_empty()) {
+            if url::Url::parse(doh_url).map_or(true, |url| url.scheme() != "https") {
+                return invalid("the DNS-over-HTTPS resolver must be an https url");
+            }
+        }
+        if self.registry_public_key().is_some_and(|key| key.parse::<PublicKey>().is_err()) {
+            return invalid("invalid asset registry public key");
+        }
+        Ok(())
+    }
+
+    /// The genesis block hash, if it differs from the one of the chain of [`Self::id`]
+    pub fn genesis_hash(&self) -> Option<&str> {
+        self.genesis_hash.as_deref().filter(|hash| !hash.is_empty())
+    }
+
     pub fn id(&self) -> NetworkId {
+        if self.signet && !self.liquid && !self.mainnet {
+            return NetworkId::Bitcoin(bitcoin::Network::Signet);
+        }
         match (self.liquid, self.mainnet, self.development) {
             (true, true, false) => NetworkId::Elements(ElementsNetwork::Liquid),
             (true, false, false) => NetworkId::Elements(ElementsNetwork::LiquidTestnet),
@@ -110,6 +379,19 @@ impl NetworkParameters {
         }
     }
 
+    /// The policy of the network, with the overrides of development networks
+    pub fn policy(&self) -> NetworkPolicy {
+        let default = NetworkPolicy::new(self.id());
+        NetworkPolicy {
+            dust_limits: self.dust_limits.unwrap_or(default.dust_limits),
+            min_fee_rate: self.min_fee_rate.unwrap_or(default.min_fee_rate),
+            max_standard_tx_weight: self
+                .max_standard_tx_weight
+                .unwrap_or(default.max_standard_tx_weight),
+            min_change_satoshi: self.min_change_satoshi.unwrap_or(default.min_change_satoshi),
+        }
+    }
+
     pub fn policy_asset_id(&self) -> Result<elements::issuance::AssetId, Error> {
         if let Some(a) = self.policy_asset.as_ref() {
             Ok(a.parse()?)
@@ -118,6 +400,10 @@ impl NetworkParameters {
         }
     }
 
+    pub fn privacy_mode(&self) -> bool {
+        self.privacy_mode.unwrap_or(false)
+    }
+
     pub fn use_tor(&self) -> bool {
         self.use_tor.unwrap_or(false)
     }
@@ -144,6 +430,39 @@ impl NetworkParameters {
         self.asset_registry_onion_url = Some(url);
     }
 
+    /// The base url of the asset icons, None if served by the registry
+    pub fn registry_icons_url(&self) -> Option<&str> {
+        self.asset_registry_icons_url.as_deref().filter(|url| !url.is_empty())
+    }
+
+    pub fn set_asset_registry_icons_url(&mut self, url: String) {
+        self.asset_registry_icons_url = Some(url);
+    }
+
+    /// The hex public key signing the registry files, if their signatures
+    /// must be checked
+    pub fn registry_public_key(&self) -> Option<&str> {
+        self.asset_registry_public_key.as_deref().filter(|key| !key.is_empty())
+    }
+
+    pub fn set_asset_registry_public_key(&mut self, public_key: String) {
+        self.asset_registry_public_key = Some(public_key);
+    }
+
+    /// The base url of the exchange rates endpoint, if any
+    pub fn price_url(&self) -> Option<&str> {
+        let onion = self.price_onion_url.as_deref().filter(|url| !url.is_empty());
+        match onion {
+            Some(url) if self.use_tor() => Some(url),
+            _ => self.price_url.as_deref().filter(|url| !url.is_empty()),
+        }
+    }
+
+    /// The base url of the historical exchange rates endpoint, if any
+    pub fn price_history_url(&self) -> Option<&str> {
+        self.price_history_url.as_deref().filter(|url| !url.is_empty())
+    }
+
     pub fn pin_server_url(&self) -> Result<url::Url, Error> {
         let url = if self.use_tor() && !self.pin_server_onion_url.is_empty() {
             &self.pin_server_onion_url
@@ -239,4 +558,122 @@ mod tests {
             "cf3bc52a701f6111fe8be5451fc61bec5dad8c30216910ec8673d815e5936799"
         );
     }
+
+    #[test]
+    fn test_validate() {
+        let network = |extra: serde_json::Value| {
+            let mut params = serde_json::json!({
+                "name": "Custom regtest",
+                "network": "custom-regtest",
+                "development": true,
+                "liquid": false,
+                "mainnet": false,
+                "tx_explorer_url": "",
+                "address_explorer_url": "",
+                "pin_server_url": "",
+                "pin_server_onion_url": "",
+                "pin_server_public_key": "",
+                "state_dir": "",
+                "bech32_prefix": "bcrt",
+                "p2pkh_version": 111,
+                "p2sh_version": 196,
+            });
+            params.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+            serde_json::from_value::<crate::NetworkParameters>(params).unwrap().validate()
+        };
+        let policy_asset = "5ac9f65c0efcc4775e0baec4ec03abdde22473cd3cf33c0419ca290e0751b225";
+        let signet_genesis = "00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6";
+
+        network(serde_json::json!({})).unwrap();
+        network(serde_json::json!({"mainnet": true, "development": false})).unwrap_err();
+        network(serde_json::json!({"bech32_prefix": "tb"})).unwrap_err();
+        network(serde_json::json!({"p2sh_version": 5})).unwrap_err();
+        network(serde_json::json!({"genesis_hash": "00"})).unwrap_err();
+
+        // Only development networks can change the policy
+        network(serde_json::json!({"min_fee_rate": 10})).unwrap();
+        let testnet = serde_json::json!({"development": false, "bech32_prefix": "tb"});
+        let mut overriding = testnet.clone();
+        overriding["max_standard_tx_weight"] = 100_000.into();
+        network(testnet).unwrap();
+        network(overriding).unwrap_err();
+
+        let checkpoint = serde_json::json!({"101": signet_genesis});
+        network(serde_json::json!({"headers_checkpoints": checkpoint})).unwrap();
+        network(serde_json::json!({"development": false, "bech32_prefix": "tb", "headers_checkpoints": checkpoint}))
+            .unwrap_err();
+
+        // Server hostnames are resolved through https only
+        network(serde_json::json!({"doh_url": "https://1.1.1.1/dns-query"})).unwrap();
+        network(serde_json::json!({"doh_url": "http://1.1.1.1/dns-query"})).unwrap_err();
+        network(serde_json::json!({"static_hosts": {"electrum.example": ["127.0.0.1", "::1"]}}))
+            .unwrap();
+
+        // The registry files are checked against a valid key
+        let key = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+        network(serde_json::json!({"asset_registry_public_key": key})).unwrap();
+        network(serde_json::json!({"asset_registry_public_key": "02"})).unwrap_err();
+
+        // Signet shares the testnet parameters but not the genesis
+        let signet = serde_json::json!({"development": false, "bech32_prefix": "tb", "genesis_hash": signet_genesis});
+        network(signet).unwrap();
+        let signet = serde_json::json!({"signet": true, "bech32_prefix": "tb"});
+        network(signet).unwrap();
+        network(serde_json::json!({"signet": true})).unwrap_err();
+        network(serde_json::json!({"signet": true, "mainnet": true, "development": false}))
+            .unwrap_err();
+
+        let liquid = serde_json::json!({
+            "liquid": true,
+            "bech32_prefix": "ert",
+            "blech32_prefix": "el",
+            "p2pkh_version": 235,
+            "p2sh_version": 75,
+            "policy_asset": policy_asset,
+        });
+        network(liquid.clone()).unwrap();
+        let mut missing_asset = liquid.clone();
+        missing_asset.as_object_mut().unwrap().remove("policy_asset");
+        network(missing_asset).unwrap_err();
+        let mut wrong_blech32 = liquid;
+        wrong_blech32["blech32_prefix"] = "lq".into();
+        network(wrong_blech32).unwrap_err();
+    }
+
+    #[test]
+    fn test_policy() {
+        use crate::{DustLimits, NetworkPolicy};
+        use bitcoin::hashes::Hash;
+
+        let mut network = crate::NetworkParameters {
+            development: true,
+            ..Default::default()
+        };
+        assert_eq!(network.policy(), NetworkPolicy::new(network.id()));
+        assert_eq!(network.policy().min_fee_rate, 1000);
+        network.dust_limits = Some(DustLimits {
+            p2wpkh: 1000,
+            ..Default::default()
+        });
+        network.min_fee_rate = Some(10);
+        let policy = network.policy();
+        assert_eq!((policy.dust_limits.p2wpkh, policy.dust_limits.p2pkh), (1000, 546));
+        assert_eq!(policy.min_fee_rate, 10);
+        assert_eq!(policy.max_standard_tx_weight, 400_000);
+        assert_eq!(policy.min_change_satoshi, 3 * 294);
+
+        let p2wpkh = bitcoin::ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::all_zeros());
+        assert_eq!(policy.dust_limits.for_script(&p2wpkh), 1000);
+        let p2sh = bitcoin::ScriptBuf::new_p2sh(&bitcoin::ScriptHash::all_zeros());
+        assert_eq!(policy.dust_limits.for_script(&p2sh), 540);
+        assert_eq!(policy.dust_limits.for_script(&bitcoin::ScriptBuf::new_op_return(&[])), 0);
+
+        // The change floor is a preference of the wallet, on any network
+        let mut network = crate::NetworkParameters {
+            min_change_satoshi: Some(5_000),
+            ..Default::default()
+        };
+        network.validate().unwrap();
+        assert_eq!(network.policy().min_change_satoshi, 5_000);
+    }
 }
@@ -1,15 +1,35 @@
 use crate::be::BEBlockHeader;
+use crate::model::{self, NotificationKind};
 use crate::util::make_str;
-use crate::{be::BEBlockHash, model::Settings, model::TransactionType, State};
+use crate::{model::Balances, model::Settings, model::TransactionType, State};
 use log::{info, warn};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
 
 pub type NativeType =
     (extern "C" fn(*const libc::c_void, *const libc::c_char), *const libc::c_void);
+/// The default maximum number of notifications kept while nobody is listening
+pub const DEFAULT_NOTIFICATION_QUEUE_SIZE: usize = 100;
+
 #[derive(Clone)]
 pub struct NativeNotif {
-    pub native: Option<NativeType>,
+    native: Arc<Mutex<Option<Native>>>,
+
+    /// When set, amounts are omitted from notification payloads
+    pub privacy_mode: bool,
+
+    subscribers: Subscribers,
+
+    /// Notifications emitted while neither a native handler nor a subscriber is registered,
+    /// delivered to the first one registering
+    pending: Arc<Mutex<PendingQueue>>,
+
+    /// The last notification of each kind, for clients that prefer polling
+    last: Arc<Mutex<HashMap<NotificationKind, model::Notification>>>,
 
     /// With testing feature notifications are simply pushed in the following vec so assertions
     /// could check over it, it's a mutex so that methods signatures doesn't need to be mut
@@ -18,6 +38,129 @@ pub struct NativeNotif {
 }
 unsafe impl Send for NativeNotif {}
 
+/// The native handler, shared by every clone of [`NativeNotif`] so that it can
+/// be registered after the session threads started
+#[derive(Clone, Copy)]
+struct Native(NativeType);
+unsafe impl Send for Native {}
+
+pub type SubscriptionId = u64;
+
+/// A bounded queue dropping the oldest notifications when full
+struct PendingQueue {
+    queue: VecDeque<model::Notification>,
+    max_size: usize,
+    dropped: usize,
+}
+
+impl Default for PendingQueue {
+    fn default() -> Self {
+        PendingQueue {
+            queue: VecDeque::new(),
+            max_size: DEFAULT_NOTIFICATION_QUEUE_SIZE,
+            dropped: 0,
+        }
+    }
+}
+
+impl PendingQueue {
+    fn push(&mut self, ntf: model::Notification) {
+        if self.max_size == 0 {
+            self.dropped += 1;
+            return;
+        }
+        if self.queue.len() == self.max_size {
+            self.queue.pop_front();
+            self.dropped += 1;
+        }
+        self.queue.push_back(ntf);
+    }
+
+    /// Empty the queue, starting with an overflow marker if notifications were dropped
+    fn drain(&mut self) -> Vec<model::Notification> {
+        let mut ntfs = vec![];
+        if self.dropped > 0 {
+            ntfs.push(model::Notification::QueueOverflow {
+                dropped: self.dropped,
+            });
+            self.dropped = 0;
+        }
+        ntfs.extend(self.queue.drain(..));
+        ntfs
+    }
+}
+
+type Callback = Arc<dyn Fn(&model::Notification) + Send + Sync>;
+
+struct Subscriber {
+    id: SubscriptionId,
+    kinds: Vec<NotificationKind>,
+    callback: Callback,
+}
+
+/// Subscribers to typed notifications.
+///
+/// Notifications are handed to a dedicated thread, started with the first
+/// subscription, which calls the subscribers so that a slow consumer cannot
+/// block the sync loop.
+#[derive(Clone, Default)]
+struct Subscribers {
+    list: Arc<Mutex<Vec<Subscriber>>>,
+    sender: Arc<Mutex<Option<mpsc::Sender<model::Notification>>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Subscribers {
+    fn subscribe(&self, kinds: Vec<NotificationKind>, callback: Callback) -> SubscriptionId {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.list.lock().unwrap().push(Subscriber {
+            id,
+            kinds,
+            callback,
+        });
+
+        let mut sender = self.sender.lock().unwrap();
+        if sender.is_none() {
+            let (tx, rx) = mpsc::channel::<model::Notification>();
+            let list = self.list.clone();
+            thread::spawn(move || {
+                for ntf in rx {
+                    let callbacks: Vec<Callback> = list
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .filter(|s| s.kinds.is_empty() || s.kinds.contains(&ntf.kind()))
+                        .map(|s| s.callback.clone())
+                        .collect();
+                    for callback in callbacks {
+                        callback(&ntf);
+                    }
+                }
+            });
+            *sender = Some(tx);
+        }
+        id
+    }
+
+    fn is_empty(&self) -> bool {
+        self.list.lock().unwrap().is_empty()
+    }
+
+    fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        let mut list = self.list.lock().unwrap();
+        let len = list.len();
+        list.retain(|s| s.id != id);
+        list.len() != len
+    }
+
+    fn dispatch(&self, ntf: &model::Notification) {
+        if let Some(sender) = self.sender.lock().unwrap().as_ref() {
+            // the dispatching thread never exits while a sender is alive
+            let _ = sender.send(ntf.clone());
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Notification {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -49,6 +192,8 @@ struct NetworkNotification {
     current_state: State,
     next_state: State,
     wait_ms: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    server: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -72,6 +217,20 @@ pub struct TransactionNotification {
     #[serde(rename = "type")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub type_: Option<TransactionType>,
+
+    /// The height of the block including the transaction, None if unconfirmed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_height: Option<u32>,
+
+    /// The net amount of the transaction for each asset, negative if outgoing.
+    ///
+    /// None if privacy mode is enabled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub satoshi_delta: Option<Balances>,
+
+    /// True if the transaction has been evicted from the mempool or replaced.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub removed: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -84,6 +243,19 @@ pub struct BlockNotification {
 
     /// The hash of the block prior to this block
     pub previous_hash: bitcoin::BlockHash,
+
+    /// The block timestamp, as set by the miner.
+    #[serde(default)]
+    pub timestamp: u32,
+
+    /// True if the block does not extend the previously notified tip.
+    #[serde(default)]
+    pub reorg: bool,
+
+    /// The height of the last block shared with the previously notified chain,
+    /// only set on reorgs and if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub common_ancestor_height: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -109,6 +281,7 @@ impl Notification {
                 current_state: current,
                 next_state: next,
                 wait_ms: 0,
+                server: None,
             }),
             transaction: None,
             block: None,
@@ -127,34 +300,6 @@ impl Notification {
         }
     }
 
-    pub fn new_block_from_hashes(height: u32, hash: &BEBlockHash, prev_hash: &BEBlockHash) -> Self {
-        Notification {
-            network: None,
-            transaction: None,
-            block: Some(BlockNotification {
-                block_height: height,
-                block_hash: hash.into_bitcoin(),
-                previous_hash: prev_hash.into_bitcoin(),
-            }),
-            subaccount: None,
-            event: Kind::Block,
-        }
-    }
-
-    pub fn new_block_from_header(height: u32, header: &BEBlockHeader) -> Self {
-        Notification {
-            network: None,
-            transaction: None,
-            block: Some(BlockNotification {
-                block_height: height,
-                block_hash: header.block_hash().into_bitcoin(),
-                previous_hash: header.prev_block_hash().into_bitcoin(),
-            }),
-            subaccount: None,
-            event: Kind::Block,
-        }
-    }
-
     pub fn subaccount(pointer: u32, event_type: SubaccountEventType) -> Self {
         Notification {
             network: None,
@@ -169,21 +314,230 @@ impl Notification {
     }
 }
 
+impl Notification {
+    /// The legacy JSON notification for `ntf`, None if it has no legacy equivalent
+    fn from_model(ntf: &model::Notification) -> Option<Value> {
+        let legacy = match ntf {
+            model::Notification::Block {
+                height,
+                hash,
+                previous_hash,
+                timestamp,
+                reorg,
+                common_ancestor_height,
+            } => Notification {
+                network: None,
+                transaction: None,
+                block: Some(BlockNotification {
+                    block_height: *height,
+                    block_hash: *hash,
+                    previous_hash: *previous_hash,
+                    timestamp: *timestamp,
+                    reorg: *reorg,
+                    common_ancestor_height: *common_ancestor_height,
+                }),
+                subaccount: None,
+                event: Kind::Block,
+            },
+            model::Notification::Transaction {
+                txid,
+                subaccounts,
+                satoshi,
+                type_,
+                block_height,
+                satoshi_delta,
+                removed,
+            } => Notification::new_transaction(&TransactionNotification {
+                subaccounts: subaccounts.clone(),
+                txid: *txid,
+                satoshi: *satoshi,
+                type_: type_.clone(),
+                block_height: *block_height,
+                satoshi_delta: satoshi_delta.clone(),
+                removed: *removed,
+            }),
+            model::Notification::Network {
+                connected,
+                next_state,
+                server,
+                ..
+            } => {
+                let mut ntf = Notification::new_network((*connected).into(), *next_state);
+                if let Some(network) = ntf.network.as_mut() {
+                    network.server = server.clone();
+                }
+                ntf
+            }
+            model::Notification::Settings(settings) => {
+                return Some(json!({"settings":settings,"event":"settings"}))
+            }
+            model::Notification::Subaccount {
+                pointer,
+            } => Notification::subaccount(*pointer, SubaccountEventType::New),
+            model::Notification::SyncProgress {
+                subaccount,
+            } => Notification::subaccount(*subaccount, SubaccountEventType::Synced),
+            model::Notification::SpvStatus {
+                ..
+            } => return None,
+            model::Notification::QueueOverflow {
+                dropped,
+            } => {
+                return Some(json!({"queue_overflow":{"dropped":dropped},"event":"queue_overflow"}))
+            }
+            model::Notification::AddressReuse {
+                subaccount,
+                address,
+                pointer,
+                txid,
+                previous_txids,
+            } => {
+                let address_reuse = json!({
+                    "subaccount": subaccount,
+                    "address": address,
+                    "pointer": pointer,
+                    "txhash": txid,
+                    "previous_txhashes": previous_txids,
+                });
+                return Some(json!({"address_reuse":address_reuse,"event":"address_reuse"}));
+            }
+            model::Notification::ClockSkew {
+                skew_seconds,
+            } => {
+                let clock_skew = json!({ "skew_seconds": skew_seconds });
+                return Some(json!({"clock_skew":clock_skew,"event":"clock_skew"}));
+            }
+            model::Notification::TransactionReorged {
+                subaccount,
+                txid,
+                height,
+                reorg_count,
+            } => {
+                let transaction_reorged = json!({
+                    "subaccount": subaccount,
+                    "txhash": txid,
+                    "block_height": height,
+                    "reorg_count": reorg_count,
+                });
+                return Some(
+                    json!({"transaction_reorged":transaction_reorged,"event":"transaction_reorged"}),
+                );
+            }
+            model::Notification::ExpectedPayment(payment) => {
+                return Some(json!({"expected_payment":payment,"event":"expected_payment"}));
+            }
+            model::Notification::AssetsRefreshProgress {
+                refresh_id,
+                what,
+                downloaded,
+                total,
+            } => {
+                let progress = json!({
+                    "refresh_id": refresh_id,
+                    "what": what,
+                    "downloaded": downloaded,
+                    "total": total,
+                });
+                return Some(
+                    json!({"assets_refresh_progress":progress,"event":"assets_refresh_progress"}),
+                );
+            }
+            model::Notification::AssetsRefreshWarning {
+                refresh_id,
+                message,
+            } => {
+                let warning = json!({
+                    "refresh_id": refresh_id,
+                    "message": message,
+                });
+                return Some(
+                    json!({"assets_refresh_warning":warning,"event":"assets_refresh_warning"}),
+                );
+            }
+            model::Notification::AssetsRefreshCompleted {
+                refresh_id,
+                not_modified,
+                error,
+            } => {
+                let completed = json!({
+                    "refresh_id": refresh_id,
+                    "not_modified": not_modified,
+                    "error": error,
+                });
+                return Some(
+                    json!({"assets_refresh_completed":completed,"event":"assets_refresh_completed"}),
+                );
+            }
+        };
+        Some(serde_json::to_value(legacy).unwrap())
+    }
+}
+
+/// The JSON of `ntf`, in the native callback format if it has one
+pub fn to_json(ntf: &model::Notification) -> Value {
+    Notification::from_model(ntf)
+        .unwrap_or_else(|| serde_json::to_value(ntf).expect("notifications are serializable"))
+}
+
 impl NativeNotif {
     #[cfg(not(feature = "testing"))]
     pub fn new() -> Self {
         NativeNotif {
-            native: None,
+            native: Arc::new(Mutex::new(None)),
+            privacy_mode: false,
+            subscribers: Subscribers::default(),
+            pending: Arc::new(Mutex::new(PendingQueue::default())),
+            last: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
-    // TODO once every notification is a struct, accept a `Notification` here
-    fn notify<T: Serialize>(&self, data: T) {
-        let data = serde_json::to_value(data).unwrap();
+    /// Deliver `ntf` to the subscribers and, through the legacy JSON format, to
+    /// the native callback
+    fn notify(&self, mut ntf: model::Notification) {
+        if self.privacy_mode {
+            match &mut ntf {
+                model::Notification::Transaction {
+                    satoshi,
+                    satoshi_delta,
+                    ..
+                } => {
+                    *satoshi = None;
+                    *satoshi_delta = None;
+                }
+                model::Notification::ExpectedPayment(payment) => {
+                    payment.satoshi = None;
+                    payment.received_satoshi = None;
+                }
+                _ => (),
+            }
+        }
+        self.last.lock().unwrap().insert(ntf.kind(), ntf.clone());
 
+        let mut pending = self.pending.lock().unwrap();
+        let native = *self.native.lock().unwrap();
+        if native.is_none() && self.subscribers.is_empty() {
+            pending.push(ntf.clone());
+            drop(pending);
+            if let Some(data) = Notification::from_model(&ntf) {
+                info!("queued notification: {:?}", data);
+                self.push(data);
+            }
+            return;
+        }
+        drop(pending);
+        self.deliver(native, &ntf);
+    }
+
+    fn deliver(&self, native: Option<Native>, ntf: &model::Notification) {
+        self.subscribers.dispatch(ntf);
+
+        let data = match Notification::from_model(ntf) {
+            Some(data) => data,
+            None => return,
+        };
         info!("push notification: {:?}", data);
-        if let Some((handler, self_context)) = self.native.as_ref() {
-            handler(*self_context, make_str(data.to_string()));
+        if let Some(Native((handler, self_context))) = native {
+            handler(self_context, make_str(data.to_string()));
         } else {
             if !cfg!(feature = "testing") {
                 warn!("no registered handler to receive notification");
@@ -193,36 +547,200 @@ impl NativeNotif {
     }
 
     pub fn set_native(&mut self, native_type: NativeType) {
-        self.native = Some(native_type);
+        let mut pending = self.pending.lock().unwrap();
+        *self.native.lock().unwrap() = Some(Native(native_type));
+        let (handler, self_context) = native_type;
+        for ntf in pending.drain() {
+            if let Some(data) = Notification::from_model(&ntf) {
+                info!("push queued notification: {:?}", data);
+                handler(self_context, make_str(data.to_string()));
+            }
+        }
     }
 
-    pub fn block_from_hashes(&self, height: u32, hash: &BEBlockHash, prev_hash: &BEBlockHash) {
-        self.notify(Notification::new_block_from_hashes(height, hash, prev_hash));
+    /// Set the maximum number of notifications kept while nobody is listening
+    pub fn set_queue_size(&self, max_size: usize) {
+        self.pending.lock().unwrap().max_size = max_size;
     }
 
-    pub fn block_from_header(&self, height: u32, header: &BEBlockHeader) {
-        self.notify(Notification::new_block_from_header(height, &header));
+    /// The last notification emitted of the given `kind`
+    pub fn last_notification(&self, kind: NotificationKind) -> Option<model::Notification> {
+        self.last.lock().unwrap().get(&kind).cloned()
+    }
+
+    /// The JSON of the last notification of the given `kind`, in the native
+    /// callback format if it has one
+    pub fn last_notification_json(&self, kind: NotificationKind) -> Option<Value> {
+        self.last_notification(kind).map(|ntf| to_json(&ntf))
+    }
+
+    /// Register `callback` to be called for every notification of the given
+    /// `kinds`, or for every notification if `kinds` is empty.
+    ///
+    /// Callbacks are called from a dedicated thread.
+    pub fn subscribe<F>(&self, kinds: &[NotificationKind], callback: F) -> SubscriptionId
+    where
+        F: Fn(&model::Notification) + Send + Sync + 'static,
+    {
+        let mut pending = self.pending.lock().unwrap();
+        let id = self.subscribers.subscribe(kinds.to_vec(), Arc::new(callback));
+        for ntf in pending.drain() {
+            self.subscribers.dispatch(&ntf);
+        }
+        id
+    }
+
+    /// Remove a subscription, returns false if it didn't exist
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        self.subscribers.unsubscribe(id)
+    }
+
+    /// Notify a new tip, `reorg` is true if it does not extend the previously notified tip
+    pub fn block(
+        &self,
+        height: u32,
+        header: &BEBlockHeader,
+        reorg: bool,
+        common_ancestor_height: Option<u32>,
+    ) {
+        self.notify(model::Notification::Block {
+            height,
+            hash: header.block_hash().into_bitcoin(),
+            previous_hash: header.prev_block_hash().into_bitcoin(),
+            timestamp: header.time(),
+            reorg,
+            common_ancestor_height,
+        });
     }
 
     pub fn settings(&self, settings: &Settings) {
-        let data = json!({"settings":settings,"event":"settings"});
-        self.notify(data);
+        self.notify(model::Notification::Settings(settings.clone()));
     }
 
     pub fn updated_txs(&self, ntf: &TransactionNotification) {
-        self.notify(Notification::new_transaction(ntf));
+        self.notify(model::Notification::Transaction {
+            txid: ntf.txid,
+            subaccounts: ntf.subaccounts.clone(),
+            satoshi: ntf.satoshi,
+            type_: ntf.type_.clone(),
+            block_height: ntf.block_height,
+            satoshi_delta: ntf.satoshi_delta.clone(),
+            removed: ntf.removed,
+        });
     }
 
     pub fn network(&self, current: State, desired: State) {
-        self.notify(Notification::new_network(current, desired));
+        self.notify(model::Notification::Network {
+            connected: current == State::Connected,
+            login_required: false,
+            next_state: desired,
+            server: None,
+        });
+    }
+
+    /// The session switched to the electrum server `server` and is connected
+    pub fn network_switched(&self, server: &str) {
+        self.notify(model::Notification::Network {
+            connected: true,
+            login_required: false,
+            next_state: State::Connected,
+            server: Some(server.to_string()),
+        });
     }
 
     pub fn subaccount_new(&self, pointer: u32) {
-        self.notify(Notification::subaccount(pointer, SubaccountEventType::New));
+        self.notify(model::Notification::Subaccount {
+            pointer,
+        });
     }
 
     pub fn subaccount_synced(&self, pointer: u32) {
-        self.notify(Notification::subaccount(pointer, SubaccountEventType::Synced));
+        self.notify(model::Notification::SyncProgress {
+            subaccount: pointer,
+        });
+    }
+
+    pub fn address_reuse(
+        &self,
+        subaccount: u32,
+        address: String,
+        pointer: u32,
+        txid: bitcoin::Txid,
+        previous_txids: Vec<bitcoin::Txid>,
+    ) {
+        self.notify(model::Notification::AddressReuse {
+            subaccount,
+            address,
+            pointer,
+            txid,
+            previous_txids,
+        });
+    }
+
+    pub fn clock_skew(&self, skew_seconds: i64) {
+        self.notify(model::Notification::ClockSkew {
+            skew_seconds,
+        });
+    }
+
+    pub fn transaction_reorged(
+        &self,
+        subaccount: u32,
+        txid: bitcoin::Txid,
+        height: u32,
+        reorg_count: u32,
+    ) {
+        self.notify(model::Notification::TransactionReorged {
+            subaccount,
+            txid,
+            height,
+            reorg_count,
+        });
+    }
+
+    pub fn expected_payment(&self, payment: model::ExpectedPayment) {
+        self.notify(model::Notification::ExpectedPayment(payment));
+    }
+
+    pub fn assets_refresh_progress(
+        &self,
+        refresh_id: u64,
+        what: String,
+        downloaded: u64,
+        total: Option<u64>,
+    ) {
+        self.notify(model::Notification::AssetsRefreshProgress {
+            refresh_id,
+            what,
+            downloaded,
+            total,
+        });
+    }
+
+    pub fn assets_refresh_warning(&self, refresh_id: u64, message: String) {
+        self.notify(model::Notification::AssetsRefreshWarning {
+            refresh_id,
+            message,
+        });
+    }
+
+    pub fn assets_refresh_completed(
+        &self,
+        refresh_id: u64,
+        not_modified: bool,
+        error: Option<String>,
+    ) {
+        self.notify(model::Notification::AssetsRefreshCompleted {
+            refresh_id,
+            not_modified,
+            error,
+        });
+    }
+
+    pub fn spv_status(&self, verified: usize) {
+        self.notify(model::Notification::SpvStatus {
+            verified,
+        });
     }
 
     #[cfg(not(feature = "testing"))]
@@ -235,7 +753,11 @@ impl NativeNotif {
 impl NativeNotif {
     pub fn new() -> Self {
         NativeNotif {
-            native: None,
+            native: Arc::new(Mutex::new(None)),
+            privacy_mode: false,
+            subscribers: Subscribers::default(),
+            pending: Arc::new(Mutex::new(PendingQueue::default())),
+            last: Arc::new(Mutex::new(HashMap::new())),
             testing: std::sync::Arc::new(std::sync::Mutex::new(vec![])),
         }
     }
@@ -279,18 +801,286 @@ mod test {
             txid: bitcoin::Txid::all_zeros(),
             satoshi: None,
             type_: None,
+            block_height: None,
+            satoshi_delta: None,
+            removed: false,
         });
         assert_eq!(expected, serde_json::to_value(&obj).unwrap());
     }
 
+    #[test]
+    fn test_address_reuse_json() {
+        let zeros = "0000000000000000000000000000000000000000000000000000000000000000";
+        let expected = json!({"event":"address_reuse","address_reuse":{"subaccount":0,"address":"bcrt1q","pointer":3,"txhash":zeros,"previous_txhashes":[zeros]}});
+        let ntf = model::Notification::AddressReuse {
+            subaccount: 0,
+            address: "bcrt1q".into(),
+            pointer: 3,
+            txid: bitcoin::Txid::all_zeros(),
+            previous_txids: vec![bitcoin::Txid::all_zeros()],
+        };
+        assert_eq!(expected, to_json(&ntf));
+    }
+
+    #[test]
+    fn test_expected_payment_json() {
+        let mut payment = model::ExpectedPayment {
+            subaccount: 0,
+            address: "bcrt1q".into(),
+            pointer: 3,
+            satoshi: Some(12345),
+            asset_id: "btc".into(),
+            created_at: 1000,
+            expires_at: Some(1900),
+            status: model::PaymentStatus::Pending,
+            txid: None,
+            received_satoshi: Some(0),
+        };
+        assert_eq!(payment.status_at(1899), model::PaymentStatus::Pending);
+        assert_eq!(payment.status_at(1900), model::PaymentStatus::Expired);
+        payment.settle("ab".repeat(32), 10000, 1500);
+        assert_eq!(payment.status_at(2000), model::PaymentStatus::Underpaid);
+
+        let expected = json!({"event":"expected_payment","expected_payment":{"subaccount":0,"address":"bcrt1q","pointer":3,"satoshi":12345,"asset_id":"btc","created_at":1000,"expires_at":1900,"status":"underpaid","txhash":"ab".repeat(32),"received_satoshi":10000}});
+        let ntf = model::Notification::ExpectedPayment(payment.clone());
+        assert_eq!(expected, to_json(&ntf));
+        assert_eq!(ntf.kind(), NotificationKind::ExpectedPayment);
+
+        for (received, now, status) in [
+            (12345, 1500, model::PaymentStatus::Exact),
+            (12346, 1500, model::PaymentStatus::Overpaid),
+            (12345, 1900, model::PaymentStatus::Late),
+        ] {
+            payment.settle("ab".repeat(32), received, now);
+            assert_eq!(payment.status, status);
+        }
+    }
+
+    #[test]
+    fn test_clock_skew_json() {
+        let expected = json!({"event":"clock_skew","clock_skew":{"skew_seconds":-10800}});
+        let ntf = model::Notification::ClockSkew {
+            skew_seconds: -10800,
+        };
+        assert_eq!(expected, to_json(&ntf));
+        assert_eq!(ntf.kind(), NotificationKind::ClockSkew);
+    }
+
+    #[test]
+    fn test_assets_refresh_json() {
+        let expected = json!({"event":"assets_refresh_completed","assets_refresh_completed":{"refresh_id":1,"not_modified":true,"error":null}});
+        let ntf = model::Notification::AssetsRefreshCompleted {
+            refresh_id: 1,
+            not_modified: true,
+            error: None,
+        };
+        assert_eq!(expected, to_json(&ntf));
+        assert_eq!(ntf.kind(), NotificationKind::AssetsRefreshCompleted);
+
+        let expected = json!({"event":"assets_refresh_warning","assets_refresh_warning":{"refresh_id":1,"message":"invalid signature"}});
+        let ntf = model::Notification::AssetsRefreshWarning {
+            refresh_id: 1,
+            message: "invalid signature".into(),
+        };
+        assert_eq!(expected, to_json(&ntf));
+        assert_eq!(ntf.kind(), NotificationKind::AssetsRefreshWarning);
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn test_transaction_privacy_mode() {
+        let mut notify = NativeNotif::new();
+        notify.privacy_mode = true;
+        notify.updated_txs(&TransactionNotification {
+            subaccounts: vec![0],
+            txid: bitcoin::Txid::all_zeros(),
+            satoshi: Some(123456),
+            type_: Some(TransactionType::Incoming),
+            block_height: None,
+            satoshi_delta: None,
+            removed: false,
+        });
+        let events = notify.filter_events("transaction");
+        assert_eq!(events.len(), 1);
+        assert!(events[0]["transaction"].get("satoshi").is_none());
+        assert_eq!(events[0]["transaction"]["type"], "incoming");
+
+        // The amounts of expected payments are stripped for the subscribers as well
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        notify.subscribe(&[NotificationKind::ExpectedPayment], move |ntf| {
+            tx.lock().unwrap().send(ntf.clone()).unwrap();
+        });
+        let mut payment = model::ExpectedPayment {
+            subaccount: 0,
+            address: "bcrt1q".into(),
+            pointer: 3,
+            satoshi: Some(12345),
+            asset_id: "btc".into(),
+            created_at: 1000,
+            expires_at: None,
+            status: model::PaymentStatus::Pending,
+            txid: None,
+            received_satoshi: Some(0),
+        };
+        payment.settle("ab".repeat(32), 10000, 1500);
+        notify.expected_payment(payment);
+        match rx.recv_timeout(std::time::Duration::from_secs(5)).unwrap() {
+            model::Notification::ExpectedPayment(payment) => {
+                assert_eq!((payment.satoshi, payment.received_satoshi), (None, None));
+                assert_eq!(payment.status, model::PaymentStatus::Underpaid);
+            }
+            other => panic!("unexpected notification {:?}", other),
+        }
+        let events = notify.filter_events("expected_payment");
+        assert_eq!(events.len(), 1);
+        assert!(events[0]["expected_payment"].get("satoshi").is_none());
+        assert!(events[0]["expected_payment"].get("received_satoshi").is_none());
+        assert_eq!(events[0]["expected_payment"]["status"], "underpaid");
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let notify = NativeNotif::new();
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        let id = notify.subscribe(&[NotificationKind::Transaction], move |ntf| {
+            tx.lock().unwrap().send(ntf.clone()).unwrap();
+        });
+        notify.network(State::Connected, State::Connected);
+        notify.updated_txs(&TransactionNotification {
+            subaccounts: vec![1],
+            txid: bitcoin::Txid::all_zeros(),
+            satoshi: Some(1000),
+            type_: Some(TransactionType::Outgoing),
+            block_height: None,
+            satoshi_delta: None,
+            removed: false,
+        });
+        let timeout = std::time::Duration::from_secs(5);
+        match rx.recv_timeout(timeout).unwrap() {
+            model::Notification::Transaction {
+                subaccounts,
+                satoshi,
+                ..
+            } => {
+                assert_eq!(subaccounts, vec![1]);
+                assert_eq!(satoshi, Some(1000));
+            }
+            other => panic!("unexpected notification {:?}", other),
+        }
+
+        assert!(notify.unsubscribe(id));
+        assert!(!notify.unsubscribe(id));
+        notify.subaccount_new(0);
+        notify.updated_txs(&TransactionNotification {
+            subaccounts: vec![1],
+            txid: bitcoin::Txid::all_zeros(),
+            satoshi: None,
+            type_: None,
+            block_height: None,
+            satoshi_delta: None,
+            removed: false,
+        });
+        assert!(rx.recv_timeout(std::time::Duration::from_millis(200)).is_err());
+    }
+
+    #[test]
+    fn test_pending_queue() {
+        let notify = NativeNotif::new();
+        notify.set_queue_size(2);
+        for pointer in 0..3 {
+            notify.subaccount_new(pointer);
+        }
+        assert!(matches!(
+            notify.last_notification(NotificationKind::Subaccount),
+            Some(model::Notification::Subaccount {
+                pointer: 2
+            })
+        ));
+        assert!(notify.last_notification(NotificationKind::Block).is_none());
+
+        // Queued notifications are flushed to the first subscriber, after the overflow marker
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        notify.subscribe(&[], move |ntf| {
+            tx.lock().unwrap().send(ntf.clone()).unwrap();
+        });
+        let timeout = std::time::Duration::from_secs(5);
+        let received: Vec<_> = (0..3).map(|_| rx.recv_timeout(timeout).unwrap()).collect();
+        assert!(matches!(
+            received[0],
+            model::Notification::QueueOverflow {
+                dropped: 1
+            }
+        ));
+        assert!(matches!(
+            received[1],
+            model::Notification::Subaccount {
+                pointer: 1
+            }
+        ));
+        assert!(matches!(
+            received[2],
+            model::Notification::Subaccount {
+                pointer: 2
+            }
+        ));
+        assert!(rx.recv_timeout(std::time::Duration::from_millis(200)).is_err());
+    }
+
+    #[test]
+    fn test_legacy_json() {
+        let ntf = model::Notification::SyncProgress {
+            subaccount: 2,
+        };
+        let expected =
+            json!({"subaccount":{"pointer":2,"event_type":"synced"},"event":"subaccount"});
+        assert_eq!(Notification::from_model(&ntf), Some(expected));
+
+        let ntf = model::Notification::Network {
+            connected: false,
+            login_required: false,
+            next_state: State::Connected,
+            server: None,
+        };
+        let expected =
+            serde_json::to_value(Notification::new_network(State::Disconnected, State::Connected))
+                .unwrap();
+        assert_eq!(Notification::from_model(&ntf), Some(expected));
+
+        let ntf = model::Notification::Network {
+            connected: true,
+            login_required: false,
+            next_state: State::Connected,
+            server: Some("127.0.0.1:50001".into()),
+        };
+        let expected = json!({"network":{"current_state":"connected","next_state":"connected","wait_ms":0,"server":"127.0.0.1:50001"},"event":"network"});
+        assert_eq!(Notification::from_model(&ntf), Some(expected));
+
+        let ntf = model::Notification::SpvStatus {
+            verified: 1,
+        };
+        assert_eq!(Notification::from_model(&ntf), None);
+    }
+
     #[test]
     fn test_block_json() {
-        let expected = json!({"block_height":0,"block_hash":"0000000000000000000000000000000000000000000000000000000000000000","previous_hash":"0000000000000000000000000000000000000000000000000000000000000000"});
-        let obj = BlockNotification {
+        let expected = json!({"block_height":0,"block_hash":"0000000000000000000000000000000000000000000000000000000000000000","previous_hash":"0000000000000000000000000000000000000000000000000000000000000000","timestamp":1296688602,"reorg":false});
+        let mut obj = BlockNotification {
             block_height: 0,
             block_hash: BlockHash::all_zeros(),
             previous_hash: BlockHash::all_zeros(),
+            timestamp: 1296688602,
+            reorg: false,
+            common_ancestor_height: None,
         };
         assert_eq!(expected, serde_json::to_value(&obj).unwrap());
+
+        obj.reorg = true;
+        obj.common_ancestor_height = Some(121);
+        let value = serde_json::to_value(&obj).unwrap();
+        assert_eq!(value["reorg"], true);
+        assert_eq!(value["common_ancestor_height"], 121);
     }
 }
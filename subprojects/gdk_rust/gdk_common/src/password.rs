@@ -1,7 +1,7 @@
 use std::fmt;
 
 // This doesn't do validation, but we could?
-#[derive(Clone)]
+#[derive(Clone, serde::Deserialize)]
 pub struct Password(String);
 
 impl Password {
@@ -0,0 +1,161 @@
+//! Redaction of amounts, asset ids and addresses from log messages.
+//!
+//! While a session in privacy mode is open the logger passes every message
+//! through [`redact`], which replaces sensitive values with short tokens. A token is
+//! derived from a salted hash of the value, so the same value maps to the same
+//! token for the lifetime of the process and log lines can still be correlated.
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use once_cell::sync::Lazy;
+
+/// The number of live [`PrivacyMode`] guards
+static PRIVACY_MODE: AtomicUsize = AtomicUsize::new(0);
+
+static SALT: Lazy<[u8; 32]> = Lazy::new(rand::random);
+
+/// Keys whose numeric value is considered an amount
+const AMOUNT_KEYS: [&str; 6] = ["satoshi", "amount", "value", "fee", "balance", "btc"];
+
+/// Wrappers that may sit between a key and its value in `Debug` output
+const VALUE_WRAPPERS: [&str; 2] = ["Some", "Number"];
+
+/// Human readable parts of segwit addresses on Bitcoin and Liquid networks
+const ADDRESS_HRPS: [&str; 9] =
+    ["bc1", "tb1", "bcrt1", "ex1", "lq1", "ert1", "el1", "tex1", "tlq1"];
+
+/// Redaction of log messages, enabled until the guard is dropped
+///
+/// Sessions in privacy mode hold one for their lifetime. The logger is
+/// global, so the messages of the other sessions are redacted as well while
+/// one is held.
+#[derive(Debug)]
+pub struct PrivacyMode(());
+
+impl PrivacyMode {
+    pub fn enable() -> Self {
+        PRIVACY_MODE.fetch_add(1, Ordering::SeqCst);
+        PrivacyMode(())
+    }
+}
+
+impl Drop for PrivacyMode {
+    fn drop(&mut self) {
+        PRIVACY_MODE.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Whether log messages must be redacted, see [`PrivacyMode`]
+pub fn privacy_mode() -> bool {
+    PRIVACY_MODE.load(Ordering::SeqCst) > 0
+}
+
+/// The message of a log record as the loggers must write it, redacted in
+/// privacy mode
+pub fn log_message(args: &fmt::Arguments) -> String {
+    let msg = args.to_string();
+    if privacy_mode() {
+        redact(&msg)
+    } else {
+        msg
+    }
+}
+
+fn token(word: &str) -> String {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&SALT[..]);
+    engine.input(word.as_bytes());
+    let hash = sha256::Hash::from_engine(engine);
+    format!("<{}>", &hash.to_string()[..8])
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Asset ids, but also txids and other hashes
+fn is_hex_id(word: &str) -> bool {
+    word.len() == 64 && word.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn is_address(word: &str) -> bool {
+    let lower = word.to_ascii_lowercase();
+    if ADDRESS_HRPS.iter().any(|hrp| lower.starts_with(hrp)) {
+        return word.len() >= 26;
+    }
+    // Base58 addresses, either unconfidential or confidential
+    ((26..=35).contains(&word.len()) || (70..=85).contains(&word.len())) && is_base58(word)
+}
+
+fn is_base58(word: &str) -> bool {
+    word.chars().all(|c| c.is_ascii_alphanumeric() && !"0OIl".contains(c))
+        && word.chars().any(|c| c.is_ascii_digit())
+        && word.chars().any(|c| c.is_ascii_alphabetic())
+}
+
+fn is_amount_key(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    is_hex_id(&key) || AMOUNT_KEYS.iter().any(|k| key.ends_with(k))
+}
+
+/// Replace amounts, asset ids and addresses in `msg` with stable short tokens.
+///
+/// Amounts are recognized as integers following an amount-like key (eg.
+/// `satoshi: 1000` or `"fee":100`) or an asset id, as in balance maps.
+pub fn redact(msg: &str) -> String {
+    let mut out = String::with_capacity(msg.len());
+    let mut last_key: Option<&str> = None;
+    let mut rest = msg;
+    while let Some(c) = rest.chars().next() {
+        let is_word = is_word_char(c);
+        let end = rest.find(|c| is_word_char(c) != is_word).unwrap_or(rest.len());
+        let (chunk, tail) = rest.split_at(end);
+        rest = tail;
+        if !is_word {
+            out.push_str(chunk);
+            continue;
+        }
+        let is_amount =
+            chunk.chars().all(|c| c.is_ascii_digit()) && last_key.is_some_and(is_amount_key);
+        if is_amount || is_hex_id(chunk) || is_address(chunk) {
+            out.push_str(&token(chunk));
+        } else {
+            out.push_str(chunk);
+        }
+        if !VALUE_WRAPPERS.contains(&chunk) {
+            last_key = Some(chunk);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        let asset = "6f0279e9ed041c3d710a9f57d0c02928416460c4b722ae3457a11eec381c526d";
+        let address = "bcrt1qx2npnl4cvlhttk08c7w4f2jg2lhtgkpj2xzh47";
+        let log = format!(
+            "push notification: Object {{\"transaction\": Object {{\"satoshi\": Number(123456), \"subaccounts\": Array [Number(0)]}}}}\n\
+             balance {{\"{asset}\": 7654321}} sent to {address} fee: Some(4242) change_amount=98765 at height 101"
+        );
+        let redacted = redact(&log);
+        for secret in ["123456", "7654321", "4242", "98765", asset, address] {
+            assert!(!redacted.contains(secret), "{} leaked in {}", secret, redacted);
+        }
+        // not sensitive values are kept
+        assert!(redacted.contains("Number(0)"));
+        assert!(redacted.contains("height 101"));
+        assert!(redacted.contains("push notification"));
+        // tokens are stable
+        assert_eq!(redact(asset), redact(asset));
+        assert_eq!(redact(&format!("{asset} {asset}")), format!("{0} {0}", redact(asset)));
+
+        let confidential = "el1qqf2uxpn2uq5g8g9hd7l8ralkcqqqa4kl9zq7znxj3y0lmj8p7sj2s8vt4k9xvt6t5rrsny7hf2ysw4cskkmpk34yhqd7xz3ss";
+        assert_ne!(redact(confidential), confidential);
+    }
+}
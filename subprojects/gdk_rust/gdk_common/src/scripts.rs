@@ -19,6 +19,12 @@ pub enum ScriptType {
     P2pkh = 2,
     #[serde(rename = "p2tr")]
     P2tr = 3,
+    /// The multisig of a `wsh(sortedmulti(..))` descriptor
+    #[serde(rename = "p2wsh")]
+    P2wsh = 4,
+    /// The 2of2 or 2of3 multisig of a Green subaccount, watched only
+    #[serde(rename = "green-multisig")]
+    GreenMultisig = 5,
 }
 
 impl fmt::Display for ScriptType {
@@ -28,6 +34,8 @@ impl fmt::Display for ScriptType {
             Self::P2wpkh => write!(f, "p2wpkh"),
             Self::P2pkh => write!(f, "p2pkh"),
             Self::P2tr => write!(f, "p2tr"),
+            Self::P2wsh => write!(f, "p2wsh"),
+            Self::GreenMultisig => write!(f, "green-multisig"),
         }
     }
 }
@@ -40,6 +48,8 @@ impl ScriptType {
             Self::P2wpkh => 1,
             Self::P2pkh => 2,
             Self::P2tr => 3,
+            Self::P2wsh => 4,
+            Self::GreenMultisig => 5,
         }
     }
 }
@@ -63,6 +73,9 @@ impl ScriptType {
     }
 
     pub fn is_segwit(self) -> bool {
-        matches!(self, ScriptType::P2wpkh | ScriptType::P2shP2wpkh | ScriptType::P2tr)
+        matches!(
+            self,
+            ScriptType::P2wpkh | ScriptType::P2shP2wpkh | ScriptType::P2tr | ScriptType::P2wsh
+        )
     }
 }
@@ -3,6 +3,7 @@ use std::fmt;
 use serde_json::Value;
 
 use crate::{
+    error::ErrorCode,
     exchange_rates::ExchangeRatesCacher,
     notification::{NativeNotif, NativeType},
     NetworkParameters,
@@ -29,6 +30,8 @@ pub trait Session: Sized + ExchangeRatesCacher {
 pub struct JsonError {
     pub message: String,
     pub error: String,
+    #[serde(flatten)]
+    pub code: ErrorCode,
 }
 
 impl fmt::Display for JsonError {
@@ -44,6 +47,7 @@ impl JsonError {
         JsonError {
             message: message.into(),
             error: "id_unknown".to_string(),
+            code: ErrorCode::Unknown,
         }
     }
 }
@@ -9,7 +9,7 @@ use bitcoin::hashes::{sha256, Hash};
 use bitcoin::{Network, NetworkKind};
 use rand::Rng;
 
-use crate::Result;
+use crate::{Error, Result};
 
 pub trait Decryptable {
     fn decrypt(self, cipher: &Aes256GcmSiv) -> Result<Vec<u8>>;
@@ -32,7 +32,7 @@ impl Decryptable for Vec<u8> {
             .ok_or_else(fn_err("vector should be longer than 12 bytes"))?;
         let mut rest = iter.collect::<Vec<_>>();
 
-        cipher.decrypt_in_place(&nonce, b"", &mut rest)?;
+        cipher.decrypt_in_place(&nonce, b"", &mut rest).map_err(|_| Error::DecryptionFailed)?;
         Ok(rest)
     }
 }
@@ -99,8 +99,12 @@ mod tests {
         let (nonce, rest) = data.clone().encrypt(&cipher).unwrap();
         let encrypted = nonce.iter().map(|byte| *byte).chain(rest.into_iter()).collect::<Vec<_>>();
 
-        let decrypted = encrypted.decrypt(&cipher).unwrap();
+        let decrypted = encrypted.clone().decrypt(&cipher).unwrap();
         assert_eq!(data, decrypted);
+
+        let other_cipher = Aes256GcmSiv::new(Key::from_slice(&[1u8; 32]));
+        let err = encrypted.decrypt(&other_cipher).unwrap_err();
+        assert_eq!(err.code(), crate::ErrorCode::DecryptionFailed);
     }
 
     #[test]
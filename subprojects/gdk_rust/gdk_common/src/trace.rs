@@ -0,0 +1,81 @@
+//! Tracing of the session calls and of the background work.
+//!
+//! Session calls, sync passes and SPV rounds run in [`tracing`] spans
+//! carrying the `wallet_hash_id` and `subaccount` involved and, recorded
+//! before the span is exited, the `elapsed_ms` it took. Without a subscriber
+//! spans and events are forwarded to the [`log`](crate::log) logger, embedders
+//! wanting them structured install their own with [`set_subscriber`].
+//!
+//! Values identifying the wallet or its funds are recorded through
+//! [`redacted`], as log messages they're replaced by tokens when privacy mode
+//! is enabled.
+
+use std::time::Instant;
+
+use tracing::span::EnteredSpan;
+use tracing::{Span, Subscriber};
+
+use crate::error::Error;
+use crate::redact;
+
+/// Install `subscriber` as the global tracing subscriber, it can be done
+/// once for the process
+pub fn set_subscriber<S>(subscriber: S) -> Result<(), Error>
+where
+    S: Subscriber + Send + Sync + 'static,
+{
+    tracing::subscriber::set_global_default(subscriber).map_err(|e| Error::Generic(e.to_string()))
+}
+
+/// `value` as it should be recorded in a span field
+pub fn redacted(value: &str) -> String {
+    if redact::privacy_mode() {
+        redact::redact(value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// A span entered until dropped, see [`timed`]
+pub struct TimedSpan {
+    span: EnteredSpan,
+    start: Instant,
+}
+
+/// Enter `span`, which records the milliseconds it stays entered in its
+/// `elapsed_ms` field, declared empty
+pub fn timed(span: Span) -> TimedSpan {
+    TimedSpan {
+        span: span.entered(),
+        start: Instant::now(),
+    }
+}
+
+impl TimedSpan {
+    /// Record `value` in the `field` of the span
+    pub fn record<V: tracing::Value>(&self, field: &str, value: V) {
+        self.span.record(field, &value);
+    }
+}
+
+impl Drop for TimedSpan {
+    fn drop(&mut self) {
+        self.span.record("elapsed_ms", &(self.start.elapsed().as_millis() as u64));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_redacted() {
+        let hash_id = "6f0279e9ed041c3d710a9f57d0c02928416460c4b722ae3457a11eec381c526d";
+        assert_eq!(redacted(hash_id), hash_id);
+        let privacy_mode = redact::PrivacyMode::enable();
+        assert_eq!(redacted(hash_id), redact::redact(hash_id));
+        assert_ne!(redacted(hash_id), hash_id);
+        drop(privacy_mode);
+        assert_eq!(redacted(hash_id), hash_id);
+    }
+}
@@ -3,18 +3,24 @@ use std::collections::{HashMap, HashSet};
 
 use gdk_common::electrum_client::ScriptStatus;
 use gdk_common::log::info;
+use gdk_common::rand::seq::SliceRandom;
+use gdk_common::rand::thread_rng;
 
-use gdk_common::bitcoin::bip32::{DerivationPath, Fingerprint, Xpub};
+use gdk_common::bitcoin::bip32::{DerivationPath, Fingerprint, KeySource, Xpriv, Xpub};
 use gdk_common::bitcoin::hashes::Hash;
 use gdk_common::bitcoin::CompressedPublicKey;
 use gdk_common::{bitcoin, elements};
 
-use gdk_common::be::{BEAddress, BEOutPoint, BEScript, BETransaction, BETransactions, BETxid};
+use gdk_common::be::{
+    BEAddress, BEBlockHeader, BEOutPoint, BEScript, BEScriptConvert, BETransaction, BETransactions,
+    BETxid,
+};
 use gdk_common::error::fn_err;
 use gdk_common::model::{
-    parse_path, AccountInfo, AddressDataResult, AddressPointer, GetPreviousAddressesOpt,
-    GetTransactionsOpt, GetTxInOut, PreviousAddress, PreviousAddresses, SPVVerifyTxResult,
-    TxListItem, Txo, UpdateAccountOpt,
+    parse_path, AccountInfo, AddressDataResult, AddressPayment, AddressPointer, DerivationMismatch,
+    GetPreviousAddressesOpt, GetTransactionsOpt, GetTxInOut, MessageFormat, PreviousAddress,
+    PreviousAddresses, SPVVerifyTxResult, TransactionType, TxListItem, Txo, TxsResult,
+    UpdateAccountOpt, WalletScript,
 };
 use gdk_common::scripts::{p2pkh_script, ScriptType};
 use gdk_common::slip132::slip132_version;
@@ -25,12 +31,18 @@ use gdk_common::util::{now, weight_to_vsize};
 use gdk_common::{ElementsNetwork, NetworkId, NetworkParameters};
 
 use crate::error::Error;
+use crate::green::GreenMultisig;
 use crate::interface::ElectrumUrl;
-use crate::store::{RawAccountCache, Store};
+use crate::multisig::Multisig;
+use crate::psbt::WalletTxo;
+use crate::resolver::Resolver;
+use crate::signer::{Signer, SoftwareSigner};
+use crate::store::{RawAccountCache, Store, StoreMeta};
 use crate::ScriptStatuses;
 
 // The number of account types, including these reserved for future use.
-// Currently only 3 are used: P2SH-P2WPKH, P2WPKH and P2PKH
+// Currently only 6 are used: P2SH-P2WPKH, P2WPKH, P2PKH, P2TR, P2WSH multisig
+// and the watch-only Green multisig
 const NUM_RESERVED_ACCOUNT_TYPES: u32 = 16;
 
 #[derive(Clone)]
@@ -47,6 +59,12 @@ pub struct Account {
     master_blinding: Option<MasterBlindingKey>,
 
     path: DerivationPath,
+
+    // multisig subaccounts only
+    multisig: Option<Multisig>,
+
+    // watch-only Green subaccounts only
+    green: Option<GreenMultisig>,
 }
 
 /// Compare xpub ignoring the fingerprint (which computation might be skipped),
@@ -76,6 +94,32 @@ impl Account {
         // cache internal/external chains
         let chains = [xpub.ckd_pub(&crate::EC, 0.into())?, xpub.ckd_pub(&crate::EC, 1.into())?];
 
+        let multisig = match script_type {
+            ScriptType::P2wsh => {
+                let settings = store.read()?.get_account_settings(account_num).cloned();
+                let descriptor = settings
+                    .and_then(|settings| settings.multisig_descriptor)
+                    .ok_or_else(|| Error::Generic("missing multisig descriptor".into()))?;
+                let origin = (master_xpub_fingerprint, path.clone());
+                Some(Multisig::new(&descriptor, &origin, &xpub)?)
+            }
+            _ => None,
+        };
+
+        let green = match (script_type, network.id()) {
+            (ScriptType::GreenMultisig, NetworkId::Bitcoin(bitcoin_network)) => {
+                let settings = store.read()?.get_account_settings(account_num).cloned();
+                let template = settings
+                    .and_then(|settings| settings.green_multisig)
+                    .ok_or_else(|| Error::Generic("missing green multisig keys".into()))?;
+                Some(GreenMultisig::new(&template, bitcoin_network)?)
+            }
+            (ScriptType::GreenMultisig, NetworkId::Elements(_)) => {
+                return Err(Error::InvalidSubaccount(account_num))
+            }
+            _ => None,
+        };
+
         store.write().unwrap().make_account(account_num, xpub.clone(), discovered)?;
 
         info!("initialized account #{} path={} type={:?}", account_num, path, script_type);
@@ -90,6 +134,8 @@ impl Account {
             store,
             master_blinding,
             path,
+            multisig,
+            green,
         })
     }
 
@@ -97,10 +143,30 @@ impl Account {
         self.account_num
     }
 
+    /// Follow a change of the SPV setting of the session
+    pub fn set_spv_enabled(&mut self, enabled: bool) {
+        self.network.spv_enabled = Some(enabled);
+    }
+
     pub fn script_type(&self) -> ScriptType {
         self.script_type
     }
 
+    pub fn master_xpub_fingerprint(&self) -> Fingerprint {
+        self.master_xpub_fingerprint
+    }
+
+    /// The cosigners of a multisig subaccount, `None` for single key ones
+    pub fn multisig(&self) -> Option<&Multisig> {
+        self.multisig.as_ref()
+    }
+
+    /// The scripts of the Green subaccount watched by the account, if it is
+    /// one, which can't be signed by the session
+    pub fn green(&self) -> Option<&GreenMultisig> {
+        self.green.as_ref()
+    }
+
     fn descriptor(&self, is_internal: bool) -> Result<String, Error> {
         let internal_idx = if is_internal {
             1
@@ -112,6 +178,16 @@ impl Account {
             ScriptType::P2wpkh => ("wpkh", ""),
             ScriptType::P2pkh => ("pkh", ""),
             ScriptType::P2tr => ("tr", ""),
+            ScriptType::P2wsh => {
+                let multisig =
+                    self.multisig.as_ref().ok_or(Error::InvalidSubaccount(self.account_num))?;
+                return multisig.descriptor(is_internal);
+            }
+            ScriptType::GreenMultisig => {
+                let green =
+                    self.green.as_ref().ok_or(Error::InvalidSubaccount(self.account_num))?;
+                return Ok(green.descriptor(is_internal));
+            }
         };
         let (_, path) = get_account_derivation(self.account_num, self.network.id())?;
         let parent_fingerprint = self.master_xpub_fingerprint.to_string();
@@ -188,6 +264,12 @@ impl Account {
         if let Some(hidden) = opt.hidden {
             settings.hidden = hidden;
         }
+        if opt.required_num_confs.is_some() {
+            settings.required_num_confs = opt.required_num_confs;
+        }
+        if opt.trust_own_unconfirmed.is_some() {
+            settings.trust_own_unconfirmed = opt.trust_own_unconfirmed;
+        }
         store_write.set_account_settings(self.account_num, settings)?;
         Ok(true)
     }
@@ -200,6 +282,14 @@ impl Account {
     }
 
     pub fn derive_address(&self, is_internal: bool, index: u32) -> Result<BEAddress, Error> {
+        if let (Some(multisig), NetworkId::Bitcoin(network)) = (&self.multisig, self.network.id()) {
+            let account_path =
+                DerivationPath::from(&[(is_internal as u32).into(), index.into()][..]);
+            return Ok(BEAddress::Bitcoin(multisig.address(&account_path, network)));
+        }
+        if let (Some(green), NetworkId::Bitcoin(network)) = (&self.green, self.network.id()) {
+            return Ok(BEAddress::Bitcoin(green.address(is_internal, index, network)?));
+        }
         derive_address(
             &self.chains[is_internal as usize],
             index,
@@ -209,6 +299,96 @@ impl Account {
         )
     }
 
+    /// The address at `index` derived again from the account xpub in the
+    /// store rather than from the one of the signer, None for multisig
+    /// accounts whose addresses need the keys of the cosigners too
+    pub fn derive_stored_address(
+        &self,
+        is_internal: bool,
+        index: u32,
+    ) -> Result<Option<BEAddress>, Error> {
+        if self.multisig.is_some() || self.green.is_some() {
+            return Ok(None);
+        }
+        let xpub = self.store.read()?.account_cache(self.account_num)?.xpub;
+        let chain = xpub.ckd_pub(&crate::EC, (is_internal as u32).into())?;
+        let address = derive_address(
+            &chain,
+            index,
+            self.script_type,
+            self.network.id(),
+            self.master_blinding.as_ref(),
+        )?;
+        Ok(Some(address))
+    }
+
+    /// Compare the cached scripts with the ones derived again from the
+    /// account xpub, all of them or `sample` random ones
+    ///
+    /// If `repair`, the mismatching scripts are replaced and their statuses
+    /// forgotten, so that the next sync subscribes to them and fetches their
+    /// history.
+    pub fn audit_derivations(
+        &self,
+        sample: Option<usize>,
+        repair: bool,
+    ) -> Result<(usize, Vec<DerivationMismatch>), Error> {
+        let mut cached: Vec<(DerivationPath, BEScript)> = {
+            let store = self.store.read()?;
+            let acc_store = store.account_cache(self.account_num)?;
+            acc_store.scripts.iter().map(|(path, script)| (path.clone(), script.clone())).collect()
+        };
+        if let Some(sample) = sample {
+            cached.shuffle(&mut thread_rng());
+            cached.truncate(sample);
+        }
+
+        let mut mismatches = vec![];
+        for (path, stored) in cached.iter() {
+            let (is_internal, pointer) = parse_path(path)?;
+            let derived = self.derive_address(is_internal, pointer)?.script_pubkey();
+            if &derived != stored {
+                mismatches.push((path, stored, derived, is_internal, pointer));
+            }
+        }
+
+        if repair && !mismatches.is_empty() {
+            let mut store = self.store.write()?;
+            let acc_store = store.account_cache_mut(self.account_num)?;
+            for (path, stored, derived, _, _) in mismatches.iter() {
+                if acc_store.paths.get(*stored) == Some(*path) {
+                    acc_store.paths.remove(*stored);
+                }
+                acc_store.paths.insert(derived.clone(), (*path).clone());
+                acc_store.scripts.insert((*path).clone(), derived.clone());
+                if let Some(statuses) = acc_store.script_statuses.as_mut() {
+                    statuses.remove(&stored.clone().into_bitcoin());
+                    statuses.remove(&derived.clone().into_bitcoin());
+                }
+            }
+        }
+
+        let mismatches = mismatches
+            .into_iter()
+            .map(|(_, stored, derived, is_internal, pointer)| DerivationMismatch {
+                subaccount: self.account_num,
+                pointer,
+                is_internal,
+                stored_script: stored.to_hex(),
+                derived_script: derived.to_hex(),
+                repaired: repair,
+            })
+            .collect();
+        Ok((cached.len(), mismatches))
+    }
+
+    /// The script cached for the address at `index`, if it was ever given
+    pub fn cached_script(&self, is_internal: bool, index: u32) -> Result<Option<BEScript>, Error> {
+        let account_path = DerivationPath::from(&[(is_internal as u32).into(), index.into()][..]);
+        let store = self.store.read()?;
+        Ok(store.account_cache(self.account_num)?.scripts.get(&account_path).cloned())
+    }
+
     pub fn get_next_address(
         &self,
         is_internal: bool,
@@ -243,6 +423,7 @@ impl Account {
             is_internal: is_internal,
             is_confidential: is_blinded,
             unconfidential_address: unconfidential_address,
+            device_confirmed: false,
         })
     }
 
@@ -321,21 +502,72 @@ impl Account {
         })
     }
 
-    pub fn list_tx(&self, opt: &GetTransactionsOpt) -> Result<Vec<TxListItem>, Error> {
+    pub fn list_tx(&self, opt: &GetTransactionsOpt) -> Result<TxsResult, Error> {
         let store = self.store.read()?;
         let acc_store = store.account_cache(self.account_num)?;
 
         let tip_height = store.cache.tip_height();
         let num_confs = opt.num_confs.unwrap_or(0);
+        let sync_generation = opt.sync_generation.unwrap_or_else(|| acc_store.sync_generation());
+        let amount_asset = match (&opt.asset_id, self.network.id()) {
+            (Some(asset_id), _) => asset_id.clone(),
+            (None, NetworkId::Bitcoin(_)) => "btc".to_string(),
+            (None, NetworkId::Elements(_)) => self.network.policy_asset.clone().unwrap_or_default(),
+        };
 
         let mut txs = vec![];
+        // Replaced and dropped transactions are listed as unconfirmed
+        let removed_txids = acc_store.removed_txids().map(|txid| (txid, &None));
         let mut my_txids: Vec<(&BETxid, &Option<u32>)> = acc_store
             .heights
             .iter()
+            .chain(removed_txids)
+            .filter(|(txid, _)| acc_store.tx_generation(txid) <= sync_generation)
             .filter(|(_, height)| {
                 num_confs <= height.map_or(0, |height| (tip_height + 1).saturating_sub(height))
             })
+            .filter(|(_, height)| {
+                if opt.created_at_from.is_none() && opt.created_at_to.is_none() {
+                    return true;
+                }
+                let range =
+                    opt.created_at_from.unwrap_or(0)..=opt.created_at_to.unwrap_or(u64::MAX);
+                range.contains(&tx_timestamp(&store, height))
+            })
+            .filter(|(txid, _)| {
+                if opt.asset_id.is_none() && opt.type_.is_none() && opt.min_satoshi.is_none() {
+                    return true;
+                }
+                let txe = match acc_store.all_txs.get(*txid) {
+                    Some(txe) => txe,
+                    None => return false,
+                };
+                let satoshi = txe.tx.my_balance_changes(
+                    &acc_store.all_txs,
+                    &acc_store.paths,
+                    &acc_store.unblinded,
+                );
+                if let Some(asset_id) = &opt.asset_id {
+                    if !satoshi.contains_key(asset_id) {
+                        return false;
+                    }
+                }
+                if let Some(min_satoshi) = opt.min_satoshi {
+                    let amount = satoshi.get(&amount_asset).map_or(0, |v| v.unsigned_abs());
+                    if amount < min_satoshi {
+                        return false;
+                    }
+                }
+                if let Some(type_) = &opt.type_ {
+                    let is_redeposit = txe.tx.is_redeposit(&acc_store.paths, &acc_store.all_txs);
+                    if txe.tx.type_(&satoshi, is_redeposit) != *type_ {
+                        return false;
+                    }
+                }
+                true
+            })
             .collect();
+        // Unconfirmed first, then by descending height, ties broken by txid
         my_txids.sort_by(|a, b| {
             let height_cmp = b.1.unwrap_or(std::u32::MAX).cmp(&a.1.unwrap_or(std::u32::MAX));
             match height_cmp {
@@ -343,48 +575,109 @@ impl Account {
                 h @ _ => h,
             }
         });
+        let total = my_txids.len();
 
         for (tx_id, height) in my_txids.iter().skip(opt.first).take(opt.count) {
-            let txe = acc_store
-                .all_txs
-                .get(*tx_id)
-                .ok_or_else(fn_err(&format!("list_tx no tx {}", tx_id)))?;
-            let tx = &txe.tx;
-
-            let timestamp = height
-                .map(|h| store.cache.headers.get(&h))
-                .flatten()
-                .map(|h| 1_000_000u64.saturating_mul(h.time() as u64))
-                .unwrap_or_else(now); // in microseconds
-
-            let memo = store.get_memo(tx_id).cloned().unwrap_or("".to_string());
-
-            let fee = tx.fee(
-                &acc_store.all_txs,
-                &acc_store.unblinded,
-                &self.network.policy_asset_id().ok(),
-            )?;
+            txs.push(self.tx_list_item(&store, acc_store, tx_id, height)?);
+        }
+        info!("list_tx {:?}", txs.iter().map(|e| &e.txhash).collect::<Vec<&String>>());
+
+        Ok(TxsResult {
+            transactions: txs,
+            total,
+            sync_generation,
+        })
+    }
 
-            let fee_rate = txe.fee_rate(fee);
+    /// The transactions of the account whose memo or an output label contains
+    /// `query`, compared in lowercase, in no particular order
+    pub fn search_tx(&self, query: &str) -> Result<Vec<TxListItem>, Error> {
+        let store = self.store.read()?;
+        let acc_store = store.account_cache(self.account_num)?;
+        let query = query.to_lowercase();
+        let contains = |text: &String| text.to_lowercase().contains(&query);
+        let mut txs = vec![];
+        for (txid, height) in acc_store.heights.iter() {
+            let matches = store.get_memo(txid).is_some_and(contains)
+                || store
+                    .get_output_labels(txid)
+                    .is_some_and(|labels| labels.values().any(contains));
+            if matches {
+                txs.push(self.tx_list_item(&store, acc_store, txid, height)?);
+            }
+        }
+        Ok(txs)
+    }
 
-            let satoshi =
-                tx.my_balance_changes(&acc_store.all_txs, &acc_store.paths, &acc_store.unblinded);
+    /// The transaction `txid` as listed by `list_tx`, None if it does not belong to the account
+    pub fn get_tx_list_item(&self, txid: &BETxid) -> Result<Option<TxListItem>, Error> {
+        let store = self.store.read()?;
+        let acc_store = store.account_cache(self.account_num)?;
+        match acc_store.heights.get(txid) {
+            Some(height) => Ok(Some(self.tx_list_item(&store, acc_store, txid, height)?)),
+            None if acc_store.removal(txid).is_some() => {
+                Ok(Some(self.tx_list_item(&store, acc_store, txid, &None)?))
+            }
+            None => Ok(None),
+        }
+    }
 
-            let is_redeposit = tx.is_redeposit(&acc_store.paths, &acc_store.all_txs);
-            let type_ = tx.type_(&satoshi, is_redeposit);
-            let user_signed = type_.user_signed();
+    fn tx_list_item(
+        &self,
+        store: &StoreMeta,
+        acc_store: &RawAccountCache,
+        tx_id: &BETxid,
+        height: &Option<u32>,
+    ) -> Result<TxListItem, Error> {
+        let txe =
+            acc_store.all_txs.get(tx_id).ok_or_else(fn_err(&format!("list_tx no tx {}", tx_id)))?;
+        let tx = &txe.tx;
 
-            let spv_verified = if self.network.spv_enabled.unwrap_or(false) {
-                store.spv_verification_status(self.num(), tx_id)
-            } else {
-                SPVVerifyTxResult::Disabled
-            };
+        let timestamp = tx_timestamp(store, height);
+        let header = height.and_then(|h| store.cache.headers.get(&h));
 
-            let rbf_optin = tx.rbf_optin();
-            let can_rbf = height.is_none() && rbf_optin && user_signed;
+        let memo = store.get_memo(tx_id).cloned().unwrap_or("".to_string());
 
-            let inputs = tx
-                .previous_outputs()
+        let fee =
+            tx.fee(&acc_store.all_txs, &acc_store.unblinded, &self.network.policy_asset_id().ok())?;
+
+        let fee_rate = txe.fee_rate(fee);
+
+        let mut satoshi =
+            tx.my_balance_changes(&acc_store.all_txs, &acc_store.paths, &acc_store.unblinded);
+
+        let is_redeposit = tx.is_redeposit(&acc_store.paths, &acc_store.all_txs);
+        let type_ = tx.type_(&satoshi, is_redeposit);
+        let user_signed = type_.user_signed();
+
+        let policy_asset = match self.network.id() {
+            NetworkId::Bitcoin(_) => "btc".to_string(),
+            NetworkId::Elements(_) => self.network.policy_asset.clone().unwrap_or_default(),
+        };
+        if type_ == TransactionType::Redeposit {
+            // Only the fee leaves the subaccount, the assets sent back to it
+            // are not payments
+            satoshi = HashMap::from([(policy_asset.clone(), -(fee as i64))]);
+        }
+
+        let spv_verified = if self.network.spv_enabled.unwrap_or(false) {
+            store.spv_verification_status(self.num(), tx_id)
+        } else {
+            SPVVerifyTxResult::Disabled
+        };
+
+        let fiat_at_confirmation =
+            store.confirmation_rate(tx_id).filter(|_| height.is_some()).map(|rate| {
+                rate.fiat_at_confirmation(satoshi.get(&policy_asset).copied().unwrap_or(0))
+            });
+
+        let removed = acc_store.removal(tx_id);
+        let reorgs = acc_store.reorgs(tx_id);
+        let rbf_optin = tx.rbf_optin();
+        let can_rbf = height.is_none() && removed.is_none() && rbf_optin && user_signed;
+
+        let inputs =
+            tx.previous_outputs()
                 .iter()
                 .enumerate()
                 .map(|(vin, beoutpoint)| {
@@ -494,11 +787,13 @@ impl Account {
                         unconfidential_address,
                         blinding_key,
                         script_pubkey,
+                        label: store.get_output_label(beoutpoint).cloned(),
                     })
                 })
                 .collect::<Result<Vec<GetTxInOut>, Error>>()?;
 
-            let outputs = (0..tx.output_len() as u32)
+        let outputs =
+            (0..tx.output_len() as u32)
                 .map(|vout| {
                     let (is_relevant, is_internal, pointer) = {
                         match acc_store.paths.get(&tx.output_script(vout)) {
@@ -576,39 +871,130 @@ impl Account {
                         unconfidential_address,
                         blinding_key,
                         script_pubkey,
+                        label: store.get_output_label(&tx.outpoint(vout)).cloned(),
                     })
                 })
                 .collect::<Result<Vec<GetTxInOut>, Error>>()?;
 
-            let discount_weight = match &txe.tx {
-                BETransaction::Bitcoin(_tx) => txe.weight,
-                BETransaction::Elements(tx) => tx.discount_weight(),
-            };
+        let internal_destinations = match type_ {
+            TransactionType::Redeposit => {
+                outputs.iter().filter(|o| o.is_relevant).map(|o| o.address.clone()).collect()
+            }
+            _ => vec![],
+        };
 
-            txs.push(TxListItem {
-                block_height: height.unwrap_or(0),
-                created_at_ts: timestamp,
-                type_,
-                memo,
-                txhash: tx_id.to_string(),
-                satoshi,
-                rbf_optin,
-                can_cpfp: false,
-                can_rbf,
-                spv_verified: spv_verified.to_string(),
-                fee,
-                fee_rate,
-                inputs,
-                outputs,
-                transaction_size: txe.size,
-                transaction_vsize: weight_to_vsize(txe.weight),
-                transaction_weight: txe.weight,
-                discount_weight,
-            });
+        let discount_weight = match &txe.tx {
+            BETransaction::Bitcoin(_tx) => txe.weight,
+            BETransaction::Elements(tx) => tx.discount_weight(),
+        };
+
+        Ok(TxListItem {
+            block_height: height.unwrap_or(0),
+            created_at_ts: timestamp,
+            type_,
+            memo,
+            txhash: tx_id.to_string(),
+            satoshi,
+            rbf_optin,
+            can_cpfp: false,
+            can_rbf,
+            spv_verified: spv_verified.to_string(),
+            fee,
+            fee_rate,
+            inputs,
+            outputs,
+            transaction_size: txe.size,
+            transaction_vsize: weight_to_vsize(txe.weight),
+            transaction_weight: txe.weight,
+            discount_weight,
+            fiat_at_confirmation,
+            removed,
+            internal_destinations,
+            reorg_count: reorgs.map_or(0, |reorgs| reorgs.count),
+            last_reorg_height: reorgs.map(|reorgs| reorgs.last_height),
+            block_hash: header.map(|header| header.block_hash().to_string()),
+            block_time: header.map(BEBlockHeader::time),
+        })
+    }
+
+    /// The path relative to the account of `address`
+    pub fn get_address_path(&self, address: &BEAddress) -> Result<DerivationPath, Error> {
+        let store = self.store.read()?;
+        let acc_store = store.account_cache(self.account_num)?;
+        Ok(acc_store.get_path(&address.script_pubkey())?.clone())
+    }
+
+    /// Sign `message` with the key at `path` of `signer`, which must hold
+    /// the keys of the account.
+    ///
+    /// Returns the signature and the address of the key.
+    pub fn sign_message(
+        &self,
+        signer: &dyn Signer,
+        path: &DerivationPath,
+        message: &str,
+        format: MessageFormat,
+    ) -> Result<(String, bitcoin::Address), Error> {
+        let network = match self.network.id() {
+            NetworkId::Bitcoin(network) => network,
+            NetworkId::Elements(_) => return Err(Error::Generic("not a Bitcoin account".into())),
+        };
+        let address = bitcoin_address(&self.public_key(path), self.script_type, network)?;
+        let full_path = self.get_full_path(path);
+        let signature = signer.sign_message(&full_path, self.script_type, message, format)?;
+        Ok((signature, address))
+    }
+
+    /// The wallet data of `script_pubkey`, if it belongs to the account
+    pub fn wallet_txo(
+        &self,
+        acc_store: &RawAccountCache,
+        script_pubkey: &BEScript,
+    ) -> Option<WalletTxo> {
+        if self.green.is_some() {
+            // None of the keys of the outputs is of the wallet
+            return None;
         }
-        info!("list_tx {:?}", txs.iter().map(|e| &e.txhash).collect::<Vec<&String>>());
+        let path = acc_store.paths.get(script_pubkey)?;
+        let origin = (self.master_xpub_fingerprint, self.get_full_path(path));
+        let (witness_script, cosigner_keys) = match &self.multisig {
+            Some(multisig) => {
+                let mut keys = multisig.public_keys(path);
+                keys.retain(|(_, key_origin)| *key_origin != origin);
+                (Some(multisig.witness_script(path)), keys)
+            }
+            None => (None, vec![]),
+        };
+        Some(WalletTxo {
+            subaccount: self.account_num,
+            public_key: self.public_key(path),
+            origin,
+            script_type: self.script_type,
+            witness_script,
+            cosigner_keys,
+        })
+    }
 
-        Ok(txs)
+    /// Whether `origin` is a key of the account
+    pub fn has_key(&self, origin: &KeySource) -> bool {
+        let (fingerprint, path) = origin;
+        self.green.is_none()
+            && *fingerprint == self.master_xpub_fingerprint
+            && path.as_ref().starts_with(self.path.as_ref())
+    }
+
+    /// The signer of the account from its extended private key `xprv`
+    pub fn xprv_signer(&self, xprv: &Xpriv) -> Result<SoftwareSigner, Error> {
+        if self.green.is_some() {
+            return Err(Error::WatchOnlySubaccount(self.account_num));
+        }
+        xpubs_equivalent(&Xpub::from_priv(&crate::EC, xprv), &self.xpub)?;
+        Ok(SoftwareSigner::from_account_xprv(*xprv, self.path.clone()))
+    }
+
+    /// The derivation path of the account from the master key
+    pub fn path(&self) -> &DerivationPath {
+        &self.path
     }
 
     pub fn public_key(&self, path: &DerivationPath) -> CompressedPublicKey {
@@ -617,6 +1003,14 @@ impl Account {
     }
 
     pub fn script_code(&self, path: &DerivationPath) -> BEScript {
+        if let Some(multisig) = &self.multisig {
+            // script_code is the witness script for p2wsh
+            return multisig.witness_script(path).into();
+        }
+        if let Some(green) = &self.green {
+            let (is_internal, pointer) = parse_path(path).expect("account paths are chain/pointer");
+            return green.script_code(is_internal, pointer).expect("green scripts derive").into();
+        }
         // FIXME: TAPROOT: elements p2tr
         let public_key = self.public_key(path);
         match (self.network.id(), self.script_type) {
@@ -675,15 +1069,71 @@ impl Account {
         })
     }
 
+    /// The utxo created by the unbroadcast `tx` at `vout`, paying the address
+    /// of the account at `account_path`, to spend it in a chained transaction
+    pub fn unbroadcast_txo(
+        &self,
+        tx: &BETransaction,
+        vout: u32,
+        account_path: &DerivationPath,
+    ) -> Result<Txo, Error> {
+        let (satoshi, txoutsecrets, txoutcommitments) = match tx {
+            BETransaction::Bitcoin(tx) => (tx.output[vout as usize].value.to_sat(), None, None),
+            BETransaction::Elements(tx) => {
+                let txout = &tx.output[vout as usize];
+                let master_blinding = self
+                    .master_blinding
+                    .as_ref()
+                    .ok_or_else(|| Error::Generic("missing master blinding key".into()))?;
+                let secrets = crate::unblind_output(txout.clone(), master_blinding, None)?;
+                (secrets.value, Some(secrets), Some((txout.asset, txout.value, txout.nonce)))
+            }
+        };
+        Ok(Txo {
+            outpoint: tx.outpoint(vout),
+            height: None,
+
+            public_key: self.public_key(account_path).into(),
+            script_pubkey: tx.output_script(vout),
+            script_code: self.script_code(account_path),
+
+            subaccount: self.account_num,
+            script_type: self.script_type,
+
+            user_path: self.get_full_path(account_path).into(),
+
+            satoshi,
+            sequence: None,
+            txoutsecrets,
+            txoutcommitments,
+        })
+    }
+
     pub fn unspents(&self) -> Result<HashSet<BEOutPoint>, Error> {
+        self.unspents_filtered(|_| true)
+    }
+
+    /// The outputs unspent once the block at `height` is connected, only
+    /// the transactions confirmed up to it are accounted
+    pub fn unspents_at(&self, height: u32) -> Result<HashSet<BEOutPoint>, Error> {
+        self.unspents_filtered(|h| matches!(h, Some(h) if h > 0 && h <= height))
+    }
+
+    /// The outputs unspent by the transactions whose height is `included`
+    fn unspents_filtered(
+        &self,
+        included: impl Fn(Option<u32>) -> bool,
+    ) -> Result<HashSet<BEOutPoint>, Error> {
         let mut relevant_outputs = HashSet::new();
         let mut inputs = HashSet::new();
         let store_read = self.store.read()?;
         let acc_store = store_read.account_cache(self.account_num)?;
         for (txid, txe) in acc_store.all_txs.iter() {
-            if !acc_store.heights.contains_key(&txid) {
-                // transaction has been replaced or dropped out of mempool
-                continue;
+            match acc_store.heights.get(txid) {
+                Some(height) if included(*height) => {}
+                // transaction has been replaced or dropped out of mempool,
+                // or is not confirmed at the height
+                _ => continue,
             }
             inputs.extend(txe.tx.previous_outputs());
             for vout in 0..(txe.tx.output_len() as u32) {
@@ -744,6 +1194,131 @@ impl Account {
         Ok(result)
     }
 
+    /// Find `script_pubkey` among the scripts of the account, the cached ones
+    /// and the ones up to `gap_limit` plus `search_depth` after the last used
+    /// of each chain.
+    ///
+    /// Scripts derived here are not stored, the pointers are left unchanged.
+    pub fn find_script(
+        &self,
+        script_pubkey: &BEScript,
+        gap_limit: u32,
+        search_depth: u32,
+    ) -> Result<Option<WalletScript>, Error> {
+        let store = self.store.read()?;
+        let acc_store = store.account_cache(self.account_num)?;
+        let wallet_script = |account_path: &DerivationPath| -> Result<WalletScript, Error> {
+            let (is_internal, pointer) = parse_path(account_path)?;
+            Ok(WalletScript {
+                subaccount: self.account_num,
+                is_internal,
+                pointer,
+                user_path: self.get_full_path(account_path).into(),
+            })
+        };
+        if let Ok(account_path) = acc_store.get_path(script_pubkey) {
+            return wallet_script(account_path).map(Some);
+        }
+        for is_internal in [false, true] {
+            let window_end = std::cmp::max(
+                acc_store.get_next_pointer(is_internal),
+                acc_store.last_used[is_internal] + gap_limit,
+            );
+            for index in 0..window_end.saturating_add(search_depth) {
+                let account_path =
+                    DerivationPath::from(&[(is_internal as u32).into(), index.into()][..]);
+                if acc_store.scripts.contains_key(&account_path) {
+                    continue;
+                }
+                if self.derive_address(is_internal, index)?.script_pubkey() == *script_pubkey {
+                    return wallet_script(&account_path).map(Some);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// The outputs of the wallet transactions paying `script_pubkey`, oldest first
+    pub fn address_payments(&self, script_pubkey: &BEScript) -> Result<Vec<AddressPayment>, Error> {
+        let store_read = self.store.read()?;
+        let acc_store = store_read.account_cache(self.account_num)?;
+        let tip_height = store_read.cache.tip_height();
+        let mut payments = vec![];
+        // Use heights to filter out the transactions reorged out or replaced
+        for (txid, height) in acc_store.heights.iter() {
+            let tx = match acc_store.all_txs.get(txid) {
+                Some(txe) => &txe.tx,
+                None => continue,
+            };
+            for vout in 0..tx.output_len() as u32 {
+                if &tx.output_script(vout) != script_pubkey {
+                    continue;
+                }
+                let block_height = height.unwrap_or(0);
+                payments.push(AddressPayment {
+                    txhash: txid.to_string(),
+                    pt_idx: vout,
+                    satoshi: tx.output_value(vout, &acc_store.unblinded).unwrap_or(0),
+                    asset_id: tx.output_asset(vout, &acc_store.unblinded).map(|a| a.to_string()),
+                    block_height,
+                    confirmations: match block_height {
+                        0 => 0,
+                        height => (tip_height + 1).saturating_sub(height),
+                    },
+                });
+            }
+        }
+        // Unconfirmed last
+        payments.sort_by(|a, b| {
+            let key = |payment: &AddressPayment| match payment.block_height {
+                0 => u32::MAX,
+                height => height,
+            };
+            key(a).cmp(&key(b)).then_with(|| (&a.txhash, a.pt_idx).cmp(&(&b.txhash, b.pt_idx)))
+        });
+        Ok(payments)
+    }
+
+    /// What the store knows of `script_pubkey`: the transactions paying or
+    /// spending it with their heights, its unspent outputs and its status
+    pub(crate) fn stored_script(&self, script_pubkey: &BEScript) -> Result<StoredScript, Error> {
+        let store_read = self.store.read()?;
+        let acc_store = store_read.account_cache(self.account_num)?;
+        let mut stored = StoredScript {
+            status: acc_store
+                .script_statuses
+                .as_ref()
+                .and_then(|statuses| statuses.get(&script_pubkey.clone().into_bitcoin()))
+                .cloned(),
+            ..Default::default()
+        };
+        let mut inputs = HashSet::new();
+        for (txid, height) in acc_store.heights.iter() {
+            let tx = match acc_store.all_txs.get(txid) {
+                Some(txe) => &txe.tx,
+                None => continue,
+            };
+            inputs.extend(tx.previous_outputs());
+            let mut touches = false;
+            for vout in 0..tx.output_len() as u32 {
+                if &tx.output_script(vout) == script_pubkey {
+                    stored.utxos.insert(tx.outpoint(vout));
+                    touches = true;
+                }
+            }
+            touches |= tx.previous_outputs().into_iter().any(|outpoint| {
+                acc_store.all_txs.get(&outpoint.txid()).is_some_and(|previous| {
+                    &previous.tx.output_script(outpoint.vout()) == script_pubkey
+                })
+            });
+            if touches {
+                stored.txs.insert(*txid, *height);
+            }
+        }
+        stored.utxos.retain(|outpoint| !inputs.contains(outpoint));
+        Ok(stored)
+    }
+
     pub fn get_address_data(&self, address: &BEAddress) -> Result<AddressDataResult, Error> {
         let store_read = self.store.read()?;
         let acc_store = store_read.account_cache(self.account_num)?;
@@ -755,6 +1330,14 @@ impl Account {
     }
 }
 
+/// See [`Account::stored_script`]
+#[derive(Debug, Default)]
+pub(crate) struct StoredScript {
+    pub(crate) txs: HashMap<BETxid, Option<u32>>,
+    pub(crate) utxos: HashSet<BEOutPoint>,
+    pub(crate) status: Option<ScriptStatus>,
+}
+
 pub(crate) fn compute_script_status<Txs>(txs: Txs) -> ScriptStatus
 where
     Txs: IntoIterator<Item = (BETxid, i32)>,
@@ -789,6 +1372,9 @@ pub fn get_account_script_purpose(account_num: u32) -> Result<(ScriptType, u32),
         1 => (ScriptType::P2wpkh, 84),
         2 => (ScriptType::P2pkh, 44),
         3 => (ScriptType::P2tr, 86),
+        4 => (ScriptType::P2wsh, 48),
+        // Not derived from the wallet keys, the purpose is unused
+        5 => (ScriptType::GreenMultisig, 0),
         _ => return Err(Error::InvalidSubaccount(account_num)),
     })
 }
@@ -802,8 +1388,13 @@ pub fn get_account_derivation(
     let bip32_account_num = account_num / NUM_RESERVED_ACCOUNT_TYPES;
 
     // BIP44: m / purpose' / coin_type' / account' / change / address_index
-    let path: DerivationPath =
-        format!("{}'/{}'/{}'", purpose, coin_type, bip32_account_num).parse().unwrap();
+    // BIP48: m / purpose' / coin_type' / account' / script_type' / change / address_index
+    let path = match script_type {
+        ScriptType::P2wsh => format!("{}'/{}'/{}'/2'", purpose, coin_type, bip32_account_num),
+        ScriptType::GreenMultisig => "m".to_string(),
+        _ => format!("{}'/{}'/{}'", purpose, coin_type, bip32_account_num),
+    };
+    let path: DerivationPath = path.parse().unwrap();
 
     info!("derivation path for account {}: {}", account_num, path);
 
@@ -837,35 +1428,47 @@ fn derive_address(
     master_blinding: Option<&MasterBlindingKey>,
 ) -> Result<BEAddress, Error> {
     let child_key = xpub.ckd_pub(&crate::EC, index.into())?;
+    key_address(&child_key.to_pub(), script_type, network_id, master_blinding)
+}
+
+/// The address of `script_type` of `public_key`, confidential on Liquid
+pub(crate) fn key_address(
+    public_key: &CompressedPublicKey,
+    script_type: ScriptType,
+    network_id: NetworkId,
+    master_blinding: Option<&MasterBlindingKey>,
+) -> Result<BEAddress, Error> {
     match network_id {
         NetworkId::Bitcoin(network) => {
-            let address = bitcoin_address(&child_key.to_pub(), script_type, network);
+            let address = bitcoin_address(public_key, script_type, network)?;
             Ok(BEAddress::Bitcoin(address))
         }
         NetworkId::Elements(network) => {
-            let address = elements_address(
-                &child_key.to_pub(),
-                master_blinding.expect("we are in elements but master blinding is None"),
-                script_type,
-                network,
-            );
+            let master_blinding = master_blinding.ok_or(Error::MissingMasterBlindingKey)?;
+            let address = elements_address(public_key, master_blinding, script_type, network)?;
             Ok(BEAddress::Elements(address))
         }
     }
 }
 
+/// The error of single key operations on multisig addresses
+fn not_single_key(script_type: ScriptType) -> Error {
+    Error::Generic(format!("{} addresses are not of a single key", script_type))
+}
+
 fn bitcoin_address(
     public_key: &CompressedPublicKey,
     script_type: ScriptType,
     net: bitcoin::Network,
-) -> bitcoin::Address {
+) -> Result<bitcoin::Address, Error> {
     use gdk_common::bitcoin::Address;
-    match script_type {
+    Ok(match script_type {
         ScriptType::P2shP2wpkh => Address::p2shwpkh(public_key, net),
         ScriptType::P2wpkh => Address::p2wpkh(public_key, net),
         ScriptType::P2pkh => Address::p2pkh(public_key, net),
         ScriptType::P2tr => Address::p2tr(&crate::EC, (*public_key).into(), None, net),
-    }
+        ScriptType::P2wsh | ScriptType::GreenMultisig => return Err(not_single_key(script_type)),
+    })
 }
 
 fn elements_address(
@@ -873,7 +1476,7 @@ fn elements_address(
     master_blinding_key: &MasterBlindingKey,
     script_type: ScriptType,
     net: ElementsNetwork,
-) -> elements::Address {
+) -> Result<elements::Address, Error> {
     let addr_params = net.address_params();
     let address = match script_type {
         ScriptType::P2pkh => elements::Address::p2pkh(&public_key.0.into(), None, addr_params),
@@ -885,16 +1488,18 @@ fn elements_address(
             let (x_only, _) = public_key.0.x_only_public_key();
             elements::Address::p2tr(&crate::EC, x_only, None, None, addr_params)
         }
+        ScriptType::P2wsh | ScriptType::GreenMultisig => return Err(not_single_key(script_type)),
     };
     let script_pubkey = address.script_pubkey();
     let blinding_prv = asset_blinding_key_to_ec_private_key(master_blinding_key, &script_pubkey);
     let blinding_pub = ec_public_key_from_private_key(blinding_prv);
-    address.to_confidential(blinding_pub)
+    Ok(address.to_confidential(blinding_pub))
 }
 
 pub fn discover_account(
     electrum_url: &ElectrumUrl,
     proxy: Option<&str>,
+    resolver: &Resolver,
     account_xpub: &Xpub,
     script_type: ScriptType,
     gap_limit: u32,
@@ -902,13 +1507,13 @@ pub fn discover_account(
     use gdk_common::electrum_client::ElectrumApi;
 
     // build our own client so that the subscriptions are dropped at the end
-    let client = electrum_url.build_client(proxy, None)?;
+    let client = electrum_url.build_client(proxy, None, resolver)?;
 
     let external_xpub = account_xpub.ckd_pub(&crate::EC, 0.into())?;
     for index in 0..gap_limit {
         let child_key = external_xpub.ckd_pub(&crate::EC, index.into())?;
         // Every network has the same scriptpubkey
-        let script = bitcoin_address(&child_key.to_pub(), script_type, bitcoin::Network::Bitcoin)
+        let script = bitcoin_address(&child_key.to_pub(), script_type, bitcoin::Network::Bitcoin)?
             .script_pubkey();
 
         if client.script_subscribe(&script)?.is_some() {
@@ -919,6 +1524,14 @@ pub fn discover_account(
     Ok(false)
 }
 
+/// The timestamp of the block at `height` in microseconds, now if unconfirmed
+fn tx_timestamp(store: &StoreMeta, height: &Option<u32>) -> u64 {
+    height
+        .and_then(|h| store.cache.headers.get(&h))
+        .map(|h| 1_000_000u64.saturating_mul(h.time() as u64))
+        .unwrap_or_else(now)
+}
+
 fn is_blinded_inner(blinder: &str) -> bool {
     blinder.chars().any(|c| c != '0')
 }
@@ -961,9 +1574,11 @@ mod test {
         test_derivation(1, ScriptType::P2wpkh, "84'/1'/0'");
         test_derivation(2, ScriptType::P2pkh, "44'/1'/0'");
         test_derivation(3, ScriptType::P2tr, "86'/1'/0'");
+        test_derivation(4, ScriptType::P2wsh, "48'/1'/0'/2'");
+        test_derivation(5, ScriptType::GreenMultisig, "m");
 
         // reserved for future use, currently rejected
-        for n in 4..=15 {
+        for n in 6..=15 {
             test_derivation_fails(n);
         }
 
@@ -971,12 +1586,15 @@ mod test {
         test_derivation(17, ScriptType::P2wpkh, "84'/1'/1'");
         test_derivation(18, ScriptType::P2pkh, "44'/1'/1'");
         test_derivation(19, ScriptType::P2tr, "86'/1'/1'");
-        test_derivation_fails(20);
+        test_derivation(20, ScriptType::P2wsh, "48'/1'/1'/2'");
+        test_derivation(21, ScriptType::GreenMultisig, "m");
+        test_derivation_fails(22);
 
         test_derivation(160, ScriptType::P2shP2wpkh, "49'/1'/10'");
         test_derivation(161, ScriptType::P2wpkh, "84'/1'/10'");
         test_derivation(162, ScriptType::P2pkh, "44'/1'/10'");
         test_derivation(163, ScriptType::P2tr, "86'/1'/10'");
+        test_derivation(164, ScriptType::P2wsh, "48'/1'/10'/2'");
     }
 
     #[test]
@@ -1030,4 +1648,127 @@ mod test {
             assert_eq!(script_status, expected);
         }
     }
+
+    #[test]
+    fn test_find_script() {
+        use gdk_common::store::ToCipher;
+        use std::sync::{Arc, RwLock};
+
+        let mut network = NetworkParameters::default();
+        network.development = true;
+        let xpub = Xpub::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let dir = tempfile::TempDir::new().unwrap();
+        let store = StoreMeta::new(dir.path(), &xpub.to_cipher().unwrap(), 1000).unwrap();
+        let store = Arc::new(RwLock::new(store));
+        let account =
+            Account::new(network, Fingerprint::default(), xpub, None, store.clone(), 0, false)
+                .unwrap();
+        account.get_next_address(false, false, 20).unwrap();
+
+        let script = |is_internal, index| {
+            account.derive_address(is_internal, index).unwrap().script_pubkey()
+        };
+        let find = |script, search_depth| {
+            account
+                .find_script(&script, 20, search_depth)
+                .unwrap()
+                .map(|s| (s.is_internal, s.pointer))
+        };
+        let pointers = |store: &Store| {
+            let store = store.read().unwrap();
+            let acc_store = store.account_cache(0).unwrap();
+            (
+                acc_store.get_next_pointer(false),
+                acc_store.get_next_pointer(true),
+                acc_store.paths.len(),
+            )
+        };
+        let before = pointers(&store);
+
+        // Given out, not given out within the gap window, and change
+        assert_eq!(find(script(false, 0), 0), Some((false, 0)));
+        assert_eq!(find(script(false, 10), 0), Some((false, 10)));
+        assert_eq!(find(script(true, 3), 0), Some((true, 3)));
+        let found = account.find_script(&script(true, 3), 20, 0).unwrap().unwrap();
+        assert_eq!(found.subaccount, 0);
+        assert_eq!(
+            DerivationPath::from(found.user_path),
+            DerivationPath::from_str("m/49'/1'/0'/1/3").unwrap()
+        );
+
+        // Beyond the gap window only with a search depth
+        assert_eq!(find(script(false, 30), 0), None);
+        assert_eq!(find(script(false, 30), 15), Some((false, 30)));
+
+        let foreign =
+            bitcoin::ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::from_byte_array([1; 20]));
+        assert_eq!(find(BEScript::Bitcoin(foreign), 100), None);
+
+        assert_eq!(pointers(&store), before);
+    }
+
+    #[test]
+    fn test_audit_derivations() {
+        use gdk_common::store::ToCipher;
+        use std::sync::{Arc, RwLock};
+
+        let mut network = NetworkParameters::default();
+        network.development = true;
+        let xpub = Xpub::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let dir = tempfile::TempDir::new().unwrap();
+        let store = StoreMeta::new(dir.path(), &xpub.to_cipher().unwrap(), 1000).unwrap();
+        let store = Arc::new(RwLock::new(store));
+        let account =
+            Account::new(network, Fingerprint::default(), xpub, None, store.clone(), 0, false)
+                .unwrap();
+        for _ in 0..5 {
+            account.get_next_address(false, false, 20).unwrap();
+        }
+        let (checked, mismatches) = account.audit_derivations(None, false).unwrap();
+        assert_eq!(checked, 5);
+        assert!(mismatches.is_empty());
+
+        // Corrupt the script cached for the third address
+        let path = DerivationPath::from_str("m/0/2").unwrap();
+        let derived = account.derive_address(false, 2).unwrap().script_pubkey();
+        let corrupt = BEScript::Bitcoin(bitcoin::ScriptBuf::new_p2wpkh(
+            &bitcoin::WPubkeyHash::from_byte_array([1; 20]),
+        ));
+        {
+            let mut store = store.write().unwrap();
+            let acc_store = store.account_cache_mut(0).unwrap();
+            acc_store.scripts.insert(path.clone(), corrupt.clone());
+            acc_store.paths.remove(&derived);
+            acc_store.paths.insert(corrupt.clone(), path.clone());
+        }
+
+        let (_, mismatches) = account.audit_derivations(None, false).unwrap();
+        let expected = DerivationMismatch {
+            subaccount: 0,
+            pointer: 2,
+            is_internal: false,
+            stored_script: corrupt.to_hex(),
+            derived_script: derived.to_hex(),
+            repaired: false,
+        };
+        assert_eq!(mismatches, [expected.clone()]);
+        // Not repaired, still detected
+        let (_, mismatches) = account.audit_derivations(Some(32), false).unwrap();
+        assert_eq!(mismatches, [expected.clone()]);
+
+        let (_, mismatches) = account.audit_derivations(None, true).unwrap();
+        assert_eq!(
+            mismatches,
+            [DerivationMismatch {
+                repaired: true,
+                ..expected
+            }]
+        );
+        let (_, mismatches) = account.audit_derivations(None, false).unwrap();
+        assert!(mismatches.is_empty());
+        let store = store.read().unwrap();
+        let acc_store = store.account_cache(0).unwrap();
+        assert_eq!(acc_store.paths.get(&derived), Some(&path));
+        assert_eq!(acc_store.paths.get(&corrupt), None);
+    }
 }
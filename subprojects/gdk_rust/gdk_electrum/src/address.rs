@@ -0,0 +1,479 @@
+//! Validation of the addresses given by the user.
+//!
+//! Addresses are often pasted with stray whitespace, or typed in uppercase to
+//! fit a QR code alphanumeric mode. [`parse_address`] trims them, accepts the
+//! all-uppercase form of bech32 and blech32 addresses, as their specification
+//! does, and tells why an address is not valid instead of failing with the
+//! error of the underlying library.
+//!
+//! Addresses are bound to the network by its prefixes, the ones the network
+//! parameters are validated against: an address of another network, Bitcoin
+//! or Liquid, mainnet or testnet, fails with [`Error::AddressWrongNetwork`]
+//! so that the caller can warn of the mistake.
+
+use std::str::FromStr;
+
+use gdk_common::amount::{self, AmountError};
+use gdk_common::be::BEAddress;
+use gdk_common::bitcoin::hashes::hex::FromHex;
+use gdk_common::error::ErrorCode;
+use gdk_common::network::NetworkParameters;
+use gdk_common::{bitcoin, elements, AddressPrefixes, ElementsNetwork, NetworkId};
+use serde::{Deserialize, Serialize};
+
+use crate::bip21;
+use crate::error::Error;
+
+/// The characters of the data part of bech32 and blech32 strings, by value
+const CHARSET: &str = "qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+const BECH32_CHECKSUM_LEN: usize = 6;
+const BLECH32_CHECKSUM_LEN: usize = 12;
+
+/// The length of the blinding public key in the program of blech32 addresses
+const BLINDING_KEY_LEN: usize = 33;
+
+/// The length of the payload of base58 addresses: the version and a hash
+const BASE58_LEN: usize = 21;
+
+/// The length of the payload of confidential base58 addresses: the blinded
+/// prefix, the version, the blinding public key and a hash
+const BASE58_CONFIDENTIAL_LEN: usize = 2 + BLINDING_KEY_LEN + 20;
+
+/// Parse `address` for `network`
+///
+/// Besides the errors of the address libraries, it fails with:
+/// - [`Error::AddressMixedCase`] for bech32 addresses mixing upper and lower case
+/// - [`Error::AddressWrongNetwork`] for addresses whose prefix is not one of `network`
+/// - [`Error::AddressUnknownWitnessVersion`] for witness versions above 16
+/// - [`Error::AddressWrongProgramLength`] for witness programs of invalid length
+/// - [`Error::AddressBadChecksum`] for well formed bech32 addresses with a wrong checksum
+pub fn parse_address(address: &str, network: NetworkId) -> Result<BEAddress, Error> {
+    let address = address.trim();
+    let prefixes = network.address_prefixes();
+    let address = match segwit_hrp(address) {
+        Some(hrp) => {
+            let lowercase = address.to_lowercase();
+            check_segwit(&lowercase, hrp, address, network, &prefixes)?;
+            lowercase
+        }
+        None => {
+            check_base58(address, &prefixes)?;
+            address.to_string()
+        }
+    };
+    match network {
+        NetworkId::Bitcoin(network) => {
+            let address = bitcoin::Address::from_str(&address)?;
+            let address = address.require_network(network).map_err(|e| match e {
+                bitcoin::address::ParseError::NetworkValidation(_) => Error::AddressWrongNetwork,
+                e => e.into(),
+            })?;
+            Ok(BEAddress::Bitcoin(address))
+        }
+        NetworkId::Elements(network) => {
+            let address = elements::Address::from_str(&address)?;
+            ensure!(address.params == network.address_params(), Error::AddressWrongNetwork);
+            Ok(BEAddress::Elements(address))
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ValidateAddressOpt {
+    /// The network parameters the address must belong to
+    pub network: NetworkParameters,
+
+    /// The address to validate
+    pub address: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ValidateAddressResult {
+    pub is_valid: bool,
+
+    /// The `id_*` string of the error, empty if the address is valid
+    pub error: String,
+
+    /// Why the address is not valid
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<ErrorCode>,
+}
+
+/// Validate the address in `opt`, telling why it is not valid
+///
+/// Used by the transaction builder of GDK so that both session types report
+/// the same errors for the addresses given by the user.
+pub fn validate_address(opt: &ValidateAddressOpt) -> ValidateAddressResult {
+    match parse_address(&opt.address, opt.network.id()) {
+        Ok(_) => ValidateAddressResult {
+            is_valid: true,
+            error: String::new(),
+            error_code: None,
+        },
+        Err(e) => ValidateAddressResult {
+            is_valid: false,
+            error: e.to_gdk_code(),
+            error_code: Some(e.code()),
+        },
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CheckSendAmountOpt {
+    /// The network parameters of the transaction
+    pub network: NetworkParameters,
+
+    /// The hex output script the amount is sent to
+    pub scriptpubkey: String,
+
+    /// The asset sent, the policy asset if not set
+    #[serde(default)]
+    pub asset_id: Option<String>,
+
+    /// The amount in satoshi, or in the smallest unit of the asset
+    #[serde(default)]
+    pub satoshi: Option<i64>,
+
+    /// The amount as a decimal in the unit of the asset, BTC for the policy
+    /// asset, instead of `satoshi`
+    #[serde(default)]
+    pub amount: Option<String>,
+
+    /// The decimal places of the asset, the ones in the asset registry, needed
+    /// to parse `amount` for assets other than the policy asset
+    #[serde(default)]
+    pub precision: Option<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CheckSendAmountResult {
+    /// The amount in satoshi, if it can be sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub satoshi: Option<u64>,
+
+    /// The `id_*` string of the error, empty if the amount can be sent
+    pub error: String,
+
+    /// Why the amount can't be sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<ErrorCode>,
+}
+
+/// Check the amount in `opt` can be sent to its output script, converting it
+/// to satoshi, telling why it can't otherwise
+pub fn check_send_amount(opt: &CheckSendAmountOpt) -> CheckSendAmountResult {
+    match send_amount(opt) {
+        Ok(satoshi) => CheckSendAmountResult {
+            satoshi: Some(satoshi),
+            error: String::new(),
+            error_code: None,
+        },
+        Err(e) => CheckSendAmountResult {
+            satoshi: None,
+            error: e.to_gdk_code(),
+            error_code: Some(e.code()),
+        },
+    }
+}
+
+/// The amount in `opt` in satoshi, if it can be sent
+///
+/// Only the policy asset has a dust limit, the one of the output script.
+fn send_amount(opt: &CheckSendAmountOpt) -> Result<u64, Error> {
+    let is_policy_asset =
+        !opt.network.liquid || opt.asset_id.is_none() || opt.asset_id == opt.network.policy_asset;
+    let satoshi = match (opt.satoshi, &opt.amount) {
+        (Some(satoshi), None) => {
+            ensure!(satoshi >= 0, AmountError::Negative);
+            satoshi as u64
+        }
+        (None, Some(decimal)) => {
+            let precision = match is_policy_asset {
+                true => bip21::DEFAULT_PRECISION,
+                false => opt.precision.ok_or(Error::InvalidAmount)?,
+            };
+            amount::parse(decimal, precision)?
+        }
+        _ => return Err(Error::InvalidAmount),
+    };
+    let dust = match is_policy_asset {
+        true => {
+            let script = Vec::<u8>::from_hex(&opt.scriptpubkey)?;
+            opt.network.policy().dust_limits.for_script(bitcoin::Script::from_bytes(&script))
+        }
+        false => 0,
+    };
+    Ok(amount::check_send(satoshi, dust)?)
+}
+
+/// The human readable part of `address` if it is a bech32 or blech32 address
+/// of any of the networks
+fn segwit_hrp(address: &str) -> Option<&'static str> {
+    let (hrp, _) = address.rsplit_once('1')?;
+    let hrp = hrp.to_lowercase();
+    all_hrps().into_iter().find(|known| **known == hrp)
+}
+
+/// Check the structure of the segwit `address`, in lowercase, whose human
+/// readable part is `hrp`, and `original` is not mixed case
+fn check_segwit(
+    address: &str,
+    hrp: &str,
+    original: &str,
+    network: NetworkId,
+    prefixes: &AddressPrefixes,
+) -> Result<(), Error> {
+    let has_lower = original.chars().any(|c| c.is_ascii_lowercase());
+    let has_upper = original.chars().any(|c| c.is_ascii_uppercase());
+    ensure!(!(has_lower && has_upper), Error::AddressMixedCase);
+    let blech32 = prefixes.blech32 == Some(hrp);
+    ensure!(prefixes.bech32 == hrp || blech32, Error::AddressWrongNetwork);
+
+    let checksum_len = if blech32 {
+        BLECH32_CHECKSUM_LEN
+    } else {
+        BECH32_CHECKSUM_LEN
+    };
+    let data = &address[hrp.len() + 1..];
+    let values = match data.chars().map(|c| CHARSET.find(c)).collect::<Option<Vec<_>>>() {
+        Some(values) if values.len() > checksum_len => values,
+        // Left to the address libraries to report
+        _ => return Ok(()),
+    };
+    let version = values[0] as u8;
+    ensure!(version <= 16, Error::AddressUnknownWitnessVersion(version));
+
+    let program_len = (values.len() - 1 - checksum_len) * 5 / 8;
+    let key_len = if blech32 {
+        BLINDING_KEY_LEN
+    } else {
+        0
+    };
+    let valid_len = match (version, program_len.checked_sub(key_len)) {
+        (0, Some(len)) => len == 20 || len == 32,
+        (_, Some(len)) => (2..=40).contains(&len),
+        (_, None) => false,
+    };
+    ensure!(valid_len, Error::AddressWrongProgramLength(program_len));
+
+    // The structure is valid, the address libraries can only reject the checksum
+    let parsed = match network {
+        NetworkId::Bitcoin(_) => bitcoin::Address::from_str(address).is_ok(),
+        NetworkId::Elements(_) => elements::Address::from_str(address).is_ok(),
+    };
+    ensure!(parsed, Error::AddressBadChecksum);
+    Ok(())
+}
+
+/// Check the version of the base58 `address` is one of `prefixes`
+///
+/// Strings that do not decode as an address are left to the address
+/// libraries to report.
+fn check_base58(address: &str, prefixes: &AddressPrefixes) -> Result<(), Error> {
+    let payload = match bitcoin::base58::decode_check(address) {
+        Ok(payload) => payload,
+        Err(_) => return Ok(()),
+    };
+    let versions = [prefixes.p2pkh, prefixes.p2sh];
+    let ours = match payload.len() {
+        BASE58_LEN => versions.contains(&payload[0]),
+        BASE58_CONFIDENTIAL_LEN => {
+            prefixes.blinded == Some(payload[0]) && versions.contains(&payload[1])
+        }
+        _ => return Ok(()),
+    };
+    ensure!(ours, Error::AddressWrongNetwork);
+    Ok(())
+}
+
+/// The human readable parts of the segwit addresses of all the networks
+fn all_hrps() -> Vec<&'static str> {
+    let mut all = vec![];
+    for network in [
+        NetworkId::Bitcoin(bitcoin::Network::Bitcoin),
+        NetworkId::Bitcoin(bitcoin::Network::Testnet),
+        NetworkId::Bitcoin(bitcoin::Network::Regtest),
+        NetworkId::Elements(ElementsNetwork::Liquid),
+        NetworkId::Elements(ElementsNetwork::LiquidTestnet),
+        NetworkId::Elements(ElementsNetwork::ElementsRegtest),
+    ] {
+        let prefixes = network.address_prefixes();
+        all.push(prefixes.bech32);
+        all.extend(prefixes.blech32);
+    }
+    all
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BTC: NetworkId = NetworkId::Bitcoin(bitcoin::Network::Bitcoin);
+    const TESTNET: NetworkId = NetworkId::Bitcoin(bitcoin::Network::Testnet);
+    const LIQUID: NetworkId = NetworkId::Elements(ElementsNetwork::Liquid);
+    const LIQUID_TESTNET: NetworkId = NetworkId::Elements(ElementsNetwork::LiquidTestnet);
+
+    #[test]
+    fn test_parse_address() {
+        let public_key = bitcoin::CompressedPublicKey::from_str(
+            "03ad1d8e89212f0b92c74d23bb710c00662ad1470198ac48c43f7d6f93a2a26873",
+        )
+        .unwrap();
+        let params = ElementsNetwork::Liquid.address_params();
+        let unconfidential = elements::Address::p2wpkh(&public_key.into(), None, params);
+        let confidential = unconfidential.to_confidential(public_key.0).to_string();
+        let unconfidential = unconfidential.to_string();
+        let p2wpkh = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+        let p2tr = "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0";
+        let p2pkh = "1BvBMSEYstWetqTFn5Au4m5GFg7xJaNVN2";
+
+        for (address, network) in [
+            (p2wpkh, BTC),
+            (p2tr, BTC),
+            (p2pkh, BTC),
+            (confidential.as_str(), LIQUID),
+            (unconfidential.as_str(), LIQUID),
+        ] {
+            let parsed = parse_address(address, network).unwrap();
+            assert_eq!(parsed.to_string(), address);
+            // Surrounding whitespace and the uppercase bech32 form are accepted
+            let padded = format!(" \t{}\n", address);
+            assert_eq!(parse_address(&padded, network).unwrap(), parsed);
+            if segwit_hrp(address).is_some() {
+                let upper = address.to_uppercase();
+                assert_eq!(parse_address(&upper, network).unwrap(), parsed);
+            }
+        }
+
+        let invalid_address = |network_mismatch| ErrorCode::InvalidAddress {
+            network_mismatch,
+        };
+        // Any single character change breaks the checksum
+        let typo = |address: &str| {
+            let last = if address.ends_with('q') {
+                'p'
+            } else {
+                'q'
+            };
+            format!("{}{}", &address[..address.len() - 1], last)
+        };
+        let table = [
+            (
+                "bc1qW508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4".into(),
+                BTC,
+                ErrorCode::InvalidAddressCase,
+            ),
+            (confidential.replacen("lq1", "LQ1", 1), LIQUID, ErrorCode::InvalidAddressCase),
+            (typo(p2wpkh), BTC, ErrorCode::InvalidAddressChecksum),
+            (typo(&confidential), LIQUID, ErrorCode::InvalidAddressChecksum),
+            (typo(&unconfidential), LIQUID, ErrorCode::InvalidAddressChecksum),
+            ("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx".into(), BTC, invalid_address(true)),
+            ("mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn".into(), BTC, invalid_address(true)),
+            (p2wpkh.into(), LIQUID, invalid_address(true)),
+            (confidential.clone(), BTC, invalid_address(true)),
+            (
+                "bc1lw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4".into(),
+                BTC,
+                ErrorCode::UnknownWitnessVersion,
+            ),
+            (confidential.replacen("lq1q", "lq1l", 1), LIQUID, ErrorCode::UnknownWitnessVersion),
+            (
+                "bc1qw508d6qejxtdg4y5r3zarvaryv8f3t4".into(),
+                BTC,
+                ErrorCode::InvalidWitnessProgramLength,
+            ),
+            (confidential[..40].to_string(), LIQUID, ErrorCode::InvalidWitnessProgramLength),
+            ("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3tb".into(), BTC, invalid_address(false)),
+            ("not an address".into(), BTC, invalid_address(false)),
+        ];
+        for (address, id, code) in table {
+            let err = parse_address(&address, id).unwrap_err();
+            assert_eq!(err.code(), code, "{} on {:?}: {}", address, id, err);
+        }
+    }
+
+    #[test]
+    fn test_parse_address_network() {
+        let public_key = bitcoin::CompressedPublicKey::from_str(
+            "03ad1d8e89212f0b92c74d23bb710c00662ad1470198ac48c43f7d6f93a2a26873",
+        )
+        .unwrap();
+        let blinding_key = Some(public_key.0);
+        let addresses = |id| -> Vec<String> {
+            match id {
+                NetworkId::Bitcoin(network) => vec![
+                    bitcoin::Address::p2wpkh(&public_key, network).to_string(),
+                    bitcoin::Address::p2pkh(public_key, network).to_string(),
+                    bitcoin::Address::p2shwpkh(&public_key, network).to_string(),
+                ],
+                NetworkId::Elements(network) => {
+                    let params = network.address_params();
+                    let public_key = public_key.into();
+                    vec![
+                        elements::Address::p2wpkh(&public_key, blinding_key, params).to_string(),
+                        elements::Address::p2wpkh(&public_key, None, params).to_string(),
+                        elements::Address::p2pkh(&public_key, blinding_key, params).to_string(),
+                        elements::Address::p2pkh(&public_key, None, params).to_string(),
+                        elements::Address::p2shwpkh(&public_key, blinding_key, params).to_string(),
+                    ]
+                }
+            }
+        };
+
+        // Every address is valid on its network only
+        let ids = [BTC, TESTNET, LIQUID, LIQUID_TESTNET];
+        for id in ids {
+            for address in addresses(id) {
+                assert_eq!(parse_address(&address, id).unwrap().to_string(), address);
+                for other in ids.into_iter().filter(|other| *other != id) {
+                    let err = parse_address(&address, other).unwrap_err();
+                    assert_eq!(
+                        err.code(),
+                        ErrorCode::InvalidAddress {
+                            network_mismatch: true
+                        },
+                        "{} of {:?} on {:?}: {}",
+                        address,
+                        id,
+                        other,
+                        err
+                    );
+                }
+            }
+        }
+
+        // Base58 strings other than addresses are not of another network
+        let wif = "5HueCGU8rMjxEXxiPuD5BDku4MkFqeZyd4dZ1jvhTVqvbTLvyTJ";
+        let err = parse_address(wif, LIQUID).unwrap_err();
+        assert_eq!(
+            err.code(),
+            ErrorCode::InvalidAddress {
+                network_mismatch: false
+            }
+        );
+    }
+
+    #[test]
+    fn test_validate_address() {
+        let network = NetworkParameters {
+            mainnet: true,
+            ..Default::default()
+        };
+        let opt = |address: &str| ValidateAddressOpt {
+            network: network.clone(),
+            address: address.to_string(),
+        };
+        let valid = validate_address(&opt(" bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4 "));
+        assert!(valid.is_valid);
+        assert_eq!(
+            serde_json::to_value(&valid).unwrap(),
+            serde_json::json!({"is_valid": true, "error": ""})
+        );
+
+        let invalid = validate_address(&opt("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5"));
+        assert!(!invalid.is_valid);
+        assert_eq!(invalid.error, "id_invalid_address");
+        assert_eq!(invalid.error_code, Some(ErrorCode::InvalidAddressChecksum));
+    }
+}
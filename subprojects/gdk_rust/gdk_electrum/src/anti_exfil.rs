@@ -0,0 +1,447 @@
+//! Host side of the ECDSA anti-exfil protocol, checking an external signer
+//! can't leak its keys through the nonces of its signatures.
+//!
+//! The host sends a commitment to random entropy with the signing request
+//! and the signer answers with the nonce point it's going to use. Then the
+//! host reveals its entropy and the signer signs with its nonce tweaked
+//! with it, as in the sign-to-contract scheme of libsecp256k1-zkp. Having
+//! chosen its nonce before knowing the entropy, the signer can't bias the
+//! final one, which the host checks before accepting the signature.
+
+use crate::error::Error;
+use crate::signer::{input_message, SignatureType, Signer};
+use gdk_common::be::{BETransaction, BETxOut};
+use gdk_common::bitcoin::bip32::DerivationPath;
+use gdk_common::bitcoin::hashes::{sha256, Hash, HashEngine};
+use gdk_common::bitcoin::secp256k1::constants::CURVE_ORDER;
+use gdk_common::bitcoin::secp256k1::{ecdsa, Message, PublicKey, Scalar};
+use gdk_common::rand::{thread_rng, Rng};
+use gdk_common::EC;
+
+/// The commitment to `host_entropy` sent with the signing request
+pub fn host_commitment(host_entropy: &[u8; 32]) -> [u8; 32] {
+    tagged_hash(b"s2c/ecdsa/data", &[host_entropy])
+}
+
+/// The tweak of the nonce of the signer, whose nonce point before the
+/// tweak is `signer_commitment`
+///
+/// Fails if the tweak overflows the curve order, with negligible probability.
+pub fn nonce_tweak(signer_commitment: &PublicKey, host_entropy: &[u8; 32]) -> Option<Scalar> {
+    let hash = tagged_hash(b"s2c/ecdsa/point", &[&signer_commitment.serialize(), host_entropy]);
+    Scalar::from_be_bytes(hash).ok()
+}
+
+/// Whether `signature` is a valid signature of `message` by `public_key`
+/// whose nonce point is `signer_commitment` tweaked with `host_entropy`
+pub fn verify(
+    signature: &ecdsa::Signature,
+    message: &Message,
+    public_key: &PublicKey,
+    host_entropy: &[u8; 32],
+    signer_commitment: &PublicKey,
+) -> bool {
+    if EC.verify_ecdsa(message, signature, public_key).is_err() {
+        return false;
+    }
+    let nonce_point = match nonce_tweak(signer_commitment, host_entropy)
+        .and_then(|tweak| signer_commitment.add_exp_tweak(&EC, &tweak).ok())
+    {
+        Some(nonce_point) => nonce_point,
+        None => return false,
+    };
+    let mut x = [0u8; 32];
+    x.copy_from_slice(&nonce_point.serialize()[1..]);
+    signature.serialize_compact()[..32] == reduce(x)
+}
+
+/// Sign the inputs of `tx` as [`Signer::sign_tx`], running the anti-exfil
+/// protocol with `signer` for the ECDSA signatures
+///
+/// Taproot inputs are signed as usual, the protocol covering ECDSA only.
+/// Fails with [`Error::AntiExfilVerification`] for the first input whose
+/// signature doesn't commit to the host entropy.
+pub(crate) fn sign_tx(
+    signer: &dyn Signer,
+    tx: &BETransaction,
+    prevouts: &[Option<BETxOut>],
+    paths: &[Option<DerivationPath>],
+    sighash_types: &[Option<u32>],
+) -> Result<Vec<Option<Vec<u8>>>, Error> {
+    let mut rng = thread_rng();
+    let mut expected = vec![];
+    let mut host_entropy = vec![];
+    for (i, path) in paths.iter().enumerate() {
+        let ecdsa_input = match path {
+            Some(path) => {
+                let public_key = signer.get_xpub(path)?.public_key;
+                let sighash_type = sighash_types.get(i).copied().flatten();
+                match input_message(tx, prevouts, i, &public_key, sighash_type)? {
+                    (message, SignatureType::Ecdsa(sighash_byte)) => {
+                        Some((public_key, message, sighash_byte))
+                    }
+                    (_, SignatureType::Schnorr(_)) => None,
+                }
+            }
+            None => None,
+        };
+        host_entropy.push(ecdsa_input.as_ref().map(|_| rng.gen::<[u8; 32]>()));
+        expected.push(ecdsa_input);
+    }
+    let host_commitments: Vec<_> =
+        host_entropy.iter().map(|entropy| entropy.as_ref().map(host_commitment)).collect();
+
+    let signer_commitments =
+        signer.anti_exfil_commit(tx, prevouts, paths, sighash_types, &host_commitments)?;
+    let signatures =
+        signer.anti_exfil_sign_tx(tx, prevouts, paths, sighash_types, &host_entropy)?;
+
+    for (i, (entropy, expected)) in host_entropy.iter().zip(expected.iter()).enumerate() {
+        let (entropy, (public_key, message, sighash_byte)) = match (entropy, expected) {
+            (Some(entropy), Some(expected)) => (entropy, expected),
+            _ => continue,
+        };
+        let signer_commitment = signer_commitments.get(i).cloned().flatten();
+        let signature = signatures.get(i).cloned().flatten();
+        let verified = match (signer_commitment, signature) {
+            (Some(signer_commitment), Some(signature)) => match signature.split_last() {
+                Some((byte, der)) if byte == sighash_byte => ecdsa::Signature::from_der(der)
+                    .map(|s| verify(&s, message, public_key, entropy, &signer_commitment))
+                    .unwrap_or(false),
+                _ => false,
+            },
+            _ => false,
+        };
+        if !verified {
+            return Err(Error::AntiExfilVerification(i));
+        }
+    }
+    Ok(signatures)
+}
+
+fn tagged_hash(tag: &[u8], data: &[&[u8]]) -> [u8; 32] {
+    let tag = sha256::Hash::hash(tag);
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_byte_array());
+    engine.input(tag.as_byte_array());
+    for data in data {
+        engine.input(data);
+    }
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+/// The x coordinate `x` of a point modulo the curve order, the `r` value of
+/// an ECDSA signature whose nonce point it is
+fn reduce(x: [u8; 32]) -> [u8; 32] {
+    if Scalar::from_be_bytes(x).is_ok() {
+        return x;
+    }
+    let mut reduced = [0u8; 32];
+    let mut borrow = 0i16;
+    for i in (0..32).rev() {
+        let mut digit = x[i] as i16 - CURVE_ORDER[i] as i16 - borrow;
+        borrow = (digit < 0) as i16;
+        digit += borrow * 256;
+        reduced[i] = digit as u8;
+    }
+    reduced
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::signer::SoftwareSigner;
+    use gdk_common::bitcoin;
+    use gdk_common::bitcoin::bip32::{Xpriv, Xpub};
+    use gdk_common::bitcoin::key::TapTweak;
+    use gdk_common::bitcoin::secp256k1::SecretKey;
+    use gdk_common::bitcoin::{
+        Amount, CompressedPublicKey, NetworkKind, OutPoint, ScriptBuf, Transaction, TxIn, TxOut,
+    };
+    use gdk_common::model::MessageFormat;
+    use gdk_common::scripts::ScriptType;
+    use gdk_common::util::MasterBlindingKey;
+    use gdk_common::NetworkId;
+    use std::str::FromStr;
+
+    /// A signer taking part in the anti-exfil protocol, not tweaking the
+    /// nonce of the input `cheat_input` with the host entropy
+    struct MockSigner {
+        xprv: Xpriv,
+        cheat_input: Option<usize>,
+    }
+
+    impl MockSigner {
+        fn software(&self) -> SoftwareSigner {
+            SoftwareSigner::from_account_xprv(self.xprv, DerivationPath::master())
+        }
+
+        fn secret_key(&self, path: &DerivationPath) -> SecretKey {
+            self.xprv.derive_priv(&EC, path).unwrap().private_key
+        }
+
+        /// The nonce committed for `message` before the host entropy is known
+        fn nonce(
+            &self,
+            secret_key: &SecretKey,
+            message: &Message,
+            host_commitment: &[u8; 32],
+        ) -> SecretKey {
+            let mut engine = sha256::Hash::engine();
+            engine.input(&secret_key.secret_bytes());
+            engine.input(message.as_ref());
+            engine.input(host_commitment);
+            SecretKey::from_slice(sha256::Hash::from_engine(engine).as_byte_array()).unwrap()
+        }
+
+        /// The message signed for input `i` and its sighash byte
+        fn message(
+            &self,
+            tx: &BETransaction,
+            prevouts: &[Option<BETxOut>],
+            i: usize,
+            secret_key: &SecretKey,
+            sighash_types: &[Option<u32>],
+        ) -> (Message, u8) {
+            let sighash_type = sighash_types.get(i).copied().flatten();
+            match input_message(tx, prevouts, i, &secret_key.public_key(&EC), sighash_type) {
+                Ok((message, SignatureType::Ecdsa(sighash_byte))) => (message, sighash_byte),
+                _ => panic!("not an ecdsa input"),
+            }
+        }
+    }
+
+    impl Signer for MockSigner {
+        fn get_xpub(&self, path: &DerivationPath) -> Result<Xpub, Error> {
+            self.software().get_xpub(path)
+        }
+
+        fn sign_tx(
+            &self,
+            tx: &BETransaction,
+            prevouts: &[Option<BETxOut>],
+            paths: &[Option<DerivationPath>],
+            sighash_types: &[Option<u32>],
+        ) -> Result<Vec<Option<Vec<u8>>>, Error> {
+            self.software().sign_tx(tx, prevouts, paths, sighash_types)
+        }
+
+        fn sign_message(
+            &self,
+            path: &DerivationPath,
+            script_type: ScriptType,
+            message: &str,
+            format: MessageFormat,
+        ) -> Result<String, Error> {
+            self.software().sign_message(path, script_type, message, format)
+        }
+
+        fn get_master_blinding_key(&self) -> Result<MasterBlindingKey, Error> {
+            self.software().get_master_blinding_key()
+        }
+
+        fn display_address(
+            &self,
+            path: &DerivationPath,
+            script_type: ScriptType,
+            network: NetworkId,
+        ) -> Result<String, Error> {
+            self.software().display_address(path, script_type, network)
+        }
+
+        fn anti_exfil(&self) -> bool {
+            true
+        }
+
+        fn anti_exfil_commit(
+            &self,
+            tx: &BETransaction,
+            prevouts: &[Option<BETxOut>],
+            paths: &[Option<DerivationPath>],
+            sighash_types: &[Option<u32>],
+            host_commitments: &[Option<[u8; 32]>],
+        ) -> Result<Vec<Option<PublicKey>>, Error> {
+            Ok(paths
+                .iter()
+                .zip(host_commitments)
+                .enumerate()
+                .map(|(i, (path, host_commitment))| {
+                    let host_commitment = host_commitment.as_ref()?;
+                    let secret_key = self.secret_key(path.as_ref()?);
+                    let (message, _) = self.message(tx, prevouts, i, &secret_key, sighash_types);
+                    let nonce = self.nonce(&secret_key, &message, host_commitment);
+                    Some(nonce.public_key(&EC))
+                })
+                .collect())
+        }
+
+        fn anti_exfil_sign_tx(
+            &self,
+            tx: &BETransaction,
+            prevouts: &[Option<BETxOut>],
+            paths: &[Option<DerivationPath>],
+            sighash_types: &[Option<u32>],
+            host_entropy: &[Option<[u8; 32]>],
+        ) -> Result<Vec<Option<Vec<u8>>>, Error> {
+            let mut signatures = self.sign_tx(tx, prevouts, paths, sighash_types)?;
+            for (i, (path, entropy)) in paths.iter().zip(host_entropy).enumerate() {
+                let (path, entropy) = match (path, entropy) {
+                    (Some(path), Some(entropy)) => (path, entropy),
+                    _ => continue,
+                };
+                let secret_key = self.secret_key(path);
+                let (message, sighash_byte) =
+                    self.message(tx, prevouts, i, &secret_key, sighash_types);
+                let mut nonce = self.nonce(&secret_key, &message, &host_commitment(entropy));
+                if self.cheat_input != Some(i) {
+                    let tweak = nonce_tweak(&nonce.public_key(&EC), entropy).unwrap();
+                    nonce = nonce.add_tweak(&tweak).unwrap();
+                }
+                let mut signature =
+                    sign_with_nonce(&message, &secret_key, &nonce).serialize_der().to_vec();
+                signature.push(sighash_byte);
+                signatures[i] = Some(signature);
+            }
+            Ok(signatures)
+        }
+    }
+
+    /// ECDSA signature of `message` with `secret_key` and nonce `nonce`
+    fn sign_with_nonce(
+        message: &Message,
+        secret_key: &SecretKey,
+        nonce: &SecretKey,
+    ) -> ecdsa::Signature {
+        let mut r = [0u8; 32];
+        r.copy_from_slice(&nonce.public_key(&EC).serialize()[1..]);
+        let r = reduce(r);
+        // s = (message + r * secret_key) / nonce
+        let s = secret_key
+            .mul_tweak(&Scalar::from_be_bytes(r).unwrap())
+            .unwrap()
+            .add_tweak(&Scalar::from_be_bytes(*message.as_ref()).unwrap())
+            .unwrap()
+            .mul_tweak(&Scalar::from(inverse(nonce)))
+            .unwrap();
+        let mut compact = [0u8; 64];
+        compact[..32].copy_from_slice(&r);
+        compact[32..].copy_from_slice(&s.secret_bytes());
+        let mut signature = ecdsa::Signature::from_compact(&compact).unwrap();
+        signature.normalize_s();
+        signature
+    }
+
+    /// The inverse of `key` modulo the curve order, `key^(n - 2)`
+    fn inverse(key: &SecretKey) -> SecretKey {
+        let mut exponent = CURVE_ORDER;
+        exponent[31] -= 2;
+        let mut one = [0u8; 32];
+        one[31] = 1;
+        let mut result = SecretKey::from_slice(&one).unwrap();
+        for byte in exponent {
+            for bit in (0..8).rev() {
+                result = result.mul_tweak(&Scalar::from(result)).unwrap();
+                if (byte >> bit) & 1 == 1 {
+                    result = result.mul_tweak(&Scalar::from(*key)).unwrap();
+                }
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn test_anti_exfil() {
+        let xprv = Xpriv::new_master(NetworkKind::Test, &[2; 32]).unwrap();
+        let path = DerivationPath::from_str("m/84'/1'/0'/0/1").unwrap();
+        let public_key = xprv.derive_priv(&EC, &path).unwrap().private_key.public_key(&EC);
+        let compressed = CompressedPublicKey(public_key);
+        let (output_key, _) = public_key.x_only_public_key().0.tap_tweak(&EC, None);
+        let script_pubkeys = [
+            ScriptBuf::new_p2wpkh(&compressed.wpubkey_hash()),
+            ScriptBuf::new_p2tr_tweaked(output_key),
+            ScriptBuf::new_op_return([]),
+            ScriptBuf::new_p2pkh(&compressed.pubkey_hash()),
+        ];
+        let prevouts: Vec<_> = script_pubkeys
+            .iter()
+            .map(|script_pubkey| {
+                Some(BETxOut::Bitcoin(TxOut {
+                    value: Amount::from_sat(10_000),
+                    script_pubkey: script_pubkey.clone(),
+                }))
+            })
+            .collect();
+        let tx = BETransaction::Bitcoin(Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: (0..4)
+                .map(|vout| TxIn {
+                    previous_output: OutPoint::new(bitcoin::Txid::all_zeros(), vout),
+                    ..Default::default()
+                })
+                .collect(),
+            output: vec![],
+        });
+        let paths = vec![Some(path.clone()), Some(path.clone()), None, Some(path)];
+
+        let signer = MockSigner {
+            xprv,
+            cheat_input: None,
+        };
+        let signatures = sign_tx(&signer, &tx, &prevouts, &paths, &[]).unwrap();
+        assert!(signatures[1].is_some());
+        assert!(signatures[2].is_none());
+        for i in [0, 3] {
+            let signature = signatures[i].as_ref().unwrap();
+            let signature = ecdsa::Signature::from_der(&signature[..signature.len() - 1]).unwrap();
+            let message = input_message(&tx, &prevouts, i, &public_key, None).unwrap().0;
+            EC.verify_ecdsa(&message, &signature, &public_key).unwrap();
+        }
+
+        // Valid signatures whose nonce doesn't commit to the host entropy
+        for i in [0, 3] {
+            let signer = MockSigner {
+                xprv,
+                cheat_input: Some(i),
+            };
+            match sign_tx(&signer, &tx, &prevouts, &paths, &[]) {
+                Err(Error::AntiExfilVerification(input)) => assert_eq!(input, i),
+                _ => panic!("unexpected result"),
+            }
+        }
+
+        // Signers not taking part in the protocol can't be asked to
+        let software = signer.software();
+        assert!(!software.anti_exfil());
+        assert!(sign_tx(&software, &tx, &prevouts, &paths, &[]).is_err());
+    }
+
+    #[test]
+    fn test_verify() {
+        let secret_key = SecretKey::from_slice(&[3; 32]).unwrap();
+        let public_key = secret_key.public_key(&EC);
+        let message = Message::from_digest([4; 32]);
+        let host_entropy = [5; 32];
+        let nonce = SecretKey::from_slice(&[6; 32]).unwrap();
+        let signer_commitment = nonce.public_key(&EC);
+        let tweak = nonce_tweak(&signer_commitment, &host_entropy).unwrap();
+        let signature = sign_with_nonce(&message, &secret_key, &nonce.add_tweak(&tweak).unwrap());
+        assert!(verify(&signature, &message, &public_key, &host_entropy, &signer_commitment));
+        assert!(!verify(&signature, &message, &public_key, &[7; 32], &signer_commitment));
+        let other_commitment = SecretKey::from_slice(&[8; 32]).unwrap().public_key(&EC);
+        assert!(!verify(&signature, &message, &public_key, &host_entropy, &other_commitment));
+        let other_message = Message::from_digest([9; 32]);
+        assert!(!verify(
+            &signature,
+            &other_message,
+            &public_key,
+            &host_entropy,
+            &signer_commitment
+        ));
+
+        // The nonce isn't tweaked, but the signature is valid
+        let signature = sign_with_nonce(&message, &secret_key, &nonce);
+        EC.verify_ecdsa(&message, &signature, &public_key).unwrap();
+        assert!(!verify(&signature, &message, &public_key, &host_entropy, &signer_commitment));
+    }
+}
@@ -0,0 +1,346 @@
+//! BIP21 payment URIs, and their Liquid adaptation adding the `assetid`
+//! parameter, see <https://github.com/ElementsProject/elements/issues/805>.
+//!
+//! Amounts are decimals in the unit of their asset, with at most as many
+//! decimal places as the asset precision: 8 for BTC and L-BTC, the one in
+//! the asset registry for other Liquid assets.
+
+use crate::address;
+use crate::error::Error;
+use gdk_common::amount;
+use gdk_common::be::BEAddress;
+use gdk_common::elements::AssetId;
+use gdk_common::model::ParsedUri;
+use gdk_common::url::form_urlencoded;
+use gdk_common::NetworkId;
+use std::str::FromStr;
+
+/// The precision of BTC and L-BTC amounts
+pub const DEFAULT_PRECISION: u8 = 8;
+
+/// Parse the payment `uri` for `network`, converting its amount to satoshi
+/// with `precision` decimal places.
+///
+/// Unknown parameters are ignored, unless they are required ones (`req-*`).
+pub fn parse_uri(uri: &str, network: NetworkId, precision: u8) -> Result<ParsedUri, Error> {
+    let (scheme, rest) = uri.trim().split_once(':').ok_or(Error::InvalidUri)?;
+    if !scheme.eq_ignore_ascii_case(network.bip21_prefix()) {
+        return Err(Error::InvalidUri);
+    }
+    let (address, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let (address, is_confidential) = parse_address(address, network)?;
+    let mut parsed = ParsedUri {
+        address,
+        is_confidential,
+        ..Default::default()
+    };
+    let mut decimal_amount = None;
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        match key.as_ref() {
+            "amount" => decimal_amount = Some(value.into_owned()),
+            "label" => parsed.label = Some(value.into_owned()),
+            "message" => parsed.message = Some(value.into_owned()),
+            "pj" => parsed.payjoin = Some(value.into_owned()),
+            "assetid" if is_confidential.is_some() => {
+                let asset_id = AssetId::from_str(&value).map_err(|_| Error::InvalidAssetId)?;
+                parsed.asset_id = Some(asset_id.to_string());
+            }
+            key if key.starts_with("req-") => {
+                return Err(Error::UnsupportedUriParameter(key.to_string()))
+            }
+            _ => (),
+        }
+    }
+    if let Some(decimal_amount) = decimal_amount {
+        // On Liquid an amount is meaningless without its asset
+        if is_confidential.is_some() && parsed.asset_id.is_none() {
+            return Err(Error::InvalidAssetId);
+        }
+        parsed.satoshi = Some(amount::parse(&decimal_amount, precision)?);
+    }
+    Ok(parsed)
+}
+
+/// Create the payment URI of `address` for `network`, with the optional
+/// amount `satoshi` of the asset with `precision` decimal places.
+///
+/// On Liquid `asset_id` is required if `satoshi` is set, on Bitcoin it must not be set.
+pub fn create_uri(
+    address: &str,
+    satoshi: Option<u64>,
+    asset_id: Option<AssetId>,
+    label: Option<&str>,
+    network: NetworkId,
+    precision: u8,
+) -> Result<String, Error> {
+    let (address, is_confidential) = parse_address(address, network)?;
+    let is_liquid = is_confidential.is_some();
+    if (is_liquid && satoshi.is_some() && asset_id.is_none()) || (!is_liquid && asset_id.is_some())
+    {
+        return Err(Error::InvalidAssetId);
+    }
+    let mut params = vec![];
+    if let Some(satoshi) = satoshi {
+        amount::check_supply(satoshi)?;
+        params.push(("amount", format_amount(satoshi, precision)));
+    }
+    if let Some(asset_id) = asset_id {
+        params.push(("assetid", asset_id.to_string()));
+    }
+    if let Some(label) = label {
+        params.push(("label", percent_encode(label)));
+    }
+    let mut uri = format!("{}:{}", network.bip21_prefix(), address);
+    for (i, (key, value)) in params.into_iter().enumerate() {
+        uri.push(if i == 0 {
+            '?'
+        } else {
+            '&'
+        });
+        uri.push_str(key);
+        uri.push('=');
+        uri.push_str(&value);
+    }
+    Ok(uri)
+}
+
+/// Parse `address` for `network`, returning its canonical form (lowercase
+/// for bech32) and, on Liquid only, whether it is confidential
+fn parse_address(address: &str, network: NetworkId) -> Result<(String, Option<bool>), Error> {
+    match address::parse_address(address, network)? {
+        BEAddress::Bitcoin(address) => Ok((address.to_string(), None)),
+        BEAddress::Elements(address) => Ok((address.to_string(), Some(address.is_blinded()))),
+    }
+}
+
+/// Format `satoshi` as a decimal with `precision` decimal places, without trailing zeros
+fn format_amount(satoshi: u64, precision: u8) -> String {
+    let amount = amount::format(satoshi as i64, precision);
+    match amount.split_once('.') {
+        Some((integer, fraction)) => match fraction.trim_end_matches('0') {
+            "" => integer.to_string(),
+            fraction => format!("{}.{}", integer, fraction),
+        },
+        None => amount,
+    }
+}
+
+/// Percent encode all but the unreserved characters of RFC 3986
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::new();
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gdk_common::bitcoin::secp256k1::{PublicKey, SecretKey};
+    use gdk_common::{bitcoin, elements, ElementsNetwork, EC};
+
+    const BTC: NetworkId = NetworkId::Bitcoin(bitcoin::Network::Bitcoin);
+    const LIQUID: NetworkId = NetworkId::Elements(ElementsNetwork::Liquid);
+    const P2PKH: &str = "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa";
+    const P2WPKH: &str = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+    const LBTC: &str = "6f0279e9ed041c3d710a9f57d0c02928416460c4b722ae3457a11eec381c526d";
+
+    fn liquid_address(blinded: bool) -> String {
+        let public_key = PublicKey::from_secret_key(&EC, &SecretKey::from_slice(&[1; 32]).unwrap());
+        let blinder = Some(public_key).filter(|_| blinded);
+        let params = &elements::AddressParams::LIQUID;
+        elements::Address::p2wpkh(&bitcoin::PublicKey::new(public_key), blinder, params).to_string()
+    }
+
+    fn parsed(address: &str) -> ParsedUri {
+        ParsedUri {
+            address: address.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_uri() {
+        let confidential = liquid_address(true);
+        let unconfidential = liquid_address(false);
+        let cases = vec![
+            (format!("bitcoin:{}", P2PKH), BTC, parsed(P2PKH)),
+            (
+                format!("bitcoin:{}?label=Luke-Jr", P2PKH),
+                BTC,
+                ParsedUri {
+                    label: Some("Luke-Jr".into()),
+                    ..parsed(P2PKH)
+                },
+            ),
+            (
+                format!("bitcoin:{}?amount=20.3&label=Luke-Jr", P2PKH),
+                BTC,
+                ParsedUri {
+                    satoshi: Some(2_030_000_000),
+                    label: Some("Luke-Jr".into()),
+                    ..parsed(P2PKH)
+                },
+            ),
+            (
+                format!("bitcoin:{}?amount=50&message=Donation%20for%20project%20xyz", P2PKH),
+                BTC,
+                ParsedUri {
+                    satoshi: Some(5_000_000_000),
+                    message: Some("Donation for project xyz".into()),
+                    ..parsed(P2PKH)
+                },
+            ),
+            (
+                format!(
+                    "bitcoin:{}?somethingyoudontunderstand=50&somethingelseyoudontget=999",
+                    P2PKH
+                ),
+                BTC,
+                parsed(P2PKH),
+            ),
+            // Uppercase scheme and bech32 address, as in QR codes
+            (
+                format!("BITCOIN:{}?amount=.00000001", P2WPKH.to_uppercase()),
+                BTC,
+                ParsedUri {
+                    satoshi: Some(1),
+                    ..parsed(P2WPKH)
+                },
+            ),
+            (
+                format!(" bitcoin:{}?amount=1.&label=a+b%26c ", P2WPKH),
+                BTC,
+                ParsedUri {
+                    satoshi: Some(100_000_000),
+                    label: Some("a b&c".into()),
+                    ..parsed(P2WPKH)
+                },
+            ),
+            (
+                format!("bitcoin:{}?amount=0.1&pj=https://example.com/pj%3Fid%3D1&pjos=0", P2WPKH),
+                BTC,
+                ParsedUri {
+                    satoshi: Some(10_000_000),
+                    payjoin: Some("https://example.com/pj?id=1".into()),
+                    ..parsed(P2WPKH)
+                },
+            ),
+            (
+                format!("liquidnetwork:{}?amount=0.001&assetid={}", confidential, LBTC),
+                LIQUID,
+                ParsedUri {
+                    satoshi: Some(100_000),
+                    asset_id: Some(LBTC.into()),
+                    is_confidential: Some(true),
+                    ..parsed(&confidential)
+                },
+            ),
+            (
+                format!("liquidnetwork:{}?assetid={}", unconfidential, LBTC.to_uppercase()),
+                LIQUID,
+                ParsedUri {
+                    asset_id: Some(LBTC.into()),
+                    is_confidential: Some(false),
+                    ..parsed(&unconfidential)
+                },
+            ),
+        ];
+        for (uri, network, expected) in cases {
+            assert_eq!(parse_uri(&uri, network, DEFAULT_PRECISION).unwrap(), expected, "{}", uri);
+        }
+    }
+
+    #[test]
+    fn test_parse_uri_errors() {
+        let confidential = liquid_address(true);
+        let cases = vec![
+            (
+                format!("bitcoin:{}?req-somethingyoudontunderstand=50", P2PKH),
+                BTC,
+                "id_unknown_bip21_parameter",
+            ),
+            (format!("bitcoin:{}?amount=0.000000001", P2PKH), BTC, "id_invalid_amount"),
+            (format!("bitcoin:{}?amount=1,5", P2PKH), BTC, "id_invalid_amount"),
+            (format!("bitcoin:{}?amount=-1", P2PKH), BTC, "id_invalid_amount"),
+            (format!("bitcoin:{}?amount=1e3", P2PKH), BTC, "id_invalid_amount"),
+            (format!("bitcoin:{}?amount=.", P2PKH), BTC, "id_invalid_amount"),
+            (format!("bitcoin:{}?amount=999999999999", P2PKH), BTC, "id_invalid_amount"),
+            ("bitcoin:notanaddress".to_string(), BTC, "id_invalid_address"),
+            ("bitcoin:?amount=1".to_string(), BTC, "id_invalid_address"),
+            (P2PKH.to_string(), BTC, "id_invalid_address"),
+            (format!("litecoin:{}", P2PKH), BTC, "id_invalid_address"),
+            (format!("liquidnetwork:{}", confidential), BTC, "id_invalid_address"),
+            (format!("liquidtestnet:{}", confidential), LIQUID, "id_invalid_address"),
+            (format!("liquidnetwork:{}?amount=1", confidential), LIQUID, "id_invalid_asset_id"),
+            (format!("liquidnetwork:{}?assetid=xyz", confidential), LIQUID, "id_invalid_asset_id"),
+        ];
+        for (uri, network, code) in cases {
+            let err = parse_uri(&uri, network, DEFAULT_PRECISION).unwrap_err();
+            assert_eq!(err.to_gdk_code(), code, "{}", uri);
+        }
+
+        let testnet = "bitcoin:tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+        let err = parse_uri(testnet, BTC, DEFAULT_PRECISION).unwrap_err();
+        assert_eq!(
+            err.code(),
+            gdk_common::error::ErrorCode::InvalidAddress {
+                network_mismatch: true
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_uri_precision() {
+        let uri = |amount: &str| format!("bitcoin:{}?amount={}", P2PKH, amount);
+        assert_eq!(parse_uri(&uri("1.23"), BTC, 2).unwrap().satoshi, Some(123));
+        assert_eq!(parse_uri(&uri("7"), BTC, 0).unwrap().satoshi, Some(7));
+        assert!(parse_uri(&uri("1.234"), BTC, 2).is_err());
+        assert!(parse_uri(&uri("7.5"), BTC, 0).is_err());
+    }
+
+    #[test]
+    fn test_create_uri() {
+        assert_eq!(
+            create_uri(P2PKH, None, None, None, BTC, DEFAULT_PRECISION).unwrap(),
+            format!("bitcoin:{}", P2PKH)
+        );
+        let uri = create_uri(
+            &P2WPKH.to_uppercase(),
+            Some(2_030_000_000),
+            None,
+            Some("Luke-Jr & co"),
+            BTC,
+            DEFAULT_PRECISION,
+        )
+        .unwrap();
+        assert_eq!(uri, format!("bitcoin:{}?amount=20.3&label=Luke-Jr%20%26%20co", P2WPKH));
+        let parsed = parse_uri(&uri, BTC, DEFAULT_PRECISION).unwrap();
+        assert_eq!(parsed.satoshi, Some(2_030_000_000));
+        assert_eq!(parsed.label.as_deref(), Some("Luke-Jr & co"));
+        assert_eq!(format_amount(1, DEFAULT_PRECISION), "0.00000001");
+        assert_eq!(format_amount(1_000, 0), "1000");
+
+        let address = liquid_address(true);
+        let asset_id = AssetId::from_str(LBTC).unwrap();
+        let uri =
+            create_uri(&address, Some(100_000), Some(asset_id), None, LIQUID, DEFAULT_PRECISION)
+                .unwrap();
+        assert_eq!(uri, format!("liquidnetwork:{}?amount=0.001&assetid={}", address, LBTC));
+        assert_eq!(parse_uri(&uri, LIQUID, DEFAULT_PRECISION).unwrap().satoshi, Some(100_000));
+
+        let err = create_uri(&address, Some(1), None, None, LIQUID, DEFAULT_PRECISION).unwrap_err();
+        assert_eq!(err.to_gdk_code(), "id_invalid_asset_id");
+        let err =
+            create_uri(P2PKH, Some(1), Some(asset_id), None, BTC, DEFAULT_PRECISION).unwrap_err();
+        assert_eq!(err.to_gdk_code(), "id_invalid_asset_id");
+        let err = create_uri(P2PKH, None, None, None, LIQUID, DEFAULT_PRECISION).unwrap_err();
+        assert_eq!(err.to_gdk_code(), "id_invalid_address");
+    }
+}
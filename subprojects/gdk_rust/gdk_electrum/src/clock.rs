@@ -0,0 +1,140 @@
+//! The network adjusted time.
+//!
+//! The clock of a device can be wrong by hours, making the blocks just found
+//! look far in the past or in the future. The timestamps of the tips got from
+//! the server are samples of the time of the network: the median of their
+//! offsets from the local clock is the skew of the local clock, which the
+//! checks of the header timestamps correct.
+
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+use gdk_common::util;
+
+/// How far in the future of the network time a block timestamp can be, as
+/// in Bitcoin Core
+pub const MAX_FUTURE_BLOCK_TIME: u64 = 2 * 60 * 60;
+
+/// The skew above which the user is warned to fix the clock of the device.
+///
+/// Tips are rarely this old, a lower skew can be just the age of the tip.
+pub const CLOCK_SKEW_WARNING: u64 = 2 * 60 * 60;
+
+/// The number of the most recent samples kept
+const MAX_SAMPLES: usize = 11;
+
+/// The source of the local time
+pub trait Clock: Debug + Send + Sync {
+    /// The unix timestamp in seconds
+    fn now(&self) -> u64;
+}
+
+/// The clock of the device
+#[derive(Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        util::now() / 1_000_000
+    }
+}
+
+/// The local time corrected by the skew of the local clock, shared by the
+/// clones
+#[derive(Debug, Clone)]
+pub struct NetworkTime {
+    clock: Arc<dyn Clock>,
+
+    /// The offsets in seconds of the local clock from the timestamps of the
+    /// last tips, the newest last
+    samples: Arc<Mutex<VecDeque<i64>>>,
+}
+
+impl Default for NetworkTime {
+    fn default() -> Self {
+        NetworkTime::new(Arc::new(SystemClock))
+    }
+}
+
+impl NetworkTime {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        NetworkTime {
+            clock,
+            samples: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /// Add the `timestamp` of a tip got from the server, returning the skew
+    /// if it exceeds [`CLOCK_SKEW_WARNING`] and did not before
+    pub fn add_sample(&self, timestamp: u32) -> Option<i64> {
+        let was_skewed = self.skew().is_some_and(exceeds_warning);
+        {
+            let mut samples = self.samples.lock().unwrap();
+            if samples.len() == MAX_SAMPLES {
+                samples.pop_front();
+            }
+            samples.push_back(self.clock.now() as i64 - timestamp as i64);
+        }
+        self.skew().filter(|skew| exceeds_warning(*skew) && !was_skewed)
+    }
+
+    /// The seconds the local clock is ahead of the network, negative if it
+    /// is behind, `None` before the first sample
+    pub fn skew(&self) -> Option<i64> {
+        let mut samples: Vec<i64> = self.samples.lock().unwrap().iter().copied().collect();
+        samples.sort_unstable();
+        samples.get(samples.len() / 2).copied()
+    }
+
+    /// The unix timestamp in seconds of the network
+    pub fn now(&self) -> u64 {
+        let now = self.clock.now() as i64 - self.skew().unwrap_or(0);
+        now.max(0) as u64
+    }
+}
+
+fn exceeds_warning(skew: i64) -> bool {
+    skew.unsigned_abs() > CLOCK_SKEW_WARNING
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// A clock `offset` seconds off the clock of the device
+    #[derive(Debug)]
+    pub(crate) struct SkewedClock(pub i64);
+
+    impl Clock for SkewedClock {
+        fn now(&self) -> u64 {
+            (SystemClock.now() as i64 + self.0) as u64
+        }
+    }
+
+    #[test]
+    fn test_network_time() {
+        let three_hours = 3 * 60 * 60;
+        let time = NetworkTime::new(Arc::new(SkewedClock(-three_hours)));
+        let now = SystemClock.now();
+        assert_eq!(time.skew(), None);
+        assert!(time.now() < now);
+
+        // The first tip just found tells the skew, warned about once
+        let skew = time.add_sample(now as u32).unwrap();
+        assert!((-three_hours..=-three_hours + 1).contains(&skew));
+        assert!(time.now().abs_diff(now) <= 1);
+        assert_eq!(time.add_sample(now as u32), None);
+
+        // A stale tip does not move the median of the fresh ones
+        time.add_sample(now as u32);
+        time.add_sample((now - 10 * 60 * 60) as u32);
+        assert!(time.now().abs_diff(now) <= 1);
+
+        // Newer samples agreeing with the local clock replace the old ones
+        for _ in 0..MAX_SAMPLES {
+            time.add_sample(time.clock.now() as u32);
+        }
+        assert!(time.skew().unwrap().abs() <= 1);
+    }
+}
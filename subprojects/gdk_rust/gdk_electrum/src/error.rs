@@ -1,6 +1,10 @@
 use crate::BETxid;
+use gdk_common::amount::AmountError;
 use gdk_common::bitcoin::bip32::Xpub;
-use gdk_common::error::Error as CommonError;
+use gdk_common::error::{Error as CommonError, ErrorCode};
+use gdk_common::exchange_rates::Currency;
+use gdk_common::model::{MessageFormat, RejectReason};
+use gdk_common::scripts::ScriptType;
 use gdk_common::{bitcoin, electrum_client, elements, serde_cbor, ureq};
 use serde::ser::Serialize;
 use std::convert::From;
@@ -15,6 +19,35 @@ pub enum Error {
     #[error("could not parse SocketAddr `{0}`")]
     AddrParse(String),
 
+    #[error(
+        "the device shows address {device} instead of {expected}, the host may be compromised"
+    )]
+    AddressMismatch {
+        expected: String,
+        device: String,
+    },
+
+    #[error("bech32 address mixing upper and lower case")]
+    AddressMixedCase,
+
+    #[error("invalid address checksum")]
+    AddressBadChecksum,
+
+    #[error("address of another network")]
+    AddressWrongNetwork,
+
+    #[error("unknown address witness version {0}")]
+    AddressUnknownWitnessVersion(u8),
+
+    #[error("invalid address witness program length {0}")]
+    AddressWrongProgramLength(usize),
+
+    #[error(transparent)]
+    Amount(#[from] AmountError),
+
+    #[error("the signature of input {0} does not commit to the anti-exfil host entropy")]
+    AntiExfilVerification(usize),
+
     #[error("Expected a {expected}")]
     AvailableIndexesBadResponse {
         expected: String,
@@ -62,6 +95,9 @@ pub enum Error {
     #[error(transparent)]
     Encryption(#[from] block_modes::BlockModeError),
 
+    #[error("id_fee_rate_is_below_minimum")]
+    FeeRateBelowMinimum,
+
     #[error(transparent)]
     JSON(#[from] serde_json::error::Error),
 
@@ -74,6 +110,54 @@ pub enum Error {
     #[error("invalid headers")]
     InvalidHeaders,
 
+    #[error("height {height} is above the tip {tip}")]
+    HeightAboveTip {
+        height: u32,
+        tip: u32,
+    },
+
+    #[error("id_insufficient_funds")]
+    InsufficientFunds,
+
+    #[error("insufficient funds to pay the fee")]
+    InsufficientFundsForFee,
+
+    #[error("id_invalid_amount")]
+    InvalidAmount,
+
+    #[error("the transaction exceeds the spend limits and must be approved")]
+    ConfirmationRequired,
+
+    #[error("signing input {input} with sighash type {sighash_type:#x} lets others change the outputs it pays")]
+    DangerousSighash {
+        input: usize,
+        sighash_type: u32,
+    },
+
+    #[error("the device did not display the address in time")]
+    DisplayAddressTimeout,
+
+    #[error("the {0} exchange rate is not available")]
+    FiatRateUnavailable(Currency),
+
+    #[error("the {currency} currency is not available at the {exchange} exchange")]
+    UnsupportedCurrency {
+        currency: String,
+        exchange: String,
+    },
+
+    #[error("id_invalid_address")]
+    InvalidMainchainAddress,
+
+    #[error("invalid ownership proof: {0}")]
+    InvalidOwnershipProof(String),
+
+    #[error("id_invalid_asset_id")]
+    InvalidAssetId,
+
+    #[error("id_invalid_address")]
+    InvalidUri,
+
     #[error(transparent)]
     InvalidKeyIvLength(#[from] block_modes::InvalidKeyIvLength),
 
@@ -98,12 +182,24 @@ pub enum Error {
     #[error("Master blinding key is missing but we need it")]
     MissingMasterBlindingKey,
 
+    #[error("No signer, pass the extended private key or login with a signer")]
+    MissingSigner,
+
+    #[error("none of the keys of the multisig descriptor is the one of the wallet")]
+    MultisigKeyNotFound,
+
+    #[error("subaccount {0} is watch-only")]
+    WatchOnlySubaccount(u32),
+
     #[error("Mutex is poisoned: {0}")]
     MutexPoisonError(String),
 
     #[error("Invalid proxy socket: {0}")]
     InvalidProxySocket(String),
 
+    #[error("cannot resolve {0}: {1}")]
+    NameResolution(String, String),
+
     #[error("{}", match .0 {
         gdk_pin_client::Error::InvalidPin
         | gdk_pin_client::Error::Decryption(_) => "id_invalid_pin",
@@ -111,6 +207,9 @@ pub enum Error {
     })]
     PinClient(#[from] gdk_pin_client::Error),
 
+    #[error("proxy {0} is unreachable")]
+    ProxyUnreachable(String),
+
     #[error("RW lock is poisoned: {0}")]
     RwLockPoisonError(String),
 
@@ -126,24 +225,90 @@ pub enum Error {
     #[error(transparent)]
     Send(#[from] std::sync::mpsc::SendError<()>),
 
+    #[error("the transaction policy requires a confirmation: {0}")]
+    PolicyConfirmationRequired(String),
+
+    #[error("the transaction policy rejects the transaction: {0}")]
+    PolicyRejected(String),
+
+    #[error("quick login unavailable, login with the mnemonic: {0}")]
+    QuickLoginUnavailable(String),
+
+    #[error("id_session_closed")]
+    SessionClosed,
+
+    #[error("id_session_paused")]
+    SessionPaused,
+
+    #[error("server tip {height} is more than {max_behind} blocks behind the known tip {known}")]
+    ServerBehind {
+        height: u32,
+        known: u32,
+        max_behind: u32,
+    },
+
+    #[error("server genesis {found} does not match the network genesis {expected}")]
+    ServerGenesisMismatch {
+        expected: String,
+        found: String,
+    },
+
+    #[error("settings changed by another session, current revision is {0}")]
+    SettingsConflict(u64),
+
     #[error(transparent)]
     SliceConversionError(#[from] std::array::TryFromSliceError),
 
+    #[error("the fee estimates were not updated in the last {max_age} seconds, a fee rate must be given")]
+    StaleFeeEstimates {
+        max_age: u64,
+    },
+
     #[error(transparent)]
     StdIOError(#[from] std::io::Error),
 
     #[error("attempt to access the store without calling load_store first")]
     StoreNotLoaded,
 
+    #[error("id_transaction_too_large")]
+    TransactionTooLarge,
+
+    #[error("the transaction is rejected ({reason}): {message}")]
+    TransactionRejected {
+        reason: RejectReason,
+        message: String,
+    },
+
+    #[error("{count} inputs are needed, at most {max} fit in a standard transaction")]
+    TooManyInputs {
+        count: usize,
+        max: usize,
+    },
+
+    #[error("threads not stopped: {}", .0.join(", "))]
+    ThreadsNotStopped(Vec<String>),
+
     #[error("Transaction not found ({0})")]
     TxNotFound(BETxid),
 
     #[error(transparent)]
     UnblindError(#[from] elements::UnblindError),
 
+    #[error("no session on network {0:?}")]
+    UnknownNetwork(String),
+
     #[error(transparent)]
     UreqError(#[from] ureq::Error),
 
+    #[error("id_unknown_bip21_parameter")]
+    UnsupportedUriParameter(String),
+
+    #[error("message format {format:?} is not supported for {script_type} addresses")]
+    UnsupportedMessageFormat {
+        format: MessageFormat,
+        script_type: ScriptType,
+    },
+
     //#[error(transparent)]
     //Sighash(#[from] sighash::Error),
     #[error(
@@ -158,6 +323,12 @@ pub enum Error {
     #[error("{0} does not exist")]
     FileNotExist(PathBuf),
 
+    #[error("input {0} of the transaction is not signed")]
+    UnsignedInput(usize),
+
+    #[error("id_user_not_found_or_invalid")]
+    UserNotFoundOrInvalid,
+
     #[error("{0}")]
     Generic(String),
 }
@@ -202,20 +373,122 @@ impl<T> From<PoisonError<MutexGuard<'_, T>>> for Error {
 }
 
 impl Error {
-    /// Convert the error to a GDK-compatible code.
-    pub fn to_gdk_code(&self) -> String {
+    /// Classify the error, see [`ErrorCode`].
+    pub fn code(&self) -> ErrorCode {
         use super::Error::*;
-        match *self {
+        match self {
+            Amount(e) => e.code(),
+            AddressBadChecksum => ErrorCode::InvalidAddressChecksum,
+            AddressMixedCase => ErrorCode::InvalidAddressCase,
+            AddressUnknownWitnessVersion(_) => ErrorCode::UnknownWitnessVersion,
+            AddressWrongProgramLength(_) => ErrorCode::InvalidWitnessProgramLength,
+            BitcoinAddressParseError(bitcoin::address::ParseError::NetworkValidation(_))
+            | AddressWrongNetwork
+            | InvalidMainchainAddress => ErrorCode::InvalidAddress {
+                network_mismatch: true,
+            },
+            BitcoinAddressParseError(_) | ElementsAddressError(_) | InvalidUri => {
+                ErrorCode::InvalidAddress {
+                    network_mismatch: false,
+                }
+            }
+            ClientError(e) => ErrorCode::from_electrum(e),
+            Common(e) => e.code(),
+            ConfirmationRequired => ErrorCode::ConfirmationRequired,
+            DisplayAddressTimeout => ErrorCode::Timeout,
+            UserNotFoundOrInvalid => ErrorCode::UserNotFoundOrInvalid,
+            Encryption(_) => ErrorCode::DecryptionFailed,
+            FeeRateBelowMinimum => ErrorCode::FeeRateBelowMinimum,
+            InsufficientFunds => ErrorCode::InsufficientFunds,
+            InsufficientFundsForFee => ErrorCode::InsufficientFundsForFee,
+            InvalidAmount => ErrorCode::InvalidAmount,
+            InvalidAssetId => ErrorCode::InvalidAssetId,
+            InvalidProxySocket(_) | ProxyUnreachable(_) => ErrorCode::ProxyUnreachable,
+            NameResolution(..) => ErrorCode::ConnectionFailed,
+            // Sessions logged in with credentials always have the master
+            // blinding key, it can be missing only for watch-only sessions.
+            MissingMasterBlindingKey => ErrorCode::WatchOnly,
+            // Sessions logged in with a signer can sign without the keys
+            // being passed, watch-only ones can't sign.
+            MissingSigner => ErrorCode::WatchOnly,
+            WatchOnlySubaccount(_) => ErrorCode::WatchOnly,
             // An invalid pin attempt. Should trigger an increment to the
             // caller counter as after 3 consecutive wrong guesses the server
             // will delete the corresponding key. Other errors should leave
             // such counter unchanged.
             PinClient(gdk_pin_client::Error::InvalidPin | gdk_pin_client::Error::Decryption(_)) => {
-                "id_invalid_pin"
+                ErrorCode::InvalidPin
+            }
+            PinClient(_) => ErrorCode::ConnectionFailed,
+            PolicyConfirmationRequired(_) => ErrorCode::ConfirmationRequired,
+            PolicyRejected(_) => ErrorCode::PolicyRejected,
+            QuickLoginUnavailable(_) => ErrorCode::QuickLoginUnavailable,
+            ServerBehind {
+                ..
+            } => ErrorCode::ServerBehind,
+            ServerGenesisMismatch {
+                ..
+            } => ErrorCode::ServerGenesisMismatch,
+            SessionClosed => ErrorCode::SessionClosed,
+            SessionPaused => ErrorCode::SessionPaused,
+            SettingsConflict(_) => ErrorCode::SettingsConflict,
+            StaleFeeEstimates {
+                ..
+            } => ErrorCode::StaleFeeEstimates,
+            StdIOError(e) => ErrorCode::from_io(e),
+            StoreNotLoaded => ErrorCode::StoreLocked,
+            TransactionTooLarge => ErrorCode::TransactionTooLarge,
+            TxNotFound(_) => ErrorCode::TransactionNotFound,
+            TransactionRejected {
+                reason,
+                ..
+            } => match reason {
+                RejectReason::MinRelayFeeNotMet => ErrorCode::FeeRateBelowMinimum,
+                RejectReason::Dust => ErrorCode::AmountBelowDust,
+                RejectReason::TxSize => ErrorCode::TransactionTooLarge,
+                _ => ErrorCode::TransactionRejected,
+            },
+            TooManyInputs {
+                ..
+            } => ErrorCode::TransactionTooLarge,
+            UnsupportedCurrency {
+                ..
+            } => ErrorCode::UnsupportedCurrency,
+            UnsupportedUriParameter(_) => ErrorCode::UnsupportedUriParameter,
+            UreqError(e) => ErrorCode::from_ureq(e),
+            _ => ErrorCode::Unknown,
+        }
+    }
+
+    /// Whether the server could not be reached, rather than failing the
+    /// request
+    pub fn is_connection_error(&self) -> bool {
+        matches!(
+            self.code(),
+            ErrorCode::ConnectionFailed | ErrorCode::Timeout | ErrorCode::ProxyUnreachable
+        )
+    }
+
+    /// The typed rejection of a transaction refused by the server on
+    /// broadcast, if the message relayed from the node has a known reason
+    pub fn into_rejection(self) -> Self {
+        if let Error::ClientError(electrum_client::Error::Protocol(value)) = &self {
+            let message = match value.get("message").and_then(|m| m.as_str()) {
+                Some(message) => message.to_string(),
+                None => value.to_string(),
+            };
+            if let Some(reason) = RejectReason::from_message(&message) {
+                return Error::TransactionRejected {
+                    reason,
+                    message,
+                };
             }
-            PinClient(_) => "id_connection_failed",
-            _ => "id_unknown",
         }
-        .to_string()
+        self
+    }
+
+    /// Convert the error to a GDK-compatible code.
+    pub fn to_gdk_code(&self) -> String {
+        self.code().gdk_id().to_string()
     }
 }
@@ -0,0 +1,166 @@
+//! Transaction history exports for accounting, in CSV or JSON.
+//!
+//! The fiat values use the exchange rate recorded when the transactions got
+//! confirmed, transactions confirmed before rates were recorded have none.
+
+use std::collections::BTreeSet;
+use std::io::Write;
+
+use gdk_common::model::{ExportFormat, ExportedTransaction, TransactionType, TxListItem};
+
+use crate::error::Error;
+
+/// The export of `tx`
+pub(crate) fn exported_transaction(tx: &TxListItem) -> ExportedTransaction {
+    let outgoing = tx.type_ == TransactionType::Outgoing;
+    let address = tx
+        .outputs
+        .iter()
+        .find(|output| output.is_relevant != outgoing && !output.address.is_empty())
+        .map(|output| output.address.clone())
+        .unwrap_or_default();
+    let fiat = tx.fiat_at_confirmation.as_ref();
+    ExportedTransaction {
+        timestamp: tx.created_at_ts / 1_000_000,
+        txid: tx.txhash.clone(),
+        direction: tx.type_.clone(),
+        amounts: tx.satoshi.clone(),
+        fee: tx.fee,
+        memo: tx.memo.clone(),
+        address,
+        height: Some(tx.block_height).filter(|height| *height > 0),
+        fiat_value: fiat.map(|fiat| fiat.fiat.clone()),
+        fiat_currency: fiat.map(|fiat| fiat.fiat_currency.clone()),
+    }
+}
+
+/// Write `txs` to `writer`, as a JSON array or as CSV with a column per
+/// asset, the one of `policy_asset` first
+pub fn write_transactions<W: Write>(
+    txs: &[ExportedTransaction],
+    format: ExportFormat,
+    policy_asset: &str,
+    writer: &mut W,
+) -> Result<(), Error> {
+    match format {
+        ExportFormat::Json => {
+            writer.write_all(b"[")?;
+            for (i, tx) in txs.iter().enumerate() {
+                if i > 0 {
+                    writer.write_all(b",")?;
+                }
+                serde_json::to_writer(&mut *writer, tx)?;
+            }
+            writer.write_all(b"]")?;
+        }
+        ExportFormat::Csv => {
+            let other_assets: BTreeSet<&String> = txs
+                .iter()
+                .flat_map(|tx| tx.amounts.keys())
+                .filter(|asset| *asset != policy_asset)
+                .collect();
+            let assets: Vec<&str> = std::iter::once(policy_asset)
+                .chain(other_assets.into_iter().map(String::as_str))
+                .collect();
+
+            let mut header = vec!["timestamp", "txid", "direction"];
+            header.extend(assets.iter());
+            header.extend(["fee", "memo", "address", "height", "fiat_value", "fiat_currency"]);
+            write_csv_row(writer, header.into_iter().map(String::from))?;
+
+            for tx in txs {
+                let direction = serde_json::to_value(&tx.direction)?;
+                let mut row = vec![
+                    tx.timestamp.to_string(),
+                    tx.txid.clone(),
+                    direction.as_str().unwrap_or_default().to_string(),
+                ];
+                row.extend(
+                    assets
+                        .iter()
+                        .map(|asset| tx.amounts.get(*asset).copied().unwrap_or(0).to_string()),
+                );
+                row.extend([
+                    tx.fee.to_string(),
+                    tx.memo.clone(),
+                    tx.address.clone(),
+                    tx.height.map(|height| height.to_string()).unwrap_or_default(),
+                    tx.fiat_value.clone().unwrap_or_default(),
+                    tx.fiat_currency.clone().unwrap_or_default(),
+                ]);
+                write_csv_row(writer, row.into_iter())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_csv_row<W: Write>(
+    writer: &mut W,
+    fields: impl Iterator<Item = String>,
+) -> Result<(), Error> {
+    let fields: Vec<String> = fields.map(|field| escape_csv(&field)).collect();
+    writer.write_all(fields.join(",").as_bytes())?;
+    writer.write_all(b"\r\n")?;
+    Ok(())
+}
+
+/// Quote `field` if needed, as in RFC 4180
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_escape_csv() {
+        assert_eq!(escape_csv("invoice 1234"), "invoice 1234");
+        assert_eq!(escape_csv("rent, march"), "\"rent, march\"");
+        assert_eq!(escape_csv("the \"big\" one"), "\"the \"\"big\"\" one\"");
+        assert_eq!(escape_csv("two\nlines"), "\"two\nlines\"");
+        assert_eq!(escape_csv("café ☕"), "café ☕");
+    }
+
+    #[test]
+    fn test_write_transactions() {
+        let tx = |txid: &str, amounts: &[(&str, i64)], memo: &str| ExportedTransaction {
+            timestamp: 1_700_000_000,
+            txid: txid.into(),
+            direction: TransactionType::Incoming,
+            amounts: amounts.iter().map(|(asset, satoshi)| (asset.to_string(), *satoshi)).collect(),
+            fee: 150,
+            memo: memo.into(),
+            address: "address".into(),
+            height: Some(101),
+            fiat_value: Some("20.00".into()),
+            fiat_currency: Some("USD".into()),
+        };
+        let txs = vec![tx("aa", &[("lbtc", 100_000)], "a, \"b\""), tx("bb", &[("xyz", 5)], "")];
+
+        let mut csv = vec![];
+        write_transactions(&txs, ExportFormat::Csv, "lbtc", &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<_> = csv.split("\r\n").collect();
+        assert_eq!(
+            lines[0],
+            "timestamp,txid,direction,lbtc,xyz,fee,memo,address,height,fiat_value,fiat_currency"
+        );
+        assert_eq!(
+            lines[1],
+            "1700000000,aa,incoming,100000,0,150,\"a, \"\"b\"\"\",address,101,20.00,USD"
+        );
+        assert_eq!(lines[2], "1700000000,bb,incoming,0,5,150,,address,101,20.00,USD");
+        assert_eq!(lines[3], "");
+
+        let mut json = vec![];
+        write_transactions(&txs, ExportFormat::Json, "lbtc", &mut json).unwrap();
+        let parsed: Vec<ExportedTransaction> = serde_json::from_slice(&json).unwrap();
+        assert_eq!(parsed, txs);
+    }
+}
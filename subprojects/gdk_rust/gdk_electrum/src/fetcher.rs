@@ -0,0 +1,295 @@
+//! De-duplication of the transactions fetched from the server.
+//!
+//! The subaccounts of a wallet, synced concurrently, and the sessions sharing
+//! a connection often need the same transactions: a transaction paying
+//! several subaccounts, or the previous transactions of the inputs. A
+//! [`TxFetcher`] fetches each of them once: the requests for a transaction
+//! being fetched wait for it instead of asking the server again, and the
+//! transactions fetched recently are kept in a small cache.
+//!
+//! The raw transactions are handed out as buffers shared with the cache, so
+//! that a large transaction needed by several callers is not copied. Parsing
+//! the server responses into these buffers is left to the electrum client.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Condvar, Mutex};
+
+use gdk_common::bitcoin::Txid;
+
+use crate::error::Error;
+
+/// The number of raw transactions kept in the cache
+const CACHE_SIZE: usize = 1_000;
+
+/// Fetches the raw transactions of a connection, see the
+/// [module documentation](self)
+#[derive(Default)]
+pub(crate) struct TxFetcher {
+    cache: Mutex<RawTxCache>,
+
+    /// The transactions being fetched, with what the waiting callers get
+    in_flight: Mutex<HashMap<Txid, Arc<InFlight>>>,
+}
+
+/// A transaction being fetched
+#[derive(Default)]
+struct InFlight {
+    /// The raw transaction once fetched, `Some(None)` if the fetch failed
+    raw: Mutex<Option<Option<Arc<Vec<u8>>>>>,
+    done: Condvar,
+}
+
+/// The raw transactions used the most recently
+#[derive(Default)]
+struct RawTxCache {
+    txs: HashMap<Txid, (Arc<Vec<u8>>, u64)>,
+    by_use: BTreeMap<u64, Txid>,
+    uses: u64,
+}
+
+impl RawTxCache {
+    fn get(&mut self, txid: &Txid) -> Option<Arc<Vec<u8>>> {
+        let (raw, last_use) = self.txs.get_mut(txid)?;
+        self.by_use.remove(last_use);
+        self.uses += 1;
+        *last_use = self.uses;
+        self.by_use.insert(self.uses, *txid);
+        Some(raw.clone())
+    }
+
+    fn insert(&mut self, txid: Txid, raw: Arc<Vec<u8>>) {
+        if self.get(&txid).is_some() {
+            return;
+        }
+        self.uses += 1;
+        self.txs.insert(txid, (raw, self.uses));
+        self.by_use.insert(self.uses, txid);
+        if self.txs.len() > CACHE_SIZE {
+            if let Some((_, oldest)) = self.by_use.pop_first() {
+                self.txs.remove(&oldest);
+            }
+        }
+    }
+}
+
+impl TxFetcher {
+    /// The raw transactions `txids`, in order, and the number of them fetched
+    /// with `fetch`
+    ///
+    /// The raw transactions are shared with the cache, not copied.
+    ///
+    /// Only the transactions neither cached nor being fetched by another
+    /// caller are fetched, with a single call. If the fetch of another caller
+    /// fails, the transactions it was fetching are fetched again.
+    pub(crate) fn get<F>(&self, txids: &[Txid], fetch: F) -> Result<(Vec<Arc<Vec<u8>>>, u64), Error>
+    where
+        F: Fn(&[Txid]) -> Result<Vec<Vec<u8>>, Error>,
+    {
+        let mut found = HashMap::new();
+        let mut to_fetch = vec![];
+        let mut to_wait = vec![];
+        {
+            let mut cache = self.cache.lock()?;
+            let mut in_flight = self.in_flight.lock()?;
+            for txid in txids.iter().collect::<HashSet<_>>() {
+                if let Some(raw) = cache.get(txid) {
+                    found.insert(*txid, raw);
+                } else if let Some(fetching) = in_flight.get(txid) {
+                    to_wait.push((*txid, fetching.clone()));
+                } else {
+                    in_flight.insert(*txid, Arc::new(InFlight::default()));
+                    to_fetch.push(*txid);
+                }
+            }
+        }
+
+        let fetched = if to_fetch.is_empty() {
+            Ok(vec![])
+        } else {
+            fetch_checked(&to_fetch, &fetch)
+        };
+        let fetched = self.publish(&to_fetch, fetched)?;
+        found.extend(to_fetch.iter().copied().zip(fetched));
+
+        let mut to_refetch = vec![];
+        for (txid, fetching) in to_wait {
+            let mut raw = fetching.raw.lock()?;
+            while raw.is_none() {
+                raw = fetching.done.wait(raw)?;
+            }
+            match raw.as_ref().and_then(Option::as_ref) {
+                Some(raw) => {
+                    found.insert(txid, raw.clone());
+                }
+                None => to_refetch.push(txid),
+            }
+        }
+        if !to_refetch.is_empty() {
+            let refetched = fetch_checked(&to_refetch, &fetch)?;
+            found.extend(to_refetch.iter().copied().zip(refetched.into_iter().map(Arc::new)));
+        }
+
+        let raw_txs = txids.iter().map(|txid| found[txid].clone()).collect();
+        Ok((raw_txs, (to_fetch.len() + to_refetch.len()) as u64))
+    }
+
+    /// Cache the transactions fetched and hand them to the callers waiting
+    /// for them, or let them know the fetch failed
+    fn publish(
+        &self,
+        txids: &[Txid],
+        fetched: Result<Vec<Vec<u8>>, Error>,
+    ) -> Result<Vec<Arc<Vec<u8>>>, Error> {
+        let fetched = fetched.map(|raw_txs| raw_txs.into_iter().map(Arc::new).collect::<Vec<_>>());
+        if let Ok(raw_txs) = &fetched {
+            let mut cache = self.cache.lock()?;
+            for (txid, raw) in txids.iter().zip(raw_txs) {
+                cache.insert(*txid, raw.clone());
+            }
+        }
+        let mut in_flight = self.in_flight.lock()?;
+        for (i, txid) in txids.iter().enumerate() {
+            if let Some(fetching) = in_flight.remove(txid) {
+                let raw = fetched.as_ref().ok().map(|raw_txs| raw_txs[i].clone());
+                *fetching.raw.lock()? = Some(raw);
+                fetching.done.notify_all();
+            }
+        }
+        fetched
+    }
+}
+
+/// Fetch `txids` with `fetch`, checking a transaction is returned for each
+fn fetch_checked<F>(txids: &[Txid], fetch: &F) -> Result<Vec<Vec<u8>>, Error>
+where
+    F: Fn(&[Txid]) -> Result<Vec<Vec<u8>>, Error>,
+{
+    let raw_txs = fetch(txids)?;
+    ensure!(
+        raw_txs.len() == txids.len(),
+        Error::Generic(format!("expected {} transactions, got {}", txids.len(), raw_txs.len()))
+    );
+    Ok(raw_txs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gdk_common::bitcoin::hashes::Hash;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Counts the bytes allocated by each thread
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get() + layout.size()));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    /// The result of `f` and the bytes it allocated
+    fn allocated_by<T>(f: impl FnOnce() -> T) -> (T, usize) {
+        let before = ALLOCATED.with(Cell::get);
+        let result = f();
+        (result, ALLOCATED.with(Cell::get) - before)
+    }
+
+    fn txid(i: u8) -> Txid {
+        Txid::from_byte_array([i; 32])
+    }
+
+    #[test]
+    fn test_tx_fetcher() {
+        let fetcher = TxFetcher::default();
+        let calls = AtomicU64::new(0);
+        let fetch = |txids: &[Txid]| -> Result<Vec<Vec<u8>>, Error> {
+            calls.fetch_add(1, Ordering::Relaxed);
+            thread::sleep(Duration::from_millis(100));
+            Ok(txids.iter().map(|txid| txid.to_byte_array().to_vec()).collect())
+        };
+
+        // Concurrent requests for the same transaction make a single fetch
+        let results: Vec<_> = thread::scope(|scope| {
+            let handles: Vec<_> =
+                (0..5).map(|_| scope.spawn(|| fetcher.get(&[txid(1)], fetch).unwrap())).collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert_eq!(results.iter().map(|(_, fetched)| fetched).sum::<u64>(), 1);
+        assert!(results.iter().all(|(raw_txs, _)| raw_txs == &vec![Arc::new(vec![1; 32])]));
+        assert!(fetcher.in_flight.lock().unwrap().is_empty());
+
+        // Cached transactions are not fetched again, duplicates are fetched once
+        let (raw_txs, fetched) = fetcher.get(&[txid(2), txid(1), txid(2)], fetch).unwrap();
+        assert_eq!(raw_txs, [2, 1, 2].map(|i| Arc::new(vec![i; 32])).to_vec());
+        assert_eq!(fetched, 1);
+        assert!(Arc::ptr_eq(&raw_txs[0], &raw_txs[2]));
+        assert!(Arc::ptr_eq(&raw_txs[1], &results[0].0[0]));
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+        // Failed fetches are not cached
+        let failing =
+            |_: &[Txid]| -> Result<Vec<Vec<u8>>, Error> { Err(Error::Generic("offline".into())) };
+        assert!(fetcher.get(&[txid(3)], failing).is_err());
+        assert!(fetcher.in_flight.lock().unwrap().is_empty());
+        assert_eq!(fetcher.get(&[txid(3)], fetch).unwrap().1, 1);
+    }
+
+    #[test]
+    fn test_tx_fetcher_allocations() {
+        const RAW_TX_SIZE: usize = 400_000;
+        let fetcher = TxFetcher::default();
+        let fetch = |txids: &[Txid]| -> Result<Vec<Vec<u8>>, Error> {
+            Ok(txids.iter().map(|_| vec![0; RAW_TX_SIZE]).collect())
+        };
+
+        // Fetching allocates the buffer of the transaction once
+        let (_, allocated) = allocated_by(|| fetcher.get(&[txid(1)], fetch).unwrap());
+        assert!(allocated >= RAW_TX_SIZE);
+        assert!(allocated < 2 * RAW_TX_SIZE, "{} bytes allocated", allocated);
+
+        // The next callers share it, copying it would allocate it again
+        let ((raw_txs, fetched), allocated) =
+            allocated_by(|| fetcher.get(&[txid(1), txid(1)], fetch).unwrap());
+        assert_eq!(fetched, 0);
+        assert!(allocated < RAW_TX_SIZE / 100, "{} bytes allocated", allocated);
+        let (_, copied) = allocated_by(|| raw_txs[0].to_vec());
+        assert!(copied >= RAW_TX_SIZE);
+    }
+
+    #[test]
+    fn test_raw_tx_cache() {
+        let indexed_txid = |i: usize| {
+            let mut bytes = [0u8; 32];
+            bytes[..8].copy_from_slice(&(i as u64).to_le_bytes());
+            Txid::from_byte_array(bytes)
+        };
+        let mut cache = RawTxCache::default();
+        for i in 0..CACHE_SIZE {
+            cache.insert(indexed_txid(i), Arc::new(vec![]));
+        }
+
+        // The first transaction is used again, the second is evicted instead
+        assert!(cache.get(&indexed_txid(0)).is_some());
+        cache.insert(indexed_txid(CACHE_SIZE), Arc::new(vec![]));
+        assert_eq!(cache.txs.len(), CACHE_SIZE);
+        assert!(cache.get(&indexed_txid(0)).is_some());
+        assert!(cache.get(&indexed_txid(1)).is_none());
+    }
+}
@@ -0,0 +1,313 @@
+//! Watch-only subaccounts of Green 2of2 and 2of3 wallets, giving a view of
+//! the funds of a Green subaccount independent of the Green service.
+//!
+//! A Green subaccount pays the scripts of the keys at the same pointer of
+//! the service key, the user key and, for 2of3 subaccounts, the recovery
+//! key. Its pointers are mapped to the chains of the watching subaccount:
+//! - the external chain has the `p2wsh` addresses of Green, a P2SH-P2WSH
+//!   2of2 or 2of3 multisig;
+//! - the internal chain has the `csv` addresses of Green, a P2SH-P2WSH 2of2
+//!   spendable by the user key alone after the CSV blocks, if the subaccount
+//!   has them, the legacy `p2sh` multisig addresses otherwise.
+//!
+//! Nothing of these subaccounts can be signed by the session, only recovery
+//! transactions of the CSV addresses can be, with the user key passed.
+
+use crate::account::xpubs_equivalent;
+use crate::error::Error;
+use crate::selection::required_fee;
+use gdk_common::bitcoin::bip32::{ChildNumber, Xpriv, Xpub};
+use gdk_common::bitcoin::hashes::Hash;
+use gdk_common::bitcoin::script::{Builder, PushBytesBuf};
+use gdk_common::bitcoin::secp256k1::Message;
+use gdk_common::bitcoin::sighash::{EcdsaSighashType, SighashCache};
+use gdk_common::bitcoin::transaction::Version;
+use gdk_common::bitcoin::{
+    absolute, ecdsa, Address, Amount, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn,
+    TxOut, Witness,
+};
+use gdk_common::miniscript::descriptor::{DefiniteDescriptorKey, Descriptor, DescriptorPublicKey};
+use gdk_common::model::GreenMultisigTemplate;
+
+/// The largest relative timelock in blocks of Green CSV scripts
+const MAX_CSV_BLOCKS: u32 = 0xffff;
+
+/// The scripts of a Green subaccount
+#[derive(Debug, Clone)]
+pub struct GreenMultisig {
+    template: GreenMultisigTemplate,
+    /// The descriptors of the external and internal chains
+    descriptors: [Descriptor<DescriptorPublicKey>; 2],
+}
+
+impl GreenMultisig {
+    pub fn new(template: &GreenMultisigTemplate, network: Network) -> Result<Self, Error> {
+        let GreenMultisigTemplate {
+            service_xpub,
+            user_xpub,
+            recovery_xpub,
+            csv_blocks,
+        } = template;
+        let xpubs = [Some(service_xpub), Some(user_xpub), recovery_xpub.as_ref()];
+        if let Some(xpub) = xpubs.iter().flatten().find(|xpub| xpub.network != network.into()) {
+            return Err(Error::Generic(format!("green key {} is for another network", xpub)));
+        }
+
+        let service = format!("{}/*", service_xpub);
+        let mut keys = vec![service.clone(), format!("{}/1/*", user_xpub)];
+        keys.extend(recovery_xpub.as_ref().map(|xpub| format!("{}/1/*", xpub)));
+        let multisig = format!("multi(2,{})", keys.join(","));
+        let external = format!("sh(wsh({}))", multisig);
+        let internal = match (csv_blocks, recovery_xpub) {
+            (None, _) => format!("sh({})", multisig),
+            (Some(_), Some(_)) => {
+                return Err(Error::Generic("2of3 green subaccounts have no CSV addresses".into()))
+            }
+            (Some(csv_blocks), None) if *csv_blocks == 0 || *csv_blocks > MAX_CSV_BLOCKS => {
+                return Err(Error::Generic(format!("invalid CSV blocks {}", csv_blocks)))
+            }
+            // <user> OP_CHECKSIGVERIFY <service> OP_CHECKSIG
+            // OP_IFDUP OP_NOTIF <csv_blocks> OP_CHECKSEQUENCEVERIFY OP_ENDIF
+            (Some(csv_blocks), None) => format!(
+                "sh(wsh(and_v(v:pk({}/1/*),or_d(pk({}),older({})))))",
+                user_xpub, service, csv_blocks
+            ),
+        };
+        let parse = |desc: &str| -> Result<_, Error> { Ok(desc.parse()?) };
+        Ok(GreenMultisig {
+            template: template.clone(),
+            descriptors: [parse(&external)?, parse(&internal)?],
+        })
+    }
+
+    /// The user key of the subaccount
+    pub fn user_xpub(&self) -> &Xpub {
+        &self.template.user_xpub
+    }
+
+    /// The relative timelock of the CSV addresses, if the subaccount has them
+    pub fn csv_blocks(&self) -> Option<u32> {
+        self.template.csv_blocks
+    }
+
+    /// The descriptor of the external or internal chain, with checksum
+    pub fn descriptor(&self, is_internal: bool) -> String {
+        self.descriptors[is_internal as usize].to_string()
+    }
+
+    fn derived(
+        &self,
+        is_internal: bool,
+        pointer: u32,
+    ) -> Result<Descriptor<DefiniteDescriptorKey>, Error> {
+        let descriptor = &self.descriptors[is_internal as usize];
+        descriptor.at_derivation_index(pointer).map_err(|e| Error::Generic(e.to_string()))
+    }
+
+    /// The address at `pointer` of the external or internal chain
+    pub fn address(
+        &self,
+        is_internal: bool,
+        pointer: u32,
+        network: Network,
+    ) -> Result<Address, Error> {
+        Ok(self.derived(is_internal, pointer)?.address(network)?)
+    }
+
+    /// The witness script, or the redeem script of legacy addresses, at
+    /// `pointer` of the external or internal chain
+    pub fn script_code(&self, is_internal: bool, pointer: u32) -> Result<ScriptBuf, Error> {
+        Ok(self.derived(is_internal, pointer)?.explicit_script()?)
+    }
+
+    /// A transaction sending `satoshi`, paid by `outpoint` to the CSV address
+    /// at `pointer`, to `script_pubkey`, less the fee at `fee_rate`.
+    ///
+    /// It is signed by the user key alone, derived from its extended private
+    /// key `xprv`, and can be mined once `outpoint` has the CSV blocks of
+    /// confirmations.
+    pub fn recovery_transaction(
+        &self,
+        xprv: &Xpriv,
+        pointer: u32,
+        outpoint: OutPoint,
+        satoshi: u64,
+        script_pubkey: ScriptBuf,
+        fee_rate: u64,
+    ) -> Result<Transaction, Error> {
+        let csv_blocks = self
+            .csv_blocks()
+            .ok_or_else(|| Error::Generic("the subaccount has no CSV addresses".into()))?;
+        xpubs_equivalent(&Xpub::from_priv(&crate::EC, xprv), &self.template.user_xpub)?;
+        let witness_script = self.script_code(true, pointer)?;
+        let redeem_script = ScriptBuf::new_p2wsh(&witness_script.wscript_hash());
+        let redeem_script = PushBytesBuf::try_from(redeem_script.into_bytes()).unwrap();
+        let mut tx = Transaction {
+            version: Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: outpoint,
+                script_sig: Builder::new().push_slice(redeem_script).into_script(),
+                sequence: Sequence::from_height(csv_blocks as u16),
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::ZERO,
+                script_pubkey,
+            }],
+        };
+
+        // The expired spend: an empty service signature, the user one
+        let witness = |user_signature: &[u8]| {
+            Witness::from_slice(&[&[][..], user_signature, witness_script.as_bytes()])
+        };
+        tx.input[0].witness = witness(&[0; 73][..]);
+        let fee = required_fee(tx.weight().to_wu() as usize, fee_rate);
+        let value = satoshi.checked_sub(fee).ok_or(Error::InsufficientFundsForFee)?;
+        tx.output[0].value = Amount::from_sat(value);
+
+        let path = [ChildNumber::from(1), ChildNumber::from(pointer)];
+        let private_key = xprv.derive_priv(&crate::EC, &path)?.private_key;
+        let sighash = SighashCache::new(&tx)
+            .p2wsh_signature_hash(
+                0,
+                &witness_script,
+                Amount::from_sat(satoshi),
+                EcdsaSighashType::All,
+            )
+            .map_err(|e| Error::Generic(e.to_string()))?;
+        let message = Message::from_digest(sighash.to_byte_array());
+        let signature = crate::EC.sign_ecdsa(&message, &private_key);
+        let signature = ecdsa::Signature::sighash_all(signature).serialize();
+        tx.input[0].witness = witness(signature.as_ref());
+        Ok(tx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gdk_common::bitcoin::bip32::DerivationPath;
+    use gdk_common::bitcoin::opcodes::all::*;
+    use gdk_common::bitcoin::{NetworkKind, PublicKey, Txid};
+    use gdk_common::EC;
+
+    fn xprv(seed: u8) -> Xpriv {
+        Xpriv::new_master(NetworkKind::Test, &[seed; 32]).unwrap()
+    }
+
+    fn xpub(seed: u8) -> Xpub {
+        Xpub::from_priv(&EC, &xprv(seed))
+    }
+
+    fn key(xpub: &Xpub, path: &str) -> PublicKey {
+        let path: DerivationPath = path.parse().unwrap();
+        PublicKey::new(xpub.derive_pub(&EC, &path).unwrap().public_key)
+    }
+
+    #[test]
+    fn test_green_multisig() {
+        let (service, user, recovery) = (xpub(1), xpub(2), xpub(3));
+        let template = GreenMultisigTemplate {
+            service_xpub: service,
+            user_xpub: user,
+            recovery_xpub: None,
+            csv_blocks: Some(144),
+        };
+        let green = GreenMultisig::new(&template, Network::Regtest).unwrap();
+
+        // The keys are in the order of Green, not sorted
+        let multisig = Builder::new()
+            .push_int(2)
+            .push_key(&key(&service, "m/7"))
+            .push_key(&key(&user, "m/1/7"))
+            .push_int(2)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        assert_eq!(green.script_code(false, 7).unwrap(), multisig);
+        let address = green.address(false, 7, Network::Regtest).unwrap();
+        assert_eq!(address, Address::p2shwsh(&multisig, Network::Regtest));
+
+        let csv = Builder::new()
+            .push_key(&key(&user, "m/1/7"))
+            .push_opcode(OP_CHECKSIGVERIFY)
+            .push_key(&key(&service, "m/7"))
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_IFDUP)
+            .push_opcode(OP_NOTIF)
+            .push_int(144)
+            .push_opcode(OP_CSV)
+            .push_opcode(OP_ENDIF)
+            .into_script();
+        assert_eq!(green.script_code(true, 7).unwrap(), csv);
+        let address = green.address(true, 7, Network::Regtest).unwrap();
+        assert_eq!(address, Address::p2shwsh(&csv, Network::Regtest));
+
+        // Without CSV the internal chain has the legacy addresses
+        let template = GreenMultisigTemplate {
+            recovery_xpub: Some(recovery),
+            csv_blocks: None,
+            ..template
+        };
+        let green = GreenMultisig::new(&template, Network::Regtest).unwrap();
+        let multisig = Builder::new()
+            .push_int(2)
+            .push_key(&key(&service, "m/7"))
+            .push_key(&key(&user, "m/1/7"))
+            .push_key(&key(&recovery, "m/1/7"))
+            .push_int(3)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        let address = green.address(true, 7, Network::Regtest).unwrap();
+        assert_eq!(address, Address::p2sh(&multisig, Network::Regtest).unwrap());
+
+        // 2of3 subaccounts have no CSV addresses, the keys must be of the network
+        let csv_2of3 = GreenMultisigTemplate {
+            csv_blocks: Some(144),
+            ..template.clone()
+        };
+        assert!(GreenMultisig::new(&csv_2of3, Network::Regtest).is_err());
+        assert!(GreenMultisig::new(&template, Network::Bitcoin).is_err());
+    }
+    #[test]
+    fn test_recovery_transaction() {
+        let template = GreenMultisigTemplate {
+            service_xpub: xpub(1),
+            user_xpub: xpub(2),
+            recovery_xpub: None,
+            csv_blocks: Some(144),
+        };
+        let green = GreenMultisig::new(&template, Network::Regtest).unwrap();
+        let outpoint = OutPoint::new(Txid::all_zeros(), 1);
+        let script_pubkey = green.address(false, 1, Network::Regtest).unwrap().script_pubkey();
+        let recover = |xprv: &Xpriv, satoshi| {
+            green.recovery_transaction(xprv, 7, outpoint, satoshi, script_pubkey.clone(), 1000)
+        };
+        let tx = recover(&xprv(2), 10_000).unwrap();
+        assert_eq!(tx.input[0].sequence, Sequence::from_height(144));
+        assert_eq!(tx.output[0].value.to_sat(), 10_000 - tx.vsize() as u64);
+
+        // The expired spend of the user key alone
+        let witness: Vec<_> = tx.input[0].witness.iter().collect();
+        let witness_script = green.script_code(true, 7).unwrap();
+        assert_eq!(witness.len(), 3);
+        assert!(witness[0].is_empty());
+        assert_eq!(witness[2], witness_script.as_bytes());
+        let signature = ecdsa::Signature::from_slice(witness[1]).unwrap();
+        let sighash = SighashCache::new(&tx)
+            .p2wsh_signature_hash(
+                0,
+                &witness_script,
+                Amount::from_sat(10_000),
+                EcdsaSighashType::All,
+            )
+            .unwrap();
+        let message = Message::from_digest(sighash.to_byte_array());
+        let user_key = key(&xpub(2), "m/1/7").inner;
+        EC.verify_ecdsa(&message, &signature.signature, &user_key).unwrap();
+
+        // The key must be the user one, the amount must pay the fee
+        assert!(recover(&xprv(1), 10_000).is_err());
+        assert!(matches!(recover(&xprv(2), 100).unwrap_err(), Error::InsufficientFundsForFee));
+    }
+}
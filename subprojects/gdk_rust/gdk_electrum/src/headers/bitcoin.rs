@@ -1,3 +1,4 @@
+use crate::clock::{NetworkTime, MAX_FUTURE_BLOCK_TIME};
 use crate::error::*;
 use crate::headers::compute_merkle_root;
 use crate::spv::calc_difficulty_retarget;
@@ -13,12 +14,13 @@ use gdk_common::electrum_client;
 use gdk_common::elements::hashes::Hash;
 use gdk_common::log::{info, warn};
 use gdk_common::once_cell::sync::Lazy;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::iter::FromIterator;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Mutex;
 
 pub static HEADERS_FILE_MUTEX: Lazy<HashMap<Network, Mutex<()>>> = Lazy::new(|| {
@@ -26,17 +28,42 @@ pub static HEADERS_FILE_MUTEX: Lazy<HashMap<Network, Mutex<()>>> = Lazy::new(||
         (Network::Bitcoin, Mutex::new(())),
         (Network::Testnet, Mutex::new(())),
         (Network::Regtest, Mutex::new(())),
-        (Network::Signet, Mutex::new(())), // unused
+        (Network::Signet, Mutex::new(())),
     ])
 });
 
+/// The number of the most recent headers kept in memory by default, two
+/// difficulty periods so that the difficulty checks don't read the file
+pub const DEFAULT_HEADERS_WINDOW: u32 = 2 * DIFFCHANGE_INTERVAL;
+
+/// The headers are written in batches of this many headers, 20kB, a multiple
+/// of the 4kB pages as well as of the 80 bytes headers
+const PAGE_HEADERS: u32 = 256;
+
 #[derive(Debug)]
 pub struct HeadersChain {
     path: PathBuf,
     height: u32,
-    last: block::Header,
+
+    /// The height of the first header of the file, zero unless the chain
+    /// starts at a checkpoint
+    base: u32,
+
+    /// The most recent headers, up to the tip, never empty
+    window: VecDeque<block::Header>,
+    window_size: u32,
+
+    /// The number of the most recent headers not written to the file yet
+    pending: u32,
+
+    /// The number of headers read from the file
+    file_reads: AtomicU64,
+
     checkpoints: HashMap<u32, BlockHash>,
     pub network: Network,
+
+    /// The time the header timestamps are checked against
+    network_time: NetworkTime,
 }
 
 impl HeadersChain {
@@ -45,56 +72,134 @@ impl HeadersChain {
     ///
     /// if the file doesn't exist, a chain with only the genesis block (relative to `network`) is returned
     pub fn new<P: AsRef<Path>>(path: P, network: Network) -> Result<HeadersChain, Error> {
+        Self::with_window(path, network, DEFAULT_HEADERS_WINDOW)
+    }
+
+    /// Like [`Self::new`], keeping the most recent `window_size` headers in
+    /// memory, at least [`PAGE_HEADERS`]
+    ///
+    /// The headers in the window are got without reading the file.
+    pub fn with_window<P: AsRef<Path>>(
+        path: P,
+        network: Network,
+        window_size: u32,
+    ) -> Result<HeadersChain, Error> {
+        Self::open(path, network, window_size, 0, || Ok(genesis_block(network).header))
+    }
+
+    /// Like [`Self::with_window`], for a chain starting at `checkpoint`
+    /// instead of genesis, whose header is got with `fetch` if the chain
+    /// file doesn't exist yet
+    ///
+    /// The chain has its own file and doesn't know the headers below the
+    /// checkpoint.
+    pub fn from_checkpoint<P, F>(
+        path: P,
+        network: Network,
+        window_size: u32,
+        checkpoint: (u32, BlockHash),
+        fetch: F,
+    ) -> Result<HeadersChain, Error>
+    where
+        P: AsRef<Path>,
+        F: FnOnce(u32) -> Result<block::Header, Error>,
+    {
+        let (height, hash) = checkpoint;
+        Self::open(path, network, window_size, height, || {
+            let header = fetch(height)?;
+            if header.block_hash() != hash {
+                return Err(Error::InvalidHeaders);
+            }
+            Ok(header)
+        })
+    }
+
+    /// Open the chain starting at `base`, creating its file with the header
+    /// got from `first` if it doesn't exist
+    fn open<P, F>(
+        path: P,
+        network: Network,
+        window_size: u32,
+        base: u32,
+        first: F,
+    ) -> Result<HeadersChain, Error>
+    where
+        P: AsRef<Path>,
+        F: FnOnce() -> Result<block::Header, Error>,
+    {
         std::fs::create_dir_all(path.as_ref())?;
         let mut filepath: PathBuf = path.as_ref().into();
-        filepath.push(format!("headers_chain_{}", network));
-        let checkpoints = get_checkpoints(network);
+        filepath.push(match base {
+            0 => format!("headers_chain_{}", network),
+            base => format!("headers_chain_{}_{}", network, base),
+        });
         if !filepath.exists() {
             info!("{:?} chain file doesn't exist, creating", filepath);
-            let last = genesis_block(network).header;
+            let first = serialize(&first()?);
             let mut file = File::create(&filepath)?;
-            file.write_all(&serialize(&last))?;
-            let height = 0;
-
-            Ok(HeadersChain {
-                path: filepath,
-                height,
-                last,
-                checkpoints,
-                network,
-            })
+            file.write_all(&first)?;
         } else {
             info!("{:?} chain file exists, reading", filepath);
-            let mut file = File::open(&filepath)?;
-            let file_size = file.metadata()?.len();
-            if file_size % 80 != 0 || file_size < 80 {
-                return Err(Error::InvalidHeaders);
-            }
-            let wanted_seek = file_size - 80;
-            let effective_seek = file.seek(SeekFrom::Start(wanted_seek))?;
-            if wanted_seek != effective_seek {
-                warn!("Seek failed wanted:{} effective:{}", wanted_seek, effective_seek);
-                return Err(Error::Generic("failed seek".into()));
-            }
-            let mut buf = [0u8; 80];
-            file.read_exact(&mut buf)?;
-            let height = (file_size as u32 / 80) - 1;
-            let last: block::Header = deserialize(&buf)?;
-
-            Ok(HeadersChain {
-                path: filepath,
-                height,
-                last,
-                checkpoints,
-                network,
-            })
         }
+        let file_size = std::fs::metadata(&filepath)?.len();
+        if file_size % 80 != 0 || file_size < 80 {
+            return Err(Error::InvalidHeaders);
+        }
+        let mut chain = HeadersChain {
+            path: filepath,
+            height: base + (file_size as u32 / 80) - 1,
+            base,
+            window: VecDeque::new(),
+            window_size: window_size.max(PAGE_HEADERS),
+            pending: 0,
+            file_reads: AtomicU64::new(0),
+            checkpoints: get_checkpoints(network),
+            network,
+            network_time: NetworkTime::default(),
+        };
+        chain.load_window()?;
+        Ok(chain)
+    }
+
+    /// Read the most recent headers of the file into the window, with a
+    /// single read
+    fn load_window(&mut self) -> Result<(), Error> {
+        let len = self.window_size.min(self.height + 1 - self.base);
+        let buf = self.read(self.height + 1 - len, len)?;
+        self.window = buf.chunks(80).map(deserialize).collect::<Result<_, _>>()?;
+        Ok(())
+    }
+
+    /// Read `count` headers from the file, starting at `height`
+    fn read(&self, height: u32, count: u32) -> Result<Vec<u8>, Error> {
+        let mut file = File::open(&self.path)?;
+        let wanted_seek = (height - self.base) as u64 * 80;
+        let effective_seek = file.seek(SeekFrom::Start(wanted_seek))?;
+        if wanted_seek != effective_seek {
+            warn!("Seek failed wanted:{} effective:{}", wanted_seek, effective_seek);
+            return Err(Error::Generic("failed seek".into()));
+        }
+        let mut buf = vec![0u8; count as usize * 80];
+        file.read_exact(&mut buf)?;
+        self.file_reads.fetch_add(count as u64, Ordering::Relaxed);
+        Ok(buf)
+    }
+
+    /// The number of headers read from the file
+    pub fn file_reads(&self) -> u64 {
+        self.file_reads.load(Ordering::Relaxed)
     }
 
     pub fn height(&self) -> u32 {
         self.height
     }
 
+    /// The height of the first header of the chain, see
+    /// [`Self::from_checkpoint`]
+    pub fn base(&self) -> u32 {
+        self.base
+    }
+
     fn pow_allow_min_difficulty_blocks(&self) -> bool {
         // Special difficulty rule for testnet and regtest:
         // If the next block's timestamp is more than 2* 10 minutes
@@ -112,7 +217,7 @@ impl HeadersChain {
             // loop at most DIFFCHANGE_INTERVAL times
             let bits = loop {
                 let header = self.get(height)?;
-                if height == 0
+                if height == self.base
                     || height % DIFFCHANGE_INTERVAL == 0
                     || header.difficulty(&self.network) != 1
                 {
@@ -127,44 +232,82 @@ impl HeadersChain {
     }
 
     pub fn get(&self, height: u32) -> Result<block::Header, Error> {
-        let mut file = File::open(&self.path)?;
-        let wanted_seek = height as u64 * 80;
-        let effective_seek = file.seek(SeekFrom::Start(wanted_seek))?;
-        if wanted_seek != effective_seek {
-            warn!("Seek failed wanted:{} effective:{}", wanted_seek, effective_seek);
-            return Err(Error::Generic("failed seek".into()));
+        if height > self.height || height < self.base {
+            return Err(Error::Generic(format!("no header at height {}", height)));
         }
-        let mut buf = [0u8; 80];
-        file.read_exact(&mut buf)?;
-        let header: block::Header = deserialize(&buf)?;
-        Ok(header)
+        let window_start = self.height + 1 - self.window.len() as u32;
+        if height >= window_start {
+            return Ok(self.window[(height - window_start) as usize]);
+        }
+        Ok(deserialize(&self.read(height, 1)?)?)
     }
 
     /// to handle reorgs, it's necessary to remove some of the last headers
     pub fn remove(&mut self, headers_to_remove: u32) -> Result<(), Error> {
-        let headers_to_remove = headers_to_remove.min(self.height);
+        let headers_to_remove = headers_to_remove.min(self.height - self.base);
         let new_height = self.height - headers_to_remove;
-        let new_size = (new_height + 1) as u64 * 80;
+        if headers_to_remove <= self.pending {
+            // Not written yet, the window holds more than the pending headers
+            self.pending -= headers_to_remove;
+            self.window.truncate(self.window.len() - headers_to_remove as usize);
+            self.height = new_height;
+            return Ok(());
+        }
+        let new_size = (new_height + 1 - self.base) as u64 * 80;
         let file = OpenOptions::new().write(true).open(&self.path)?;
-        self.last = self.get(new_height)?;
-        self.height = new_height;
         file.set_len(new_size)?;
-        Ok(())
+        self.height = new_height;
+        self.pending = 0;
+        self.load_window()
+    }
+
+    /// Check the header timestamps against `network_time` instead of the
+    /// local clock
+    pub fn set_network_time(&mut self, network_time: NetworkTime) {
+        self.network_time = network_time;
+    }
+
+    /// Check the headers at the heights of `checkpoints` too
+    pub fn add_checkpoints(&mut self, checkpoints: &HashMap<u32, BlockHash>) {
+        self.checkpoints.extend(checkpoints.iter().map(|(height, hash)| (*height, *hash)));
     }
 
     pub fn tip(&self) -> block::Header {
-        self.last
+        *self.window.back().expect("the window holds at least the tip")
     }
 
     /// write new headers to the file if checks are passed
+    ///
+    /// The signet block solution is committed in the coinbase transaction and can't be checked
+    /// from the headers, signet headers are validated by their proof of work like on other chains
     pub fn push(&mut self, new_headers: Vec<block::Header>) -> Result<(), Error> {
         let mut curr_bits = self.curr_bits()?;
-        let mut serialized = Vec::with_capacity(new_headers.len() * 80);
         let mut cache = HashMap::new();
+        let pushed = self.push_checked(new_headers, &mut curr_bits, &mut cache);
+        // The headers checked before an invalid one are kept
+        self.write_pages()?;
+        pushed?;
+        info!(
+            "chain tip height {} hash {} file {:?}",
+            self.height,
+            self.tip().block_hash(),
+            self.path
+        );
+        Ok(())
+    }
+
+    fn push_checked(
+        &mut self,
+        new_headers: Vec<block::Header>,
+        curr_bits: &mut u32,
+        cache: &mut HashMap<u32, block::Header>,
+    ) -> Result<(), Error> {
         for new_header in new_headers {
             let new_height = self.height + 1;
-            if self.last.block_hash() != new_header.prev_blockhash
+            let last = self.tip();
+            if last.block_hash() != new_header.prev_blockhash
                 || new_header.validate_pow(new_header.target()).is_err()
+                || new_header.time as u64 > self.network_time.now() + MAX_FUTURE_BLOCK_TIME
             {
                 return Err(Error::InvalidHeaders);
             }
@@ -172,25 +315,29 @@ impl HeadersChain {
             if new_height % DIFFCHANGE_INTERVAL == 0 {
                 if let Network::Regtest = self.network {
                     // regtest doesn't retarget https://github.com/bitcoin/bitcoin/blob/7fcf53f7b4524572d1d0c9a5fdc388e87eb02416/src/pow.cpp#L51
+                } else if new_height - DIFFCHANGE_INTERVAL < self.base {
+                    // The period started below the checkpoint, its first header is unknown:
+                    // the new target is trusted as the checkpoint is
+                    *curr_bits = new_header.bits.to_consensus();
                 } else {
                     let first_height = new_height - DIFFCHANGE_INTERVAL;
                     let first = match cache.remove(&first_height) {
                         Some(header) => header,
                         None => self.get(first_height)?,
                     };
-                    let new_target = calc_difficulty_retarget(&first, &self.last);
+                    let new_target = calc_difficulty_retarget(&first, &last, self.network);
                     if new_header.bits.to_consensus()
                         != bitcoin_29::BlockHeader::compact_target_from_u256(&new_target)
                     {
                         return Err(Error::InvalidHeaders);
                     }
-                    curr_bits = new_header.bits.to_consensus();
+                    *curr_bits = new_header.bits.to_consensus();
                 }
             } else {
-                if new_header.bits != CompactTarget::from_consensus(curr_bits) {
+                if new_header.bits != CompactTarget::from_consensus(*curr_bits) {
                     if !self.pow_allow_min_difficulty_blocks()
                         || new_header.difficulty(&self.network) != 1
-                        || new_header.time.checked_sub(self.last.time).unwrap_or(0)
+                        || new_header.time.checked_sub(last.time).unwrap_or(0)
                             <= 2 * TARGET_BLOCK_SPACING
                     {
                         return Err(Error::InvalidHeaders);
@@ -204,17 +351,13 @@ impl HeadersChain {
                 info!("checkpoint {} {} is ok", new_height, hash);
             }
             cache.insert(new_height, new_header.clone());
-            serialized.extend(serialize(&new_header));
-            self.last = new_header;
+            self.window.push_back(new_header);
+            if self.window.len() > self.window_size as usize {
+                self.window.pop_front();
+            }
+            self.pending += 1;
             self.height = new_height;
         }
-        self.flush(&mut serialized)?;
-        info!(
-            "chain tip height {} hash {} file {:?}",
-            self.height,
-            self.tip().block_hash(),
-            self.path
-        );
         Ok(())
     }
 
@@ -237,19 +380,64 @@ impl HeadersChain {
         }
     }
 
-    /// write `serialized` bytes to the file, forcing flush so we are sure next `get()` will have
-    /// also this data if requested
-    fn flush(&mut self, serialized: &mut Vec<u8>) -> Result<(), Error> {
-        if !serialized.is_empty() {
-            let mut file = OpenOptions::new().append(true).open(&self.path)?;
-            file.write_all(&serialized)?;
-            file.flush()?;
-            serialized.clear();
+    /// Write the pending headers filling whole pages of the file, the others
+    /// stay in the window until [`Self::flush`]
+    fn write_pages(&mut self) -> Result<(), Error> {
+        let len = self.height + 1 - self.base;
+        let written = len - self.pending;
+        let pages_end = len / PAGE_HEADERS * PAGE_HEADERS;
+        if pages_end > written {
+            self.write(pages_end - written)?;
+        }
+        Ok(())
+    }
+
+    /// Write all the pending headers to the file, forcing flush so that other
+    /// chains opened on the file have them too
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.write(self.pending)
+    }
+
+    /// Write the `count` oldest pending headers
+    fn write(&mut self, count: u32) -> Result<(), Error> {
+        if count == 0 {
+            return Ok(());
+        }
+        let first = self.window.len() - self.pending as usize;
+        let mut serialized = Vec::with_capacity(count as usize * 80);
+        for header in self.window.range(first..first + count as usize) {
+            serialized.extend(serialize(header));
         }
+        let mut file = OpenOptions::new().append(true).open(&self.path)?;
+        file.write_all(&serialized)?;
+        file.flush()?;
+        self.pending -= count;
         Ok(())
     }
 }
 
+impl Drop for HeadersChain {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            warn!("cannot write the headers {:?}", e);
+        }
+    }
+}
+
+/// The highest checkpoint of `network` or of `extra` at or below `height`,
+/// excluding genesis
+pub fn checkpoint_below(
+    network: Network,
+    extra: &HashMap<u32, BlockHash>,
+    height: u32,
+) -> Option<(u32, BlockHash)> {
+    get_checkpoints(network)
+        .into_iter()
+        .chain(extra.iter().map(|(height, hash)| (*height, *hash)))
+        .filter(|(checkpoint, _)| *checkpoint > 0 && *checkpoint <= height)
+        .max_by_key(|(checkpoint, _)| *checkpoint)
+}
+
 fn get_checkpoints(network: Network) -> HashMap<u32, BlockHash> {
     let mut checkpoints = HashMap::new();
     let mut i = |n, s| checkpoints.insert(n, BlockHash::from_str(s).unwrap());
@@ -278,12 +466,13 @@ fn get_checkpoints(network: Network) -> HashMap<u32, BlockHash> {
 
 #[cfg(test)]
 mod test {
-    use crate::headers::bitcoin::HeadersChain;
+    use crate::headers::bitcoin::{HeadersChain, PAGE_HEADERS};
     use gdk_common::bitcoin::block;
+    use gdk_common::bitcoin::blockdata::constants::genesis_block;
     use gdk_common::bitcoin::consensus::encode::Decodable;
     use gdk_common::bitcoin::hash_types::BlockHash;
     use gdk_common::bitcoin::hashes::hex::FromHex;
-    use gdk_common::bitcoin::{Network, Txid};
+    use gdk_common::bitcoin::{CompactTarget, Network, Txid};
     use gdk_common::electrum_client::GetMerkleRes;
     use std::str::FromStr;
     use tempfile::TempDir;
@@ -387,4 +576,191 @@ mod test {
         );
         assert!(chain.get(200).is_err());
     }
+
+    #[test]
+    fn test_signet_headers() {
+        let temp = TempDir::new().unwrap();
+        let mut chain = HeadersChain::new(&temp, Network::Signet).unwrap();
+        assert_eq!(
+            BlockHash::from_str("00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6")
+                .unwrap(),
+            chain.tip().block_hash()
+        );
+
+        // a block with the regtest difficulty, easier than the signet one
+        let mut header = block::Header {
+            prev_blockhash: chain.tip().block_hash(),
+            bits: CompactTarget::from_consensus(0x207fffff),
+            time: chain.tip().time + 600,
+            ..chain.tip()
+        };
+        while header.validate_pow(header.target()).is_err() {
+            header.nonce += 1;
+        }
+        assert!(chain.push(vec![header]).is_err());
+        assert_eq!(chain.height(), 0);
+    }
+
+    /// `count` regtest headers on top of `tip`
+    fn regtest_headers(tip: block::Header, count: usize) -> Vec<block::Header> {
+        regtest_headers_at(tip, count, tip.time + 600)
+    }
+
+    /// `count` regtest headers on top of `tip`, 10 minutes apart from `time`
+    fn regtest_headers_at(tip: block::Header, count: usize, time: u32) -> Vec<block::Header> {
+        let mut headers: Vec<block::Header> = vec![];
+        for i in 0..count {
+            let prev = headers.last().copied().unwrap_or(tip);
+            let mut header = block::Header {
+                prev_blockhash: prev.block_hash(),
+                time: time + i as u32 * 600,
+                nonce: 0,
+                ..prev
+            };
+            while header.validate_pow(header.target()).is_err() {
+                header.nonce += 1;
+            }
+            headers.push(header);
+        }
+        headers
+    }
+
+    #[test]
+    fn test_headers_window() {
+        let temp = TempDir::new().unwrap();
+        let mut chain = HeadersChain::with_window(&temp, Network::Regtest, 0).unwrap();
+        let headers = regtest_headers(chain.tip(), 600);
+        chain.push(headers.clone()).unwrap();
+        assert_eq!(chain.height(), 600);
+        assert_eq!(chain.window.len(), PAGE_HEADERS as usize);
+
+        // Written by whole pages, the last headers stay in memory
+        let file_len = std::fs::metadata(&chain.path).unwrap().len();
+        assert_eq!(file_len, 512 * 80);
+        assert_eq!(file_len % 4096, 0);
+        assert_eq!(chain.pending, 89);
+
+        // The recent headers are got without reading the file
+        for i in 0..10_000u32 {
+            let height = 600 - i % PAGE_HEADERS;
+            assert_eq!(chain.get(height).unwrap(), headers[height as usize - 1]);
+        }
+        assert_eq!(chain.file_reads(), 0);
+
+        // Deep history is read from the file
+        assert_eq!(chain.get(10).unwrap(), headers[9]);
+        assert_eq!(chain.file_reads(), 1);
+
+        // Removing pending headers only, then written ones
+        chain.remove(50).unwrap();
+        assert_eq!((chain.height(), chain.pending), (550, 39));
+        assert_eq!(std::fs::metadata(&chain.path).unwrap().len(), file_len);
+        chain.remove(100).unwrap();
+        assert_eq!((chain.height(), chain.pending), (450, 0));
+        assert_eq!(chain.tip(), headers[449]);
+        assert_eq!(chain.window.len(), PAGE_HEADERS as usize);
+        chain.push(headers[450..].to_vec()).unwrap();
+
+        // The pending headers are written when dropped
+        drop(chain);
+        let chain = HeadersChain::with_window(&temp, Network::Regtest, 1_000).unwrap();
+        assert_eq!(chain.height(), 600);
+        assert_eq!(chain.tip(), headers[599]);
+        assert_eq!(chain.window.len(), 601);
+        assert_eq!(chain.get(0).unwrap(), genesis_block(Network::Regtest).header);
+    }
+
+    #[test]
+    fn test_headers_from_checkpoint() {
+        let temp = TempDir::new().unwrap();
+        let full = HeadersChain::new(&temp, Network::Regtest).unwrap();
+        let headers = regtest_headers(full.tip(), 700);
+        let fetch = |height: u32| Ok::<_, crate::error::Error>(headers[height as usize - 1]);
+
+        // The header fetched must be the checkpoint one
+        let wrong = (100, headers[98].block_hash());
+        assert!(HeadersChain::from_checkpoint(&temp, Network::Regtest, 0, wrong, fetch).is_err());
+
+        let checkpoint = (100, headers[99].block_hash());
+        let mut chain =
+            HeadersChain::from_checkpoint(&temp, Network::Regtest, 0, checkpoint, fetch).unwrap();
+        assert_eq!((chain.base(), chain.height()), (100, 100));
+        chain.push(headers[100..].to_vec()).unwrap();
+        assert_eq!(chain.height(), 700);
+        assert!(chain.get(99).is_err());
+        assert_eq!(chain.get(100).unwrap(), headers[99]);
+
+        // Written by whole pages from the checkpoint
+        assert_eq!(std::fs::metadata(&chain.path).unwrap().len(), 512 * 80);
+        let reads = chain.file_reads();
+        assert_eq!(chain.get(110).unwrap(), headers[109]);
+        assert_eq!(chain.file_reads(), reads + 1);
+
+        // A reorg can't remove the checkpoint
+        chain.remove(1_000).unwrap();
+        assert_eq!(chain.height(), 100);
+        chain.push(headers[100..650].to_vec()).unwrap();
+        drop(chain);
+
+        // The chain is reopened from its own file, without fetching
+        let chain = HeadersChain::from_checkpoint(&temp, Network::Regtest, 0, checkpoint, |_| {
+            panic!("the checkpoint header is in the file")
+        })
+        .unwrap();
+        assert_eq!((chain.base(), chain.height()), (100, 650));
+        assert_eq!(chain.tip(), headers[649]);
+        assert_eq!(full.height(), 0);
+    }
+
+    #[test]
+    fn test_checkpoint_below() {
+        use super::checkpoint_below;
+        use std::collections::HashMap;
+
+        let hash = |s| BlockHash::from_str(s).unwrap();
+        let none = HashMap::new();
+        assert_eq!(checkpoint_below(Network::Bitcoin, &none, 99_999), None);
+        assert_eq!(
+            checkpoint_below(Network::Bitcoin, &none, 250_000),
+            Some((
+                200_000,
+                hash("000000000000034a7dedef4a161fa058a2d67a173a90155f3a2fe6fc132e0ebf")
+            ))
+        );
+        assert_eq!(checkpoint_below(Network::Regtest, &none, 1_000), None);
+
+        let extra = HashMap::from([(150, genesis_block(Network::Regtest).block_hash())]);
+        assert_eq!(checkpoint_below(Network::Regtest, &extra, 149), None);
+        assert_eq!(checkpoint_below(Network::Regtest, &extra, 150).unwrap().0, 150);
+    }
+
+    #[test]
+    fn test_headers_clock_skew() {
+        use crate::clock::test::SkewedClock;
+        use crate::clock::{Clock, SystemClock};
+        use std::sync::Arc;
+
+        let temp = TempDir::new().unwrap();
+        let mut chain = HeadersChain::new(&temp, Network::Regtest).unwrap();
+        let history = regtest_headers(chain.tip(), 10);
+        chain.push(history).unwrap();
+
+        // Headers near the tip, on a device with the clock 3 hours behind
+        let now = SystemClock.now() as u32;
+        let headers = regtest_headers_at(chain.tip(), 3, now - 1200);
+        let network_time = NetworkTime::new(Arc::new(SkewedClock(-3 * 60 * 60)));
+        chain.set_network_time(network_time.clone());
+        assert!(chain.push(headers.clone()).is_err());
+        assert_eq!(chain.height(), 10);
+
+        // They are valid once the network time is known from the server tip
+        network_time.add_sample(now);
+        chain.push(headers).unwrap();
+        assert_eq!(chain.height(), 13);
+
+        // Timestamps too far in the future of the network are still invalid
+        let future = regtest_headers_at(chain.tip(), 1, now + 3 * 60 * 60);
+        assert!(chain.push(future).is_err());
+        assert_eq!(chain.height(), 13);
+    }
 }
@@ -1,6 +1,6 @@
 use std::str::FromStr;
 
-use gdk_common::elements::hashes::Hash;
+use gdk_common::elements::hashes::{sha256, Hash};
 use gdk_common::elements::BlockExtData;
 
 use crate::error::*;
@@ -33,6 +33,17 @@ const LIQUID_TESTNET_GENESIS_HASH: &'static str =
 const ELEMENTS_REGTEST_GENESIS_HASH: &'static str =
     "209577bda6bf4b5804bd46f8621580dd6d4e8bfa2d190e1c50e932492baca07d";
 
+/// The genesis block hash of `network`, None for regtest since it depends on the chain parameters
+pub fn genesis_hash(network: ElementsNetwork) -> Option<BlockHash> {
+    match network {
+        ElementsNetwork::Liquid => Some(BlockHash::from_str(LIQUID_GENESIS_HASH).unwrap()),
+        ElementsNetwork::LiquidTestnet => {
+            Some(BlockHash::from_str(LIQUID_TESTNET_GENESIS_HASH).unwrap())
+        }
+        ElementsNetwork::ElementsRegtest => None,
+    }
+}
+
 impl Verifier {
     pub fn new(network: ElementsNetwork) -> Self {
         let (is_regtest, genesis_hash) = match network {
@@ -155,18 +166,167 @@ impl Verifier {
     }
 }
 
+/// Checks the signblock witness of the tips against the keys of the federation
+///
+/// Unlike [`Verifier`] it supports dynafed headers, where the signblockscript
+/// is usually a p2wsh of a multisig of the functionaries. An empty set of keys
+/// expects the `OP_TRUE` signblockscript of the elementsd regtest default.
+pub struct SignblockChecker {
+    pubkeys: Vec<PublicKey>,
+}
+
+impl SignblockChecker {
+    /// The checker for `network`, with the configured federation keys or the
+    /// known ones of the network
+    ///
+    /// Returns None if the keys of the federation are not known.
+    pub fn new(
+        network: ElementsNetwork,
+        pubkeys: Option<&[String]>,
+    ) -> Result<Option<Self>, Error> {
+        let pubkeys = match pubkeys {
+            Some(pubkeys) => pubkeys
+                .iter()
+                .map(|pubkey| {
+                    PublicKey::from_str(pubkey).map_err(|e| {
+                        Error::Generic(format!("invalid federation pubkey {}: {}", pubkey, e))
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            None => match network {
+                ElementsNetwork::Liquid => {
+                    let challenge = Script::from(Vec::<u8>::from_hex(CHALLENGE).unwrap());
+                    multisig_pubkeys(&challenge).expect("valid challenge").1
+                }
+                ElementsNetwork::ElementsRegtest => vec![],
+                ElementsNetwork::LiquidTestnet => return Ok(None),
+            },
+        };
+        Ok(Some(SignblockChecker {
+            pubkeys,
+        }))
+    }
+
+    /// Verify that `header` is signed by the federation
+    pub fn verify(&self, header: &BlockHeader) -> Result<(), Error> {
+        let (script, mut witness) = match &header.ext {
+            BlockExtData::Proof {
+                challenge,
+                solution,
+            } => {
+                let mut witness = vec![];
+                for instr in solution.instructions_minimal() {
+                    match instr {
+                        Ok(Instruction::PushBytes(data)) => witness.push(data.to_vec()),
+                        _ => return Err(Error::InvalidHeaders),
+                    }
+                }
+                (challenge.clone(), witness)
+            }
+            BlockExtData::Dynafed {
+                current,
+                signblock_witness,
+                ..
+            } => {
+                let script = current.signblockscript().ok_or(Error::InvalidHeaders)?;
+                (script.clone(), signblock_witness.clone())
+            }
+        };
+
+        let script = if script.is_v0_p2wsh() {
+            let witness_script = Script::from(witness.pop().ok_or(Error::InvalidHeaders)?);
+            let hash = sha256::Hash::hash(witness_script.as_bytes());
+            if &script.as_bytes()[2..] != hash.as_byte_array() {
+                return Err(Error::InvalidHeaders);
+            }
+            witness_script
+        } else {
+            script
+        };
+
+        if self.pubkeys.is_empty() {
+            return match script.as_bytes() {
+                [op] if *op == opcodes::all::OP_PUSHNUM_1.into_u8() => Ok(()),
+                _ => Err(Error::InvalidHeaders),
+            };
+        }
+
+        let (required_sigs, pubkeys) = multisig_pubkeys(&script).ok_or(Error::InvalidHeaders)?;
+        let mut expected = self.pubkeys.clone();
+        let mut found = pubkeys.clone();
+        expected.sort();
+        found.sort();
+        if expected != found {
+            return Err(Error::InvalidHeaders);
+        }
+
+        // The first element is the dummy consumed by OP_CHECKMULTISIG
+        let signatures = witness
+            .iter()
+            .filter(|el| !el.is_empty())
+            .map(|el| Signature::from_der(el).map_err(|_| Error::InvalidHeaders))
+            .collect::<Result<Vec<_>, _>>()?;
+        if signatures.len() != required_sigs {
+            return Err(Error::InvalidHeaders);
+        }
+        let msg = Message::from_digest(header.block_hash().to_byte_array());
+        let mut pubkeys = pubkeys.iter();
+        for signature in signatures.iter() {
+            if !pubkeys.any(|pubkey| crate::EC.verify_ecdsa(&msg, signature, &pubkey.inner).is_ok())
+            {
+                return Err(Error::InvalidHeaders);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The required signatures and the keys of the `m <pubkeys> n OP_CHECKMULTISIG` `script`
+fn multisig_pubkeys(script: &Script) -> Option<(usize, Vec<PublicKey>)> {
+    let instructions = script.instructions_minimal().collect::<Result<Vec<_>, _>>().ok()?;
+    let push_num = |instr: &Instruction| match instr {
+        Instruction::Op(op) => match op.classify(ClassifyContext::Legacy) {
+            Class::PushNum(val) if val > 0 => Some(val as usize),
+            _ => None,
+        },
+        _ => None,
+    };
+    match instructions.as_slice() {
+        [first, keys @ .., last, Instruction::Op(op)] if *op == opcodes::all::OP_CHECKMULTISIG => {
+            let required = push_num(first)?;
+            let pubkeys = keys
+                .iter()
+                .map(|instr| match instr {
+                    Instruction::PushBytes(data) => PublicKey::from_slice(data).ok(),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?;
+            (push_num(last)? == pubkeys.len() && required <= pubkeys.len())
+                .then_some((required, pubkeys))
+        }
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::headers::liquid::Verifier;
+    use crate::headers::liquid::{SignblockChecker, Verifier};
     use gdk_common::bitcoin::hashes::hex::FromHex;
     use gdk_common::elements::encode::deserialize;
     use gdk_common::elements::{BlockExtData, BlockHeader, Script};
     use gdk_common::rand::seq::SliceRandom;
     use gdk_common::ElementsNetwork;
 
+    /// A block of elementsd regtest, signed by the default `OP_TRUE` signblockscript
+    const REGTEST_HEADER: &str = "000000a07da0ac2b4932e9501c0e192dfa8b4e6ddd801562f846bd04584bbfa6bd779520a297a6b54050bd32f46e7b738931f2bfc0f9ebc2663e2057dbdf26c5472c73439ee3ec5e01000000022200204ae81572f06e1b88fd5ced7a1a000945432e83e1551e6f721ee9c00b8cc332604a00000017a91472c44f957fc011d97e3406667dca5b1c930c4026870151014202fcba7ecf41bc7e1be4ee122d9d22e3333671eb0a3a87b5cdf099d59874e1940f02fcba7ecf41bc7e1be4ee122d9d22e3333671eb0a3a87b5cdf099d59874e1940f00010151";
+
+    /// The block 1 of liquid, signed by the federation
+    const LIQUID_BLOCK_1: &str = "000000200360208a889692372c8d68b084a62efdf60ea1a359a04c94b20d223658276614c8a804bd8a3f6bcfa6f6dc06e596b9b3cab6b57e357185b0e8d0ca3d9da327f25b32ac5b01000000fd01025b21026a2a106ec32c8a1e8052e5d02a7b0a150423dbd9b116fc48d46630ff6e6a05b92102791646a8b49c2740352b4495c118d876347bf47d0551c01c4332fdc2df526f1a2102888bda53a424466b0451627df22090143bbf7c060e9eacb1e38426f6b07f2ae12102aee8967150dee220f613de3b239320355a498808084a93eaf39a34dcd62024852102d46e9259d0a0bb2bcbc461a3e68f34adca27b8d08fbe985853992b4b104e27412102e9944e35e5750ab621e098145b8e6cf373c273b7c04747d1aa020be0af40ccd62102f9a9d4b10a6d6c56d8c955c547330c589bb45e774551d46d415e51cd9ad5116321033b421566c124dfde4db9defe4084b7aa4e7f36744758d92806b8f72c2e943309210353dcc6b4cf6ad28aceb7f7b2db92a4bf07ac42d357adf756f3eca790664314b621037f55980af0455e4fb55aad9b85a55068bb6dc4740ea87276dc693f4598db45fa210384001daa88dabd23db878dbb1ce5b4c2a5fa72c3113e3514bf602325d0c37b8e21039056d089f2fe72dbc0a14780b4635b0dc8a1b40b7a59106325dd1bc45cc70493210397ab8ea7b0bf85bc7fc56bb27bf85e75502e94e76a6781c409f3f2ec3d1122192103b00e3b5b77884bf3cae204c4b4eac003601da75f96982ffcb3dcb29c5ee419b92103c1f3c0874cfe34b8131af34699589aacec4093399739ae352e8a46f80a6f68375faefd130300463044022024ec1f6d78bf5cc7364b43e1b5939ea8fa863afeec6ca5d58e204fae7b7ff3af02202f279a21a9efb3fa9dbe43a7f6b814f50e14c5dd4113d9799ec1efb5667aa879463044022044687115bc51db921d6e1a1b9b3542bfa6520bf67c59d7d44af0fa3ea112d566022010fe2da9dc4370b86d4fd58258cae35b61eadf5cbc2bf2572006033b72ee39a8473045022100f95a688a049e7fc956749fa18730b19093f6fa99a49ae8f44071441a7cea2579022039110e0d07d4fc42d669528f8ad82509f1d4ce53d769c27c33ac693e42452266473045022100b745d91095cdff00d691d02bb6a06bc8468d0977cecb4be11189f792392395f20220591d985f45607ea05efe3f696dfb8b0b5cd51bf9c4e26fd7656f0d42408fad5d4630440220377729c8cdd2fd5a2dc7dfcebd9abb1cea541826490f635091a3db4c0487a5bc02207158f761f3188464ff02fa72d75ae03ae196f38f2dfb4b46bb0a453a88bfed954630440220110f034d50f1415b5cd8baf9a39954c9e998e400030c1efd147e7b6cf7735ef102206344118dc9d77a6479a57d8fddbee7dc8ca227b937bcac08f18cc12ded8c0c23463044022055471e35dccd254a00357d7695638cd09f84362cc5b2c42fa1e44964c4483efa022069c038b11cf7fa2285aee29276b893eb288b86af567a7927f5a04fa948c4f7da47304502210083767f0d1347cb409fabb4aa3ac3981a8d7f9d7994c883e53de4d23acf1be9840220339b1683e3aa7cc7e6e32558a2e84c0c2ced04aa4fbff256c0f6372754094e70473045022100f34805c8394c547ceeeff635510d5a581a2a199179acecddac523de0eef61df70220786172710c88342c7e94ac1ada5b96db1bf87044168c143867faa8d2ace4f3874730450221009c9486c87e17b7a9f5217fca9d11a54d2f84bd1902b81f5169c6985f9ae6c3aa02202a3dc55d5f683d1d1b3577110b186b708965de345041256bb3fd0ec2b8165a7a46304402201db14fd5e8ff0a9d962bd98f2da5077500ff0120183a60c5a302dc48543e74d0022055b53a399e22f81571e96b39943fe456c5b8890345848be97d516fe42d522ad0";
+
     #[test]
     fn test_regtest() {
-        let regtest_header : BlockHeader = deserialize(&Vec::<u8>::from_hex("000000a07da0ac2b4932e9501c0e192dfa8b4e6ddd801562f846bd04584bbfa6bd779520a297a6b54050bd32f46e7b738931f2bfc0f9ebc2663e2057dbdf26c5472c73439ee3ec5e01000000022200204ae81572f06e1b88fd5ced7a1a000945432e83e1551e6f721ee9c00b8cc332604a00000017a91472c44f957fc011d97e3406667dca5b1c930c4026870151014202fcba7ecf41bc7e1be4ee122d9d22e3333671eb0a3a87b5cdf099d59874e1940f02fcba7ecf41bc7e1be4ee122d9d22e3333671eb0a3a87b5cdf099d59874e1940f00010151").unwrap()).unwrap();
+        let regtest_header: BlockHeader =
+            deserialize(&Vec::<u8>::from_hex(REGTEST_HEADER).unwrap()).unwrap();
 
         match regtest_header.ext {
             BlockExtData::Proof {
@@ -192,7 +352,8 @@ mod test {
         assert!(verifier.verify_header(&genesis_header).is_err());
 
         // liquid block 1
-        let block_header : BlockHeader = deserialize(&Vec::<u8>::from_hex("000000200360208a889692372c8d68b084a62efdf60ea1a359a04c94b20d223658276614c8a804bd8a3f6bcfa6f6dc06e596b9b3cab6b57e357185b0e8d0ca3d9da327f25b32ac5b01000000fd01025b21026a2a106ec32c8a1e8052e5d02a7b0a150423dbd9b116fc48d46630ff6e6a05b92102791646a8b49c2740352b4495c118d876347bf47d0551c01c4332fdc2df526f1a2102888bda53a424466b0451627df22090143bbf7c060e9eacb1e38426f6b07f2ae12102aee8967150dee220f613de3b239320355a498808084a93eaf39a34dcd62024852102d46e9259d0a0bb2bcbc461a3e68f34adca27b8d08fbe985853992b4b104e27412102e9944e35e5750ab621e098145b8e6cf373c273b7c04747d1aa020be0af40ccd62102f9a9d4b10a6d6c56d8c955c547330c589bb45e774551d46d415e51cd9ad5116321033b421566c124dfde4db9defe4084b7aa4e7f36744758d92806b8f72c2e943309210353dcc6b4cf6ad28aceb7f7b2db92a4bf07ac42d357adf756f3eca790664314b621037f55980af0455e4fb55aad9b85a55068bb6dc4740ea87276dc693f4598db45fa210384001daa88dabd23db878dbb1ce5b4c2a5fa72c3113e3514bf602325d0c37b8e21039056d089f2fe72dbc0a14780b4635b0dc8a1b40b7a59106325dd1bc45cc70493210397ab8ea7b0bf85bc7fc56bb27bf85e75502e94e76a6781c409f3f2ec3d1122192103b00e3b5b77884bf3cae204c4b4eac003601da75f96982ffcb3dcb29c5ee419b92103c1f3c0874cfe34b8131af34699589aacec4093399739ae352e8a46f80a6f68375faefd130300463044022024ec1f6d78bf5cc7364b43e1b5939ea8fa863afeec6ca5d58e204fae7b7ff3af02202f279a21a9efb3fa9dbe43a7f6b814f50e14c5dd4113d9799ec1efb5667aa879463044022044687115bc51db921d6e1a1b9b3542bfa6520bf67c59d7d44af0fa3ea112d566022010fe2da9dc4370b86d4fd58258cae35b61eadf5cbc2bf2572006033b72ee39a8473045022100f95a688a049e7fc956749fa18730b19093f6fa99a49ae8f44071441a7cea2579022039110e0d07d4fc42d669528f8ad82509f1d4ce53d769c27c33ac693e42452266473045022100b745d91095cdff00d691d02bb6a06bc8468d0977cecb4be11189f792392395f20220591d985f45607ea05efe3f696dfb8b0b5cd51bf9c4e26fd7656f0d42408fad5d4630440220377729c8cdd2fd5a2dc7dfcebd9abb1cea541826490f635091a3db4c0487a5bc02207158f761f3188464ff02fa72d75ae03ae196f38f2dfb4b46bb0a453a88bfed954630440220110f034d50f1415b5cd8baf9a39954c9e998e400030c1efd147e7b6cf7735ef102206344118dc9d77a6479a57d8fddbee7dc8ca227b937bcac08f18cc12ded8c0c23463044022055471e35dccd254a00357d7695638cd09f84362cc5b2c42fa1e44964c4483efa022069c038b11cf7fa2285aee29276b893eb288b86af567a7927f5a04fa948c4f7da47304502210083767f0d1347cb409fabb4aa3ac3981a8d7f9d7994c883e53de4d23acf1be9840220339b1683e3aa7cc7e6e32558a2e84c0c2ced04aa4fbff256c0f6372754094e70473045022100f34805c8394c547ceeeff635510d5a581a2a199179acecddac523de0eef61df70220786172710c88342c7e94ac1ada5b96db1bf87044168c143867faa8d2ace4f3874730450221009c9486c87e17b7a9f5217fca9d11a54d2f84bd1902b81f5169c6985f9ae6c3aa02202a3dc55d5f683d1d1b3577110b186b708965de345041256bb3fd0ec2b8165a7a46304402201db14fd5e8ff0a9d962bd98f2da5077500ff0120183a60c5a302dc48543e74d0022055b53a399e22f81571e96b39943fe456c5b8890345848be97d516fe42d522ad0").unwrap()).unwrap();
+        let block_header: BlockHeader =
+            deserialize(&Vec::<u8>::from_hex(LIQUID_BLOCK_1).unwrap()).unwrap();
         assert!(verifier.verify_header(&block_header).is_ok());
 
         let mut wrong_header = block_header.clone();
@@ -259,4 +420,61 @@ mod test {
             assert!(false);
         }
     }
+
+    #[test]
+    fn test_signblock_checker() {
+        let regtest_header: BlockHeader =
+            deserialize(&Vec::<u8>::from_hex(REGTEST_HEADER).unwrap()).unwrap();
+        let block_header: BlockHeader =
+            deserialize(&Vec::<u8>::from_hex(LIQUID_BLOCK_1).unwrap()).unwrap();
+
+        let regtest =
+            SignblockChecker::new(ElementsNetwork::ElementsRegtest, None).unwrap().unwrap();
+        assert!(regtest.verify(&regtest_header).is_ok());
+        assert!(regtest.verify(&block_header).is_err());
+
+        let liquid = SignblockChecker::new(ElementsNetwork::Liquid, None).unwrap().unwrap();
+        assert!(liquid.verify(&block_header).is_ok());
+        assert!(liquid.verify(&regtest_header).is_err());
+
+        assert!(SignblockChecker::new(ElementsNetwork::LiquidTestnet, None).unwrap().is_none());
+
+        // a block signed by a different federation
+        let pubkeys = vec![
+            "026a2a106ec32c8a1e8052e5d02a7b0a150423dbd9b116fc48d46630ff6e6a05b9".to_string(),
+            "02791646a8b49c2740352b4495c118d876347bf47d0551c01c4332fdc2df526f1a".to_string(),
+        ];
+        let other = SignblockChecker::new(ElementsNetwork::Liquid, Some(pubkeys.as_slice()))
+            .unwrap()
+            .unwrap();
+        assert!(other.verify(&block_header).is_err());
+        assert!(
+            SignblockChecker::new(ElementsNetwork::Liquid, Some(&["00".to_string()][..])).is_err()
+        );
+
+        // a block whose signatures don't commit to its hash
+        let mut wrong_header = block_header.clone();
+        wrong_header.time += 1;
+        assert!(liquid.verify(&wrong_header).is_err());
+
+        // a block missing a signature
+        let mut wrong_header = block_header.clone();
+        if let BlockExtData::Proof {
+            challenge,
+            solution,
+        } = wrong_header.ext
+        {
+            let mut solution_bytes = solution.into_bytes();
+            let last_sig_len = *solution_bytes.iter().rev().nth(0x46).unwrap() as usize;
+            assert_eq!(last_sig_len, 0x46);
+            solution_bytes.truncate(solution_bytes.len() - last_sig_len - 1);
+            wrong_header.ext = BlockExtData::Proof {
+                challenge,
+                solution: solution_bytes.into(),
+            };
+            assert!(liquid.verify(&wrong_header).is_err());
+        } else {
+            assert!(false);
+        }
+    }
 }
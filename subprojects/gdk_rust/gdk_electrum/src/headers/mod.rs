@@ -1,6 +1,7 @@
 use crate::error::Error;
-use crate::headers::bitcoin::{HeadersChain, HEADERS_FILE_MUTEX};
+use crate::headers::bitcoin::{HeadersChain, DEFAULT_HEADERS_WINDOW, HEADERS_FILE_MUTEX};
 use crate::headers::liquid::Verifier;
+use crate::resolver::Resolver;
 use crate::session::determine_electrum_url;
 use electrum_client::{Client, ElectrumApi, GetMerkleRes};
 use gdk_common::aes::aead::NewAead;
@@ -64,11 +65,12 @@ trait ParamsMethods {
 impl ParamsMethods for SPVCommonParams {
     fn build_client(&self) -> Result<Client, Error> {
         let url = determine_electrum_url(&self.network)?;
-        url.build_client(self.network.proxy.as_deref(), self.timeout)
+        url.build_client(self.network.proxy.as_deref(), self.timeout, &Resolver::new(&self.network))
     }
     fn headers_chain(&self) -> Result<HeadersChain, Error> {
         let network = self.bitcoin_network().expect("headers_chain available only on bitcoin");
-        Ok(HeadersChain::new(&self.network.state_dir, network)?)
+        let window = self.network.headers_window.unwrap_or(DEFAULT_HEADERS_WINDOW);
+        Ok(HeadersChain::with_window(&self.network.state_dir, network, window)?)
     }
     fn verified_cache(&self) -> Result<VerifiedCache, Error> {
         Ok(VerifiedCache::new(&self.network.state_dir, self.network.id(), &self.encryption_key))
@@ -1,10 +1,13 @@
 use serde::{Deserialize, Serialize};
 
 use crate::error::*;
+use crate::resolver::Resolver;
 
-use electrum_client::{Client, ConfigBuilder, Socks5Config};
+use electrum_client::{Client, ClientType, ConfigBuilder, RawClient, Socks5Config};
 use gdk_common::electrum_client;
+use gdk_common::model::ElectrumServer;
 use gdk_common::network::NETWORK_REQUEST_TIMEOUT;
+use std::io::ErrorKind;
 use std::net::ToSocketAddrs;
 use std::str::FromStr;
 
@@ -15,8 +18,15 @@ pub enum ElectrumUrl {
 }
 
 impl ElectrumUrl {
-    pub fn build_client(&self, proxy: Option<&str>, timeout: Option<u8>) -> Result<Client, Error> {
+    /// A client of the server, resolving its hostname with `resolver`
+    pub fn build_client(
+        &self,
+        proxy: Option<&str>,
+        timeout: Option<u8>,
+        resolver: &Resolver,
+    ) -> Result<Client, Error> {
         let mut config = ConfigBuilder::new();
+        let mut socks = None;
 
         // TODO: add support for socks5 credentials?
         if let Some(proxy) = proxy {
@@ -29,7 +39,8 @@ impl ElectrumUrl {
                 if proxy.to_socket_addrs().is_err() {
                     return Err(Error::InvalidProxySocket(proxy.to_string()));
                 }
-                config = config.socks5(Some(Socks5Config::new(proxy)));
+                config = config.socks5(Some(Socks5Config::new(proxy.clone())));
+                socks = Some(proxy);
             }
         }
 
@@ -37,13 +48,50 @@ impl ElectrumUrl {
 
         config = config.timeout(Some(timeout));
 
+        // Malformed urls are left to the client to report
+        let resolved = match self.host_port() {
+            Some((host, port)) => resolver.resolve(host, port, proxy)?,
+            None => None,
+        };
+        if let Some(resolved) = resolved {
+            let config = config.build();
+            return match self {
+                // TLS is still validated against the hostname
+                ElectrumUrl::Tls(_, validate) => {
+                    let client = RawClient::new_ssl(resolved, *validate, config.timeout())?;
+                    Ok(ClientType::SSL(client).into())
+                }
+                ElectrumUrl::Plaintext(_) => {
+                    let mut last_err = None;
+                    for addr in resolved.addrs {
+                        match Client::from_config(&format!("tcp://{}", addr), config.clone()) {
+                            Ok(client) => return Ok(client),
+                            Err(e) => last_err = Some(e),
+                        }
+                    }
+                    Err(last_err.map(Into::into).unwrap_or_else(|| {
+                        Error::NameResolution(resolved.host, "no address".into())
+                    }))
+                }
+            };
+        }
+
         let (url, config) = match self {
             ElectrumUrl::Tls(url, validate) => {
                 (format!("ssl://{}", url), config.validate_domain(*validate))
             }
             ElectrumUrl::Plaintext(url) => (format!("tcp://{}", url), config),
         };
-        Ok(Client::from_config(&url, config.build())?)
+        Client::from_config(&url, config.build()).map_err(|e| match (socks, &e) {
+            // The connection to the server goes through the proxy, so a refused
+            // connection means the proxy itself is not reachable
+            (Some(proxy), electrum_client::Error::IOError(io))
+                if io.kind() == ErrorKind::ConnectionRefused =>
+            {
+                Error::ProxyUnreachable(proxy)
+            }
+            _ => e.into(),
+        })
     }
 
     pub fn url(&self) -> &str {
@@ -53,6 +101,12 @@ impl ElectrumUrl {
         }
     }
 
+    /// The hostname and the port of the server
+    fn host_port(&self) -> Option<(&str, u16)> {
+        let (host, port) = self.url().rsplit_once(':')?;
+        Some((host, port.parse().ok()?))
+    }
+
     pub fn is_onion(&self) -> bool {
         match self {
             ElectrumUrl::Tls(_, _) => false,
@@ -82,6 +136,17 @@ impl FromStr for ElectrumUrl {
     }
 }
 
+impl TryFrom<&ElectrumServer> for ElectrumUrl {
+    type Error = Error;
+    fn try_from(server: &ElectrumServer) -> Result<Self, Error> {
+        ElectrumUrl::from_str(&server.url)?;
+        Ok(match server.tls {
+            true => ElectrumUrl::Tls(server.url.clone(), server.validate_domain),
+            false => ElectrumUrl::Plaintext(server.url.clone()),
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
     use gdk_common::bitcoin::bip32::{Xpriv, Xpub};
@@ -94,6 +159,7 @@ mod test {
     use gdk_common::bitcoin::sighash::{EcdsaSighashType, SighashCache};
     use gdk_common::bitcoin::Amount;
     use gdk_common::bitcoin::{Address, Network, NetworkKind, Transaction};
+    use gdk_common::error::ErrorCode;
     use gdk_common::scripts::p2shwpkh_script_sig;
     use std::str::FromStr;
 
@@ -205,7 +271,7 @@ mod test {
         let invalid_proxy = "invalid_proxy";
 
         assert!(matches!(
-            url.build_client(Some(&invalid_proxy), None),
+            url.build_client(Some(&invalid_proxy), None, &Resolver::default()),
             Err(Error::InvalidProxySocket(p)) if p == invalid_proxy
         ));
     }
@@ -220,9 +286,24 @@ mod test {
 
         for valid_proxy in ["127.0.0.1:9050", "socks5://127.0.0.1:9050", "localhost:9050"] {
             assert!(!matches!(
-                url.build_client(Some(valid_proxy), None),
+                url.build_client(Some(valid_proxy), None, &Resolver::default()),
                 Err(Error::InvalidProxySocket(_))
             ));
         }
     }
+
+    /// Tests that a proxy refusing connections results in `ProxyUnreachable`.
+    #[test]
+    fn unreachable_proxy() {
+        let url = ElectrumUrl::Plaintext("127.0.0.1:50001".into());
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let proxy = format!("127.0.0.1:{}", port);
+
+        let err = match url.build_client(Some(&proxy), None, &Resolver::default()) {
+            Err(e) => e,
+            Ok(_) => panic!("proxy {} is not listening", proxy),
+        };
+        assert!(matches!(&err, Error::ProxyUnreachable(p) if *p == proxy));
+        assert_eq!(err.code(), ErrorCode::ProxyUnreachable);
+    }
 }
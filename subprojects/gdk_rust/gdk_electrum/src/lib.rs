@@ -6,28 +6,66 @@ extern crate serde_json;
 #[macro_use]
 extern crate gdk_common;
 
-use gdk_common::log::{debug, info, trace, warn};
-use gdk_pin_client::{Pin, PinClient, PinData};
-use headers::bitcoin::HEADERS_FILE_MUTEX;
+use gdk_common::log::{debug, info, warn};
+use gdk_pin_client::{HttpPinServer, Pin, PinClient, PinData, PinServer};
+use headers::bitcoin::{DEFAULT_HEADERS_WINDOW, HEADERS_FILE_MUTEX};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 pub mod account;
+pub mod address;
+pub mod anti_exfil;
+pub mod bip21;
+pub mod clock;
 pub mod error;
+pub mod export;
+mod fetcher;
+pub mod green;
 pub mod headers;
 pub mod interface;
+mod listener;
+pub mod manager;
+pub mod message;
+pub mod migration;
+pub mod multi;
+pub mod multisig;
+pub mod payjoin;
+pub mod pegout;
+pub mod policy;
+mod pool;
+pub mod price;
+pub mod psbt;
+pub mod quick_login;
+pub mod reserves;
+pub mod resolver;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+mod selection;
 pub mod session;
+pub mod signer;
+pub mod simulation;
 pub mod spv;
 pub mod sweep;
+pub mod watch_only;
 
 use crate::account::{
-    discover_account, get_account_script_purpose, get_last_next_account_nums, Account,
+    discover_account, get_account_derivation, get_account_script_purpose,
+    get_last_next_account_nums, Account,
 };
+use crate::clock::{NetworkTime, MAX_FUTURE_BLOCK_TIME};
 use crate::error::Error;
+use crate::green::GreenMultisig;
 use crate::interface::ElectrumUrl;
+use crate::manager::SessionManager;
+use crate::multisig::Multisig;
+use crate::policy::{PolicyDecision, PolicyStage, PolicyTransaction, TransactionPolicy};
+use crate::price::{AvailableCurrencies, HttpPriceSource, PriceSource};
+use crate::quick_login::{CredentialProtector, SoftwareProtector};
+use crate::resolver::Resolver;
+use crate::signer::{Signer, SoftwareSigner};
 use crate::store::*;
 
-use gdk_common::bitcoin::bip32::{DerivationPath, Fingerprint};
+use gdk_common::bitcoin::bip32::{DerivationPath, Fingerprint, Xpriv, Xpub};
 use gdk_common::bitcoin::hashes::hex::FromHex;
 use gdk_common::bitcoin::hex::DisplayHex;
 use gdk_common::bitcoin::Txid;
@@ -35,46 +73,69 @@ use gdk_common::{bitcoin, elements};
 
 use gdk_common::model::*;
 use gdk_common::network::NetworkParameters;
+use gdk_common::redact::PrivacyMode;
+use gdk_common::scripts::ScriptType;
 use gdk_common::store::ToCipher;
-use gdk_common::util::{asset_blinding_key_to_ec_private_key, MasterBlindingKey};
+use gdk_common::trace::{redacted, timed};
+use gdk_common::tracing::{field::Empty, info_span, Span};
+use gdk_common::util::{
+    self, asset_blinding_key_to_ec_private_key, weight_to_vsize, MasterBlindingKey,
+};
 use gdk_common::{be::*, State};
 
 use gdk_common::aes::aead::NewAead;
 use gdk_common::aes::{Aes256GcmSiv, Key};
+use gdk_common::amount::{self, AmountFormatter, Unit};
 use gdk_common::electrum_client::{self, RawHeaderNotification, ScriptStatus};
 use gdk_common::elements::confidential::{self, Asset, Nonce};
 use gdk_common::error::Error::{BtcEncodingError, ElementsEncodingError};
-use gdk_common::exchange_rates::{Currency, ExchangeRatesCache};
+use gdk_common::exchange_rates::{Currency, ExchangeRatesCache, Pair};
 use gdk_common::network;
 use gdk_common::NetworkId;
 use gdk_common::EC;
 use std::collections::hash_map::Entry;
-use std::collections::{BTreeSet, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::convert::{TryFrom, TryInto};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::time::{Duration, SystemTime};
-use std::{iter, thread};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::headers::bitcoin::HeadersChain;
-use crate::headers::liquid::Verifier;
+use crate::headers::bitcoin::{checkpoint_below, HeadersChain};
+use crate::headers::liquid::{SignblockChecker, Verifier};
 use crate::headers::ChainOrVerifier;
 use crate::spv::SpvCrossValidator;
-use electrum_client::{Client, ElectrumApi};
-use gdk_common::bitcoin::blockdata::constants::DIFFCHANGE_INTERVAL;
-pub use gdk_common::notification::{NativeNotif, Notification, TransactionNotification};
+use electrum_client::{Client, ElectrumApi, ToElectrumScriptHash};
+use gdk_common::bitcoin::blockdata::constants::{genesis_block, DIFFCHANGE_INTERVAL};
+pub use gdk_common::notification::{
+    BlockNotification, NativeNotif, Notification, SubscriptionId, TransactionNotification,
+};
 use gdk_common::rand::seq::SliceRandom;
-use gdk_common::rand::thread_rng;
+use gdk_common::rand::{thread_rng, Rng};
 use gdk_common::ureq;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::Hasher;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
 use std::thread::JoinHandle;
 
 const CROSS_VALIDATION_RATE: u8 = 4; // Once every 4 thread loop runs, or roughly 28 seconds
 pub const DEFAULT_GAP_LIMIT: u32 = 20;
+const MAX_ANNOUNCED_BLOCKS: usize = 144; // Notified blocks kept to find the common ancestor on reorgs
 const FEE_ESTIMATE_INTERVAL: Duration = Duration::from_secs(120);
+const DISPLAY_ADDRESS_TIMEOUT: Duration = Duration::from_secs(300); // Time for the user to check the address on the device
+const CONFIRMATION_RATE_BLOCKS: u32 = 6; // Transactions confirmed further back are not given the current exchange rate
+const DEFAULT_SYNC_INTERVAL: u32 = 30; // Seconds between syncs without notifications
+const RECONNECT_INTERVAL: u32 = 1; // Seconds between attempts to reach the server
+const DEFAULT_SYNC_PARALLELISM: u32 = 3; // Accounts synced at once
+const HEALTH_SILENT_SECS: u64 = 10; // Server silence degrading the session health
+const HEALTH_LOST_SECS: u64 = 300; // Server silence making the session unhealthy
+const HEALTH_MAX_TIP_LAG: u32 = 6; // SPV headers missing before the session is degraded
+const HEALTH_MAX_BACKLOG: u64 = 100; // Script changes not synced before the session is degraded
+const JOIN_TIMEOUT: Duration = Duration::from_secs(20); // Wait for the background threads to stop
+const PENDING_BROADCAST_MAX_AGE: u64 = 24 * 60 * 60; // Seconds a failed broadcast is retried for
+const MAX_SERVER_BEHIND: u32 = 6; // Blocks a server can miss to be switched to
+const LOGIN_AUDIT_SAMPLE: usize = 32; // Cached scripts of each subaccount checked at login
 
 type ScriptStatuses = HashMap<bitcoin::ScriptBuf, ScriptStatus>;
 
@@ -113,6 +174,8 @@ impl TryFrom<(RawHeaderNotification, NetworkId)> for HeightHeader {
 }
 
 struct Syncer {
+    /// Recorded in the spans of the sync, redacted
+    wallet_hash_id: String,
     accounts: Arc<RwLock<HashMap<u32, Account>>>,
     store: Store,
     master_blinding: Option<MasterBlindingKey>,
@@ -121,25 +184,325 @@ struct Syncer {
     gap_limit: u32,
     synced_accounts: HashSet<u32>,
     user_wants_to_sync: Arc<AtomicBool>,
+
+    /// The scripts subscribed to and their notifications
+    subscriptions: Arc<listener::Subscriptions>,
+
+    /// Fetches the transactions, shared with the sessions of the same
+    /// connection if managed
+    tx_fetcher: Arc<fetcher::TxFetcher>,
+
+    /// To record the exchange rates of the transactions getting confirmed
+    xr_cache: ExchangeRatesCache,
+    price_source: Option<Arc<dyn PriceSource>>,
+
+    /// The transactions confirmed below this height are ignored, the height
+    /// of the wallet birthday if asserted, else zero
+    ignore_below: u32,
+
+    /// The server the queued transactions are broadcast to
+    server: String,
+}
+
+/// The syncing of a session with the server, advanced by [`SyncLoop::step`]
+/// by the syncer thread of the session, when woken by the server
+/// notifications or every sync interval, or by a [`SessionManager`]
+struct SyncLoop {
+    syncer: Syncer,
+    tipper: Tipper,
+    notify: NativeNotif,
+    state_updater: StateUpdater,
+    first_sync: Arc<AtomicBool>,
+
+    // electrum_client::Client stores the last electrum_client::ScriptStatus
+    // for each script it has subscribed to, however to access it we have
+    // to use `script_pop` which removes the status from the Client internal
+    // storage. OTOH we need to remember the last script status corresponding
+    // to a script, since it is needed to determine if the script had a
+    // transaction and if its status has changed w.r.t. to the cached one.
+    // So we store the last statuses for each script in this map.
+    last_statuses: ScriptStatuses,
+
+    txs_to_notify: Vec<TransactionNotification>,
+    reuses_to_notify: Vec<AddressReuse>,
+    reorgs_to_notify: Vec<TxReorg>,
+    payments_to_notify: Vec<ExpectedPayment>,
+
+    /// Set when the last step was interrupted by a new block, to sync again
+    /// without waiting
+    sync_again: bool,
+
+    metrics: Arc<Mutex<SessionMetrics>>,
+}
+
+impl SyncLoop {
+    /// Sync the wallet and the tip with `client`, returning false if syncing
+    /// should stop
+    fn step(&mut self, client: &Client) -> bool {
+        // What was notified so far is synced now
+        self.syncer.subscriptions.take_tip();
+        self.last_statuses.extend(self.syncer.subscriptions.take_statuses());
+
+        let tip_before_sync = match self.tipper.server_tip(client) {
+            Ok(height) => height,
+            Err(Error::Common(BtcEncodingError(_)))
+            | Err(Error::Common(ElementsEncodingError(_))) => {
+                // We aren't able to decode the blockheaders returned by the server,
+                // do not sync further.
+                return false;
+            }
+            Err(e) => {
+                self.state_updater.update_if_needed(false);
+                warn!("exception in tipper {e:?}");
+                return true;
+            }
+        };
+
+        let first_sync_bool = self.first_sync.load(Ordering::Relaxed);
+        let sync_result = {
+            let _span = timed(info_span!(
+                "sync",
+                wallet_hash_id = %redacted(&self.syncer.wallet_hash_id),
+                first_sync = first_sync_bool,
+                elapsed_ms = Empty
+            ));
+            self.syncer.sync(client, &mut self.last_statuses, first_sync_bool)
+        };
+        match sync_result {
+            Ok(sync_result) => {
+                self.state_updater.update_if_needed(true);
+                // Skip sending transaction notifications if it's the
+                // first call to sync. This allows us to _not_ notify
+                // transactions that were sent or received before
+                // login.
+                if first_sync_bool {
+                    info!("first sync completed");
+                } else {
+                    self.txs_to_notify.extend(sync_result.tx_ntfs);
+                    self.reuses_to_notify.extend(sync_result.address_reuses);
+                    self.reorgs_to_notify.extend(sync_result.reorgs);
+                    self.payments_to_notify.extend(sync_result.expected_payments);
+                }
+                self.first_sync.store(false, Ordering::Relaxed);
+                if let Ok(mut metrics) = self.metrics.lock() {
+                    metrics.last_sync_timestamp = Some(util::now() / 1_000_000);
+                    metrics.syncs += 1;
+                    metrics.histories_fetched += sync_result.histories_fetched;
+                    metrics.txs_fetched += sync_result.txs_fetched;
+                    metrics.outputs_unblinded += sync_result.outputs_unblinded;
+                    metrics.max_parallel_syncs =
+                        metrics.max_parallel_syncs.max(sync_result.parallel_syncs);
+                }
+
+                for pointer in sync_result.accounts {
+                    if self.syncer.synced_accounts.insert(pointer) {
+                        // First sync loop for this account, notify the caller that the
+                        // transactions received offline have been synced.
+                        self.notify.subaccount_synced(pointer);
+                    }
+                }
+
+                match self.syncer.replay_broadcasts(client) {
+                    // Sync again to notify the transactions broadcast
+                    Ok(true) => self.sync_again = true,
+                    Ok(false) => (),
+                    Err(e) => warn!("cannot broadcast the queued transactions {e:?}"),
+                }
+            }
+            Err(Error::UserDoesntWantToSync) => {
+                info!("{}", Error::UserDoesntWantToSync);
+                return false;
+            }
+            Err(e) => {
+                self.state_updater.update_if_needed(false);
+                warn!("Error during sync, {:?}", e);
+                return true;
+            }
+        }
+
+        let tip_notified = self.syncer.subscriptions.take_tip();
+        match self.tipper.pop_tip(client) {
+            // If a block arrives while we are syncing
+            // transactions, transactions might be returned as
+            // unconfirmed even if they belong to the newly
+            // notified block. Sync again to ensure
+            // consistency.
+            Ok(new_tip) if new_tip || tip_notified => {
+                self.sync_again = true;
+                return true;
+            }
+            Err(_) => return true,
+            _ => (),
+        }
+
+        // Another session of the same wallet might have changed the settings
+        if let Ok(mut store_write) = self.syncer.store.write() {
+            match store_write.reload_settings_if_changed() {
+                Ok(Some(settings)) => {
+                    drop(store_write);
+                    self.notify.settings(&settings);
+                }
+                Ok(None) => (),
+                Err(e) => warn!("cannot reload settings {:?}", e),
+            }
+        }
+
+        match self.tipper.update_cache_if_needed(
+            client,
+            tip_before_sync.height,
+            tip_before_sync.header,
+        ) {
+            Ok(Some(update)) => {
+                sample_network_time(&self.tipper.network_time, &self.notify, &update.header);
+                self.tipper.check_signblock(update.height, &update.header);
+                self.notify.block(
+                    update.height,
+                    &update.header,
+                    update.reorg,
+                    update.common_ancestor_height,
+                )
+            }
+            Ok(None) => (),
+            Err(e) => warn!("cannot update tip {:?}", e),
+        }
+        while let Some(ntf) = self.txs_to_notify.pop() {
+            info!("New tx notification: {}", ntf.txid);
+            self.notify.updated_txs(&ntf);
+        }
+        for reuse in self.reuses_to_notify.drain(..) {
+            info!("address reuse notification: {}", reuse.txid);
+            self.notify.address_reuse(
+                reuse.subaccount,
+                reuse.address,
+                reuse.pointer,
+                reuse.txid,
+                reuse.previous_txids,
+            );
+        }
+        for reorg in self.reorgs_to_notify.drain(..) {
+            info!("transaction reorged notification: {}", reorg.txid);
+            self.notify.transaction_reorged(
+                reorg.subaccount,
+                reorg.txid,
+                reorg.height,
+                reorg.reorg_count,
+            );
+        }
+        for payment in self.payments_to_notify.drain(..) {
+            info!("expected payment notification: {}", payment.address);
+            self.notify.expected_payment(payment);
+        }
+
+        true
+    }
+}
+
+/// What the scan of a chain of an account found, see [`Syncer::scan_chain`]
+#[derive(Default)]
+struct ChainScan {
+    /// The statuses of the scripts got from the server
+    last_statuses: ScriptStatuses,
+
+    /// The statuses computed from the histories fetched
+    new_statuses: ScriptStatuses,
+
+    history_txs_id: HashSet<BETxid>,
+    heights_set: HashSet<u32>,
+    txid_height: HashMap<BETxid, Option<u32>>,
+    txids_to_remove: Vec<BETxid>,
+
+    /// The scripts derived that were not in the store
+    scripts: HashMap<BEScript, DerivationPath>,
+
+    /// The index of the last script used
+    last_used: u32,
+
+    histories_fetched: u64,
+}
+
+/// What the sync of an account found, see [`Syncer::sync_account`]
+#[derive(Default)]
+struct AccountSync {
+    /// The statuses of the scripts got from the server
+    last_statuses: ScriptStatuses,
+
+    updated_txs: HashMap<BETxid, TxUpdate>,
+
+    /// The confirmed transactions whose block was removed
+    reorgs: Vec<TxReorg>,
+
+    histories_fetched: u64,
+    txs_fetched: u64,
+    outputs_unblinded: u64,
 }
 
 struct SyncResult {
     /// The transaction notifications to emit
     tx_ntfs: Vec<TransactionNotification>,
 
+    /// The address reuses to notify
+    address_reuses: Vec<AddressReuse>,
+
+    /// The reorganized transactions to notify
+    reorgs: Vec<TxReorg>,
+
+    /// The expected payments settled, to notify
+    expected_payments: Vec<ExpectedPayment>,
+
     /// The accounts synced
     accounts: Vec<u32>,
+
+    /// The number of script histories fetched
+    histories_fetched: u64,
+
+    /// The number of transactions fetched from the server
+    txs_fetched: u64,
+
+    /// The number of outputs unblinded
+    outputs_unblinded: u64,
+
+    /// The maximum number of accounts synced at once
+    parallel_syncs: u64,
 }
 
 pub struct Tipper {
     pub store: Store,
     pub network: NetworkParameters,
+
+    /// Sampled with the timestamps of the new tips
+    pub network_time: NetworkTime,
+
+    /// Block hashes of the recently notified chain, used to detect reorgs
+    announced: BTreeMap<u32, BEBlockHash>,
+
+    /// Checks the signatures of the federation on liquid tips, None if they
+    /// can't be checked
+    signblock_checker: Option<SignblockChecker>,
+
+    /// The check of the last liquid tip, None on bitcoin
+    pub signblock: Arc<Mutex<Option<SignblockValidation>>>,
+}
+
+/// A new tip to notify
+pub struct TipUpdate {
+    pub height: u32,
+    pub header: BEBlockHeader,
+
+    /// The new tip does not extend the previously notified one
+    pub reorg: bool,
+
+    /// The last block in common with the previously notified chain, if known
+    pub common_ancestor_height: Option<u32>,
 }
 
 pub struct Headers {
     pub store: Store,
     pub checker: ChainOrVerifier,
     pub cross_validator: Option<SpvCrossValidator>,
+
+    /// The check of the last liquid tip, transactions are not SPV verified
+    /// while it is invalid
+    pub signblock: Arc<Mutex<Option<SignblockValidation>>>,
 }
 
 pub struct ElectrumSession {
@@ -168,6 +531,10 @@ pub struct ElectrumSession {
     // True if the last call (to the Electrum server) succeeded
     pub last_network_call_succeeded: Arc<AtomicBool>,
 
+    /// The unix timestamp in seconds of the last answer of the server, 0 if
+    /// none since the session was created
+    last_answer: Arc<AtomicU64>,
+
     pub store: Option<Store>,
 
     /// Spent utxos
@@ -180,27 +547,106 @@ pub struct ElectrumSession {
 
     xr_cache: ExchangeRatesCache,
 
-    /// The keys are exchange names, the values are all the currencies that a
-    /// given exchange has data for.
-    available_currencies: Option<HashMap<String, Vec<Currency>>>,
+    /// The currencies available at each exchange and when they were fetched
+    available_currencies: Option<(SystemTime, AvailableCurrencies)>,
+
+    /// Source of the exchange rates, if `None` they're fetched from the price
+    /// server of the network
+    price_source: Option<Arc<dyn PriceSource>>,
+
+    /// The PIN server protecting credentials, if `None` the one of the network
+    /// reached through the session proxy
+    pin_server: Option<Arc<dyn PinServer>>,
+
+    /// Protects the credentials kept for quick login, if `None` a
+    /// [`SoftwareProtector`] with its key in the state dir
+    credential_protector: Option<Arc<dyn CredentialProtector>>,
+
+    /// The compliance check of the transactions signed and broadcast, see
+    /// [`ElectrumSession::set_transaction_policy`]
+    transaction_policy: Option<TransactionPolicy>,
+
+    /// One-time tokens returned by `approve_transaction`, with the approved
+    /// txid of the transaction without its input scripts
+    confirmation_tokens: HashMap<String, BETxid>,
 
     first_sync: Arc<AtomicBool>,
 
+    /// Updated by the sync loop, see [`Self::get_session_metrics`]
+    metrics: Arc<Mutex<SessionMetrics>>,
+
     /// Number of consecutive unused scripts/addresses to monitor.
     gap_limit: u32,
 
-    /// Last time fees were asked to the server
-    fee_fetched_at: Arc<Mutex<SystemTime>>,
+    /// Last time fees were asked to the server, on the monotonic clock so
+    /// that changes of the local clock don't repeat or delay the requests
+    fee_fetched_at: Arc<Mutex<Option<Instant>>>,
+
+    /// Last time fees could not be fetched, not to ask the server again on
+    /// every call while offline
+    fee_failed_at: Mutex<Option<Instant>>,
+
+    /// The time of the network, estimated from the tips of the server
+    network_time: NetworkTime,
+
+    /// The signer of the wallet, if logged in with one
+    signer: Option<Arc<dyn Signer>>,
+
+    /// Set between [`ElectrumSession::pause`] and [`ElectrumSession::resume`]
+    paused: Option<Paused>,
+
+    /// The manager syncing the session, if not synced by its own threads
+    manager: Option<Arc<manager::Shared>>,
+
+    /// The id of the syncing of the session in `manager`, if started
+    managed_sync: Option<u64>,
+
+    /// The scripts subscribed to by the sync loop, if started
+    subscriptions: Option<Arc<listener::Subscriptions>>,
+
+    /// The height of the SPV headers chain, 0 until known
+    spv_height: Arc<AtomicU32>,
+
+    /// False to stop the headers thread alone, when SPV is disabled on a
+    /// syncing session
+    spv_running: Arc<AtomicBool>,
+
+    /// The check of the signblock witness of the last liquid tip, see
+    /// [`NetworkInfo::signblock`]
+    signblock: Arc<Mutex<Option<SignblockValidation>>>,
+
+    /// True after [`ElectrumSession::stop`], all the calls fail afterwards
+    closed: bool,
+
+    /// The name of the store file, identifying the wallet in the traces
+    wallet_hash_id: Option<String>,
+
+    /// Keeps the log messages redacted while the session is alive, if it is
+    /// in privacy mode
+    _privacy_mode: Option<PrivacyMode>,
+}
+
+/// The state of a paused session
+struct Paused {
+    /// Calls are served from the store, see [`PauseOpt::serve_cache`]
+    serve_cache: bool,
+
+    /// The threads were running, they're restarted on resume
+    was_syncing: bool,
 }
 
 #[derive(Clone)]
 pub struct StateUpdater {
     current: Arc<AtomicBool>,
+    last_answer: Arc<AtomicU64>,
     notify: NativeNotif,
 }
 
 impl StateUpdater {
     fn update_if_needed(&self, new_network_call_succeeded: bool) {
+        if new_network_call_succeeded {
+            self.last_answer.store(util::now() / 1_000_000, Ordering::Relaxed);
+        }
         let last_network_call_succeeded =
             self.current.swap(new_network_call_succeeded, Ordering::Relaxed);
         if last_network_call_succeeded != new_network_call_succeeded {
@@ -228,6 +674,37 @@ fn socksify(proxy: Option<&str>) -> Option<String> {
     }
 }
 
+/// Check the server is on the same chain as `network`
+///
+/// Servers not supporting `server.features` and elements regtest, whose genesis
+/// depends on the chain parameters, are not checked unless the network
+/// parameters specify the genesis.
+fn check_server_genesis(client: &Client, network: &NetworkParameters) -> Result<(), Error> {
+    let expected = match (network.genesis_hash(), network.id()) {
+        (Some(hash), _) => hash.to_lowercase(),
+        (None, NetworkId::Bitcoin(network)) => genesis_block(network).block_hash().to_string(),
+        (None, NetworkId::Elements(network)) => match headers::liquid::genesis_hash(network) {
+            Some(hash) => hash.to_string(),
+            None => return Ok(()),
+        },
+    };
+    let found = match client.server_features() {
+        Ok(features) => features.genesis_hash.to_lower_hex_string(),
+        Err(e) => {
+            warn!("cannot get server features {:?}", e);
+            return Ok(());
+        }
+    };
+    ensure!(
+        found == expected,
+        Error::ServerGenesisMismatch {
+            expected,
+            found
+        }
+    );
+    Ok(())
+}
+
 fn try_get_fee_estimates(client: &Client, min_rate: u64) -> Result<Vec<FeeEstimate>, Error> {
     let relay_fee = (client.relay_fee()? * 100_000_000.0) as u64;
     let min_rate = min_rate.max(relay_fee);
@@ -288,6 +765,8 @@ impl ElectrumSession {
     }
 
     pub fn connect(&mut self, net_params: &Value) -> Result<(), Error> {
+        ensure!(self.paused.is_none(), Error::SessionPaused);
+
         // gdk tor session may change the proxy port after a restart, so we update the proxy here
         self.proxy = socksify(net_params.get("proxy").and_then(|p| p.as_str()));
 
@@ -305,10 +784,13 @@ impl ElectrumSession {
             // with a ping to emit a notification
             let electrum_url = self.url.clone();
             let proxy = self.proxy.clone();
-            match electrum_url.build_client(proxy.as_deref(), None) {
+            let resolver = Resolver::new(&self.network);
+            match electrum_url.build_client(proxy.as_deref(), None, &resolver) {
                 Ok(client) => match client.ping() {
                     Ok(_) => {
                         info!("succesfully pinged electrum server {:?}", electrum_url.url());
+                        check_server_genesis(&client, &self.network)?;
+                        self.sample_server_tip(&client);
                         self.last_network_call_succeeded.store(true, Ordering::Relaxed);
                         true
                     }
@@ -328,9 +810,25 @@ impl ElectrumSession {
         Ok(())
     }
 
+    /// Sample the time of the network with the tip of the server, warning
+    /// if the local clock is off
+    fn sample_server_tip(&self, client: &Client) {
+        let tip = client
+            .block_headers_subscribe_raw()
+            .map_err(Error::from)
+            .and_then(|tip| HeightHeader::try_from((tip, self.network.id())));
+        match tip {
+            Ok(tip) => sample_network_time(&self.network_time, &self.notify, &tip.header),
+            Err(e) => warn!("cannot get the tip to sample the network time: {:?}", e),
+        }
+    }
+
     pub fn disconnect(&mut self) -> Result<(), Error> {
+        // A paused session has no threads running already, it just won't resume
+        let was_syncing = self.paused.take().map(|paused| paused.was_syncing).unwrap_or(false);
+
         // A call to disconnect signals that the caller does to wants the background threads to run
-        if self.user_wants_to_sync.swap(false, Ordering::Relaxed) {
+        if self.user_wants_to_sync.swap(false, Ordering::Relaxed) || was_syncing {
             // This is an actual disconnect, stop the threads and send the notification
             self.join_threads();
 
@@ -347,16 +845,224 @@ impl ElectrumSession {
         Ok(())
     }
 
-    fn inner_decrypt_with_pin(&self, details: &DecryptWithPinDetails) -> Result<Vec<u8>, Error> {
-        let agent = self.build_request_agent()?;
+    /// Switch to the electrum server `opt` without logging in again
+    ///
+    /// The new server must be on the chain of the network and at most
+    /// [`MAX_SERVER_BEHIND`] blocks behind the tip known to the session,
+    /// otherwise the session keeps using the current server. If syncing, the
+    /// background threads are restarted on the new server, subscribing again
+    /// to the wallet scripts. The choice is kept in the store and used at the
+    /// next login.
+    pub fn set_electrum_server(&mut self, opt: &ElectrumServer) -> Result<(), Error> {
+        ensure!(self.paused.is_none(), Error::SessionPaused);
+        let url = ElectrumUrl::try_from(opt)?;
+
+        let resolver = Resolver::new(&self.network);
+        let client = url.build_client(self.proxy.as_deref(), None, &resolver)?;
+        check_server_genesis(&client, &self.network)?;
+        let tip =
+            HeightHeader::try_from((client.block_headers_subscribe_raw()?, self.network.id()))?;
+        let store_tip = match self.store() {
+            Ok(store) => store.read()?.cache.tip_height(),
+            Err(_) => 0,
+        };
+        let known = store_tip.max(self.spv_height.load(Ordering::Relaxed));
+        ensure!(
+            tip.height + MAX_SERVER_BEHIND >= known,
+            Error::ServerBehind {
+                height: tip.height,
+                known,
+                max_behind: MAX_SERVER_BEHIND,
+            }
+        );
+
+        let was_syncing = !self.handles.is_empty() || self.managed_sync.is_some();
+        if was_syncing {
+            self.join_threads();
+        }
+        let previous = std::mem::replace(&mut self.url, url);
+        if was_syncing {
+            if let Err(e) = self.start_threads() {
+                warn!("cannot sync with {}, back to {}: {:?}", opt.url, previous.url(), e);
+                self.join_threads();
+                self.url = previous;
+                self.start_threads()?;
+                return Err(e);
+            }
+        }
+        info!("switched electrum server from {} to {}", previous.url(), opt.url);
+
+        if let Ok(store) = self.store() {
+            store.write()?.set_electrum_server(opt.clone())?;
+        }
+        self.sample_server_tip(&client);
+        self.last_network_call_succeeded.store(true, Ordering::Relaxed);
+        self.notify.network_switched(&opt.url);
+        Ok(())
+    }
 
-        let pin_client = PinClient::new(
-            agent,
-            self.network.pin_server_url()?,
-            self.network.pin_server_public_key()?,
+    /// Enable or disable SPV without logging in again
+    ///
+    /// Disabled, the session trusts its server: no headers are downloaded nor
+    /// cross-validated and the transactions are reported
+    /// [`SPVVerifyTxResult::Disabled`]. The headers thread of a syncing
+    /// session is stopped or started, the verifications done before are kept.
+    pub fn set_spv_enabled(&mut self, opt: &SetSpvEnabledOpt) -> Result<(), Error> {
+        if self.network.spv_enabled.unwrap_or(false) == opt.enabled {
+            return Ok(());
+        }
+        self.network.spv_enabled = Some(opt.enabled);
+        for account in self.accounts.write()?.values_mut() {
+            account.set_spv_enabled(opt.enabled);
+        }
+        // Managed sessions never verify transactions, paused ones start the
+        // thread on resume
+        let syncing = !self.handles.is_empty();
+        if !opt.enabled {
+            self.stop_headers_thread();
+        } else if syncing {
+            let wallet_birthday = self.resolve_wallet_birthday().unwrap_or_else(|e| {
+                warn!("cannot resolve the wallet birthday {:?}", e);
+                None
+            });
+            self.spv_running.store(true, Ordering::Relaxed);
+            self.start_headers_thread(wallet_birthday.and_then(|birthday| birthday.height))?;
+        }
+        info!("spv enabled: {}", opt.enabled);
+        Ok(())
+    }
+
+    /// Stop the headers thread, if running, leaving the others
+    fn stop_headers_thread(&mut self) {
+        self.spv_running.store(false, Ordering::Relaxed);
+        let headers = self.handles.iter().position(|h| h.thread().name() == Some("headers"));
+        if let Some(index) = headers {
+            if self.handles.remove(index).join().is_err() {
+                warn!("thread headers panicked");
+            }
+        }
+    }
+
+    /// Replace the PIN server used to encrypt and decrypt with a PIN
+    pub fn set_pin_server(&mut self, server: Arc<dyn PinServer>) {
+        self.pin_server = Some(server);
+    }
+
+    /// Replace the protector of the credentials kept for quick login, eg.
+    /// with one backed by the keystore of the device
+    pub fn set_credential_protector(&mut self, protector: Arc<dyn CredentialProtector>) {
+        self.credential_protector = Some(protector);
+    }
+
+    fn credential_protector(&self) -> Arc<dyn CredentialProtector> {
+        match &self.credential_protector {
+            Some(protector) => Arc::clone(protector),
+            None => Arc::new(SoftwareProtector::new(
+                Path::new(&self.network.state_dir).join(quick_login::SOFTWARE_KEY_FILE),
+            )),
+        }
+    }
+
+    /// The file of the credentials kept for the quick login of `wallet_hash_id`
+    fn quick_login_path(&self, wallet_hash_id: &str) -> Result<PathBuf, Error> {
+        ensure!(
+            !wallet_hash_id.is_empty() && wallet_hash_id.chars().all(|c| c.is_ascii_hexdigit()),
+            Error::Generic("invalid wallet_hash_id".into())
         );
+        let store_dir = Path::new(&self.network.state_dir).join(wallet_hash_id);
+        Ok(quick_login::quick_login_path(&store_dir))
+    }
+
+    /// Keep `credentials` of the wallet logged in for [`Self::quick_login`],
+    /// protected by the [`CredentialProtector`] of the session
+    pub fn enable_quick_login(&self, credentials: &Credentials) -> Result<(), Error> {
+        let wallet_hash_id = self.wallet_hash_id.as_ref().ok_or(Error::StoreNotLoaded)?;
+        let master_xpub = credentials.master_xpub(&self.network)?;
+        ensure!(
+            self.network.wallet_hash_id(&master_xpub) == *wallet_hash_id,
+            Error::Generic("the credentials are not of the wallet logged in".into())
+        );
+        let path = self.quick_login_path(wallet_hash_id)?;
+        quick_login::save(&path, self.credential_protector().as_ref(), credentials)?;
+        info!("quick login enabled");
+        Ok(())
+    }
+
+    /// Log in with the credentials kept by [`Self::enable_quick_login`]
+    ///
+    /// Fails with `id_quick_login_unavailable` if they can't be recovered,
+    /// the wallet must then be logged in with its mnemonic.
+    pub fn quick_login(&mut self, opt: &QuickLoginOpt) -> Result<LoginData, Error> {
+        let path = self.quick_login_path(&opt.wallet_hash_id)?;
+        let credentials = quick_login::load(&path, self.credential_protector().as_ref())?;
+        self.login(&credentials)
+    }
+
+    /// Forget the credentials kept for the quick login of the wallet
+    pub fn disable_quick_login(&self, opt: &QuickLoginOpt) -> Result<(), Error> {
+        quick_login::remove(&self.quick_login_path(&opt.wallet_hash_id)?)
+    }
+
+    /// Check the transactions before signing and broadcasting them with
+    /// `policy`, replacing the previous one
+    pub fn set_transaction_policy(&mut self, policy: TransactionPolicy) {
+        self.transaction_policy = Some(policy);
+    }
 
-        pin_client.decrypt(&details.pin_data, &details.pin).map_err(Into::into)
+    /// Submit `tx` to the policy set with [`Self::set_transaction_policy`],
+    /// if any, failing if it rejects it
+    ///
+    /// Transactions for which the policy requires a confirmation pass once
+    /// approved with [`Self::approve_transaction`], each approval at a single stage.
+    fn check_policy(&mut self, stage: PolicyStage, tx: &BETransaction) -> Result<(), Error> {
+        let policy = match &self.transaction_policy {
+            Some(policy) => policy,
+            None => return Ok(()),
+        };
+        // Not logged in sessions can broadcast, without recognizing the wallet outputs
+        let store = self.store().ok();
+        let store_read = store.as_ref().map(|store| store.read()).transpose()?;
+        let accounts = self.accounts.read()?;
+        let wallet = store_read.as_deref().map(|store| psbt::Wallet {
+            accounts: &accounts,
+            store,
+        });
+        let policy_tx = PolicyTransaction::new(stage, tx, self.network.id(), wallet.as_ref());
+        match policy(&policy_tx) {
+            PolicyDecision::Allow => Ok(()),
+            PolicyDecision::Reject(reason) => {
+                info!("transaction {} rejected by the policy: {}", policy_tx.txid, reason);
+                Err(Error::PolicyRejected(reason))
+            }
+            PolicyDecision::RequireConfirmation(reason) => {
+                let token = self
+                    .confirmation_tokens
+                    .iter()
+                    .find(|(_, txid)| **txid == tx.unsigned_txid())
+                    .map(|(token, _)| token.clone())
+                    .ok_or(Error::PolicyConfirmationRequired(reason))?;
+                // Tokens can be used only once
+                self.confirmation_tokens.remove(&token);
+                Ok(())
+            }
+        }
+    }
+
+    /// A client of the server set with [`Self::set_pin_server`], otherwise of
+    /// the PIN server of the network
+    fn pin_client(&self) -> Result<PinClient, Error> {
+        let server = match &self.pin_server {
+            Some(server) => Arc::clone(server),
+            None => Arc::new(HttpPinServer::new(
+                self.build_request_agent()?,
+                self.network.pin_server_url()?,
+            )),
+        };
+        Ok(PinClient::with_server(server, self.network.pin_server_public_key()?))
+    }
+
+    fn inner_decrypt_with_pin(&self, details: &DecryptWithPinDetails) -> Result<Vec<u8>, Error> {
+        self.pin_client()?.decrypt(&details.pin_data, &details.pin).map_err(Into::into)
     }
 
     pub fn decrypt_with_pin(
@@ -400,13 +1106,24 @@ impl ElectrumSession {
                     _ => return Err(Error::Generic("Missing filename or key".into())),
                 },
             };
-            path.push(filename);
+            path.push(&filename);
             info!("Store root path: {:?}", path);
+            self.wallet_hash_id = Some(filename);
 
-            let store = StoreMeta::new(&path, &cipher, self.network.id())?;
+            let store = StoreMeta::new(&path, &cipher, self.network.policy().min_fee_rate)?;
+            if let Some(server) = store.get_electrum_server() {
+                match ElectrumUrl::try_from(server) {
+                    Ok(url) => self.url = url,
+                    Err(e) => warn!("ignoring the stored electrum server: {:?}", e),
+                }
+            }
             let store = Arc::new(RwLock::new(store));
             self.store = Some(store);
         }
+        if let Some(birthday) = &opt.wallet_birthday {
+            birthday.validate()?;
+            self.store()?.write()?.set_wallet_birthday(birthday.clone())?;
+        }
         if let Some(fingerprint) = opt.master_xpub_fingerprint {
             self.master_xpub_fingerprint = fingerprint;
             self.is_initialized = true;
@@ -479,6 +1196,7 @@ impl ElectrumSession {
             master_xpub_fingerprint: Some(master_xpub_fingerprint),
             filename: None,
             encryption_key_hex: None,
+            wallet_birthday: None,
         })?;
         if let Some(ref master_blinding_key) = master_blinding_key {
             self.set_master_blinding_key(&SetMasterBlindingKeyOpt {
@@ -501,716 +1219,3673 @@ impl ElectrumSession {
                 xpub: account.xpub,
             });
         }
+        for account_num in self.get_subaccount_nums()? {
+            if let Some(template) = self.green_multisig_template(account_num)? {
+                self.create_subaccount(CreateAccountOpt {
+                    subaccount: account_num,
+                    name: "".to_string(),
+                    xpub: template.user_xpub,
+                    discovered: false,
+                    is_already_created: true,
+                    allow_gaps: true,
+                })?;
+                xpubs.push(AccountXpub {
+                    account_num,
+                    xpub: template.user_xpub,
+                });
+            }
+        }
 
-        self.start_threads()?;
+        self.finish_login()?;
         Ok(LoginData {
             wallet_hash_id: self.network.wallet_hash_id(&master_xpub),
             xpub_hash_id: self.network.xpub_hash_id(&master_xpub),
             master_xpub_fingerprint: self.master_xpub_fingerprint,
             xpubs,
             master_blinding_key,
+            syncing: self.first_sync.load(Ordering::Relaxed),
         })
     }
 
-    pub fn join_threads(&mut self) {
-        while let Some(handle) = self.handles.pop() {
-            handle.join().expect("Couldn't join on the associated thread");
-        }
+    /// Log in with the wallet of `signer`, which is then used for all the
+    /// signing of the session, the keys are never asked to it
+    pub fn login_with_signer(&mut self, signer: Arc<dyn Signer>) -> Result<LoginData, Error> {
+        self.restore_with_signer(signer, None)
     }
 
-    pub fn state_updater(&self) -> Result<StateUpdater, Error> {
-        Ok(StateUpdater {
-            current: self.last_network_call_succeeded.clone(),
-            notify: self.notify.clone(),
-        })
+    /// Log in with the mnemonic of `credentials`, signing with a
+    /// [`SoftwareSigner`]
+    pub fn login(&mut self, credentials: &Credentials) -> Result<LoginData, Error> {
+        let signer = SoftwareSigner::from_seed(&credentials.seed()?, self.network.bip32_network())?;
+        let login_data = self.login_with_signer(Arc::new(signer))?;
+        let bip39_passphrase = !credentials.bip39_passphrase.is_empty();
+        self.store()?.write()?.set_bip39_passphrase(bip39_passphrase)?;
+        Ok(login_data)
     }
 
-    pub fn start_threads(&mut self) -> Result<(), Error> {
-        if !self.user_wants_to_sync.load(Ordering::Relaxed) {
-            return Err(Error::Generic("connect must be called before start_threads".into()));
-        }
-
-        if self.handles.len() > 0 {
-            // Threads are already running
-            return Ok(());
-        }
-
-        let master_blinding = if self.network.liquid {
-            let master_blinding = self.store()?.read()?.cache.master_blinding.clone();
-            if master_blinding.is_none() {
-                return Err(Error::MissingMasterBlindingKey);
-            }
-            master_blinding
+    /// Like [`Self::login_with_signer`], restoring the wallet from its
+    /// birthday if given, see [`WalletBirthday`]
+    pub fn restore_with_signer(
+        &mut self,
+        signer: Arc<dyn Signer>,
+        wallet_birthday: Option<WalletBirthday>,
+    ) -> Result<LoginData, Error> {
+        let master_xpub = signer.get_xpub(&DerivationPath::master())?;
+        self.load_store(&LoadStoreOpt {
+            master_xpub: Some(master_xpub),
+            master_xpub_fingerprint: Some(master_xpub.fingerprint()),
+            filename: None,
+            encryption_key_hex: None,
+            wallet_birthday,
+        })?;
+        let master_blinding_key = if self.network.liquid {
+            let master_blinding_key = signer.get_master_blinding_key()?;
+            self.set_master_blinding_key(&SetMasterBlindingKeyOpt {
+                master_blinding_key: master_blinding_key.clone(),
+            })?;
+            Some(master_blinding_key)
         } else {
             None
         };
 
-        {
-            let store = self.store()?;
-            let store_read = store.read()?;
-            let tip_height = store_read.cache.tip_height();
-            let tip_hash = store_read.cache.tip_block_hash();
-            let tip_prev_hash = store_read.cache.tip_prev_block_hash();
-            // Do not notify a block if we haven't fetched one yet
-            if tip_hash != BEBlockHash::default() {
-                self.notify.block_from_hashes(tip_height, &tip_hash, &tip_prev_hash);
-            }
-        };
+        let mut xpubs = vec![];
+        for account_num in self.get_subaccount_nums()? {
+            let xpub = match self.green_multisig_template(account_num)? {
+                Some(template) => template.user_xpub,
+                None => {
+                    let (_, path) = get_account_derivation(account_num, self.network.id())?;
+                    signer.get_xpub(&path)?
+                }
+            };
+            self.create_subaccount(CreateAccountOpt {
+                subaccount: account_num,
+                name: "".to_string(),
+                xpub,
+                discovered: false,
+                is_already_created: true,
+                allow_gaps: true,
+            })?;
+            xpubs.push(AccountXpub {
+                account_num,
+                xpub,
+            });
+        }
+        self.signer = Some(signer);
 
-        info!(
-            "building client, url {}, proxy {}",
-            self.url.url(),
-            self.proxy.as_ref().unwrap_or(&"".to_string())
-        );
+        self.finish_login()?;
+        Ok(LoginData {
+            wallet_hash_id: self.network.wallet_hash_id(&master_xpub),
+            xpub_hash_id: self.network.xpub_hash_id(&master_xpub),
+            master_xpub_fingerprint: self.master_xpub_fingerprint,
+            xpubs,
+            master_blinding_key,
+            syncing: self.first_sync.load(Ordering::Relaxed),
+        })
+    }
 
-        if let Ok(fee_client) = self.url.build_client(self.proxy.as_deref(), None) {
-            info!("building built end");
-            let fee_store = self.store()?;
-            let fee_fetched_at = self.fee_fetched_at.clone();
-            let min_rate = self.network.id().default_min_fee_rate();
-            thread::spawn(move || {
-                match try_get_fee_estimates(&fee_client, min_rate) {
-                    Ok(fee_estimates) => {
-                        fee_store.write().unwrap().cache.fee_estimates = fee_estimates;
-                        let mut fee_fetched_at = fee_fetched_at.lock().unwrap();
-                        *fee_fetched_at = SystemTime::now();
-                    }
-                    Err(e) => {
-                        warn!("can't update fee estimates {:?}", e)
-                    }
-                };
-            });
+    /// Set the username and password giving watch-only access to the wallet,
+    /// replacing the previous ones if any, or remove the access if both are
+    /// empty
+    pub fn set_watch_only_credentials(
+        &mut self,
+        opt: WatchOnlyUserCredentials,
+    ) -> Result<(), Error> {
+        let signer = self.signer.clone().ok_or(Error::MissingSigner)?;
+        let username = opt.username;
+        let password = opt.password.get_password_str();
+        if username.is_empty() != password.is_empty() {
+            return Err(Error::Generic("username and password must be both set or empty".into()));
         }
 
-        let sync_interval = self.network.sync_interval.unwrap_or(1);
+        let store = self.store()?;
+        let previous = store.read()?.store.watch_only_username.clone();
+        if username.is_empty() {
+            if let Some(previous) = previous {
+                watch_only::remove(&self.network, &previous)?;
+            }
+            store.write()?.store.watch_only_username = None;
+            return Ok(());
+        }
 
-        if self.network.spv_enabled.unwrap_or(false) {
-            let checker = match self.network.id() {
-                NetworkId::Bitcoin(network) => {
-                    ChainOrVerifier::Chain(HeadersChain::new(&self.network.state_dir, network)?)
-                }
-                NetworkId::Elements(network) => {
-                    let verifier = Verifier::new(network);
-                    ChainOrVerifier::Verifier(verifier)
+        let mut xpubs = vec![];
+        for account_num in self.get_subaccount_nums()? {
+            let xpub = match self.green_multisig_template(account_num)? {
+                Some(template) => template.user_xpub,
+                None => {
+                    let (_, path) = get_account_derivation(account_num, self.network.id())?;
+                    signer.get_xpub(&path)?
                 }
             };
-
-            let cross_validator =
-                SpvCrossValidator::from_network(&self.network, &self.proxy, self.timeout)?;
-
-            let mut headers = Headers {
-                store: self.store()?,
-                checker,
-                cross_validator,
+            xpubs.push(AccountXpub {
+                account_num,
+                xpub,
+            });
+        }
+        let bundle = watch_only::WatchOnlyBundle {
+            master_xpub: signer.get_xpub(&DerivationPath::master())?,
+            xpubs,
+            master_blinding_key: store.read()?.cache.master_blinding.clone(),
+        };
+        // The previous access is kept if the new one can't be saved
+        watch_only::save(&self.network, &username, &password, &bundle)?;
+        if let Some(previous) = previous.filter(|previous| *previous != username) {
+            watch_only::remove(&self.network, &previous)?;
+        }
+        store.write()?.store.watch_only_username = Some(username);
+        Ok(())
+    }
+
+    /// The username of the watch-only access to the wallet, empty if not set
+    pub fn get_watch_only_username(&self) -> Result<String, Error> {
+        Ok(self.store()?.read()?.store.watch_only_username.clone().unwrap_or_default())
+    }
+
+    /// Log in watch-only with the username and password set with
+    /// [`Self::set_watch_only_credentials`] by a full session
+    pub fn login_watch_only(&mut self, opt: WatchOnlyUserCredentials) -> Result<LoginData, Error> {
+        let bundle =
+            watch_only::load(&self.network, &opt.username, &opt.password.get_password_str())?;
+        let master_xpub = bundle.master_xpub;
+        self.load_store(&LoadStoreOpt {
+            master_xpub: Some(master_xpub),
+            master_xpub_fingerprint: Some(master_xpub.fingerprint()),
+            filename: None,
+            encryption_key_hex: None,
+            wallet_birthday: None,
+        })?;
+        if let Some(ref master_blinding_key) = bundle.master_blinding_key {
+            self.set_master_blinding_key(&SetMasterBlindingKeyOpt {
+                master_blinding_key: master_blinding_key.clone(),
+            })?;
+        }
+
+        for account in bundle.xpubs.iter() {
+            self.create_subaccount(CreateAccountOpt {
+                subaccount: account.account_num,
+                name: "".to_string(),
+                xpub: account.xpub,
+                discovered: false,
+                is_already_created: true,
+                allow_gaps: true,
+            })?;
+        }
+
+        self.finish_login()?;
+        Ok(LoginData {
+            wallet_hash_id: self.network.wallet_hash_id(&master_xpub),
+            xpub_hash_id: self.network.xpub_hash_id(&master_xpub),
+            master_xpub_fingerprint: self.master_xpub_fingerprint,
+            xpubs: bundle.xpubs,
+            master_blinding_key: bundle.master_blinding_key,
+            syncing: self.first_sync.load(Ordering::Relaxed),
+        })
+    }
+
+    /// The signer of `account`: the one of its extended private key `xprv`
+    /// if given, the one of the session otherwise
+    fn account_signer(
+        &self,
+        account: &Account,
+        xprv: Option<&Xpriv>,
+    ) -> Result<Arc<dyn Signer>, Error> {
+        if account.green().is_some() {
+            return Err(Error::WatchOnlySubaccount(account.num()));
+        }
+        match xprv {
+            Some(xprv) => Ok(Arc::new(account.xprv_signer(xprv)?)),
+            None => self.signer.clone().ok_or(Error::MissingSigner),
+        }
+    }
+
+    /// Audit a sample of the cached scripts, repairing them, and start the
+    /// threads syncing the wallet if `connect` was called, otherwise on connect
+    ///
+    /// Logging in needs no network then: the wallet is served from the
+    /// store until the first sync, see [`Self::get_sync_status`].
+    fn finish_login(&mut self) -> Result<(), Error> {
+        let opt = AuditDerivationsOpt {
+            sample: Some(LOGIN_AUDIT_SAMPLE),
+            repair: true,
+        };
+        for mismatch in self.audit_derivations(&opt)?.mismatches {
+            warn!("repaired the script of {:?}", mismatch);
+        }
+        if self.user_wants_to_sync.load(Ordering::Relaxed) {
+            self.start_threads()?;
+        }
+        Ok(())
+    }
+
+    /// Whether the wallet has not synced yet since login
+    pub fn get_sync_status(&self) -> SyncStatus {
+        SyncStatus {
+            syncing: self.first_sync.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Check the running balances of every subaccount against their full
+    /// recomputation from the transactions in the store
+    ///
+    /// Inconsistent balances are replaced by the recomputed ones and reported
+    /// as an error.
+    pub fn verify_store(&self) -> Result<(), Error> {
+        let store = self.store()?;
+        let mut store_write = store.write()?;
+        let mut inconsistent = vec![];
+        for (account_num, acc_store) in store_write.cache.accounts.iter_mut() {
+            let computed = Some(acc_store.compute_utxo_balances());
+            if computed != acc_store.utxo_balances {
+                warn!(
+                    "subaccount {} balances {:?} differ from the recomputed {:?}",
+                    account_num,
+                    acc_store.utxo_balances.as_ref().map(UtxoBalances::balances),
+                    computed.as_ref().map(UtxoBalances::balances)
+                );
+                acc_store.utxo_balances = computed;
+                inconsistent.push(*account_num);
+            }
+        }
+        ensure!(
+            inconsistent.is_empty(),
+            Error::Generic(format!("inconsistent balances in subaccounts {:?}", inconsistent))
+        );
+        Ok(())
+    }
+
+    /// The composition of the wallet of the session, for listing wallets
+    ///
+    /// Only the store is read, no network call is made.
+    pub fn get_wallet_info(&self) -> Result<WalletInfo, Error> {
+        let mut subaccounts: Vec<_> = self
+            .accounts
+            .read()?
+            .values()
+            .map(|account| WalletInfoSubaccount {
+                pointer: account.num(),
+                script_type: account.script_type(),
+            })
+            .collect();
+        subaccounts.sort_by_key(|subaccount| subaccount.pointer);
+
+        let mut balances = Balances::new();
+        for subaccount in subaccounts.iter() {
+            let opt = GetBalanceOpt {
+                subaccount: subaccount.pointer,
+                num_confs: Some(0),
+                confidential_utxos_only: None,
+                asset_id: None,
+                trust_own_unconfirmed: Some(true),
             };
+            for (asset_id, satoshi) in self.get_balance(&opt)? {
+                *balances.entry(asset_id).or_default() += satoshi;
+            }
+        }
 
-            let headers_url = self.url.clone();
-            let proxy = self.proxy.clone();
-            let notify_blocks = self.notify.clone();
-            let chunk_size = DIFFCHANGE_INTERVAL as usize;
-            let user_wants_to_sync = self.user_wants_to_sync.clone();
-            let max_reorg_blocks = self.network.max_reorg_blocks.unwrap_or(144);
-
-            let headers_handle = thread::spawn(move || {
-                info!("starting headers thread");
-                let mut round = 0u8;
-
-                'outer: loop {
-                    if wait_or_close(&user_wants_to_sync, 7) {
-                        info!("closing headers thread");
-                        break;
-                    }
-                    let mut _lock;
-                    if let ChainOrVerifier::Chain(chain) = &headers.checker {
-                        _lock = HEADERS_FILE_MUTEX
-                            .get(&chain.network)
-                            .expect("unreachable because map populate with every enum variants")
-                            .lock()
-                            .unwrap();
-                    }
+        let store = self.store()?;
+        let store_read = store.read()?;
+        Ok(WalletInfo {
+            wallet_hash_id: self.wallet_hash_id.clone(),
+            network: self.network.name.clone(),
+            watch_only: self.signer.is_none(),
+            subaccounts,
+            balances,
+            wallet_birthday: store_read.wallet_birthday().cloned(),
+            store_version: store_read.version(),
+            bip39_passphrase: store_read.bip39_passphrase(),
+        })
+    }
 
-                    if let Ok(client) = headers_url.build_client(proxy.as_deref(), None) {
-                        loop {
-                            if !user_wants_to_sync.load(Ordering::Relaxed) {
-                                info!("closing headers thread");
-                                break 'outer;
-                            }
-                            match headers.ask(chunk_size, &client) {
-                                Ok(headers_found) => {
-                                    if headers_found < chunk_size {
-                                        break;
-                                    } else {
-                                        info!("headers found: {}", headers_found);
-                                    }
-                                }
-                                Err(Error::InvalidHeaders) => {
-                                    warn!("invalid headers");
-                                    // this should handle reorgs and also broke IO writes update
-                                    headers.store.write().unwrap().cache.txs_verif.clear();
-                                    if let Err(e) = headers.remove(max_reorg_blocks) {
-                                        warn!("failed removing headers: {:?}", e);
-                                        break;
-                                    }
-                                    // XXX clear affected blocks/txs more surgically?
-                                }
-                                Err(Error::Common(BtcEncodingError(_)))
-                                | Err(Error::Common(ElementsEncodingError(_))) => {
-                                    // We aren't able to decode the blockheaders returned by the server,
-                                    // do not sync headers further.
-                                    break 'outer;
-                                }
-                                Err(e) => {
-                                    warn!("error while asking headers {}", e);
-                                    thread::sleep(Duration::from_millis(500));
-                                }
-                            }
-                        }
+    /// The wallet birthday with its height, resolving its timestamp with the
+    /// server the first time
+    fn resolve_wallet_birthday(&self) -> Result<Option<WalletBirthday>, Error> {
+        let store = self.store()?;
+        let birthday = match store.read()?.wallet_birthday() {
+            Some(birthday) if birthday.height.is_none() => birthday.clone(),
+            birthday => return Ok(birthday.cloned()),
+        };
+        let timestamp = birthday.timestamp.unwrap_or(0);
+        let height = height_at_time(&self.server_client()?, timestamp, self.network.id())?;
+        info!("wallet birthday {} resolved to height {}", timestamp, height);
+        let birthday = WalletBirthday {
+            height: Some(height),
+            ..birthday
+        };
+        store.write()?.set_wallet_birthday(birthday.clone())?;
+        Ok(Some(birthday))
+    }
 
-                        match headers.get_proofs(&client) {
-                            Ok(found) => {
-                                if found > 0 {
-                                    info!("found proof {}", found)
-                                }
-                            }
-                            Err(e) => warn!("error in getting proofs {:?}", e),
-                        }
+    /// The counters of the syncing of the session, for observability
+    pub fn get_session_metrics(&self) -> Result<SessionMetrics, Error> {
+        Ok(self.metrics.lock()?.clone())
+    }
 
-                        if round % CROSS_VALIDATION_RATE == 0 {
-                            let status_changed = headers.cross_validate();
-                            if status_changed {
-                                // TODO: improve block notification
-                                if let Ok(store_read) = headers.store.read() {
-                                    let tip_height = store_read.cache.tip_height();
-                                    let tip_hash = store_read.cache.tip_block_hash();
-                                    let tip_prev_hash = store_read.cache.tip_prev_block_hash();
-                                    notify_blocks.block_from_hashes(
-                                        tip_height,
-                                        &tip_hash,
-                                        &tip_prev_hash,
-                                    );
-                                }
-                            }
-                        }
+    /// The health of the session, from what its threads recorded
+    ///
+    /// No network call is made, it can be polled every few seconds.
+    pub fn get_health(&self) -> Result<SessionHealth, Error> {
+        let now = util::now() / 1_000_000;
+        let syncing = self.user_wants_to_sync.load(Ordering::Relaxed) && self.paused.is_none();
+        let connected = self.last_network_call_succeeded.load(Ordering::Relaxed);
+        let last_message_age = match self.last_answer.load(Ordering::Relaxed) {
+            0 => None,
+            last_answer => Some(now.saturating_sub(last_answer)),
+        };
+        let (server_tip_height, cross_validation_valid, store_flush_age) = match &self.store {
+            Some(store) => {
+                let store_read = store.read()?;
+                (
+                    store_read.cache.tip_.as_ref().map(|(height, _)| *height),
+                    store_read.cache.cross_validation_result.as_ref().map(|r| r.is_valid()),
+                    store_read
+                        .flushed_at()
+                        .and_then(|at| at.elapsed().ok())
+                        .map(|age| age.as_secs()),
+                )
+            }
+            None => (None, None, None),
+        };
+        let local_tip_height = match self.spv_height.load(Ordering::Relaxed) {
+            0 => None,
+            height => Some(height),
+        };
+        let last_sync_age =
+            self.metrics.lock()?.last_sync_timestamp.map(|at| now.saturating_sub(at));
+        let sync_backlog =
+            self.subscriptions.as_ref().map_or(0, |subscriptions| subscriptions.pending()) as u64;
+        let signblock_status = self.signblock.lock()?.as_ref().map(|signblock| signblock.status);
+
+        let mut reasons = vec![];
+        if !syncing {
+            reasons.push(HealthReason::NotSyncing);
+        } else {
+            if !connected {
+                reasons.push(HealthReason::ServerUnreachable);
+            }
+            // Managed sessions hear from the server only when synced
+            let sync_interval = self.network.sync_interval.unwrap_or(1) as u64;
+            let silent_after = HEALTH_SILENT_SECS.max(2 * sync_interval);
+            match last_message_age {
+                Some(age) if age > HEALTH_LOST_SECS => reasons.push(HealthReason::ServerLost),
+                Some(age) if age > silent_after => reasons.push(HealthReason::ServerSilent),
+                _ => (),
+            }
+            if last_sync_age.is_none() {
+                reasons.push(HealthReason::NotSynced);
+            }
+        }
+        if let (Some(local), Some(server)) = (local_tip_height, server_tip_height) {
+            if local + HEALTH_MAX_TIP_LAG < server {
+                reasons.push(HealthReason::TipBehind);
+            }
+        }
+        if cross_validation_valid == Some(false) {
+            reasons.push(HealthReason::CrossValidationFailed);
+        }
+        if sync_backlog > HEALTH_MAX_BACKLOG {
+            reasons.push(HealthReason::SyncBacklog);
+        }
+        if signblock_status == Some(SignblockStatus::Invalid) {
+            reasons.push(HealthReason::SignblockInvalid);
+        }
 
-                        round = round.wrapping_add(1);
-                    }
+        Ok(SessionHealth {
+            status: reasons.iter().map(HealthReason::status).max().unwrap_or(HealthStatus::Healthy),
+            reasons,
+            connected,
+            last_message_age,
+            server_tip_height,
+            local_tip_height,
+            cross_validation_valid,
+            store_flush_age,
+            last_sync_age,
+            sync_backlog,
+        })
+    }
+
+    /// Stop the threads and close the connections to the server, for instance
+    /// while the app is suspended, keeping the session logged in
+    ///
+    /// The store is flushed, so the app can be killed while paused. Meanwhile
+    /// calls fail with [`Error::SessionPaused`], or are served from the store
+    /// if `opt.serve_cache`.
+    pub fn pause(&mut self, opt: &PauseOpt) -> Result<(), Error> {
+        let was_syncing = match &self.paused {
+            Some(paused) => paused.was_syncing,
+            None => {
+                let was_syncing = self.user_wants_to_sync.swap(false, Ordering::Relaxed);
+                self.join_threads();
+                if let Ok(store) = self.store() {
+                    store.write()?.flush()?;
                 }
-            });
-            self.handles.push(headers_handle);
+                was_syncing
+            }
+        };
+        self.paused = Some(Paused {
+            serve_cache: opt.serve_cache,
+            was_syncing,
+        });
+        Ok(())
+    }
+
+    /// Undo [`Self::pause`], reconnecting to the server if the session was
+    /// connected, the wallet is then synced from where it was left
+    pub fn resume(&mut self) -> Result<(), Error> {
+        if let Some(paused) = self.paused.take() {
+            if paused.was_syncing {
+                self.user_wants_to_sync.store(true, Ordering::Relaxed);
+                if self.is_initialized {
+                    self.start_threads()?;
+                }
+            }
         }
+        Ok(())
+    }
 
-        let mut syncer = Syncer {
-            accounts: self.accounts.clone(),
-            store: self.store()?,
-            master_blinding: master_blinding.clone(),
-            network: self.network.clone(),
-            recent_spent_utxos: self.recent_spent_utxos.clone(),
-            gap_limit: self.gap_limit,
-            synced_accounts: HashSet::new(),
-            user_wants_to_sync: self.user_wants_to_sync.clone(),
+    /// Fail if `method` can't be called while the session is paused or
+    /// after it has been stopped
+    pub fn check_paused(&self, method: &str) -> Result<(), Error> {
+        ensure!(!self.closed, Error::SessionClosed);
+        match &self.paused {
+            Some(paused) if !paused.serve_cache => match method {
+                "pause" | "resume" | "disconnect" => Ok(()),
+                _ => Err(Error::SessionPaused),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// A client of the electrum server, unless the session is paused, shared
+    /// with the other sessions of the manager if any
+    fn server_client(&self) -> Result<Arc<Client>, Error> {
+        ensure!(self.paused.is_none(), Error::SessionPaused);
+        match &self.manager {
+            Some(manager) => {
+                manager.client(&self.url, self.proxy.as_deref(), &Resolver::new(&self.network))
+            }
+            None => {
+                let resolver = Resolver::new(&self.network);
+                Ok(Arc::new(self.url.build_client(self.proxy.as_deref(), None, &resolver)?))
+            }
+        }
+    }
+
+    /// Sync the session with the threads and the server connections of
+    /// `manager` instead of its own, it must be called before `connect`
+    pub fn set_session_manager(&mut self, manager: &SessionManager) {
+        self.manager = Some(manager.shared());
+    }
+
+    /// Join the background threads, waiting for them at most [`JOIN_TIMEOUT`]
+    ///
+    /// Returns the names of the threads that panicked or didn't stop in time,
+    /// the latter are left running detached.
+    pub fn join_threads(&mut self) -> Vec<String> {
+        if let (Some(manager), Some(id)) = (&self.manager, self.managed_sync.take()) {
+            manager.unregister(id);
+        }
+        let deadline = Instant::now() + JOIN_TIMEOUT;
+        let mut failed = vec![];
+        for handle in self.handles.drain(..) {
+            let name = handle.thread().name().unwrap_or("unnamed").to_string();
+            while !handle.is_finished() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(50));
+            }
+            if !handle.is_finished() {
+                warn!("thread {} did not stop in {:?}", name, JOIN_TIMEOUT);
+                failed.push(name);
+            } else if handle.join().is_err() {
+                warn!("thread {} panicked", name);
+                failed.push(name);
+            }
+        }
+        failed
+    }
+
+    /// Stop the session for good
+    ///
+    /// The background threads are signaled to stop and joined, which closes
+    /// their connections to the server and with them the subscriptions, then
+    /// the store is flushed. Afterwards every call fails with
+    /// [`Error::SessionClosed`]. Fails with [`Error::ThreadsNotStopped`] if
+    /// some threads panicked or didn't stop within [`JOIN_TIMEOUT`], the store
+    /// is flushed anyway.
+    pub fn stop(&mut self) -> Result<(), Error> {
+        ensure!(!self.closed, Error::SessionClosed);
+        self.closed = true;
+        let was_syncing = self.paused.take().map(|paused| paused.was_syncing).unwrap_or(false);
+        let was_syncing = self.user_wants_to_sync.swap(false, Ordering::Relaxed) || was_syncing;
+        let not_stopped = self.join_threads();
+        if let Ok(store) = self.store() {
+            store.write()?.flush()?;
+        }
+        if was_syncing {
+            self.notify.network(State::Disconnected, State::Disconnected);
+        }
+        ensure!(not_stopped.is_empty(), Error::ThreadsNotStopped(not_stopped));
+        Ok(())
+    }
+
+    pub fn state_updater(&self) -> Result<StateUpdater, Error> {
+        Ok(StateUpdater {
+            current: self.last_network_call_succeeded.clone(),
+            last_answer: self.last_answer.clone(),
+            notify: self.notify.clone(),
+        })
+    }
+
+    pub fn start_threads(&mut self) -> Result<(), Error> {
+        if !self.user_wants_to_sync.load(Ordering::Relaxed) {
+            return Err(Error::Generic("connect must be called before start_threads".into()));
+        }
+
+        if self.handles.len() > 0 || self.managed_sync.is_some() {
+            // Threads are already running
+            return Ok(());
+        }
+
+        let master_blinding = if self.network.liquid {
+            let master_blinding = self.store()?.read()?.cache.master_blinding.clone();
+            if master_blinding.is_none() {
+                return Err(Error::MissingMasterBlindingKey);
+            }
+            master_blinding
+        } else {
+            None
         };
 
-        let tipper = Tipper {
-            store: self.store()?,
-            network: self.network.clone(),
+        {
+            let store = self.store()?;
+            let store_read = store.read()?;
+            // Do not notify a block if we haven't fetched one yet
+            if let Some((tip_height, tip_header)) = &store_read.cache.tip_ {
+                self.notify.block(*tip_height, tip_header, false, None);
+            }
+        };
+
+        info!(
+            "building client, url {}, proxy {}",
+            self.url.url(),
+            self.proxy.as_ref().unwrap_or(&"".to_string())
+        );
+
+        let wallet_birthday = self.resolve_wallet_birthday().unwrap_or_else(|e| {
+            warn!("cannot resolve the wallet birthday {:?}", e);
+            None
+        });
+        let birthday_height = wallet_birthday.as_ref().and_then(|birthday| birthday.height);
+
+        // Managed sessions get the fee estimates on request and don't verify
+        // transactions, not to have threads and connections of their own
+        if self.manager.is_none() {
+            self.start_fee_and_headers_threads(birthday_height)?;
+        }
+
+        let subscriptions = Arc::new(listener::Subscriptions::default());
+        self.subscriptions = Some(subscriptions.clone());
+        let sync_loop = SyncLoop {
+            syncer: Syncer {
+                wallet_hash_id: self.wallet_hash_id.clone().unwrap_or_default(),
+                accounts: self.accounts.clone(),
+                store: self.store()?,
+                master_blinding: master_blinding.clone(),
+                network: self.network.clone(),
+                recent_spent_utxos: self.recent_spent_utxos.clone(),
+                gap_limit: self.gap_limit,
+                synced_accounts: HashSet::new(),
+                user_wants_to_sync: self.user_wants_to_sync.clone(),
+                subscriptions: subscriptions.clone(),
+                tx_fetcher: Arc::new(fetcher::TxFetcher::default()),
+                xr_cache: self.xr_cache.clone(),
+                price_source: self.price_source(None)?,
+                ignore_below: wallet_birthday
+                    .filter(|birthday| birthday.asserted)
+                    .and_then(|birthday| birthday.height)
+                    .unwrap_or(0),
+                server: self.url.url().to_string(),
+            },
+            tipper: Tipper {
+                store: self.store()?,
+                network: self.network.clone(),
+                network_time: self.network_time.clone(),
+                announced: BTreeMap::new(),
+                signblock_checker: match self.network.id() {
+                    NetworkId::Elements(network) => {
+                        SignblockChecker::new(network, self.network.federation_pubkeys.as_deref())?
+                    }
+                    NetworkId::Bitcoin(_) => None,
+                },
+                signblock: self.signblock.clone(),
+            },
+            notify: self.notify.clone(),
+            // Only the syncer thread is responsible to send network notification due for the state
+            // of the electrum server. This is to avoid intermittent connect/disconnect if one endpoint
+            // works while another don't. Once we categorize the disconnection by endpoint we can
+            // monitor state of every network call.
+            state_updater: self.state_updater()?,
+            first_sync: self.first_sync.clone(),
+            last_statuses: ScriptStatuses::new(),
+            txs_to_notify: vec![],
+            reuses_to_notify: vec![],
+            reorgs_to_notify: vec![],
+            payments_to_notify: vec![],
+            sync_again: false,
+            metrics: self.metrics.clone(),
         };
 
-        info!("login STATUS block:{:?} tx:{}", self.block_status()?, self.tx_status()?);
+        info!("login STATUS block:{:?} tx:{}", self.get_block_status()?, self.tx_status()?);
+
+        if let Some(manager) = &self.manager {
+            // Managed sessions are not woken by notifications
+            let sync_interval = self.network.sync_interval.unwrap_or(1);
+            let interval = Duration::from_secs(sync_interval as u64);
+            let id = manager.register(&self.url, self.proxy.as_deref(), sync_loop, interval)?;
+            self.managed_sync = Some(id);
+            return Ok(());
+        }
 
+        let sync_interval = self.network.sync_interval.unwrap_or(DEFAULT_SYNC_INTERVAL);
         let user_wants_to_sync = self.user_wants_to_sync.clone();
-        let notify = self.notify.clone();
         let url = self.url.clone();
         let proxy = self.proxy.clone();
-
-        // Only the syncer thread is responsible to send network notification due for the state
-        // of the electrum server. This is to avoid intermittent connect/disconnect if one endpoint
-        // works while another don't. Once we categorize the disconnection by endpoint we can
-        // monitor state of every network call.
-        let state_updater = self.state_updater()?;
-        let first_sync = self.first_sync.clone();
-
-        let syncer_tipper_handle = thread::spawn(move || {
-            info!("starting syncer & tipper thread");
-
-            let mut txs_to_notify = vec![];
-
-            // electrum_client::Client stores the last electrum_client::ScriptStatus
-            // for each script it has subscribed to, however to access it we have
-            // to use `script_pop` which removes the status from the Client internal
-            // storage. OTOH we need to remember the last script status corresponding
-            // to a script, since it is needed to determine if the script had a
-            // transaction and if its status has changed w.r.t. to the cached one.
-            // So we store the last statuses for each script in this map.
-            let mut last_statuses = ScriptStatuses::new();
-
-            let mut client = loop {
-                // In theory this loop is superfluous, because the client is created at the
-                // beginning of the next loop before being used, however, rust compiler thinks
-                // it could be not initialized so we need to initialize it.
-                match url.build_client(proxy.as_deref(), None) {
-                    Ok(new_client) => break new_client,
-                    Err(_) => {
-                        if wait_or_close(&user_wants_to_sync, sync_interval) {
-                            // The thread needs to stop when `user_wants_to_sync` is false.
-                            // below this is done by just breaking from the main loop,
-                            // but here we are out of the loop so we return.
-                            // (If you start the threads without connection you are stuck in this
-                            // loop so it must be handled)
-                            info!(
+        let resolver = Resolver::new(&self.network);
+        let last_answer = self.last_answer.clone();
+        let mut sync_loop = sync_loop;
+
+        let syncer_tipper_handle =
+            thread::Builder::new().name("syncer".into()).spawn(move || {
+                info!("starting syncer & tipper thread");
+
+                let mut client = loop {
+                    // In theory this loop is superfluous, because the client is created at the
+                    // beginning of the next loop before being used, however, rust compiler thinks
+                    // it could be not initialized so we need to initialize it.
+                    match url.build_client(proxy.as_deref(), None, &resolver) {
+                        Ok(new_client) => break Arc::new(new_client),
+                        Err(_) => {
+                            if wait_or_close(&user_wants_to_sync, RECONNECT_INTERVAL) {
+                                // The thread needs to stop when `user_wants_to_sync` is false.
+                                // below this is done by just breaking from the main loop,
+                                // but here we are out of the loop so we return.
+                                // (If you start the threads without connection you are stuck in this
+                                // loop so it must be handled)
+                                info!(
                                 "closing syncer & tipper thread by breaking build client attempts"
                             );
-                            return;
+                                return;
+                            }
                         }
-                    }
+                    };
                 };
-            };
-
-            let mut avoid_first_wait = true;
-            loop {
-                let is_connected = state_updater.current.load(Ordering::Relaxed);
-                debug!("loop start is_connected:{is_connected}");
 
-                if avoid_first_wait {
-                    avoid_first_wait = false;
-                } else if wait_or_close(&user_wants_to_sync, sync_interval) {
-                    info!("closing syncer & tipper thread");
-                    break;
+                // Checked here rather than at login, not to wait for the server
+                if let Err(e) = check_server_genesis(&client, &sync_loop.syncer.network) {
+                    warn!("not syncing: {}", e);
+                    return;
                 }
 
-                if !is_connected {
-                    match url.build_client(proxy.as_deref(), None) {
-                        Ok(new_client) => client = new_client,
-                        Err(e) => {
-                            warn!("cannot build client {e:?}");
-                            continue;
-                        }
+                let subscriptions = sync_loop.syncer.subscriptions.clone();
+                let (waker, wakeups) = std::sync::mpsc::channel();
+                let listen = |client: &Arc<Client>| {
+                    listener::Listener::spawn(
+                        client.clone(),
+                        subscriptions.clone(),
+                        waker.clone(),
+                        user_wants_to_sync.clone(),
+                        last_answer.clone(),
+                    )
+                };
+                let mut listener = listen(&client);
+
+                let mut avoid_first_wait = true;
+                loop {
+                    let is_connected = sync_loop.state_updater.current.load(Ordering::Relaxed);
+                    debug!("loop start is_connected:{is_connected}");
+
+                    // Woken by the server notifications, the timer is a safety net
+                    let timeout = if is_connected {
+                        Duration::from_secs(sync_interval as u64)
+                    } else {
+                        Duration::from_secs(RECONNECT_INTERVAL as u64)
                     };
-                }
-
-                let tip_before_sync = match tipper.server_tip(&client) {
-                    Ok(height) => height,
-                    Err(Error::Common(BtcEncodingError(_)))
-                    | Err(Error::Common(ElementsEncodingError(_))) => {
-                        // We aren't able to decode the blockheaders returned by the server,
-                        // do not sync further.
+                    if avoid_first_wait || std::mem::take(&mut sync_loop.sync_again) {
+                        avoid_first_wait = false;
+                    } else if listener::wait_wakeup(&wakeups, &user_wants_to_sync, timeout) {
+                        info!("closing syncer & tipper thread");
                         break;
                     }
-                    Err(e) => {
-                        state_updater.update_if_needed(false);
-                        warn!("exception in tipper {e:?}");
-                        continue;
-                    }
-                };
-
-                let first_sync_bool = first_sync.load(Ordering::Relaxed);
-                match syncer.sync(&client, &mut last_statuses, first_sync_bool) {
-                    Ok(sync_result) => {
-                        state_updater.update_if_needed(true);
-                        // Skip sending transaction notifications if it's the
-                        // first call to sync. This allows us to _not_ notify
-                        // transactions that were sent or received before
-                        // login.
-                        if first_sync_bool {
-                            info!("first sync completed");
-                        } else {
-                            txs_to_notify.extend(sync_result.tx_ntfs);
-                        }
-                        first_sync.store(false, Ordering::Relaxed);
 
-                        for pointer in sync_result.accounts {
-                            if syncer.synced_accounts.insert(pointer) {
-                                // First sync loop for this account, notify the caller that the
-                                // transactions received offline have been synced.
-                                notify.subaccount_synced(pointer);
+                    if !is_connected {
+                        match url.build_client(proxy.as_deref(), None, &resolver) {
+                            Ok(new_client) => {
+                                client = Arc::new(new_client);
+                                listener.stop();
+                                subscriptions.clear();
+                                listener = listen(&client);
                             }
-                        }
+                            Err(e) => {
+                                warn!("cannot build client {e:?}");
+                                continue;
+                            }
+                        };
                     }
-                    Err(Error::UserDoesntWantToSync) => {
-                        info!("{}", Error::UserDoesntWantToSync);
+
+                    if !sync_loop.step(&client) {
                         break;
                     }
-                    Err(e) => {
-                        state_updater.update_if_needed(false);
-                        warn!("Error during sync, {:?}", e);
-                        continue;
-                    }
                 }
+                listener.stop();
+            })?;
+        self.handles.push(syncer_tipper_handle);
 
-                match tipper.pop_tip(&client) {
-                    // If a block arrives while we are syncing
-                    // transactions, transactions might be returned as
-                    // unconfirmed even if they belong to the newly
-                    // notified block. Sync again to ensure
-                    // consistency.
-                    Ok(true) => continue,
-                    Err(_) => continue,
-                    _ => (),
-                }
+        Ok(())
+    }
 
-                if let Ok(Some(HeightHeader {
-                    height,
-                    header,
-                })) =
-                    tipper.update_cache_if_needed(tip_before_sync.height, tip_before_sync.header)
-                {
-                    notify.block_from_header(height, &header);
+    /// Start the thread getting the fee estimates and, if SPV is enabled,
+    /// the one downloading and verifying the headers, from the checkpoint
+    /// below `birthday_height` if any
+    fn start_fee_and_headers_threads(&mut self, birthday_height: Option<u32>) -> Result<(), Error> {
+        // Not to wait for the server, nothing is asked to it before the threads start
+        let fee_url = self.url.clone();
+        let fee_proxy = self.proxy.clone();
+        let fee_resolver = Resolver::new(&self.network);
+        let fee_store = self.store()?;
+        let fee_fetched_at = self.fee_fetched_at.clone();
+        let min_rate = self.network.policy().min_fee_rate;
+        let fee_handle = thread::Builder::new().name("fee".into()).spawn(move || {
+            let fee_client = match fee_url.build_client(fee_proxy.as_deref(), None, &fee_resolver) {
+                Ok(fee_client) => fee_client,
+                Err(e) => {
+                    warn!("can't build fee client {:?}", e);
+                    return;
+                }
+            };
+            match try_get_fee_estimates(&fee_client, min_rate) {
+                Ok(fee_estimates) => {
+                    fee_store
+                        .write()
+                        .unwrap()
+                        .set_fee_estimates(fee_estimates, util::now() / 1_000_000);
+                    let mut fee_fetched_at = fee_fetched_at.lock().unwrap();
+                    *fee_fetched_at = Some(Instant::now());
                 }
-                while let Some(ntf) = txs_to_notify.pop() {
-                    info!("New tx notification: {}", ntf.txid);
-                    notify.updated_txs(&ntf);
+                Err(e) => {
+                    warn!("can't update fee estimates {:?}", e)
                 }
-            }
-        });
-        self.handles.push(syncer_tipper_handle);
+            };
+        })?;
+        self.handles.push(fee_handle);
+
+        if self.network.spv_enabled.unwrap_or(false) {
+            self.spv_running.store(true, Ordering::Relaxed);
+            self.start_headers_thread(birthday_height)?;
+        }
 
         Ok(())
     }
 
-    pub fn get_receive_address(&self, opt: &GetAddressOpt) -> Result<AddressPointer, Error> {
-        debug!("get_receive_address {:?}", opt);
-        let address = self.get_account(opt.subaccount)?.get_next_address(
-            opt.is_internal.unwrap_or(false),
-            opt.ignore_gap_limit.unwrap_or(false),
-            self.gap_limit,
-        )?;
+    /// Start the thread downloading the headers and verifying the wallet
+    /// transactions, running until the session disconnects or SPV is
+    /// disabled, see [`Self::set_spv_enabled`]
+    fn start_headers_thread(&mut self, birthday_height: Option<u32>) -> Result<(), Error> {
+        let checker = match self.network.id() {
+            NetworkId::Bitcoin(network) => {
+                let window = self.network.headers_window.unwrap_or(DEFAULT_HEADERS_WINDOW);
+                let state_dir = &self.network.state_dir;
+                let checkpoints = &self.network.headers_checkpoints;
+                let checkpoint = birthday_height
+                    .and_then(|height| checkpoint_below(network, checkpoints, height));
+                let mut chain = match checkpoint {
+                    Some(checkpoint) => HeadersChain::from_checkpoint(
+                        state_dir,
+                        network,
+                        window,
+                        checkpoint,
+                        |height| Ok(self.server_client()?.block_header(height as usize)?),
+                    )
+                    .or_else(|e| {
+                        warn!("cannot start the headers at {}: {:?}", checkpoint.0, e);
+                        HeadersChain::with_window(state_dir, network, window)
+                    })?,
+                    None => HeadersChain::with_window(state_dir, network, window)?,
+                };
+                chain.add_checkpoints(checkpoints);
+                chain.set_network_time(self.network_time.clone());
+                ChainOrVerifier::Chain(chain)
+            }
+            NetworkId::Elements(network) => {
+                let verifier = Verifier::new(network);
+                ChainOrVerifier::Verifier(verifier)
+            }
+        };
+
+        let cross_validator =
+            SpvCrossValidator::from_network(&self.network, &self.proxy, self.timeout)?;
+
+        let mut headers = Headers {
+            store: self.store()?,
+            checker,
+            cross_validator,
+            signblock: self.signblock.clone(),
+        };
+
+        let headers_url = self.url.clone();
+        let proxy = self.proxy.clone();
+        let resolver = Resolver::new(&self.network);
+        let notify_blocks = self.notify.clone();
+        let chunk_size = DIFFCHANGE_INTERVAL as usize;
+        let user_wants_to_sync = self.user_wants_to_sync.clone();
+        let spv_running = self.spv_running.clone();
+        let running = move || {
+            user_wants_to_sync.load(Ordering::Relaxed) && spv_running.load(Ordering::Relaxed)
+        };
+        let max_reorg_blocks = self.network.max_reorg_blocks.unwrap_or(144);
+        let spv_height = self.spv_height.clone();
+        let wallet_hash_id = self.wallet_hash_id.clone().unwrap_or_default();
+        let metrics = self.metrics.clone();
+
+        let headers_handle = thread::Builder::new().name("headers".into()).spawn(move || {
+            info!("starting headers thread");
+            let mut round = 0u8;
+
+            'outer: loop {
+                if wait_while(&running, 7) {
+                    info!("closing headers thread");
+                    break;
+                }
+                let mut _lock;
+                if let ChainOrVerifier::Chain(chain) = &headers.checker {
+                    _lock = HEADERS_FILE_MUTEX
+                        .get(&chain.network)
+                        .expect("unreachable because map populate with every enum variants")
+                        .lock()
+                        .unwrap();
+                }
+
+                if let Ok(client) = headers_url.build_client(proxy.as_deref(), None, &resolver) {
+                    let span = timed(info_span!(
+                        "spv_round",
+                        wallet_hash_id = %redacted(&wallet_hash_id),
+                        round,
+                        headers = Empty,
+                        proofs = Empty,
+                        elapsed_ms = Empty
+                    ));
+                    let mut headers_total = 0;
+                    loop {
+                        if !running() {
+                            info!("closing headers thread");
+                            break 'outer;
+                        }
+                        match headers.ask(chunk_size, &client) {
+                            Ok(headers_found) => {
+                                headers_total += headers_found;
+                                if let Ok(mut metrics) = metrics.lock() {
+                                    metrics.headers_downloaded += headers_found as u64;
+                                }
+                                span.record("headers", headers_total as u64);
+                                if headers_found < chunk_size {
+                                    break;
+                                }
+                            }
+                            Err(Error::InvalidHeaders) => {
+                                warn!("invalid headers");
+                                // this should handle reorgs and also broke IO writes update
+                                headers.store.write().unwrap().cache.txs_verif.clear();
+                                if let Err(e) = headers.remove(max_reorg_blocks) {
+                                    warn!("failed removing headers: {:?}", e);
+                                    break;
+                                }
+                                // XXX clear affected blocks/txs more surgically?
+                            }
+                            Err(Error::Common(BtcEncodingError(_)))
+                            | Err(Error::Common(ElementsEncodingError(_))) => {
+                                // We aren't able to decode the blockheaders returned by the server,
+                                // do not sync headers further.
+                                break 'outer;
+                            }
+                            Err(e) => {
+                                warn!("error while asking headers {}", e);
+                                thread::sleep(Duration::from_millis(500));
+                            }
+                        }
+                    }
+                    // Other chains on the file, like the ones of the SPV api, see the
+                    // headers once caught up
+                    if let ChainOrVerifier::Chain(chain) = &mut headers.checker {
+                        if let Err(e) = chain.flush() {
+                            warn!("cannot write the headers {:?}", e);
+                        }
+                        spv_height.store(chain.height(), Ordering::Relaxed);
+                    }
+
+                    match headers.get_proofs(&client) {
+                        Ok(found) => {
+                            span.record("proofs", found as u64);
+                            if found > 0 {
+                                notify_blocks.spv_status(found);
+                            }
+                        }
+                        Err(e) => warn!("error in getting proofs {:?}", e),
+                    }
+
+                    if round % CROSS_VALIDATION_RATE == 0 {
+                        let status_changed = headers.cross_validate();
+                        if status_changed {
+                            // TODO: improve block notification
+                            if let Ok(store_read) = headers.store.read() {
+                                if let Some((tip_height, tip_header)) = &store_read.cache.tip_ {
+                                    notify_blocks.block(*tip_height, tip_header, false, None);
+                                }
+                            }
+                        }
+                    }
+
+                    round = round.wrapping_add(1);
+                }
+            }
+        })?;
+        self.handles.push(headers_handle);
+
+        Ok(())
+    }
+
+    pub fn get_receive_address(&self, opt: &GetAddressOpt) -> Result<AddressPointer, Error> {
+        debug!("get_receive_address {:?}", opt);
+        let account = self.get_account(opt.subaccount)?;
+        let expected_payment = self.expected_payment(opt)?;
+        let mut address = account.get_next_address(
+            opt.is_internal.unwrap_or(false),
+            opt.ignore_gap_limit.unwrap_or(false),
+            self.gap_limit,
+        )?;
+        if opt.confirm_on_device.unwrap_or(false) {
+            let signer = self.signer.clone().ok_or(Error::MissingSigner)?;
+            confirm_address(
+                signer,
+                DerivationPath::from(address.user_path.clone()),
+                account.script_type(),
+                self.network.id(),
+                &address.address,
+                DISPLAY_ADDRESS_TIMEOUT,
+            )?;
+            address.device_confirmed = true;
+        }
+        if let Some(payment) = expected_payment {
+            self.store()?.write()?.insert_expected_payment(ExpectedPayment {
+                address: address.address.clone(),
+                pointer: address.pointer,
+                ..payment
+            })?;
+        }
         debug!("get_address {:?}", address);
         Ok(address)
     }
 
-    pub fn get_previous_addresses(
+    /// The payment expected by `opt`, without its address yet, None if it
+    /// does not expect one
+    fn expected_payment(&self, opt: &GetAddressOpt) -> Result<Option<ExpectedPayment>, Error> {
+        let satoshi = match opt.expected_satoshi {
+            Some(satoshi) => satoshi,
+            None => {
+                ensure!(
+                    opt.expected_asset.is_none() && opt.expiry_seconds.is_none(),
+                    Error::InvalidAmount
+                );
+                return Ok(None);
+            }
+        };
+        ensure!(
+            !opt.is_internal.unwrap_or(false),
+            Error::Generic("payments can be expected to receive addresses only".into())
+        );
+        amount::check_send(satoshi, 0)?;
+        validate_asset_filter(self.network.id(), opt.expected_asset.as_deref())?;
+        let now = util::now() / 1_000_000;
+        Ok(Some(ExpectedPayment {
+            subaccount: opt.subaccount,
+            address: String::new(),
+            pointer: 0,
+            satoshi: Some(satoshi),
+            asset_id: opt.expected_asset.clone().unwrap_or_else(|| self.policy_asset()),
+            created_at: now,
+            expires_at: opt.expiry_seconds.map(|expiry| now.saturating_add(expiry)),
+            status: PaymentStatus::Pending,
+            txid: None,
+            received_satoshi: Some(0),
+        }))
+    }
+
+    /// The payments expected to receive addresses, oldest first
+    ///
+    /// The pending ones are always returned, the expired and the paid ones
+    /// only if requested.
+    pub fn get_expected_payments(
+        &self,
+        opt: &GetExpectedPaymentsOpt,
+    ) -> Result<ExpectedPayments, Error> {
+        let now = util::now() / 1_000_000;
+        let store = self.store()?;
+        let store_read = store.read()?;
+        let payments = store_read
+            .expected_payments()
+            .filter(|payment| opt.subaccount.map_or(true, |num| num == payment.subaccount))
+            .map(|payment| ExpectedPayment {
+                status: payment.status_at(now),
+                ..payment.clone()
+            })
+            .filter(|payment| match payment.status {
+                PaymentStatus::Pending => true,
+                PaymentStatus::Expired => opt.include_expired,
+                _ => opt.include_paid,
+            })
+            .collect();
+        Ok(ExpectedPayments {
+            payments,
+        })
+    }
+
+    pub fn get_previous_addresses(
+        &self,
+        opt: &GetPreviousAddressesOpt,
+    ) -> Result<PreviousAddresses, Error> {
+        self.get_account(opt.subaccount)?.get_previous_addresses(opt)
+    }
+
+    pub fn encrypt_with_pin(&self, details: &EncryptWithPinDetails) -> Result<PinData, Error> {
+        let plaintext = serde_json::to_vec(&details.plaintext)?;
+        self.pin_client()?.encrypt(&plaintext, &details.pin).map_err(Into::into)
+    }
+
+    /// Get the subaccount pointers/numbers from the store
+    ///
+    /// Multisig sessions receive the subaccount pointer from the server
+    /// and then get the xpubs for them from the signer. We need to allow
+    /// to do the same. So we fetch the subaccount pointers from the
+    /// persisted store and return them here.
+    pub fn get_subaccount_nums(&self) -> Result<Vec<u32>, Error> {
+        let mut account_nums = self.store()?.read()?.account_nums();
+        // For compatibility reason, account 0 must always be present
+        if !account_nums.contains(&0) {
+            // Insert it at the start to preserve sorting
+            account_nums.insert(0, 0);
+        }
+        Ok(account_nums)
+    }
+
+    pub fn get_subaccounts(&mut self) -> Result<Vec<AccountInfo>, Error> {
+        self.get_accounts()?.iter().map(|a| a.info()).collect()
+    }
+
+    pub fn get_accounts_settings(&mut self) -> Result<HashMap<u32, AccountSettings>, Error> {
+        Ok(self.store()?.read()?.get_accounts_settings().clone())
+    }
+
+    pub fn create_subaccount(&mut self, opt: CreateAccountOpt) -> Result<AccountInfo, Error> {
+        let store = self.store()?.clone();
+        let master_blinding = store.read()?.cache.master_blinding.clone();
+        let network = self.network.clone();
+        let mut accounts = self.accounts.write()?;
+
+        // Allow discovery of already created subaccounts
+        if opt.discovered {
+            if let Entry::Occupied(entry) = accounts.entry(opt.subaccount) {
+                store.write()?.account_cache_mut(opt.subaccount)?.bip44_discovered = opt.discovered;
+                return entry.get().info();
+            }
+        }
+
+        if !opt.allow_gaps {
+            // Check that the given subaccount number is the next available one for its script type.
+            let (script_type, _) = get_account_script_purpose(opt.subaccount)?;
+            let (last_account, next_account) =
+                get_last_next_account_nums(accounts.keys().copied().collect(), script_type);
+
+            if opt.subaccount != next_account {
+                // The subaccount already exists, or skips over the next available subaccount number
+                bail!(Error::InvalidSubaccount(opt.subaccount));
+            }
+            if let Some(last_account) = last_account {
+                // This is the next subaccount number, but the last one is still unused
+                let account = accounts
+                    .get(&last_account)
+                    .ok_or_else(|| Error::InvalidSubaccount(last_account))?;
+                if !opt.is_already_created && !account.has_transactions()? {
+                    bail!(Error::AccountGapsDisallowed);
+                }
+            }
+        }
+
+        let account = match accounts.entry(opt.subaccount) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let account = entry.insert(Account::new(
+                    network,
+                    self.master_xpub_fingerprint,
+                    opt.xpub, // account xpub
+                    master_blinding,
+                    store,
+                    opt.subaccount,
+                    opt.discovered,
+                )?);
+                if !opt.name.is_empty() {
+                    account.set_name(&opt.name)?;
+                }
+                account
+            }
+        };
+        if !opt.is_already_created {
+            self.notify.subaccount_new(opt.subaccount);
+        }
+        account.info()
+    }
+
+    /// The number, derivation path and extended public key of the next
+    /// multisig subaccount, Bitcoin only
+    fn next_multisig_account(&self) -> Result<(u32, DerivationPath, Xpub), Error> {
+        if self.network.liquid {
+            return Err(Error::Generic("multisig subaccounts are not supported on Liquid".into()));
+        }
+        let signer = self.signer.clone().ok_or(Error::MissingSigner)?;
+        let (_, subaccount) = get_last_next_account_nums(
+            self.accounts.read()?.keys().copied().collect(),
+            ScriptType::P2wsh,
+        );
+        let (_, path) = get_account_derivation(subaccount, self.network.id())?;
+        let xpub = signer.get_xpub(&path)?;
+        Ok((subaccount, path, xpub))
+    }
+
+    /// The key of the wallet to share with the cosigners of the next
+    /// multisig subaccount, to be put in its descriptor
+    pub fn get_multisig_key(&self) -> Result<MultisigKeyResult, Error> {
+        let (subaccount, path, xpub) = self.next_multisig_account()?;
+        Ok(MultisigKeyResult {
+            subaccount,
+            key: format!("[{}/{}]{}", self.master_xpub_fingerprint, path, xpub),
+        })
+    }
+
+    /// Create the next multisig subaccount from its `wsh(sortedmulti(..))`
+    /// descriptor, which must have the key of `get_multisig_key` among its
+    /// keys
+    pub fn create_multisig_subaccount(
+        &mut self,
+        opt: CreateMultisigAccountOpt,
+    ) -> Result<AccountInfo, Error> {
+        let (subaccount, path, xpub) = self.next_multisig_account()?;
+        // Fail before persisting a descriptor the subaccount can't be made of
+        Multisig::new(&opt.descriptor, &(self.master_xpub_fingerprint, path), &xpub)?;
+        let settings = AccountSettings {
+            name: opt.name.clone(),
+            multisig_descriptor: Some(opt.descriptor),
+            ..Default::default()
+        };
+        self.store()?.write()?.set_account_settings(subaccount, settings)?;
+        self.create_subaccount(CreateAccountOpt {
+            subaccount,
+            name: opt.name,
+            xpub,
+            discovered: false,
+            is_already_created: false,
+            allow_gaps: true,
+        })
+    }
+
+    /// The keys of the Green subaccount watched by `account_num`, if it is one
+    fn green_multisig_template(
+        &self,
+        account_num: u32,
+    ) -> Result<Option<GreenMultisigTemplate>, Error> {
+        let store = self.store()?;
+        let store_read = store.read()?;
+        let settings = store_read.get_account_settings(account_num);
+        Ok(settings.and_then(|settings| settings.green_multisig.clone()))
+    }
+
+    /// Create a subaccount watching a Green 2of2 or 2of3 subaccount from its
+    /// keys, reporting its balance and transactions, Bitcoin only.
+    ///
+    /// The subaccount can't be signed, see [`green`].
+    pub fn create_green_multisig_subaccount(
+        &mut self,
+        opt: CreateGreenMultisigAccountOpt,
+    ) -> Result<AccountInfo, Error> {
+        let network = match self.network.id() {
+            NetworkId::Bitcoin(network) => network,
+            NetworkId::Elements(_) => {
+                return Err(Error::Generic("green subaccounts are not supported on Liquid".into()))
+            }
+        };
+        // Fail before persisting keys the subaccount can't be made of
+        GreenMultisig::new(&opt.template, network)?;
+        let (_, subaccount) = get_last_next_account_nums(
+            self.accounts.read()?.keys().copied().collect(),
+            ScriptType::GreenMultisig,
+        );
+        let settings = AccountSettings {
+            name: opt.name.clone(),
+            green_multisig: Some(opt.template.clone()),
+            ..Default::default()
+        };
+        self.store()?.write()?.set_account_settings(subaccount, settings)?;
+        self.create_subaccount(CreateAccountOpt {
+            subaccount,
+            name: opt.name,
+            xpub: opt.template.user_xpub,
+            discovered: false,
+            is_already_created: false,
+            allow_gaps: true,
+        })
+    }
+
+    /// Create the recovery transactions of the utxos of the CSV addresses of
+    /// a Green subaccount, each sending a utxo to `address` signed by the
+    /// user key alone, to be mined once the CSV blocks have passed.
+    ///
+    /// The user stores them offline, creating them again when the utxos of
+    /// the subaccount change. The utxos too small to pay the fee of their
+    /// transaction are left out.
+    pub fn create_recovery_transactions(
+        &self,
+        opt: &CreateRecoveryTransactionsOpt,
+    ) -> Result<RecoveryTransactionsResult, Error> {
+        let account = self.get_account(opt.subaccount)?;
+        let green = account
+            .green()
+            .filter(|green| green.csv_blocks().is_some())
+            .ok_or_else(|| Error::Generic("the subaccount has no CSV addresses".into()))?;
+        let csv_blocks = green.csv_blocks().unwrap_or_default();
+        let script_pubkey =
+            address::parse_address(&opt.address, self.network.id())?.script_pubkey().into_bitcoin();
+        let dust_limit = self.network.policy().dust_limits.for_script(&script_pubkey);
+        let min_fee_rate = self.get_min_fee_rate()?;
+        let fee_rate = match opt.fee_rate {
+            Some(fee_rate) => fee_rate,
+            None => self.default_fee_rate()?,
+        };
+        ensure!(fee_rate >= min_fee_rate, Error::FeeRateBelowMinimum);
+
+        let block_height = self.get_block_height()?;
+        let store = self.store()?;
+        let store_read = store.read()?;
+        let acc_store = store_read.account_cache(opt.subaccount)?;
+        let mut transactions = vec![];
+        for outpoint in account.unspents()? {
+            let txo = account.txo(&outpoint, acc_store)?;
+            let (is_internal, pointer) = parse_path(acc_store.get_path(&txo.script_pubkey)?)?;
+            let outpoint = match (is_internal, outpoint) {
+                (true, BEOutPoint::Bitcoin(outpoint)) => outpoint,
+                // Only the internal chain has CSV addresses
+                _ => continue,
+            };
+            let tx = match green.recovery_transaction(
+                &opt.xprv,
+                pointer,
+                outpoint,
+                txo.satoshi,
+                script_pubkey.clone(),
+                fee_rate,
+            ) {
+                Ok(tx) if tx.output[0].value.to_sat() >= dust_limit => tx,
+                Ok(_) | Err(Error::InsufficientFundsForFee) => continue,
+                Err(e) => return Err(e),
+            };
+            let expiry_height = txo.height.filter(|height| *height > 0).map(|h| h + csv_blocks);
+            transactions.push(RecoveryTransaction {
+                txhash: outpoint.txid.to_string(),
+                pt_idx: outpoint.vout,
+                satoshi: txo.satoshi,
+                expiry_height,
+                is_expired: expiry_height.is_some_and(|height| height <= block_height + 1),
+                transaction: bitcoin::consensus::encode::serialize_hex(&tx),
+            });
+        }
+        transactions.sort_by(|a, b| (&a.txhash, a.pt_idx).cmp(&(&b.txhash, b.pt_idx)));
+        Ok(RecoveryTransactionsResult {
+            block_height,
+            transactions,
+        })
+    }
+
+    pub fn discover_subaccount(&self, opt: DiscoverAccountOpt) -> Result<bool, Error> {
+        discover_account(
+            &self.url,
+            self.proxy.as_deref(),
+            &Resolver::new(&self.network),
+            &opt.xpub,
+            opt.script_type,
+            self.gap_limit,
+        )
+    }
+
+    pub fn get_next_subaccount(&self, opt: GetNextAccountOpt) -> Result<u32, Error> {
+        let (_, next_account) = get_last_next_account_nums(
+            self.accounts.read()?.keys().copied().collect(),
+            opt.script_type,
+        );
+        Ok(next_account)
+    }
+
+    pub fn get_last_empty_subaccount(&self, opt: GetLastEmptyAccountOpt) -> Result<u32, Error> {
+        let (last_account, next_account) = get_last_next_account_nums(
+            self.accounts.read()?.keys().copied().collect(),
+            opt.script_type,
+        );
+        match last_account {
+            Some(last_account) => {
+                if self.get_account(last_account)?.info()?.bip44_discovered {
+                    Ok(next_account)
+                } else {
+                    Ok(last_account)
+                }
+            }
+            None => Ok(next_account),
+        }
+    }
+
+    pub fn get_block_height(&self) -> Result<u32, Error> {
+        Ok(self.store()?.read()?.cache.tip_height())
+    }
+
+    pub fn update_subaccount(&mut self, opt: UpdateAccountOpt) -> Result<bool, Error> {
+        self.get_account(opt.subaccount)?.set_settings(opt)
+    }
+
+    pub fn get_transactions(&self, opt: &GetTransactionsOpt) -> Result<TxsResult, Error> {
+        validate_asset_filter(self.network.id(), opt.asset_id.as_deref())?;
+        let mut txs = self.get_account(opt.subaccount)?.list_tx(opt)?;
+        let formatter = self.amount_formatter(&opt.asset_precisions);
+        let policy_asset = self.policy_asset();
+        for tx in txs.transactions.iter_mut() {
+            tx.amounts = formatter.format_balances(&tx.satoshi);
+            tx.fee_amount = formatter.format(tx.fee as i64, &policy_asset);
+            for output in tx.outputs.iter_mut() {
+                if !output.is_relevant {
+                    // Update the output with the information necessary for bumping
+                    if let Ok(data) = self.get_scriptpubkey_data(&output.script_pubkey) {
+                        // This is an output belonging to the wallet, but not to opt.subaccount
+                        output.subaccount = data.subaccount;
+                        output.pointer = data.pointer;
+                        output.is_internal = data.is_internal;
+                        output.address_type = data.address_type;
+                    }
+                }
+            }
+        }
+        Ok(txs)
+    }
+
+    /// The key of the policy asset amounts, "btc" on Bitcoin
+    fn policy_asset(&self) -> String {
+        match self.network.id() {
+            NetworkId::Bitcoin(_) => "btc".to_string(),
+            NetworkId::Elements(_) => self.network.policy_asset.clone().unwrap_or_default(),
+        }
+    }
+
+    /// The formatter of the returned amounts in the current `unit` setting
+    ///
+    /// The settings are read on each call, so that changing the unit takes
+    /// effect without logging in again.
+    fn amount_formatter(&self, asset_precisions: &HashMap<String, u8>) -> AmountFormatter {
+        let unit = self.get_settings().and_then(|s| s.unit.parse().ok()).unwrap_or(Unit::Btc);
+        AmountFormatter::new(unit, &self.policy_asset(), asset_precisions.clone())
+    }
+
+    /// The transactions whose memo contains `opt.query`, from the store only
+    pub fn search_transactions(
+        &self,
+        opt: &SearchTransactionsOpt,
+    ) -> Result<SearchTransactionsResult, Error> {
+        let mut transactions = vec![];
+        for account in self.get_accounts()? {
+            transactions.extend(account.search_tx(&opt.query)?.into_iter().map(|transaction| {
+                FoundTransaction {
+                    subaccount: account.num(),
+                    transaction,
+                }
+            }));
+        }
+        // Unconfirmed transactions have a zero height, ties broken like in list_tx
+        transactions.sort_by(|a, b| {
+            let key = |found: &FoundTransaction| match found.transaction.block_height {
+                0 => u32::MAX,
+                height => height,
+            };
+            key(b)
+                .cmp(&key(a))
+                .then_with(|| b.transaction.txhash.cmp(&a.transaction.txhash))
+                .then_with(|| a.subaccount.cmp(&b.subaccount))
+        });
+        Ok(SearchTransactionsResult {
+            transactions,
+        })
+    }
+
+    /// Write to `writer` the transactions of `opt.subaccount` created in the
+    /// given range, oldest first, see [`export`]
+    pub fn export_transactions<W: std::io::Write>(
+        &self,
+        opt: &ExportTransactionsOpt,
+        writer: &mut W,
+    ) -> Result<(), Error> {
+        let list_opt = GetTransactionsOpt {
+            count: usize::MAX,
+            subaccount: opt.subaccount,
+            created_at_from: opt.created_at_from,
+            created_at_to: opt.created_at_to,
+            ..Default::default()
+        };
+        let mut txs = self.get_account(opt.subaccount)?.list_tx(&list_opt)?.transactions;
+        txs.reverse();
+        let policy_asset = self.policy_asset();
+        let exported: Vec<_> = txs.iter().map(export::exported_transaction).collect();
+        export::write_transactions(&exported, opt.format, &policy_asset, writer)
+    }
+
+    /// Record the exchange rates of the confirmed transactions that have none,
+    /// as of their block time, from the historical rates of the price source
+    ///
+    /// Transactions whose rate cannot be obtained are left without one.
+    pub fn backfill_fiat_at_confirmation(&self) -> Result<BackfillFiatResult, Error> {
+        let pricing = self.get_settings().unwrap_or_default().pricing;
+        let currency = Currency::from_str(pricing.currency())?;
+        let pair = Pair::new(Currency::BTC, currency);
+        let source = self.price_source(None)?;
+
+        let list_opt = GetTransactionsOpt {
+            count: usize::MAX,
+            ..Default::default()
+        };
+        let mut pending = HashMap::new();
+        for account in self.get_accounts()? {
+            for tx in account.list_tx(&list_opt)?.transactions {
+                if tx.block_height > 0 && tx.fiat_at_confirmation.is_none() {
+                    pending.insert(tx.txhash, tx.created_at_ts / 1_000_000);
+                }
+            }
+        }
+
+        let mut backfilled = 0;
+        let total = pending.len();
+        for (txhash, timestamp) in pending {
+            let source = match source.as_deref() {
+                Some(source) => source,
+                None => break,
+            };
+            let ticker = match source.fetch_historical(pair, pricing.exchange(), timestamp) {
+                Ok(Some(ticker)) if ticker.rate.is_finite() && ticker.rate > 0.0 => ticker,
+                Ok(Some(ticker)) => {
+                    warn!("ignoring invalid exchange rate {:?}", ticker);
+                    continue;
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("cannot fetch the {} exchange rate of {}: {:?}", pair, txhash, e);
+                    continue;
+                }
+            };
+            let rate = ConfirmationRate {
+                currency: currency.to_string(),
+                rate: ticker.rate,
+            };
+            let txid = BETxid::from_hex(&txhash, self.network.id())?;
+            self.store()?.write()?.insert_confirmation_rates(&[txid], &rate)?;
+            backfilled += 1;
+        }
+        Ok(BackfillFiatResult {
+            backfilled,
+            missing: total - backfilled,
+        })
+    }
+
+    pub fn get_transaction_hex(&self, txid: &str) -> Result<String, Error> {
+        let opt = GetRawTransactionOpt {
+            txid: txid.to_string(),
+            cache: false,
+        };
+        Ok(self.get_raw_transaction(&opt)?.transaction)
+    }
+
+    /// The transaction `opt.txid`, from the cache if it's a wallet
+    /// transaction or was kept before, otherwise from the server.
+    ///
+    /// Fails with [`Error::TxNotFound`] if the server doesn't have it, and
+    /// with a connection error if it can't be reached.
+    pub fn get_raw_transaction(
+        &self,
+        opt: &GetRawTransactionOpt,
+    ) -> Result<GetRawTransactionResult, Error> {
+        let txid = BETxid::from_hex(&opt.txid, self.network.id())?;
+        let result = |tx: &BETransaction, source| GetRawTransactionResult {
+            txhash: txid.to_string(),
+            transaction: tx.serialize().to_lower_hex_string(),
+            source,
+        };
+        let store = self.store()?;
+        {
+            let store_read = store.read()?;
+            if let Ok(entry) = store_read.get_tx_entry(&txid) {
+                return Ok(result(&entry.tx, RawTransactionSource::Wallet));
+            }
+            if let Some(entry) = store_read.foreign_tx(&txid) {
+                return Ok(result(&entry.tx, RawTransactionSource::Foreign));
+            }
+        }
+
+        let raw = match self.server_client()?.transaction_get_raw(&txid.into_bitcoin()) {
+            Ok(raw) => raw,
+            Err(e @ electrum_client::Error::Protocol(_)) => {
+                info!("transaction {} not found on the server: {:?}", txid, e);
+                return Err(Error::TxNotFound(txid));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let tx = BETransaction::deserialize(&raw, self.network.id())?;
+        ensure!(tx.txid() == txid, Error::Generic(format!("server sent another tx for {}", txid)));
+        let mut store_write = store.write()?;
+        // A wallet transaction missing from the cache, eg. if the file of
+        // the transactions was lost, goes back with the others
+        if !store_write.restore_wallet_tx(&tx)? && opt.cache {
+            store_write.insert_foreign_tx(tx.clone())?;
+        }
+        Ok(result(&tx, RawTransactionSource::Server))
+    }
+
+    /// The transaction `txid` decoded and annotated with the wallet data.
+    ///
+    /// Transactions not belonging to the wallet are fetched from the server
+    /// and returned with `not_wallet_tx` set.
+    pub fn get_transaction_details(&self, txid: &str) -> Result<TransactionDetails, Error> {
+        let txid = BETxid::from_hex(txid, self.network.id())?;
+        let mut items = vec![];
+        for account in self.get_accounts()? {
+            if let Some(item) = account.get_tx_list_item(&txid)? {
+                items.push((account.num(), item));
+            }
+        }
+        if items.is_empty() {
+            return self.get_foreign_transaction_details(&txid);
+        }
+
+        // Each account marks as relevant only its own inputs and outputs
+        let (_, first) = &items[0];
+        let mut inputs = first.inputs.clone();
+        let mut outputs = first.outputs.clone();
+        for (_, item) in items.iter().skip(1) {
+            let elements =
+                inputs.iter_mut().zip(&item.inputs).chain(outputs.iter_mut().zip(&item.outputs));
+            for (inout, other) in elements {
+                if other.is_relevant {
+                    *inout = other.clone();
+                }
+            }
+        }
+
+        let store = self.store()?;
+        let store = store.read()?;
+        let txe = store.get_tx_entry(&txid)?;
+        let inputs = inputs
+            .into_iter()
+            .zip(txe.tx.previous_outputs())
+            .map(|(inout, outpoint)| {
+                let prev_tx = store.get_tx_entry(&outpoint.txid()).ok().map(|e| &e.tx);
+                tx_details_inout(inout, prev_tx, outpoint.vout(), Some(outpoint))
+            })
+            .collect();
+        let outputs = outputs
+            .into_iter()
+            .map(|inout| {
+                let vout = inout.pt_idx;
+                tx_details_inout(inout, Some(&txe.tx), vout, None)
+            })
+            .collect();
+        let block_height = first.block_height;
+        let confirmations = match block_height {
+            0 => 0,
+            h => (store.cache.tip_height() + 1).saturating_sub(h),
+        };
+
+        Ok(TransactionDetails {
+            txhash: txid.to_string(),
+            transaction: txe.tx.serialize().to_lower_hex_string(),
+            not_wallet_tx: false,
+            block_height,
+            confirmations,
+            fee: Some(first.fee),
+            memo: first.memo.clone(),
+            spv_verified: first.spv_verified.clone(),
+            inputs,
+            outputs,
+            subaccounts: items.iter().map(|(num, item)| (*num, item.satoshi.clone())).collect(),
+            transaction_vsize: first.transaction_vsize,
+            transaction_weight: first.transaction_weight,
+            broadcast: store.get_broadcast(&txid).cloned(),
+        })
+    }
+
+    fn get_foreign_transaction_details(&self, txid: &BETxid) -> Result<TransactionDetails, Error> {
+        let id = self.network.id();
+        let client = self.server_client()?;
+        let tx =
+            BETransaction::deserialize(&client.transaction_get_raw(&txid.into_bitcoin())?, id)?;
+
+        let prev_txids: Vec<Txid> =
+            tx.previous_output_txids().into_iter().map(|txid| txid.into_bitcoin()).collect();
+        let mut prev_txs = BETransactions::default();
+        for raw in client.batch_transaction_get_raw(&prev_txids)? {
+            let prev_tx = BETransaction::deserialize(&raw, id)?;
+            prev_txs.insert(prev_tx.txid(), prev_tx.into());
+        }
+
+        let relevant_inout = |inout: &mut GetTxInOut| {
+            if let Ok(data) = self.get_scriptpubkey_data(&inout.script_pubkey) {
+                inout.is_relevant = true;
+                inout.subaccount = data.subaccount;
+                inout.is_internal = data.is_internal;
+                inout.pointer = data.pointer;
+                inout.address_type = data.address_type;
+            }
+        };
+        let inputs: Vec<TxDetailsInOut> = tx
+            .previous_outputs()
+            .into_iter()
+            .enumerate()
+            .map(|(vin, outpoint)| {
+                let prev_tx = prev_txs.get(&outpoint.txid()).map(|e| &e.tx);
+                let mut inout = foreign_inout(prev_tx, outpoint.vout(), false, vin as u32, id);
+                relevant_inout(&mut inout);
+                tx_details_inout(inout, prev_tx, outpoint.vout(), Some(outpoint))
+            })
+            .collect();
+        let outputs: Vec<TxDetailsInOut> = (0..tx.output_len() as u32)
+            .map(|vout| {
+                let mut inout = foreign_inout(Some(&tx), vout, true, vout, id);
+                relevant_inout(&mut inout);
+                tx_details_inout(inout, Some(&tx), vout, None)
+            })
+            .collect();
+
+        let mut subaccounts: HashMap<u32, Balances> = HashMap::new();
+        for (inout, sign) in inputs.iter().map(|e| (e, -1)).chain(outputs.iter().map(|e| (e, 1))) {
+            if inout.inout.is_relevant {
+                let asset = inout.inout.asset_id.clone().unwrap_or_else(|| "btc".to_string());
+                let balance = subaccounts.entry(inout.inout.subaccount).or_default();
+                *balance.entry(asset).or_default() += sign * inout.inout.satoshi as i64;
+            }
+        }
+
+        let all_prevouts_known =
+            prev_txids.iter().all(|txid| prev_txs.contains_key(&(*txid).into()));
+        let fee = if tx.is_elements() || all_prevouts_known {
+            tx.fee(&prev_txs, &HashMap::new(), &self.network.policy_asset_id().ok()).ok()
+        } else {
+            None
+        };
+
+        // The height is in the history of any script of the transaction
+        let block_height = (0..tx.output_len() as u32)
+            .map(|vout| tx.output_script(vout))
+            .find(|script| !script.is_empty())
+            .map(|script| client.script_get_history(&script.into_bitcoin()))
+            .transpose()?
+            .and_then(|history| history.into_iter().find(|h| h.tx_hash == txid.into_bitcoin()))
+            .map_or(0, |h| h.height.max(0) as u32);
+        let confirmations = match block_height {
+            0 => 0,
+            h => (client.block_headers_subscribe_raw()?.height as u32 + 1).saturating_sub(h),
+        };
+        let memo = match self.store() {
+            Ok(store) => store.read()?.get_memo(txid).cloned().unwrap_or_default(),
+            Err(_) => String::new(),
+        };
+        let spv_verified = if block_height == 0 {
+            SPVVerifyTxResult::Unconfirmed
+        } else if self.network.spv_enabled.unwrap_or(false) {
+            SPVVerifyTxResult::NotVerified
+        } else {
+            SPVVerifyTxResult::Disabled
+        };
+        let weight = tx.get_weight();
+
+        Ok(TransactionDetails {
+            txhash: txid.to_string(),
+            transaction: tx.serialize().to_lower_hex_string(),
+            not_wallet_tx: true,
+            block_height,
+            confirmations,
+            fee,
+            memo,
+            spv_verified: spv_verified.to_string(),
+            inputs,
+            outputs,
+            subaccounts,
+            transaction_vsize: weight_to_vsize(weight),
+            transaction_weight: weight,
+            broadcast: None,
+        })
+    }
+
+    /// Sign a message with the key of an address of the wallet, derived from the
+    /// subaccount extended private key given by the caller
+    pub fn sign_message(&self, opt: &SignMessageOpt) -> Result<SignMessageResult, Error> {
+        let network = self.message_network()?;
+        let account = self.get_account(opt.subaccount)?;
+        let path = match (&opt.address, &opt.path) {
+            (Some(address), None) => {
+                let address = bitcoin::Address::from_str(address)?.require_network(network)?;
+                account.get_address_path(&BEAddress::Bitcoin(address))?
+            }
+            (None, Some(path)) => path.clone().into(),
+            _ => {
+                return Err(Error::Generic(
+                    "sign_message: either address or path is required".into(),
+                ))
+            }
+        };
+        let format =
+            opt.format.unwrap_or_else(|| MessageFormat::default_for(account.script_type()));
+        let signer = self.account_signer(&account, opt.xprv.as_ref())?;
+        let (signature, address) =
+            account.sign_message(signer.as_ref(), &path, &opt.message, format)?;
+        Ok(SignMessageResult {
+            signature,
+            address: address.to_string(),
+            format,
+        })
+    }
+
+    /// Verify a message signature for any address, in the legacy or BIP322 format
+    pub fn verify_message(&self, opt: &VerifyMessageOpt) -> Result<bool, Error> {
+        let network = self.message_network()?;
+        let address = bitcoin::Address::from_str(&opt.address)?.require_network(network)?;
+        Ok(message::verify_message(&address, &opt.message, &opt.signature))
+    }
+
+    /// Prove the ownership of utxos of a subaccount for proof-of-reserves,
+    /// signing a proof committing to a message, see [`reserves`]
+    pub fn create_ownership_proof(
+        &self,
+        opt: &CreateOwnershipProofOpt,
+    ) -> Result<OwnershipProof, Error> {
+        if let NetworkId::Elements(_) = self.network.id() {
+            return Err(Error::Generic("ownership proofs are not supported on Liquid yet".into()));
+        }
+        let account = self.get_account(opt.subaccount)?;
+        let signer = self.account_signer(&account, opt.xprv.as_ref())?;
+        let store = self.store()?;
+        let store_read = store.read()?;
+        let acc_store = store_read.account_cache(opt.subaccount)?;
+
+        let unspents = account.unspents()?;
+        let mut outpoints = vec![];
+        if opt.utxos.is_empty() {
+            outpoints.extend(unspents);
+            outpoints.sort_by_cached_key(|o| (o.txid().to_hex(), o.vout()));
+        }
+        for utxo in opt.utxos.iter() {
+            let outpoint = unspents
+                .iter()
+                .find(|o| o.txid().to_hex() == utxo.txhash && o.vout() == utxo.pt_idx)
+                .ok_or_else(|| {
+                    Error::Generic(format!(
+                        "{}:{} is not an unspent output of the subaccount",
+                        utxo.txhash, utxo.pt_idx
+                    ))
+                })?;
+            outpoints.push(outpoint.clone());
+        }
+        ensure!(!outpoints.is_empty(), Error::InsufficientFunds);
+        let mut utxos = vec![];
+        for outpoint in outpoints {
+            let satoshi = account.txo(&outpoint, acc_store)?.satoshi;
+            if let BEOutPoint::Bitcoin(outpoint) = outpoint {
+                utxos.push((outpoint, satoshi));
+            }
+        }
+
+        let mut proof = reserves::proof_psbt(&opt.message, &utxos)?;
+        let accounts = self.accounts.read()?;
+        let wallet = psbt::Wallet {
+            accounts: &accounts,
+            store: &store_read,
+        };
+        let wallet_data = wallet.wallet_data(&BETransaction::Bitcoin(proof.unsigned_tx.clone()));
+        psbt::add_bitcoin_wallet_data(&mut proof, wallet_data);
+        psbt::sign_bitcoin(&mut proof, &account, signer.as_ref(), None, false)?;
+        psbt::finalize_bitcoin_inputs(&mut proof, 1..proof.inputs.len())?;
+
+        Ok(OwnershipProof {
+            proof: base64::encode(proof.serialize()),
+            satoshi: utxos.iter().map(|(_, satoshi)| satoshi).sum(),
+            utxos: utxos
+                .into_iter()
+                .map(|(outpoint, satoshi)| ProvenUtxo {
+                    txhash: outpoint.txid.to_string(),
+                    pt_idx: outpoint.vout,
+                    satoshi,
+                })
+                .collect(),
+        })
+    }
+
+    /// Verify a proof of ownership for a message, made by any wallet, returning
+    /// the utxos it covers
+    ///
+    /// No wallet key is needed, whether the utxos are still unspent is not
+    /// checked.
+    pub fn verify_ownership_proof(
+        &self,
+        opt: &VerifyOwnershipProofOpt,
+    ) -> Result<OwnershipProof, Error> {
+        if let NetworkId::Elements(_) = self.network.id() {
+            return Err(Error::Generic("ownership proofs are not supported on Liquid yet".into()));
+        }
+        let utxos = reserves::verify_ownership_proof(&opt.proof, &opt.message)?;
+        Ok(OwnershipProof {
+            proof: opt.proof.clone(),
+            satoshi: utxos.iter().map(|(_, satoshi)| satoshi).sum(),
+            utxos: utxos
+                .into_iter()
+                .map(|(outpoint, satoshi)| ProvenUtxo {
+                    txhash: outpoint.txid.to_string(),
+                    pt_idx: outpoint.vout,
+                    satoshi,
+                })
+                .collect(),
+        })
+    }
+
+    /// Export the transaction of a create transaction result as a PSBT, or a
+    /// PSET on Liquid, with the key origins of the wallet inputs and outputs
+    pub fn psbt_from_create_transaction(
+        &self,
+        opt: &PsbtFromTransactionOpt,
+    ) -> Result<PsbtResult, Error> {
+        let transaction = BETransaction::from_hex(&opt.transaction, self.network.id())?;
+        let store = self.store()?;
+        let store_read = store.read()?;
+        let accounts = self.accounts.read()?;
+        let wallet = psbt::Wallet {
+            accounts: &accounts,
+            store: &store_read,
+        };
+        let wallet_data = wallet.wallet_data(&transaction);
+        let sighash_types: Vec<_> =
+            opt.transaction_inputs.iter().map(|input| input.user_sighash).collect();
+        Ok(PsbtResult {
+            psbt: psbt::create_psbt(transaction, wallet_data, &sighash_types)?,
+        })
+    }
+
+    /// Sign the inputs of a PSBT, or PSET on Liquid, belonging to a subaccount,
+    /// with the subaccount extended private key given by the caller
+    pub fn sign_psbt(&mut self, opt: &SignPsbtOpt) -> Result<SignPsbtResult, Error> {
+        self.check_policy(PolicyStage::Sign, &psbt::unsigned_tx(&opt.psbt, self.network.id())?)?;
+        let account = self.get_account(opt.subaccount)?;
+        let signer = self.account_signer(&account, opt.xprv.as_ref())?;
+        psbt::sign_psbt(
+            &opt.psbt,
+            self.network.id(),
+            &account,
+            signer.as_ref(),
+            opt.inputs.as_deref(),
+            opt.expert,
+        )
+    }
+
+    /// Finalize a signed PSBT, or PSET on Liquid, and extract the transaction
+    pub fn finalize_and_extract(&self, opt: &FinalizePsbtOpt) -> Result<FinalizePsbtResult, Error> {
+        let transaction = psbt::finalize_psbt(&opt.psbt, self.network.id())?;
+        Ok(FinalizePsbtResult {
+            transaction: transaction.serialize().to_lower_hex_string(),
+            txhash: transaction.txid().to_string(),
+        })
+    }
+
+    /// The details of a PSBT, or PSET on Liquid, created by another party, to
+    /// be acknowledged by the user before signing it with `psbt_sign`
+    pub fn psbt_get_details(&self, opt: &PsbtGetDetailsOpt) -> Result<PsbtDetails, Error> {
+        let store = self.store()?;
+        let store_read = store.read()?;
+        let accounts = self.accounts.read()?;
+        let wallet = psbt::Wallet {
+            accounts: &accounts,
+            store: &store_read,
+        };
+        let max_fee_rate = opt.max_fee_rate.unwrap_or(psbt::DEFAULT_MAX_FEE_RATE);
+        psbt::psbt_details(&opt.psbt, self.network.id(), &wallet, max_fee_rate)
+    }
+
+    /// Sign the subaccount inputs of a PSBT, or PSET on Liquid, created by
+    /// another party, if it is still the one of the acknowledged details
+    pub fn psbt_sign(&mut self, opt: &PsbtSignOpt) -> Result<SignPsbtResult, Error> {
+        self.check_policy(PolicyStage::Sign, &psbt::unsigned_tx(&opt.psbt, self.network.id())?)?;
+        let store = self.store()?;
+        let store_read = store.read()?;
+        let accounts = self.accounts.read()?;
+        let account = accounts
+            .get(&opt.subaccount)
+            .ok_or_else(|| Error::InvalidSubaccount(opt.subaccount))?;
+        let signer = self.account_signer(account, opt.xprv.as_ref())?;
+        let wallet = psbt::Wallet {
+            accounts: &accounts,
+            store: &store_read,
+        };
+        psbt::psbt_sign(
+            &opt.psbt,
+            self.network.id(),
+            &wallet,
+            &opt.txhash,
+            account,
+            signer.as_ref(),
+        )
+    }
+
+    fn message_network(&self) -> Result<bitcoin::Network, Error> {
+        match self.network.id() {
+            NetworkId::Bitcoin(network) => Ok(network),
+            NetworkId::Elements(_) => {
+                Err(Error::Generic("message signing is not supported on Liquid".into()))
+            }
+        }
+    }
+
+    /// Parse a BIP21 payment URI, or its Liquid adaptation, telling whether
+    /// its address is one of the wallet
+    pub fn parse_uri(&self, opt: &ParseUriOpt) -> Result<ParsedUri, Error> {
+        let precision = opt.precision.unwrap_or(bip21::DEFAULT_PRECISION);
+        let mut parsed = bip21::parse_uri(&opt.uri, self.network.id(), precision)?;
+        let script_pubkey = self.parse_address(&parsed.address)?.script_pubkey();
+        parsed.send_to_self = self.find_script(&script_pubkey, 0)?.is_some();
+        Ok(parsed)
+    }
+
+    /// Create a BIP21 payment URI, or its Liquid adaptation, to receive to an address
+    pub fn create_uri(&self, opt: &CreateUriOpt) -> Result<CreateUriResult, Error> {
+        let asset_id = match (&opt.asset_id, opt.satoshi) {
+            (Some(asset_id), _) => Some(asset_id.parse().map_err(|_| Error::InvalidAssetId)?),
+            (None, Some(_)) if self.network.liquid => Some(self.network.policy_asset_id()?),
+            _ => None,
+        };
+        let uri = bip21::create_uri(
+            &opt.address,
+            opt.satoshi,
+            asset_id,
+            opt.label.as_deref(),
+            self.network.id(),
+            opt.precision.unwrap_or(bip21::DEFAULT_PRECISION),
+        )?;
+        Ok(CreateUriResult {
+            uri,
+        })
+    }
+
+    pub fn get_scriptpubkey_data(&self, script_pubkey: &str) -> Result<ScriptPubKeyData, Error> {
+        let script = BEScript::from_hex(script_pubkey, self.network.id())?;
+        let store = self.store()?;
+        let store = store.read()?;
+        let accounts = self.get_accounts()?;
+        for account in accounts.iter() {
+            let account_cache = store.account_cache(account.num())?;
+            if let Ok(path) = account_cache.get_path(&script) {
+                let (is_internal, pointer) = parse_path(path)?;
+                return Ok(ScriptPubKeyData {
+                    subaccount: account.num(),
+                    branch: 1,
+                    pointer: pointer,
+                    subtype: 0,
+                    is_internal: is_internal,
+                    address_type: account.script_type().to_string(),
+                });
+            }
+        }
+        return Err(Error::ScriptPubkeyNotFound);
+    }
+
+    /// Whether an address or script pubkey belongs to the wallet, and where
+    /// it is derived, searching all subaccounts and chains up to the gap
+    /// window plus `opt.search_depth`, without generating new addresses
+    pub fn is_mine(&self, opt: &IsMineOpt) -> Result<IsMineResult, Error> {
+        let script = self.parse_address_or_script(&opt.address_or_script)?;
+        let script = self.find_script(&script, opt.search_depth)?;
+        Ok(IsMineResult {
+            is_mine: script.is_some(),
+            script,
+        })
+    }
+
+    /// Find `script_pubkey` in all subaccounts, see [`Account::find_script`]
+    fn find_script(
+        &self,
+        script_pubkey: &BEScript,
+        search_depth: u32,
+    ) -> Result<Option<WalletScript>, Error> {
+        for account in self.get_accounts()? {
+            if let Some(script) =
+                account.find_script(script_pubkey, self.gap_limit, search_depth)?
+            {
+                return Ok(Some(script));
+            }
+        }
+        Ok(None)
+    }
+
+    fn parse_address_or_script(&self, address_or_script: &str) -> Result<BEScript, Error> {
+        self.parse_address(address_or_script)
+            .map(|address| address.script_pubkey())
+            .or_else(|e| BEScript::from_hex(address_or_script, self.network.id()).or(Err(e)))
+    }
+
+    pub fn set_transaction_memo(&self, txid: &str, memo: &str) -> Result<(), Error> {
+        let txid = BETxid::from_hex(txid, self.network.id())?;
+        if memo.len() > 1024 {
+            return Err(Error::Generic("Too long memo (max 1024)".into()));
+        }
+        self.store()?.write()?.insert_memo(txid, memo)?;
+
+        Ok(())
+    }
+
+    /// Set the label of an output of a wallet transaction, removing it if
+    /// the label is empty
+    pub fn set_output_label(&self, opt: &OutputLabel) -> Result<(), Error> {
+        let txid = BETxid::from_hex(&opt.txhash, self.network.id())?;
+        if opt.label.len() > 1024 {
+            return Err(Error::Generic("Too long label (max 1024)".into()));
+        }
+        let store = self.store()?;
+        let mut store_write = store.write()?;
+        let txe = store_write.get_tx_entry(&txid)?;
+        ensure!(
+            (opt.pt_idx as usize) < txe.tx.output_len(),
+            Error::Generic(format!("transaction {} has no output {}", opt.txhash, opt.pt_idx))
+        );
+        let outpoint = txe.tx.outpoint(opt.pt_idx);
+        store_write.insert_output_label(&outpoint, &opt.label)
+    }
+
+    /// The memos and the output labels of the wallet, to import them in
+    /// another session with [`Self::import_labels`]
+    pub fn export_labels(&self) -> Result<WalletLabels, Error> {
+        let store = self.store()?;
+        let store_read = store.read()?;
+        let memos = store_read
+            .store
+            .memos
+            .iter()
+            .filter(|(_, memo)| !memo.is_empty())
+            .map(|(txid, memo)| (txid.to_string(), memo.clone()))
+            .collect();
+        let mut output_labels: Vec<OutputLabel> = store_read
+            .output_labels()
+            .flat_map(|(txid, labels)| {
+                labels.iter().map(move |(vout, label)| OutputLabel {
+                    txhash: txid.to_string(),
+                    pt_idx: *vout,
+                    label: label.clone(),
+                })
+            })
+            .collect();
+        output_labels.sort_by(|a, b| (&a.txhash, a.pt_idx).cmp(&(&b.txhash, b.pt_idx)));
+        Ok(WalletLabels {
+            memos,
+            output_labels,
+        })
+    }
+
+    /// Import the memos and the output labels exported by
+    /// [`Self::export_labels`], replacing the ones already set
+    ///
+    /// The labels of transactions not in the wallet are imported as well,
+    /// they show once the transactions are synced.
+    pub fn import_labels(&self, labels: &WalletLabels) -> Result<(), Error> {
+        let store = self.store()?;
+        let mut store_write = store.write()?;
+        for (txid, memo) in labels.memos.iter() {
+            let txid = BETxid::from_hex(txid, self.network.id())?;
+            store_write.insert_memo(txid, memo)?;
+        }
+        for label in labels.output_labels.iter() {
+            let txid = BETxid::from_hex(&label.txhash, self.network.id())?;
+            let outpoint = match txid {
+                BETxid::Bitcoin(txid) => BEOutPoint::new_bitcoin(txid, label.pt_idx),
+                BETxid::Elements(txid) => BEOutPoint::new_elements(txid, label.pt_idx),
+            };
+            store_write.insert_output_label(&outpoint, &label.label)?;
+        }
+        Ok(())
+    }
+
+    fn set_recent_spent_utxos(&self, tx: &BETransaction) -> Result<(), Error> {
+        let mut recent_spent_utxos = self.recent_spent_utxos.write()?;
+        (*recent_spent_utxos).extend(tx.previous_outputs());
+        Ok(())
+    }
+
+    /// Broadcast a transaction, transactions exceeding the spend limits in
+    /// the settings must be sent with [`Self::send_transaction`] and an
+    /// approval token
+    pub fn broadcast_transaction(&mut self, tx_hex: &str) -> Result<String, Error> {
+        let transaction = BETransaction::from_hex(&tx_hex, self.network.id())?;
+        // Not logged in sessions have no spend limits
+        if self.store().is_ok() {
+            ensure!(
+                !self.spend_limits_check(&transaction)?.requires_confirmation,
+                Error::ConfirmationRequired
+            );
+        }
+        self.broadcast(&transaction, tx_hex)
+    }
+
+    /// Broadcast `transaction` without checking the spend limits
+    fn broadcast(&mut self, transaction: &BETransaction, tx_hex: &str) -> Result<String, Error> {
+        info!("broadcast_transaction {:#?}", transaction.txid());
+        if let Some(input) = transaction.unsigned_input() {
+            return Err(Error::UnsignedInput(input));
+        }
+        self.check_policy(PolicyStage::Broadcast, &transaction)?;
+        // Not logged in sessions can broadcast, but spends are not accounted
+        let spent = match self.store() {
+            Ok(store) => Some(self.spent_satoshi(&*store.read()?, &transaction)?),
+            Err(_) => None,
+        };
+        let hex = Vec::<u8>::from_hex(tx_hex)?;
+        let broadcast = self
+            .server_client()
+            .and_then(|client| Ok((client.transaction_broadcast_raw(&hex)?, client)));
+        let (txid, client) = match broadcast {
+            Ok(broadcast) => broadcast,
+            Err(e) => {
+                // Broadcast again when the server can be reached, if logged in
+                if let Some(spent) = spent.filter(|_| e.is_connection_error()) {
+                    if let Err(queue_err) = self.queue_broadcast(&transaction, tx_hex, spent) {
+                        warn!("cannot queue the transaction {:?}", queue_err);
+                    }
+                }
+                return Err(e.into_rejection());
+            }
+        };
+        self.set_recent_spent_utxos(&transaction)?;
+        if let Some(spent) = spent {
+            let now = util::now() / 1_000_000;
+            let details = broadcast_details(&client, tx_hex, &txid, self.url.url(), now);
+            let store = self.store()?;
+            let mut store_write = store.write()?;
+            store_write.remove_pending_broadcast(&transaction.txid().to_string())?;
+            if spent > 0 {
+                store_write.insert_spend(transaction.txid(), spent, now)?;
+            }
+            store_write.insert_broadcast(&transaction.txid(), details)?;
+        }
+        Ok(format!("{}", txid))
+    }
+
+    /// Whether the nodes would accept the transaction in their mempool
+    ///
+    /// Electrum servers can't test a transaction without broadcasting it, so
+    /// it's checked against the relay policy of the network, see
+    /// [`Self::mempool_rejection`].
+    pub fn check_mempool_acceptance(
+        &self,
+        opt: &CheckMempoolAcceptanceOpt,
+    ) -> Result<MempoolAcceptance, Error> {
+        let transaction = BETransaction::from_hex(&opt.transaction, self.network.id())?;
+        Ok(match self.mempool_rejection(&transaction)? {
+            Some((reason, message)) => MempoolAcceptance {
+                accepted: false,
+                reason: Some(reason),
+                message: Some(message),
+            },
+            None => MempoolAcceptance {
+                accepted: true,
+                reason: None,
+                message: None,
+            },
+        })
+    }
+
+    /// Why the nodes would reject `transaction` from their mempool, None if
+    /// it passes the checks
+    ///
+    /// The transaction must be standard in size, signed, without dust outputs
+    /// and final at the next block, its inputs must exist on the server and
+    /// its fee rate must be above the minimum relayed. Conflicts and the
+    /// limits on the chains of unconfirmed transactions are only found on
+    /// broadcast.
+    fn mempool_rejection(
+        &self,
+        transaction: &BETransaction,
+    ) -> Result<Option<(RejectReason, String)>, Error> {
+        let policy = self.network.policy();
+        let weight = transaction.get_weight();
+        if weight as u64 > policy.max_standard_tx_weight {
+            let message = format!("weight {} above {}", weight, policy.max_standard_tx_weight);
+            return Ok(Some((RejectReason::TxSize, message)));
+        }
+        if let Some(input) = transaction.unsigned_input() {
+            let message = format!("input {} is not signed", input);
+            return Ok(Some((RejectReason::MissingWitness, message)));
+        }
+        if let BETransaction::Bitcoin(tx) = transaction {
+            for (vout, output) in tx.output.iter().enumerate() {
+                let dust_limit = policy.dust_limits.for_script(&output.script_pubkey);
+                if output.value.to_sat() < dust_limit {
+                    let message = format!(
+                        "output {} of {} is below the dust limit {}",
+                        vout,
+                        output.value.to_sat(),
+                        dust_limit
+                    );
+                    return Ok(Some((RejectReason::Dust, message)));
+                }
+            }
+        }
+
+        let client = self.server_client()?;
+        let inputs = transaction.previous_sequence_and_outpoints();
+        let lock_time = transaction.lock_time();
+        if lock_time > 0 && inputs.iter().any(|(sequence, _)| *sequence != u32::MAX) {
+            // Time locks are compared with the median time past, behind the current time
+            let next = match lock_time < 500_000_000 {
+                true => client.block_headers_subscribe_raw()?.height as u32 + 1,
+                false => (util::now() / 1_000_000) as u32,
+            };
+            if lock_time >= next {
+                let message = format!("lock time {} not reached at {}", lock_time, next);
+                return Ok(Some((RejectReason::NonFinal, message)));
+            }
+        }
+
+        let mut previous_txs = BETransactions::default();
+        if let Ok(store) = self.store() {
+            let store_read = store.read()?;
+            for (_, outpoint) in inputs.iter() {
+                if let Ok(txe) = store_read.get_tx_entry(&outpoint.txid()) {
+                    previous_txs.insert(outpoint.txid(), txe.clone());
+                }
+            }
+        }
+        for (_, outpoint) in inputs.iter() {
+            if !previous_txs.contains_key(&outpoint.txid()) {
+                let txid = outpoint.txid().into_bitcoin();
+                let raw = match client.transaction_get_raw(&txid).map_err(Error::from) {
+                    Ok(raw) => raw,
+                    Err(e) if e.is_connection_error() => return Err(e),
+                    Err(_) => {
+                        let message = format!("previous transaction {} not found", outpoint.txid());
+                        return Ok(Some((RejectReason::MissingInputs, message)));
+                    }
+                };
+                let previous_tx = BETransaction::deserialize(&raw, self.network.id())?;
+                previous_txs.insert(outpoint.txid(), previous_tx.into());
+            }
+            let output_len = previous_txs[&outpoint.txid()].tx.output_len();
+            if outpoint.vout() as usize >= output_len {
+                let message = format!(
+                    "previous transaction {} has no output {}",
+                    outpoint.txid(),
+                    outpoint.vout()
+                );
+                return Ok(Some((RejectReason::MissingInputs, message)));
+            }
+        }
+
+        let policy_asset = self.network.policy_asset_id().ok();
+        let fee = transaction.fee(&previous_txs, &HashMap::new(), &policy_asset)?;
+        let fee_rate = BETransactionEntry::from(transaction.clone()).fee_rate(fee);
+        let min_fee_rate = self.get_min_fee_rate().unwrap_or(policy.min_fee_rate);
+        if fee_rate < min_fee_rate {
+            let message = format!("fee rate {} below the minimum {}", fee_rate, min_fee_rate);
+            return Ok(Some((RejectReason::MinRelayFeeNotMet, message)));
+        }
+        Ok(None)
+    }
+
+    /// Queue `transaction`, whose broadcast failed because the server could
+    /// not be reached, to broadcast it again once connected
+    fn queue_broadcast(
+        &self,
+        transaction: &BETransaction,
+        tx_hex: &str,
+        spent: u64,
+    ) -> Result<(), Error> {
+        info!("queueing transaction {} to broadcast it again", transaction.txid());
+        // Its inputs are not to be spent again
+        self.set_recent_spent_utxos(transaction)?;
+        let queued = QueuedBroadcast {
+            pending: PendingBroadcast {
+                txhash: transaction.txid().to_string(),
+                transaction: tx_hex.to_string(),
+                created_at: util::now() / 1_000_000,
+                expired: false,
+            },
+            spent,
+        };
+        self.store()?.write()?.queue_broadcast(queued)
+    }
+
+    /// The transactions whose broadcast failed because the server could not
+    /// be reached, oldest first
+    ///
+    /// They are broadcast again by the syncer once connected, unless expired.
+    pub fn get_pending_broadcasts(&self) -> Result<GetPendingBroadcastsResult, Error> {
+        let store = self.store()?;
+        let store_read = store.read()?;
+        Ok(GetPendingBroadcastsResult {
+            pending_broadcasts: store_read
+                .pending_broadcasts()
+                .map(|q| q.pending.clone())
+                .collect(),
+        })
+    }
+
+    /// Stop broadcasting again the transaction `opt.txhash`, removing it from
+    /// the pending broadcasts
+    pub fn cancel_pending_broadcast(&self, opt: &CancelPendingBroadcastOpt) -> Result<(), Error> {
+        let txid = BETxid::from_hex(&opt.txhash, self.network.id())?;
+        let removed = self.store()?.write()?.remove_pending_broadcast(&opt.txhash)?;
+        ensure!(removed, Error::TxNotFound(txid));
+        Ok(())
+    }
+
+    /// Broadcast a transaction, if it exceeds the spend limits in the settings
+    /// it must have been approved with [`Self::approve_transaction`]
+    ///
+    /// With `opt.payjoin`, if the transaction pays a URI with a payjoin
+    /// endpoint, the payjoin transaction is broadcast instead, falling back
+    /// to the given one if the receiver fails.
+    ///
+    /// With `opt.client_request_id`, the transaction is recorded before being
+    /// broadcast, retries with the same id return it if the server has it.
+    pub fn send_transaction(
+        &mut self,
+        opt: &SendTransactionOpt,
+    ) -> Result<SendTransactionResult, Error> {
+        let transaction = BETransaction::from_hex(&opt.transaction, self.network.id())?;
+        let now = util::now() / 1_000_000;
+        if let Some(id) = &opt.client_request_id {
+            if let Some(result) = self.sent_request(id, now)? {
+                info!("transaction of client request {} already sent", id);
+                return Ok(result);
+            }
+        }
+        if opt.check_acceptance {
+            if let Some((reason, message)) = self.mempool_rejection(&transaction)? {
+                return Err(Error::TransactionRejected {
+                    reason,
+                    message,
+                });
+            }
+        }
+        if let Some(id) = &opt.client_request_id {
+            self.record_request(id, transaction.txid(), false, now)?;
+        }
+        let token = match self.spend_limits_check(&transaction)?.requires_confirmation {
+            true => {
+                let token = opt.confirmation_token.as_ref().ok_or(Error::ConfirmationRequired)?;
+                let approved = self.confirmation_tokens.get(token);
+                ensure!(
+                    approved == Some(&transaction.unsigned_txid()),
+                    Error::ConfirmationRequired
+                );
+                Some(token)
+            }
+            false => None,
+        };
+        let payjoin_txhash = match &opt.payjoin {
+            Some(payjoin_opt) => self.payjoin(&transaction, payjoin_opt).and_then(|tx| match tx {
+                Some(tx) => self.broadcast(&tx, &tx.serialize().to_lower_hex_string()).map(Some),
+                None => Ok(None),
+            }),
+            None => Ok(None),
+        };
+        let mut result = match payjoin_txhash {
+            Ok(Some(txhash)) => SendTransactionResult {
+                txhash,
+                payjoin: true,
+                payjoin_error: None,
+                broadcast: None,
+            },
+            Ok(None) => SendTransactionResult {
+                txhash: self.broadcast(&transaction, &opt.transaction)?,
+                payjoin: false,
+                payjoin_error: None,
+                broadcast: None,
+            },
+            Err(e) => {
+                warn!("payjoin failed, sending the original transaction: {:?}", e);
+                SendTransactionResult {
+                    txhash: self.broadcast(&transaction, &opt.transaction)?,
+                    payjoin: false,
+                    payjoin_error: Some(e.to_string()),
+                    broadcast: None,
+                }
+            }
+        };
+        result.broadcast = self.broadcast_details(&result.txhash);
+        if let Some(token) = token {
+            // Tokens can be used only once
+            self.confirmation_tokens.remove(token);
+        }
+        if let Some(id) = opt.client_request_id.as_ref().filter(|_| result.payjoin) {
+            let txid = BETxid::from_hex(&result.txhash, self.network.id())?;
+            self.record_request(id, txid, true, now)?;
+        }
+        Ok(result)
+    }
+
+    /// The transaction sent with the client request `id`, if it has not
+    /// expired and the server has it in the mempool or in a block
+    fn sent_request(&self, id: &str, now: u64) -> Result<Option<SendTransactionResult>, Error> {
+        let request = {
+            let store = self.store()?;
+            let store_read = store.read()?;
+            let expiry = store_read.get_settings().unwrap_or_default().client_request_expiry();
+            match store_read.client_request(id, now, expiry) {
+                Some(request) => request.clone(),
+                None => return Ok(None),
+            }
+        };
+        match self.server_client()?.transaction_get_raw(&request.txid) {
+            Ok(_) => Ok(Some(SendTransactionResult {
+                txhash: request.txid.to_string(),
+                payjoin: request.payjoin,
+                payjoin_error: None,
+                broadcast: self.broadcast_details(&request.txid.to_string()),
+            })),
+            Err(e) => {
+                // Never broadcast, or dropped from the mempool
+                warn!("transaction of client request {} not found: {:?}", id, e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// How the transaction `txhash` was broadcast, if sent by the wallet
+    /// while logged in
+    fn broadcast_details(&self, txhash: &str) -> Option<BroadcastDetails> {
+        let txid = BETxid::from_hex(txhash, self.network.id()).ok()?;
+        let store = self.store().ok()?;
+        let store_read = store.read().ok()?;
+        store_read.get_broadcast(&txid).cloned()
+    }
+
+    fn record_request(&self, id: &str, txid: BETxid, payjoin: bool, now: u64) -> Result<(), Error> {
+        let store = self.store()?;
+        let mut store_write = store.write()?;
+        let expiry = store_write.get_settings().unwrap_or_default().client_request_expiry();
+        let request = ClientRequest {
+            txid: txid.into_bitcoin(),
+            payjoin,
+            timestamp: now,
+        };
+        store_write.insert_client_request(id, request, expiry)
+    }
+
+    /// The payjoin version of `tx`, if `opt.uri` has a payjoin endpoint.
+    ///
+    /// All inputs of `tx` must be of `opt.subaccount`, which are signed
+    /// again after checking the receiver proposal.
+    fn payjoin(
+        &self,
+        tx: &BETransaction,
+        opt: &PayjoinOpt,
+    ) -> Result<Option<BETransaction>, Error> {
+        let parsed = bip21::parse_uri(&opt.uri, self.network.id(), bip21::DEFAULT_PRECISION)?;
+        let endpoint = match parsed.payjoin {
+            Some(endpoint) => endpoint,
+            None => return Ok(None),
+        };
+        let signed_tx = match tx {
+            BETransaction::Bitcoin(tx) => tx,
+            BETransaction::Elements(_) => {
+                return Err(Error::Generic("payjoin is not supported on Liquid".into()))
+            }
+        };
+        let (original, max_additional_fee, change_index) = {
+            let store = self.store()?;
+            let store_read = store.read()?;
+            let accounts = self.accounts.read()?;
+            let account = accounts
+                .get(&opt.subaccount)
+                .ok_or_else(|| Error::InvalidSubaccount(opt.subaccount))?;
+            let wallet = psbt::Wallet {
+                accounts: &accounts,
+                store: &store_read,
+            };
+            let (inputs, outputs) = wallet.wallet_data(tx);
+            let mut previous_outputs = vec![];
+            for (txin, input) in signed_tx.input.iter().zip(&inputs) {
+                match input {
+                    Some((BETransaction::Bitcoin(previous_tx), txo))
+                        if txo.subaccount == opt.subaccount =>
+                    {
+                        let vout = txin.previous_output.vout as usize;
+                        previous_outputs.push(previous_tx.output[vout].clone());
+                    }
+                    _ => {
+                        return Err(Error::Generic(
+                            "payjoin requires all inputs from the subaccount".into(),
+                        ))
+                    }
+                }
+            }
+            let original = payjoin::original_psbt(signed_tx, previous_outputs)?;
+            let max_additional_fee = match opt.max_additional_fee {
+                Some(max_additional_fee) => max_additional_fee,
+                None => payjoin::default_max_additional_fee(
+                    &original,
+                    signed_tx,
+                    account.script_type(),
+                )?,
+            };
+            let change_index = outputs.iter().position(Option::is_some);
+            (original, max_additional_fee, change_index)
+        };
+
+        let url = payjoin::endpoint_url(
+            &endpoint,
+            change_index,
+            max_additional_fee,
+            self.network.development,
+        )?;
+        info!("requesting payjoin proposal from {}", url);
+        let mut proposal =
+            payjoin::request_proposal(&self.build_request_agent()?, &url, &original)?;
+        payjoin::check_proposal(&original, &proposal, change_index, max_additional_fee)?;
+
+        let store = self.store()?;
+        let store_read = store.read()?;
+        let accounts = self.accounts.read()?;
+        let account = accounts
+            .get(&opt.subaccount)
+            .ok_or_else(|| Error::InvalidSubaccount(opt.subaccount))?;
+        let wallet = psbt::Wallet {
+            accounts: &accounts,
+            store: &store_read,
+        };
+        let wallet_data = wallet.wallet_data(&BETransaction::Bitcoin(proposal.unsigned_tx.clone()));
+        for (input, wallet_input) in proposal.inputs.iter_mut().zip(&wallet_data.0) {
+            if wallet_input.is_some() {
+                // Only trust our own data for the inputs we sign
+                *input = Default::default();
+            }
+        }
+        psbt::add_bitcoin_wallet_data(&mut proposal, wallet_data);
+        let signer = self.account_signer(account, opt.xprv.as_ref())?;
+        psbt::sign_bitcoin(&mut proposal, account, signer.as_ref(), None, false)?;
+        let tx = psbt::finalize_psbt(&base64::encode(proposal.serialize()), self.network.id())?;
+        Ok(Some(tx))
+    }
+
+    /// Approve sending `tx_hex` even if it exceeds the spend limits, returning
+    /// a one-time token to pass to [`Self::send_transaction`].
+    ///
+    /// The approval is bound to the transaction without its input scripts, so
+    /// it can be given before signing any kind of input.
+    pub fn approve_transaction(&mut self, tx_hex: &str) -> Result<String, Error> {
+        let transaction = BETransaction::from_hex(tx_hex, self.network.id())?;
+        let token = thread_rng().gen::<[u8; 32]>().to_lower_hex_string();
+        self.confirmation_tokens.insert(token.clone(), transaction.unsigned_txid());
+        Ok(token)
+    }
+
+    /// Whether sending `tx_hex` exceeds the spend limits in the settings
+    pub fn check_spend_limits(&self, tx_hex: &str) -> Result<SpendLimitsCheck, Error> {
+        let transaction = BETransaction::from_hex(tx_hex, self.network.id())?;
+        self.spend_limits_check(&transaction)
+    }
+
+    fn spend_limits_check(&self, transaction: &BETransaction) -> Result<SpendLimitsCheck, Error> {
+        let store = self.store()?;
+        let store_read = store.read()?;
+        let satoshi = self.spent_satoshi(&store_read, transaction)?;
+        let spent_last_day = store_read.spent_in_window(util::now() / 1_000_000);
+        let limits = store_read.get_settings().and_then(|s| s.spend_limits).unwrap_or_default();
+        Ok(SpendLimitsCheck {
+            satoshi,
+            spent_last_day,
+            requires_confirmation: limits.exceeded(satoshi, spent_last_day),
+        })
+    }
+
+    /// The policy asset amount that `transaction` sends out of the wallet, fee included
+    fn spent_satoshi(&self, store: &StoreMeta, transaction: &BETransaction) -> Result<u64, Error> {
+        let mut spent: i64 = 0;
+        for acc_store in store.cache.accounts.values() {
+            match transaction {
+                BETransaction::Bitcoin(_) => {
+                    let balances = transaction.my_balance_changes(
+                        &acc_store.all_txs,
+                        &acc_store.paths,
+                        &acc_store.unblinded,
+                    );
+                    spent -= balances.get("btc").copied().unwrap_or(0);
+                }
+                BETransaction::Elements(tx) => {
+                    // The outputs are not in the store yet, unblind the wallet ones here
+                    let policy_asset = self.network.policy_asset_id()?;
+                    for input in tx.input.iter() {
+                        if let Some(secrets) = acc_store.unblinded.get(&input.previous_output) {
+                            if secrets.asset == policy_asset {
+                                spent += secrets.value as i64;
+                            }
+                        }
+                    }
+                    for output in tx.output.iter() {
+                        if !acc_store.paths.contains_key(&output.script_pubkey.clone().into()) {
+                            continue;
+                        }
+                        let secrets = match (output.asset, output.value) {
+                            (Asset::Explicit(asset), confidential::Value::Explicit(value)) => {
+                                Some((asset, value))
+                            }
+                            _ => store.cache.master_blinding.as_ref().and_then(|key| {
+                                let secrets = unblind_output(output.clone(), key, None).ok()?;
+                                Some((secrets.asset, secrets.value))
+                            }),
+                        };
+                        if let Some((asset, value)) = secrets {
+                            if asset == policy_asset {
+                                spent -= value as i64;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(spent.max(0) as u64)
+    }
+
+    /// The estimates are returned as an array of 25 elements. Each element is
+    /// an integer representing the fee estimate expressed as satoshi per 1000
+    /// bytes. The first element is the minimum relay fee as returned by the
+    /// network, while the remaining elements are the current estimates to use
+    /// for a transaction to confirm from 1 to 24 blocks.
+    ///
+    /// If the server can't be asked, eg. offline or before the first
+    /// response, the last known estimates are returned flagged as stale.
+    /// They're persisted in the store, so that transactions can be previewed
+    /// offline after a restart.
+    pub fn get_fee_estimates(&self) -> Result<FeeEstimates, Error> {
+        let mut fee_fetched_at = self.fee_fetched_at.lock()?;
+        let fresh =
+            |at: &Option<Instant>| at.is_some_and(|at| at.elapsed() < FEE_ESTIMATE_INTERVAL);
+        let mut fee_failed_at = self.fee_failed_at.lock()?;
+        if !fresh(&fee_fetched_at) && !fresh(&fee_failed_at) && self.paused.is_none() {
+            let min_rate = self.network.policy().min_fee_rate;
+            let fetched =
+                self.server_client().and_then(|client| try_get_fee_estimates(&client, min_rate));
+            match fetched {
+                Ok(fee_estimates) => {
+                    let now = util::now() / 1_000_000;
+                    self.store()?.write()?.set_fee_estimates(fee_estimates, now);
+                    *fee_fetched_at = Some(Instant::now());
+                    *fee_failed_at = None;
+                }
+                Err(e) => {
+                    warn!("can't update fee estimates, using the last known ones {:?}", e);
+                    *fee_failed_at = Some(Instant::now());
+                }
+            }
+        }
+        let store = self.store()?;
+        let store_read = store.read()?;
+        Ok(FeeEstimates {
+            fees: store_read.fee_estimates(),
+            stale: !fresh(&fee_fetched_at),
+            fetched_at: store_read.fee_estimates_at(),
+        })
+    }
+
+    /// The fee rate of the transactions not given one, the minimum one
+    ///
+    /// It's known from the fee estimates, if they're older than the
+    /// `max_fee_estimates_age` setting the caller must give a fee rate.
+    fn default_fee_rate(&self) -> Result<u64, Error> {
+        let max_age = self.get_settings().unwrap_or_default().max_fee_estimates_age();
+        let fetched_at = self.get_fee_estimates()?.fetched_at;
+        let now = util::now() / 1_000_000;
+        ensure!(
+            fetched_at.is_some_and(|at| now.saturating_sub(at) <= max_age),
+            Error::StaleFeeEstimates {
+                max_age
+            }
+        );
+        self.get_min_fee_rate()
+    }
+
+    /// The network of the session, with the dust limits and fee rates
+    /// enforced when building transactions on it
+    pub fn get_network_info(&self) -> NetworkInfo {
+        NetworkInfo {
+            name: self.network.name.clone(),
+            liquid: self.network.liquid,
+            mainnet: self.network.mainnet,
+            policy: self.network.policy(),
+            clock_skew_seconds: self.network_time.skew(),
+            signblock: self.signblock.lock().ok().and_then(|signblock| signblock.clone()),
+        }
+    }
+
+    pub fn get_min_fee_rate(&self) -> Result<u64, Error> {
+        let default_min_rate = self.network.policy().min_fee_rate;
+        let min_rate = self.store()?.read()?.min_fee_rate();
+        Ok(min_rate.max(default_min_rate))
+    }
+
+    /// Return the settings or None if the store is not loaded (not logged in)
+    pub fn get_settings(&self) -> Option<Settings> {
+        Some(self.store().ok()?.read().ok()?.get_settings().unwrap_or_default())
+    }
+
+    /// Update the settings, if `value` contains a `revision` it must match the
+    /// current one, otherwise the settings have been changed by another session.
+    pub fn change_settings(&mut self, value: &Value) -> Result<(), Error> {
+        // Fetched before locking the store, if not available the pricing is not validated
+        let currencies = match value.get("pricing") {
+            Some(_) => {
+                let source = self.price_source(None)?;
+                match price::available_currencies(
+                    &mut self.available_currencies,
+                    source.as_deref(),
+                    price::CURRENCIES_TTL,
+                ) {
+                    Ok(currencies) => Some(currencies.clone()),
+                    Err(e) => {
+                        warn!("cannot validate pricing settings: {:?}", e);
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        let store = self.store()?;
+        let mut store_write = store.write()?;
+        store_write.reload_settings_if_changed()?;
+        let mut settings = store_write.get_settings().unwrap_or_default();
+        if let Some(revision) = value.get("revision").and_then(|v| v.as_u64()) {
+            ensure!(revision == settings.revision(), Error::SettingsConflict(settings.revision()));
+        }
+        settings.update(value)?;
+        if let Some(currencies) = currencies {
+            let pricing = &settings.pricing;
+            price::validate_pricing(&currencies, pricing.currency(), pricing.exchange())?;
+        }
+        settings.revision = Some(settings.revision() + 1);
+        store_write.insert_settings(Some(settings.clone()))?;
+        drop(store_write);
+        self.notify.settings(&settings);
+        Ok(())
+    }
+
+    pub fn get_available_currencies(
+        &mut self,
+        params: &GetAvailableCurrenciesParams,
+    ) -> Result<Value, Error> {
+        let source = self.price_source(params.url.as_deref())?;
+        let currencies = price::available_currencies(
+            &mut self.available_currencies,
+            source.as_deref(),
+            price::CURRENCIES_TTL,
+        )?;
+
+        let all = currencies.values().flatten().collect::<BTreeSet<_>>();
+
+        Ok(json!({ "all": all, "per_exchange": currencies }))
+    }
+
+    /// Replace the source of the exchange rates and of the available currencies
+    pub fn set_price_source(&mut self, source: Arc<dyn PriceSource>) {
+        self.price_source = Some(source);
+    }
+
+    /// The source set with [`Self::set_price_source`], otherwise the price
+    /// server at `url` or at the price url of the network
+    fn price_source(&self, url: Option<&str>) -> Result<Option<Arc<dyn PriceSource>>, Error> {
+        if let Some(source) = &self.price_source {
+            return Ok(Some(Arc::clone(source)));
+        }
+        Ok(match url.filter(|url| !url.is_empty()).or_else(|| self.network.price_url()) {
+            Some(url) => Some(Arc::new(
+                HttpPriceSource::new(self.build_request_agent()?, url)
+                    .with_history_url(self.network.price_history_url()),
+            )),
+            None => None,
+        })
+    }
+
+    /// Convert an amount to all the units and to the fiat currency in the
+    /// settings, or in `opt.pricing` if given.
+    ///
+    /// If a current exchange rate is not available the fiat values are `None`
+    /// and `fiat_rate_stale` is set, converting from fiat fails instead.
+    pub fn convert_amount(&self, opt: &ConvertAmountOpt) -> Result<ConvertAmountResult, Error> {
+        let pricing = match &opt.pricing {
+            Some(pricing) => pricing.clone(),
+            None => self.get_settings().unwrap_or_default().pricing,
+        };
+        let currency = Currency::from_str(pricing.currency())?;
+        let source = self.price_source(None)?;
+        let rate = price::current_rate(
+            &self.xr_cache,
+            source.as_deref(),
+            Pair::new(Currency::BTC, currency),
+            pricing.exchange(),
+            price::RATE_TTL,
+        );
+        price::convert_amount(opt, currency, rate)
+    }
+
+    pub fn get_unspent_outputs(&self, opt: &GetUnspentOpt) -> Result<GetUnspentOutputs, Error> {
+        let mut unspent_outputs: HashMap<String, Vec<UnspentOutput>> = HashMap::new();
+        let account = self.get_account(opt.subaccount)?;
+        let formatter = self.amount_formatter(&opt.asset_precisions);
+
+        let (num_confs, trust_own_unconfirmed) =
+            self.required_confs(opt.subaccount, opt.num_confs, opt.trust_own_unconfirmed)?;
+
+        let store = self.store()?;
+        let store_read = store.read()?;
+        let acc_store = store_read.account_cache(opt.subaccount)?;
+        let height = store_read.cache.tip_height();
+
+        let confidential_utxos_only = opt.confidential_utxos_only.unwrap_or(false);
+        validate_asset_filter(self.network.id(), opt.asset_id.as_deref())?;
+
+        for outpoint in account.unspents()? {
+            let utxo = account.txo(&outpoint, acc_store)?;
+            let confirmations = match utxo.height {
+                None | Some(0) => 0,
+                Some(h) => (height + 1).saturating_sub(h),
+            };
+            let own_unconfirmed = confirmations == 0
+                && acc_store
+                    .all_txs
+                    .get(&outpoint.txid())
+                    .is_some_and(|txe| acc_store.spends_own_outputs(&txe.tx));
+            let spendable = match own_unconfirmed {
+                true => trust_own_unconfirmed,
+                false => confirmations >= num_confs,
+            };
+            if !spendable || (confidential_utxos_only && !utxo.is_confidential()) {
+                continue;
+            }
+            let asset_id = match &utxo.txoutsecrets {
+                None => "btc".to_string(),
+                Some(s) => s.asset.to_string(),
+            };
+            if opt.asset_id.as_ref().is_some_and(|a| *a != asset_id) {
+                continue;
+            }
+            let label = store_read.get_output_label(&utxo.outpoint).cloned();
+            let provenance = match opt.include_provenance {
+                true => acc_store
+                    .provenance(&outpoint.txid())
+                    .map(|hops| hops.iter().map(|txid| txid.to_string()).collect::<Vec<_>>()),
+                false => None,
+            };
+            let mut utxo: UnspentOutput = utxo.try_into()?;
+            utxo.amount = formatter.format(utxo.satoshi as i64, &asset_id);
+            utxo.label = label;
+            utxo.provenance = provenance;
+            (*unspent_outputs.entry(asset_id).or_insert(vec![])).push(utxo);
+        }
+        Ok(GetUnspentOutputs(unspent_outputs))
+    }
+
+    /// The confirmations required of the utxos of the subaccount and whether
+    /// its own unconfirmed outputs are trusted regardless of them
+    ///
+    /// The values given by the caller take precedence over the subaccount
+    /// settings, which take precedence over the wallet ones. So the balance,
+    /// the unspent outputs and the coins available to new transactions agree.
+    fn required_confs(
         &self,
-        opt: &GetPreviousAddressesOpt,
-    ) -> Result<PreviousAddresses, Error> {
-        self.get_account(opt.subaccount)?.get_previous_addresses(opt)
+        subaccount: u32,
+        num_confs: Option<u32>,
+        trust_own_unconfirmed: Option<bool>,
+    ) -> Result<(u32, bool), Error> {
+        let store = self.store()?;
+        let store_read = store.read()?;
+        let settings = store_read.get_settings().unwrap_or_default();
+        let account_settings = store_read.get_account_settings(subaccount);
+        let num_confs = num_confs
+            .or_else(|| account_settings.and_then(|s| s.required_num_confs))
+            .or(settings.required_num_confs)
+            .unwrap_or(0);
+        let trust_own_unconfirmed = trust_own_unconfirmed
+            .or_else(|| account_settings.and_then(|s| s.trust_own_unconfirmed))
+            .or(settings.trust_own_unconfirmed)
+            .unwrap_or(true);
+        Ok((num_confs, trust_own_unconfirmed))
     }
 
-    pub fn encrypt_with_pin(&self, details: &EncryptWithPinDetails) -> Result<PinData, Error> {
-        let agent = self.build_request_agent()?;
+    /// The unspent outputs of the subaccount once the block at `opt.height`
+    /// was connected, replaying the cached transactions confirmed up to it
+    ///
+    /// The server is not asked, transactions not yet synced are missing.
+    pub fn get_unspent_outputs_at(&self, opt: &GetAtHeightOpt) -> Result<GetUnspentOutputs, Error> {
+        let mut unspent_outputs: HashMap<String, Vec<UnspentOutput>> = HashMap::new();
+        let account = self.get_account(opt.subaccount)?;
+        let formatter = self.amount_formatter(&opt.asset_precisions);
+        validate_asset_filter(self.network.id(), opt.asset_id.as_deref())?;
 
-        let pin_client = PinClient::new(
-            agent,
-            self.network.pin_server_url()?,
-            self.network.pin_server_public_key()?,
+        let store = self.store()?;
+        let store_read = store.read()?;
+        let acc_store = store_read.account_cache(opt.subaccount)?;
+        let tip = store_read.cache.tip_height();
+        ensure!(
+            opt.height <= tip,
+            Error::HeightAboveTip {
+                height: opt.height,
+                tip
+            }
         );
 
-        let plaintext = serde_json::to_vec(&details.plaintext)?;
-        pin_client.encrypt(&plaintext, &details.pin).map_err(Into::into)
-    }
-
-    /// Get the subaccount pointers/numbers from the store
-    ///
-    /// Multisig sessions receive the subaccount pointer from the server
-    /// and then get the xpubs for them from the signer. We need to allow
-    /// to do the same. So we fetch the subaccount pointers from the
-    /// persisted store and return them here.
-    pub fn get_subaccount_nums(&self) -> Result<Vec<u32>, Error> {
-        let mut account_nums = self.store()?.read()?.account_nums();
-        // For compatibility reason, account 0 must always be present
-        if !account_nums.contains(&0) {
-            // Insert it at the start to preserve sorting
-            account_nums.insert(0, 0);
+        for outpoint in account.unspents_at(opt.height)? {
+            let utxo = account.txo(&outpoint, acc_store)?;
+            let asset_id = match &utxo.txoutsecrets {
+                None => "btc".to_string(),
+                Some(s) => s.asset.to_string(),
+            };
+            if opt.asset_id.as_ref().is_some_and(|a| *a != asset_id) {
+                continue;
+            }
+            let label = store_read.get_output_label(&utxo.outpoint).cloned();
+            let mut utxo: UnspentOutput = utxo.try_into()?;
+            utxo.amount = formatter.format(utxo.satoshi as i64, &asset_id);
+            utxo.label = label;
+            (*unspent_outputs.entry(asset_id).or_insert(vec![])).push(utxo);
         }
-        Ok(account_nums)
+        Ok(GetUnspentOutputs(unspent_outputs))
     }
 
-    pub fn get_subaccounts(&mut self) -> Result<Vec<AccountInfo>, Error> {
-        self.get_accounts()?.iter().map(|a| a.info()).collect()
+    /// Register `callback` for the session notifications of the given `kinds`,
+    /// all notifications if empty. See [`NativeNotif::subscribe`].
+    pub fn subscribe<F>(&self, kinds: &[NotificationKind], callback: F) -> SubscriptionId
+    where
+        F: Fn(&gdk_common::model::Notification) + Send + Sync + 'static,
+    {
+        self.notify.subscribe(kinds, callback)
     }
 
-    pub fn get_accounts_settings(&mut self) -> Result<HashMap<u32, AccountSettings>, Error> {
-        Ok(self.store()?.read()?.get_accounts_settings().clone())
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        self.notify.unsubscribe(id)
     }
 
-    pub fn create_subaccount(&mut self, opt: CreateAccountOpt) -> Result<AccountInfo, Error> {
-        let store = self.store()?.clone();
-        let master_blinding = store.read()?.cache.master_blinding.clone();
-        let network = self.network.clone();
-        let mut accounts = self.accounts.write()?;
+    pub fn get_last_notification_of_type(
+        &self,
+        opt: &GetLastNotificationOpt,
+    ) -> Result<Option<Value>, Error> {
+        Ok(self.notify.last_notification_json(opt.kind))
+    }
 
-        // Allow discovery of already created subaccounts
-        if opt.discovered {
-            if let Entry::Occupied(entry) = accounts.entry(opt.subaccount) {
-                store.write()?.account_cache_mut(opt.subaccount)?.bip44_discovered = opt.discovered;
-                return entry.get().info();
+    pub fn create_pegout_transaction(
+        &self,
+        opt: &CreatePegoutOpt,
+    ) -> Result<CreatePegoutResult, Error> {
+        let mainchain = match self.network.id() {
+            NetworkId::Elements(network) => network.mainchain_network(),
+            NetworkId::Bitcoin(_) => {
+                return Err(Error::Generic("peg-out is only available on liquid".into()))
             }
-        }
-
-        if !opt.allow_gaps {
-            // Check that the given subaccount number is the next available one for its script type.
-            let (script_type, _) = get_account_script_purpose(opt.subaccount)?;
-            let (last_account, next_account) =
-                get_last_next_account_nums(accounts.keys().copied().collect(), script_type);
+        };
+        let address = pegout::parse_mainchain_address(&opt.mainchain_address, mainchain)?;
+        let policy_asset = self.network.policy_asset_id()?;
+        let min_fee_rate = self.get_min_fee_rate()?;
+        let fee_rate = match opt.fee_rate {
+            Some(fee_rate) => fee_rate,
+            None => self.default_fee_rate()?,
+        };
+        ensure!(fee_rate >= min_fee_rate, Error::FeeRateBelowMinimum);
+        let account = self.get_account(opt.subaccount)?;
 
-            if opt.subaccount != next_account {
-                // The subaccount already exists, or skips over the next available subaccount number
-                bail!(Error::InvalidSubaccount(opt.subaccount));
-            }
-            if let Some(last_account) = last_account {
-                // This is the next subaccount number, but the last one is still unused
-                let account = accounts
-                    .get(&last_account)
-                    .ok_or_else(|| Error::InvalidSubaccount(last_account))?;
-                if !opt.is_already_created && !account.has_transactions()? {
-                    bail!(Error::AccountGapsDisallowed);
+        let utxos = {
+            let store = self.store()?;
+            let store_read = store.read()?;
+            let acc_store = store_read.account_cache(opt.subaccount)?;
+            let mut utxos = vec![];
+            for outpoint in account.unspents()? {
+                let utxo = account.txo(&outpoint, acc_store)?;
+                if utxo.txoutsecrets.map(|s| s.asset) != Some(policy_asset) {
+                    continue;
                 }
+                let txid = outpoint.txid();
+                let txe = acc_store.all_txs.get(&txid).ok_or_else(|| Error::TxNotFound(txid))?;
+                let txout = match &txe.tx {
+                    BETransaction::Elements(tx) => tx.output[outpoint.vout() as usize].clone(),
+                    BETransaction::Bitcoin(_) => continue,
+                };
+                utxos.push((utxo, txout));
             }
-        }
+            utxos
+        };
 
-        let account = match accounts.entry(opt.subaccount) {
-            Entry::Occupied(entry) => entry.into_mut(),
-            Entry::Vacant(entry) => {
-                let account = entry.insert(Account::new(
-                    network,
-                    self.master_xpub_fingerprint,
-                    opt.xpub, // account xpub
-                    master_blinding,
-                    store,
-                    opt.subaccount,
-                    opt.discovered,
-                )?);
-                if !opt.name.is_empty() {
-                    account.set_name(&opt.name)?;
+        let policy = self.network.policy();
+        let mut change = account.get_next_address(true, false, self.gap_limit)?;
+        let mut consolidation_steps = vec![];
+        let utxos = match opt.split {
+            false => utxos,
+            true => {
+                let change_script = elements::Address::from_str(&change.address)?.script_pubkey();
+                let change_dust = policy
+                    .dust_limits
+                    .for_script(bitcoin::Script::from_bytes(change_script.as_bytes()));
+                let mut groups = selection::plan_chained(
+                    utxos.iter().map(|u| u.0.clone()).collect(),
+                    opt.satoshi + change_dust,
+                    fee_rate,
+                    migration::ELEMENTS_OUTPUTS_WEIGHT,
+                    pegout::PEGOUT_OUTPUTS_WEIGHT,
+                    account.script_type(),
+                    policy.max_standard_tx_weight,
+                )?;
+                let last: HashSet<_> =
+                    groups.pop().unwrap_or_default().into_iter().map(|u| u.outpoint).collect();
+
+                // Each step sends its inputs and the change of the previous
+                // one to a new change address
+                let mut chained: Option<(Txo, elements::TxOut)> = None;
+                for mut inputs in groups {
+                    inputs.extend(chained.take().map(|(txo, _)| txo));
+                    let destination =
+                        BEAddress::Elements(elements::Address::from_str(&change.address)?);
+                    let built = migration::build_migration_tx(
+                        &inputs,
+                        &[destination],
+                        Some(policy_asset),
+                        fee_rate,
+                        &policy,
+                    )?;
+                    let account_path = DerivationPath::from(&[1.into(), change.pointer.into()][..]);
+                    let txo = account.unbroadcast_txo(&built.tx, 0, &account_path)?;
+                    let txout = match &built.tx {
+                        BETransaction::Elements(tx) => tx.output[0].clone(),
+                        BETransaction::Bitcoin(_) => unreachable!("peg-outs are on liquid"),
+                    };
+                    consolidation_steps.push(PegoutConsolidationStep {
+                        transaction: built.tx.serialize().to_lower_hex_string(),
+                        fee: built.fee,
+                        transaction_vsize: built.vsize,
+                        transaction_inputs: inputs
+                            .into_iter()
+                            .map(TryInto::try_into)
+                            .collect::<Result<_, _>>()?,
+                        change_address: change.address,
+                        change_amount: txo.satoshi,
+                    });
+                    chained = Some((txo, txout));
+                    change = account.get_next_address(true, false, self.gap_limit)?;
                 }
-                account
+                utxos.into_iter().filter(|u| last.contains(&u.0.outpoint)).chain(chained).collect()
             }
         };
-        if !opt.is_already_created {
-            self.notify.subaccount_new(opt.subaccount);
-        }
-        account.info()
-    }
 
-    pub fn discover_subaccount(&self, opt: DiscoverAccountOpt) -> Result<bool, Error> {
-        discover_account(
-            &self.url,
-            self.proxy.as_deref(),
-            &opt.xpub,
-            opt.script_type,
-            self.gap_limit,
-        )
-    }
+        let change_address = elements::Address::from_str(&change.address)?;
+        let pegout_script = pegout::pegout_script(mainchain, &address);
+        let built = pegout::build_pegout_tx(
+            utxos,
+            pegout_script,
+            opt.satoshi,
+            &change_address,
+            policy_asset,
+            fee_rate,
+            &policy,
+        )?;
 
-    pub fn get_next_subaccount(&self, opt: GetNextAccountOpt) -> Result<u32, Error> {
-        let (_, next_account) = get_last_next_account_nums(
-            self.accounts.read()?.keys().copied().collect(),
-            opt.script_type,
-        );
-        Ok(next_account)
+        Ok(CreatePegoutResult {
+            transaction: elements::encode::serialize_hex(&built.tx),
+            mainchain_address: address.to_string(),
+            satoshi: opt.satoshi,
+            fee: built.fee,
+            fee_rate,
+            transaction_vsize: built.vsize,
+            transaction_inputs: built
+                .used_utxos
+                .into_iter()
+                .map(TryInto::try_into)
+                .collect::<Result<_, _>>()?,
+            change_address: change.address,
+            change_amount: built.change_amount,
+            change_decision: built.change_decision,
+            consolidation_steps,
+        })
     }
 
-    pub fn get_last_empty_subaccount(&self, opt: GetLastEmptyAccountOpt) -> Result<u32, Error> {
-        let (last_account, next_account) = get_last_next_account_nums(
-            self.accounts.read()?.keys().copied().collect(),
-            opt.script_type,
-        );
-        match last_account {
-            Some(last_account) => {
-                if self.get_account(last_account)?.info()?.bip44_discovered {
-                    Ok(next_account)
-                } else {
-                    Ok(last_account)
+    /// Plan the transactions sending every utxo of every subaccount to the
+    /// destination wallet, to be confirmed by the user and executed with
+    /// [`Self::execute_wallet_migration`].
+    ///
+    /// The utxos are split across transactions within the maximum standard
+    /// weight. On Liquid each transaction sends a single asset, the ones of
+    /// other assets than L-BTC spend an L-BTC utxo too to pay their fee.
+    pub fn create_wallet_migration(
+        &self,
+        opt: &CreateWalletMigrationOpt,
+    ) -> Result<WalletMigrationPlan, Error> {
+        let min_fee_rate = self.get_min_fee_rate()?;
+        let fee_rate = match opt.fee_rate {
+            Some(fee_rate) => fee_rate,
+            None => self.default_fee_rate()?,
+        };
+        ensure!(fee_rate >= min_fee_rate, Error::FeeRateBelowMinimum);
+        let policy_asset = match self.network.id() {
+            NetworkId::Elements(_) => Some(self.network.policy_asset_id()?),
+            NetworkId::Bitcoin(_) => None,
+        };
+
+        let mut utxos = vec![];
+        {
+            let store = self.store()?;
+            let store_read = store.read()?;
+            // Green subaccounts are watch-only, their funds can't be moved
+            for account in self.get_accounts()?.into_iter().filter(|a| a.green().is_none()) {
+                let acc_store = store_read.account_cache(account.num())?;
+                for outpoint in account.unspents()? {
+                    utxos.push(account.txo(&outpoint, acc_store)?);
                 }
             }
-            None => Ok(next_account),
         }
+        ensure!(!utxos.is_empty(), Error::InsufficientFunds);
+
+        let policy = self.network.policy();
+        let groups = migration::group_utxos(utxos, policy_asset, policy.max_standard_tx_weight)?;
+        let needed =
+            groups.iter().map(|inputs| migration::destinations_needed(inputs, policy_asset)).sum();
+        let mut destinations = self.migration_destinations(opt, needed)?.into_iter();
+
+        let mut steps = vec![];
+        for inputs in groups {
+            let count = migration::destinations_needed(&inputs, policy_asset);
+            let addresses: Vec<_> = destinations.by_ref().take(count).collect();
+            let built = migration::build_migration_tx(
+                &inputs,
+                &addresses,
+                policy_asset,
+                fee_rate,
+                &policy,
+            )?;
+            steps.push(WalletMigrationStep {
+                transaction: built.tx.serialize().to_lower_hex_string(),
+                fee: built.fee,
+                transaction_vsize: built.vsize,
+                transaction_inputs: inputs
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<_, _>>()?,
+                outputs: built
+                    .outputs
+                    .into_iter()
+                    .map(|(address, satoshi, asset)| WalletMigrationOutput {
+                        address: address.to_string(),
+                        satoshi,
+                        asset_id: asset.map(|a| a.to_string()),
+                    })
+                    .collect(),
+            });
+        }
+        Ok(WalletMigrationPlan {
+            id: migration::plan_id(&steps),
+            fee_rate,
+            fee: steps.iter().map(|s| s.fee).sum(),
+            steps,
+        })
     }
 
-    pub fn get_block_height(&self) -> Result<u32, Error> {
-        Ok(self.store()?.read()?.cache.tip_height())
+    /// The first `count` destination addresses of a wallet migration
+    fn migration_destinations(
+        &self,
+        opt: &CreateWalletMigrationOpt,
+        count: usize,
+    ) -> Result<Vec<BEAddress>, Error> {
+        let network_id = self.network.id();
+        if let Some(xpub) = &opt.destination_xpub {
+            let (is_mainnet, script_type, xpub) =
+                gdk_common::slip132::decode_from_slip132_string(xpub)?;
+            ensure!(
+                is_mainnet == self.network.mainnet,
+                Error::Generic("destination xpub of another network".into())
+            );
+            let chain = xpub.ckd_pub(&EC, 0.into())?;
+            return (0..count as u32)
+                .map(|index| {
+                    let public_key = chain.ckd_pub(&EC, index.into())?.to_pub();
+                    account::key_address(
+                        &public_key,
+                        script_type,
+                        network_id,
+                        opt.destination_master_blinding_key.as_ref(),
+                    )
+                })
+                .collect();
+        }
+        ensure!(
+            opt.destination_addresses.len() >= count,
+            Error::Generic(format!(
+                "the migration needs {} destination addresses, {} given",
+                count,
+                opt.destination_addresses.len()
+            ))
+        );
+        opt.destination_addresses[..count]
+            .iter()
+            .map(|address| address::parse_address(address, network_id))
+            .collect()
     }
 
-    pub fn update_subaccount(&mut self, opt: UpdateAccountOpt) -> Result<bool, Error> {
-        self.get_account(opt.subaccount)?.set_settings(opt)
-    }
+    /// Sign and broadcast in order the transactions of a wallet migration
+    /// `plan`, returned by [`Self::create_wallet_migration`].
+    ///
+    /// The progress is persisted in the store: executing the plan again,
+    /// after an interruption, skips the transactions already broadcast and
+    /// broadcasts the ones already signed as they were. The transactions are
+    /// confirmed by the user with the plan, they are not checked against the
+    /// spend limits.
+    pub fn execute_wallet_migration(
+        &mut self,
+        plan: &WalletMigrationPlan,
+    ) -> Result<ExecuteWalletMigrationResult, Error> {
+        ensure!(
+            migration::plan_id(&plan.steps) == plan.id,
+            Error::Generic("the migration plan has been modified".into())
+        );
+        let mut migration = match self.store()?.read()?.wallet_migration() {
+            Some(migration) if migration.plan.id == plan.id => migration.clone(),
+            _ => WalletMigration::new(plan.clone()),
+        };
 
-    pub fn get_transactions(&self, opt: &GetTransactionsOpt) -> Result<TxsResult, Error> {
-        let mut txs = self.get_account(opt.subaccount)?.list_tx(opt)?;
-        for tx in txs.iter_mut() {
-            for output in tx.outputs.iter_mut() {
-                if !output.is_relevant {
-                    // Update the output with the information necessary for bumping
-                    if let Ok(data) = self.get_scriptpubkey_data(&output.script_pubkey) {
-                        // This is an output belonging to the wallet, but not to opt.subaccount
-                        output.subaccount = data.subaccount;
-                        output.pointer = data.pointer;
-                        output.is_internal = data.is_internal;
-                        output.address_type = data.address_type;
-                    }
+        let mut txhashes = vec![];
+        let mut skipped = 0;
+        for i in 0..plan.steps.len() {
+            let signed = match &migration.signed[i] {
+                Some(signed) => BETransaction::from_hex(signed, self.network.id())?,
+                None => {
+                    let tx =
+                        BETransaction::from_hex(&plan.steps[i].transaction, self.network.id())?;
+                    let signed = self.sign_wallet_transaction(tx)?;
+                    migration.signed[i] = Some(signed.serialize().to_lower_hex_string());
+                    self.store()?.write()?.set_wallet_migration(migration.clone())?;
+                    signed
                 }
+            };
+            let txid = signed.txid();
+            txhashes.push(txid.to_string());
+            if migration.broadcast[i] {
+                skipped += 1;
+                continue;
+            }
+            // Broadcast before the interruption, but not recorded
+            if self.server_client()?.transaction_get_raw(&txid.into_bitcoin()).is_ok() {
+                info!("wallet migration step {} already broadcast: {}", i, txid);
+                skipped += 1;
+            } else {
+                info!("wallet migration step {}: {}", i, txid);
+                self.broadcast_transaction(&signed.serialize().to_lower_hex_string())?;
             }
+            migration.broadcast[i] = true;
+            self.store()?.write()?.set_wallet_migration(migration.clone())?;
         }
-        Ok(TxsResult(txs))
+        Ok(ExecuteWalletMigrationResult {
+            txhashes,
+            skipped,
+        })
     }
 
-    pub fn get_transaction_hex(&self, txid: &str) -> Result<String, Error> {
-        let txid = BETxid::from_hex(txid, self.network.id())?;
-        let store = self.store()?;
-        let store = store.read()?;
-        if let Ok(entry) = store.get_tx_entry(&txid) {
-            Ok(entry.tx.serialize().to_lower_hex_string())
-        } else {
-            let client = self.url.build_client(self.proxy.as_deref(), None)?;
-            Ok(client.transaction_get_raw(&txid.into_bitcoin())?.to_lower_hex_string())
-            // FIXME: cache the fetched transaction
+    /// Sign with the session signer all the wallet inputs of `tx`, of any
+    /// subaccount, and finalize it
+    fn sign_wallet_transaction(&self, tx: BETransaction) -> Result<BETransaction, Error> {
+        let signer = self.signer.clone().ok_or(Error::MissingSigner)?;
+        let (mut psbt, subaccounts) = {
+            let store = self.store()?;
+            let store_read = store.read()?;
+            let accounts = self.accounts.read()?;
+            let wallet = psbt::Wallet {
+                accounts: &accounts,
+                store: &store_read,
+            };
+            let wallet_data = wallet.wallet_data(&tx);
+            let subaccounts: BTreeSet<u32> =
+                wallet_data.0.iter().flatten().map(|(_, txo)| txo.subaccount).collect();
+            (psbt::create_psbt(tx, wallet_data, &[])?, subaccounts)
+        };
+        for subaccount in subaccounts {
+            let account = self.get_account(subaccount)?;
+            let signed =
+                psbt::sign_psbt(&psbt, self.network.id(), &account, signer.as_ref(), None, false)?;
+            psbt = signed.psbt;
         }
+        psbt::finalize_psbt(&psbt, self.network.id())
     }
 
-    pub fn get_scriptpubkey_data(&self, script_pubkey: &str) -> Result<ScriptPubKeyData, Error> {
-        let script = BEScript::from_hex(script_pubkey, self.network.id())?;
-        let store = self.store()?;
-        let store = store.read()?;
-        let accounts = self.get_accounts()?;
-        for account in accounts.iter() {
-            let account_cache = store.account_cache(account.num())?;
-            if let Ok(path) = account_cache.get_path(&script) {
-                let (is_internal, pointer) = parse_path(path)?;
-                return Ok(ScriptPubKeyData {
-                    subaccount: account.num(),
-                    branch: 1,
-                    pointer: pointer,
-                    subtype: 0,
-                    is_internal: is_internal,
-                    address_type: account.script_type().to_string(),
+    /// Return the balance of the subaccount.
+    ///
+    /// Without confirmation or confidentiality requirements it's the running
+    /// balance kept by the store, otherwise it's computed from the unspent outputs.
+    /// If `opt.asset_id` is set, only the balance for that asset is returned.
+    /// The confirmation requirements default to the subaccount and wallet
+    /// settings, see [`Self::required_confs`].
+    pub fn get_balance(&self, opt: &GetBalanceOpt) -> Result<Balances, Error> {
+        let (num_confs, trust_own_unconfirmed) =
+            self.required_confs(opt.subaccount, opt.num_confs, opt.trust_own_unconfirmed)?;
+        if num_confs == 0 && trust_own_unconfirmed && !opt.confidential_utxos_only.unwrap_or(false)
+        {
+            validate_asset_filter(self.network.id(), opt.asset_id.as_deref())?;
+            let store = self.store()?;
+            let store_read = store.read()?;
+            let acc_store = store_read.account_cache(opt.subaccount)?;
+            if let Some(utxo_balances) = &acc_store.utxo_balances {
+                let balances = utxo_balances.balances();
+                return Ok(match &opt.asset_id {
+                    None => balances
+                        .iter()
+                        .map(|(asset_id, satoshi)| (asset_id.clone(), *satoshi as i64))
+                        .collect(),
+                    Some(asset_id) => {
+                        let satoshi = balances.get(asset_id).copied().unwrap_or(0);
+                        [(asset_id.clone(), satoshi as i64)].into()
+                    }
                 });
             }
         }
-        return Err(Error::ScriptPubkeyNotFound);
-    }
-
-    pub fn set_transaction_memo(&self, txid: &str, memo: &str) -> Result<(), Error> {
-        let txid = BETxid::from_hex(txid, self.network.id())?;
-        if memo.len() > 1024 {
-            return Err(Error::Generic("Too long memo (max 1024)".into()));
-        }
-        self.store()?.write()?.insert_memo(txid, memo)?;
-
-        Ok(())
-    }
-
-    fn set_recent_spent_utxos(&self, tx: &BETransaction) -> Result<(), Error> {
-        let mut recent_spent_utxos = self.recent_spent_utxos.write()?;
-        (*recent_spent_utxos).extend(tx.previous_outputs());
-        Ok(())
-    }
-
-    pub fn broadcast_transaction(&mut self, tx_hex: &str) -> Result<String, Error> {
-        let transaction = BETransaction::from_hex(&tx_hex, self.network.id())?;
-
-        info!("broadcast_transaction {:#?}", transaction.txid());
-        let client = self.url.build_client(self.proxy.as_deref(), None)?;
-        let hex = Vec::<u8>::from_hex(tx_hex)?;
-        let txid = client.transaction_broadcast_raw(&hex)?;
-        self.set_recent_spent_utxos(&transaction)?;
-        Ok(format!("{}", txid))
-    }
-
-    /// The estimates are returned as an array of 25 elements. Each element is
-    /// an integer representing the fee estimate expressed as satoshi per 1000
-    /// bytes. The first element is the minimum relay fee as returned by the
-    /// network, while the remaining elements are the current estimates to use
-    /// for a transaction to confirm from 1 to 24 blocks.
-    pub fn get_fee_estimates(&mut self) -> Result<Vec<FeeEstimate>, Error> {
-        let mut fee_fetched_at = self.fee_fetched_at.lock()?;
-        if *fee_fetched_at + FEE_ESTIMATE_INTERVAL > SystemTime::now() {
-            // Skip network call
-            Ok(self.store()?.read()?.fee_estimates())
-        } else {
-            let min_rate = self.network.id().default_min_fee_rate();
-            let fee_estimates = try_get_fee_estimates(
-                &self.url.build_client(self.proxy.as_deref(), None)?,
-                min_rate,
-            )
-            .unwrap_or_else(|_| vec![FeeEstimate(min_rate); 25]);
-            self.store()?.write()?.cache.fee_estimates = fee_estimates.clone();
-            *fee_fetched_at = SystemTime::now();
-            Ok(fee_estimates)
-        }
-        //TODO better implement default
+        let unspent_opt = GetUnspentOpt {
+            subaccount: opt.subaccount,
+            num_confs: Some(num_confs),
+            confidential_utxos_only: opt.confidential_utxos_only,
+            all_coins: None,
+            asset_id: opt.asset_id.clone(),
+            trust_own_unconfirmed: Some(trust_own_unconfirmed),
+            asset_precisions: HashMap::new(),
+            include_provenance: false,
+        };
+        let mut balances: Balances = self
+            .get_unspent_outputs(&unspent_opt)?
+            .0
+            .into_iter()
+            .map(|(asset_id, utxos)| (asset_id, utxos.iter().map(|u| u.satoshi as i64).sum()))
+            .collect();
+        if let Some(asset_id) = &opt.asset_id {
+            balances.entry(asset_id.clone()).or_insert(0);
+        }
+        Ok(balances)
     }
 
-    pub fn get_min_fee_rate(&self) -> Result<u64, Error> {
-        let default_min_rate = self.network.id().default_min_fee_rate();
-        let min_rate = self.store()?.read()?.min_fee_rate();
-        Ok(min_rate.max(default_min_rate))
+    /// The balance of the subaccount once the block at `opt.height` was
+    /// connected, zero for the assets it held none of
+    ///
+    /// It's computed from [`Self::get_unspent_outputs_at`], without asking
+    /// the server.
+    pub fn get_balance_at(&self, opt: &GetAtHeightOpt) -> Result<Balances, Error> {
+        let mut balances: Balances = self
+            .get_unspent_outputs_at(opt)?
+            .0
+            .into_iter()
+            .map(|(asset_id, utxos)| (asset_id, utxos.iter().map(|u| u.satoshi as i64).sum()))
+            .collect();
+        let asset_id = match (&opt.asset_id, self.network.id()) {
+            (Some(asset_id), _) => asset_id.clone(),
+            (None, NetworkId::Bitcoin(_)) => "btc".to_string(),
+            (None, NetworkId::Elements(_)) => self.network.policy_asset.clone().unwrap_or_default(),
+        };
+        balances.entry(asset_id).or_insert(0);
+        Ok(balances)
     }
 
-    /// Return the settings or None if the store is not loaded (not logged in)
-    pub fn get_settings(&self) -> Option<Settings> {
-        Some(self.store().ok()?.read().ok()?.get_settings().unwrap_or_default())
+    fn parse_address(&self, address: &str) -> Result<BEAddress, Error> {
+        address::parse_address(address, self.network.id())
     }
 
-    pub fn change_settings(&mut self, value: &Value) -> Result<(), Error> {
-        let mut settings = self.get_settings().ok_or_else(|| Error::StoreNotLoaded)?;
-        settings.update(value)?;
-        self.store()?.write()?.insert_settings(Some(settings.clone()))?;
-        self.notify.settings(&settings);
-        Ok(())
+    pub fn get_address_data(&self, opt: AddressDataRequest) -> Result<AddressDataResult, Error> {
+        let address = self.parse_address(&opt.address)?;
+        self.get_accounts()?
+            .into_iter()
+            .filter_map(|a| a.get_address_data(&address).ok())
+            .next()
+            .ok_or(Error::ScriptPubkeyNotFound)
     }
 
-    pub fn get_available_currencies(
-        &mut self,
-        params: &GetAvailableCurrenciesParams,
-    ) -> Result<Value, Error> {
-        let currencies = match &self.available_currencies {
-            Some(map) => map,
-
-            None => self.available_currencies.get_or_insert(fetch_available_currencies(
-                &self.build_request_agent()?,
-                &params.url,
-            )?),
-        };
+    /// Every payment received by an address of `opt.subaccount`, from the store
+    pub fn get_address_payments(
+        &self,
+        opt: &GetAddressPaymentsOpt,
+    ) -> Result<AddressPayments, Error> {
+        let account = self.get_account(opt.subaccount)?;
+        let (address, is_internal, pointer) =
+            self.wallet_address(&account, opt.address.as_deref(), opt.pointer, opt.is_internal)?;
+        Ok(AddressPayments {
+            payments: account.address_payments(&address.script_pubkey())?,
+            address: address.to_string(),
+            pointer,
+            is_internal,
+        })
+    }
 
-        let all = currencies.values().flatten().collect::<BTreeSet<_>>();
+    /// The address of `account` given, or if None the one at `pointer`,
+    /// with whether it is internal and its pointer
+    fn wallet_address(
+        &self,
+        account: &Account,
+        address: Option<&str>,
+        pointer: Option<u32>,
+        is_internal: bool,
+    ) -> Result<(BEAddress, bool, u32), Error> {
+        match (address, pointer) {
+            (Some(address), _) => {
+                let address = self.parse_address(address)?;
+                let path =
+                    account.get_address_path(&address).map_err(|_| Error::ScriptPubkeyNotFound)?;
+                let (is_internal, pointer) = parse_path(&path)?;
+                Ok((address, is_internal, pointer))
+            }
+            (None, Some(pointer)) => {
+                Ok((account.derive_address(is_internal, pointer)?, is_internal, pointer))
+            }
+            (None, None) => Err(Error::Generic("address or pointer required".into())),
+        }
+    }
 
-        Ok(json!({ "all": all, "per_exchange": &currencies }))
+    /// Compare the cached scripts of every subaccount with the ones derived
+    /// from the subaccount xpub, see [`Account::audit_derivations`]
+    ///
+    /// Repaired scripts are subscribed to by the next sync, which is started
+    /// right away if the session is syncing.
+    pub fn audit_derivations(
+        &mut self,
+        opt: &AuditDerivationsOpt,
+    ) -> Result<DerivationAudit, Error> {
+        let mut audit = DerivationAudit::default();
+        for account in self.get_accounts()? {
+            let (checked, mismatches) = account.audit_derivations(opt.sample, opt.repair)?;
+            audit.checked += checked;
+            audit.mismatches.extend(mismatches);
+        }
+        let syncing = !self.handles.is_empty() || self.managed_sync.is_some();
+        if opt.repair && !audit.mismatches.is_empty() && syncing {
+            self.join_threads();
+            self.start_threads()?;
+        }
+        Ok(audit)
     }
 
-    pub fn get_unspent_outputs(&self, opt: &GetUnspentOpt) -> Result<GetUnspentOutputs, Error> {
-        let mut unspent_outputs: HashMap<String, Vec<UnspentOutput>> = HashMap::new();
+    /// Check the receive address at `opt.pointer` against a compromised
+    /// signer or server
+    ///
+    /// The address is derived again from the account xpub in the store, and
+    /// its history asked to the `address_verification_server` of the network,
+    /// if any, to match the one known by the wallet. A failed check is
+    /// reported in the result, not as an error.
+    pub fn verify_receive_address(
+        &self,
+        opt: &VerifyReceiveAddressOpt,
+    ) -> Result<ReceiveAddressVerification, Error> {
         let account = self.get_account(opt.subaccount)?;
+        let address = account.derive_address(opt.is_internal, opt.pointer)?;
+        let script_pubkey = address.script_pubkey();
 
-        let store = self.store()?;
-        let store_read = store.read()?;
-        let acc_store = store_read.account_cache(opt.subaccount)?;
-        let height = store_read.cache.tip_height();
-
-        let num_confs = opt.num_confs.unwrap_or(0);
-        let confidential_utxos_only = opt.confidential_utxos_only.unwrap_or(false);
+        let cached = account.cached_script(opt.is_internal, opt.pointer)?;
+        let derivation = match account.derive_stored_address(opt.is_internal, opt.pointer)? {
+            _ if cached.as_ref().is_some_and(|cached| cached != &script_pubkey) => {
+                AddressCheck::failed("the address differs from the one given before".into())
+            }
+            Some(stored) if stored.to_string() != address.to_string() => {
+                AddressCheck::failed(format!("the stored xpub derives {}", stored))
+            }
+            Some(_) => AddressCheck::passed(),
+            None => AddressCheck::skipped("multisig addresses are not of a single xpub".into()),
+        };
 
-        for outpoint in account.unspents()? {
-            let utxo = account.txo(&outpoint, acc_store)?;
-            let confirmations = match utxo.height {
-                None | Some(0) => 0,
-                Some(h) => (height + 1).saturating_sub(h),
-            };
-            if num_confs > confirmations || (confidential_utxos_only && !utxo.is_confidential()) {
-                continue;
+        let address_match = match &opt.address {
+            Some(given) if given == &address.to_string() => AddressCheck::passed(),
+            Some(given) => {
+                AddressCheck::failed(format!("{} is not the address at {}", given, opt.pointer))
             }
-            let asset_id = match &utxo.txoutsecrets {
-                None => "btc".to_string(),
-                Some(s) => s.asset.to_string(),
-            };
-            (*unspent_outputs.entry(asset_id).or_insert(vec![])).push(utxo.try_into()?);
+            None => AddressCheck::skipped("no address given".into()),
+        };
+
+        let server_history = match &self.network.address_verification_server {
+            Some(server) => self.verify_address_history(&account, server, &script_pubkey),
+            None => AddressCheck::skipped("no verification server".into()),
+        };
+
+        let verified = [&derivation, &address_match, &server_history]
+            .iter()
+            .all(|check| check.status != AddressCheckStatus::Failed);
+        Ok(ReceiveAddressVerification {
+            address: address.to_string(),
+            pointer: opt.pointer,
+            is_internal: opt.is_internal,
+            derivation,
+            address_match,
+            server_history,
+            verified,
+        })
+    }
+
+    /// Check `server` knows the same transactions of `script_pubkey` as the
+    /// store of `account`
+    fn verify_address_history(
+        &self,
+        account: &Account,
+        server: &str,
+        script_pubkey: &BEScript,
+    ) -> AddressCheck {
+        let history = ElectrumUrl::from_str(server)
+            .and_then(|url| {
+                let resolver = Resolver::new(&self.network);
+                url.build_client(self.proxy.as_deref(), None, &resolver)
+            })
+            .and_then(|client| {
+                Ok(client.script_get_history(&script_pubkey.clone().into_bitcoin())?)
+            });
+        let history = match history {
+            Ok(history) => history,
+            Err(e) => return AddressCheck::failed(format!("cannot ask {}: {}", server, e)),
+        };
+        let stored = match account.stored_script(script_pubkey) {
+            Ok(stored) => stored,
+            Err(e) => return AddressCheck::failed(e.to_string()),
+        };
+        let net = self.network.id();
+        let server_txs: HashSet<BETxid> =
+            history.iter().map(|tx| tx.tx_hash.into_net(net)).collect();
+        let stored_txs: HashSet<BETxid> = stored.txs.keys().cloned().collect();
+        if server_txs == stored_txs {
+            AddressCheck::passed()
+        } else {
+            AddressCheck::failed(format!(
+                "{} has {} transactions of the address, the wallet {}",
+                server,
+                server_txs.len(),
+                stored_txs.len()
+            ))
         }
-        Ok(GetUnspentOutputs(unspent_outputs))
     }
 
-    pub fn get_address_data(&self, opt: AddressDataRequest) -> Result<AddressDataResult, Error> {
-        let address = match self.network.id() {
-            NetworkId::Bitcoin(_) => {
-                BEAddress::Bitcoin(bitcoin::Address::from_str(&opt.address)?.assume_checked())
-            }
-            NetworkId::Elements(_) => {
-                BEAddress::Elements(elements::Address::from_str(&opt.address)?)
+    /// Compare what the server and the store know of a script of the
+    /// wallet, without changing the store
+    ///
+    /// Meant for support: a script whose store view differs from the server
+    /// one while their statuses match won't be fixed by a sync.
+    pub fn diagnose_script(&self, opt: &DiagnoseScriptOpt) -> Result<ScriptDiagnosis, Error> {
+        let account = self.get_account(opt.subaccount)?;
+        let (address, is_internal, pointer) =
+            self.wallet_address(&account, opt.address.as_deref(), opt.pointer, opt.is_internal)?;
+        let script_pubkey = address.script_pubkey();
+        let script = script_pubkey.clone().into_bitcoin();
+        let stored = account.stored_script(&script_pubkey)?;
+
+        let client = self.server_client()?;
+        let history = client.script_get_history(&script)?;
+        let unspent = client.script_list_unspent(&script)?;
+
+        let net = self.network.id();
+        let server_txs: HashMap<BETxid, u32> =
+            history.iter().map(|tx| (tx.tx_hash.into_net(net), tx.height.max(0) as u32)).collect();
+        let server_status = (!history.is_empty()).then(|| {
+            account::compute_script_status(
+                history.iter().map(|tx| (BETxid::Bitcoin(tx.tx_hash), tx.height)),
+            )
+        });
+        let mut only_on_server = vec![];
+        let mut height_mismatches = vec![];
+        for (txid, server_height) in server_txs.iter() {
+            match stored.txs.get(txid) {
+                None => only_on_server.push(txid.to_string()),
+                Some(height) if height.unwrap_or(0) != *server_height => {
+                    height_mismatches.push(HeightMismatch {
+                        txhash: txid.to_string(),
+                        server_height: *server_height,
+                        store_height: height.unwrap_or(0),
+                    })
+                }
+                Some(_) => {}
             }
-        };
-        self.get_accounts()?
-            .into_iter()
-            .filter_map(|a| a.get_address_data(&address).ok())
-            .next()
-            .ok_or(Error::ScriptPubkeyNotFound)
+        }
+        let mut only_in_store: Vec<_> = stored
+            .txs
+            .keys()
+            .filter(|txid| !server_txs.contains_key(txid))
+            .map(ToString::to_string)
+            .collect();
+
+        let outpoint = |txid: &BETxid, vout: u32| format!("{}:{}", txid, vout);
+        let server_utxos: HashSet<String> = unspent
+            .iter()
+            .map(|utxo| outpoint(&utxo.tx_hash.into_net(net), utxo.tx_pos as u32))
+            .collect();
+        let store_utxos: HashSet<String> =
+            stored.utxos.iter().map(|utxo| outpoint(&utxo.txid(), utxo.vout())).collect();
+        let mut utxos_only_on_server: Vec<_> =
+            server_utxos.difference(&store_utxos).cloned().collect();
+        let mut utxos_only_in_store: Vec<_> =
+            store_utxos.difference(&server_utxos).cloned().collect();
+
+        only_on_server.sort();
+        only_in_store.sort();
+        height_mismatches.sort_by(|a, b| a.txhash.cmp(&b.txhash));
+        utxos_only_on_server.sort();
+        utxos_only_in_store.sort();
+        Ok(ScriptDiagnosis {
+            address: address.to_string(),
+            pointer,
+            is_internal,
+            script_hash: script.to_electrum_scripthash().to_string(),
+            subscribed: self
+                .subscriptions
+                .as_ref()
+                .is_some_and(|subscriptions| subscriptions.is_subscribed(&script)),
+            server_status: server_status.map(|status| format!("{:x}", status)),
+            store_status: stored.status.map(|status| format!("{:x}", status)),
+            only_on_server,
+            only_in_store,
+            height_mismatches,
+            utxos_only_on_server,
+            utxos_only_in_store,
+        })
     }
 
+    #[deprecated(note = "use `get_block_status`, telling the server tip from the local headers")]
     pub fn block_status(&self) -> Result<(u32, BEBlockHash), Error> {
         let store = self.store()?;
         let store_read = store.read()?;
@@ -1219,13 +4894,39 @@ impl ElectrumSession {
         Ok(tip)
     }
 
+    /// The tip reported by the server and the height of the SPV headers
+    /// chain, which lags behind while the headers are downloaded
+    pub fn get_block_status(&self) -> Result<BlockStatus, Error> {
+        let (server_tip_height, server_tip_hash) = {
+            let store = self.store()?;
+            let store_read = store.read()?;
+            (store_read.cache.tip_height(), store_read.cache.tip_block_hash())
+        };
+        let local_header_height = match self.spv_height.load(Ordering::Relaxed) {
+            0 => None,
+            height => Some(height),
+        };
+        let in_sync = match self.network.spv_enabled.unwrap_or(false) && !self.network.liquid {
+            true => local_header_height.is_some_and(|height| height >= server_tip_height),
+            false => true,
+        };
+        let status = BlockStatus {
+            local_header_height,
+            server_tip_height,
+            server_tip_hash: server_tip_hash.to_string(),
+            in_sync,
+        };
+        info!("block status={:?}", status);
+        Ok(status)
+    }
+
     pub fn tx_status(&self) -> Result<u64, Error> {
         let mut opt = GetTransactionsOpt::default();
         opt.count = 100;
         let mut hasher = DefaultHasher::new();
         for account in self.get_accounts()? {
             opt.subaccount = account.num();
-            let txs = self.get_transactions(&opt)?.0;
+            let txs = self.get_transactions(&opt)?.transactions;
             for tx in txs.iter() {
                 std::hash::Hash::hash(&tx.txhash, &mut hasher);
             }
@@ -1245,24 +4946,132 @@ impl Tipper {
         let header = client.block_headers_pop_raw()?;
         Ok(header.is_some())
     }
+    /// Save the new tip if it changed, returning how it relates to the previous one
     pub fn update_cache_if_needed(
-        &self,
+        &mut self,
+        client: &Client,
         new_height: u32,
         new_header: BEBlockHeader,
-    ) -> Result<Option<HeightHeader>, Error> {
-        let do_update = match &self.store.read()?.cache.tip_ {
-            None => true,
+    ) -> Result<Option<TipUpdate>, Error> {
+        let current = self.store.read()?.cache.tip_.clone();
+        let (reorg, common_ancestor_height) = match current {
+            None => (false, None),
             Some((current_height, current_header)) => {
-                &new_height != current_height || &new_header != current_header
+                if new_height == current_height && new_header == current_header {
+                    return Ok(None);
+                }
+                self.remember(current_height, &current_header);
+                let current_hash = current_header.block_hash();
+                let reorg = if new_height == current_height + 1 {
+                    new_header.prev_block_hash() != current_hash
+                } else if new_height > current_height + 1 {
+                    self.server_hashes(client, &[current_height])?.get(&current_height)
+                        != Some(&current_hash)
+                } else {
+                    true
+                };
+                let common_ancestor_height = if reorg {
+                    self.common_ancestor(client, new_height.min(current_height))?
+                } else {
+                    None
+                };
+                (reorg, common_ancestor_height)
             }
         };
-        if do_update {
-            info!("saving in store new tip {:?}", new_height);
-            self.store.write()?.update_tip(new_height, new_header.clone())?;
-            Ok(Some((new_height, new_header).into()))
-        } else {
-            Ok(None)
+        if reorg {
+            // Forget the blocks that are no longer in the best chain
+            let first_stale = common_ancestor_height.map_or(0, |h| h + 1);
+            self.announced.split_off(&first_stale);
+        }
+
+        info!("saving in store new tip {:?} reorg:{}", new_height, reorg);
+        let mut store_write = self.store.write()?;
+        if reorg {
+            // The blocks of the transactions may have been replaced, their
+            // headers are downloaded again by the next sync
+            let first_stale = common_ancestor_height.map_or(0, |h| h + 1);
+            store_write.cache.headers.retain(|height, _| *height < first_stale);
+        }
+        store_write.update_tip(new_height, new_header.clone())?;
+        drop(store_write);
+        self.remember(new_height, &new_header);
+        Ok(Some(TipUpdate {
+            height: new_height,
+            header: new_header,
+            reorg,
+            common_ancestor_height,
+        }))
+    }
+
+    /// Check that the new liquid tip at `height` is signed by the federation
+    pub fn check_signblock(&self, height: u32, header: &BEBlockHeader) {
+        let header = match header {
+            BEBlockHeader::Elements(header) => header,
+            BEBlockHeader::Bitcoin(_) => return,
+        };
+        let status = match &self.signblock_checker {
+            None => SignblockStatus::Unchecked,
+            Some(checker) => match checker.verify(header) {
+                Ok(()) => SignblockStatus::Valid,
+                Err(e) => {
+                    warn!("tip {} is not signed by the federation: {:?}", height, e);
+                    SignblockStatus::Invalid
+                }
+            },
+        };
+        if let Ok(mut signblock) = self.signblock.lock() {
+            *signblock = Some(SignblockValidation {
+                status,
+                height,
+                block_hash: header.block_hash().to_string(),
+            });
+        }
+    }
+
+    /// Keep track of `header` and its parent hash as part of the notified chain
+    fn remember(&mut self, height: u32, header: &BEBlockHeader) {
+        self.announced.insert(height, header.block_hash());
+        if let Some(prev_height) = height.checked_sub(1) {
+            self.announced.insert(prev_height, header.prev_block_hash());
+        }
+        while self.announced.len() > MAX_ANNOUNCED_BLOCKS {
+            let first = *self.announced.keys().next().expect("not empty");
+            self.announced.remove(&first);
+        }
+    }
+
+    /// The block hashes at `heights` in the server chain
+    fn server_hashes(
+        &self,
+        client: &Client,
+        heights: &[u32],
+    ) -> Result<HashMap<u32, BEBlockHash>, Error> {
+        let raw_headers = client.batch_block_header_raw(heights.to_vec())?;
+        let mut hashes = HashMap::new();
+        for (height, raw) in heights.iter().zip(raw_headers) {
+            let header = BEBlockHeader::deserialize(&raw, self.network.id())?;
+            hashes.insert(*height, header.block_hash());
+        }
+        Ok(hashes)
+    }
+
+    /// The highest notified block at or below `max_height` that is still in the server chain.
+    ///
+    /// Returns None if it can't be determined exactly from the recently notified blocks.
+    fn common_ancestor(&self, client: &Client, max_height: u32) -> Result<Option<u32>, Error> {
+        let known: Vec<u32> = self.announced.range(..=max_height).map(|(h, _)| *h).collect();
+        if known.is_empty() {
+            return Ok(None);
+        }
+        let server = self.server_hashes(client, &known)?;
+        for height in known.iter().rev() {
+            if server.get(height) == self.announced.get(height) {
+                // Exact only if the block right above was checked and differs
+                let exact = *height == max_height || self.announced.contains_key(&(height + 1));
+                return Ok(exact.then_some(*height));
+            }
         }
+        Ok(None)
     }
 }
 
@@ -1281,6 +5090,12 @@ impl Headers {
     }
 
     pub fn get_proofs(&mut self, client: &Client) -> Result<usize, Error> {
+        let signblock_status = self.signblock.lock()?.as_ref().map(|signblock| signblock.status);
+        if signblock_status == Some(SignblockStatus::Invalid) {
+            // The proofs could be of blocks not signed by the federation
+            warn!("not verifying proofs until the tip is signed by the federation");
+            return Ok(0);
+        }
         let mut proofs_done = 0;
         let account_nums = self.store.read()?.account_nums();
 
@@ -1307,33 +5122,53 @@ impl Headers {
                 .collect();
             drop(store_read);
 
-            let mut txs_verified = HashMap::new();
-            for (txid, height) in needs_proof {
-                let verified = match client
-                    .transaction_get_merkle(&txid.into_bitcoin(), height as usize)
-                {
-                    Ok(proof) => match &self.checker {
-                        ChainOrVerifier::Chain(chain) => chain
-                            .verify_tx_proof(txid.ref_bitcoin().unwrap(), height, proof)
-                            .is_ok(),
-                        ChainOrVerifier::Verifier(verifier) => {
-                            if let Some(BEBlockHeader::Elements(header)) =
-                                self.store.read()?.cache.headers.get(&height)
-                            {
-                                verifier
-                                    .verify_tx_proof(txid.ref_elements().unwrap(), proof, &header)
-                                    .is_ok()
-                            } else {
-                                false
-                            }
+            // fetch the proofs, then verify them in the worker pool
+            let proofs: Vec<_> = needs_proof
+                .into_iter()
+                .map(|(txid, height)| {
+                    match client.transaction_get_merkle(&txid.into_bitcoin(), height as usize) {
+                        Ok(proof) => (txid, height, Some(proof)),
+                        Err(e) => {
+                            warn!("failed fetching merkle inclusion proof for {}: {:?}", txid, e);
+                            (txid, height, None)
+                        }
+                    }
+                })
+                .collect();
+            let headers: HashMap<u32, BEBlockHeader> = match &self.checker {
+                ChainOrVerifier::Chain(_) => HashMap::new(),
+                ChainOrVerifier::Verifier(_) => {
+                    let store_read = self.store.read()?;
+                    proofs
+                        .iter()
+                        .filter_map(|(_, height, _)| {
+                            Some((*height, store_read.cache.headers.get(height)?.clone()))
+                        })
+                        .collect()
+                }
+            };
+            let checker = &self.checker;
+            let results = pool::map(proofs, |(txid, height, proof)| {
+                let verified = match (checker, proof) {
+                    (_, None) => false,
+                    (ChainOrVerifier::Chain(chain), Some(proof)) => {
+                        chain.verify_tx_proof(txid.ref_bitcoin().unwrap(), height, proof).is_ok()
+                    }
+                    (ChainOrVerifier::Verifier(verifier), Some(proof)) => {
+                        if let Some(BEBlockHeader::Elements(header)) = headers.get(&height) {
+                            verifier
+                                .verify_tx_proof(txid.ref_elements().unwrap(), proof, header)
+                                .is_ok()
+                        } else {
+                            false
                         }
-                    },
-                    Err(e) => {
-                        warn!("failed fetching merkle inclusion proof for {}: {:?}", txid, e);
-                        false
                     }
                 };
+                (txid, verified)
+            });
 
+            let mut txs_verified = HashMap::new();
+            for (txid, verified) in results {
                 if verified {
                     info!("proof for {} verified!", txid);
                     txs_verified.insert(txid, SPVVerifyTxResult::Verified);
@@ -1386,46 +5221,361 @@ impl Headers {
     }
 }
 
+/// A receive address paid again, see
+/// [`Notification::AddressReuse`](gdk_common::model::Notification::AddressReuse)
+struct AddressReuse {
+    subaccount: u32,
+    address: String,
+    pointer: u32,
+    txid: bitcoin::Txid,
+    previous_txids: Vec<bitcoin::Txid>,
+}
+
+/// A confirmed transaction whose block was removed, see
+/// [`Notification::TransactionReorged`](gdk_common::model::Notification::TransactionReorged)
+struct TxReorg {
+    subaccount: u32,
+    txid: bitcoin::Txid,
+    height: u32,
+    reorg_count: u32,
+}
+
+/// A wallet transaction that appeared, confirmed or disappeared during a sync
+struct TxUpdate {
+    tx: BETransaction,
+    height: Option<u32>,
+    removed: bool,
+
+    /// The transaction was not in the store before
+    new: bool,
+}
+
+/// The details of the broadcast of `tx_hex`, just acknowledged by `client`
+/// of `server`, asking it if it has the transaction in its mempool
+fn broadcast_details(
+    client: &Client,
+    tx_hex: &str,
+    txid: &Txid,
+    server: &str,
+    now: u64,
+) -> BroadcastDetails {
+    let in_mempool = match client.transaction_get_raw(txid) {
+        Ok(_) => Some(true),
+        Err(electrum_client::Error::Protocol(_)) => Some(false),
+        Err(e) => {
+            warn!("cannot check the mempool for {}: {:?}", txid, e);
+            None
+        }
+    };
+    BroadcastDetails {
+        transaction: tx_hex.to_string(),
+        broadcast_at: now,
+        server: server.to_string(),
+        in_mempool,
+    }
+}
+
 #[derive(Default)]
 struct DownloadTxResult {
     txs: Vec<(BETxid, BETransaction)>,
     unblinds: Vec<(elements::OutPoint, elements::TxOutSecrets)>,
     is_previous: HashSet<BETxid>,
+
+    /// The number of transactions fetched from the server
+    fetched: u64,
+
+    /// The number of outputs unblinded
+    unblinded: u64,
 }
 
-impl Syncer {
-    /// Sync the wallet
-    ///
-    /// Return a vector of transaction notifications to emit and a vector of
-    /// accounts that have been synced.
-    pub fn sync(
+impl Syncer {
+    /// Broadcast again the queued transactions, returning whether any has
+    /// been broadcast
+    ///
+    /// The transactions rejected by the server are marked as expired.
+    fn replay_broadcasts(&self, client: &Client) -> Result<bool, Error> {
+        let now = util::now() / 1_000_000;
+        let queued = self.store.write()?.broadcasts_to_retry(now, PENDING_BROADCAST_MAX_AGE)?;
+        let mut broadcast = false;
+        for queued in queued {
+            let pending = &queued.pending;
+            let transaction = BETransaction::from_hex(&pending.transaction, self.network.id())?;
+            let hex = Vec::<u8>::from_hex(&pending.transaction)?;
+            match client.transaction_broadcast_raw(&hex) {
+                Ok(txid) => {
+                    info!("queued transaction {} broadcast", pending.txhash);
+                    self.recent_spent_utxos.write()?.extend(transaction.previous_outputs());
+                    let details =
+                        broadcast_details(client, &pending.transaction, &txid, &self.server, now);
+                    let mut store = self.store.write()?;
+                    store.remove_pending_broadcast(&pending.txhash)?;
+                    if queued.spent > 0 {
+                        store.insert_spend(transaction.txid(), queued.spent, now)?;
+                    }
+                    store.insert_broadcast(&transaction.txid(), details)?;
+                    broadcast = true;
+                }
+                Err(e) => {
+                    let e = Error::from(e);
+                    if e.is_connection_error() {
+                        // Try again on the next step
+                        return Err(e);
+                    }
+                    warn!("queued transaction {} rejected {:?}", pending.txhash, e);
+                    self.store.write()?.expire_broadcast(&pending.txhash)?;
+                }
+            }
+        }
+        Ok(broadcast)
+    }
+
+    /// Sync the wallet
+    ///
+    /// Return a vector of transaction notifications to emit and a vector of
+    /// accounts that have been synced.
+    pub fn sync(
+        &self,
+        client: &Client,
+        last_statuses: &mut ScriptStatuses,
+        first_sync: bool,
+    ) -> Result<SyncResult, Error> {
+        let accounts = self.accounts.read().unwrap();
+        let mut updated_txs: HashMap<BETxid, TxUpdate> = HashMap::new();
+
+        // Up to `parallelism` accounts are synced at once, scanning their chains concurrently
+        let parallelism =
+            self.network.sync_parallelism.unwrap_or(DEFAULT_SYNC_PARALLELISM).max(1) as usize;
+        let running = AtomicUsize::new(0);
+        let max_running = AtomicUsize::new(0);
+        let sync_span = Span::current();
+        let sync_account = |account: &Account| {
+            let _span = timed(info_span!(
+                parent: &sync_span,
+                "sync_account",
+                subaccount = account.num(),
+                elapsed_ms = Empty
+            ));
+            let now_running = running.fetch_add(1, Ordering::Relaxed) + 1;
+            max_running.fetch_max(now_running, Ordering::Relaxed);
+            let result =
+                self.sync_account(account, client, last_statuses, first_sync, parallelism > 1);
+            running.fetch_sub(1, Ordering::Relaxed);
+            result
+        };
+        let accounts_list: Vec<&Account> = accounts.values().collect();
+        let mut account_syncs = vec![];
+        for chunk in accounts_list.chunks(parallelism) {
+            if chunk.len() == 1 {
+                account_syncs.push(sync_account(chunk[0]));
+                continue;
+            }
+            thread::scope(|scope| {
+                let handles: Vec<_> =
+                    chunk.iter().map(|account| scope.spawn(|| sync_account(account))).collect();
+                for handle in handles {
+                    account_syncs
+                        .push(handle.join().expect("Couldn't join on the associated thread"));
+                }
+            });
+        }
+
+        let mut histories_fetched = 0;
+        let mut txs_fetched = 0;
+        let mut outputs_unblinded = 0;
+        let mut reorgs = vec![];
+        for account_sync in account_syncs {
+            let account_sync = account_sync?;
+            last_statuses.extend(account_sync.last_statuses);
+            updated_txs.extend(account_sync.updated_txs);
+            reorgs.extend(account_sync.reorgs);
+            histories_fetched += account_sync.histories_fetched;
+            txs_fetched += account_sync.txs_fetched;
+            outputs_unblinded += account_sync.outputs_unblinded;
+        }
+
+        // Transactions confirmed while offline would get a later rate
+        if !first_sync {
+            if let Err(e) = self.record_confirmation_rates(&updated_txs) {
+                warn!("cannot record the confirmation exchange rates {:?}", e);
+            }
+        }
+
+        self.empty_recent_spent_utxos()?;
+        let mut account_nums: Vec<u32> = accounts.keys().copied().collect();
+        account_nums.sort();
+
+        let address_reuses = self.find_address_reuses(&accounts, &updated_txs)?;
+        let expected_payments = self.settle_expected_payments(&accounts, &updated_txs)?;
+
+        // TODO: skip this computation if it's the first sync (no transaction notifications)
+        let tx_ntfs = self.create_tx_notifications(updated_txs, &account_nums)?;
+
+        Ok(SyncResult {
+            tx_ntfs,
+            address_reuses,
+            reorgs,
+            expected_payments,
+            accounts: account_nums,
+            histories_fetched,
+            txs_fetched,
+            outputs_unblinded,
+            parallel_syncs: max_running.into_inner() as u64,
+        })
+    }
+
+    /// The receive addresses paid by the new transactions that other
+    /// transactions paid before
+    fn find_address_reuses(
+        &self,
+        accounts: &HashMap<u32, Account>,
+        updated_txs: &HashMap<BETxid, TxUpdate>,
+    ) -> Result<Vec<AddressReuse>, Error> {
+        let store_read = self.store.read()?;
+        let mut reuses = vec![];
+        for (txid, update) in updated_txs.iter().filter(|(_, update)| update.new) {
+            for account in accounts.values() {
+                let acc_store = store_read.account_cache(account.num())?;
+                let mut pointers = HashSet::new();
+                for vout in 0..update.tx.output_len() as u32 {
+                    let script_pubkey = update.tx.output_script(vout);
+                    let (is_internal, pointer) = match acc_store.paths.get(&script_pubkey) {
+                        Some(path) => parse_path(path)?,
+                        None => continue,
+                    };
+                    if is_internal || !pointers.insert(pointer) {
+                        continue;
+                    }
+                    let mut previous_txids: Vec<bitcoin::Txid> = acc_store
+                        .heights
+                        .keys()
+                        .filter(|other| *other != txid)
+                        .filter(|other| {
+                            acc_store
+                                .all_txs
+                                .get(other)
+                                .is_some_and(|txe| txe.tx.creates_script_pubkey(&script_pubkey))
+                        })
+                        .map(|other| other.into_bitcoin())
+                        .collect();
+                    if previous_txids.is_empty() {
+                        continue;
+                    }
+                    previous_txids.sort();
+                    reuses.push(AddressReuse {
+                        subaccount: account.num(),
+                        address: account.derive_address(false, pointer)?.to_string(),
+                        pointer,
+                        txid: txid.into_bitcoin(),
+                        previous_txids,
+                    });
+                }
+            }
+        }
+        Ok(reuses)
+    }
+
+    /// Settle the pending payments expected to the receive addresses paid by
+    /// the new transactions
+    fn settle_expected_payments(
+        &self,
+        accounts: &HashMap<u32, Account>,
+        updated_txs: &HashMap<BETxid, TxUpdate>,
+    ) -> Result<Vec<ExpectedPayment>, Error> {
+        let pending: Vec<(u32, u32, String)> = self
+            .store
+            .read()?
+            .expected_payments()
+            .filter(|payment| payment.status == PaymentStatus::Pending)
+            .map(|payment| (payment.subaccount, payment.pointer, payment.asset_id.clone()))
+            .collect();
+        if pending.is_empty() {
+            return Ok(vec![]);
+        }
+        let now = util::now() / 1_000_000;
+        let mut settled = vec![];
+        for (txid, update) in updated_txs.iter().filter(|(_, update)| update.new && !update.removed)
+        {
+            for (subaccount, pointer, asset_id) in pending.iter() {
+                let account = match accounts.get(subaccount) {
+                    Some(account) => account,
+                    None => continue,
+                };
+                let received = {
+                    let store_read = self.store.read()?;
+                    let acc_store = store_read.account_cache(*subaccount)?;
+                    let script_pubkey = account.derive_address(false, *pointer)?.script_pubkey();
+                    let mut received = None;
+                    for vout in 0..update.tx.output_len() as u32 {
+                        if update.tx.output_script(vout) != script_pubkey {
+                            continue;
+                        }
+                        let asset = match update.tx.output_asset(vout, &acc_store.unblinded) {
+                            Some(asset) => asset.to_string(),
+                            None if update.tx.is_elements() => continue,
+                            None => "btc".to_string(),
+                        };
+                        if asset != *asset_id {
+                            continue;
+                        }
+                        let value = update.tx.output_value(vout, &acc_store.unblinded);
+                        *received.get_or_insert(0) += value.unwrap_or(0);
+                    }
+                    received
+                };
+                if let Some(received) = received {
+                    let mut store_write = self.store.write()?;
+                    let payment = store_write.settle_expected_payment(
+                        *subaccount,
+                        *pointer,
+                        txid,
+                        received,
+                        now,
+                    )?;
+                    settled.extend(payment);
+                }
+            }
+        }
+        Ok(settled)
+    }
+
+    /// Record the exchange rate of the transactions confirmed in the last
+    /// blocks, for the exports
+    fn record_confirmation_rates(
         &self,
-        client: &Client,
-        last_statuses: &mut ScriptStatuses,
-        first_sync: bool,
-    ) -> Result<SyncResult, Error> {
-        trace!("start sync");
-
-        let accounts = self.accounts.read().unwrap();
-        let mut updated_txs: HashMap<BETxid, BETransaction> = HashMap::new();
-
-        for account in accounts.values() {
-            self.sync_account(account, client, last_statuses, &mut updated_txs, first_sync)?;
+        updated_txs: &HashMap<BETxid, TxUpdate>,
+    ) -> Result<(), Error> {
+        let (tip_height, pricing) = {
+            let store_read = self.store.read()?;
+            (store_read.cache.tip_height(), store_read.get_settings().unwrap_or_default().pricing)
+        };
+        let confirmed: Vec<BETxid> = updated_txs
+            .iter()
+            .filter(|(_, update)| {
+                update.height.is_some_and(|height| {
+                    height > 0 && tip_height.saturating_sub(height) < CONFIRMATION_RATE_BLOCKS
+                })
+            })
+            .map(|(txid, _)| *txid)
+            .collect();
+        if confirmed.is_empty() {
+            return Ok(());
         }
-
-        self.empty_recent_spent_utxos()?;
-        let mut account_nums: Vec<u32> = accounts.keys().copied().collect();
-        account_nums.sort();
-
-        // TODO: skip this computation if it's the first sync (no transaction notifications)
-        let tx_ntfs = self.create_tx_notifications(updated_txs, &account_nums)?;
-
-        trace!("end sync");
-
-        Ok(SyncResult {
-            tx_ntfs,
-            accounts: account_nums,
-        })
+        let currency = Currency::from_str(pricing.currency())?;
+        let rate = price::current_rate(
+            &self.xr_cache,
+            self.price_source.as_deref(),
+            Pair::new(Currency::BTC, currency),
+            pricing.exchange(),
+            price::RATE_TTL,
+        );
+        if let Some((rate, _)) = rate {
+            let rate = ConfirmationRate {
+                currency: currency.to_string(),
+                rate,
+            };
+            self.store.write()?.insert_confirmation_rates(&confirmed, &rate)?;
+        }
+        Ok(())
     }
 
     /// Create the transaction notifications.
@@ -1438,12 +5588,13 @@ impl Syncer {
     /// performance penalty.
     fn create_tx_notifications(
         &self,
-        updated_txs: HashMap<BETxid, BETransaction>,
+        updated_txs: HashMap<BETxid, TxUpdate>,
         account_nums: &Vec<u32>,
     ) -> Result<Vec<TransactionNotification>, Error> {
         let mut tx_ntfs = Vec::<TransactionNotification>::new();
         let store_read = self.store.read()?;
-        for tx in updated_txs.values() {
+        for update in updated_txs.values() {
+            let tx = &update.tx;
             let mut tx_accounts = vec![];
             'account_loop: for account_num in account_nums {
                 let acc_store = store_read.account_cache(*account_num)?;
@@ -1474,11 +5625,19 @@ impl Syncer {
             if let Some(account_num) = tx_accounts.first() {
                 let acc_store = store_read.account_cache(*account_num)?;
                 let (satoshi, type_) = self.ntf_satoshi_type(&tx, &acc_store);
+                let satoshi_delta = tx.my_balance_changes(
+                    &acc_store.all_txs,
+                    &acc_store.paths,
+                    &acc_store.unblinded,
+                );
                 let ntf = TransactionNotification {
                     subaccounts: tx_accounts,
                     txid: tx.txid().into_bitcoin(),
                     satoshi,
                     type_,
+                    block_height: update.height,
+                    satoshi_delta: Some(satoshi_delta),
+                    removed: update.removed,
                 };
                 tx_ntfs.push(ntf);
             }
@@ -1486,126 +5645,121 @@ impl Syncer {
         Ok(tx_ntfs)
     }
 
-    fn sync_account(
+    /// Subscribe to the scripts of a chain of `account` up to the gap limit,
+    /// fetching the history of those whose status differs from the cached one
+    fn scan_chain(
         &self,
         account: &Account,
         client: &Client,
-        last_statuses: &mut ScriptStatuses,
-        updated_txs: &mut HashMap<BETxid, BETransaction>,
+        is_internal: bool,
+        last_statuses: &ScriptStatuses,
+        map_script_txids: &HashMap<bitcoin::ScriptBuf, HashSet<BETxid>>,
         first_sync: bool,
-    ) -> Result<(), Error> {
-        let map_script_txids = self.create_map_script_txids(account)?;
-        let mut new_statuses = ScriptStatuses::new();
+    ) -> Result<ChainScan, Error> {
         let cache_statuses = account.status()?;
-        let mut history_txs_id = HashSet::<BETxid>::new();
-        let mut heights_set = HashSet::new();
-        let mut txid_height = HashMap::<BETxid, _>::new();
-        let mut txids_to_remove = vec![];
-        let mut scripts = HashMap::new();
-        let mut last_used = Indexes::default();
-        let mut wallet_chains = vec![0, 1];
-        wallet_chains.shuffle(&mut thread_rng());
-        for i in wallet_chains {
-            let is_internal = i == 1;
-            let mut count_consecutive_empty = 0;
-            'outer: for batch_count in 0.. {
-                if !self.user_wants_to_sync.load(Ordering::Relaxed) {
-                    return Err(Error::UserDoesntWantToSync);
-                }
-                let batch = account.get_script_batch(is_internal, batch_count)?;
-                for (cached, _, path, script) in &batch {
-                    if !cached {
-                        scripts.insert(script.clone(), path.clone());
-                    }
+        let mut scan = ChainScan::default();
+        let mut count_consecutive_empty = 0;
+        for batch_count in 0.. {
+            if !self.user_wants_to_sync.load(Ordering::Relaxed) {
+                return Err(Error::UserDoesntWantToSync);
+            }
+            let batch = account.get_script_batch(is_internal, batch_count)?;
+            for (cached, _, path, script) in &batch {
+                if !cached {
+                    scan.scripts.insert(script.clone(), path.clone());
                 }
-                let b_scripts: Vec<_> =
-                    batch.iter().map(|(_, _, _, script)| script.clone().into_bitcoin()).collect();
-
-                // "script_subscribe" network calls
-                if first_sync {
-                    // During the first sync we haven't subscribed to any script yet, to minimize
-                    // the network calls we do a batch request.
-                    match client.batch_script_subscribe(b_scripts.iter().map(|s| s.as_script())) {
-                        Ok(v) => {
-                            for (status, b_script) in v.iter().zip(b_scripts) {
-                                if let Some(status) = status {
-                                    // First time script is subscribed, script is in at least one tx
-                                    last_statuses.insert(b_script, *status);
-                                } else {
-                                    // First time script is subscribed, script is not in any tx
-                                    if let Some(txids) = map_script_txids.get(&b_script) {
-                                        // The script has some txs in the cache, remove them
-                                        txids_to_remove.extend(txids);
-                                    }
-                                }
-                            }
-                        }
-                        Err(e) => return Err(Error::ClientError(e)),
-                    }
-                } else {
-                    // During syncs following the first one, we might highly likely hit Error::AlreadySubscribed,
-                    // which allows us to skip network calls. However this is not compatible with
-                    // the batch calls, since the return value is Result<Vec<_>, _> we can't
-                    // realize if multiple scripts are already subscribed. Thus here we do single
-                    // network calls, although if the subscription is still in place, the
-                    // iteration will not require a network call. This case should be the most
-                    // frequent one.
-                    for b_script in b_scripts {
-                        match client.script_subscribe(&b_script) {
-                            Ok(Some(status)) => {
-                                // Subscription dropped, created a new one, script is in at least 1 tx
-                                last_statuses.insert(b_script, status);
-                            }
-                            Ok(None) => {
-                                // Subscription dropped, created a new one, script is not in any tx
+            }
+            let b_scripts: Vec<_> =
+                batch.iter().map(|(_, _, _, script)| script.clone().into_bitcoin()).collect();
+
+            // "script_subscribe" network calls, the listener pops their notifications
+            self.subscriptions.subscribed(b_scripts.iter().cloned());
+            if first_sync {
+                // During the first sync we haven't subscribed to any script yet, to minimize
+                // the network calls we do a batch request.
+                match client.batch_script_subscribe(b_scripts.iter().map(|s| s.as_script())) {
+                    Ok(v) => {
+                        for (status, b_script) in v.iter().zip(b_scripts) {
+                            if let Some(status) = status {
+                                // First time script is subscribed, script is in at least one tx
+                                scan.last_statuses.insert(b_script, *status);
+                            } else {
+                                // First time script is subscribed, script is not in any tx
                                 if let Some(txids) = map_script_txids.get(&b_script) {
                                     // The script has some txs in the cache, remove them
-                                    txids_to_remove.extend(txids);
-                                }
-                            }
-                            Err(gdk_common::electrum_client::Error::AlreadySubscribed(_)) => {
-                                // Already subscribed for this script (no network call)
-                                if let Some(status) = client.script_pop(&b_script)? {
-                                    // There is an update, new txs for this script
-                                    last_statuses.insert(b_script, status);
-                                } else {
-                                    // There are no new transactions since last iteration
+                                    scan.txids_to_remove.extend(txids);
                                 }
                             }
-                            Err(e) => return Err(Error::ClientError(e)),
-                        };
+                        }
                     }
+                    Err(e) => return Err(Error::ClientError(e)),
                 }
-
-                let mut b_scripts_updated = vec![];
-                for (_, index, _, script) in batch {
-                    let b_script = script.into_bitcoin();
-                    match last_statuses.get(&b_script) {
-                        Some(last_status) => {
-                            // Script has a tx
-                            count_consecutive_empty = 0;
-                            if is_internal {
-                                last_used.internal = index;
-                            } else {
-                                last_used.external = index;
-                            }
-                            let cache_status = cache_statuses.get(&b_script);
-                            if Some(last_status) != cache_status {
-                                // Something has changed, get the history for this script
-                                b_scripts_updated.push(b_script);
+            } else {
+                // During syncs following the first one, we might highly likely hit Error::AlreadySubscribed,
+                // which allows us to skip network calls. However this is not compatible with
+                // the batch calls, since the return value is Result<Vec<_>, _> we can't
+                // realize if multiple scripts are already subscribed. Thus here we do single
+                // network calls, although if the subscription is still in place, the
+                // iteration will not require a network call. This case should be the most
+                // frequent one.
+                for b_script in b_scripts {
+                    match client.script_subscribe(&b_script) {
+                        Ok(Some(status)) => {
+                            // Subscription dropped, created a new one, script is in at least 1 tx
+                            scan.last_statuses.insert(b_script, status);
+                        }
+                        Ok(None) => {
+                            // Subscription dropped, created a new one, script is not in any tx
+                            if let Some(txids) = map_script_txids.get(&b_script) {
+                                // The script has some txs in the cache, remove them
+                                scan.txids_to_remove.extend(txids);
                             }
                         }
-                        None => {
-                            // Script never had a tx, initially and neither via updates
-                            count_consecutive_empty += 1;
-                            if count_consecutive_empty > self.gap_limit {
-                                // No need to sync further
-                                break 'outer;
+                        Err(gdk_common::electrum_client::Error::AlreadySubscribed(_)) => {
+                            // Already subscribed for this script (no network call)
+                            if let Some(status) = client.script_pop(&b_script)? {
+                                // There is an update, new txs for this script
+                                scan.last_statuses.insert(b_script, status);
+                            } else {
+                                // There are no new transactions since last iteration
                             }
                         }
+                        Err(e) => return Err(Error::ClientError(e)),
+                    };
+                }
+            }
+
+            let mut b_scripts_updated = vec![];
+            let mut gap_reached = false;
+            for (_, index, _, script) in batch {
+                let b_script = script.into_bitcoin();
+                let last_status =
+                    scan.last_statuses.get(&b_script).or_else(|| last_statuses.get(&b_script));
+                match last_status {
+                    Some(last_status) => {
+                        // Script has a tx
+                        count_consecutive_empty = 0;
+                        scan.last_used = index;
+                        if Some(last_status) != cache_statuses.get(&b_script) {
+                            // Something has changed, get the history for this script
+                            b_scripts_updated.push(b_script);
+                        }
+                    }
+                    None => {
+                        // Script never had a tx, initially and neither via updates
+                        count_consecutive_empty += 1;
+                        if count_consecutive_empty > self.gap_limit {
+                            // No need to sync further
+                            gap_reached = true;
+                            break;
+                        }
                     }
                 }
+            }
 
+            // Only the scripts whose status changed, or that got used
+            if !b_scripts_updated.is_empty() {
+                scan.histories_fetched += b_scripts_updated.len() as u64;
                 let history = client
                     .batch_script_get_history(b_scripts_updated.iter().map(|s| s.as_script()))?;
 
@@ -1613,7 +5767,7 @@ impl Syncer {
                     let txid_height_pairs =
                         history.iter().map(|tx| (BETxid::Bitcoin(tx.tx_hash), tx.height));
                     let status = account::compute_script_status(txid_height_pairs);
-                    new_statuses.insert(b_script.clone(), status);
+                    scan.new_statuses.insert(b_script.clone(), status);
 
                     let mut server_txids_for_this_script = HashSet::new();
 
@@ -1623,14 +5777,17 @@ impl Syncer {
                         // el.height =  0 means unconfirmed with confirmed parents
                         // but we threat those tx the same
                         let height = el.height.max(0);
-                        heights_set.insert(height as u32);
+                        if height > 0 && (height as u32) < self.ignore_below {
+                            continue;
+                        }
+                        scan.heights_set.insert(height as u32);
                         if height == 0 {
-                            txid_height.insert(el.tx_hash.into_net(net), None);
+                            scan.txid_height.insert(el.tx_hash.into_net(net), None);
                         } else {
-                            txid_height.insert(el.tx_hash.into_net(net), Some(height as u32));
+                            scan.txid_height.insert(el.tx_hash.into_net(net), Some(height as u32));
                         }
 
-                        history_txs_id.insert(el.tx_hash.into_net(net));
+                        scan.history_txs_id.insert(el.tx_hash.into_net(net));
 
                         server_txids_for_this_script.insert(el.tx_hash.into_net(net));
                     }
@@ -1641,27 +5798,92 @@ impl Syncer {
                     for txid in
                         cache_txids_for_this_script.difference(&server_txids_for_this_script)
                     {
-                        txids_to_remove.push(*txid);
+                        scan.txids_to_remove.push(*txid);
                     }
                 }
             }
+            if gap_reached {
+                break;
+            }
+        }
+        Ok(scan)
+    }
+
+    /// Sync `account`, fetching the history of the scripts whose status
+    /// differs from the cached one
+    ///
+    /// If `parallel` its external and internal chains are scanned concurrently.
+    fn sync_account(
+        &self,
+        account: &Account,
+        client: &Client,
+        last_statuses: &ScriptStatuses,
+        first_sync: bool,
+        parallel: bool,
+    ) -> Result<AccountSync, Error> {
+        let map_script_txids = self.create_map_script_txids(account)?;
+        let mut wallet_chains = vec![false, true];
+        wallet_chains.shuffle(&mut thread_rng());
+        let scan = |is_internal| {
+            self.scan_chain(
+                account,
+                client,
+                is_internal,
+                last_statuses,
+                &map_script_txids,
+                first_sync,
+            )
+        };
+        let scans = if parallel {
+            thread::scope(|scope| {
+                let handles: Vec<_> =
+                    wallet_chains.iter().map(|i| scope.spawn(move || scan(*i))).collect();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().expect("Couldn't join on the associated thread"))
+                    .collect::<Result<Vec<_>, Error>>()
+            })?
+        } else {
+            wallet_chains.iter().map(|i| scan(*i)).collect::<Result<Vec<_>, Error>>()?
+        };
+
+        let mut result = AccountSync::default();
+        let mut new_statuses = ScriptStatuses::new();
+        let mut history_txs_id = HashSet::<BETxid>::new();
+        let mut heights_set = HashSet::new();
+        let mut txid_height = HashMap::<BETxid, _>::new();
+        let mut txids_to_remove = vec![];
+        let mut scripts = HashMap::new();
+        let mut last_used = Indexes::default();
+        for (is_internal, scan) in wallet_chains.into_iter().zip(scans) {
+            result.last_statuses.extend(scan.last_statuses);
+            new_statuses.extend(scan.new_statuses);
+            history_txs_id.extend(scan.history_txs_id);
+            heights_set.extend(scan.heights_set);
+            txid_height.extend(scan.txid_height);
+            txids_to_remove.extend(scan.txids_to_remove);
+            scripts.extend(scan.scripts);
+            last_used[is_internal] = scan.last_used;
+            result.histories_fetched += scan.histories_fetched;
         }
+        let updated_txs = &mut result.updated_txs;
         let new_txs = self.download_txs(account.num(), &history_txs_id, &scripts, &client)?;
+        result.txs_fetched += new_txs.fetched;
+        result.outputs_unblinded += new_txs.unblinded;
         let headers = self.download_headers(account.num(), &heights_set, &client)?;
         let store_last_used = {
             let store_read = self.store.read()?;
             let acc_store = store_read.account_cache(account.num())?;
             acc_store.get_both_last_used()
         };
-        Ok(
-            if !new_txs.txs.is_empty()
-                || !headers.is_empty()
-                || store_last_used != last_used
-                || !scripts.is_empty()
-                || !txid_height.is_empty()
-                || !txids_to_remove.is_empty()
-            {
-                info!(
+        if !new_txs.txs.is_empty()
+            || !headers.is_empty()
+            || store_last_used != last_used
+            || !scripts.is_empty()
+            || !txid_height.is_empty()
+            || !txids_to_remove.is_empty()
+        {
+            info!(
                 "There are changes in the store new_txs:{:?} headers:{:?} txid_height:{:?} scripts:{:?} store_last_used_changed:{}",
                 new_txs.txs.iter().map(|tx| tx.0).collect::<Vec<_>>(),
                 headers,
@@ -1669,45 +5891,123 @@ impl Syncer {
                 scripts,
                 store_last_used != last_used
             );
-                let mut store_write = self.store.write()?;
-                store_write.cache.headers.extend(headers.into_iter().map(Into::into));
-
-                let acc_store = store_write.account_cache_mut(account.num())?;
-                acc_store.set_both_last_used(last_used);
-                acc_store
-                    .all_txs
-                    .extend(new_txs.txs.iter().cloned().map(|(txid, tx)| (txid, tx.into())));
-                acc_store.unblinded.extend(new_txs.unblinds);
-
-                for txid in txids_to_remove {
-                    acc_store.heights.remove(&txid);
+            let mut store_write = self.store.write()?;
+            store_write.cache.headers.extend(headers.into_iter().map(Into::into));
+
+            let acc_store = store_write.account_cache_mut(account.num())?;
+            acc_store.set_both_last_used(last_used);
+            acc_store
+                .all_txs
+                .extend(new_txs.txs.iter().cloned().map(|(txid, tx)| (txid, tx.into())));
+            acc_store.unblinded.extend(new_txs.unblinds);
+
+            // Transactions already in the store that got confirmed, that a reorg unconfirmed
+            // or moved to another block, or that are no longer returned by the server
+            // (evicted from the mempool or replaced)
+            let mut reorged = vec![];
+            for (txid, height) in txid_height.iter() {
+                let previous = acc_store.heights.get(txid);
+                if previous.is_some_and(|previous| previous != height) {
+                    if let Some(Some(previous_height)) = previous {
+                        // The proof of the block the transaction was in doesn't hold anymore
+                        reorged.push((*txid, *previous_height));
+                    }
+                    if let Some(tx) = acc_store.all_txs.get(txid) {
+                        updated_txs.insert(
+                            *txid,
+                            TxUpdate {
+                                tx: tx.tx.clone(),
+                                height: *height,
+                                removed: false,
+                                new: false,
+                            },
+                        );
+                    }
                 }
-
-                acc_store.heights.extend(txid_height.into_iter());
-                acc_store.scripts.extend(scripts.clone().into_iter().map(|(a, b)| (b, a)));
-                acc_store.paths.extend(scripts.into_iter());
-
-                if acc_store.script_statuses.is_none() {
-                    acc_store.script_statuses = Some(HashMap::new());
+            }
+            for (txid, previous_height) in reorged.iter() {
+                let reorgs = acc_store.record_reorg(*txid, *previous_height);
+                result.reorgs.push(TxReorg {
+                    subaccount: account.num(),
+                    txid: txid.into_bitcoin(),
+                    height: *previous_height,
+                    reorg_count: reorgs.count,
+                });
+            }
+            if !txid_height.is_empty() || !txids_to_remove.is_empty() {
+                acc_store.new_sync_generation(txid_height.keys());
+            }
+            let balance_txids: Vec<BETxid> =
+                txid_height.keys().chain(txids_to_remove.iter()).cloned().collect();
+            let mut removed = vec![];
+            for txid in txids_to_remove {
+                if acc_store.heights.remove(&txid).is_some() && !txid_height.contains_key(&txid) {
+                    removed.push(txid);
+                    if let Some(tx) = acc_store.all_txs.get(&txid) {
+                        updated_txs.insert(
+                            txid,
+                            TxUpdate {
+                                tx: tx.tx.clone(),
+                                height: None,
+                                removed: true,
+                                new: false,
+                            },
+                        );
+                    }
                 }
-                acc_store
-                    .script_statuses
-                    .as_mut()
-                    .expect("always some because created if None in previous line")
-                    .extend(new_statuses);
+            }
 
-                for tx in new_txs.txs.iter() {
-                    // Do not emit notifications for previous transactions that we fetched to
-                    // compute the fee.
-                    if !new_txs.is_previous.contains(&tx.0) {
-                        updated_txs.insert(tx.0, tx.1.clone());
-                    }
+            for tx in new_txs.txs.iter() {
+                // Do not emit notifications for previous transactions that we fetched to
+                // compute the fee.
+                if !new_txs.is_previous.contains(&tx.0) {
+                    updated_txs.insert(
+                        tx.0,
+                        TxUpdate {
+                            tx: tx.1.clone(),
+                            height: txid_height.get(&tx.0).copied().flatten(),
+                            removed: false,
+                            new: true,
+                        },
+                    );
                 }
+            }
 
-                store_write.flush()?;
-                drop(store_write);
-            },
-        )
+            acc_store.unmark_removed(txid_height.keys());
+            acc_store.heights.extend(txid_height.into_iter());
+            acc_store.scripts.extend(scripts.clone().into_iter().map(|(a, b)| (b, a)));
+            acc_store.paths.extend(scripts.into_iter());
+
+            // Caches written before the provenance was recorded get it for all their transactions
+            let provenance_txids: Vec<BETxid> = match acc_store.provenance {
+                None => acc_store.heights.keys().copied().collect(),
+                Some(_) => new_txs
+                    .txs
+                    .iter()
+                    .map(|(txid, _)| *txid)
+                    .filter(|txid| !new_txs.is_previous.contains(txid))
+                    .collect(),
+            };
+            acc_store.record_provenance(provenance_txids.iter());
+
+            if acc_store.script_statuses.is_none() {
+                acc_store.script_statuses = Some(HashMap::new());
+            }
+            acc_store
+                .script_statuses
+                .as_mut()
+                .expect("always some because created if None in previous line")
+                .extend(new_statuses);
+            acc_store.update_utxo_balances(balance_txids.iter());
+            acc_store.mark_removed(removed.iter());
+            for (txid, _) in reorged {
+                store_write.cache.txs_verif.remove(&txid);
+            }
+
+            store_write.flush()?;
+            drop(store_write);
+        }
+        Ok(result)
     }
 
     /// Create a map `script -> [txid]` of account owned script_pubkeys
@@ -1778,15 +6078,22 @@ impl Syncer {
         heights_set: &HashSet<u32>,
         client: &Client,
     ) -> Result<Vec<HeightHeader>, Error> {
-        let heights_in_db: HashSet<u32> = {
+        // Also the headers of the transactions already in the store, forgotten
+        // by a reorg
+        let heights_to_download: Vec<u32> = {
             let store_read = self.store.read()?;
             let acc_store = store_read.account_cache(account_num)?;
-            iter::once(0).chain(acc_store.heights.iter().filter_map(|(_, h)| *h)).collect()
+            let tx_heights = acc_store.heights.values().filter_map(|h| *h);
+            let heights: HashSet<u32> = heights_set
+                .iter()
+                .cloned()
+                .chain(tx_heights)
+                .filter(|h| *h != 0 && !store_read.cache.headers.contains_key(h))
+                .collect();
+            heights.into_iter().collect()
         };
 
         let mut result = vec![];
-        let heights_to_download: Vec<u32> =
-            heights_set.difference(&heights_in_db).cloned().collect();
         if !heights_to_download.is_empty() {
             let headers_bytes_downloaded =
                 client.batch_block_header_raw(heights_to_download.clone())?;
@@ -1813,7 +6120,6 @@ impl Syncer {
         client: &Client,
     ) -> Result<DownloadTxResult, Error> {
         let mut txs = vec![];
-        let mut unblinds = vec![];
         let mut is_previous = HashSet::new();
 
         let mut txs_in_db =
@@ -1822,53 +6128,34 @@ impl Syncer {
         let txs_to_download: Vec<bitcoin::Txid> =
             history_txs_id.difference(&txs_in_db).map(BETxidConvert::into_bitcoin).collect();
         if !txs_to_download.is_empty() {
-            let txs_bytes_downloaded = client.batch_transaction_get_raw(txs_to_download.iter())?;
-            let mut txs_downloaded: Vec<BETransaction> = vec![];
-            for vec in txs_bytes_downloaded {
-                let tx = BETransaction::deserialize(&vec, self.network.id())?;
-                txs_downloaded.push(tx);
-            }
+            let (txs_bytes_downloaded, fetched) = self.get_raw_txs(&txs_to_download, client)?;
+            let mut fetched_total = fetched;
+            let txs_downloaded = deserialize_txs(txs_bytes_downloaded, self.network.id())?;
             info!("txs_downloaded {:?}", txs_downloaded.len());
             let mut previous_txs_to_download = HashSet::new();
-            for tx in txs_downloaded.into_iter() {
-                let txid = tx.txid();
-                txs_in_db.insert(txid);
-
-                if let BETransaction::Elements(tx) = &tx {
-                    info!("compute OutPoint Unblinded");
-                    for (i, output) in tx.output.iter().enumerate() {
-                        let be_script = output.script_pubkey.clone().into_be();
-                        let store_read = self.store.read()?;
-                        let acc_store = store_read.account_cache(account_num)?;
-                        // could be the searched script it's not yet in the store, because created in the current run, thus it's searched also in the `scripts`
-                        if acc_store.paths.contains_key(&be_script)
-                            || scripts.contains_key(&be_script)
-                        {
-                            let vout = i as u32;
-                            let outpoint = elements::OutPoint {
-                                txid: tx.txid(),
-                                vout,
-                            };
-
-                            let unblinded = unblind_output(
-                                output.clone(),
-                                self.master_blinding.as_ref().unwrap(),
-                                Some(outpoint),
-                            );
-                            match unblinded {
-                                Ok(unblinded) => unblinds.push((outpoint, unblinded)),
-                                Err(e) => warn!("{} cannot unblind, ignoring (could be sender messed up with the blinding process) {}", outpoint, e),
-                            }
-                        }
-                    }
-                } else {
+            for tx in txs_downloaded.iter() {
+                txs_in_db.insert(tx.txid());
+                if let BETransaction::Bitcoin(_) = tx {
                     // download all previous output only for bitcoin (to calculate fee of incoming tx)
                     for previous_txid in tx.previous_output_txids() {
                         previous_txs_to_download.insert(previous_txid);
                     }
                 }
-                txs.push((txid, tx));
             }
+            let (unblinds, unblinded) = match self.master_blinding.as_ref() {
+                Some(master_blinding) => {
+                    info!("compute OutPoint Unblinded");
+                    unblind_txs(
+                        &self.store,
+                        account_num,
+                        &txs_downloaded,
+                        scripts,
+                        master_blinding,
+                    )?
+                }
+                None => (vec![], 0),
+            };
+            txs.extend(txs_downloaded.into_iter().map(|tx| (tx.txid(), tx)));
 
             let txs_to_download: Vec<bitcoin::Txid> = previous_txs_to_download
                 .difference(&txs_in_db)
@@ -1876,10 +6163,9 @@ impl Syncer {
                 .collect();
 
             if !txs_to_download.is_empty() {
-                let txs_bytes_downloaded =
-                    client.batch_transaction_get_raw(txs_to_download.iter())?;
-                for vec in txs_bytes_downloaded {
-                    let tx = BETransaction::deserialize(&vec, self.network.id())?;
+                let (txs_bytes_downloaded, fetched) = self.get_raw_txs(&txs_to_download, client)?;
+                fetched_total += fetched;
+                for tx in deserialize_txs(txs_bytes_downloaded, self.network.id())? {
                     let txid = tx.txid();
                     if !txs.iter().any(|t| &t.0 == &txid) {
                         is_previous.insert(txid);
@@ -1891,43 +6177,95 @@ impl Syncer {
                 txs,
                 unblinds,
                 is_previous,
+                fetched: fetched_total,
+                unblinded,
             })
         } else {
             Ok(DownloadTxResult::default())
         }
     }
-}
 
-fn fetch_available_currencies(
-    agent: &ureq::Agent,
-    url: &str,
-) -> Result<HashMap<String, Vec<Currency>>, Error> {
-    #[derive(serde::Deserialize)]
-    struct ExchangeInfos {
-        pairs: Vec<(Currency, Currency)>,
+    /// The raw transactions `txids` and the number of them fetched from the
+    /// server, see [`fetcher::TxFetcher::get`]
+    fn get_raw_txs(
+        &self,
+        txids: &[bitcoin::Txid],
+        client: &Client,
+    ) -> Result<(Vec<Arc<Vec<u8>>>, u64), Error> {
+        self.tx_fetcher.get(txids, |txids| Ok(client.batch_transaction_get_raw(txids)?))
     }
+}
 
-    let endpoint = format!("{url}/v0/venues");
-
-    let response = agent.get(&endpoint).call()?.into_json::<HashMap<String, ExchangeInfos>>()?;
+/// Deserialize raw transactions in the worker pool, straight from the buffers
+/// shared with the transaction cache
+fn deserialize_txs(raw_txs: Vec<Arc<Vec<u8>>>, id: NetworkId) -> Result<Vec<BETransaction>, Error> {
+    pool::map(raw_txs, |raw_tx| BETransaction::deserialize(&raw_tx, id)).into_iter().collect()
+}
 
-    let map = response.into_iter().map(|(exchange, infos)| {
-        let currencies = infos.pairs.into_iter().map(|(first, second)| {
-            // Either the first or the second currency in the pair must be
-            // fiat (but not both).
-            if !(first.is_fiat() ^ second.is_fiat()) {
-                panic!("Was expecting one currency in the pair to be Bitcoin, got {}-{} instead", first, second);
+/// Unblind the outputs of `txs` belonging to the account, in the worker pool,
+/// returning them and the number of outputs unblinded
+///
+/// The store is read once to select the outputs, it's not locked while
+/// unblinding. The outputs already unblinded in the store are skipped.
+fn unblind_txs(
+    store: &Store,
+    account_num: u32,
+    txs: &[BETransaction],
+    scripts: &HashMap<BEScript, DerivationPath>,
+    master_blinding: &MasterBlindingKey,
+) -> Result<(Vec<(elements::OutPoint, elements::TxOutSecrets)>, u64), Error> {
+    let mut outputs = vec![];
+    {
+        let store_read = store.read()?;
+        let acc_store = store_read.account_cache(account_num)?;
+        for tx in txs {
+            if let BETransaction::Elements(tx) = tx {
+                let txid = tx.txid();
+                for (vout, output) in tx.output.iter().enumerate() {
+                    let be_script = output.script_pubkey.clone().into_be();
+                    let outpoint = elements::OutPoint {
+                        txid,
+                        vout: vout as u32,
+                    };
+                    if acc_store.unblinded.contains_key(&outpoint) {
+                        continue;
+                    }
+                    // could be the searched script it's not yet in the store, because created in the current run, thus it's searched also in the `scripts`
+                    if acc_store.paths.contains_key(&be_script) || scripts.contains_key(&be_script)
+                    {
+                        outputs.push((outpoint, output.clone()));
+                    }
+                }
             }
-            if first.is_fiat() { first } else { second }
-        }).collect::<Vec<Currency>>();
+        }
+    }
 
-        (exchange, currencies)
-    }).collect();
+    let unblinding = outputs.len() as u64;
+    Ok((unblind_outputs(outputs, master_blinding), unblinding))
+}
 
-    Ok(map)
+/// Unblind `outputs` in the worker pool, leaving out the ones that cannot be
+/// unblinded
+pub(crate) fn unblind_outputs(
+    outputs: Vec<(elements::OutPoint, elements::TxOut)>,
+    master_blinding: &MasterBlindingKey,
+) -> Vec<(elements::OutPoint, elements::TxOutSecrets)> {
+    let unblinded = pool::map(outputs, |(outpoint, output)| {
+        (outpoint, unblind_output(output, master_blinding, Some(outpoint)))
+    });
+    unblinded
+        .into_iter()
+        .filter_map(|(outpoint, unblinded)| match unblinded {
+            Ok(unblinded) => Some((outpoint, unblinded)),
+            Err(e) => {
+                warn!("{} cannot unblind, ignoring (could be sender messed up with the blinding process) {}", outpoint, e);
+                None
+            }
+        })
+        .collect()
 }
 
-fn unblind_output(
+pub(crate) fn unblind_output(
     output: elements::TxOut,
     master_blinding: &MasterBlindingKey,
     outpoint: Option<elements::OutPoint>,
@@ -1959,8 +6297,14 @@ fn unblind_output(
 }
 
 fn wait_or_close(user_wants_to_sync: &Arc<AtomicBool>, interval: u32) -> bool {
+    wait_while(|| user_wants_to_sync.load(Ordering::Relaxed), interval)
+}
+
+/// Wait `interval` seconds, returning true as soon as `running` does not
+/// hold anymore
+fn wait_while(running: impl Fn() -> bool, interval: u32) -> bool {
     for _ in 0..(interval * 2) {
-        if !user_wants_to_sync.load(Ordering::Relaxed) {
+        if !running() {
             // Threads should stop, close
             return true;
         }
@@ -1969,6 +6313,91 @@ fn wait_or_close(user_wants_to_sync: &Arc<AtomicBool>, interval: u32) -> bool {
     false
 }
 
+/// An element of a transaction not belonging to the wallet, as far as it can be decoded
+/// from `tx`, the transaction creating it if known
+fn foreign_inout(
+    tx: Option<&BETransaction>,
+    vout: u32,
+    is_output: bool,
+    pt_idx: u32,
+    id: NetworkId,
+) -> GetTxInOut {
+    let (address, script_pubkey) = match tx {
+        Some(tx) => {
+            (tx.output_address(vout, id).unwrap_or_default(), tx.output_script(vout).to_hex())
+        }
+        None => (String::new(), String::new()),
+    };
+    GetTxInOut {
+        address,
+        address_type: String::new(),
+        is_output,
+        is_relevant: false,
+        is_spent: !is_output,
+        subaccount: 0,
+        is_internal: false,
+        pointer: 0,
+        pt_idx,
+        satoshi: 0,
+        subtype: 0,
+        asset_id: None,
+        asset_blinder: None,
+        amount_blinder: None,
+        is_blinded: None,
+        is_confidential: None,
+        blinding_key: None,
+        unconfidential_address: None,
+        script_pubkey,
+        label: None,
+    }
+}
+
+/// Complete `inout` with the explicit value and asset, or the commitments if
+/// it could not be unblinded, taken from output `vout` of `tx`
+fn tx_details_inout(
+    mut inout: GetTxInOut,
+    tx: Option<&BETransaction>,
+    vout: u32,
+    prevout: Option<BEOutPoint>,
+) -> TxDetailsInOut {
+    let mut commitments = None;
+    if let Some(tx) = tx.filter(|tx| tx.is_elements() && inout.asset_id.is_none()) {
+        match (tx.output_explicit_value(vout), tx.output_explicit_asset(vout)) {
+            (Some(satoshi), Some(asset)) => {
+                inout.satoshi = satoshi;
+                inout.asset_id = Some(asset.to_string());
+            }
+            _ => commitments = tx.output_commitments_hex(vout),
+        }
+    } else if let Some(tx) = tx.filter(|tx| !tx.is_elements()) {
+        inout.satoshi = tx.output_explicit_value(vout).unwrap_or(inout.satoshi);
+    }
+    let (asset_commitment, value_commitment) = commitments.unzip();
+    let script = tx.map(|tx| tx.output_script(vout));
+    TxDetailsInOut {
+        inout,
+        prevout_txhash: prevout.as_ref().map(|o| o.txid().to_string()),
+        prevout_pt_idx: prevout.as_ref().map(|o| o.vout()),
+        asset_commitment,
+        value_commitment,
+        script_type: script.as_ref().map(|s| s.output_type().to_string()),
+        op_return_data: script.and_then(|s| s.op_return_data()).map(|d| d.to_lower_hex_string()),
+    }
+}
+
+/// Check an asset filter passed to the listing methods: Bitcoin only has "btc",
+/// Liquid assets are identified by their hex asset id.
+fn validate_asset_filter(network: NetworkId, asset_id: Option<&str>) -> Result<(), Error> {
+    match (network, asset_id) {
+        (_, None) | (NetworkId::Bitcoin(_), Some("btc")) => Ok(()),
+        (NetworkId::Bitcoin(_), Some(_)) => Err(Error::InvalidAssetId),
+        (NetworkId::Elements(_), Some(asset_id)) => {
+            elements::AssetId::from_str(asset_id).map_err(|_| Error::InvalidAssetId)?;
+            Ok(())
+        }
+    }
+}
+
 // Some pin_data encrypt the bare mnemonic, not a json.
 // If we cannot deserialize the plaintext into a json,
 // we attempt to deserialize it into a bare mnemonic.
@@ -1991,16 +6420,104 @@ fn bare_mnemonic_from_utf8(decrypted: &[u8]) -> Result<Credentials, Error> {
     })
 }
 
+/// The identifiers of the wallet of `opt.credentials` on `opt.network`, as
+/// returned at login, computed without a session
+pub fn get_wallet_identifier(opt: &GetWalletIdentifierOpt) -> Result<WalletIdentifier, Error> {
+    let master_xpub = match &opt.credentials {
+        WalletIdentifierCredentials::Mnemonic(credentials) => {
+            credentials.master_xpub(&opt.network)?
+        }
+        WalletIdentifierCredentials::MasterXpub {
+            master_xpub,
+        } => *master_xpub,
+    };
+    if master_xpub.network != opt.network.bip32_network() {
+        return Err(Error::Common(gdk_common::error::Error::MismatchingNetwork));
+    }
+    Ok(WalletIdentifier {
+        wallet_hash_id: opt.network.wallet_hash_id(&master_xpub),
+        xpub_hash_id: opt.network.xpub_hash_id(&master_xpub),
+    })
+}
+
 #[cfg(feature = "testing")]
 impl ElectrumSession {
     pub fn filter_events(&self, event: &str) -> Vec<Value> {
         self.notify.filter_events(event)
     }
+
+    /// Drop `txid` from the store of `subaccount`, leaving the script
+    /// statuses as they are so that the sync doesn't fetch it again
+    pub fn forget_tx(&self, subaccount: u32, txid: &str) -> Result<(), Error> {
+        let txid = BETxid::from_hex(txid, self.network.id())?;
+        let store = self.store()?;
+        let mut store_write = store.write()?;
+        let acc_store = store_write.account_cache_mut(subaccount)?;
+        acc_store.heights.remove(&txid);
+        acc_store.all_txs.remove(&txid);
+        Ok(())
+    }
+}
+
+/// The height of the first block with a timestamp at least `timestamp` less
+/// [`MAX_FUTURE_BLOCK_TIME`], as the block timestamps are not in order, or
+/// the tip height if none
+fn height_at_time(client: &Client, timestamp: u64, network: NetworkId) -> Result<u32, Error> {
+    let target = timestamp.saturating_sub(MAX_FUTURE_BLOCK_TIME);
+    let (mut low, mut high) = (0, client.block_headers_subscribe_raw()?.height as u32);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        let header = BEBlockHeader::deserialize(&client.block_header_raw(mid as usize)?, network)?;
+        if (header.time() as u64) < target {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    Ok(low)
+}
+
+/// Add the timestamp of the new tip `header` to `network_time`, notifying if
+/// the local clock is found off the network
+fn sample_network_time(network_time: &NetworkTime, notify: &NativeNotif, header: &BEBlockHeader) {
+    if let Some(skew) = network_time.add_sample(header.time()) {
+        warn!("the local clock is off the network by {} seconds", skew);
+        notify.clock_skew(skew);
+    }
+}
+
+/// Display the receive `address` at `path` on the device of `signer`,
+/// failing if it computes a different one or doesn't answer within `timeout`
+fn confirm_address(
+    signer: Arc<dyn Signer>,
+    path: DerivationPath,
+    script_type: ScriptType,
+    network: NetworkId,
+    address: &str,
+    timeout: Duration,
+) -> Result<(), Error> {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(signer.display_address(&path, script_type, network));
+    });
+    let device = receiver.recv_timeout(timeout).map_err(|_| Error::DisplayAddressTimeout)??;
+    if device != address {
+        return Err(Error::AddressMismatch {
+            expected: address.to_string(),
+            device,
+        });
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::signer::SoftwareSigner;
+    use gdk_common::bitcoin::bip32::Xpub;
+    use gdk_common::session::Session;
+    use gdk_common::util::{ec_public_key_from_private_key, MasterBlindingKey};
+    use std::time::Instant;
 
     #[test]
     fn test_bare_mnemonic() {
@@ -2011,4 +6528,298 @@ mod test {
         assert!(bare_mnemonic_from_utf8(&format!("{}.", mnemonic).as_bytes()).is_err());
         assert!(bare_mnemonic_from_utf8(b"\x00\x9f\x92\x96").is_err());
     }
+
+    #[test]
+    fn test_asset_filter() {
+        let bitcoin = NetworkId::Bitcoin(bitcoin::Network::Regtest);
+        let liquid = NetworkId::Elements(network::ElementsNetwork::ElementsRegtest);
+        let asset_id = "5ac9f65c0efcc4775e0baec4ec03abdde22473cd3cf33c0419ca290e0751b225";
+
+        assert!(validate_asset_filter(bitcoin, None).is_ok());
+        assert!(validate_asset_filter(bitcoin, Some("btc")).is_ok());
+        assert!(validate_asset_filter(bitcoin, Some(asset_id)).is_err());
+        assert!(validate_asset_filter(liquid, None).is_ok());
+        assert!(validate_asset_filter(liquid, Some(asset_id)).is_ok());
+        assert!(validate_asset_filter(liquid, Some("btc")).is_err());
+    }
+
+    /// A signer answering after `delay`, displaying the address of another key if `malicious`
+    struct MockSigner {
+        software: SoftwareSigner,
+        malicious: bool,
+        delay: Duration,
+    }
+
+    impl Signer for MockSigner {
+        fn get_xpub(&self, path: &DerivationPath) -> Result<Xpub, Error> {
+            self.software.get_xpub(path)
+        }
+
+        fn sign_tx(
+            &self,
+            tx: &BETransaction,
+            prevouts: &[Option<BETxOut>],
+            paths: &[Option<DerivationPath>],
+            sighash_types: &[Option<u32>],
+        ) -> Result<Vec<Option<Vec<u8>>>, Error> {
+            self.software.sign_tx(tx, prevouts, paths, sighash_types)
+        }
+
+        fn sign_message(
+            &self,
+            path: &DerivationPath,
+            script_type: ScriptType,
+            message: &str,
+            format: MessageFormat,
+        ) -> Result<String, Error> {
+            self.software.sign_message(path, script_type, message, format)
+        }
+
+        fn get_master_blinding_key(&self) -> Result<MasterBlindingKey, Error> {
+            self.software.get_master_blinding_key()
+        }
+
+        fn display_address(
+            &self,
+            path: &DerivationPath,
+            script_type: ScriptType,
+            network: NetworkId,
+        ) -> Result<String, Error> {
+            thread::sleep(self.delay);
+            let path = match self.malicious {
+                true => path.child(1.into()),
+                false => path.clone(),
+            };
+            self.software.display_address(&path, script_type, network)
+        }
+    }
+
+    #[test]
+    fn test_confirm_address() {
+        let network = NetworkId::Bitcoin(bitcoin::Network::Regtest);
+        let liquid = NetworkId::Elements(network::ElementsNetwork::ElementsRegtest);
+        let path = DerivationPath::from_str("m/84'/1'/0'/0/5").unwrap();
+        let signer = |malicious, delay| -> Arc<dyn Signer> {
+            Arc::new(MockSigner {
+                software: SoftwareSigner::from_seed(&[1; 32], bitcoin::NetworkKind::Test).unwrap(),
+                malicious,
+                delay,
+            })
+        };
+        let timeout = Duration::from_secs(10);
+        let honest = signer(false, Duration::ZERO);
+        let address = honest.display_address(&path, ScriptType::P2wpkh, network).unwrap();
+        assert!(address.starts_with("bcrt1q"));
+        let confidential = honest.display_address(&path, ScriptType::P2wpkh, liquid).unwrap();
+        assert!(confidential.starts_with("el1"));
+
+        confirm_address(
+            honest.clone(),
+            path.clone(),
+            ScriptType::P2wpkh,
+            network,
+            &address,
+            timeout,
+        )
+        .unwrap();
+        confirm_address(honest, path.clone(), ScriptType::P2wpkh, liquid, &confidential, timeout)
+            .unwrap();
+
+        let malicious = signer(true, Duration::ZERO);
+        let err = confirm_address(
+            malicious,
+            path.clone(),
+            ScriptType::P2wpkh,
+            network,
+            &address,
+            timeout,
+        )
+        .unwrap_err();
+        match err {
+            Error::AddressMismatch {
+                expected,
+                device,
+            } => {
+                assert_eq!(expected, address);
+                assert_ne!(device, address);
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+
+        let slow = signer(false, Duration::from_secs(5));
+        let err = confirm_address(
+            slow,
+            path,
+            ScriptType::P2wpkh,
+            network,
+            &address,
+            Duration::from_millis(10),
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::DisplayAddressTimeout));
+        assert_eq!(err.to_gdk_code(), "id_timeout");
+    }
+
+    /// 200 confidential outputs to a script of `xpub`, in 20 transactions
+    fn confidential_txs(
+        xpub: &Xpub,
+        master_blinding: &MasterBlindingKey,
+    ) -> (Vec<BETransaction>, BEScript, elements::AssetId) {
+        use gdk_common::elements::confidential::{AssetBlindingFactor, ValueBlindingFactor};
+        use gdk_common::elements::{AddressParams, AssetId, LockTime, Transaction, TxOut};
+
+        let pk = bitcoin::PublicKey::from_slice(&xpub.public_key.serialize()).unwrap();
+        let address = elements::Address::p2wpkh(&pk, None, &AddressParams::ELEMENTS);
+        let blinding_key =
+            asset_blinding_key_to_ec_private_key(master_blinding, &address.script_pubkey());
+        let address = address.to_confidential(ec_public_key_from_private_key(blinding_key));
+        let asset = AssetId::from_slice(&[1; 32]).unwrap();
+        let spent = [elements::TxOutSecrets::new(
+            asset,
+            AssetBlindingFactor::zero(),
+            1_000_000,
+            ValueBlindingFactor::zero(),
+        )];
+        let txs = (0..20)
+            .map(|_| {
+                let output = (0..10)
+                    .map(|i| {
+                        TxOut::new_not_last_confidential(
+                            &mut thread_rng(),
+                            &EC,
+                            1_000 + i,
+                            address.clone(),
+                            asset,
+                            &spent,
+                        )
+                        .unwrap()
+                        .0
+                    })
+                    .collect();
+                BETransaction::Elements(Transaction {
+                    version: 2,
+                    lock_time: LockTime::ZERO,
+                    input: vec![],
+                    output,
+                })
+            })
+            .collect();
+        (txs, address.script_pubkey().into_be(), asset)
+    }
+
+    #[test]
+    fn test_unblind_txs_without_blocking_store() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let xpub = Xpub::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let cipher = xpub.to_cipher().unwrap();
+        let mut store = StoreMeta::new(dir.path(), &cipher, 1000).unwrap();
+        store.make_account(0, xpub, false).unwrap();
+        let store: Store = Arc::new(RwLock::new(store));
+
+        let master_blinding = MasterBlindingKey::from([7; 32]);
+        let (txs, script, asset) = confidential_txs(&xpub, &master_blinding);
+        let scripts: HashMap<BEScript, DerivationPath> =
+            [(script, DerivationPath::master())].into();
+
+        let done = AtomicBool::new(false);
+        let ((unblinds, unblinding), max_wait) = thread::scope(|scope| {
+            let unblinding = scope.spawn(|| {
+                let unblinds = unblind_txs(&store, 0, &txs, &scripts, &master_blinding).unwrap();
+                done.store(true, Ordering::Relaxed);
+                unblinds
+            });
+            // The store stays available, e.g. to a block notification
+            let mut max_wait = Duration::ZERO;
+            while !done.load(Ordering::Relaxed) {
+                let start = Instant::now();
+                drop(store.write().unwrap());
+                max_wait = max_wait.max(start.elapsed());
+                thread::sleep(Duration::from_millis(1));
+            }
+            (unblinding.join().unwrap(), max_wait)
+        });
+
+        assert_eq!(unblinding, 200);
+        assert_eq!(unblinds.len(), 200);
+        assert!(unblinds.iter().all(|(_, secrets)| secrets.asset == asset));
+        let total: u64 = unblinds.iter().map(|(_, secrets)| secrets.value).sum();
+        assert_eq!(total, 20 * (0..10).map(|i| 1_000 + i).sum::<u64>());
+        assert!(max_wait < Duration::from_millis(200), "store locked for {:?}", max_wait);
+    }
+
+    #[test]
+    fn test_unblinded_kept_across_logins() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let xpub = Xpub::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let cipher = xpub.to_cipher().unwrap();
+        let master_blinding = MasterBlindingKey::from([7; 32]);
+        let (txs, script, _) = confidential_txs(&xpub, &master_blinding);
+        let scripts: HashMap<BEScript, DerivationPath> =
+            [(script.clone(), DerivationPath::master())].into();
+        let open = || Arc::new(RwLock::new(StoreMeta::new(dir.path(), &cipher, 1000).unwrap()));
+
+        // First login, the outputs are unblinded and stored with the transactions
+        {
+            let store = open();
+            let mut store_write = store.write().unwrap();
+            store_write.cache.master_blinding = Some(master_blinding.clone());
+            store_write.make_account(0, xpub, false).unwrap();
+            drop(store_write);
+            let (unblinds, unblinding) =
+                unblind_txs(&store, 0, &txs, &scripts, &master_blinding).unwrap();
+            assert_eq!(unblinding, 200);
+            let mut store_write = store.write().unwrap();
+            let acc_store = store_write.account_cache_mut(0).unwrap();
+            acc_store.paths.insert(script, DerivationPath::master());
+            acc_store.all_txs.extend(txs.iter().map(|tx| (tx.txid(), tx.clone().into())));
+            acc_store.unblinded.extend(unblinds);
+            store_write.flush().unwrap();
+        }
+
+        // Second login, nothing is unblinded again
+        let store = open();
+        let (unblinds, unblinding) =
+            unblind_txs(&store, 0, &txs, &scripts, &master_blinding).unwrap();
+        assert_eq!(unblinding, 0);
+        assert!(unblinds.is_empty());
+        assert_eq!(store.read().unwrap().account_cache(0).unwrap().unblinded.len(), 200);
+
+        // After a change of the unblinding the stored outputs are unblinded again
+        store.write().unwrap().cache.unblinded_version = Some(UNBLINDED_VERSION - 1);
+        store.write().unwrap().account_cache_mut(0).unwrap().unblinded.clear();
+        store.write().unwrap().flush().unwrap();
+        drop(store);
+        let store = open();
+        let store_read = store.read().unwrap();
+        assert_eq!(store_read.cache.unblinded_version, Some(UNBLINDED_VERSION));
+        assert_eq!(store_read.account_cache(0).unwrap().unblinded.len(), 200);
+    }
+
+    #[test]
+    fn test_pin_server_unreachable() {
+        struct Offline;
+        impl PinServer for Offline {
+            fn post(&self, _: &str, _: Option<&Value>) -> Result<Value, gdk_pin_client::Error> {
+                Err(gdk_pin_client::Error::ServerCallFailed)
+            }
+        }
+
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let pin_server_url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let mut network = serde_json::to_value(NetworkParameters::default()).unwrap();
+        network["electrum_url"] = "127.0.0.1:1".into();
+        network["pin_server_url"] = pin_server_url.into();
+        network["pin_server_public_key"] =
+            "0332b7b1348bde8ca4b46b9dcc30320e140ca26428160a27bdbfc30b34ec87c547".into();
+        let mut session = ElectrumSession::new(serde_json::from_value(network).unwrap()).unwrap();
+        let details: EncryptWithPinDetails =
+            serde_json::from_value(serde_json::json!({"pin": "123456", "plaintext": {}})).unwrap();
+
+        let err = session.encrypt_with_pin(&details).unwrap_err();
+        assert_eq!(err.to_gdk_code(), "id_connection_failed");
+        session.set_pin_server(Arc::new(Offline));
+        let err = session.encrypt_with_pin(&details).unwrap_err();
+        assert_eq!(err.to_gdk_code(), "id_connection_failed");
+    }
 }
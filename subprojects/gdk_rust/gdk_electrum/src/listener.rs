@@ -0,0 +1,214 @@
+//! Wakeups of the syncer thread on the server notifications.
+//!
+//! The electrum client reads the notifications of its subscriptions only
+//! while making calls, so a [`Listener`] thread pings the server every
+//! [`LISTEN_INTERVAL`] and wakes the syncer through a channel as soon as the
+//! tip or the status of a wallet script changes. The syncer still syncs every
+//! sync interval without notifications, as a safety net.
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use gdk_common::bitcoin::ScriptBuf;
+use gdk_common::electrum_client::{self, Client, ElectrumApi};
+use gdk_common::log::{info, warn};
+use gdk_common::util;
+
+use crate::ScriptStatuses;
+
+/// How often the listener reads the notifications sent by the server
+pub(crate) const LISTEN_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The subscriptions of a sync loop and what the server notified for them
+/// that is not synced yet, shared with the listener of its client
+#[derive(Default)]
+pub(crate) struct Subscriptions {
+    /// The wallet scripts subscribed to with the current client
+    scripts: Mutex<HashSet<ScriptBuf>>,
+
+    /// The last statuses notified for the scripts
+    statuses: Mutex<ScriptStatuses>,
+
+    /// Whether a new tip was notified
+    tip: AtomicBool,
+}
+
+impl Subscriptions {
+    /// Record the subscription of `scripts`
+    pub(crate) fn subscribed(&self, scripts: impl IntoIterator<Item = ScriptBuf>) {
+        self.scripts.lock().unwrap().extend(scripts);
+    }
+
+    /// Forget the scripts subscribed to, the subscriptions of a client are
+    /// lost when it's replaced
+    pub(crate) fn clear(&self) {
+        self.scripts.lock().unwrap().clear();
+    }
+
+    /// Whether `script` is subscribed to with the current client
+    pub(crate) fn is_subscribed(&self, script: &ScriptBuf) -> bool {
+        self.scripts.lock().unwrap().contains(script)
+    }
+
+    /// Take the script statuses notified since the last call
+    pub(crate) fn take_statuses(&self) -> ScriptStatuses {
+        std::mem::take(&mut *self.statuses.lock().unwrap())
+    }
+
+    /// The number of script statuses notified and not taken yet
+    pub(crate) fn pending(&self) -> usize {
+        self.statuses.lock().unwrap().len()
+    }
+
+    /// Whether a new tip was notified since the last call
+    pub(crate) fn take_tip(&self) -> bool {
+        self.tip.swap(false, Ordering::Relaxed)
+    }
+
+    /// Pop the notifications read by `client`, returning whether there was any
+    fn pop(&self, client: &Client) -> Result<bool, electrum_client::Error> {
+        let mut notified = false;
+        while client.block_headers_pop_raw()?.is_some() {
+            self.tip.store(true, Ordering::Relaxed);
+            notified = true;
+        }
+        for script in self.scripts.lock().unwrap().iter() {
+            // Not subscribed if the client reconnected in the meantime
+            while let Ok(Some(status)) = client.script_pop(script) {
+                self.statuses.lock().unwrap().insert(script.clone(), status);
+                notified = true;
+            }
+        }
+        Ok(notified)
+    }
+}
+
+/// Reads the notifications of a client in its own thread, waking the syncer
+/// when there are some
+pub(crate) struct Listener {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl Listener {
+    /// Listen to the notifications of `client` for `subscriptions`, until
+    /// stopped or `user_wants_to_sync` is false, waking the syncer a last
+    /// time then
+    ///
+    /// The syncer is woken once too when the server stops answering, to
+    /// notice the disconnection. The time of the last answer is recorded in
+    /// `last_answer`, in unix seconds.
+    pub(crate) fn spawn(
+        client: Arc<Client>,
+        subscriptions: Arc<Subscriptions>,
+        waker: Sender<()>,
+        user_wants_to_sync: Arc<AtomicBool>,
+        last_answer: Arc<AtomicU64>,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_listener = stop.clone();
+        let handle = thread::spawn(move || {
+            info!("starting listener thread");
+            let mut failing = false;
+            while !stop_listener.load(Ordering::Relaxed) {
+                if !user_wants_to_sync.load(Ordering::Relaxed) {
+                    let _ = waker.send(());
+                    break;
+                }
+                let wake = match client.ping().and_then(|_| subscriptions.pop(&client)) {
+                    Ok(notified) => {
+                        failing = false;
+                        last_answer.store(util::now() / 1_000_000, Ordering::Relaxed);
+                        notified
+                    }
+                    Err(e) => {
+                        if !failing {
+                            warn!("listener cannot reach the server {:?}", e);
+                        }
+                        !std::mem::replace(&mut failing, true)
+                    }
+                };
+                if wake && waker.send(()).is_err() {
+                    break;
+                }
+                thread::sleep(LISTEN_INTERVAL);
+            }
+            info!("closing listener thread");
+        });
+        Listener {
+            stop,
+            handle,
+        }
+    }
+
+    /// Stop listening and wait for the thread to end
+    pub(crate) fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.handle.join().expect("Couldn't join on the associated thread");
+    }
+}
+
+/// Wait for a wakeup from a listener or for `timeout`, returning true if
+/// syncing should stop
+///
+/// The wakeups received meanwhile are all consumed, a single sync covers them.
+pub(crate) fn wait_wakeup(
+    wakeups: &Receiver<()>,
+    user_wants_to_sync: &AtomicBool,
+    timeout: Duration,
+) -> bool {
+    match wakeups.recv_timeout(timeout) {
+        Ok(()) => while wakeups.try_recv().is_ok() {},
+        Err(RecvTimeoutError::Timeout) => (),
+        // The syncer keeps a sender to spawn the listeners
+        Err(RecvTimeoutError::Disconnected) => unreachable!(),
+    }
+    !user_wants_to_sync.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_wait_wakeup() {
+        let (sender, receiver) = channel();
+        let user_wants_to_sync = AtomicBool::new(true);
+        assert!(!wait_wakeup(&receiver, &user_wants_to_sync, Duration::from_millis(1)));
+
+        // Pending wakeups are consumed by a single wait
+        for _ in 0..3 {
+            sender.send(()).unwrap();
+        }
+        assert!(!wait_wakeup(&receiver, &user_wants_to_sync, Duration::from_secs(60)));
+        assert!(receiver.try_recv().is_err());
+
+        user_wants_to_sync.store(false, Ordering::Relaxed);
+        sender.send(()).unwrap();
+        assert!(wait_wakeup(&receiver, &user_wants_to_sync, Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_subscriptions() {
+        let subscriptions = Subscriptions::default();
+        assert!(!subscriptions.take_tip());
+        subscriptions.tip.store(true, Ordering::Relaxed);
+        assert!(subscriptions.take_tip());
+        assert!(!subscriptions.take_tip());
+
+        subscriptions.subscribed(vec![ScriptBuf::new()]);
+        assert_eq!(subscriptions.scripts.lock().unwrap().len(), 1);
+        subscriptions.clear();
+        assert!(subscriptions.scripts.lock().unwrap().is_empty());
+        assert!(subscriptions.take_statuses().is_empty());
+        subscriptions.statuses.lock().unwrap().insert(ScriptBuf::new(), [0; 32].into());
+        assert_eq!(subscriptions.pending(), 1);
+        subscriptions.take_statuses();
+        assert_eq!(subscriptions.pending(), 0);
+    }
+}
@@ -0,0 +1,221 @@
+//! Many wallets synced in one process with shared threads and connections.
+//!
+//! Each [`ElectrumSession`](crate::ElectrumSession) normally syncs with its own
+//! threads and electrum connections. Sessions given a [`SessionManager`] with
+//! [`set_session_manager`](crate::ElectrumSession::set_session_manager) are
+//! synced instead by a fixed number of worker threads, over a single
+//! connection per server: the script subscriptions of all their wallets are
+//! multiplexed on it. Their stores stay separate, the transactions fetched
+//! on a connection are fetched once for all of them.
+//!
+//! Managed sessions get the fee estimates when asked and don't verify
+//! transactions with SPV. A wallet must not be logged in by two sessions of
+//! the same manager, as they would compete for the notifications of its
+//! scripts.
+//!
+//! The asset registry is shared by all the sessions of the process already.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use gdk_common::electrum_client::Client;
+use gdk_common::log::{info, warn};
+
+use crate::error::Error;
+use crate::fetcher::TxFetcher;
+use crate::interface::ElectrumUrl;
+use crate::resolver::Resolver;
+use crate::{check_server_genesis, SyncLoop};
+
+/// How long idle workers wait before looking for a session to sync
+const IDLE_WAIT: Duration = Duration::from_millis(100);
+
+/// Syncs the sessions given to it, see the [module documentation](self)
+pub struct SessionManager {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+/// The state of a [`SessionManager`] shared with its workers and sessions
+pub(crate) struct Shared {
+    /// The server connections, by server and proxy
+    clients: Mutex<HashMap<String, Arc<Client>>>,
+
+    /// The transaction fetchers of the connections, by server and proxy
+    tx_fetchers: Mutex<HashMap<String, Arc<TxFetcher>>>,
+
+    /// The syncing of the connected sessions
+    syncs: Mutex<Vec<Arc<ManagedSync>>>,
+
+    next_id: AtomicU64,
+
+    running: AtomicBool,
+}
+
+/// The syncing of a session, advanced by a worker every `interval`
+struct ManagedSync {
+    id: u64,
+    client: Arc<Client>,
+    interval: Duration,
+    next_due: Mutex<Instant>,
+
+    /// Whether a worker is syncing the session
+    busy: AtomicBool,
+
+    sync_loop: Mutex<SyncLoop>,
+}
+
+impl SessionManager {
+    /// A manager syncing its sessions with `threads` worker threads
+    pub fn new(threads: usize) -> Self {
+        let shared = Arc::new(Shared {
+            clients: Mutex::new(HashMap::new()),
+            tx_fetchers: Mutex::new(HashMap::new()),
+            syncs: Mutex::new(vec![]),
+            next_id: AtomicU64::new(0),
+            running: AtomicBool::new(true),
+        });
+        let workers = (0..threads.max(1))
+            .map(|_| {
+                let shared = shared.clone();
+                thread::spawn(move || shared.work())
+            })
+            .collect();
+        SessionManager {
+            shared,
+            workers,
+        }
+    }
+
+    /// The number of server connections open
+    pub fn connections(&self) -> usize {
+        self.shared.clients.lock().unwrap().len()
+    }
+
+    /// The number of sessions being synced
+    pub fn sessions(&self) -> usize {
+        self.shared.syncs.lock().unwrap().len()
+    }
+
+    pub(crate) fn shared(&self) -> Arc<Shared> {
+        self.shared.clone()
+    }
+}
+
+impl Drop for SessionManager {
+    fn drop(&mut self) {
+        self.shared.running.store(false, Ordering::Relaxed);
+        for worker in self.workers.drain(..) {
+            worker.join().expect("Couldn't join on the associated thread");
+        }
+    }
+}
+
+impl Shared {
+    /// The connection to the server at `url`, opened if needed
+    ///
+    /// The client reconnects by itself when a call fails.
+    pub(crate) fn client(
+        &self,
+        url: &ElectrumUrl,
+        proxy: Option<&str>,
+        resolver: &Resolver,
+    ) -> Result<Arc<Client>, Error> {
+        let key = connection_key(url, proxy);
+        let mut clients = self.clients.lock()?;
+        if let Some(client) = clients.get(&key) {
+            return Ok(client.clone());
+        }
+        info!("opening a shared connection to {}", url.url());
+        let client = Arc::new(url.build_client(proxy, None, resolver)?);
+        clients.insert(key, client.clone());
+        Ok(client)
+    }
+
+    /// Start syncing a session with the server at `url` every `interval`,
+    /// returning the id to stop it with [`Self::unregister`]
+    pub(crate) fn register(
+        &self,
+        url: &ElectrumUrl,
+        proxy: Option<&str>,
+        mut sync_loop: SyncLoop,
+        interval: Duration,
+    ) -> Result<u64, Error> {
+        let client = self.client(url, proxy, &Resolver::new(&sync_loop.syncer.network))?;
+        check_server_genesis(&client, &sync_loop.syncer.network)?;
+        sync_loop.syncer.tx_fetcher =
+            self.tx_fetchers.lock()?.entry(connection_key(url, proxy)).or_default().clone();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.syncs.lock()?.push(Arc::new(ManagedSync {
+            id,
+            client,
+            interval,
+            next_due: Mutex::new(Instant::now()),
+            busy: AtomicBool::new(false),
+            sync_loop: Mutex::new(sync_loop),
+        }));
+        Ok(id)
+    }
+
+    /// Stop syncing the session `id`, waiting for its ongoing sync if any
+    pub(crate) fn unregister(&self, id: u64) {
+        let removed = {
+            let mut syncs = self.syncs.lock().unwrap();
+            let index = syncs.iter().position(|sync| sync.id == id);
+            index.map(|index| syncs.remove(index))
+        };
+        if let Some(sync) = removed {
+            drop(sync.sync_loop.lock());
+        }
+    }
+
+    /// The loop of a worker, syncing the session due for it the earliest
+    fn work(&self) {
+        while self.running.load(Ordering::Relaxed) {
+            let sync = match self.next_due() {
+                Some(sync) => sync,
+                None => {
+                    thread::sleep(IDLE_WAIT);
+                    continue;
+                }
+            };
+            let keep_syncing = match sync.sync_loop.lock() {
+                Ok(mut sync_loop) => sync_loop.step(&sync.client),
+                Err(e) => {
+                    warn!("sync of session {} poisoned: {}", sync.id, e);
+                    false
+                }
+            };
+            *sync.next_due.lock().unwrap() = Instant::now() + sync.interval;
+            sync.busy.store(false, Ordering::Relaxed);
+            if !keep_syncing {
+                self.unregister(sync.id);
+            }
+        }
+    }
+
+    /// Take the session due for a sync the earliest, if any
+    fn next_due(&self) -> Option<Arc<ManagedSync>> {
+        let now = Instant::now();
+        let syncs = self.syncs.lock().unwrap();
+        let mut due: Vec<_> = syncs
+            .iter()
+            .filter(|sync| !sync.busy.load(Ordering::Relaxed))
+            .map(|sync| (*sync.next_due.lock().unwrap(), sync))
+            .filter(|(next_due, _)| *next_due <= now)
+            .collect();
+        due.sort_by_key(|(next_due, _)| *next_due);
+        let (_, sync) = due.into_iter().next()?;
+        // Taken under the lock of `syncs`, no other worker can take it meanwhile
+        sync.busy.store(true, Ordering::Relaxed);
+        Some(sync.clone())
+    }
+}
+
+/// The key of the connection to the server at `url` through `proxy`
+fn connection_key(url: &ElectrumUrl, proxy: Option<&str>) -> String {
+    format!("{}|{}", url.url(), proxy.unwrap_or_default())
+}
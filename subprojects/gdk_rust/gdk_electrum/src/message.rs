@@ -0,0 +1,338 @@
+//! Signing and verification of messages with Bitcoin keys.
+//!
+//! Legacy signatures follow BIP137: a compact recoverable signature of the
+//! message hash, with a header byte encoding the recovery id and the address
+//! type. Verification also accepts signatures made by Electrum, which uses
+//! the p2pkh headers for segwit addresses too.
+//!
+//! BIP322 simple signatures are the witness of a virtual transaction spending
+//! an output locked by the address, they're supported for p2wpkh and p2tr.
+
+use crate::error::Error;
+use gdk_common::bitcoin::absolute::LockTime;
+use gdk_common::bitcoin::consensus::encode::{deserialize, serialize};
+use gdk_common::bitcoin::hashes::{sha256, Hash, HashEngine};
+use gdk_common::bitcoin::key::TapTweak;
+use gdk_common::bitcoin::opcodes::all::OP_RETURN;
+use gdk_common::bitcoin::script::Builder;
+use gdk_common::bitcoin::secp256k1::ecdsa::{RecoverableSignature, RecoveryId};
+use gdk_common::bitcoin::secp256k1::{Keypair, Message, SecretKey, XOnlyPublicKey};
+use gdk_common::bitcoin::sighash::{EcdsaSighashType, Prevouts, SighashCache, TapSighashType};
+use gdk_common::bitcoin::sign_message::signed_msg_hash;
+use gdk_common::bitcoin::transaction::Version;
+use gdk_common::bitcoin::{
+    ecdsa, taproot, Address, Amount, CompressedPublicKey, OutPoint, PublicKey, ScriptBuf, Sequence,
+    Transaction, TxIn, TxOut, Txid, Witness,
+};
+use gdk_common::model::MessageFormat;
+use gdk_common::scripts::ScriptType;
+use gdk_common::EC;
+use std::convert::TryFrom;
+
+/// BIP137 header of compressed keys for `script_type`, the recovery id is added to it
+fn legacy_header(script_type: ScriptType) -> Option<u8> {
+    match script_type {
+        ScriptType::P2pkh => Some(31),
+        ScriptType::P2shP2wpkh => Some(35),
+        ScriptType::P2wpkh => Some(39),
+        ScriptType::P2tr | ScriptType::P2wsh | ScriptType::GreenMultisig => None,
+    }
+}
+
+/// Sign `message` with `private_key` for an address of type `script_type`,
+/// returning the base64 encoded signature
+pub fn sign_message(
+    private_key: &SecretKey,
+    script_type: ScriptType,
+    message: &str,
+    format: MessageFormat,
+) -> Result<String, Error> {
+    let unsupported = || Error::UnsupportedMessageFormat {
+        format,
+        script_type,
+    };
+    let public_key = CompressedPublicKey(private_key.public_key(&EC));
+    match format {
+        MessageFormat::Legacy => {
+            let header = legacy_header(script_type).ok_or_else(unsupported)?;
+            let msg = Message::from_digest(signed_msg_hash(message).to_byte_array());
+            let (recid, signature) =
+                EC.sign_ecdsa_recoverable(&msg, private_key).serialize_compact();
+            let mut bytes = vec![header + recid.to_i32() as u8];
+            bytes.extend_from_slice(&signature);
+            Ok(base64::encode(bytes))
+        }
+        MessageFormat::Bip322 => {
+            let witness = match script_type {
+                ScriptType::P2wpkh => {
+                    let script_pubkey = ScriptBuf::new_p2wpkh(&public_key.wpubkey_hash());
+                    let to_sign = bip322_to_sign(&script_pubkey, message, Witness::new());
+                    let sighash = SighashCache::new(&to_sign)
+                        .p2wpkh_signature_hash(
+                            0,
+                            &script_pubkey,
+                            Amount::ZERO,
+                            EcdsaSighashType::All,
+                        )
+                        .map_err(|e| Error::Generic(e.to_string()))?;
+                    let msg = Message::from_digest(sighash.to_byte_array());
+                    let signature = ecdsa::Signature::sighash_all(EC.sign_ecdsa(&msg, private_key));
+                    Witness::p2wpkh(&signature, &public_key.0)
+                }
+                ScriptType::P2tr => {
+                    let script_pubkey = ScriptBuf::new_p2tr(&EC, public_key.0.into(), None);
+                    let to_sign = bip322_to_sign(&script_pubkey, message, Witness::new());
+                    let prevouts = [TxOut {
+                        value: Amount::ZERO,
+                        script_pubkey,
+                    }];
+                    let sighash = SighashCache::new(&to_sign)
+                        .taproot_key_spend_signature_hash(
+                            0,
+                            &Prevouts::All(&prevouts),
+                            TapSighashType::Default,
+                        )
+                        .map_err(|e| Error::Generic(e.to_string()))?;
+                    let msg = Message::from_digest(sighash.to_byte_array());
+                    let keypair = Keypair::from_secret_key(&EC, private_key).tap_tweak(&EC, None);
+                    let signature = taproot::Signature {
+                        signature: EC.sign_schnorr(&msg, &keypair.to_inner()),
+                        sighash_type: TapSighashType::Default,
+                    };
+                    Witness::p2tr_key_spend(&signature)
+                }
+                _ => return Err(unsupported()),
+            };
+            Ok(base64::encode(serialize(&witness)))
+        }
+    }
+}
+
+/// Whether `signature` is a valid signature of `message` for `address`,
+/// either in the legacy or in the BIP322 simple format
+pub fn verify_message(address: &Address, message: &str, signature: &str) -> bool {
+    let bytes = match base64::decode(signature) {
+        Ok(bytes) => bytes,
+        Err(_) => return false,
+    };
+    if bytes.len() == 65 && (27..=42).contains(&bytes[0]) {
+        return verify_legacy(address, message, &bytes);
+    }
+    match deserialize::<Witness>(&bytes) {
+        Ok(witness) => verify_bip322_simple(address, message, witness),
+        Err(_) => false,
+    }
+}
+
+fn verify_legacy(address: &Address, message: &str, bytes: &[u8]) -> bool {
+    let header = bytes[0] - 27;
+    let recovered = RecoveryId::from_i32((header % 4) as i32)
+        .and_then(|recid| RecoverableSignature::from_compact(&bytes[1..], recid))
+        .and_then(|signature| {
+            let msg = Message::from_digest(signed_msg_hash(message).to_byte_array());
+            EC.recover_ecdsa(&msg, &signature)
+        });
+    let public_key = match recovered {
+        Ok(inner) => PublicKey {
+            inner,
+            // Headers 27-30 are for uncompressed keys
+            compressed: header >= 4,
+        },
+        Err(_) => return false,
+    };
+    let script_pubkey = address.script_pubkey();
+    if ScriptBuf::new_p2pkh(&public_key.pubkey_hash()) == script_pubkey {
+        return true;
+    }
+    // The header is not checked against the address type, since Electrum
+    // signs for segwit addresses with the p2pkh headers
+    match CompressedPublicKey::try_from(public_key) {
+        Ok(public_key) => {
+            let p2wpkh = ScriptBuf::new_p2wpkh(&public_key.wpubkey_hash());
+            let p2sh_p2wpkh = ScriptBuf::new_p2sh(&p2wpkh.script_hash());
+            script_pubkey == p2wpkh || script_pubkey == p2sh_p2wpkh
+        }
+        Err(_) => false,
+    }
+}
+
+fn verify_bip322_simple(address: &Address, message: &str, witness: Witness) -> bool {
+    let script_pubkey = address.script_pubkey();
+    let to_sign = bip322_to_sign(&script_pubkey, message, witness.clone());
+    let mut cache = SighashCache::new(&to_sign);
+    if script_pubkey.is_p2wpkh() {
+        if witness.len() != 2 {
+            return false;
+        }
+        let (signature, public_key) = match (
+            ecdsa::Signature::from_slice(&witness[0]),
+            CompressedPublicKey::from_slice(&witness[1]),
+        ) {
+            (Ok(signature), Ok(public_key)) => (signature, public_key),
+            _ => return false,
+        };
+        if ScriptBuf::new_p2wpkh(&public_key.wpubkey_hash()) != script_pubkey {
+            return false;
+        }
+        match cache.p2wpkh_signature_hash(0, &script_pubkey, Amount::ZERO, signature.sighash_type) {
+            Ok(sighash) => {
+                let msg = Message::from_digest(sighash.to_byte_array());
+                EC.verify_ecdsa(&msg, &signature.signature, &public_key.0).is_ok()
+            }
+            Err(_) => false,
+        }
+    } else if script_pubkey.is_p2tr() {
+        if witness.len() != 1 {
+            return false;
+        }
+        let (signature, output_key) = match (
+            taproot::Signature::from_slice(&witness[0]),
+            XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..]),
+        ) {
+            (Ok(signature), Ok(output_key)) => (signature, output_key),
+            _ => return false,
+        };
+        let prevouts = [TxOut {
+            value: Amount::ZERO,
+            script_pubkey: script_pubkey.clone(),
+        }];
+        match cache.taproot_key_spend_signature_hash(
+            0,
+            &Prevouts::All(&prevouts),
+            signature.sighash_type,
+        ) {
+            Ok(sighash) => {
+                let msg = Message::from_digest(sighash.to_byte_array());
+                EC.verify_schnorr(&signature.signature, &msg, &output_key).is_ok()
+            }
+            Err(_) => false,
+        }
+    } else {
+        false
+    }
+}
+
+/// The BIP322 tagged hash of `message`
+fn bip322_message_hash(message: &str) -> sha256::Hash {
+    let tag = sha256::Hash::hash(b"BIP0322-signed-message");
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_byte_array());
+    engine.input(tag.as_byte_array());
+    engine.input(message.as_bytes());
+    sha256::Hash::from_engine(engine)
+}
+
+/// The BIP322 virtual transaction signed with `witness`, spending the
+/// virtual output locked by `script_pubkey` and committing to `message`
+fn bip322_to_sign(script_pubkey: &ScriptBuf, message: &str, witness: Witness) -> Transaction {
+    let to_spend = Transaction {
+        version: Version(0),
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), 0xFFFFFFFF),
+            script_sig: Builder::new()
+                .push_int(0)
+                .push_slice(bip322_message_hash(message).as_byte_array())
+                .into_script(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: script_pubkey.clone(),
+        }],
+    };
+    Transaction {
+        version: Version(0),
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(to_spend.compute_txid(), 0),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ZERO,
+            witness,
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+        }],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gdk_common::bitcoin::{Network, PrivateKey};
+    use std::str::FromStr;
+
+    // BIP322 test vectors
+    const PRIVATE_KEY: &str = "L3VFeEujGtevx9w18HD1fhRbCH67Az2dpCymeRE1SoPK6XQtaN2k";
+    const P2WPKH: &str = "bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l";
+    const P2TR: &str = "bc1ppv609nr0vr25u07u95waq5lucwfm6tde4nydujnu8npg4q75mr5sxq8lt3";
+
+    fn address(s: &str) -> Address {
+        Address::from_str(s).unwrap().require_network(Network::Bitcoin).unwrap()
+    }
+
+    #[test]
+    fn test_bip322_vectors() {
+        assert_eq!(
+            bip322_message_hash("").to_string(),
+            "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+        );
+        assert_eq!(
+            bip322_message_hash("Hello World").to_string(),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+
+        let p2wpkh = address(P2WPKH);
+        let signature = "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI=";
+        assert!(verify_message(&p2wpkh, "Hello World", signature));
+        assert!(!verify_message(&p2wpkh, "Hello World!", signature));
+
+        let p2tr = address(P2TR);
+        let signature = "AUHd69PrJQEv+oKTfZ8l+WROBHuy9HKrbFCJu7U1iK2iiEy1vMU5EfMtjc+VSHM7aU0SDbak5IUZRVno2P5mjSafAQ==";
+        assert!(verify_message(&p2tr, "Hello World", signature));
+        assert!(!verify_message(&p2wpkh, "Hello World", signature));
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let private_key = PrivateKey::from_wif(PRIVATE_KEY).unwrap().inner;
+        let public_key = CompressedPublicKey(private_key.public_key(&EC));
+        let p2pkh = Address::p2pkh(public_key, Network::Bitcoin);
+        let p2sh_p2wpkh = Address::p2shwpkh(&public_key, Network::Bitcoin);
+        let p2wpkh = address(P2WPKH);
+        let p2tr = address(P2TR);
+        let message = "Hello World";
+
+        let cases = [
+            (ScriptType::P2pkh, MessageFormat::Legacy, &p2pkh),
+            (ScriptType::P2shP2wpkh, MessageFormat::Legacy, &p2sh_p2wpkh),
+            (ScriptType::P2wpkh, MessageFormat::Legacy, &p2wpkh),
+            (ScriptType::P2wpkh, MessageFormat::Bip322, &p2wpkh),
+            (ScriptType::P2tr, MessageFormat::Bip322, &p2tr),
+        ];
+        for (script_type, format, address) in cases {
+            let signature = sign_message(&private_key, script_type, message, format).unwrap();
+            assert!(verify_message(address, message, &signature), "{:?} {:?}", script_type, format);
+            assert!(!verify_message(address, "other", &signature));
+            if format == MessageFormat::Bip322 {
+                assert!(!verify_message(&p2pkh, message, &signature));
+            }
+        }
+
+        // Electrum signs for segwit addresses with the p2pkh header
+        let signature =
+            sign_message(&private_key, ScriptType::P2pkh, message, MessageFormat::Legacy).unwrap();
+        assert!(verify_message(&p2wpkh, message, &signature));
+        assert!(!verify_message(&p2tr, message, &signature));
+
+        assert!(
+            sign_message(&private_key, ScriptType::P2tr, message, MessageFormat::Legacy).is_err()
+        );
+        assert!(
+            sign_message(&private_key, ScriptType::P2pkh, message, MessageFormat::Bip322).is_err()
+        );
+        assert!(!verify_message(&p2wpkh, message, "not base64!"));
+    }
+}
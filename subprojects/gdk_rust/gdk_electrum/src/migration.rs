@@ -0,0 +1,380 @@
+use crate::error::Error;
+use crate::pegout::input_signature_weight;
+use crate::selection::{input_weight, required_fee, sort_largest_first};
+use gdk_common::be::{BEAddress, BEOutPoint, BEScriptConvert, BETransaction};
+use gdk_common::bitcoin::hashes::{sha256, Hash};
+use gdk_common::elements::pset::{self, PartiallySignedTransaction};
+use gdk_common::elements::{self, AssetId, TxOutSecrets};
+use gdk_common::model::{Txo, WalletMigrationStep};
+use gdk_common::rand::thread_rng;
+use gdk_common::scripts::ScriptType;
+use gdk_common::util::weight_to_vsize;
+use gdk_common::{bitcoin, NetworkPolicy, EC};
+use std::collections::{BTreeMap, HashMap};
+
+/// Weight left for the outputs and the transaction overhead when grouping
+/// the inputs: a single output on Bitcoin, two confidential outputs and the
+/// fee output on Liquid
+const BITCOIN_OUTPUTS_WEIGHT: u64 = 1_000;
+pub(crate) const ELEMENTS_OUTPUTS_WEIGHT: u64 = 12_000;
+
+/// Group `utxos` into the inputs of the migration transactions, so that each
+/// transaction is within `max_weight`.
+///
+/// On Liquid, with `policy_asset` given, each transaction spends a single
+/// asset: the transactions of the other assets also spend the largest policy
+/// asset utxo left, to pay their fee.
+pub(crate) fn group_utxos(
+    mut utxos: Vec<Txo>,
+    policy_asset: Option<AssetId>,
+    max_weight: u64,
+) -> Result<Vec<Vec<Txo>>, Error> {
+    let outputs_weight = match policy_asset {
+        Some(_) => ELEMENTS_OUTPUTS_WEIGHT,
+        None => BITCOIN_OUTPUTS_WEIGHT,
+    };
+    let available = max_weight.saturating_sub(outputs_weight);
+    sort_largest_first(&mut utxos);
+
+    let mut policy_utxos = vec![];
+    let mut asset_utxos: BTreeMap<AssetId, Vec<Txo>> = BTreeMap::new();
+    for utxo in utxos {
+        match utxo.txoutsecrets.map(|s| s.asset).filter(|asset| Some(*asset) != policy_asset) {
+            Some(asset) => asset_utxos.entry(asset).or_default().push(utxo),
+            None => policy_utxos.push(utxo),
+        }
+    }
+
+    let mut groups = vec![];
+    let fee_input_weight = input_weight(ScriptType::P2pkh);
+    for utxos in asset_utxos.into_values() {
+        for mut group in batches(utxos, available.saturating_sub(fee_input_weight))? {
+            ensure!(!policy_utxos.is_empty(), Error::InsufficientFundsForFee);
+            group.push(policy_utxos.remove(0));
+            groups.push(group);
+        }
+    }
+    groups.extend(batches(policy_utxos, available)?);
+    Ok(groups)
+}
+
+/// Split `utxos` in order into groups whose inputs weigh at most `max_weight`
+fn batches(utxos: Vec<Txo>, max_weight: u64) -> Result<Vec<Vec<Txo>>, Error> {
+    let mut groups = vec![];
+    let mut group = vec![];
+    let mut weight = 0;
+    for utxo in utxos {
+        let utxo_weight = input_weight(utxo.script_type);
+        ensure!(utxo_weight <= max_weight, Error::TransactionTooLarge);
+        if weight + utxo_weight > max_weight {
+            groups.push(std::mem::take(&mut group));
+            weight = 0;
+        }
+        weight += utxo_weight;
+        group.push(utxo);
+    }
+    if !group.is_empty() {
+        groups.push(group);
+    }
+    Ok(groups)
+}
+
+/// The number of destination addresses paid by the transaction spending `inputs`
+pub(crate) fn destinations_needed(inputs: &[Txo], policy_asset: Option<AssetId>) -> usize {
+    let spends_asset =
+        inputs.iter().any(|u| u.txoutsecrets.is_some_and(|s| Some(s.asset) != policy_asset));
+    match spends_asset {
+        // The asset, and what is left of the fee input
+        true => 2,
+        false => 1,
+    }
+}
+
+/// The id of the plan of `steps`, the hash of their transactions
+pub(crate) fn plan_id(steps: &[WalletMigrationStep]) -> String {
+    let transactions: String = steps.iter().map(|s| s.transaction.as_str()).collect();
+    sha256::Hash::hash(transactions.as_bytes()).to_string()
+}
+
+pub(crate) struct MigrationTx {
+    pub tx: BETransaction,
+    pub fee: u64,
+    pub vsize: usize,
+
+    /// The destinations paid, with the amount and the asset
+    pub outputs: Vec<(BEAddress, u64, Option<AssetId>)>,
+}
+
+/// Build the transaction sending all of `inputs` to `destinations`, as many
+/// as [`destinations_needed`], paying `fee_rate`.
+///
+/// On Liquid the destinations must be confidential, and the transaction is
+/// blinded.
+pub(crate) fn build_migration_tx(
+    inputs: &[Txo],
+    destinations: &[BEAddress],
+    policy_asset: Option<AssetId>,
+    fee_rate: u64,
+    policy: &NetworkPolicy,
+) -> Result<MigrationTx, Error> {
+    match policy_asset {
+        None => build_bitcoin_tx(inputs, &destinations[0], fee_rate, policy),
+        Some(policy_asset) => {
+            build_elements_tx(inputs, destinations, policy_asset, fee_rate, policy)
+        }
+    }
+}
+
+fn signatures_weight(inputs: &[Txo]) -> usize {
+    inputs.iter().map(|u| input_signature_weight(u.script_type)).sum()
+}
+
+fn build_bitcoin_tx(
+    inputs: &[Txo],
+    destination: &BEAddress,
+    fee_rate: u64,
+    policy: &NetworkPolicy,
+) -> Result<MigrationTx, Error> {
+    let script_pubkey = destination.script_pubkey().into_bitcoin();
+    let total: u64 = inputs.iter().map(|u| u.satoshi).sum();
+    let mut tx = bitcoin::Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: bitcoin::absolute::LockTime::ZERO,
+        input: inputs
+            .iter()
+            .map(|u| match u.outpoint {
+                BEOutPoint::Bitcoin(outpoint) => Ok(bitcoin::TxIn {
+                    previous_output: outpoint,
+                    sequence: bitcoin::Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    ..Default::default()
+                }),
+                BEOutPoint::Elements(_) => Err(Error::Generic("not a bitcoin utxo".into())),
+            })
+            .collect::<Result<_, _>>()?,
+        output: vec![bitcoin::TxOut {
+            value: bitcoin::Amount::from_sat(total),
+            script_pubkey: script_pubkey.clone(),
+        }],
+    };
+    // The output value has a fixed size, the fee does not change the weight
+    let weight = tx.weight().to_wu() as usize + signatures_weight(inputs);
+    ensure!(weight as u64 <= policy.max_standard_tx_weight, Error::TransactionTooLarge);
+    let fee = required_fee(weight, fee_rate);
+    let satoshi = total.saturating_sub(fee);
+    ensure!(
+        satoshi >= policy.dust_limits.for_script(&script_pubkey),
+        Error::InsufficientFundsForFee
+    );
+    tx.output[0].value = bitcoin::Amount::from_sat(satoshi);
+    Ok(MigrationTx {
+        tx: BETransaction::Bitcoin(tx),
+        fee,
+        vsize: weight_to_vsize(weight),
+        outputs: vec![(destination.clone(), satoshi, None)],
+    })
+}
+
+fn build_elements_tx(
+    inputs: &[Txo],
+    destinations: &[BEAddress],
+    policy_asset: AssetId,
+    fee_rate: u64,
+    policy: &NetworkPolicy,
+) -> Result<MigrationTx, Error> {
+    let mut totals: BTreeMap<AssetId, u64> = BTreeMap::new();
+    for input in inputs {
+        let secrets =
+            input.txoutsecrets.ok_or_else(|| Error::Generic("not a liquid utxo".into()))?;
+        *totals.entry(secrets.asset).or_default() += secrets.value;
+    }
+    let policy_total = totals.remove(&policy_asset).unwrap_or(0);
+    // The asset sent first, then the policy asset left
+    let mut outputs: Vec<(BEAddress, u64, Option<AssetId>)> = totals
+        .into_iter()
+        .chain(std::iter::once((policy_asset, policy_total)))
+        .zip(destinations)
+        .map(|((asset, satoshi), address)| (address.clone(), satoshi, Some(asset)))
+        .collect();
+    let policy_output = outputs.len() - 1;
+    let policy_script = outputs[policy_output].0.script_pubkey().into_elements();
+    let policy_dust =
+        policy.dust_limits.for_script(bitcoin::Script::from_bytes(policy_script.as_bytes()));
+
+    let mut fee = 0u64;
+    loop {
+        ensure!(policy_total >= fee + policy_dust, Error::InsufficientFundsForFee);
+        outputs[policy_output].1 = policy_total - fee;
+
+        let mut pset = PartiallySignedTransaction::new_v2();
+        let mut secrets: HashMap<usize, TxOutSecrets> = HashMap::new();
+        for (i, txo) in inputs.iter().enumerate() {
+            let outpoint = match txo.outpoint {
+                BEOutPoint::Elements(o) => o,
+                BEOutPoint::Bitcoin(_) => return Err(Error::Generic("not a liquid utxo".into())),
+            };
+            let mut input = pset::Input::from_prevout(outpoint);
+            if let Some((asset, value, nonce)) = txo.txoutcommitments {
+                input.witness_utxo = Some(elements::TxOut {
+                    asset,
+                    value,
+                    nonce,
+                    script_pubkey: txo.script_pubkey.clone().into_elements(),
+                    witness: Default::default(),
+                });
+            }
+            pset.add_input(input);
+            if let Some(s) = txo.txoutsecrets {
+                secrets.insert(i, s);
+            }
+        }
+        for (address, satoshi, asset) in outputs.iter() {
+            let address =
+                address.elements().ok_or_else(|| Error::Generic("not a liquid address".into()))?;
+            let blinding_key = address.blinding_pubkey.ok_or_else(|| {
+                Error::Generic("migration destinations must be confidential".into())
+            })?;
+            let mut output = pset::Output::new_explicit(
+                address.script_pubkey(),
+                *satoshi,
+                asset.unwrap_or(policy_asset),
+                Some(blinding_key.into()),
+            );
+            output.blinder_index = Some(0);
+            pset.add_output(output);
+        }
+        pset.add_output(pset::Output::from_txout(elements::TxOut::new_fee(fee, policy_asset)));
+
+        pset.blind_last(&mut thread_rng(), &EC, &secrets)
+            .map_err(|e| Error::Generic(format!("cannot blind migration: {}", e)))?;
+        let tx = pset.extract_tx().map_err(|e| Error::Generic(e.to_string()))?;
+
+        let weight = tx.weight() + signatures_weight(inputs);
+        ensure!(weight as u64 <= policy.max_standard_tx_weight, Error::TransactionTooLarge);
+        let required = required_fee(weight, fee_rate);
+        if fee >= required {
+            return Ok(MigrationTx {
+                tx: BETransaction::Elements(tx),
+                fee,
+                vsize: weight_to_vsize(weight),
+                outputs,
+            });
+        }
+        fee = required;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gdk_common::be::BEScript;
+    use std::str::FromStr;
+
+    fn txo(byte: u8, satoshi: u64, asset: Option<AssetId>) -> Txo {
+        let txid = bitcoin::Txid::from_byte_array([byte; 32]);
+        let (outpoint, txoutsecrets) = match asset {
+            Some(asset) => {
+                let outpoint =
+                    elements::OutPoint::new(elements::Txid::from_byte_array([byte; 32]), 0);
+                let secrets = TxOutSecrets::new(
+                    asset,
+                    elements::confidential::AssetBlindingFactor::zero(),
+                    satoshi,
+                    elements::confidential::ValueBlindingFactor::zero(),
+                );
+                (BEOutPoint::Elements(outpoint), Some(secrets))
+            }
+            None => (BEOutPoint::Bitcoin(bitcoin::OutPoint::new(txid, 0)), None),
+        };
+        Txo {
+            outpoint,
+            height: None,
+            public_key: bitcoin::PublicKey::from_str(
+                "03ad1d8e89212f0b92c74d23bb710c00662ad1470198ac48c43f7d6f93a2a26873",
+            )
+            .unwrap(),
+            script_pubkey: BEScript::default(),
+            script_code: BEScript::default(),
+            subaccount: 0,
+            script_type: ScriptType::P2wpkh,
+            user_path: vec![],
+            satoshi,
+            sequence: None,
+            txoutsecrets,
+            txoutcommitments: None,
+        }
+    }
+
+    #[test]
+    fn test_group_utxos_weight() {
+        let utxos: Vec<_> = (0..35).map(|i| txo(i, 1_000 + i as u64, None)).collect();
+        let per_input = input_weight(ScriptType::P2wpkh);
+        let max_weight = BITCOIN_OUTPUTS_WEIGHT + 10 * per_input;
+        let groups = group_utxos(utxos.clone(), None, max_weight).unwrap();
+        let sizes: Vec<_> = groups.iter().map(Vec::len).collect();
+        assert_eq!(sizes, [10, 10, 10, 5]);
+        // Largest first
+        assert_eq!(groups[0][0].satoshi, 1_034);
+        assert_eq!(groups[3][4].satoshi, 1_000);
+
+        assert_eq!(group_utxos(utxos.clone(), None, 400_000).unwrap().len(), 1);
+        let err = group_utxos(utxos, None, BITCOIN_OUTPUTS_WEIGHT).unwrap_err();
+        assert!(matches!(err, Error::TransactionTooLarge));
+    }
+
+    #[test]
+    fn test_group_utxos_by_asset() {
+        let policy_asset = AssetId::from_slice(&[1; 32]).unwrap();
+        let asset_a = AssetId::from_slice(&[2; 32]).unwrap();
+        let asset_b = AssetId::from_slice(&[3; 32]).unwrap();
+        let utxos = vec![
+            txo(1, 500, Some(policy_asset)),
+            txo(2, 900, Some(policy_asset)),
+            txo(3, 700, Some(policy_asset)),
+            txo(4, 10, Some(asset_a)),
+            txo(5, 20, Some(asset_b)),
+            txo(6, 30, Some(asset_a)),
+        ];
+        let groups = group_utxos(utxos.clone(), Some(policy_asset), 400_000).unwrap();
+        let summary: Vec<Vec<u64>> =
+            groups.iter().map(|g| g.iter().map(|u| u.satoshi).collect()).collect();
+        // Each asset pays the fee with the largest policy asset utxo left
+        let (a, b) = match asset_a < asset_b {
+            true => (vec![30, 10, 900], vec![20, 700]),
+            false => (vec![20, 900], vec![30, 10, 700]),
+        };
+        assert_eq!(summary, [a, b, vec![500]]);
+        assert_eq!(destinations_needed(&groups[0], Some(policy_asset)), 2);
+        assert_eq!(destinations_needed(&groups[2], Some(policy_asset)), 1);
+
+        // Not enough policy asset utxos to pay the fees
+        let err = group_utxos(utxos[2..].to_vec(), Some(policy_asset), 400_000).unwrap_err();
+        assert!(matches!(err, Error::InsufficientFundsForFee));
+    }
+
+    #[test]
+    fn test_build_bitcoin_tx() {
+        let policy = NetworkPolicy::new(gdk_common::NetworkId::Bitcoin(bitcoin::Network::Regtest));
+        let public_key = bitcoin::CompressedPublicKey::from_str(
+            "03ad1d8e89212f0b92c74d23bb710c00662ad1470198ac48c43f7d6f93a2a26873",
+        )
+        .unwrap();
+        let address =
+            BEAddress::Bitcoin(bitcoin::Address::p2wpkh(&public_key, bitcoin::Network::Regtest));
+        let inputs: Vec<_> = (0..3).map(|i| txo(i, 10_000, None)).collect();
+        let built = build_migration_tx(&inputs, &[address.clone()], None, 2_000, &policy).unwrap();
+        assert_eq!(built.fee, built.vsize as u64 * 2);
+        assert_eq!(built.outputs, [(address.clone(), 30_000 - built.fee, None)]);
+        match built.tx {
+            BETransaction::Bitcoin(tx) => {
+                assert_eq!(tx.input.len(), 3);
+                assert_eq!(tx.output[0].value.to_sat(), 30_000 - built.fee);
+            }
+            BETransaction::Elements(_) => panic!("not a bitcoin transaction"),
+        }
+
+        let inputs = vec![txo(0, 300, None)];
+        let err =
+            build_migration_tx(&inputs, &[address], None, 2_000, &policy).map(|_| ()).unwrap_err();
+        assert!(matches!(err, Error::InsufficientFundsForFee));
+    }
+}
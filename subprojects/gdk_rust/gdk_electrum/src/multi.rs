@@ -0,0 +1,290 @@
+//! Sessions of the same wallet on several networks, eg. Bitcoin and Liquid,
+//! with a unified view of their balances and transactions.
+//!
+//! Each network has its own [`ElectrumSession`], the sessions share the
+//! notification queue and the price source and are logged in and out
+//! together.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use gdk_common::model::{
+    Balances, Credentials, GetBalanceOpt, GetTransactionsOpt, LoginData, Notification,
+    NotificationKind, SendTransactionOpt, SendTransactionResult, TxListItem, TxsResult,
+};
+use gdk_common::notification::{NativeNotif, SubscriptionId};
+use gdk_common::session::{JsonError, Session};
+use gdk_common::NetworkParameters;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::error::Error;
+use crate::price::PriceSource;
+use crate::ElectrumSession;
+
+/// A transaction of the merged listing, with the name of its network
+#[derive(Debug, Clone, Serialize)]
+pub struct MultiTxListItem {
+    pub network: String,
+
+    #[serde(flatten)]
+    pub tx: TxListItem,
+}
+
+/// A page of the transactions of all the networks
+#[derive(Debug, Serialize)]
+pub struct MultiTxsResult {
+    /// The requested page of transactions, most recent first
+    pub transactions: Vec<MultiTxListItem>,
+
+    /// The number of transactions matching the filters, in all the networks
+    pub total: usize,
+}
+
+/// One session per network, keyed by a name chosen by the caller
+pub struct MultiSession {
+    sessions: BTreeMap<String, ElectrumSession>,
+
+    /// The notification queue of all the sessions
+    notify: NativeNotif,
+}
+
+impl MultiSession {
+    /// Create a session for each of `networks`, sharing one notification
+    /// queue
+    pub fn new<I>(networks: I) -> Result<Self, JsonError>
+    where
+        I: IntoIterator<Item = (String, NetworkParameters)>,
+    {
+        let mut notify = NativeNotif::new();
+        let mut sessions = BTreeMap::new();
+        for (name, network) in networks {
+            if network.privacy_mode() {
+                notify.privacy_mode = true;
+            }
+            if let Some(queue_size) = network.notification_queue_size {
+                notify.set_queue_size(queue_size);
+            }
+            let session = ElectrumSession::new(network)?;
+            if sessions.insert(name.clone(), session).is_some() {
+                return Err(Error::Generic(format!("network {:?} given twice", name)).into());
+            }
+        }
+        if sessions.is_empty() {
+            return Err(Error::Generic("no network given".into()).into());
+        }
+        for session in sessions.values_mut() {
+            session.notify = notify.clone();
+        }
+        Ok(MultiSession {
+            sessions,
+            notify,
+        })
+    }
+
+    /// The names of the networks, in order
+    pub fn networks(&self) -> impl Iterator<Item = &str> {
+        self.sessions.keys().map(String::as_str)
+    }
+
+    /// The session of `network`
+    pub fn session(&self, network: &str) -> Result<&ElectrumSession, Error> {
+        self.sessions.get(network).ok_or_else(|| Error::UnknownNetwork(network.to_string()))
+    }
+
+    /// The session of `network`, to call the methods without a merged
+    /// counterpart
+    pub fn session_mut(&mut self, network: &str) -> Result<&mut ElectrumSession, Error> {
+        self.sessions.get_mut(network).ok_or_else(|| Error::UnknownNetwork(network.to_string()))
+    }
+
+    /// The notification queue shared by the sessions
+    pub fn notify(&mut self) -> &mut NativeNotif {
+        &mut self.notify
+    }
+
+    /// Register `callback` for the notifications of all the sessions, see
+    /// [`NativeNotif::subscribe`]
+    pub fn subscribe<F>(&self, kinds: &[NotificationKind], callback: F) -> SubscriptionId
+    where
+        F: Fn(&Notification) + Send + Sync + 'static,
+    {
+        self.notify.subscribe(kinds, callback)
+    }
+
+    /// Use `source` for the exchange rates of all the sessions
+    pub fn set_price_source(&mut self, source: Arc<dyn PriceSource>) {
+        for session in self.sessions.values_mut() {
+            session.set_price_source(Arc::clone(&source));
+        }
+    }
+
+    /// Connect all the sessions with their network parameters, none stays
+    /// connected if one fails
+    pub fn connect(&mut self) -> Result<(), Error> {
+        let mut connected = 0;
+        let mut failure = None;
+        for session in self.sessions.values_mut() {
+            let connect = serde_json::to_value(&session.network)
+                .map_err(Error::from)
+                .and_then(|net_params| session.connect(&net_params));
+            if let Err(e) = connect {
+                failure = Some(e);
+                break;
+            }
+            connected += 1;
+        }
+        match failure {
+            Some(e) => {
+                for session in self.sessions.values_mut().take(connected) {
+                    let _ = session.disconnect();
+                }
+                Err(e)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Disconnect all the sessions, returning the first failure
+    pub fn disconnect(&mut self) -> Result<(), Error> {
+        self.sessions.values_mut().map(ElectrumSession::disconnect).fold(Ok(()), Result::and)
+    }
+
+    /// Log in the wallet of `credentials` on all the networks
+    ///
+    /// If a login fails the sessions are logged out, and can't be used any
+    /// longer.
+    pub fn login(
+        &mut self,
+        credentials: &Credentials,
+    ) -> Result<BTreeMap<String, LoginData>, Error> {
+        let logins: Result<BTreeMap<_, _>, Error> = self
+            .sessions
+            .iter_mut()
+            .map(|(name, session)| Ok((name.clone(), session.login(credentials)?)))
+            .collect();
+        if logins.is_err() {
+            let _ = self.logout();
+        }
+        logins
+    }
+
+    /// Stop all the sessions, returning the first failure
+    pub fn logout(&mut self) -> Result<(), Error> {
+        self.sessions.values_mut().map(ElectrumSession::stop).fold(Ok(()), Result::and)
+    }
+
+    /// The balances of each network
+    pub fn get_balance(&self, opt: &GetBalanceOpt) -> Result<BTreeMap<String, Balances>, Error> {
+        self.sessions
+            .iter()
+            .map(|(name, session)| Ok((name.clone(), session.get_balance(opt)?)))
+            .collect()
+    }
+
+    /// The transactions of all the networks, most recent first
+    ///
+    /// `opt.first` and `opt.count` select the page of the merged listing,
+    /// the other filters are applied on each network.
+    pub fn get_transactions(&self, opt: &GetTransactionsOpt) -> Result<MultiTxsResult, Error> {
+        // The page may be made of the most recent transactions of a single network
+        let network_opt = GetTransactionsOpt {
+            first: 0,
+            count: opt.first + opt.count,
+            sync_generation: None,
+            ..opt.clone()
+        };
+        let mut pages = vec![];
+        for (name, session) in self.sessions.iter() {
+            pages.push((name.clone(), session.get_transactions(&network_opt)?));
+        }
+        Ok(merge_txs(pages, opt.first, opt.count))
+    }
+
+    /// Send a signed transaction of `network`
+    pub fn send_transaction(
+        &mut self,
+        network: &str,
+        opt: &SendTransactionOpt,
+    ) -> Result<SendTransactionResult, Error> {
+        self.session_mut(network)?.send_transaction(opt)
+    }
+
+    /// Call `method` of the session of `network`
+    pub fn handle_call(
+        &mut self,
+        network: &str,
+        method: &str,
+        input: Value,
+    ) -> Result<Value, JsonError> {
+        self.session_mut(network)?.handle_call(method, input)
+    }
+}
+
+/// The page from `first` of the transactions of all the pages, ordered by
+/// creation time, most recent first
+fn merge_txs(pages: Vec<(String, TxsResult)>, first: usize, count: usize) -> MultiTxsResult {
+    let total = pages.iter().map(|(_, page)| page.total).sum();
+    let mut transactions: Vec<_> = pages
+        .into_iter()
+        .flat_map(|(network, page)| {
+            page.transactions.into_iter().map(move |tx| MultiTxListItem {
+                network: network.clone(),
+                tx,
+            })
+        })
+        .collect();
+    transactions.sort_by(|a, b| b.tx.created_at_ts.cmp(&a.tx.created_at_ts));
+    MultiTxsResult {
+        transactions: transactions.into_iter().skip(first).take(count).collect(),
+        total,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn page(created_at: &[u64], total: usize) -> TxsResult {
+        TxsResult {
+            transactions: created_at
+                .iter()
+                .map(|created_at_ts| TxListItem {
+                    created_at_ts: *created_at_ts,
+                    ..Default::default()
+                })
+                .collect(),
+            total,
+            sync_generation: 0,
+        }
+    }
+
+    #[test]
+    fn test_merge_txs() {
+        let pages = || {
+            vec![
+                ("bitcoin".to_string(), page(&[50, 30, 10], 3)),
+                ("liquid".to_string(), page(&[40, 20], 5)),
+            ]
+        };
+        let listed = |result: MultiTxsResult| -> Vec<(String, u64)> {
+            result
+                .transactions
+                .into_iter()
+                .map(|item| (item.network, item.tx.created_at_ts))
+                .collect()
+        };
+
+        let merged = merge_txs(pages(), 0, 3);
+        assert_eq!(merged.total, 8);
+        assert_eq!(
+            listed(merged),
+            vec![("bitcoin".into(), 50), ("liquid".into(), 40), ("bitcoin".into(), 30)]
+        );
+        assert_eq!(
+            listed(merge_txs(pages(), 3, 10)),
+            vec![("liquid".into(), 20), ("bitcoin".into(), 10)]
+        );
+        assert!(listed(merge_txs(pages(), 5, 10)).is_empty());
+    }
+}
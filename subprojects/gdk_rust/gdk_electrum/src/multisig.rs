@@ -0,0 +1,236 @@
+//! Multisig subaccounts, receiving on the `wsh(sortedmulti(k,...))` addresses
+//! of the keys of several wallets, one of them being this wallet.
+//!
+//! The session signs the inputs with its own key only: transactions are
+//! exchanged as PSBTs and complete once `k` cosigners have signed them, see
+//! [`crate::psbt`].
+
+use crate::account::xpubs_equivalent;
+use crate::error::Error;
+use gdk_common::bitcoin::bip32::{ChildNumber, DerivationPath, KeySource, Xpub};
+use gdk_common::bitcoin::opcodes::all::{OP_CHECKMULTISIG, OP_PUSHNUM_1};
+use gdk_common::bitcoin::script::{Builder, Instruction};
+use gdk_common::bitcoin::{
+    ecdsa, Address, CompressedPublicKey, Network, PublicKey, Script, ScriptBuf, Witness,
+};
+use gdk_common::descriptor::{parse_multisig_descriptor, MultisigDescriptorKey};
+use gdk_common::miniscript::descriptor::{
+    Descriptor, DescriptorPublicKey, DescriptorXKey, Wildcard,
+};
+use std::collections::BTreeMap;
+
+/// The keys of a multisig subaccount and the number of signatures its
+/// outputs require
+#[derive(Debug, Clone)]
+pub struct Multisig {
+    threshold: usize,
+    keys: Vec<MultisigDescriptorKey>,
+}
+
+impl Multisig {
+    /// The multisig of `descriptor`, which must have the account key `xpub`
+    /// with `origin` among its keys
+    pub fn new(descriptor: &str, origin: &KeySource, xpub: &Xpub) -> Result<Self, Error> {
+        let (threshold, keys) = parse_multisig_descriptor(descriptor)?;
+        let is_ours = |(fingerprint, path, key): &MultisigDescriptorKey| {
+            (*fingerprint, path) == (origin.0, &origin.1) && xpubs_equivalent(key, xpub).is_ok()
+        };
+        if !keys.iter().any(is_ours) {
+            return Err(Error::MultisigKeyNotFound);
+        }
+        if let Some((_, _, key)) = keys.iter().find(|(_, _, key)| key.network != xpub.network) {
+            return Err(Error::Generic(format!("multisig key {} is for another network", key)));
+        }
+        Ok(Multisig {
+            threshold,
+            keys,
+        })
+    }
+
+    /// The number of signatures required to spend the outputs
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// The keys of all the cosigners at `path`, relative to their account
+    /// keys, with their origins, sorted as in the witness script
+    pub fn public_keys(&self, path: &DerivationPath) -> Vec<(CompressedPublicKey, KeySource)> {
+        let mut keys: Vec<_> = self
+            .keys
+            .iter()
+            .map(|(fingerprint, origin, xpub)| {
+                let public_key = xpub.derive_pub(&crate::EC, path).unwrap().to_pub();
+                (public_key, (*fingerprint, origin.extend(path)))
+            })
+            .collect();
+        // BIP67
+        keys.sort_by_key(|(public_key, _)| public_key.to_bytes());
+        keys
+    }
+
+    /// The witness script of the output at `path`
+    pub fn witness_script(&self, path: &DerivationPath) -> ScriptBuf {
+        let keys: Vec<_> = self.public_keys(path).into_iter().map(|(key, _)| key).collect();
+        multisig_script(self.threshold, &keys)
+    }
+
+    /// The address at `path`
+    pub fn address(&self, path: &DerivationPath, network: Network) -> Address {
+        Address::p2wsh(&self.witness_script(path), network)
+    }
+
+    /// The descriptor of the receive or change addresses, with checksum
+    pub fn descriptor(&self, is_internal: bool) -> Result<String, Error> {
+        let keys: Vec<_> = self
+            .keys
+            .iter()
+            .map(|(fingerprint, path, xpub)| {
+                DescriptorPublicKey::XPub(DescriptorXKey {
+                    origin: Some((*fingerprint, path.clone())),
+                    xkey: *xpub,
+                    derivation_path: DerivationPath::from(vec![ChildNumber::from(
+                        is_internal as u32,
+                    )]),
+                    wildcard: Wildcard::Unhardened,
+                })
+                .to_string()
+            })
+            .collect();
+        let desc = format!("wsh(sortedmulti({},{}))", self.threshold, keys.join(","));
+        let (desc, _) = Descriptor::parse_descriptor(&crate::EC, &desc)?;
+        Ok(desc.to_string())
+    }
+}
+
+/// The `threshold` of `keys` OP_CHECKMULTISIG script, keeping the order of `keys`
+fn multisig_script(threshold: usize, keys: &[CompressedPublicKey]) -> ScriptBuf {
+    let mut builder = Builder::new().push_int(threshold as i64);
+    for key in keys {
+        builder = builder.push_key(&PublicKey::from(*key));
+    }
+    builder.push_int(keys.len() as i64).push_opcode(OP_CHECKMULTISIG).into_script()
+}
+
+/// The threshold and the keys of the OP_CHECKMULTISIG `script`, if it is one
+fn parse_multisig_script(script: &Script) -> Option<(usize, Vec<PublicKey>)> {
+    let pushnum = |instruction: &Instruction| match instruction {
+        Instruction::Op(op) => {
+            let n = op.to_u8().checked_sub(OP_PUSHNUM_1.to_u8())? as usize + 1;
+            Some(n).filter(|n| *n <= 16)
+        }
+        _ => None,
+    };
+    let instructions = script.instructions().collect::<Result<Vec<_>, _>>().ok()?;
+    let (last, instructions) = instructions.split_last()?;
+    if *last != Instruction::Op(OP_CHECKMULTISIG) {
+        return None;
+    }
+    let (threshold, instructions) = instructions.split_first()?;
+    let (n, keys) = instructions.split_last()?;
+    let (threshold, n) = (pushnum(threshold)?, pushnum(n)?);
+    let keys = keys
+        .iter()
+        .map(|instruction| match instruction {
+            Instruction::PushBytes(bytes) => PublicKey::from_slice(bytes.as_bytes()).ok(),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    if keys.len() != n || threshold > n {
+        return None;
+    }
+    Some((threshold, keys))
+}
+
+/// The final witness of an input spending the multisig `witness_script`
+/// with the `signatures` of its keys, failing if they are too few
+pub(crate) fn finalize_witness(
+    witness_script: &Script,
+    signatures: &BTreeMap<PublicKey, ecdsa::Signature>,
+) -> Result<Witness, &'static str> {
+    let (threshold, keys) = parse_multisig_script(witness_script).ok_or("unsupported script")?;
+    // The signatures must be in the order of the keys
+    let signatures: Vec<_> =
+        keys.iter().filter_map(|key| signatures.get(key)).take(threshold).collect();
+    if signatures.len() < threshold {
+        return Err("missing signatures");
+    }
+    let mut witness = Witness::new();
+    // The extra element popped by OP_CHECKMULTISIG
+    witness.push(Vec::<u8>::new());
+    for signature in signatures {
+        witness.push(signature.serialize());
+    }
+    witness.push(witness_script.as_bytes());
+    Ok(witness)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gdk_common::bitcoin::bip32::{Fingerprint, Xpriv};
+    use gdk_common::bitcoin::secp256k1::Message;
+    use gdk_common::bitcoin::NetworkKind;
+    use gdk_common::EC;
+
+    fn cosigner(seed: u8) -> (Xpriv, String) {
+        let path: DerivationPath = "m/48'/1'/0'/2'".parse().unwrap();
+        let master = Xpriv::new_master(NetworkKind::Test, &[seed; 32]).unwrap();
+        let xprv = master.derive_priv(&EC, &path).unwrap();
+        let key =
+            format!("[{}/48'/1'/0'/2']{}", master.fingerprint(&EC), Xpub::from_priv(&EC, &xprv));
+        (xprv, key)
+    }
+
+    #[test]
+    fn test_multisig() {
+        let (xprv1, key1) = cosigner(1);
+        let (xprv2, key2) = cosigner(2);
+        let (_, key3) = cosigner(3);
+        let descriptor = format!("wsh(sortedmulti(2,{},{}/<0;1>/*,{}))", key1, key2, key3);
+        let xpub1 = Xpub::from_priv(&EC, &xprv1);
+        let master1 = Xpriv::new_master(NetworkKind::Test, &[1; 32]).unwrap().fingerprint(&EC);
+        let origin = (master1, "m/48'/1'/0'/2'".parse().unwrap());
+        let multisig = Multisig::new(&descriptor, &origin, &xpub1).unwrap();
+        assert_eq!(multisig.threshold(), 2);
+
+        // The key of the wallet must be among the keys, with its origin
+        let other_origin = (Fingerprint::default(), origin.1.clone());
+        let err = Multisig::new(&descriptor, &other_origin, &xpub1).unwrap_err();
+        assert!(matches!(err, Error::MultisigKeyNotFound));
+        let descriptor_without_us = format!("wsh(sortedmulti(1,{},{}))", key2, key3);
+        let err = Multisig::new(&descriptor_without_us, &origin, &xpub1).unwrap_err();
+        assert!(matches!(err, Error::MultisigKeyNotFound));
+
+        let path: DerivationPath = "m/1/7".parse().unwrap();
+        let keys = multisig.public_keys(&path);
+        assert_eq!(keys.len(), 3);
+        assert!(keys.windows(2).all(|w| w[0].0.to_bytes() < w[1].0.to_bytes()));
+        let witness_script = multisig.witness_script(&path);
+        let (threshold, script_keys) = parse_multisig_script(&witness_script).unwrap();
+        assert_eq!(threshold, 2);
+        assert_eq!(script_keys, keys.iter().map(|(k, _)| PublicKey::from(*k)).collect::<Vec<_>>());
+
+        // The change descriptor derives the same address
+        let change = multisig.descriptor(true).unwrap();
+        let change: Descriptor<DescriptorPublicKey> = change.parse().unwrap();
+        let address = change.at_derivation_index(7).unwrap().address(Network::Regtest).unwrap();
+        assert_eq!(address, multisig.address(&path, Network::Regtest));
+
+        // Signatures are put in the order of the keys, one is not enough
+        let message = Message::from_digest([1; 32]);
+        let sign = |xprv: &Xpriv| {
+            let private_key = xprv.derive_priv(&EC, &path).unwrap().private_key;
+            let public_key = PublicKey::new(private_key.public_key(&EC));
+            (public_key, ecdsa::Signature::sighash_all(EC.sign_ecdsa(&message, &private_key)))
+        };
+        let mut signatures: BTreeMap<_, _> = [sign(&xprv1)].into_iter().collect();
+        assert_eq!(finalize_witness(&witness_script, &signatures), Err("missing signatures"));
+        signatures.extend([sign(&xprv2)]);
+        let witness = finalize_witness(&witness_script, &signatures).unwrap();
+        let ordered: Vec<_> =
+            script_keys.iter().filter_map(|k| signatures.get(k)).map(|s| s.to_vec()).collect();
+        let elements: Vec<_> = witness.iter().map(<[u8]>::to_vec).collect();
+        assert_eq!(elements, [vec![vec![]], ordered, vec![witness_script.to_bytes()]].concat());
+        assert!(finalize_witness(&ScriptBuf::new(), &signatures).is_err());
+    }
+}
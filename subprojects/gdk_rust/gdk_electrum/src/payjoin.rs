@@ -0,0 +1,340 @@
+//! Payjoin (BIP78) sender.
+//!
+//! The original transaction, signed and with all inputs from the wallet, is
+//! posted as a PSBT to the receiver endpoint, which returns a proposal
+//! adding some of its inputs. The proposal is checked not to take more from
+//! the wallet than the original transaction and the allowed additional fee,
+//! then the wallet inputs are signed again.
+//!
+//! Output substitution is always disabled, so the receiver can only
+//! increase the amount of its output.
+
+use crate::error::Error;
+use crate::pegout::input_signature_weight;
+use gdk_common::bitcoin::psbt::{self, Psbt};
+use gdk_common::bitcoin::{Amount, ScriptBuf, Transaction, TxOut, Witness};
+use gdk_common::scripts::ScriptType;
+use gdk_common::ureq;
+use gdk_common::url::Url;
+use std::time::Duration;
+
+/// How long to wait for the receiver proposal before sending the original transaction
+pub const PAYJOIN_TIMEOUT: Duration = Duration::from_secs(60);
+
+fn proposal_error(reason: &str) -> Error {
+    Error::Generic(format!("invalid payjoin proposal: {}", reason))
+}
+
+/// The original PSBT of the signed `tx`, whose inputs spend `previous_outputs`
+pub(crate) fn original_psbt(tx: &Transaction, previous_outputs: Vec<TxOut>) -> Result<Psbt, Error> {
+    let mut unsigned_tx = tx.clone();
+    for input in unsigned_tx.input.iter_mut() {
+        input.script_sig = ScriptBuf::new();
+        input.witness = Witness::new();
+    }
+    let mut psbt = Psbt::from_unsigned_tx(unsigned_tx)
+        .map_err(|e| Error::Generic(format!("cannot create psbt: {}", e)))?;
+    for ((input, txin), utxo) in psbt.inputs.iter_mut().zip(&tx.input).zip(previous_outputs) {
+        *input = psbt::Input {
+            witness_utxo: Some(utxo),
+            final_script_sig: Some(txin.script_sig.clone()).filter(|s| !s.is_empty()),
+            final_script_witness: Some(txin.witness.clone()).filter(|w| !w.is_empty()),
+            ..Default::default()
+        };
+    }
+    Ok(psbt)
+}
+
+/// The fee of an additional input of `script_type` at the fee rate of
+/// `original`, the default maximum the receiver can deduct from the change
+pub(crate) fn default_max_additional_fee(
+    original: &Psbt,
+    signed_tx: &Transaction,
+    script_type: ScriptType,
+) -> Result<u64, Error> {
+    let fee = psbt_fee(original)?;
+    let input_weight = 41 * 4 + input_signature_weight(script_type) as u64;
+    Ok(fee * input_weight / signed_tx.weight().to_wu())
+}
+
+/// The receiver `endpoint` with the sender parameters, `change_index` is
+/// the wallet output the receiver can deduct up to `max_additional_fee` from.
+///
+/// The endpoint must be https or an onion service, unless `allow_http`.
+pub(crate) fn endpoint_url(
+    endpoint: &str,
+    change_index: Option<usize>,
+    max_additional_fee: u64,
+    allow_http: bool,
+) -> Result<Url, Error> {
+    let mut url = Url::parse(endpoint)
+        .map_err(|e| Error::Generic(format!("invalid payjoin endpoint: {}", e)))?;
+    let is_onion = url.host_str().is_some_and(|host| host.ends_with(".onion"));
+    match url.scheme() {
+        "https" => (),
+        "http" if is_onion || allow_http => (),
+        _ => return Err(Error::Generic("payjoin endpoint must be https".into())),
+    }
+    {
+        let mut query = url.query_pairs_mut();
+        query.append_pair("v", "1");
+        query.append_pair("disableoutputsubstitution", "true");
+        if let Some(change_index) = change_index {
+            query.append_pair("additionalfeeoutputindex", &change_index.to_string());
+            query.append_pair("maxadditionalfeecontribution", &max_additional_fee.to_string());
+        }
+    }
+    Ok(url)
+}
+
+/// Post `original` to the receiver at `url` and parse its proposal
+pub(crate) fn request_proposal(
+    agent: &ureq::Agent,
+    url: &Url,
+    original: &Psbt,
+) -> Result<Psbt, Error> {
+    let response = agent
+        .post(url.as_str())
+        .timeout(PAYJOIN_TIMEOUT)
+        .set("Content-Type", "text/plain")
+        .send_string(&base64::encode(original.serialize()))?;
+    let proposal = base64::decode(response.into_string()?.trim())?;
+    Psbt::deserialize(&proposal).map_err(|e| proposal_error(&e.to_string()))
+}
+
+/// Check the receiver `proposal` against the `original` PSBT as in BIP78:
+/// the wallet inputs are unchanged and unsigned, the receiver inputs are
+/// finalized and of the same type, no output is removed or decreased but
+/// the change at `change_index`, by at most `max_additional_fee`, which must
+/// go to the fee.
+pub(crate) fn check_proposal(
+    original: &Psbt,
+    proposal: &Psbt,
+    change_index: Option<usize>,
+    max_additional_fee: u64,
+) -> Result<(), Error> {
+    let original_tx = &original.unsigned_tx;
+    let tx = &proposal.unsigned_tx;
+    if tx.version != original_tx.version || tx.lock_time != original_tx.lock_time {
+        return Err(proposal_error("version or lock time changed"));
+    }
+
+    let script_type = |utxo: &TxOut| {
+        let script = &utxo.script_pubkey;
+        (script.is_p2pkh(), script.is_p2sh(), script.is_p2wpkh(), script.is_p2tr())
+    };
+    let sender_script_type = script_type(spend_utxo(original, 0)?);
+    let mut sender_inputs = 0;
+    let mut receiver_inputs = 0;
+    for (i, (txin, input)) in tx.input.iter().zip(&proposal.inputs).enumerate() {
+        let is_final = input.final_script_sig.is_some() || input.final_script_witness.is_some();
+        match original_tx.input.iter().find(|o| o.previous_output == txin.previous_output) {
+            Some(original_txin) => {
+                if txin.sequence != original_txin.sequence {
+                    return Err(proposal_error("sequence of a wallet input changed"));
+                }
+                if is_final || !input.partial_sigs.is_empty() {
+                    return Err(proposal_error("wallet input is signed"));
+                }
+                sender_inputs += 1;
+            }
+            None => {
+                if !is_final {
+                    return Err(proposal_error("receiver input is not finalized"));
+                }
+                if txin.sequence != original_tx.input[0].sequence {
+                    return Err(proposal_error("receiver input has a different sequence"));
+                }
+                if script_type(spend_utxo(proposal, i)?) != sender_script_type {
+                    return Err(proposal_error("receiver input has a different script type"));
+                }
+                receiver_inputs += 1;
+            }
+        }
+    }
+    if sender_inputs != original_tx.input.len() {
+        return Err(proposal_error("wallet input removed"));
+    }
+    if receiver_inputs == 0 {
+        return Err(proposal_error("no receiver input"));
+    }
+
+    let mut change_decrease = Amount::ZERO;
+    for (i, original_output) in original_tx.output.iter().enumerate() {
+        let output = tx
+            .output
+            .iter()
+            .find(|output| output.script_pubkey == original_output.script_pubkey)
+            .ok_or_else(|| proposal_error("output removed"))?;
+        if Some(i) == change_index {
+            change_decrease = original_output.value.checked_sub(output.value).unwrap_or_default();
+            if change_decrease.to_sat() > max_additional_fee {
+                return Err(proposal_error("additional fee too high"));
+            }
+        } else if output.value < original_output.value {
+            return Err(proposal_error("output decreased"));
+        }
+    }
+    // What is taken from the change must go to the fee, not to the receiver
+    if psbt_fee(proposal)? < psbt_fee(original)? + change_decrease.to_sat() {
+        return Err(proposal_error("fee decreased"));
+    }
+    Ok(())
+}
+
+fn spend_utxo(psbt: &Psbt, i: usize) -> Result<&TxOut, Error> {
+    psbt.spend_utxo(i).map_err(|_| proposal_error("missing previous output"))
+}
+
+fn psbt_fee(psbt: &Psbt) -> Result<u64, Error> {
+    let inputs = (0..psbt.inputs.len())
+        .map(|i| spend_utxo(psbt, i).map(|utxo| utxo.value.to_sat()))
+        .sum::<Result<u64, Error>>()?;
+    let outputs = psbt.unsigned_tx.output.iter().map(|o| o.value.to_sat()).sum::<u64>();
+    inputs.checked_sub(outputs).ok_or_else(|| proposal_error("outputs exceed inputs"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gdk_common::bitcoin::hashes::Hash;
+    use gdk_common::bitcoin::{absolute, transaction, OutPoint, Sequence, TxIn, Txid, WPubkeyHash};
+
+    fn utxo(satoshi: u64, n: u8) -> TxOut {
+        TxOut {
+            value: Amount::from_sat(satoshi),
+            script_pubkey: ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([n; 20])),
+        }
+    }
+
+    fn txin(n: u8) -> TxIn {
+        TxIn {
+            previous_output: OutPoint::new(Txid::from_byte_array([n; 32]), 0),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::from_slice(&[vec![n]]),
+            ..Default::default()
+        }
+    }
+
+    /// A wallet input of 100_000 paying 60_000 to the receiver, 39_000 of change
+    fn original() -> Psbt {
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![txin(1)],
+            output: vec![utxo(60_000, 2), utxo(39_000, 1)],
+        };
+        original_psbt(&tx, vec![utxo(100_000, 1)]).unwrap()
+    }
+
+    /// The receiver adds an input of 50_000 to its output, taking `fee` from the change
+    fn proposal(original: &Psbt, fee: u64) -> Psbt {
+        let mut proposal = original.clone();
+        proposal.inputs[0].final_script_witness = None;
+        proposal.unsigned_tx.input.push(TxIn {
+            witness: Witness::new(),
+            ..txin(3)
+        });
+        proposal.inputs.push(psbt::Input {
+            witness_utxo: Some(utxo(50_000, 3)),
+            final_script_witness: Some(Witness::from_slice(&[vec![3]])),
+            ..Default::default()
+        });
+        proposal.unsigned_tx.output[0].value = Amount::from_sat(110_000);
+        proposal.unsigned_tx.output[1].value = Amount::from_sat(39_000 - fee);
+        proposal
+    }
+
+    #[test]
+    fn test_check_proposal() {
+        let original = original();
+        assert_eq!(original.inputs[0].final_script_witness, Some(Witness::from_slice(&[vec![1]])));
+        assert!(original.unsigned_tx.input[0].witness.is_empty());
+
+        let valid = proposal(&original, 200);
+        check_proposal(&original, &valid, Some(1), 300).unwrap();
+
+        let cases: Vec<(&str, Box<dyn Fn(&mut Psbt)>)> = vec![
+            (
+                "additional fee too high",
+                Box::new(|p| p.unsigned_tx.output[1].value -= Amount::from_sat(200)),
+            ),
+            ("output removed", Box::new(|p| drop(p.unsigned_tx.output.remove(1)))),
+            (
+                "output decreased",
+                Box::new(|p| p.unsigned_tx.output[0].value = Amount::from_sat(59_000)),
+            ),
+            ("fee decreased", Box::new(|p| p.unsigned_tx.output[0].value += Amount::from_sat(200))),
+            (
+                "wallet input is signed",
+                Box::new(|p| {
+                    p.inputs[0].final_script_witness = Some(Witness::from_slice(&[vec![1]]))
+                }),
+            ),
+            (
+                "sequence of a wallet input changed",
+                Box::new(|p| p.unsigned_tx.input[0].sequence = Sequence::MAX),
+            ),
+            (
+                "receiver input is not finalized",
+                Box::new(|p| p.inputs[1].final_script_witness = None),
+            ),
+            (
+                "receiver input has a different script type",
+                Box::new(|p| {
+                    p.inputs[1].witness_utxo.as_mut().unwrap().script_pubkey =
+                        ScriptBuf::new_op_return([])
+                }),
+            ),
+            (
+                "wallet input removed",
+                Box::new(|p| {
+                    p.unsigned_tx.input.remove(0);
+                    p.inputs.remove(0);
+                }),
+            ),
+            (
+                "version or lock time changed",
+                Box::new(|p| p.unsigned_tx.lock_time = absolute::LockTime::from_consensus(1)),
+            ),
+        ];
+        for (reason, tamper) in cases {
+            let mut invalid = valid.clone();
+            tamper(&mut invalid);
+            let err = check_proposal(&original, &invalid, Some(1), 300).unwrap_err();
+            assert_eq!(err.to_string(), format!("invalid payjoin proposal: {}", reason));
+        }
+
+        // Without a change output the receiver pays the whole fee
+        let err = check_proposal(&original, &valid, None, 300).unwrap_err();
+        assert_eq!(err.to_string(), "invalid payjoin proposal: output decreased");
+        let err = check_proposal(&original, &original, Some(1), 300).unwrap_err();
+        assert_eq!(err.to_string(), "invalid payjoin proposal: wallet input is signed");
+    }
+
+    #[test]
+    fn test_endpoint_url() {
+        let url = endpoint_url("https://example.com/pj?id=1", Some(1), 300, false).unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://example.com/pj?id=1&v=1&disableoutputsubstitution=true\
+             &additionalfeeoutputindex=1&maxadditionalfeecontribution=300"
+        );
+        assert!(endpoint_url("http://example.com/pj", None, 0, false).is_err());
+        assert!(endpoint_url("http://example.onion/pj", None, 0, false).is_ok());
+        assert!(endpoint_url("http://127.0.0.1:8080/pj", None, 0, true).is_ok());
+        assert!(endpoint_url("not a url", None, 0, true).is_err());
+    }
+
+    #[test]
+    fn test_default_max_additional_fee() {
+        let original = original();
+        let mut signed_tx = original.unsigned_tx.clone();
+        signed_tx.input[0].witness = Witness::from_slice(&[vec![0; 72], vec![0; 33]]);
+        // 1_000 sat for 562 weight units, an additional p2wpkh input is 272
+        assert_eq!(
+            default_max_additional_fee(&original, &signed_tx, ScriptType::P2wpkh).unwrap(),
+            1_000 * 272 / 562
+        );
+    }
+}
@@ -0,0 +1,291 @@
+use crate::error::Error;
+use crate::selection::{self, ChangeParams};
+use gdk_common::amount;
+use gdk_common::be::BEOutPoint;
+use gdk_common::bitcoin::blockdata::constants::genesis_block;
+use gdk_common::bitcoin::hashes::Hash;
+use gdk_common::elements::pset::{self, PartiallySignedTransaction};
+use gdk_common::elements::script::Builder;
+use gdk_common::elements::{self, AssetId, TxOutSecrets};
+use gdk_common::model::{ChangeDecision, Txo};
+use gdk_common::rand::thread_rng;
+use gdk_common::scripts::ScriptType;
+use gdk_common::util::weight_to_vsize;
+use gdk_common::{bitcoin, NetworkPolicy, EC};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Parse `address` and check it belongs to the mainchain `network`
+pub fn parse_mainchain_address(
+    address: &str,
+    network: bitcoin::Network,
+) -> Result<bitcoin::Address, Error> {
+    let address = bitcoin::Address::from_str(address)?;
+    address.require_network(network).map_err(|_| Error::InvalidMainchainAddress)
+}
+
+/// The peg-out output script: an OP_RETURN carrying the genesis hash of the
+/// mainchain and the script pubkey to be created there.
+pub fn pegout_script(network: bitcoin::Network, address: &bitcoin::Address) -> elements::Script {
+    let genesis_hash = genesis_block(network).block_hash();
+    Builder::new()
+        .push_opcode(elements::opcodes::all::OP_RETURN)
+        .push_slice(genesis_hash.as_byte_array())
+        .push_slice(address.script_pubkey().as_bytes())
+        .into_script()
+}
+
+/// Weight of a peg-out transaction without its inputs: the peg-out output,
+/// the confidential change and the fee output
+pub(crate) const PEGOUT_OUTPUTS_WEIGHT: u64 = 12_000;
+
+/// Estimated weight of the witness and script sig added when signing an input
+pub(crate) fn input_signature_weight(script_type: ScriptType) -> usize {
+    match script_type {
+        // 23 bytes of script sig plus a pubkey and signature in the witness
+        ScriptType::P2shP2wpkh => 23 * 4 + 108,
+        ScriptType::P2wpkh => 108,
+        ScriptType::P2pkh => 107 * 4,
+        ScriptType::P2tr => 66,
+        // An empty element, 2 signatures and a 2-of-3 script in the witness,
+        // Green subaccounts are never spent by the wallet
+        ScriptType::P2wsh | ScriptType::GreenMultisig => 1 + 1 + 2 * 73 + 106,
+    }
+}
+
+pub(crate) struct PegoutTx {
+    pub tx: elements::Transaction,
+    pub fee: u64,
+    pub vsize: usize,
+    pub used_utxos: Vec<Txo>,
+    pub change_amount: u64,
+    pub change_decision: ChangeDecision,
+}
+
+/// Build and blind a transaction pegging out `satoshi` with `pegout_script`.
+///
+/// `utxos` must be L-BTC wallet outputs paired with their previous output, they are
+/// selected largest first. The change is sent to `change_address`, which must be
+/// confidential, and is the output absorbing the blinding factors.
+///
+/// The amount and the change must be above the dust limits of `policy` for
+/// their scripts, and the transaction within its maximum weight: if the utxos
+/// needed do not fit, it fails with [`Error::TooManyInputs`] before blinding.
+/// Since the change can't be added to the fee, inputs are added to raise it
+/// above the change floor of `policy` when there are some.
+pub(crate) fn build_pegout_tx(
+    mut utxos: Vec<(Txo, elements::TxOut)>,
+    pegout_script: elements::Script,
+    satoshi: u64,
+    change_address: &elements::Address,
+    policy_asset: AssetId,
+    fee_rate: u64,
+    policy: &NetworkPolicy,
+) -> Result<PegoutTx, Error> {
+    let pegout_txout = elements::TxOut {
+        value: elements::confidential::Value::Explicit(satoshi),
+        script_pubkey: pegout_script.clone(),
+        ..Default::default()
+    };
+    let mainchain_script = pegout_txout
+        .pegout_data()
+        .ok_or_else(|| Error::Generic("invalid pegout script".into()))?
+        .script_pubkey;
+    amount::check_send(satoshi, policy.dust_limits.for_script(&mainchain_script))?;
+    let change_script = change_address.script_pubkey();
+    let change_dust =
+        policy.dust_limits.for_script(bitcoin::Script::from_bytes(change_script.as_bytes()));
+    let blinding_key = change_address
+        .blinding_pubkey
+        .ok_or_else(|| Error::Generic("change address must be confidential".into()))?;
+    let txos: Vec<_> = utxos.iter().map(|u| u.0.clone()).collect();
+    let target = satoshi + change_dust;
+    let max_weight = policy.max_standard_tx_weight;
+    if let Err(
+        e @ Error::TooManyInputs {
+            ..
+        },
+    ) =
+        selection::select_utxos(txos.clone(), target, fee_rate, PEGOUT_OUTPUTS_WEIGHT, max_weight)
+    {
+        return Err(e);
+    }
+    let change_params = ChangeParams {
+        min_change: policy.min_change_satoshi.max(change_dust),
+        dust: change_dust,
+        // Already in the weight of the outputs
+        change_weight: 0,
+        can_fold: false,
+    };
+    // The missing funds are reported by the loop below
+    let floor = selection::select_with_change_floor(
+        txos,
+        satoshi,
+        fee_rate,
+        PEGOUT_OUTPUTS_WEIGHT,
+        &change_params,
+        max_weight,
+    )
+    .ok();
+    let min_count = floor.as_ref().map_or(0, |s| s.utxos.len());
+    utxos.sort_by_key(|u| std::cmp::Reverse(u.0.satoshi));
+
+    let mut fee = 0u64;
+    loop {
+        let mut total = 0u64;
+        let mut selected = vec![];
+        for utxo in utxos.iter() {
+            if selected.len() >= min_count && total >= satoshi + fee + change_dust {
+                break;
+            }
+            total += utxo.0.satoshi;
+            selected.push(utxo);
+        }
+        if total < satoshi + fee + change_dust {
+            if total >= satoshi + change_dust {
+                return Err(Error::InsufficientFundsForFee);
+            }
+            return Err(Error::InsufficientFunds);
+        }
+        let change_amount = total - satoshi - fee;
+
+        let mut pset = PartiallySignedTransaction::new_v2();
+        let mut secrets: HashMap<usize, TxOutSecrets> = HashMap::new();
+        for (i, (txo, txout)) in selected.iter().enumerate() {
+            let outpoint = match txo.outpoint {
+                BEOutPoint::Elements(o) => o,
+                BEOutPoint::Bitcoin(_) => return Err(Error::Generic("not a liquid utxo".into())),
+            };
+            let mut input = pset::Input::from_prevout(outpoint);
+            input.witness_utxo = Some(txout.clone());
+            pset.add_input(input);
+            if let Some(s) = txo.txoutsecrets {
+                secrets.insert(i, s);
+            }
+        }
+        pset.add_output(pset::Output::new_explicit(
+            pegout_script.clone(),
+            satoshi,
+            policy_asset,
+            None,
+        ));
+        let mut change = pset::Output::new_explicit(
+            change_script.clone(),
+            change_amount,
+            policy_asset,
+            Some(blinding_key.into()),
+        );
+        change.blinder_index = Some(0);
+        pset.add_output(change);
+        pset.add_output(pset::Output::from_txout(elements::TxOut::new_fee(fee, policy_asset)));
+
+        pset.blind_last(&mut thread_rng(), &EC, &secrets)
+            .map_err(|e| Error::Generic(format!("cannot blind pegout: {}", e)))?;
+        let tx = pset.extract_tx().map_err(|e| Error::Generic(e.to_string()))?;
+
+        let weight = tx.weight()
+            + selected.iter().map(|u| input_signature_weight(u.0.script_type)).sum::<usize>();
+        ensure!(weight as u64 <= policy.max_standard_tx_weight, Error::TransactionTooLarge);
+        let vsize = weight_to_vsize(weight);
+        let required = (vsize as f64 * fee_rate as f64 / 1000.0).ceil() as u64;
+        if fee >= required {
+            // The estimate of the selection may differ from the blinded size
+            let change_decision = match &floor {
+                _ if change_amount < change_params.min_change => ChangeDecision::BelowFloor,
+                Some(s) if s.decision == ChangeDecision::AddedInput => ChangeDecision::AddedInput,
+                _ => ChangeDecision::Kept,
+            };
+            return Ok(PegoutTx {
+                tx,
+                fee,
+                vsize,
+                used_utxos: selected.into_iter().map(|u| u.0.clone()).collect(),
+                change_amount,
+                change_decision,
+            });
+        }
+        fee = required;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gdk_common::error::ErrorCode;
+
+    #[test]
+    fn test_pegout_script() {
+        let public_key = bitcoin::CompressedPublicKey::from_str(
+            "03ad1d8e89212f0b92c74d23bb710c00662ad1470198ac48c43f7d6f93a2a26873",
+        )
+        .unwrap();
+        let address = bitcoin::Address::p2wpkh(&public_key, bitcoin::Network::Regtest).to_string();
+        assert!(parse_mainchain_address(&address, bitcoin::Network::Bitcoin).is_err());
+        let address = parse_mainchain_address(&address, bitcoin::Network::Regtest).unwrap();
+        let script = pegout_script(bitcoin::Network::Regtest, &address);
+        let txout = elements::TxOut {
+            value: elements::confidential::Value::Explicit(10_000),
+            script_pubkey: script,
+            ..Default::default()
+        };
+        let data = txout.pegout_data().unwrap();
+        assert_eq!(data.value, 10_000);
+        assert_eq!(data.genesis_hash, genesis_block(bitcoin::Network::Regtest).block_hash());
+        assert_eq!(data.script_pubkey, address.script_pubkey());
+    }
+
+    #[test]
+    fn test_pegout_error_codes() {
+        let public_key = bitcoin::CompressedPublicKey::from_str(
+            "03ad1d8e89212f0b92c74d23bb710c00662ad1470198ac48c43f7d6f93a2a26873",
+        )
+        .unwrap();
+        let address = bitcoin::Address::p2wpkh(&public_key, bitcoin::Network::Regtest).to_string();
+        let err = parse_mainchain_address(&address, bitcoin::Network::Bitcoin).unwrap_err();
+        assert_eq!(
+            err.code(),
+            ErrorCode::InvalidAddress {
+                network_mismatch: true
+            }
+        );
+        let err = parse_mainchain_address("not an address", bitcoin::Network::Bitcoin).unwrap_err();
+        assert_eq!(
+            err.code(),
+            ErrorCode::InvalidAddress {
+                network_mismatch: false
+            }
+        );
+
+        let address = parse_mainchain_address(&address, bitcoin::Network::Regtest).unwrap();
+        let script = pegout_script(bitcoin::Network::Regtest, &address);
+        let change_address = elements::Address::p2wpkh(
+            &public_key.into(),
+            Some(public_key.0),
+            &elements::AddressParams::ELEMENTS,
+        );
+        let policy_asset = AssetId::default();
+        let policy = NetworkPolicy::new(gdk_common::NetworkId::Elements(
+            gdk_common::ElementsNetwork::ElementsRegtest,
+        ));
+        let build = |satoshi| {
+            build_pegout_tx(
+                vec![],
+                script.clone(),
+                satoshi,
+                &change_address,
+                policy_asset,
+                policy.min_fee_rate,
+                &policy,
+            )
+            .map(|_| ())
+            .unwrap_err()
+            .code()
+        };
+        // The limit reported by get_network_info is the one enforced
+        let dust = policy.dust_limits.p2wpkh;
+        assert_eq!(build(dust - 1), ErrorCode::AmountBelowDust);
+        assert_eq!(build(0), ErrorCode::AmountZero);
+        assert_eq!(build(amount::SATOSHI_MAX + 1), ErrorCode::AmountOverflow);
+        assert_eq!(build(dust), ErrorCode::InsufficientFunds);
+    }
+}
@@ -0,0 +1,191 @@
+//! Compliance checks of the transactions the session signs and broadcasts.
+//!
+//! A [`TransactionPolicy`] registered with
+//! [`ElectrumSession::set_transaction_policy`](crate::ElectrumSession::set_transaction_policy)
+//! is called with the outputs of each transaction before it is signed and
+//! before it is broadcast, and can reject it or require the user to approve it.
+
+use crate::psbt::Wallet;
+use gdk_common::be::{BEScript, BETransaction, BETxid};
+use gdk_common::NetworkId;
+
+/// The point at which a transaction is submitted to the policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyStage {
+    Sign,
+    Broadcast,
+}
+
+/// An output of a transaction submitted to the policy
+#[derive(Debug, Clone)]
+pub struct PolicyOutput {
+    pub script_pubkey: BEScript,
+
+    /// The address paid, unconfidential on Liquid, `None` for fee and
+    /// non-standard outputs
+    pub address: Option<String>,
+
+    /// The value of the output, unblinded on Liquid if it belongs to the
+    /// wallet, `None` for foreign confidential outputs
+    pub satoshi: Option<u64>,
+
+    /// The asset of the output on Liquid, unblinded like `satoshi`
+    pub asset_id: Option<String>,
+
+    /// The subaccount of the output if it belongs to the wallet, eg. change
+    pub subaccount: Option<u32>,
+
+    /// Whether it is the explicit fee output of a Liquid transaction
+    pub is_fee: bool,
+}
+
+/// A transaction submitted to the policy, with its change and fee outputs
+#[derive(Debug, Clone)]
+pub struct PolicyTransaction {
+    pub stage: PolicyStage,
+
+    pub txid: BETxid,
+
+    pub outputs: Vec<PolicyOutput>,
+
+    /// The fee paid, `None` if some inputs are not of the wallet
+    pub fee: Option<u64>,
+}
+
+/// The outcome of a policy check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyDecision {
+    Allow,
+
+    /// The transaction is refused, the reason is returned to the caller
+    Reject(String),
+
+    /// The transaction must be approved with
+    /// [`ElectrumSession::approve_transaction`](crate::ElectrumSession::approve_transaction)
+    /// before being broadcast
+    RequireConfirmation(String),
+}
+
+/// A compliance check of the transactions of a session
+pub type TransactionPolicy = Box<dyn Fn(&PolicyTransaction) -> PolicyDecision + Send + Sync>;
+
+impl PolicyTransaction {
+    /// The resolved outputs of `tx`, recognizing and unblinding the wallet
+    /// ones with `wallet` if logged in
+    pub(crate) fn new(
+        stage: PolicyStage,
+        tx: &BETransaction,
+        network: NetworkId,
+        wallet: Option<&Wallet>,
+    ) -> Self {
+        let (inputs, subaccounts) = match wallet {
+            Some(wallet) => {
+                let (inputs, outputs) = wallet.wallet_data(tx);
+                (Some(inputs), outputs.into_iter().map(|o| o.map(|txo| txo.subaccount)).collect())
+            }
+            None => (None, vec![None; tx.output_len()]),
+        };
+        let outputs: Vec<_> = subaccounts
+            .into_iter()
+            .enumerate()
+            .map(|(vout, subaccount)| {
+                let vout = vout as u32;
+                let mut satoshi = tx.output_explicit_value(vout);
+                let mut asset_id = tx.output_explicit_asset(vout).map(|a| a.to_string());
+                if let (BETransaction::Elements(elements_tx), Some(_), Some(wallet)) =
+                    (tx, subaccount, wallet)
+                {
+                    let output = &elements_tx.output[vout as usize];
+                    if let Some(master_blinding) = &wallet.store.cache.master_blinding {
+                        if let Ok(secrets) =
+                            crate::unblind_output(output.clone(), master_blinding, None)
+                        {
+                            satoshi = Some(secrets.value);
+                            asset_id = Some(secrets.asset.to_string());
+                        }
+                    }
+                }
+                let is_fee = match tx {
+                    BETransaction::Bitcoin(_) => false,
+                    BETransaction::Elements(tx) => tx.output[vout as usize].is_fee(),
+                };
+                PolicyOutput {
+                    script_pubkey: tx.output_script(vout),
+                    address: tx.output_address(vout, network).filter(|_| !is_fee),
+                    satoshi,
+                    asset_id,
+                    subaccount,
+                    is_fee,
+                }
+            })
+            .collect();
+        let fee = match tx {
+            BETransaction::Elements(_) => {
+                Some(outputs.iter().filter(|o| o.is_fee).filter_map(|o| o.satoshi).sum())
+            }
+            BETransaction::Bitcoin(_) => {
+                let total_in = inputs.and_then(|inputs| {
+                    inputs
+                        .into_iter()
+                        .zip(tx.previous_outputs())
+                        .map(|(input, outpoint)| {
+                            let (previous_tx, _) = input?;
+                            previous_tx.output_explicit_value(outpoint.vout())
+                        })
+                        .sum::<Option<u64>>()
+                });
+                let total_out = outputs.iter().filter_map(|o| o.satoshi).sum::<u64>();
+                total_in.and_then(|total_in| total_in.checked_sub(total_out))
+            }
+        };
+        PolicyTransaction {
+            stage,
+            txid: tx.txid(),
+            outputs,
+            fee,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gdk_common::bitcoin::hashes::Hash;
+    use gdk_common::bitcoin::{self, Amount, ScriptBuf, Transaction, TxOut, WPubkeyHash};
+
+    #[test]
+    fn test_policy_transaction_outputs() {
+        let script_pubkey = ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([1; 20]));
+        let address = bitcoin::Address::from_script(&script_pubkey, bitcoin::Network::Regtest)
+            .unwrap()
+            .to_string();
+        let tx = Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![],
+            output: vec![
+                TxOut {
+                    value: Amount::from_sat(10_000),
+                    script_pubkey,
+                },
+                TxOut {
+                    value: Amount::ZERO,
+                    script_pubkey: ScriptBuf::new_op_return([1, 2, 3]),
+                },
+            ],
+        };
+        let tx = BETransaction::Bitcoin(tx);
+        let network = NetworkId::Bitcoin(bitcoin::Network::Regtest);
+        let policy_tx = PolicyTransaction::new(PolicyStage::Sign, &tx, network, None);
+
+        assert_eq!(policy_tx.txid, tx.txid());
+        assert_eq!(policy_tx.outputs.len(), 2);
+        assert_eq!(policy_tx.outputs[0].address.as_deref(), Some(address.as_str()));
+        assert_eq!(policy_tx.outputs[0].satoshi, Some(10_000));
+        assert_eq!(policy_tx.outputs[0].subaccount, None);
+        assert!(!policy_tx.outputs[0].is_fee);
+        assert_eq!(policy_tx.outputs[1].address, None);
+        // Without the wallet the inputs values are unknown
+        assert_eq!(policy_tx.fee, None);
+    }
+}
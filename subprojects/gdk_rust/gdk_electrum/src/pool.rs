@@ -0,0 +1,77 @@
+//! A small pool of scoped worker threads for the CPU-heavy work of syncing.
+//!
+//! Unblinding confidential outputs, deserializing transactions and verifying
+//! merkle proofs can take seconds on large wallets. Done here, without
+//! holding the store lock, they don't delay the network loops and the
+//! notifications of the session.
+
+use std::num::NonZeroUsize;
+use std::thread;
+
+/// The maximum number of worker threads
+const MAX_WORKERS: usize = 4;
+
+/// Below this number of items the work is done by the calling thread
+const MIN_PARALLEL_ITEMS: usize = 8;
+
+/// Apply `f` to each of `items` with up to [`MAX_WORKERS`] threads, returning
+/// the results in the order of `items`
+pub(crate) fn map<T, R, F>(items: Vec<T>, f: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    let workers = thread::available_parallelism().map_or(1, NonZeroUsize::get).min(MAX_WORKERS);
+    if workers < 2 || items.len() < MIN_PARALLEL_ITEMS {
+        return items.into_iter().map(f).collect();
+    }
+
+    let chunk_size = (items.len() + workers - 1) / workers;
+    let mut chunks = vec![];
+    let mut items = items.into_iter();
+    loop {
+        let chunk: Vec<T> = items.by_ref().take(chunk_size).collect();
+        if chunk.is_empty() {
+            break;
+        }
+        chunks.push(chunk);
+    }
+
+    let f = &f;
+    thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| scope.spawn(move || chunk.into_iter().map(f).collect::<Vec<R>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("Couldn't join on the associated thread"))
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_map() {
+        let items: Vec<u64> = (0..1_000).collect();
+        let threads = Mutex::new(HashSet::new());
+        let squares = map(items, |i| {
+            threads.lock().unwrap().insert(thread::current().id());
+            i * i
+        });
+        assert_eq!(squares, (0..1_000).map(|i| i * i).collect::<Vec<u64>>());
+        assert!(threads.lock().unwrap().len() <= MAX_WORKERS);
+
+        // Few items stay on the calling thread
+        let caller = thread::current().id();
+        let ids = map(vec![1, 2, 3], |_| thread::current().id());
+        assert!(ids.iter().all(|id| *id == caller));
+        assert!(map(Vec::<u8>::new(), |i| i).is_empty());
+    }
+}
@@ -0,0 +1,590 @@
+//! Exchange rates used to convert amounts to and from fiat currencies.
+//!
+//! Rates are obtained from a [`PriceSource`], by default an [`HttpPriceSource`]
+//! querying the price server of the network, and kept in the session
+//! [`ExchangeRatesCache`] for [`RATE_TTL`]. When a current rate cannot be
+//! obtained conversions still succeed, but without fiat values.
+//!
+//! The currencies available at each exchange change rarely, they're kept for
+//! [`CURRENCIES_TTL`] and served from the cache when the source is offline.
+
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use gdk_common::amount::{self, Unit};
+use gdk_common::exchange_rates::{Currency, ExchangeRatesCache, Pair, Ticker};
+use gdk_common::log::{info, warn};
+use gdk_common::model::{ConvertAmountOpt, ConvertAmountResult};
+use gdk_common::ureq;
+
+use crate::error::Error;
+
+/// How long a fetched exchange rate is considered current
+pub const RATE_TTL: Duration = Duration::from_secs(60);
+
+/// How long the fetched available currencies are considered current
+pub const CURRENCIES_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The keys are exchange names, the values are all the currencies that a
+/// given exchange has data for.
+pub type AvailableCurrencies = HashMap<String, Vec<Currency>>;
+
+/// The decimal places accepted in fiat amounts
+const FIAT_PRECISION: u8 = 8;
+
+/// A provider of exchange rates
+pub trait PriceSource: Send + Sync {
+    /// Fetch the current rate of `pair` at `exchange`
+    fn fetch(&self, pair: Pair, exchange: &str) -> Result<Ticker, Error>;
+
+    /// Fetch the fiat currencies available at each exchange
+    fn available_currencies(&self) -> Result<AvailableCurrencies, Error>;
+
+    /// Fetch the rate of `pair` at `exchange` at the unix time `timestamp`,
+    /// `None` if the source has no historical rates
+    fn fetch_historical(
+        &self,
+        _pair: Pair,
+        _exchange: &str,
+        _timestamp: u64,
+    ) -> Result<Option<Ticker>, Error> {
+        Ok(None)
+    }
+}
+
+/// Fetches exchange rates from a Green price server
+pub struct HttpPriceSource {
+    agent: ureq::Agent,
+    url: String,
+
+    /// The base url of the historical rates endpoint, if any
+    history_url: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct ExchangeRateResponse {
+    // TODO: this should be returned as a number by the server.
+    // rate: f64,
+    rate: String,
+}
+
+impl ExchangeRateResponse {
+    fn ticker(&self, pair: Pair) -> Result<Ticker, Error> {
+        let rate = self
+            .rate
+            .parse::<f64>()
+            .map_err(|_| Error::Generic(format!("invalid exchange rate {}", self.rate)))?;
+        Ok(Ticker::new(pair, rate))
+    }
+}
+
+impl HttpPriceSource {
+    /// `agent` should be built with the session proxy, see
+    /// [`ElectrumSession::build_request_agent`](crate::ElectrumSession::build_request_agent)
+    pub fn new(agent: ureq::Agent, url: &str) -> Self {
+        HttpPriceSource {
+            agent,
+            url: url.to_string(),
+            history_url: None,
+        }
+    }
+
+    /// Fetch historical rates from the server at `url`
+    pub fn with_history_url(mut self, url: Option<&str>) -> Self {
+        self.history_url = url.map(str::to_string);
+        self
+    }
+}
+
+impl PriceSource for HttpPriceSource {
+    fn fetch(&self, pair: Pair, exchange: &str) -> Result<Ticker, Error> {
+        let endpoint = format!(
+            "{}/v0/venues/{}/pairs/{}/{}",
+            self.url,
+            exchange.to_ascii_uppercase(),
+            pair.first().endpoint_name(),
+            pair.second().endpoint_name()
+        );
+
+        info!("fetching {} price data from {}", pair, endpoint);
+
+        let response = self.agent.get(&endpoint).call()?.into_json::<ExchangeRateResponse>()?;
+        response.ticker(pair)
+    }
+
+    fn fetch_historical(
+        &self,
+        pair: Pair,
+        exchange: &str,
+        timestamp: u64,
+    ) -> Result<Option<Ticker>, Error> {
+        let url = match &self.history_url {
+            Some(url) => url,
+            None => return Ok(None),
+        };
+        let endpoint = format!(
+            "{}/v0/venues/{}/pairs/{}/{}/history?timestamp={}",
+            url,
+            exchange.to_ascii_uppercase(),
+            pair.first().endpoint_name(),
+            pair.second().endpoint_name(),
+            timestamp
+        );
+
+        info!("fetching {} historical price data from {}", pair, endpoint);
+
+        let response = self.agent.get(&endpoint).call()?.into_json::<ExchangeRateResponse>()?;
+        response.ticker(pair).map(Some)
+    }
+
+    fn available_currencies(&self) -> Result<AvailableCurrencies, Error> {
+        #[derive(serde::Deserialize)]
+        struct ExchangeInfos {
+            pairs: Vec<(Currency, Currency)>,
+        }
+
+        let endpoint = format!("{}/v0/venues", self.url);
+
+        let response =
+            self.agent.get(&endpoint).call()?.into_json::<HashMap<String, ExchangeInfos>>()?;
+
+        let map = response.into_iter().map(|(exchange, infos)| {
+            let currencies = infos.pairs.into_iter().map(|(first, second)| {
+                // Either the first or the second currency in the pair must be
+                // fiat (but not both).
+                if !(first.is_fiat() ^ second.is_fiat()) {
+                    panic!("Was expecting one currency in the pair to be Bitcoin, got {}-{} instead", first, second);
+                }
+                if first.is_fiat() { first } else { second }
+            }).collect::<Vec<Currency>>();
+
+            (exchange, currencies)
+        }).collect();
+
+        Ok(map)
+    }
+}
+
+/// The rate of `pair` with the time it was fetched, taken from `cache` if
+/// fetched less than `ttl` ago, otherwise from `source`.
+///
+/// Returns `None` if there is no current rate, eg. if the price server cannot
+/// be reached.
+pub fn current_rate(
+    cache: &ExchangeRatesCache,
+    source: Option<&dyn PriceSource>,
+    pair: Pair,
+    exchange: &str,
+    ttl: Duration,
+) -> Option<(f64, SystemTime)> {
+    let cached = cache.lock().unwrap().get(&pair).copied();
+    if let Some((fetched_at, rate)) = cached {
+        if fetched_at + ttl > SystemTime::now() {
+            return Some((rate, fetched_at));
+        }
+    }
+
+    match source?.fetch(pair, exchange) {
+        Ok(ticker) if ticker.rate.is_finite() && ticker.rate > 0.0 => {
+            let fetched_at = SystemTime::now();
+            cache.lock().unwrap().insert(pair, (fetched_at, ticker.rate));
+            Some((ticker.rate, fetched_at))
+        }
+        Ok(ticker) => {
+            warn!("ignoring invalid exchange rate {:?}", ticker);
+            None
+        }
+        Err(e) => {
+            warn!("cannot fetch {} exchange rate: {:?}", pair, e);
+            None
+        }
+    }
+}
+
+/// The currencies available at each exchange, taken from `cache` if fetched
+/// less than `ttl` ago, otherwise from `source`.
+///
+/// If `source` is not available the cached currencies are returned even if
+/// they're older than `ttl`.
+pub fn available_currencies<'a>(
+    cache: &'a mut Option<(SystemTime, AvailableCurrencies)>,
+    source: Option<&dyn PriceSource>,
+    ttl: Duration,
+) -> Result<&'a AvailableCurrencies, Error> {
+    let expired = match cache {
+        Some((fetched_at, _)) => *fetched_at + ttl <= SystemTime::now(),
+        None => true,
+    };
+    if expired {
+        let fetched = match source {
+            Some(source) => source.available_currencies(),
+            None => Err(Error::Generic("no price source available".into())),
+        };
+        match fetched {
+            Ok(currencies) => *cache = Some((SystemTime::now(), currencies)),
+            Err(e) if cache.is_some() => {
+                warn!("cannot fetch available currencies, using cached ones: {:?}", e)
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(&cache.as_ref().expect("filled above").1)
+}
+
+/// Check that `currency` is available at `exchange`
+pub fn validate_pricing(
+    currencies: &AvailableCurrencies,
+    currency: &str,
+    exchange: &str,
+) -> Result<(), Error> {
+    let supported = currencies
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(exchange))
+        .is_some_and(|(_, available)| available.iter().any(|c| c.to_string() == currency));
+    ensure!(
+        supported,
+        Error::UnsupportedCurrency {
+            currency: currency.to_string(),
+            exchange: exchange.to_string(),
+        }
+    );
+    Ok(())
+}
+
+/// Convert the amount in `opt` to all the units and to `currency`, using the
+/// `BTC-currency` `rate` and the time it was fetched.
+pub fn convert_amount(
+    opt: &ConvertAmountOpt,
+    currency: Currency,
+    rate: Option<(f64, SystemTime)>,
+) -> Result<ConvertAmountResult, Error> {
+    let amounts = [
+        opt.satoshi.is_some(),
+        opt.btc.is_some(),
+        opt.mbtc.is_some(),
+        opt.ubtc.is_some(),
+        opt.bits.is_some(),
+        opt.fiat.is_some(),
+    ];
+    ensure!(amounts.iter().filter(|is_set| **is_set).count() == 1, Error::InvalidAmount);
+
+    let satoshi = if let Some(satoshi) = opt.satoshi {
+        satoshi
+    } else if let Some(btc) = &opt.btc {
+        amount::parse_signed(btc, Unit::Btc.precision())?
+    } else if let Some(mbtc) = &opt.mbtc {
+        amount::parse_signed(mbtc, Unit::MilliBtc.precision())?
+    } else if let Some(ubtc) = opt.ubtc.as_ref().or(opt.bits.as_ref()) {
+        amount::parse_signed(ubtc, Unit::MicroBtc.precision())?
+    } else {
+        let fiat = opt.fiat.as_deref().unwrap_or_default();
+        let fiat =
+            amount::parse_signed(fiat, FIAT_PRECISION)? as f64 / 10f64.powi(FIAT_PRECISION as i32);
+        let (rate, _) = rate.ok_or(Error::FiatRateUnavailable(currency))?;
+        (fiat / rate * 100_000_000.0).round() as i64
+    };
+    amount::check_supply(satoshi.unsigned_abs())?;
+
+    let format = |unit: Unit| amount::format(satoshi, unit.precision());
+    Ok(ConvertAmountResult {
+        satoshi,
+        btc: format(Unit::Btc),
+        mbtc: format(Unit::MilliBtc),
+        ubtc: format(Unit::MicroBtc),
+        bits: format(Unit::Bits),
+        sats: format(Unit::Sats),
+        fiat: rate.map(|(rate, _)| format!("{:.2}", satoshi as f64 * rate / 100_000_000.0)),
+        fiat_currency: currency.to_string(),
+        fiat_rate: rate.map(|(rate, _)| format!("{:.8}", rate)),
+        fiat_rate_timestamp: rate.map(|(_, fetched_at)| {
+            fetched_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+        }),
+        fiat_rate_stale: rate.is_none(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    struct MockPriceSource {
+        rate: Option<f64>,
+        calls: AtomicU32,
+    }
+
+    impl MockPriceSource {
+        fn new(rate: Option<f64>) -> Self {
+            MockPriceSource {
+                rate,
+                calls: AtomicU32::new(0),
+            }
+        }
+
+        fn calls(&self) -> u32 {
+            self.calls.load(Ordering::Relaxed)
+        }
+    }
+
+    impl PriceSource for MockPriceSource {
+        fn fetch(&self, pair: Pair, _exchange: &str) -> Result<Ticker, Error> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            let rate = self.rate.ok_or_else(|| Error::Generic("offline".into()))?;
+            Ok(Ticker::new(pair, rate))
+        }
+
+        fn available_currencies(&self) -> Result<AvailableCurrencies, Error> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            ensure!(self.rate.is_some(), Error::Generic("offline".into()));
+            let currencies = vec![Currency::USD, Currency::EUR];
+            Ok(AvailableCurrencies::from([("BITFINEX".to_string(), currencies)]))
+        }
+    }
+
+    fn usd() -> Pair {
+        Pair::new(Currency::BTC, Currency::USD)
+    }
+
+    #[test]
+    fn test_current_rate() {
+        let cache = ExchangeRatesCache::default();
+        let source = MockPriceSource::new(Some(20_000.0));
+
+        let (rate, fetched_at) =
+            current_rate(&cache, Some(&source), usd(), "BITFINEX", RATE_TTL).unwrap();
+        assert_eq!(rate, 20_000.0);
+        assert_eq!(source.calls(), 1);
+
+        // cached
+        let cached = current_rate(&cache, Some(&source), usd(), "BITFINEX", RATE_TTL).unwrap();
+        assert_eq!(cached, (rate, fetched_at));
+        assert_eq!(source.calls(), 1);
+
+        // other pairs are fetched
+        let eur = Pair::new(Currency::BTC, Currency::EUR);
+        current_rate(&cache, Some(&source), eur, "BITFINEX", RATE_TTL).unwrap();
+        assert_eq!(source.calls(), 2);
+
+        // expired
+        let old = SystemTime::now() - 2 * RATE_TTL;
+        cache.lock().unwrap().insert(usd(), (old, 19_000.0));
+        let (rate, _) = current_rate(&cache, Some(&source), usd(), "BITFINEX", RATE_TTL).unwrap();
+        assert_eq!(rate, 20_000.0);
+        assert_eq!(source.calls(), 3);
+
+        // expired and the source is not available
+        let offline = MockPriceSource::new(None);
+        cache.lock().unwrap().insert(usd(), (old, 19_000.0));
+        assert_eq!(current_rate(&cache, Some(&offline), usd(), "BITFINEX", RATE_TTL), None);
+        assert_eq!(offline.calls(), 1);
+        assert_eq!(current_rate(&cache, None, usd(), "BITFINEX", RATE_TTL), None);
+
+        // invalid rates are not cached
+        let broken = MockPriceSource::new(Some(f64::NAN));
+        assert_eq!(current_rate(&cache, Some(&broken), usd(), "BITFINEX", RATE_TTL), None);
+        assert_eq!(cache.lock().unwrap().get(&usd()).unwrap().0, old);
+    }
+
+    #[test]
+    fn test_available_currencies() {
+        let mut cache = None;
+        let source = MockPriceSource::new(Some(20_000.0));
+        let offline = MockPriceSource::new(None);
+
+        assert!(available_currencies(&mut cache, Some(&offline), CURRENCIES_TTL).is_err());
+        assert!(available_currencies(&mut cache, None, CURRENCIES_TTL).is_err());
+
+        let currencies = available_currencies(&mut cache, Some(&source), CURRENCIES_TTL).unwrap();
+        assert_eq!(currencies["BITFINEX"], vec![Currency::USD, Currency::EUR]);
+        assert_eq!(source.calls(), 1);
+
+        // cached
+        available_currencies(&mut cache, Some(&source), CURRENCIES_TTL).unwrap();
+        assert_eq!(source.calls(), 1);
+
+        // expired, but the source is offline
+        cache.as_mut().unwrap().0 = SystemTime::now() - 2 * CURRENCIES_TTL;
+        let currencies = available_currencies(&mut cache, Some(&offline), CURRENCIES_TTL).unwrap();
+        assert_eq!(currencies.len(), 1);
+        assert_eq!(offline.calls(), 2);
+
+        // expired
+        available_currencies(&mut cache, Some(&source), CURRENCIES_TTL).unwrap();
+        assert_eq!(source.calls(), 2);
+        assert!(cache.as_ref().unwrap().0 + CURRENCIES_TTL > SystemTime::now());
+
+        let currencies = cache.unwrap().1;
+        validate_pricing(&currencies, "USD", "BITFINEX").unwrap();
+        validate_pricing(&currencies, "EUR", "bitfinex").unwrap();
+        for (currency, exchange) in [("JPY", "BITFINEX"), ("USD", "KRAKEN"), ("", "")] {
+            let err = validate_pricing(&currencies, currency, exchange).unwrap_err();
+            assert!(matches!(err, Error::UnsupportedCurrency { .. }));
+            assert_eq!(err.to_gdk_code(), "id_unsupported_currency");
+        }
+    }
+
+    #[test]
+    fn test_convert_amount() {
+        let fetched_at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let rate = Some((20_000.0, fetched_at));
+
+        let opt = ConvertAmountOpt {
+            satoshi: Some(1_234_567),
+            ..Default::default()
+        };
+        let converted = convert_amount(&opt, Currency::USD, rate).unwrap();
+        assert_eq!(
+            converted,
+            ConvertAmountResult {
+                satoshi: 1_234_567,
+                btc: "0.01234567".into(),
+                mbtc: "12.34567".into(),
+                ubtc: "12345.67".into(),
+                bits: "12345.67".into(),
+                sats: "1234567".into(),
+                fiat: Some("246.91".into()),
+                fiat_currency: "USD".into(),
+                fiat_rate: Some("20000.00000000".into()),
+                fiat_rate_timestamp: Some(1_700_000_000),
+                fiat_rate_stale: false,
+            }
+        );
+
+        let inputs = [
+            ConvertAmountOpt {
+                btc: Some("0.01234567".into()),
+                ..Default::default()
+            },
+            ConvertAmountOpt {
+                mbtc: Some("12.34567".into()),
+                ..Default::default()
+            },
+            ConvertAmountOpt {
+                bits: Some("12345.67".into()),
+                ..Default::default()
+            },
+            ConvertAmountOpt {
+                ubtc: Some("12345.67".into()),
+                ..Default::default()
+            },
+            ConvertAmountOpt {
+                fiat: Some("246.9134".into()),
+                ..Default::default()
+            },
+        ];
+        for opt in inputs.iter() {
+            assert_eq!(convert_amount(opt, Currency::USD, rate).unwrap(), converted);
+        }
+
+        let opt = ConvertAmountOpt {
+            btc: Some("-.00000001".into()),
+            ..Default::default()
+        };
+        let converted = convert_amount(&opt, Currency::USD, rate).unwrap();
+        assert_eq!(converted.satoshi, -1);
+        assert_eq!(converted.btc, "-0.00000001");
+        assert_eq!(converted.mbtc, "-0.00001");
+    }
+
+    #[test]
+    fn test_convert_amount_stale() {
+        let eur = Currency::from_str("EUR").unwrap();
+        let opt = ConvertAmountOpt {
+            btc: Some("1".into()),
+            ..Default::default()
+        };
+        let converted = convert_amount(&opt, eur, None).unwrap();
+        assert_eq!(converted.satoshi, 100_000_000);
+        assert_eq!(converted.fiat, None);
+        assert_eq!(converted.fiat_rate, None);
+        assert_eq!(converted.fiat_rate_timestamp, None);
+        assert_eq!(converted.fiat_currency, "EUR");
+        assert!(converted.fiat_rate_stale);
+
+        let opt = ConvertAmountOpt {
+            fiat: Some("10".into()),
+            ..Default::default()
+        };
+        let err = convert_amount(&opt, eur, None).unwrap_err();
+        assert!(matches!(err, Error::FiatRateUnavailable(Currency::EUR)));
+    }
+
+    #[test]
+    fn test_convert_amount_invalid() {
+        use gdk_common::error::ErrorCode;
+
+        let rate = Some((20_000.0, SystemTime::now()));
+        let invalid = [
+            (ConvertAmountOpt::default(), ErrorCode::InvalidAmount),
+            (
+                ConvertAmountOpt {
+                    satoshi: Some(1),
+                    btc: Some("1".into()),
+                    ..Default::default()
+                },
+                ErrorCode::InvalidAmount,
+            ),
+            (
+                ConvertAmountOpt {
+                    satoshi: Some(amount::SATOSHI_MAX as i64 + 1),
+                    ..Default::default()
+                },
+                ErrorCode::AmountOverflow,
+            ),
+            (
+                ConvertAmountOpt {
+                    btc: Some("-21000000.00000001".into()),
+                    ..Default::default()
+                },
+                ErrorCode::AmountOverflow,
+            ),
+            (
+                ConvertAmountOpt {
+                    btc: Some("99999999999999999".into()),
+                    ..Default::default()
+                },
+                ErrorCode::AmountOverflow,
+            ),
+            (
+                ConvertAmountOpt {
+                    fiat: Some("1000000000000".into()),
+                    ..Default::default()
+                },
+                ErrorCode::AmountOverflow,
+            ),
+            (
+                ConvertAmountOpt {
+                    btc: Some("1.123456789".into()),
+                    ..Default::default()
+                },
+                ErrorCode::AmountPrecisionLoss,
+            ),
+            (
+                ConvertAmountOpt {
+                    fiat: Some("10.000000001".into()),
+                    ..Default::default()
+                },
+                ErrorCode::AmountPrecisionLoss,
+            ),
+            (
+                ConvertAmountOpt {
+                    fiat: Some("NaN".into()),
+                    ..Default::default()
+                },
+                ErrorCode::InvalidAmount,
+            ),
+            (
+                ConvertAmountOpt {
+                    bits: Some("1e3".into()),
+                    ..Default::default()
+                },
+                ErrorCode::InvalidAmount,
+            ),
+        ];
+        for (opt, code) in invalid.iter() {
+            let err = convert_amount(opt, Currency::USD, rate).unwrap_err();
+            assert_eq!(err.code(), *code, "{:?}", opt);
+        }
+    }
+}
@@ -0,0 +1,1099 @@
+use crate::account::Account;
+use crate::anti_exfil;
+use crate::error::Error;
+use crate::multisig;
+use crate::pegout::input_signature_weight;
+use crate::signer::Signer;
+use crate::store::StoreMeta;
+use gdk_common::be::{BEScript, BEScriptConvert, BETransaction, BETxOut, BETxid};
+use gdk_common::bitcoin::bip32::{DerivationPath, Fingerprint, KeySource};
+use gdk_common::bitcoin::psbt::{self, Psbt};
+use gdk_common::bitcoin::{CompressedPublicKey, ScriptBuf, Witness};
+use gdk_common::elements::pset::PartiallySignedTransaction;
+use gdk_common::elements::TxOutSecrets;
+use gdk_common::model::{Balances, PsbtDetails, PsbtTxoDetails, PsbtWarning, SignPsbtResult};
+use gdk_common::scripts::{p2shwpkh_script_sig, ScriptType};
+use gdk_common::util::weight_to_vsize;
+use gdk_common::{bitcoin, elements, NetworkId};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+/// The default maximum fee rate of [`psbt_details`], 0.1 BTC/kvB
+pub const DEFAULT_MAX_FEE_RATE: u64 = 10_000_000;
+
+/// The wallet data of an output spent or created by a transaction
+#[derive(Debug, Clone)]
+pub struct WalletTxo {
+    pub subaccount: u32,
+
+    pub public_key: CompressedPublicKey,
+
+    /// The master key fingerprint and the full derivation path of `public_key`
+    pub origin: KeySource,
+
+    pub script_type: ScriptType,
+
+    /// The witness script of multisig outputs
+    pub witness_script: Option<ScriptBuf>,
+
+    /// The keys of the other cosigners of multisig outputs, with their origins
+    pub cosigner_keys: Vec<(CompressedPublicKey, KeySource)>,
+}
+
+impl WalletTxo {
+    fn wpkh_script(&self) -> ScriptBuf {
+        ScriptBuf::new_p2wpkh(&self.public_key.wpubkey_hash())
+    }
+
+    /// The redeem script of nested segwit outputs
+    fn redeem_script(&self) -> Option<ScriptBuf> {
+        match self.script_type {
+            ScriptType::P2shP2wpkh => Some(self.wpkh_script()),
+            _ => None,
+        }
+    }
+}
+
+/// The wallet accounts and their store, to recognize the wallet outputs
+pub(crate) struct Wallet<'a> {
+    pub accounts: &'a HashMap<u32, Account>,
+    pub store: &'a StoreMeta,
+}
+
+/// The previous transactions and wallet data of the wallet inputs of a
+/// transaction, and the wallet data of its wallet outputs
+pub(crate) type WalletData = (Vec<Option<(BETransaction, WalletTxo)>>, Vec<Option<WalletTxo>>);
+
+impl Wallet<'_> {
+    fn master_xpub_fingerprint(&self) -> Option<Fingerprint> {
+        self.accounts.values().next().map(Account::master_xpub_fingerprint)
+    }
+
+    fn txo(&self, script_pubkey: &BEScript) -> Option<WalletTxo> {
+        self.accounts.values().find_map(|account| {
+            let acc_store = self.store.account_cache(account.num()).ok()?;
+            account.wallet_txo(acc_store, script_pubkey)
+        })
+    }
+
+    fn previous_tx(&self, txid: &BETxid) -> Option<&BETransaction> {
+        let mut txs = self.store.cache.accounts.values().map(|acc_store| &acc_store.all_txs);
+        txs.find_map(|all_txs| all_txs.get(txid)).map(|txe| &txe.tx)
+    }
+
+    fn unblinded(&self, outpoint: &elements::OutPoint) -> Option<&TxOutSecrets> {
+        let mut accounts = self.store.cache.accounts.values();
+        accounts.find_map(|acc_store| acc_store.unblinded.get(outpoint))
+    }
+
+    /// The wallet data of the inputs and outputs of `tx`, `None` for foreign ones
+    pub fn wallet_data(&self, tx: &BETransaction) -> WalletData {
+        let inputs = tx
+            .previous_outputs()
+            .iter()
+            .map(|outpoint| {
+                let previous_tx = self.previous_tx(&outpoint.txid())?;
+                let txo = self.txo(&previous_tx.output_script(outpoint.vout()))?;
+                Some((previous_tx.clone(), txo))
+            })
+            .collect();
+        let outputs =
+            (0..tx.output_len() as u32).map(|vout| self.txo(&tx.output_script(vout))).collect();
+        (inputs, outputs)
+    }
+}
+
+/// Build the base64 PSBT (BIP174 v0) of `tx`, or the PSET on Liquid, with
+/// the `wallet_data` of its inputs and outputs, and the `sighash_types` to
+/// sign its inputs with.
+///
+/// Foreign inputs and outputs are left for other signers to fill.
+pub(crate) fn create_psbt(
+    tx: BETransaction,
+    wallet_data: WalletData,
+    sighash_types: &[Option<u32>],
+) -> Result<String, Error> {
+    match tx {
+        BETransaction::Bitcoin(tx) => {
+            let mut psbt = Psbt::from_unsigned_tx(tx)
+                .map_err(|e| Error::Generic(format!("cannot create psbt: {}", e)))?;
+            add_bitcoin_wallet_data(&mut psbt, wallet_data);
+            for (input, sighash_type) in psbt.inputs.iter_mut().zip(sighash_types) {
+                input.sighash_type = sighash_type.map(psbt::PsbtSighashType::from_u32);
+            }
+            Ok(base64::encode(psbt.serialize()))
+        }
+        BETransaction::Elements(tx) => {
+            let mut pset = PartiallySignedTransaction::from_tx(tx);
+            add_elements_wallet_data(&mut pset, wallet_data);
+            for (input, sighash_type) in pset.inputs_mut().iter_mut().zip(sighash_types) {
+                input.sighash_type = sighash_type.map(elements::pset::PsbtSighashType::from_u32);
+            }
+            Ok(base64::encode(elements::encode::serialize(&pset)))
+        }
+    }
+}
+
+/// Set the previous outputs, key origins and redeem scripts of the wallet
+/// inputs and outputs, replacing what other parties might have set
+pub(crate) fn add_bitcoin_wallet_data(psbt: &mut Psbt, (inputs, outputs): WalletData) {
+    for (i, wallet_input) in inputs.into_iter().enumerate() {
+        let (previous_tx, txo) = match wallet_input {
+            Some((BETransaction::Bitcoin(previous_tx), txo)) => (previous_tx, txo),
+            _ => continue,
+        };
+        let vout = psbt.unsigned_tx.input[i].previous_output.vout as usize;
+        let input = &mut psbt.inputs[i];
+        if txo.script_type.is_segwit() {
+            input.witness_utxo = Some(previous_tx.output[vout].clone());
+        }
+        match txo.script_type {
+            ScriptType::P2tr => {
+                let internal_key = txo.public_key.0.x_only_public_key().0;
+                input.tap_internal_key = Some(internal_key);
+                input.tap_key_origins.insert(internal_key, (vec![], txo.origin));
+            }
+            _ => {
+                // Signers should verify the amounts of segwit v0 inputs too
+                input.non_witness_utxo = Some(previous_tx);
+                input.redeem_script = txo.redeem_script();
+                input.witness_script = txo.witness_script.clone();
+                let cosigner_keys = txo.cosigner_keys.iter().map(|(key, o)| (key.0, o.clone()));
+                input.bip32_derivation.extend(cosigner_keys);
+                input.bip32_derivation.insert(txo.public_key.0, txo.origin);
+            }
+        }
+    }
+    for (output, txo) in psbt.outputs.iter_mut().zip(outputs) {
+        let txo = match txo {
+            Some(txo) => txo,
+            None => continue,
+        };
+        match txo.script_type {
+            ScriptType::P2tr => {
+                let internal_key = txo.public_key.0.x_only_public_key().0;
+                output.tap_internal_key = Some(internal_key);
+                output.tap_key_origins.insert(internal_key, (vec![], txo.origin));
+            }
+            _ => {
+                output.redeem_script = txo.redeem_script();
+                output.witness_script = txo.witness_script.clone();
+                let cosigner_keys = txo.cosigner_keys.iter().map(|(key, o)| (key.0, o.clone()));
+                output.bip32_derivation.extend(cosigner_keys);
+                output.bip32_derivation.insert(txo.public_key.0, txo.origin);
+            }
+        }
+    }
+}
+
+/// As [`add_bitcoin_wallet_data`], for a PSET
+fn add_elements_wallet_data(pset: &mut PartiallySignedTransaction, (inputs, outputs): WalletData) {
+    for (input, wallet_input) in pset.inputs_mut().iter_mut().zip(inputs) {
+        let (previous_tx, txo) = match wallet_input {
+            Some((BETransaction::Elements(previous_tx), txo)) => (previous_tx, txo),
+            _ => continue,
+        };
+        let vout = input.previous_output_index as usize;
+        input.witness_utxo = Some(previous_tx.output[vout].clone());
+        input.redeem_script = txo.redeem_script().map(BEScriptConvert::into_elements);
+        input.bip32_derivation.insert(txo.public_key.into(), txo.origin);
+    }
+    for (output, txo) in pset.outputs_mut().iter_mut().zip(outputs) {
+        if let Some(txo) = txo {
+            output.redeem_script = txo.redeem_script().map(BEScriptConvert::into_elements);
+            output.bip32_derivation.insert(txo.public_key.into(), txo.origin);
+        }
+    }
+}
+
+/// The transaction of `pset` without signatures, which is the same for
+/// all the parties signing it
+fn elements_unsigned_tx(pset: &PartiallySignedTransaction) -> Result<elements::Transaction, Error> {
+    let mut tx = pset.extract_tx().map_err(|e| Error::Generic(e.to_string()))?;
+    for input in tx.input.iter_mut() {
+        input.script_sig = elements::Script::new();
+        input.witness.script_witness.clear();
+    }
+    Ok(tx)
+}
+
+/// The transaction of the base64 `psbt`, or PSET on Liquid, without signatures
+pub(crate) fn unsigned_tx(psbt: &str, network: NetworkId) -> Result<BETransaction, Error> {
+    Ok(match network {
+        NetworkId::Bitcoin(_) => BETransaction::Bitcoin(parse_bitcoin_psbt(psbt)?.unsigned_tx),
+        NetworkId::Elements(_) => {
+            BETransaction::Elements(elements_unsigned_tx(&parse_elements_pset(psbt)?)?)
+        }
+    })
+}
+
+/// Sign with `signer` the inputs of `tx` having a key path in `paths`,
+/// checking a signature is returned for each input
+///
+/// The inputs of multisig subaccounts are signed with the `witness_scripts`
+/// of their previous outputs.
+///
+/// Unless `expert`, fails if the sighash type of an input signed lets other
+/// parties change the outputs it pays, see [`check_sighash`].
+fn sign_tx(
+    signer: &dyn Signer,
+    tx: &BETransaction,
+    prevouts: &[Option<BETxOut>],
+    paths: &[Option<DerivationPath>],
+    sighash_types: &[Option<u32>],
+    witness_scripts: Option<&[Option<ScriptBuf>]>,
+    expert: bool,
+) -> Result<Vec<Option<Vec<u8>>>, Error> {
+    for (i, (path, sighash_type)) in paths.iter().zip(sighash_types).enumerate() {
+        if let (Some(_), Some(sighash_type)) = (path, sighash_type) {
+            check_sighash(i, tx.output_len(), *sighash_type, expert)?;
+        }
+    }
+    let signatures = match witness_scripts {
+        Some(witness_scripts) => {
+            signer.sign_multisig_tx(tx, prevouts, paths, sighash_types, witness_scripts)?
+        }
+        None if signer.anti_exfil() => {
+            anti_exfil::sign_tx(signer, tx, prevouts, paths, sighash_types)?
+        }
+        None => signer.sign_tx(tx, prevouts, paths, sighash_types)?,
+    };
+    if signatures.len() != paths.len() {
+        return Err(Error::Generic(format!(
+            "signer returned {} signatures for {} inputs",
+            signatures.len(),
+            paths.len()
+        )));
+    }
+    Ok(signatures)
+}
+
+/// Fails if signing input `index` of a transaction with `outputs` outputs
+/// with `sighash_type` lets other parties change the outputs it pays,
+/// unless `expert`
+///
+/// Such are SIGHASH_NONE, committing to no output, and SIGHASH_SINGLE
+/// without an output at the index of the input, committing to none too.
+fn check_sighash(
+    index: usize,
+    outputs: usize,
+    sighash_type: u32,
+    expert: bool,
+) -> Result<(), Error> {
+    const SIGHASH_NONE: u32 = 2;
+    const SIGHASH_SINGLE: u32 = 3;
+    let dangerous = match sighash_type & 0x1f {
+        SIGHASH_NONE => true,
+        SIGHASH_SINGLE => index >= outputs,
+        _ => false,
+    };
+    if dangerous && !expert {
+        return Err(Error::DangerousSighash {
+            input: index,
+            sighash_type,
+        });
+    }
+    Ok(())
+}
+
+/// Sign the inputs of the base64 `psbt` belonging to `account` with
+/// `signer`, holding the keys of the account.
+///
+/// Only the `inputs` given are signed, if any, with the sighash types set in
+/// the PSBT, which must not be dangerous ones unless `expert`.
+pub(crate) fn sign_psbt(
+    psbt: &str,
+    network: NetworkId,
+    account: &Account,
+    signer: &dyn Signer,
+    inputs: Option<&[usize]>,
+    expert: bool,
+) -> Result<SignPsbtResult, Error> {
+    match network {
+        NetworkId::Bitcoin(_) => {
+            let mut psbt = parse_bitcoin_psbt(psbt)?;
+            let signed = sign_bitcoin(&mut psbt, account, signer, inputs, expert)?;
+            Ok(bitcoin_sign_result(&psbt, signed))
+        }
+        NetworkId::Elements(_) => {
+            let mut pset = parse_elements_pset(psbt)?;
+            let signed = sign_elements(&mut pset, account, signer, inputs, expert)?;
+            Ok(elements_sign_result(&pset, signed))
+        }
+    }
+}
+
+fn bitcoin_sign_result(psbt: &Psbt, signed_inputs: Vec<usize>) -> SignPsbtResult {
+    let complete_inputs: Vec<_> = (0..psbt.inputs.len())
+        .map(|i| {
+            let input = &psbt.inputs[i];
+            input.final_script_sig.is_some()
+                || input.final_script_witness.is_some()
+                || finalize_bitcoin_input(psbt, i).is_ok()
+        })
+        .collect();
+    SignPsbtResult {
+        psbt: base64::encode(psbt.serialize()),
+        signed_inputs,
+        is_complete: complete_inputs.iter().all(|c| *c),
+        complete_inputs,
+    }
+}
+
+fn elements_sign_result(
+    pset: &PartiallySignedTransaction,
+    signed_inputs: Vec<usize>,
+) -> SignPsbtResult {
+    let complete_inputs: Vec<_> = pset
+        .inputs()
+        .iter()
+        .enumerate()
+        .map(|(i, input)| {
+            input.final_script_sig.is_some()
+                || input.final_script_witness.is_some()
+                || finalize_elements_input(input, i).is_ok()
+        })
+        .collect();
+    SignPsbtResult {
+        psbt: base64::encode(elements::encode::serialize(pset)),
+        signed_inputs,
+        is_complete: complete_inputs.iter().all(|c| *c),
+        complete_inputs,
+    }
+}
+
+/// Sign the wallet inputs of the base64 `psbt` created by another party
+/// belonging to `account`, only if its transaction id is still `txhash`,
+/// the one of the details acknowledged by the user.
+///
+/// The wallet data of the inputs and outputs is set from `wallet` before
+/// signing, so the amounts signed are not the ones claimed by the PSBT.
+/// Key origins of the wallet claimed by foreign inputs are dropped.
+pub(crate) fn psbt_sign(
+    psbt: &str,
+    network: NetworkId,
+    wallet: &Wallet,
+    txhash: &str,
+    account: &Account,
+    signer: &dyn Signer,
+) -> Result<SignPsbtResult, Error> {
+    let fingerprint = account.master_xpub_fingerprint();
+    match network {
+        NetworkId::Bitcoin(_) => {
+            let mut psbt = parse_bitcoin_psbt(psbt)?;
+            let tx = BETransaction::Bitcoin(psbt.unsigned_tx.clone());
+            check_txhash(&tx, txhash)?;
+            let wallet_data = wallet.wallet_data(&tx);
+            for (input, wallet_input) in psbt.inputs.iter_mut().zip(&wallet_data.0) {
+                if wallet_input.is_none() {
+                    input.bip32_derivation.retain(|_, (f, _)| *f != fingerprint);
+                    input.tap_key_origins.retain(|_, (_, (f, _))| *f != fingerprint);
+                }
+            }
+            add_bitcoin_wallet_data(&mut psbt, wallet_data);
+            let signed = sign_bitcoin(&mut psbt, account, signer, None, false)?;
+            Ok(bitcoin_sign_result(&psbt, signed))
+        }
+        NetworkId::Elements(_) => {
+            let mut pset = parse_elements_pset(psbt)?;
+            let tx = BETransaction::Elements(elements_unsigned_tx(&pset)?);
+            check_txhash(&tx, txhash)?;
+            let wallet_data = wallet.wallet_data(&tx);
+            for (input, wallet_input) in pset.inputs_mut().iter_mut().zip(&wallet_data.0) {
+                if wallet_input.is_none() {
+                    input.bip32_derivation.retain(|_, (f, _)| *f != fingerprint);
+                }
+            }
+            add_elements_wallet_data(&mut pset, wallet_data);
+            let signed = sign_elements(&mut pset, account, signer, None, false)?;
+            Ok(elements_sign_result(&pset, signed))
+        }
+    }
+}
+
+fn check_txhash(tx: &BETransaction, txhash: &str) -> Result<(), Error> {
+    if tx.txid().to_string() != txhash {
+        return Err(Error::Generic(format!(
+            "psbt transaction {} differs from the acknowledged one {}",
+            tx.txid(),
+            txhash
+        )));
+    }
+    Ok(())
+}
+
+/// Sign the `inputs` of `psbt` belonging to `account`, all of them if not
+/// given, returning the indexes of the inputs signed
+pub(crate) fn sign_bitcoin(
+    psbt: &mut Psbt,
+    account: &Account,
+    signer: &dyn Signer,
+    inputs: Option<&[usize]>,
+    expert: bool,
+) -> Result<Vec<usize>, Error> {
+    let mut prevouts = vec![];
+    let mut keys = vec![];
+    let mut sighash_types = vec![];
+    for (i, input) in psbt.inputs.iter().enumerate() {
+        let vout = psbt.unsigned_tx.input[i].previous_output.vout as usize;
+        let prevout = input
+            .witness_utxo
+            .clone()
+            .or_else(|| input.non_witness_utxo.as_ref()?.output.get(vout).cloned());
+        // The public key and the path of the account key of the input, if any
+        let key = input
+            .bip32_derivation
+            .iter()
+            .map(|(public_key, origin)| (Some(*public_key), origin))
+            .chain(input.tap_key_origins.values().map(|(_, origin)| (None, origin)))
+            .find(|(_, origin)| account.has_key(origin))
+            .map(|(public_key, (_, path))| (public_key, path.clone()))
+            .filter(|_| is_selected(inputs, i));
+        // Foreign inputs may lack the previous output, fail only on ours
+        if key.is_some() && prevout.is_none() {
+            return Err(Error::Generic(format!(
+                "cannot sign psbt input {}: missing previous output",
+                i
+            )));
+        }
+        prevouts.push(prevout.map(BETxOut::Bitcoin));
+        keys.push(key);
+        sighash_types.push(input.sighash_type.map(|t| t.to_u32()));
+    }
+    check_selected_inputs(inputs, &keys)?;
+    let paths: Vec<_> = keys.iter().map(|key| key.as_ref().map(|(_, path)| path.clone())).collect();
+    let tx = BETransaction::Bitcoin(psbt.unsigned_tx.clone());
+    let witness_scripts: Option<Vec<_>> = account.multisig().map(|_| {
+        let inputs = psbt.inputs.iter().zip(&keys);
+        inputs.map(|(input, key)| key.as_ref().and(input.witness_script.clone())).collect()
+    });
+    let signatures = sign_tx(
+        signer,
+        &tx,
+        &prevouts,
+        &paths,
+        &sighash_types,
+        witness_scripts.as_deref(),
+        expert,
+    )?;
+
+    let mut signed = vec![];
+    for (i, (key, signature)) in keys.into_iter().zip(signatures).enumerate() {
+        let (public_key, signature) = match (key, signature) {
+            (Some((public_key, _)), Some(signature)) => (public_key, signature),
+            _ => continue,
+        };
+        let invalid = |e: &dyn std::fmt::Display| {
+            Error::Generic(format!("invalid signature of psbt input {}: {}", i, e))
+        };
+        let is_p2tr =
+            matches!(&prevouts[i], Some(BETxOut::Bitcoin(o)) if o.script_pubkey.is_p2tr());
+        let input = &mut psbt.inputs[i];
+        match public_key {
+            Some(public_key) if !is_p2tr => {
+                let signature =
+                    bitcoin::ecdsa::Signature::from_slice(&signature).map_err(|e| invalid(&e))?;
+                input.partial_sigs.insert(bitcoin::PublicKey::new(public_key), signature);
+            }
+            _ => {
+                let signature =
+                    bitcoin::taproot::Signature::from_slice(&signature).map_err(|e| invalid(&e))?;
+                input.tap_key_sig = Some(signature);
+            }
+        }
+        signed.push(i);
+    }
+    Ok(signed)
+}
+
+/// As [`sign_bitcoin`], for a PSET
+fn sign_elements(
+    pset: &mut PartiallySignedTransaction,
+    account: &Account,
+    signer: &dyn Signer,
+    inputs: Option<&[usize]>,
+    expert: bool,
+) -> Result<Vec<usize>, Error> {
+    let mut prevouts = vec![];
+    let mut keys = vec![];
+    let mut sighash_types = vec![];
+    for (i, input) in pset.inputs().iter().enumerate() {
+        let key = input
+            .bip32_derivation
+            .iter()
+            .find(|(_, origin)| account.has_key(origin))
+            .map(|(public_key, (_, path))| (*public_key, path.clone()))
+            .filter(|_| is_selected(inputs, i));
+        if key.is_some() && input.witness_utxo.is_none() {
+            return Err(Error::Generic(format!("psbt input {} has no previous output", i)));
+        }
+        prevouts.push(input.witness_utxo.clone().map(BETxOut::Elements));
+        keys.push(key);
+        sighash_types.push(input.sighash_type.map(|t| t.to_u32()));
+    }
+    check_selected_inputs(inputs, &keys)?;
+    let paths: Vec<_> = keys.iter().map(|key| key.as_ref().map(|(_, path)| path.clone())).collect();
+    let tx = BETransaction::Elements(pset.extract_tx().map_err(|e| Error::Generic(e.to_string()))?);
+    let signatures = sign_tx(signer, &tx, &prevouts, &paths, &sighash_types, None, expert)?;
+
+    let mut signed = vec![];
+    for (i, (key, signature)) in keys.into_iter().zip(signatures).enumerate() {
+        if let (Some((public_key, _)), Some(signature)) = (key, signature) {
+            pset.inputs_mut()[i].partial_sigs.insert(public_key, signature);
+            signed.push(i);
+        }
+    }
+    Ok(signed)
+}
+
+/// Whether input `i` is among the `inputs` to sign, all if not given
+fn is_selected(inputs: Option<&[usize]>, i: usize) -> bool {
+    match inputs {
+        Some(inputs) => inputs.contains(&i),
+        None => true,
+    }
+}
+
+/// Fails if an input in `inputs`, the ones to sign, has no account key in `keys`
+fn check_selected_inputs<T>(inputs: Option<&[usize]>, keys: &[Option<T>]) -> Result<(), Error> {
+    for &i in inputs.unwrap_or_default() {
+        if !matches!(keys.get(i), Some(Some(_))) {
+            return Err(Error::Generic(format!("psbt input {} is not of the subaccount", i)));
+        }
+    }
+    Ok(())
+}
+
+/// The details of the base64 `psbt` created by another party, as far as
+/// they can be known by `wallet`: the amounts of the wallet inputs and
+/// outputs come from the wallet store, the ones of foreign inputs are the
+/// ones claimed by the PSBT.
+pub(crate) fn psbt_details(
+    psbt: &str,
+    network: NetworkId,
+    wallet: &Wallet,
+    max_fee_rate: u64,
+) -> Result<PsbtDetails, Error> {
+    let fingerprint = wallet.master_xpub_fingerprint();
+    let claims_unknown_path = |origins: Vec<&KeySource>, txo: &Option<WalletTxo>| {
+        origins
+            .into_iter()
+            .filter(|(f, _)| Some(*f) == fingerprint)
+            .any(|origin| !matches!(txo, Some(txo) if txo.origin == *origin))
+    };
+    let mut warnings = vec![];
+    let (tx, inputs, outputs, fee, signature_weight) = match network {
+        NetworkId::Bitcoin(_) => {
+            let mut psbt = parse_bitcoin_psbt(psbt)?;
+            let tx = BETransaction::Bitcoin(psbt.unsigned_tx.clone());
+            let wallet_data = wallet.wallet_data(&tx);
+            for (i, (output, txo)) in psbt.outputs.iter().zip(&wallet_data.1).enumerate() {
+                let origins = output
+                    .bip32_derivation
+                    .values()
+                    .chain(output.tap_key_origins.values().map(|(_, origin)| origin));
+                if claims_unknown_path(origins.collect(), txo) {
+                    warnings.push(PsbtWarning::UnknownChangePath {
+                        output: i,
+                    });
+                }
+            }
+            let (input_subaccounts, output_subaccounts) = subaccounts(&wallet_data);
+            add_bitcoin_wallet_data(&mut psbt, wallet_data);
+
+            let mut signature_weight = 0;
+            let mut inputs = vec![];
+            for (i, subaccount) in input_subaccounts.into_iter().enumerate() {
+                let utxo = psbt.spend_utxo(i).ok();
+                let script_pubkey = utxo.map(|utxo| utxo.script_pubkey.as_script());
+                signature_weight += input_signature_weight(guess_script_type(script_pubkey));
+                inputs.push(PsbtTxoDetails {
+                    subaccount,
+                    satoshi: utxo.map(|utxo| utxo.value.to_sat()),
+                    asset_id: None,
+                });
+            }
+            let outputs: Vec<_> = psbt
+                .unsigned_tx
+                .output
+                .iter()
+                .zip(output_subaccounts)
+                .map(|(output, subaccount)| PsbtTxoDetails {
+                    subaccount,
+                    satoshi: Some(output.value.to_sat()),
+                    asset_id: None,
+                })
+                .collect();
+            let total_in = inputs.iter().map(|i| i.satoshi).sum::<Option<u64>>();
+            let total_out = outputs.iter().filter_map(|o| o.satoshi).sum::<u64>();
+            let fee = total_in.and_then(|total_in| total_in.checked_sub(total_out));
+            (tx, inputs, outputs, fee, signature_weight)
+        }
+        NetworkId::Elements(_) => {
+            let pset = parse_elements_pset(psbt)?;
+            let unsigned_tx = elements_unsigned_tx(&pset)?;
+            let tx = BETransaction::Elements(unsigned_tx.clone());
+            let wallet_data = wallet.wallet_data(&tx);
+            for (i, (output, txo)) in pset.outputs().iter().zip(&wallet_data.1).enumerate() {
+                if claims_unknown_path(output.bip32_derivation.values().collect(), txo) {
+                    warnings.push(PsbtWarning::UnknownChangePath {
+                        output: i,
+                    });
+                }
+            }
+            let (_, output_subaccounts) = subaccounts(&wallet_data);
+
+            let mut signature_weight = 0;
+            let mut inputs = vec![];
+            let pset_inputs = unsigned_tx.input.iter().zip(pset.inputs());
+            for ((input, pset_input), wallet_input) in pset_inputs.zip(&wallet_data.0) {
+                let (subaccount, secrets, script_type) = match wallet_input {
+                    Some((_, txo)) => {
+                        let secrets = wallet.unblinded(&input.previous_output);
+                        let secrets = secrets.map(|s| (s.value, s.asset));
+                        (Some(txo.subaccount), secrets, txo.script_type)
+                    }
+                    None => {
+                        let utxo = pset_input.witness_utxo.as_ref();
+                        let secrets =
+                            utxo.and_then(|u| Some((u.value.explicit()?, u.asset.explicit()?)));
+                        let script_pubkey =
+                            utxo.map(|u| bitcoin::Script::from_bytes(u.script_pubkey.as_bytes()));
+                        (None, secrets, guess_script_type(script_pubkey))
+                    }
+                };
+                signature_weight += input_signature_weight(script_type);
+                inputs.push(PsbtTxoDetails {
+                    subaccount,
+                    satoshi: secrets.map(|(value, _)| value),
+                    asset_id: secrets.map(|(_, asset)| asset.to_string()),
+                });
+            }
+            let mut outputs = vec![];
+            for (output, subaccount) in unsigned_tx.output.iter().zip(output_subaccounts) {
+                let secrets = match (output.value.explicit(), output.asset.explicit()) {
+                    (Some(value), Some(asset)) => Some((value, asset)),
+                    _ => match (subaccount, &wallet.store.cache.master_blinding) {
+                        (Some(_), Some(master_blinding)) => {
+                            crate::unblind_output(output.clone(), master_blinding, None)
+                                .ok()
+                                .map(|s| (s.value, s.asset))
+                        }
+                        _ => None,
+                    },
+                };
+                outputs.push(PsbtTxoDetails {
+                    subaccount,
+                    satoshi: secrets.map(|(value, _)| value),
+                    asset_id: secrets.map(|(_, asset)| asset.to_string()),
+                });
+            }
+            let fee = unsigned_tx
+                .output
+                .iter()
+                .filter(|output| output.is_fee())
+                .filter_map(|output| output.value.explicit())
+                .sum();
+            (tx, inputs, outputs, Some(fee), signature_weight)
+        }
+    };
+
+    let vsize = weight_to_vsize(tx.get_weight() + signature_weight);
+    let fee_rate = fee.map(|fee| fee * 1000 / vsize as u64);
+    if let Some(fee_rate) = fee_rate.filter(|fee_rate| *fee_rate > max_fee_rate) {
+        warnings.push(PsbtWarning::HighFeeRate {
+            fee_rate,
+        });
+    }
+
+    let mut satoshi = Balances::new();
+    let mut subaccounts: HashMap<u32, Balances> = HashMap::new();
+    let txos = inputs.iter().map(|i| (i, -1)).chain(outputs.iter().map(|o| (o, 1)));
+    for (txo, sign) in txos {
+        let (subaccount, value) = match (txo.subaccount, txo.satoshi) {
+            (Some(subaccount), Some(value)) => (subaccount, value as i64 * sign),
+            _ => continue,
+        };
+        let asset = txo.asset_id.clone().unwrap_or_else(|| "btc".to_string());
+        *satoshi.entry(asset.clone()).or_default() += value;
+        *subaccounts.entry(subaccount).or_default().entry(asset).or_default() += value;
+    }
+
+    Ok(PsbtDetails {
+        txhash: tx.txid().to_string(),
+        inputs,
+        outputs,
+        satoshi,
+        subaccounts,
+        fee,
+        fee_rate,
+        warnings,
+    })
+}
+
+/// The subaccounts of the wallet inputs and outputs in `wallet_data`
+fn subaccounts((inputs, outputs): &WalletData) -> (Vec<Option<u32>>, Vec<Option<u32>>) {
+    (
+        inputs.iter().map(|input| input.as_ref().map(|(_, txo)| txo.subaccount)).collect(),
+        outputs.iter().map(|output| output.as_ref().map(|txo| txo.subaccount)).collect(),
+    )
+}
+
+/// The script type of an output with `script_pubkey`, to estimate the size
+/// of its signatures, p2wpkh if unknown
+fn guess_script_type(script_pubkey: Option<&bitcoin::Script>) -> ScriptType {
+    match script_pubkey {
+        Some(script) if script.is_p2pkh() => ScriptType::P2pkh,
+        Some(script) if script.is_p2sh() => ScriptType::P2shP2wpkh,
+        Some(script) if script.is_p2tr() => ScriptType::P2tr,
+        Some(script) if script.is_p2wsh() => ScriptType::P2wsh,
+        _ => ScriptType::P2wpkh,
+    }
+}
+
+/// Finalize the inputs of the base64 `psbt` that are not final yet and
+/// extract the transaction.
+///
+/// Inputs are finalized as single key p2pkh, p2wpkh, p2sh-p2wpkh or p2tr
+/// key spends, or as p2wsh multisig spends once signed by enough
+/// cosigners, other inputs must be finalized by their signers.
+pub(crate) fn finalize_psbt(psbt: &str, network: NetworkId) -> Result<BETransaction, Error> {
+    match network {
+        NetworkId::Bitcoin(_) => {
+            let mut psbt = parse_bitcoin_psbt(psbt)?;
+            finalize_bitcoin_inputs(&mut psbt, 0..psbt.inputs.len())?;
+            Ok(BETransaction::Bitcoin(psbt.extract_tx_unchecked_fee_rate()))
+        }
+        NetworkId::Elements(_) => {
+            let mut pset = parse_elements_pset(psbt)?;
+            for (i, input) in pset.inputs_mut().iter_mut().enumerate() {
+                if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+                    continue;
+                }
+                let (script_sig, witness) = finalize_elements_input(input, i)?;
+                input.final_script_sig = script_sig;
+                input.final_script_witness = witness;
+                input.partial_sigs.clear();
+                input.bip32_derivation.clear();
+                input.redeem_script = None;
+            }
+            let tx = pset.extract_tx().map_err(|e| Error::Generic(e.to_string()))?;
+            Ok(BETransaction::Elements(tx))
+        }
+    }
+}
+
+/// Finalize the `inputs` of `psbt` not final yet, keeping only their
+/// previous outputs and final script sig and witness
+pub(crate) fn finalize_bitcoin_inputs(
+    psbt: &mut Psbt,
+    inputs: std::ops::Range<usize>,
+) -> Result<(), Error> {
+    for i in inputs {
+        let input = &psbt.inputs[i];
+        if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+            continue;
+        }
+        let (script_sig, witness) = finalize_bitcoin_input(psbt, i)?;
+        let input = &mut psbt.inputs[i];
+        *input = psbt::Input {
+            non_witness_utxo: input.non_witness_utxo.take(),
+            witness_utxo: input.witness_utxo.take(),
+            final_script_sig: Some(script_sig).filter(|s| !s.is_empty()),
+            final_script_witness: Some(witness).filter(|w| !w.is_empty()),
+            ..Default::default()
+        };
+    }
+    Ok(())
+}
+
+fn finalize_error(i: usize, reason: &str) -> Error {
+    Error::Generic(format!("cannot finalize psbt input {}: {}", i, reason))
+}
+
+/// The final script sig and witness of input `i` of `psbt`
+fn finalize_bitcoin_input(psbt: &Psbt, i: usize) -> Result<(ScriptBuf, Witness), Error> {
+    let input = &psbt.inputs[i];
+    let script_pubkey = &psbt
+        .spend_utxo(i)
+        .map_err(|_| finalize_error(i, "missing previous output"))?
+        .script_pubkey;
+    if script_pubkey.is_p2tr() {
+        let signature = input.tap_key_sig.ok_or_else(|| finalize_error(i, "missing signature"))?;
+        return Ok((ScriptBuf::new(), Witness::p2tr_key_spend(&signature)));
+    }
+    if script_pubkey.is_p2wsh() {
+        let witness_script = match &input.witness_script {
+            Some(script) if *script_pubkey == ScriptBuf::new_p2wsh(&script.wscript_hash()) => {
+                script
+            }
+            _ => return Err(finalize_error(i, "missing witness script")),
+        };
+        let witness = multisig::finalize_witness(witness_script, &input.partial_sigs)
+            .map_err(|reason| finalize_error(i, reason))?;
+        return Ok((ScriptBuf::new(), witness));
+    }
+    let (public_key, signature) =
+        input.partial_sigs.iter().next().ok_or_else(|| finalize_error(i, "missing signature"))?;
+    if script_pubkey.is_p2pkh() {
+        let script_sig = bitcoin::script::Builder::new()
+            .push_slice(signature.serialize())
+            .push_key(public_key)
+            .into_script();
+        return Ok((script_sig, Witness::new()));
+    }
+    let public_key = CompressedPublicKey::try_from(*public_key)
+        .map_err(|_| finalize_error(i, "uncompressed public key"))?;
+    let wpkh_script = ScriptBuf::new_p2wpkh(&public_key.wpubkey_hash());
+    let script_sig = if *script_pubkey == wpkh_script {
+        ScriptBuf::new()
+    } else if *script_pubkey == wpkh_script.to_p2sh() {
+        p2shwpkh_script_sig(&public_key)
+    } else {
+        return Err(finalize_error(i, "unsupported script"));
+    };
+    Ok((script_sig, Witness::p2wpkh(signature, &public_key.0)))
+}
+
+/// The final script sig and witness of a PSET input, if not empty
+type ElementsFinalInput = (Option<elements::Script>, Option<Vec<Vec<u8>>>);
+
+/// The final script sig and witness of `input`, the input `i` of a PSET
+fn finalize_elements_input(
+    input: &elements::pset::Input,
+    i: usize,
+) -> Result<ElementsFinalInput, Error> {
+    let script_pubkey = match &input.witness_utxo {
+        Some(utxo) => &utxo.script_pubkey,
+        None => return Err(finalize_error(i, "missing previous output")),
+    };
+    let (public_key, signature) =
+        input.partial_sigs.iter().next().ok_or_else(|| finalize_error(i, "missing signature"))?;
+    if script_pubkey.is_p2pkh() {
+        let script_sig = elements::script::Builder::new()
+            .push_slice(signature)
+            .push_key(public_key)
+            .into_script();
+        return Ok((Some(script_sig), None));
+    }
+    let public_key = CompressedPublicKey::try_from(*public_key)
+        .map_err(|_| finalize_error(i, "uncompressed public key"))?;
+    let wpkh_script = ScriptBuf::new_p2wpkh(&public_key.wpubkey_hash()).into_elements();
+    let script_sig = if *script_pubkey == wpkh_script.to_p2sh() {
+        Some(p2shwpkh_script_sig(&public_key).into_elements())
+    } else if *script_pubkey == wpkh_script {
+        None
+    } else {
+        return Err(finalize_error(i, "unsupported script"));
+    };
+    Ok((script_sig, Some(vec![signature.clone(), public_key.to_bytes().to_vec()])))
+}
+
+fn parse_bitcoin_psbt(psbt: &str) -> Result<Psbt, Error> {
+    Psbt::deserialize(&base64::decode(psbt)?)
+        .map_err(|e| Error::Generic(format!("invalid psbt: {}", e)))
+}
+
+fn parse_elements_pset(pset: &str) -> Result<PartiallySignedTransaction, Error> {
+    let pset: PartiallySignedTransaction = elements::encode::deserialize(&base64::decode(pset)?)?;
+    Ok(pset)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gdk_common::bitcoin::bip32::Fingerprint;
+    use gdk_common::bitcoin::hashes::Hash;
+    use gdk_common::bitcoin::secp256k1::Message;
+    use gdk_common::bitcoin::sighash::SighashCache as BitcoinSighashCache;
+    use gdk_common::bitcoin::{
+        ecdsa, Amount, EcdsaSighashType, PrivateKey, Transaction, TxIn, TxOut,
+    };
+    use gdk_common::EC;
+
+    fn transaction(input: Vec<TxIn>, output: Vec<TxOut>) -> Transaction {
+        Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input,
+            output,
+        }
+    }
+
+    #[test]
+    fn test_psbt_nested_segwit() {
+        let private_key =
+            PrivateKey::from_wif("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").unwrap();
+        let public_key = CompressedPublicKey::from_private_key(&EC, &private_key).unwrap();
+        let txo = WalletTxo {
+            subaccount: 0,
+            public_key,
+            origin: (Fingerprint::default(), "m/49'/1'/0'/0/0".parse().unwrap()),
+            script_type: ScriptType::P2shP2wpkh,
+            witness_script: None,
+            cosigner_keys: vec![],
+        };
+        let script_pubkey = txo.wpkh_script().to_p2sh();
+        let value = Amount::from_sat(100_000);
+        let previous_tx = transaction(
+            vec![TxIn::default()],
+            vec![TxOut {
+                value,
+                script_pubkey,
+            }],
+        );
+        let tx = transaction(
+            vec![
+                TxIn {
+                    previous_output: bitcoin::OutPoint::new(previous_tx.compute_txid(), 0),
+                    ..Default::default()
+                },
+                TxIn::default(),
+            ],
+            vec![TxOut {
+                value: Amount::from_sat(90_000),
+                script_pubkey: txo.wpkh_script(),
+            }],
+        );
+        let inputs = vec![Some((BETransaction::Bitcoin(previous_tx), txo.clone())), None];
+        let outputs = vec![Some(txo.clone())];
+        let psbt = create_psbt(BETransaction::Bitcoin(tx.clone()), (inputs, outputs), &[]).unwrap();
+
+        let mut psbt = parse_bitcoin_psbt(&psbt).unwrap();
+        let input = &psbt.inputs[0];
+        assert_eq!(input.witness_utxo.as_ref().unwrap().value, value);
+        assert!(input.non_witness_utxo.is_some());
+        assert_eq!(input.redeem_script, Some(txo.wpkh_script()));
+        assert_eq!(input.bip32_derivation.get(&public_key.0), Some(&txo.origin));
+        assert_eq!(psbt.inputs[1], psbt::Input::default());
+        assert_eq!(psbt.outputs[0].bip32_derivation.get(&public_key.0), Some(&txo.origin));
+
+        // The foreign input is not signed yet
+        let network = NetworkId::Bitcoin(bitcoin::Network::Regtest);
+        assert!(finalize_psbt(&base64::encode(psbt.serialize()), network).is_err());
+
+        let sighash = BitcoinSighashCache::new(&tx)
+            .p2wpkh_signature_hash(0, &txo.wpkh_script(), value, EcdsaSighashType::All)
+            .unwrap();
+        let message = Message::from_digest(sighash.to_byte_array());
+        let signature = ecdsa::Signature::sighash_all(EC.sign_ecdsa(&message, &private_key.inner));
+        psbt.inputs[0].partial_sigs.insert(public_key.into(), signature);
+        psbt.inputs[1].final_script_witness = Some(Witness::from_slice(&[vec![1u8]]));
+
+        let tx = match finalize_psbt(&base64::encode(psbt.serialize()), network).unwrap() {
+            BETransaction::Bitcoin(tx) => tx,
+            BETransaction::Elements(_) => panic!("not a bitcoin transaction"),
+        };
+        assert_eq!(tx.input[0].script_sig, p2shwpkh_script_sig(&public_key));
+        assert_eq!(tx.input[0].witness, Witness::p2wpkh(&signature, &public_key.0));
+        assert_eq!(tx.input[1].witness, Witness::from_slice(&[vec![1u8]]));
+    }
+
+    #[test]
+    fn test_sign_psbt_inputs_sighash() {
+        use crate::signer::SoftwareSigner;
+        use gdk_common::bitcoin::bip32::{Xpriv, Xpub};
+        use gdk_common::bitcoin::NetworkKind;
+        use gdk_common::store::ToCipher;
+        use gdk_common::NetworkParameters;
+        use std::sync::{Arc, RwLock};
+
+        let mut network = NetworkParameters::default();
+        network.development = true;
+        let network_id = network.id();
+        let (_, path) = crate::account::get_account_derivation(0, network_id).unwrap();
+        let master = Xpriv::new_master(NetworkKind::Test, &[1u8; 32]).unwrap();
+        let xprv = master.derive_priv(&EC, &path).unwrap();
+        let xpub = Xpub::from_priv(&EC, &xprv);
+        let dir = tempfile::TempDir::new().unwrap();
+        let store = StoreMeta::new(dir.path(), &xpub.to_cipher().unwrap(), 1000).unwrap();
+        let store = Arc::new(RwLock::new(store));
+        let account =
+            Account::new(network, Fingerprint::default(), xpub, None, store, 0, false).unwrap();
+        let signer = SoftwareSigner::from_account_xprv(xprv, path.clone());
+
+        let txo = |index: u32| {
+            let path = path.child(0.into()).child(index.into());
+            WalletTxo {
+                subaccount: 0,
+                public_key: account.public_key(&path[3..].into()),
+                origin: (Fingerprint::default(), path),
+                script_type: ScriptType::P2shP2wpkh,
+                witness_script: None,
+                cosigner_keys: vec![],
+            }
+        };
+        let (txo0, txo1) = (txo(0), txo(1));
+        let value = Amount::from_sat(100_000);
+        let previous_tx = transaction(
+            vec![TxIn::default()],
+            vec![txo0.clone(), txo1.clone()]
+                .iter()
+                .map(|txo| TxOut {
+                    value,
+                    script_pubkey: txo.wpkh_script().to_p2sh(),
+                })
+                .collect(),
+        );
+        let spend = |vout| TxIn {
+            previous_output: bitcoin::OutPoint::new(previous_tx.compute_txid(), vout),
+            ..Default::default()
+        };
+        let output = TxOut {
+            value: Amount::from_sat(90_000),
+            script_pubkey: txo0.wpkh_script(),
+        };
+        let tx = transaction(vec![spend(0), spend(1), TxIn::default()], vec![output.clone()]);
+        let previous_tx = BETransaction::Bitcoin(previous_tx);
+        let inputs = vec![
+            Some((previous_tx.clone(), txo0.clone())),
+            Some((previous_tx, txo1.clone())),
+            None,
+        ];
+        let sighash_types = [Some(0x83), Some(0x02), None];
+        let psbt =
+            create_psbt(BETransaction::Bitcoin(tx.clone()), (inputs, vec![None]), &sighash_types)
+                .unwrap();
+
+        // Only the selected input is signed, with its sighash type
+        let result = sign_psbt(&psbt, network_id, &account, &signer, Some(&[0]), false).unwrap();
+        assert_eq!(result.signed_inputs, vec![0]);
+        assert_eq!(result.complete_inputs, vec![true, false, false]);
+        assert!(!result.is_complete);
+        let signed = parse_bitcoin_psbt(&result.psbt).unwrap();
+        assert!(signed.inputs[1].partial_sigs.is_empty());
+        let signature = *signed.inputs[0].partial_sigs.get(&txo0.public_key.into()).unwrap();
+        assert_eq!(signature.sighash_type, EcdsaSighashType::SinglePlusAnyoneCanPay);
+
+        // The signature still holds once others add their inputs and outputs
+        let mut tx = tx;
+        tx.input.push(TxIn::default());
+        tx.output.push(output);
+        let sighash = BitcoinSighashCache::new(&tx)
+            .p2wpkh_signature_hash(
+                0,
+                &txo0.wpkh_script(),
+                value,
+                EcdsaSighashType::SinglePlusAnyoneCanPay,
+            )
+            .unwrap();
+        let message = Message::from_digest(sighash.to_byte_array());
+        EC.verify_ecdsa(&message, &signature.signature, &txo0.public_key.0).unwrap();
+
+        // SIGHASH_NONE is refused unless expert
+        let err = sign_psbt(&psbt, network_id, &account, &signer, Some(&[1]), false).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::DangerousSighash {
+                input: 1,
+                sighash_type: 0x02
+            }
+        ));
+        let result = sign_psbt(&psbt, network_id, &account, &signer, None, true).unwrap();
+        assert_eq!(result.signed_inputs, vec![0, 1]);
+        assert_eq!(result.complete_inputs, vec![true, true, false]);
+
+        // Foreign inputs cannot be selected
+        assert!(sign_psbt(&psbt, network_id, &account, &signer, Some(&[2]), false).is_err());
+    }
+}
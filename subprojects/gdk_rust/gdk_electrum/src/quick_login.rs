@@ -0,0 +1,256 @@
+//! Quick re-login without PIN, for wallets logged in with a mnemonic before.
+//!
+//! The credentials are kept in a file in the store directory of the wallet,
+//! encrypted with a random key that is itself wrapped by a
+//! [`CredentialProtector`], eg. one backed by the keystore of the device.
+//! [`SoftwareProtector`] is used when the bindings don't set one.
+//!
+//! When the key can't be unwrapped, eg. because the keystore entry was
+//! invalidated, the quick login fails with [`Error::QuickLoginUnavailable`]
+//! and the wallet must be logged in with its mnemonic again.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use gdk_common::aes::aead::NewAead;
+use gdk_common::aes::{Aes256GcmSiv, Key};
+use gdk_common::bitcoin::hashes::hex::FromHex;
+use gdk_common::bitcoin::hex::DisplayHex;
+use gdk_common::model::Credentials;
+use gdk_common::rand::{self, Rng};
+use gdk_common::store::{Decryptable, Encryptable};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// The name of the file of the protected credentials in the store directory
+pub const QUICK_LOGIN_FILE: &str = "quick_login";
+
+/// The name of the file of the key of the default [`SoftwareProtector`] in
+/// the state dir, shared by the wallets
+pub const SOFTWARE_KEY_FILE: &str = "quick_login_key";
+
+/// Protects the key encrypting the credentials kept for quick login
+pub trait CredentialProtector: Send + Sync {
+    /// Wrap `key`, returning the blob kept next to the store
+    fn wrap_key(&self, key: &[u8]) -> Result<Vec<u8>, Error>;
+
+    /// Recover the key wrapped by [`Self::wrap_key`]
+    fn unwrap_key(&self, wrapped: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+/// Wraps the keys with a random key kept in a file, for the platforms
+/// without a keystore
+///
+/// The credentials are only as safe as the file of the key.
+pub struct SoftwareProtector {
+    path: PathBuf,
+}
+
+impl SoftwareProtector {
+    /// A protector with its key in the file at `path`, created on the
+    /// first wrap
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        SoftwareProtector {
+            path: path.into(),
+        }
+    }
+
+    fn cipher(&self, create: bool) -> Result<Aes256GcmSiv, Error> {
+        let key = match std::fs::read(&self.path) {
+            Ok(key) => key,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && create => {
+                let key = rand::thread_rng().gen::<[u8; 32]>().to_vec();
+                write_atomically(&self.path, &key)?;
+                key
+            }
+            Err(e) => return Err(e.into()),
+        };
+        ensure!(key.len() == 32, Error::Generic("invalid protector key".into()));
+        Ok(Aes256GcmSiv::new(Key::from_slice(&key)))
+    }
+}
+
+impl CredentialProtector for SoftwareProtector {
+    fn wrap_key(&self, key: &[u8]) -> Result<Vec<u8>, Error> {
+        let (nonce, wrapped) = key.to_vec().encrypt(&self.cipher(true)?)?;
+        Ok(nonce.iter().copied().chain(wrapped).collect())
+    }
+
+    fn unwrap_key(&self, wrapped: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(wrapped.to_vec().decrypt(&self.cipher(false)?)?)
+    }
+}
+
+/// The content of the [`QUICK_LOGIN_FILE`]
+#[derive(Serialize, Deserialize)]
+struct ProtectedCredentials {
+    /// The key encrypting the credentials, wrapped by the protector
+    wrapped_key: String,
+
+    /// The nonce and the encrypted JSON of the credentials
+    credentials: String,
+}
+
+/// The file of the protected credentials of the wallet stored in `store_dir`
+pub fn quick_login_path(store_dir: &Path) -> PathBuf {
+    store_dir.join(QUICK_LOGIN_FILE)
+}
+
+/// Keep `credentials` at `path`, encrypted with a key wrapped by `protector`
+pub fn save(
+    path: &Path,
+    protector: &dyn CredentialProtector,
+    credentials: &Credentials,
+) -> Result<(), Error> {
+    let key = rand::thread_rng().gen::<[u8; 32]>();
+    let cipher = Aes256GcmSiv::new(Key::from_slice(&key));
+    let (nonce, encrypted) = serde_json::to_vec(credentials)?.encrypt(&cipher)?;
+    let protected = ProtectedCredentials {
+        wrapped_key: protector.wrap_key(&key)?.to_lower_hex_string(),
+        credentials: nonce
+            .iter()
+            .copied()
+            .chain(encrypted)
+            .collect::<Vec<_>>()
+            .to_lower_hex_string(),
+    };
+    write_atomically(path, &serde_json::to_vec(&protected)?)
+}
+
+/// The credentials kept at `path` by [`save`]
+pub fn load(path: &Path, protector: &dyn CredentialProtector) -> Result<Credentials, Error> {
+    let unavailable = |reason: &str| Error::QuickLoginUnavailable(reason.to_string());
+    let content = match std::fs::read(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(unavailable("quick login is not enabled"))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let protected: ProtectedCredentials =
+        serde_json::from_slice(&content).map_err(|_| unavailable("corrupted credentials"))?;
+    let wrapped_key =
+        Vec::<u8>::from_hex(&protected.wrapped_key).map_err(|_| unavailable("corrupted key"))?;
+    let key = protector
+        .unwrap_key(&wrapped_key)
+        .map_err(|e| unavailable(&format!("cannot unwrap the key: {}", e)))?;
+    ensure!(key.len() == 32, unavailable("invalid key"));
+    let cipher = Aes256GcmSiv::new(Key::from_slice(&key));
+    let encrypted = Vec::<u8>::from_hex(&protected.credentials)
+        .map_err(|_| unavailable("corrupted credentials"))?;
+    let decrypted =
+        encrypted.decrypt(&cipher).map_err(|_| unavailable("cannot decrypt the credentials"))?;
+    serde_json::from_slice(&decrypted).map_err(|_| unavailable("corrupted credentials"))
+}
+
+/// Forget the credentials kept at `path`, if any
+pub fn remove(path: &Path) -> Result<(), Error> {
+    // Including the file left by a save interrupted before its rename
+    for path in [path.with_extension("tmp"), path.to_path_buf()] {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Write `content` aside and rename it to `path`, so a process killed while
+/// writing leaves the previous file intact
+fn write_atomically(path: &Path, content: &[u8]) -> Result<(), Error> {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gdk_common::ErrorCode;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Wraps the keys by xoring them, failing to unwrap when `fail` is set,
+    /// like a keystore whose entry was invalidated
+    #[derive(Default)]
+    struct MockProtector {
+        fail: AtomicBool,
+    }
+
+    impl CredentialProtector for MockProtector {
+        fn wrap_key(&self, key: &[u8]) -> Result<Vec<u8>, Error> {
+            Ok(key.iter().map(|b| b ^ 0x5c).collect())
+        }
+
+        fn unwrap_key(&self, wrapped: &[u8]) -> Result<Vec<u8>, Error> {
+            ensure!(!self.fail.load(Ordering::Relaxed), Error::Generic("key invalidated".into()));
+            Ok(wrapped.iter().map(|b| b ^ 0x5c).collect())
+        }
+    }
+
+    fn credentials() -> Credentials {
+        Credentials {
+            mnemonic: "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about".into(),
+            bip39_passphrase: "TREZOR".into(),
+        }
+    }
+
+    #[test]
+    fn test_quick_login() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = quick_login_path(dir.path());
+        let protector = MockProtector::default();
+
+        let err = load(&path, &protector).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::QuickLoginUnavailable);
+
+        save(&path, &protector, &credentials()).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(!content.contains("abandon"));
+        let loaded = load(&path, &protector).unwrap();
+        assert_eq!(loaded.mnemonic, credentials().mnemonic);
+        assert_eq!(loaded.bip39_passphrase, "TREZOR");
+
+        // The keystore entry is gone, the mnemonic is needed again
+        protector.fail.store(true, Ordering::Relaxed);
+        let err = load(&path, &protector).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::QuickLoginUnavailable);
+        assert!(err.to_string().contains("key invalidated"), "{}", err);
+        protector.fail.store(false, Ordering::Relaxed);
+
+        // Tampered credentials
+        let mut protected: ProtectedCredentials = serde_json::from_str(&content).unwrap();
+        protected.credentials.insert_str(30, "00");
+        std::fs::write(&path, serde_json::to_vec(&protected).unwrap()).unwrap();
+        let err = load(&path, &protector).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::QuickLoginUnavailable);
+
+        remove(&path).unwrap();
+        remove(&path).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_software_protector() {
+        let dir = tempfile::tempdir().unwrap();
+        let protector = SoftwareProtector::new(dir.path().join("key"));
+        assert!(protector.unwrap_key(&[0u8; 40]).is_err());
+
+        let wrapped = protector.wrap_key(&[7u8; 32]).unwrap();
+        assert_ne!(&wrapped[12..], &[7u8; 32]);
+        assert_eq!(protector.unwrap_key(&wrapped).unwrap(), vec![7u8; 32]);
+
+        // The same key file unwraps after a restart
+        let protector = SoftwareProtector::new(dir.path().join("key"));
+        assert_eq!(protector.unwrap_key(&wrapped).unwrap(), vec![7u8; 32]);
+
+        // Another key doesn't
+        let other = SoftwareProtector::new(dir.path().join("other"));
+        other.wrap_key(&[0u8; 32]).unwrap();
+        assert!(other.unwrap_key(&wrapped).is_err());
+    }
+}
@@ -0,0 +1,355 @@
+//! Proofs of the ownership of wallet utxos, for proof-of-reserves.
+//!
+//! A proof is a finalized PSBT in the BIP127 format: its first input spends
+//! a challenge output committing to the message, which cannot exist, and the
+//! following ones spend the utxos proven, signed with SIGHASH_ALL so that
+//! their signatures commit to the message too. Its only output is an
+//! OP_RETURN, so the transaction can never be broadcast.
+//!
+//! Verification needs neither the wallet keys nor the chain: the signatures
+//! are checked against the previous outputs carried by the PSBT, whether the
+//! utxos are still unspent is up to the verifier.
+
+use crate::error::Error;
+use gdk_common::bitcoin::absolute::LockTime;
+use gdk_common::bitcoin::hashes::{sha256, Hash};
+use gdk_common::bitcoin::opcodes::all::{OP_PUSHNUM_1, OP_RETURN};
+use gdk_common::bitcoin::psbt::{self, Psbt};
+use gdk_common::bitcoin::script::{Builder, Instruction};
+use gdk_common::bitcoin::secp256k1::{Message, XOnlyPublicKey};
+use gdk_common::bitcoin::sighash::{EcdsaSighashType, Prevouts, SighashCache, TapSighashType};
+use gdk_common::bitcoin::transaction::Version;
+use gdk_common::bitcoin::{
+    ecdsa, taproot, Amount, CompressedPublicKey, OutPoint, PublicKey, Script, ScriptBuf, Sequence,
+    Transaction, TxIn, TxOut, Txid, Witness,
+};
+use gdk_common::scripts::p2shwpkh_script_sig;
+use gdk_common::EC;
+use std::collections::HashSet;
+
+const CHALLENGE_PREFIX: &str = "Proof-of-Reserves: ";
+
+/// The outpoint spent by the challenge input of the proofs for `message`
+pub fn challenge_outpoint(message: &str) -> OutPoint {
+    let hash = sha256::Hash::hash(format!("{}{}", CHALLENGE_PREFIX, message).as_bytes());
+    OutPoint::new(Txid::from_byte_array(hash.to_byte_array()), 0)
+}
+
+/// The output spent by the challenge input, which needs no signature
+fn challenge_txout() -> TxOut {
+    TxOut {
+        value: Amount::ZERO,
+        script_pubkey: Builder::new().push_opcode(OP_PUSHNUM_1).into_script(),
+    }
+}
+
+/// The unsigned proof of the ownership of `utxos`, with their amounts, for
+/// `message`
+///
+/// Only the previous output of the challenge input is set, the ones of the
+/// utxos are left to the wallet.
+pub(crate) fn proof_psbt(message: &str, utxos: &[(OutPoint, u64)]) -> Result<Psbt, Error> {
+    let total: u64 = utxos.iter().map(|(_, satoshi)| satoshi).sum();
+    let input = std::iter::once(challenge_outpoint(message))
+        .chain(utxos.iter().map(|(outpoint, _)| *outpoint))
+        .map(|previous_output| TxIn {
+            previous_output,
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        })
+        .collect();
+    let tx = Transaction {
+        version: Version::ONE,
+        lock_time: LockTime::ZERO,
+        input,
+        output: vec![TxOut {
+            value: Amount::from_sat(total),
+            script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+        }],
+    };
+    let mut psbt = Psbt::from_unsigned_tx(tx)
+        .map_err(|e| Error::Generic(format!("cannot create ownership proof: {}", e)))?;
+    psbt.inputs[0].witness_utxo = Some(challenge_txout());
+    Ok(psbt)
+}
+
+/// Verify the base64 `proof` of ownership for `message`, returning the
+/// utxos proven with their amounts
+pub fn verify_ownership_proof(proof: &str, message: &str) -> Result<Vec<(OutPoint, u64)>, Error> {
+    let invalid = Error::InvalidOwnershipProof;
+    let psbt = Psbt::deserialize(&base64::decode(proof)?)
+        .map_err(|e| invalid(format!("not a psbt: {}", e)))?;
+    let tx = &psbt.unsigned_tx;
+    ensure!(tx.input.len() > 1, invalid("no utxo proven".into()));
+    ensure!(
+        tx.input[0].previous_output == challenge_outpoint(message),
+        invalid("the challenge is not the one of the message".into())
+    );
+    ensure!(
+        tx.output.len() == 1 && tx.output[0].script_pubkey.is_op_return(),
+        invalid("the proof must have a single OP_RETURN output".into())
+    );
+
+    let mut outpoints = HashSet::new();
+    let mut prevouts = vec![challenge_txout()];
+    for (i, (txin, input)) in tx.input.iter().zip(&psbt.inputs).enumerate().skip(1) {
+        ensure!(
+            outpoints.insert(txin.previous_output),
+            invalid(format!("input {} is proven twice", i))
+        );
+        let prevout = previous_output(txin, input)
+            .ok_or_else(|| invalid(format!("missing previous output of input {}", i)))?;
+        prevouts.push(prevout);
+    }
+    let total: u64 = prevouts.iter().map(|o| o.value.to_sat()).sum();
+    ensure!(
+        tx.output[0].value.to_sat() == total,
+        invalid("the output does not spend the utxos proven".into())
+    );
+
+    let mut cache = SighashCache::new(tx);
+    for (i, input) in psbt.inputs.iter().enumerate().skip(1) {
+        let script_sig = input.final_script_sig.clone().unwrap_or_default();
+        let witness = input.final_script_witness.clone().unwrap_or_default();
+        ensure!(
+            verify_input(&mut cache, &prevouts, i, &script_sig, &witness),
+            invalid(format!("invalid signature of input {}", i))
+        );
+    }
+    Ok(tx.input[1..]
+        .iter()
+        .zip(&prevouts[1..])
+        .map(|(txin, prevout)| (txin.previous_output, prevout.value.to_sat()))
+        .collect())
+}
+
+/// The output spent by `txin`, the full previous transaction is required for
+/// legacy inputs since their signatures don't commit to the amount
+fn previous_output(txin: &TxIn, input: &psbt::Input) -> Option<TxOut> {
+    let outpoint = txin.previous_output;
+    let from_tx = match &input.non_witness_utxo {
+        Some(tx) if tx.compute_txid() == outpoint.txid => {
+            Some(tx.output.get(outpoint.vout as usize)?.clone())
+        }
+        Some(_) => return None,
+        None => None,
+    };
+    match (from_tx, &input.witness_utxo) {
+        (Some(from_tx), Some(witness_utxo)) if from_tx != *witness_utxo => None,
+        (Some(from_tx), _) => Some(from_tx),
+        (None, Some(witness_utxo)) if !witness_utxo.script_pubkey.is_p2pkh() => {
+            Some(witness_utxo.clone())
+        }
+        (None, _) => None,
+    }
+}
+
+/// Whether input `i` spending `prevouts[i]` is signed with `script_sig` and
+/// `witness`, with a signature committing to every input and output
+fn verify_input(
+    cache: &mut SighashCache<&Transaction>,
+    prevouts: &[TxOut],
+    i: usize,
+    script_sig: &Script,
+    witness: &Witness,
+) -> bool {
+    let prevout = &prevouts[i];
+    let script_pubkey = &prevout.script_pubkey;
+    if script_pubkey.is_p2tr() {
+        if witness.len() != 1 || !script_sig.is_empty() {
+            return false;
+        }
+        let (signature, output_key) = match (
+            taproot::Signature::from_slice(&witness[0]),
+            XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..]),
+        ) {
+            (Ok(signature), Ok(output_key)) => (signature, output_key),
+            _ => return false,
+        };
+        if !matches!(signature.sighash_type, TapSighashType::Default | TapSighashType::All) {
+            return false;
+        }
+        return match cache.taproot_key_spend_signature_hash(
+            i,
+            &Prevouts::All(prevouts),
+            signature.sighash_type,
+        ) {
+            Ok(sighash) => {
+                let msg = Message::from_digest(sighash.to_byte_array());
+                EC.verify_schnorr(&signature.signature, &msg, &output_key).is_ok()
+            }
+            Err(_) => false,
+        };
+    }
+
+    if script_pubkey.is_p2pkh() {
+        let pushes: Vec<_> = script_sig
+            .instructions()
+            .map(|instruction| match instruction {
+                Ok(Instruction::PushBytes(bytes)) => Some(bytes.as_bytes().to_vec()),
+                _ => None,
+            })
+            .collect();
+        let (signature, public_key) = match pushes.as_slice() {
+            [Some(signature), Some(public_key)] if witness.is_empty() => {
+                (ecdsa::Signature::from_slice(signature), PublicKey::from_slice(public_key))
+            }
+            _ => return false,
+        };
+        let (signature, public_key) = match (signature, public_key) {
+            (Ok(signature), Ok(public_key)) => (signature, public_key),
+            _ => return false,
+        };
+        if signature.sighash_type != EcdsaSighashType::All
+            || ScriptBuf::new_p2pkh(&public_key.pubkey_hash()) != *script_pubkey
+        {
+            return false;
+        }
+        return match cache.legacy_signature_hash(i, script_pubkey, signature.sighash_type.to_u32())
+        {
+            Ok(sighash) => {
+                let msg = Message::from_digest(sighash.to_byte_array());
+                EC.verify_ecdsa(&msg, &signature.signature, &public_key.inner).is_ok()
+            }
+            Err(_) => false,
+        };
+    }
+
+    // p2wpkh and p2sh-p2wpkh
+    if witness.len() != 2 {
+        return false;
+    }
+    let (signature, public_key) = match (
+        ecdsa::Signature::from_slice(&witness[0]),
+        CompressedPublicKey::from_slice(&witness[1]),
+    ) {
+        (Ok(signature), Ok(public_key)) => (signature, public_key),
+        _ => return false,
+    };
+    let wpkh_script = ScriptBuf::new_p2wpkh(&public_key.wpubkey_hash());
+    let expected_script_sig = if *script_pubkey == wpkh_script {
+        ScriptBuf::new()
+    } else if *script_pubkey == wpkh_script.to_p2sh() {
+        p2shwpkh_script_sig(&public_key)
+    } else {
+        return false;
+    };
+    if signature.sighash_type != EcdsaSighashType::All || *script_sig != expected_script_sig {
+        return false;
+    }
+    match cache.p2wpkh_signature_hash(i, &wpkh_script, prevout.value, signature.sighash_type) {
+        Ok(sighash) => {
+            let msg = Message::from_digest(sighash.to_byte_array());
+            EC.verify_ecdsa(&msg, &signature.signature, &public_key.0).is_ok()
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gdk_common::bitcoin::PrivateKey;
+
+    fn previous_tx(script_pubkey: ScriptBuf, satoshi: u64) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![],
+            output: vec![TxOut {
+                value: Amount::from_sat(satoshi),
+                script_pubkey,
+            }],
+        }
+    }
+
+    /// A proof for `message` over a p2wpkh and a p2pkh utxo of `private_key`
+    fn proof(private_key: &PrivateKey, message: &str) -> String {
+        let public_key = CompressedPublicKey::from_private_key(&EC, private_key).unwrap();
+        let p2wpkh = previous_tx(ScriptBuf::new_p2wpkh(&public_key.wpubkey_hash()), 10_000);
+        let p2pkh = previous_tx(ScriptBuf::new_p2pkh(&public_key.pubkey_hash()), 20_000);
+        let utxos = [
+            (OutPoint::new(p2wpkh.compute_txid(), 0), 10_000),
+            (OutPoint::new(p2pkh.compute_txid(), 0), 20_000),
+        ];
+        let mut psbt = proof_psbt(message, &utxos).unwrap();
+        psbt.inputs[1].witness_utxo = Some(p2wpkh.output[0].clone());
+        psbt.inputs[2].non_witness_utxo = Some(p2pkh.clone());
+
+        let tx = psbt.unsigned_tx.clone();
+        let mut cache = SighashCache::new(&tx);
+        let sighash = cache
+            .p2wpkh_signature_hash(
+                1,
+                &p2wpkh.output[0].script_pubkey,
+                Amount::from_sat(10_000),
+                EcdsaSighashType::All,
+            )
+            .unwrap();
+        let msg = Message::from_digest(sighash.to_byte_array());
+        let signature = ecdsa::Signature::sighash_all(EC.sign_ecdsa(&msg, &private_key.inner));
+        psbt.inputs[1].final_script_witness = Some(Witness::p2wpkh(&signature, &public_key.0));
+
+        let sighash = cache
+            .legacy_signature_hash(
+                2,
+                &p2pkh.output[0].script_pubkey,
+                EcdsaSighashType::All.to_u32(),
+            )
+            .unwrap();
+        let msg = Message::from_digest(sighash.to_byte_array());
+        let signature = ecdsa::Signature::sighash_all(EC.sign_ecdsa(&msg, &private_key.inner));
+        psbt.inputs[2].final_script_sig = Some(
+            Builder::new()
+                .push_slice(signature.serialize())
+                .push_key(&PublicKey::from(public_key))
+                .into_script(),
+        );
+        base64::encode(psbt.serialize())
+    }
+
+    #[test]
+    fn test_ownership_proof() {
+        let private_key =
+            PrivateKey::from_wif("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").unwrap();
+        let message = "Reserves of 2024-01-01";
+        let proof = proof(&private_key, message);
+
+        let proven = verify_ownership_proof(&proof, message).unwrap();
+        assert_eq!(proven.len(), 2);
+        assert_eq!(proven.iter().map(|(_, satoshi)| satoshi).sum::<u64>(), 30_000);
+
+        // The proof cannot be broadcast
+        let psbt = Psbt::deserialize(&base64::decode(&proof).unwrap()).unwrap();
+        assert_eq!(psbt.unsigned_tx.input[0].previous_output, challenge_outpoint(message));
+        assert!(psbt.unsigned_tx.output[0].script_pubkey.is_op_return());
+
+        let err = verify_ownership_proof(&proof, "Reserves of 2024-01-02").unwrap_err();
+        assert!(matches!(err, Error::InvalidOwnershipProof(_)));
+
+        // Changing the challenge to the one of another message breaks the signatures
+        let mut tampered = psbt.clone();
+        tampered.unsigned_tx.input[0].previous_output = challenge_outpoint("other");
+        let err =
+            verify_ownership_proof(&base64::encode(tampered.serialize()), "other").unwrap_err();
+        assert!(
+            matches!(err, Error::InvalidOwnershipProof(reason) if reason.contains("signature"))
+        );
+
+        // Claiming more than the utxos hold
+        let mut tampered = psbt.clone();
+        tampered.inputs[1].witness_utxo.as_mut().unwrap().value = Amount::from_sat(1_000_000);
+        tampered.unsigned_tx.output[0].value = Amount::from_sat(1_020_000);
+        let tampered = base64::encode(tampered.serialize());
+        assert!(verify_ownership_proof(&tampered, message).is_err());
+
+        // Proving a utxo twice
+        let mut tampered = psbt;
+        tampered.unsigned_tx.input.push(tampered.unsigned_tx.input[1].clone());
+        tampered.inputs.push(tampered.inputs[1].clone());
+        tampered.unsigned_tx.output[0].value = Amount::from_sat(40_000);
+        let tampered = base64::encode(tampered.serialize());
+        assert!(verify_ownership_proof(&tampered, message).is_err());
+    }
+}
@@ -0,0 +1,208 @@
+//! Resolution of the electrum servers hostnames.
+//!
+//! By default hostnames are resolved by the system, the network parameters can
+//! instead map them to static addresses or resolve them with a
+//! DNS-over-HTTPS resolver. Through a proxy hostnames are always resolved by
+//! the proxy, not to leak DNS requests.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+
+use gdk_common::electrum_client::ToSocketAddrsDomain;
+use gdk_common::network::{self, NetworkParameters};
+use serde::Deserialize;
+
+use crate::error::Error;
+
+/// The DNS record types of IPv4 and IPv6 addresses
+const RECORD_TYPES: [(&str, u16); 2] = [("A", 1), ("AAAA", 28)];
+
+/// The resolver of the servers hostnames of a network
+#[derive(Clone, Debug, Default)]
+pub struct Resolver {
+    static_hosts: HashMap<String, Vec<IpAddr>>,
+    doh_url: Option<String>,
+}
+
+/// How a hostname is resolved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Resolution {
+    /// By the socks5 proxy
+    Proxy,
+    /// To the addresses of the network parameters
+    Static(Vec<IpAddr>),
+    /// By the DNS-over-HTTPS resolver at the url
+    Doh(String),
+    /// By the system, also for IP addresses
+    System,
+}
+
+impl Resolver {
+    pub fn new(network: &NetworkParameters) -> Self {
+        Resolver {
+            static_hosts: network.static_hosts.clone(),
+            doh_url: network.doh_url.clone().filter(|url| !url.is_empty()),
+        }
+    }
+
+    /// How `host` is resolved when connecting through `proxy`
+    pub(crate) fn resolution(&self, host: &str, proxy: Option<&str>) -> Resolution {
+        if proxy.is_some_and(|proxy| !proxy.trim().is_empty()) {
+            return Resolution::Proxy;
+        }
+        if host.parse::<IpAddr>().is_ok() {
+            return Resolution::System;
+        }
+        if let Some(ips) = self.static_hosts.get(host).filter(|ips| !ips.is_empty()) {
+            return Resolution::Static(ips.clone());
+        }
+        match &self.doh_url {
+            Some(doh_url) => Resolution::Doh(doh_url.clone()),
+            None => Resolution::System,
+        }
+    }
+
+    /// The addresses of `host` to connect to `port` through `proxy`, None if
+    /// left to the system or to the proxy
+    pub(crate) fn resolve(
+        &self,
+        host: &str,
+        port: u16,
+        proxy: Option<&str>,
+    ) -> Result<Option<ResolvedHost>, Error> {
+        let ips = match self.resolution(host, proxy) {
+            Resolution::Proxy | Resolution::System => return Ok(None),
+            Resolution::Static(ips) => ips,
+            Resolution::Doh(doh_url) => doh_lookup(&doh_url, host)?,
+        };
+        Ok(Some(ResolvedHost {
+            host: host.to_string(),
+            addrs: ips.into_iter().map(|ip| SocketAddr::new(ip, port)).collect(),
+        }))
+    }
+}
+
+/// A hostname resolved to `addrs`, TLS certificates are validated against
+/// `host`
+#[derive(Debug, Clone)]
+pub(crate) struct ResolvedHost {
+    pub(crate) host: String,
+    pub(crate) addrs: Vec<SocketAddr>,
+}
+
+impl ToSocketAddrs for ResolvedHost {
+    type Iter = std::vec::IntoIter<SocketAddr>;
+
+    fn to_socket_addrs(&self) -> io::Result<Self::Iter> {
+        Ok(self.addrs.clone().into_iter())
+    }
+}
+
+impl ToSocketAddrsDomain for ResolvedHost {
+    fn domain(&self) -> Option<&str> {
+        Some(&self.host)
+    }
+}
+
+#[derive(Deserialize)]
+struct DohResponse {
+    #[serde(rename = "Status")]
+    status: u32,
+    #[serde(rename = "Answer", default)]
+    answer: Vec<DohAnswer>,
+}
+
+#[derive(Deserialize)]
+struct DohAnswer {
+    #[serde(rename = "type")]
+    type_: u16,
+    data: String,
+}
+
+/// The addresses of `host` returned by the JSON API of the DNS-over-HTTPS
+/// resolver at `doh_url`
+fn doh_lookup(doh_url: &str, host: &str) -> Result<Vec<IpAddr>, Error> {
+    let fail = |reason: String| Error::NameResolution(host.to_string(), reason);
+    let agent = network::build_request_agent(None)?;
+    let mut ips = vec![];
+    for (name, record_type) in RECORD_TYPES {
+        let response: DohResponse = agent
+            .get(doh_url)
+            .query("name", host)
+            .query("type", name)
+            .set("accept", "application/dns-json")
+            .call()
+            .map_err(|e| fail(e.to_string()))?
+            .into_json()
+            .map_err(|e| fail(e.to_string()))?;
+        ensure!(response.status == 0, fail(format!("DNS status {}", response.status)));
+        ips.extend(
+            response
+                .answer
+                .into_iter()
+                .filter(|answer| answer.type_ == record_type)
+                .filter_map(|answer| answer.data.parse::<IpAddr>().ok()),
+        );
+    }
+    ensure!(!ips.is_empty(), fail("no address".into()));
+    Ok(ips)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const HOST: &str = "electrum.example";
+    const DOH_URL: &str = "https://1.1.1.1/dns-query";
+
+    fn resolver(static_hosts: bool, doh: bool) -> Resolver {
+        let mut network = NetworkParameters::default();
+        if static_hosts {
+            network.static_hosts.insert(HOST.into(), vec!["10.0.0.1".parse().unwrap()]);
+        }
+        if doh {
+            network.doh_url = Some(DOH_URL.into());
+        }
+        Resolver::new(&network)
+    }
+
+    #[test]
+    fn test_resolution() {
+        let proxy = Some("127.0.0.1:9050");
+        let static_ips = Resolution::Static(vec!["10.0.0.1".parse().unwrap()]);
+        let doh = Resolution::Doh(DOH_URL.into());
+
+        for (static_hosts, doh_url, expected) in [
+            (true, false, static_ips.clone()),
+            (true, true, static_ips),
+            (false, true, doh),
+            (false, false, Resolution::System),
+        ] {
+            let r = resolver(static_hosts, doh_url);
+            assert_eq!(r.resolution(HOST, None), expected);
+            assert_eq!(r.resolution(HOST, Some("")), expected);
+            // The proxy resolves, whatever the parameters
+            assert_eq!(r.resolution(HOST, proxy), Resolution::Proxy);
+        }
+
+        // Only the mapped hostnames are static, IP addresses are not resolved
+        let mapped = resolver(true, false);
+        assert_eq!(mapped.resolution("other.example", None), Resolution::System);
+        let mapped_doh = resolver(true, true);
+        assert_eq!(mapped_doh.resolution("other.example", None), Resolution::Doh(DOH_URL.into()));
+        assert_eq!(mapped_doh.resolution("10.0.0.2", None), Resolution::System);
+    }
+
+    #[test]
+    fn test_resolve_static() {
+        let r = resolver(true, true);
+        let resolved = r.resolve(HOST, 50002, None).unwrap().unwrap();
+        assert_eq!(resolved.domain(), Some(HOST));
+        let addrs: Vec<_> = resolved.to_socket_addrs().unwrap().collect();
+        assert_eq!(addrs, vec!["10.0.0.1:50002".parse::<SocketAddr>().unwrap()]);
+
+        assert!(r.resolve(HOST, 50002, Some("127.0.0.1:9050")).unwrap().is_none());
+        assert!(Resolver::default().resolve(HOST, 50002, None).unwrap().is_none());
+    }
+}
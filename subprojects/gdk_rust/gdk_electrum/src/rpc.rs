@@ -0,0 +1,445 @@
+//! A JSON-RPC 2.0 server giving access to a session over a local socket.
+//!
+//! Requests and responses are JSON objects, one per line. Methods and params are
+//! the ones of the GDK JSON API accepted by [`Session::handle_call`], so that
+//! `{"jsonrpc":"2.0","method":"get_balance","params":{"subaccount":0,"num_confs":0},"id":1}`
+//! returns the same balance returned by the native call.
+//!
+//! A connection must first call `auth` with the server token, eg.
+//! `{"jsonrpc":"2.0","method":"auth","params":{"token":"..."},"id":0}`.
+//! Then it can `subscribe` to notifications, optionally filtered by kind
+//! (`{"events":["block"]}`). Notifications are sent on the same connection as
+//! JSON-RPC notifications with method `notification`, their params are the
+//! notifications as passed to the native callback.
+
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener};
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use gdk_common::log::{info, warn};
+use gdk_common::model::NotificationKind;
+use gdk_common::notification::{self, SubscriptionId};
+use gdk_common::session::Session;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::error::Error;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const INVALID_PARAMS: i64 = -32602;
+/// The session returned an error, its GDK JSON error is in the error data
+const SESSION_ERROR: i64 = -32000;
+const UNAUTHORIZED: i64 = -32001;
+
+/// How often blocked threads check if the server has been stopped
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A client not reading its notifications for this long is disconnected
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RpcEndpoint {
+    /// A unix domain socket at the given path
+    #[cfg(unix)]
+    Unix(PathBuf),
+
+    /// A TCP socket on localhost at the given port, 0 to pick a free one
+    Tcp(u16),
+}
+
+pub struct RpcServer {
+    endpoint: RpcEndpoint,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl RpcServer {
+    /// Serve `session` on `endpoint`, connections must authenticate with `token`
+    pub fn start<S>(
+        session: Arc<Mutex<S>>,
+        endpoint: RpcEndpoint,
+        token: &str,
+    ) -> Result<Self, Error>
+    where
+        S: Session + Send + 'static,
+    {
+        ensure!(!token.is_empty(), Error::Generic("rpc token cannot be empty".into()));
+        let (listener, endpoint) = match endpoint {
+            #[cfg(unix)]
+            RpcEndpoint::Unix(path) => {
+                (Listener::Unix(UnixListener::bind(&path)?), RpcEndpoint::Unix(path))
+            }
+            RpcEndpoint::Tcp(port) => {
+                let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+                let port = listener.local_addr()?.port();
+                (Listener::Tcp(listener), RpcEndpoint::Tcp(port))
+            }
+        };
+        listener.set_nonblocking()?;
+        info!("rpc server listening on {:?}", endpoint);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let token = token.to_string();
+        let stop_accept = stop.clone();
+        let handle = thread::spawn(move || {
+            let mut connections = vec![];
+            while !stop_accept.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((reader, writer)) => {
+                        let session = session.clone();
+                        let token = token.clone();
+                        let stop = stop_accept.clone();
+                        connections.push(thread::spawn(move || {
+                            serve(&session, reader, writer, &token, &stop)
+                        }));
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                    Err(e) => {
+                        warn!("rpc accept failed {:?}", e);
+                        thread::sleep(POLL_INTERVAL);
+                    }
+                }
+                connections.retain(|c: &JoinHandle<()>| !c.is_finished());
+            }
+            for connection in connections {
+                let _ = connection.join();
+            }
+        });
+
+        Ok(RpcServer {
+            endpoint,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// The endpoint the server listens on, with the actual port if 0 was requested
+    pub fn endpoint(&self) -> &RpcEndpoint {
+        &self.endpoint
+    }
+
+    /// Stop the server, closing all the connections
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+            #[cfg(unix)]
+            if let RpcEndpoint::Unix(path) = &self.endpoint {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
+
+impl Drop for RpcServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+enum Listener {
+    #[cfg(unix)]
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+type Reader = Box<dyn Read + Send>;
+type Writer = Arc<Mutex<Box<dyn Write + Send>>>;
+
+impl Listener {
+    fn set_nonblocking(&self) -> io::Result<()> {
+        match self {
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener.set_nonblocking(true),
+            Listener::Tcp(listener) => listener.set_nonblocking(true),
+        }
+    }
+
+    fn accept(&self) -> io::Result<(Reader, Writer)> {
+        match self {
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nonblocking(false)?;
+                stream.set_read_timeout(Some(POLL_INTERVAL))?;
+                stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                Ok((Box::new(stream.try_clone()?), Arc::new(Mutex::new(Box::new(stream)))))
+            }
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nonblocking(false)?;
+                stream.set_read_timeout(Some(POLL_INTERVAL))?;
+                stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                Ok((Box::new(stream.try_clone()?), Arc::new(Mutex::new(Box::new(stream)))))
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: String,
+    method: String,
+    #[serde(default)]
+    params: Value,
+    /// None for notifications, which don't get a response
+    id: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct AuthParams {
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct SubscribeParams {
+    #[serde(default)]
+    events: Vec<NotificationKind>,
+}
+
+#[derive(Deserialize)]
+struct UnsubscribeParams {
+    subscription: SubscriptionId,
+}
+
+/// The state of a client connection
+struct Connection<'a, S> {
+    session: &'a Mutex<S>,
+    writer: Writer,
+    token: &'a str,
+    authenticated: bool,
+    subscriptions: Vec<SubscriptionId>,
+}
+
+fn serve<S: Session>(
+    session: &Mutex<S>,
+    reader: Reader,
+    writer: Writer,
+    token: &str,
+    stop: &AtomicBool,
+) {
+    let mut connection = Connection {
+        session,
+        writer: writer.clone(),
+        token,
+        authenticated: false,
+        subscriptions: vec![],
+    };
+    let mut reader = BufReader::new(reader);
+    let mut line = vec![];
+    while !stop.load(Ordering::Relaxed) {
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(_) => {
+                let response = connection.handle(&line);
+                line.clear();
+                if let Some(response) = response {
+                    if let Err(e) = write_message(&writer, &response) {
+                        warn!("rpc write failed {:?}", e);
+                        break;
+                    }
+                }
+            }
+            // read timeouts, the partial line read so far is kept in `line`
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(e) => {
+                warn!("rpc read failed {:?}", e);
+                break;
+            }
+        }
+    }
+    let mut session = session.lock().unwrap();
+    for id in connection.subscriptions {
+        session.native_notification().unsubscribe(id);
+    }
+}
+
+impl<'a, S: Session> Connection<'a, S> {
+    /// Handle a request line, returning the response if one is due
+    fn handle(&mut self, line: &[u8]) -> Option<Value> {
+        let request: Request = match serde_json::from_slice(line) {
+            Ok(request) => request,
+            Err(e) => return Some(error(Value::Null, PARSE_ERROR, &e.to_string(), None)),
+        };
+        let id = request.id.clone();
+        let response = self.call(request);
+        id.map(|id| match response {
+            Ok(result) => json!({"jsonrpc": "2.0", "result": result, "id": id}),
+            Err((code, message, data)) => error(id, code, &message, data),
+        })
+    }
+
+    fn call(&mut self, request: Request) -> Result<Value, (i64, String, Option<Value>)> {
+        if request.jsonrpc != "2.0" {
+            return Err((INVALID_REQUEST, "jsonrpc must be \"2.0\"".into(), None));
+        }
+        if request.method == "auth" {
+            let params: AuthParams = parse_params(request.params)?;
+            self.authenticated = params.token == self.token;
+            if !self.authenticated {
+                return Err((UNAUTHORIZED, "invalid token".into(), None));
+            }
+            return Ok(Value::Bool(true));
+        }
+        if !self.authenticated {
+            return Err((UNAUTHORIZED, "auth must be called first".into(), None));
+        }
+        match request.method.as_str() {
+            "subscribe" => {
+                let params: SubscribeParams = parse_params(request.params)?;
+                let writer = self.writer.clone();
+                let id = self.session.lock().unwrap().native_notification().subscribe(
+                    &params.events,
+                    move |ntf| {
+                        let message = json!({
+                            "jsonrpc": "2.0",
+                            "method": "notification",
+                            "params": notification::to_json(ntf),
+                        });
+                        if let Err(e) = write_message(&writer, &message) {
+                            warn!("rpc notification write failed {:?}", e);
+                        }
+                    },
+                );
+                self.subscriptions.push(id);
+                Ok(json!(id))
+            }
+            "unsubscribe" => {
+                let params: UnsubscribeParams = parse_params(request.params)?;
+                // only the subscriptions made on this connection can be cancelled
+                if !self.subscriptions.contains(&params.subscription) {
+                    return Ok(Value::Bool(false));
+                }
+                self.subscriptions.retain(|id| *id != params.subscription);
+                let removed = self
+                    .session
+                    .lock()
+                    .unwrap()
+                    .native_notification()
+                    .unsubscribe(params.subscription);
+                Ok(Value::Bool(removed))
+            }
+            method => {
+                self.session.lock().unwrap().handle_call(method, request.params).map_err(|e| {
+                    let message = e.message.clone();
+                    (SESSION_ERROR, message, Some(e.into()))
+                })
+            }
+        }
+    }
+}
+
+fn parse_params<T: for<'de> Deserialize<'de>>(
+    params: Value,
+) -> Result<T, (i64, String, Option<Value>)> {
+    let params = if params.is_null() {
+        json!({})
+    } else {
+        params
+    };
+    serde_json::from_value(params).map_err(|e| (INVALID_PARAMS, e.to_string(), None))
+}
+
+fn error(id: Value, code: i64, message: &str, data: Option<Value>) -> Value {
+    let mut error = json!({"code": code, "message": message});
+    if let Some(data) = data {
+        error["data"] = data;
+    }
+    json!({"jsonrpc": "2.0", "error": error, "id": id})
+}
+
+fn write_message(writer: &Writer, message: &Value) -> io::Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    let mut writer = writer.lock().unwrap();
+    writer.write_all(&line)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ElectrumSession;
+    use gdk_common::model::Settings;
+    use gdk_common::NetworkParameters;
+    use std::net::TcpStream;
+
+    #[test]
+    fn test_rpc_server() {
+        let mut network = NetworkParameters::default();
+        network.electrum_url = Some("127.0.0.1:1".into());
+        let session = Arc::new(Mutex::new(ElectrumSession::new(network).unwrap()));
+        let mut server = RpcServer::start(session.clone(), RpcEndpoint::Tcp(0), "secret").unwrap();
+        let port = match server.endpoint() {
+            RpcEndpoint::Tcp(port) => *port,
+            #[cfg(unix)]
+            RpcEndpoint::Unix(_) => unreachable!(),
+        };
+
+        let stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut read = || {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            serde_json::from_str::<Value>(&line).unwrap_or(Value::Null)
+        };
+        let mut send = |line: &str| writer.write_all(format!("{}\n", line).as_bytes()).unwrap();
+
+        send("not json");
+        assert_eq!(read()["error"]["code"], PARSE_ERROR);
+        send(r#"{"jsonrpc":"2.0","method":"get_memos","id":1}"#);
+        assert_eq!(read()["error"]["code"], UNAUTHORIZED);
+        send(r#"{"jsonrpc":"2.0","method":"auth","params":{"token":"wrong"},"id":2}"#);
+        assert_eq!(read()["error"]["code"], UNAUTHORIZED);
+
+        // notifications get no response
+        send(r#"{"jsonrpc":"2.0","method":"auth","params":{"token":"secret"}}"#);
+        send(r#"{"jsonrpc":"2.0","method":"auth","params":{"token":"secret"},"id":3}"#);
+        assert_eq!(read(), json!({"jsonrpc": "2.0", "result": true, "id": 3}));
+
+        send(r#"{"jsonrpc":"2.0","method":"get_memos","id":4}"#);
+        let response = read();
+        assert_eq!(response["id"], 4);
+        assert_eq!(response["error"]["code"], SESSION_ERROR);
+        assert_eq!(response["error"]["data"]["code"], "store_locked");
+        assert_eq!(response["error"]["data"]["error"], "id_store_locked");
+
+        send(r#"{"jsonrpc":"2.0","method":"subscribe","params":{"events":["settings"]},"id":5}"#);
+        let subscription = read()["result"].as_u64().unwrap();
+        session.lock().unwrap().notify.settings(&Settings::default());
+        let notification = read();
+        assert_eq!(notification["method"], "notification");
+        assert_eq!(notification["params"]["event"], "settings");
+        assert!(notification.get("id").is_none());
+
+        // the subscriptions of other subscribers can't be cancelled
+        let other = session
+            .lock()
+            .unwrap()
+            .native_notification()
+            .subscribe(&[NotificationKind::Settings], |_| {});
+        let unsubscribe = json!({"jsonrpc": "2.0", "method": "unsubscribe", "params": {"subscription": other}, "id": 6});
+        send(&unsubscribe.to_string());
+        assert_eq!(read()["result"], false);
+        assert!(session.lock().unwrap().native_notification().unsubscribe(other));
+
+        let unsubscribe = json!({"jsonrpc": "2.0", "method": "unsubscribe", "params": {"subscription": subscription}, "id": 7});
+        send(&unsubscribe.to_string());
+        assert_eq!(read()["result"], true);
+        send(&unsubscribe.to_string());
+        assert_eq!(read()["result"], false);
+
+        server.stop();
+        assert_eq!(read(), Value::Null);
+    }
+}
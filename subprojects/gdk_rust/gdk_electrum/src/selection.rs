@@ -0,0 +1,520 @@
+//! Selection of the wallet utxos funding a transaction within the maximum
+//! weight of the network policy
+
+use crate::error::Error;
+use crate::pegout::input_signature_weight;
+use gdk_common::model::{ChangeDecision, Txo};
+use gdk_common::scripts::ScriptType;
+use gdk_common::util::weight_to_vsize;
+use std::cmp::Reverse;
+
+/// Estimated weight of an input spending `script_type` once signed
+pub(crate) fn input_weight(script_type: ScriptType) -> u64 {
+    // outpoint, sequence and the script sig length
+    (41 * 4 + input_signature_weight(script_type)) as u64
+}
+
+/// The fee of a transaction of `weight` paying `fee_rate`
+pub(crate) fn required_fee(weight: usize, fee_rate: u64) -> u64 {
+    (weight_to_vsize(weight) as f64 * fee_rate as f64 / 1000.0).ceil() as u64
+}
+
+/// Sort `utxos` largest first, by outpoint to be deterministic
+pub(crate) fn sort_largest_first(utxos: &mut [Txo]) {
+    utxos.sort_by_cached_key(|u| {
+        (Reverse(u.satoshi), u.outpoint.txid().to_string(), u.outpoint.vout())
+    });
+}
+
+/// The number of the sorted `utxos` needed to pay `target` and the fee of
+/// the transaction spending them, weighing `base_weight` without inputs,
+/// and the weight of the transaction
+fn needed(
+    utxos: &[Txo],
+    target: u64,
+    fee_rate: u64,
+    base_weight: u64,
+) -> Result<(usize, u64), Error> {
+    let mut total = 0;
+    let mut weight = base_weight;
+    for (count, utxo) in utxos.iter().enumerate() {
+        if total >= target + required_fee(weight as usize, fee_rate) {
+            return Ok((count, weight));
+        }
+        total += utxo.satoshi;
+        weight += input_weight(utxo.script_type);
+    }
+    ensure!(total >= target + required_fee(weight as usize, fee_rate), Error::InsufficientFunds);
+    Ok((utxos.len(), weight))
+}
+
+/// Select largest first the `utxos` paying `target` and the fee at
+/// `fee_rate` of the transaction spending them, weighing `base_weight`
+/// without inputs.
+///
+/// Fails with [`Error::TooManyInputs`] if the transaction would weigh more
+/// than `max_weight`, before building and signing it.
+pub(crate) fn select_utxos(
+    mut utxos: Vec<Txo>,
+    target: u64,
+    fee_rate: u64,
+    base_weight: u64,
+    max_weight: u64,
+) -> Result<Vec<Txo>, Error> {
+    sort_largest_first(&mut utxos);
+    let (count, weight) = needed(&utxos, target, fee_rate, base_weight)?;
+    if weight > max_weight {
+        return Err(too_many_inputs(&utxos, count, base_weight, max_weight));
+    }
+    utxos.truncate(count);
+    Ok(utxos)
+}
+
+/// The error of a transaction needing `count` of the sorted `utxos`, more
+/// than the ones fitting in `max_weight`
+fn too_many_inputs(utxos: &[Txo], count: usize, base_weight: u64, max_weight: u64) -> Error {
+    let mut max = 0;
+    let mut weight = base_weight;
+    for utxo in utxos.iter() {
+        weight += input_weight(utxo.script_type);
+        if weight > max_weight {
+            break;
+        }
+        max += 1;
+    }
+    Error::TooManyInputs {
+        count,
+        max,
+    }
+}
+
+/// The change output of a transaction
+pub(crate) struct ChangeParams {
+    /// The change floor, see [`gdk_common::NetworkPolicy::min_change_satoshi`]
+    pub min_change: u64,
+
+    /// The dust limit of the change script
+    pub dust: u64,
+
+    /// The weight added by the change output
+    pub change_weight: u64,
+
+    /// Whether the transaction can go without change, adding it to the fee;
+    /// not on Liquid where the change absorbs the blinding factors
+    pub can_fold: bool,
+}
+
+/// The utxos selected for a transaction and how they are split
+#[derive(Debug)]
+pub(crate) struct Selection {
+    pub utxos: Vec<Txo>,
+    pub sent: u64,
+    pub fee: u64,
+    pub change: u64,
+    pub decision: ChangeDecision,
+    pub weight: u64,
+}
+
+/// The split of the first `count` sorted `utxos`, with or without change,
+/// if they pay the transaction
+fn layout(
+    utxos: &[Txo],
+    target: u64,
+    fee_rate: u64,
+    base_weight: u64,
+    change: &ChangeParams,
+    with_change: bool,
+) -> Option<Selection> {
+    let total: u64 = utxos.iter().map(|u| u.satoshi).sum();
+    let mut weight = base_weight + utxos.iter().map(|u| input_weight(u.script_type)).sum::<u64>();
+    if with_change {
+        weight += change.change_weight;
+    }
+    let fee = required_fee(weight as usize, fee_rate);
+    let excess = total.checked_sub(target.checked_add(fee)?)?;
+    let selection = |fee, change, decision| Selection {
+        utxos: utxos.to_vec(),
+        sent: target,
+        fee,
+        change,
+        decision,
+        weight,
+    };
+    if with_change {
+        let decision = match excess >= change.min_change {
+            true => ChangeDecision::Kept,
+            false => ChangeDecision::BelowFloor,
+        };
+        (excess >= change.dust).then(|| selection(fee, excess, decision))
+    } else if excess == 0 {
+        Some(selection(fee, 0, ChangeDecision::NoChange))
+    } else {
+        change.can_fold.then(|| selection(fee + excess, 0, ChangeDecision::FoldedIntoFee))
+    }
+}
+
+/// Select largest first the `utxos` of a transaction weighing `base_weight`
+/// without inputs and change, sending `target` at `fee_rate`.
+///
+/// When the change would be below `change.min_change`, it either adds inputs
+/// to raise it or adds it to the fee, whichever pays the lower fee, and
+/// reports it in [`Selection::decision`]. Without either, it keeps the change
+/// if above the dust limit.
+///
+/// Fails with [`Error::TooManyInputs`] if the transaction would weigh more
+/// than `max_weight`.
+pub(crate) fn select_with_change_floor(
+    mut utxos: Vec<Txo>,
+    target: u64,
+    fee_rate: u64,
+    base_weight: u64,
+    change: &ChangeParams,
+    max_weight: u64,
+) -> Result<Selection, Error> {
+    sort_largest_first(&mut utxos);
+    let layout = |count: usize, with_change| {
+        layout(&utxos[..count], target, fee_rate, base_weight, change, with_change)
+    };
+    let fits = |s: &Selection| s.weight <= max_weight;
+
+    let count = (1..=utxos.len())
+        .find(|&count| layout(count, true).is_some() || layout(count, false).is_some())
+        .ok_or(Error::InsufficientFunds)?;
+    let kept = layout(count, true);
+    let folded = layout(count, false);
+    let natural = |s: &Option<Selection>| {
+        s.as_ref().map_or(false, |s| {
+            matches!(s.decision, ChangeDecision::Kept | ChangeDecision::NoChange)
+        })
+    };
+    let selection = if natural(&kept) {
+        kept
+    } else if natural(&folded) {
+        folded
+    } else {
+        let added = (count + 1..=utxos.len())
+            .filter_map(|count| layout(count, true))
+            .take_while(fits)
+            .find(|s| s.decision == ChangeDecision::Kept)
+            .map(|s| Selection {
+                decision: ChangeDecision::AddedInput,
+                ..s
+            });
+        // On a tie adding inputs keeps the change in the wallet
+        [added, folded].into_iter().flatten().filter(fits).min_by_key(|s| s.fee).or(kept)
+    };
+    let selection = selection.ok_or(Error::InsufficientFunds)?;
+    ensure!(fits(&selection), too_many_inputs(&utxos, count, base_weight, max_weight));
+    Ok(selection)
+}
+
+/// Split the selection paying `target` in a chain of transactions within
+/// `max_weight`: each one spends the change of the previous one, of
+/// `change_type`, the ones before the last send everything to the change
+/// and the last one pays `target`.
+///
+/// `step_weight` and `base_weight` are the weights without inputs of the
+/// intermediate transactions and of the last one. Returns the utxos spent by
+/// each transaction, the change of the previous one excluded, a single group
+/// if a transaction suffices.
+pub(crate) fn plan_chained(
+    mut utxos: Vec<Txo>,
+    target: u64,
+    fee_rate: u64,
+    step_weight: u64,
+    base_weight: u64,
+    change_type: ScriptType,
+    max_weight: u64,
+) -> Result<Vec<Vec<Txo>>, Error> {
+    sort_largest_first(&mut utxos);
+    let change_weight = input_weight(change_type);
+    let step_capacity = max_weight.saturating_sub(step_weight + change_weight);
+    let last_capacity = max_weight.saturating_sub(base_weight + change_weight);
+    for utxo in utxos.iter() {
+        ensure!(input_weight(utxo.script_type) <= step_capacity, Error::TransactionTooLarge);
+    }
+
+    // Spending more utxos adds transactions and their fees, try from the
+    // number needed by a single transaction
+    let (mut count, _) = needed(&utxos, target, fee_rate, base_weight)?;
+    loop {
+        let groups = chain_groups(&utxos[..count], step_capacity, last_capacity);
+        let total: u64 = utxos[..count].iter().map(|u| u.satoshi).sum();
+        let last = groups.len() - 1;
+        let fee: u64 = groups
+            .iter()
+            .enumerate()
+            .map(|(i, group)| {
+                let mut weight = if i == last {
+                    base_weight
+                } else {
+                    step_weight
+                };
+                if i > 0 {
+                    weight += change_weight;
+                }
+                weight += group.iter().map(|u| input_weight(u.script_type)).sum::<u64>();
+                required_fee(weight as usize, fee_rate)
+            })
+            .sum();
+        if total >= target + fee {
+            return Ok(groups.into_iter().map(<[Txo]>::to_vec).collect());
+        }
+        ensure!(count < utxos.len(), Error::InsufficientFunds);
+        count += 1;
+    }
+}
+
+/// Pack `utxos` in order in the groups of the chained transactions, the
+/// intermediate ones within `step_capacity` and the last one within
+/// `last_capacity`
+fn chain_groups(utxos: &[Txo], step_capacity: u64, last_capacity: u64) -> Vec<&[Txo]> {
+    let mut groups = vec![];
+    let mut rest = utxos;
+    loop {
+        let weight: u64 = rest.iter().map(|u| input_weight(u.script_type)).sum();
+        if weight <= last_capacity {
+            groups.push(rest);
+            return groups;
+        }
+        let mut len = 0;
+        let mut weight = 0;
+        for utxo in rest.iter() {
+            weight += input_weight(utxo.script_type);
+            if weight > step_capacity {
+                break;
+            }
+            len += 1;
+        }
+        let (group, tail) = rest.split_at(len);
+        groups.push(group);
+        rest = tail;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gdk_common::be::{BEOutPoint, BEScript};
+    use gdk_common::bitcoin::{self, hashes::Hash};
+    use std::str::FromStr;
+
+    fn txo(n: u32, satoshi: u64, script_type: ScriptType) -> Txo {
+        let mut txid = [0; 32];
+        txid[..4].copy_from_slice(&n.to_le_bytes());
+        Txo {
+            outpoint: BEOutPoint::Bitcoin(bitcoin::OutPoint::new(
+                bitcoin::Txid::from_byte_array(txid),
+                0,
+            )),
+            height: None,
+            public_key: bitcoin::PublicKey::from_str(
+                "03ad1d8e89212f0b92c74d23bb710c00662ad1470198ac48c43f7d6f93a2a26873",
+            )
+            .unwrap(),
+            script_pubkey: BEScript::default(),
+            script_code: BEScript::default(),
+            subaccount: 0,
+            script_type,
+            user_path: vec![],
+            satoshi,
+            sequence: None,
+            txoutsecrets: None,
+            txoutcommitments: None,
+        }
+    }
+
+    /// 3000 small utxos of alternating script types
+    fn small_utxos() -> Vec<Txo> {
+        let script_types = [ScriptType::P2wpkh, ScriptType::P2shP2wpkh, ScriptType::P2pkh];
+        (0..3000).map(|n| txo(n, 10_000, script_types[n as usize % 3])).collect()
+    }
+
+    #[test]
+    fn test_input_weight() {
+        assert_eq!(input_weight(ScriptType::P2wpkh), 272);
+        assert_eq!(input_weight(ScriptType::P2shP2wpkh), 364);
+        assert_eq!(input_weight(ScriptType::P2pkh), 592);
+    }
+
+    #[test]
+    fn test_select_utxos_too_many_inputs() {
+        let utxos = small_utxos();
+        let total: u64 = utxos.iter().map(|u| u.satoshi).sum();
+
+        // Few inputs fit
+        let selected = select_utxos(utxos.clone(), 100_000, 1_000, 1_000, 400_000).unwrap();
+        assert_eq!(selected.len(), 11);
+        let fee = required_fee(
+            1_000 + selected.iter().map(|u| input_weight(u.script_type)).sum::<u64>() as usize,
+            1_000,
+        );
+        assert!(selected.iter().map(|u| u.satoshi).sum::<u64>() >= 100_000 + fee);
+
+        // Spending almost everything needs most of the inputs, only about a
+        // thousand fit
+        let err = select_utxos(utxos.clone(), total / 2, 1_000, 1_000, 400_000).unwrap_err();
+        let (count, max) = match err {
+            Error::TooManyInputs {
+                count,
+                max,
+            } => (count, max),
+            e => panic!("unexpected error {:?}", e),
+        };
+        assert!(count > 1_500 && count < 3_000, "count {}", count);
+        let mut sorted = small_utxos();
+        sort_largest_first(&mut sorted);
+        let max_weight: u64 =
+            1_000 + sorted[..max].iter().map(|u| input_weight(u.script_type)).sum::<u64>();
+        assert!(max_weight <= 400_000);
+        assert!(max_weight + input_weight(sorted[max].script_type) > 400_000);
+
+        let err = select_utxos(utxos, total, 1_000, 1_000, 400_000).unwrap_err();
+        assert!(matches!(err, Error::InsufficientFunds));
+    }
+
+    #[test]
+    fn test_plan_chained() {
+        let utxos = small_utxos();
+        let total: u64 = utxos.iter().map(|u| u.satoshi).sum();
+        let target = total * 9 / 10;
+        let change_type = ScriptType::P2wpkh;
+        let groups =
+            plan_chained(utxos.clone(), target, 1_000, 1_000, 1_000, change_type, 400_000).unwrap();
+        assert!(groups.len() >= 3, "{} transactions", groups.len());
+
+        // Every transaction is within the maximum weight, the change included
+        for (i, group) in groups.iter().enumerate() {
+            let change = if i > 0 {
+                input_weight(change_type)
+            } else {
+                0
+            };
+            let weight =
+                1_000 + change + group.iter().map(|u| input_weight(u.script_type)).sum::<u64>();
+            assert!(weight <= 400_000, "transaction {} weighs {}", i, weight);
+        }
+
+        // The utxos spent are distinct and pay the target and the fees
+        let spent: Vec<_> = groups.iter().flatten().collect();
+        let outpoints: std::collections::HashSet<_> = spent.iter().map(|u| &u.outpoint).collect();
+        assert_eq!(outpoints.len(), spent.len());
+        let fees: u64 = groups
+            .iter()
+            .enumerate()
+            .map(|(i, group)| {
+                let change = if i > 0 {
+                    input_weight(change_type)
+                } else {
+                    0
+                };
+                let weight =
+                    1_000 + change + group.iter().map(|u| input_weight(u.script_type)).sum::<u64>();
+                required_fee(weight as usize, 1_000)
+            })
+            .sum();
+        assert!(spent.iter().map(|u| u.satoshi).sum::<u64>() >= target + fees);
+
+        // A single transaction when it fits
+        let groups =
+            plan_chained(utxos.clone(), 100_000, 1_000, 1_000, 1_000, change_type, 400_000)
+                .unwrap();
+        assert_eq!(groups.len(), 1);
+
+        let err =
+            plan_chained(utxos, total, 1_000, 1_000, 1_000, change_type, 400_000).unwrap_err();
+        assert!(matches!(err, Error::InsufficientFunds));
+    }
+
+    /// A p2wpkh change, floor three times its dust limit
+    fn change_params(can_fold: bool) -> ChangeParams {
+        ChangeParams {
+            min_change: 882,
+            dust: 294,
+            change_weight: 31 * 4,
+            can_fold,
+        }
+    }
+
+    #[test]
+    fn test_change_floor() {
+        // At 1 sat/vb one input weighs 1272 without change, 318 sat, and
+        // 1396 with change, 349 sat; two inputs with change 1668, 417 sat
+        let utxos = vec![txo(0, 10_000, ScriptType::P2wpkh), txo(1, 1_000, ScriptType::P2wpkh)];
+        let select = |target, can_fold| {
+            select_with_change_floor(
+                utxos.clone(),
+                target,
+                1_000,
+                1_000,
+                &change_params(can_fold),
+                400_000,
+            )
+        };
+
+        let s = select(5_000, true).unwrap();
+        assert_eq!(s.decision, ChangeDecision::Kept);
+        assert_eq!((s.utxos.len(), s.sent, s.fee, s.change), (1, 5_000, 349, 4_651));
+
+        let s = select(9_682, true).unwrap();
+        assert_eq!(s.decision, ChangeDecision::NoChange);
+        assert_eq!((s.utxos.len(), s.fee, s.change), (1, 318, 0));
+
+        // 82 sat of change left: folding them costs less than the 99 sat
+        // of the input and change added
+        let s = select(9_600, true).unwrap();
+        assert_eq!(s.decision, ChangeDecision::FoldedIntoFee);
+        assert_eq!((s.utxos.len(), s.sent, s.fee, s.change), (1, 9_600, 400, 0));
+
+        // 182 sat left: adding the small input is cheaper
+        let s = select(9_500, true).unwrap();
+        assert_eq!(s.decision, ChangeDecision::AddedInput);
+        assert_eq!((s.utxos.len(), s.sent, s.fee, s.change), (2, 9_500, 417, 1_083));
+
+        // Without the small input the change can only be folded
+        let s = select_with_change_floor(
+            utxos[..1].to_vec(),
+            9_500,
+            1_000,
+            1_000,
+            &change_params(true),
+            400_000,
+        )
+        .unwrap();
+        assert_eq!(s.decision, ChangeDecision::FoldedIntoFee);
+        assert_eq!((s.fee, s.change), (500, 0));
+
+        // Nor above the maximum weight
+        let s = select_with_change_floor(
+            utxos.clone(),
+            9_500,
+            1_000,
+            1_000,
+            &change_params(true),
+            1_500,
+        )
+        .unwrap();
+        assert_eq!(s.decision, ChangeDecision::FoldedIntoFee);
+
+        // Where the change can't be folded, it's kept above the dust limit
+        let s = select(9_300, false).unwrap();
+        assert_eq!(s.decision, ChangeDecision::AddedInput);
+        let s = select_with_change_floor(
+            utxos[..1].to_vec(),
+            9_300,
+            1_000,
+            1_000,
+            &change_params(false),
+            400_000,
+        )
+        .unwrap();
+        assert_eq!(s.decision, ChangeDecision::BelowFloor);
+        assert_eq!((s.fee, s.change), (349, 351));
+        let s = select(9_600, false).unwrap();
+        assert_eq!(s.decision, ChangeDecision::Kept);
+        assert_eq!((s.utxos.len(), s.change), (2, 983));
+
+        let err = select(11_000, true).unwrap_err();
+        assert!(matches!(err, Error::InsufficientFunds));
+    }
+}
@@ -1,7 +1,9 @@
 use std::{
     collections::{HashMap, HashSet},
-    sync::{atomic::AtomicBool, Arc, Mutex, RwLock},
-    time::SystemTime,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64},
+        Arc, Mutex, RwLock,
+    },
 };
 
 use gdk_common::{
@@ -11,14 +13,17 @@ use gdk_common::{
     log,
     model::*,
     notification::NativeNotif,
+    redact::PrivacyMode,
     session::{JsonError, Session},
+    trace::{redacted, timed},
+    tracing::{field::Empty, info_span},
     ureq, NetworkParameters,
 };
 use serde_json::Value;
 
 use crate::{
-    account::Account, error::Error, interface::ElectrumUrl, socksify, ElectrumSession,
-    DEFAULT_GAP_LIMIT,
+    account::Account, clock::NetworkTime, error::Error, interface::ElectrumUrl, socksify,
+    ElectrumSession, DEFAULT_GAP_LIMIT,
 };
 
 impl ExchangeRatesCacher for ElectrumSession {
@@ -29,18 +34,28 @@ impl ExchangeRatesCacher for ElectrumSession {
 
 impl Session for ElectrumSession {
     fn new(network_parameters: NetworkParameters) -> Result<Self, JsonError> {
+        network_parameters.validate().map_err(Error::from)?;
         let url = determine_electrum_url(&network_parameters)?;
         let gap_limit = network_parameters.gap_limit.unwrap_or(DEFAULT_GAP_LIMIT);
+        let mut notify = NativeNotif::new();
+        let privacy_mode = network_parameters.privacy_mode().then(|| {
+            notify.privacy_mode = true;
+            PrivacyMode::enable()
+        });
+        if let Some(queue_size) = network_parameters.notification_queue_size {
+            notify.set_queue_size(queue_size);
+        }
 
         Ok(Self {
             proxy: socksify(network_parameters.proxy.as_deref()),
             network: network_parameters,
             url,
             accounts: Arc::new(RwLock::new(HashMap::<u32, Account>::new())),
-            notify: NativeNotif::new(),
+            notify,
             handles: vec![],
             user_wants_to_sync: Arc::new(AtomicBool::new(false)),
             last_network_call_succeeded: Arc::new(AtomicBool::new(false)),
+            last_answer: Arc::new(AtomicU64::new(0)),
             timeout: None,
             store: None,
             is_initialized: false,
@@ -48,9 +63,28 @@ impl Session for ElectrumSession {
             recent_spent_utxos: Arc::new(RwLock::new(HashSet::<BEOutPoint>::new())),
             xr_cache: ExchangeRatesCache::default(),
             available_currencies: None,
+            price_source: None,
+            pin_server: None,
+            credential_protector: None,
+            transaction_policy: None,
+            confirmation_tokens: HashMap::new(),
             first_sync: Arc::new(AtomicBool::new(true)),
+            metrics: Arc::new(Mutex::new(SessionMetrics::default())),
             gap_limit,
-            fee_fetched_at: Arc::new(Mutex::new(SystemTime::UNIX_EPOCH)),
+            fee_fetched_at: Arc::new(Mutex::new(None)),
+            fee_failed_at: Mutex::new(None),
+            network_time: NetworkTime::default(),
+            signer: None,
+            paused: None,
+            manager: None,
+            managed_sync: None,
+            subscriptions: None,
+            spv_height: Arc::new(AtomicU32::new(0)),
+            spv_running: Arc::new(AtomicBool::new(false)),
+            signblock: Arc::new(Mutex::new(None)),
+            closed: false,
+            wallet_hash_id: None,
+            _privacy_mode: privacy_mode,
         })
     }
 
@@ -67,17 +101,59 @@ impl Session for ElectrumSession {
     }
 
     fn handle_call(&mut self, method: &str, input: Value) -> Result<Value, JsonError> {
+        let span = timed(info_span!(
+            "call",
+            method,
+            wallet_hash_id = Empty,
+            subaccount = Empty,
+            elapsed_ms = Empty
+        ));
+        if let Some(wallet_hash_id) = &self.wallet_hash_id {
+            span.record("wallet_hash_id", redacted(wallet_hash_id).as_str());
+        }
+        if let Some(subaccount) = input.get("subaccount").and_then(Value::as_u64) {
+            span.record("subaccount", subaccount);
+        }
+        self.check_paused(method)?;
+
         match method {
             "connect" => self.connect(&input).to_json(),
 
             "disconnect" => self.disconnect().to_json(),
+            "set_electrum_server" => {
+                self.set_electrum_server(&serde_json::from_value(input)?).to_json()
+            }
+            "set_spv_enabled" => self.set_spv_enabled(&serde_json::from_value(input)?).to_json(),
+            "stop" => self.stop().to_json(),
 
+            "login" => self.login(&serde_json::from_value(input)?).to_json(),
             "login_wo" => self.login_wo(serde_json::from_value(input)?).to_json(),
+            "login_watch_only" => self.login_watch_only(serde_json::from_value(input)?).to_json(),
+            "set_watch_only_credentials" => {
+                self.set_watch_only_credentials(serde_json::from_value(input)?).to_json()
+            }
+            "get_watch_only_username" => self.get_watch_only_username().to_json(),
             "credentials_from_pin_data" => {
                 self.credentials_from_pin_data(&serde_json::from_value(input)?).to_json()
             }
             "encrypt_with_pin" => self.encrypt_with_pin(&serde_json::from_value(input)?).to_json(),
             "decrypt_with_pin" => self.decrypt_with_pin(&serde_json::from_value(input)?).to_json(),
+            "enable_quick_login" => {
+                self.enable_quick_login(&serde_json::from_value(input)?).to_json()
+            }
+            "quick_login" => self.quick_login(&serde_json::from_value(input)?).to_json(),
+            "disable_quick_login" => {
+                self.disable_quick_login(&serde_json::from_value(input)?).to_json()
+            }
+
+            "get_sync_status" => Ok(serde_json::to_value(self.get_sync_status())?),
+            "get_session_metrics" => self.get_session_metrics().to_json(),
+            "get_wallet_info" => self.get_wallet_info().to_json(),
+            "get_health" => self.get_health().to_json(),
+            "get_block_status" => self.get_block_status().to_json(),
+            "verify_store" => self.verify_store().to_json(),
+            "pause" => self.pause(&serde_json::from_value(input)?).to_json(),
+            "resume" => self.resume().to_json(),
 
             "get_block_height" => self.get_block_height().to_json(),
 
@@ -94,6 +170,19 @@ impl Session for ElectrumSession {
                 let opt: CreateAccountOpt = serde_json::from_value(input)?;
                 self.create_subaccount(opt).to_json()
             }
+            "get_multisig_key" => self.get_multisig_key().to_json(),
+            "create_multisig_subaccount" => {
+                let opt: CreateMultisigAccountOpt = serde_json::from_value(input)?;
+                self.create_multisig_subaccount(opt).to_json()
+            }
+            "create_green_multisig_subaccount" => {
+                let opt: CreateGreenMultisigAccountOpt = serde_json::from_value(input)?;
+                self.create_green_multisig_subaccount(opt).to_json()
+            }
+            "create_recovery_transactions" => {
+                let opt: CreateRecoveryTransactionsOpt = serde_json::from_value(input)?;
+                self.create_recovery_transactions(&opt).to_json()
+            }
             "get_next_subaccount" => {
                 let opt: GetNextAccountOpt = serde_json::from_value(input)?;
                 self.get_next_subaccount(opt).to_json()
@@ -112,8 +201,47 @@ impl Session for ElectrumSession {
                 self.get_transactions(&opt).map(|x| txs_result_value(&x)).map_err(Into::into)
             }
 
+            "search_transactions" => {
+                let opt: SearchTransactionsOpt = serde_json::from_value(input)?;
+                self.search_transactions(&opt).to_json()
+            }
+
+            "export_transactions" => {
+                let opt: ExportTransactionsOpt = serde_json::from_value(input)?;
+                let mut data = vec![];
+                self.export_transactions(&opt, &mut data)
+                    .map(|()| ExportTransactionsResult {
+                        data: String::from_utf8_lossy(&data).into_owned(),
+                    })
+                    .to_json()
+            }
+
             "get_transaction_hex" => get_transaction_hex(self, &input).to_json(),
+            "get_raw_transaction" => {
+                self.get_raw_transaction(&serde_json::from_value(input)?).to_json()
+            }
+            "get_transaction_details" => get_transaction_details(self, &input).to_json(),
+            "sign_message" => self.sign_message(&serde_json::from_value(input)?).to_json(),
+            "verify_message" => self.verify_message(&serde_json::from_value(input)?).to_json(),
+            "create_ownership_proof" => {
+                self.create_ownership_proof(&serde_json::from_value(input)?).to_json()
+            }
+            "verify_ownership_proof" => {
+                self.verify_ownership_proof(&serde_json::from_value(input)?).to_json()
+            }
+            "psbt_from_create_transaction" => {
+                self.psbt_from_create_transaction(&serde_json::from_value(input)?).to_json()
+            }
+            "sign_psbt" => self.sign_psbt(&serde_json::from_value(input)?).to_json(),
+            "finalize_and_extract" => {
+                self.finalize_and_extract(&serde_json::from_value(input)?).to_json()
+            }
+            "psbt_get_details" => self.psbt_get_details(&serde_json::from_value(input)?).to_json(),
+            "psbt_sign" => self.psbt_sign(&serde_json::from_value(input)?).to_json(),
+            "parse_uri" => self.parse_uri(&serde_json::from_value(input)?).to_json(),
+            "create_uri" => self.create_uri(&serde_json::from_value(input)?).to_json(),
             "set_transaction_memo" => set_transaction_memo(self, &input),
+            "is_mine" => self.is_mine(&serde_json::from_value(input)?).to_json(),
             "get_scriptpubkey_data" => self
                 .get_scriptpubkey_data(input.as_str().ok_or_else(|| {
                     Error::Generic("get_scriptpubkey_data: input is not a string".into())
@@ -124,6 +252,24 @@ impl Session for ElectrumSession {
                     Error::Generic("broadcast_transaction: input not a string".into())
                 })?)
                 .to_json(),
+            "check_mempool_acceptance" => {
+                self.check_mempool_acceptance(&serde_json::from_value(input)?).to_json()
+            }
+            "send_transaction" => self.send_transaction(&serde_json::from_value(input)?).to_json(),
+            "get_pending_broadcasts" => self.get_pending_broadcasts().to_json(),
+            "cancel_pending_broadcast" => {
+                self.cancel_pending_broadcast(&serde_json::from_value(input)?).to_json()
+            }
+            "approve_transaction" => self
+                .approve_transaction(input.as_str().ok_or_else(|| {
+                    Error::Generic("approve_transaction: input not a string".into())
+                })?)
+                .to_json(),
+            "check_spend_limits" => self
+                .check_spend_limits(input.as_str().ok_or_else(|| {
+                    Error::Generic("check_spend_limits: input not a string".into())
+                })?)
+                .to_json(),
 
             "get_receive_address" => {
                 let a = self.get_receive_address(&serde_json::from_value(input)?).to_json();
@@ -133,21 +279,54 @@ impl Session for ElectrumSession {
             "get_previous_addresses" => {
                 self.get_previous_addresses(&serde_json::from_value(input)?).to_json()
             }
+            "get_expected_payments" => {
+                self.get_expected_payments(&serde_json::from_value(input)?).to_json()
+            }
 
             "get_fee_estimates" => {
                 self.get_fee_estimates().map_err(Into::into).and_then(|x| fee_estimate_values(&x))
             }
             "get_min_fee_rate" => self.get_min_fee_rate().to_json(),
+            "backfill_fiat_at_confirmation" => self.backfill_fiat_at_confirmation().to_json(),
+            "get_address_payments" => {
+                self.get_address_payments(&serde_json::from_value(input)?).to_json()
+            }
+            "audit_derivations" => {
+                self.audit_derivations(&serde_json::from_value(input)?).to_json()
+            }
+            "verify_receive_address" => {
+                self.verify_receive_address(&serde_json::from_value(input)?).to_json()
+            }
+            "diagnose_script" => self.diagnose_script(&serde_json::from_value(input)?).to_json(),
+            "get_network_info" => Ok(serde_json::to_value(self.get_network_info())?),
 
             "get_settings" => self.get_settings().to_json(),
             "get_available_currencies" => {
                 self.get_available_currencies(&serde_json::from_value(input)?).to_json()
             }
+            "convert_amount" => self.convert_amount(&serde_json::from_value(input)?).to_json(),
             "change_settings" => self.change_settings(&serde_json::from_value(input)?).to_json(),
 
             "get_unspent_outputs" => {
                 self.get_unspent_outputs(&serde_json::from_value(input)?).to_json()
             }
+            "get_balance" => self.get_balance(&serde_json::from_value(input)?).to_json(),
+            "get_balance_at" => self.get_balance_at(&serde_json::from_value(input)?).to_json(),
+            "get_unspent_outputs_at" => {
+                self.get_unspent_outputs_at(&serde_json::from_value(input)?).to_json()
+            }
+            "get_last_notification_of_type" => {
+                self.get_last_notification_of_type(&serde_json::from_value(input)?).to_json()
+            }
+            "create_pegout_transaction" => {
+                self.create_pegout_transaction(&serde_json::from_value(input)?).to_json()
+            }
+            "create_wallet_migration" => {
+                self.create_wallet_migration(&serde_json::from_value(input)?).to_json()
+            }
+            "execute_wallet_migration" => {
+                self.execute_wallet_migration(&serde_json::from_value(input)?).to_json()
+            }
 
             "load_store" => self.load_store(&serde_json::from_value(input)?).to_json(),
             "set_fingerprint" => self
@@ -158,6 +337,9 @@ impl Session for ElectrumSession {
             "load_blob" => self.load_blob().to_json(),
             "save_blob" => self.save_blob(serde_json::from_value(input)?).to_json(),
             "get_memos" => self.get_memos().to_json(),
+            "set_output_label" => self.set_output_label(&serde_json::from_value(input)?).to_json(),
+            "export_labels" => self.export_labels().to_json(),
+            "import_labels" => self.import_labels(&serde_json::from_value(input)?).to_json(),
             "get_master_blinding_key" => self.get_master_blinding_key().to_json(),
             "set_master_blinding_key" => {
                 self.set_master_blinding_key(&serde_json::from_value(input)?).to_json()
@@ -205,6 +387,7 @@ impl From<Error> for JsonError {
         JsonError {
             message: e.to_string(),
             error: e.to_gdk_code(),
+            code: e.code(),
         }
     }
 }
@@ -218,8 +401,19 @@ pub fn get_transaction_hex(session: &ElectrumSession, input: &Value) -> Result<S
     session.get_transaction_hex(txid)
 }
 
+pub fn get_transaction_details(
+    session: &ElectrumSession,
+    input: &Value,
+) -> Result<TransactionDetails, Error> {
+    let txid = input
+        .as_str()
+        .ok_or_else(|| Error::Generic("get_transaction_details: input is not a string".into()))?;
+
+    session.get_transaction_details(txid)
+}
+
 pub fn txs_result_value(txs: &TxsResult) -> Value {
-    json!(txs.0.clone())
+    json!(txs)
 }
 
 pub fn set_transaction_memo(session: &ElectrumSession, input: &Value) -> Result<Value, JsonError> {
@@ -235,16 +429,16 @@ pub fn set_transaction_memo(session: &ElectrumSession, input: &Value) -> Result<
     session.set_transaction_memo(txid, memo).to_json()
 }
 
-pub fn fee_estimate_values(estimates: &[FeeEstimate]) -> Result<Value, JsonError> {
-    if estimates.is_empty() {
+pub fn fee_estimate_values(estimates: &FeeEstimates) -> Result<Value, JsonError> {
+    if estimates.fees.is_empty() {
         // Current apps depend on this length
         return Err(JsonError::new("Expected at least one feerate"));
     }
 
-    Ok(json!({ "fees": estimates }))
+    Ok(serde_json::to_value(estimates)?)
 }
 
-trait ToJson {
+pub(crate) trait ToJson {
     fn to_json(self) -> Result<Value, JsonError>;
 }
 
@@ -0,0 +1,507 @@
+//! Signers of the wallet keys, used by the session for all its signing.
+//!
+//! The session only ever asks a [`Signer`] for extended public keys and
+//! signatures, so the keys can be kept by another process or a hardware
+//! device. [`SoftwareSigner`] keeps them in memory.
+
+use crate::account::key_address;
+use crate::error::Error;
+use crate::message;
+use gdk_common::be::{BEScriptConvert, BETransaction, BETxOut};
+use gdk_common::bitcoin::bip32::{DerivationPath, Xpriv, Xpub};
+use gdk_common::bitcoin::hashes::Hash;
+use gdk_common::bitcoin::key::TapTweak;
+use gdk_common::bitcoin::secp256k1::{Keypair, Message, PublicKey};
+use gdk_common::bitcoin::sighash::{EcdsaSighashType, Prevouts, SighashCache, TapSighashType};
+use gdk_common::bitcoin::{taproot, CompressedPublicKey, NetworkKind, ScriptBuf};
+use gdk_common::elements::sighash::SighashCache as ElementsSighashCache;
+use gdk_common::model::MessageFormat;
+use gdk_common::scripts::{p2pkh_script, ScriptType};
+use gdk_common::util::{asset_blinding_key_from_seed, MasterBlindingKey};
+use gdk_common::{elements, NetworkId, EC};
+use std::convert::TryFrom;
+
+/// The keys of a wallet, identified by their full path from the master key
+pub trait Signer: Send + Sync {
+    /// The extended public key at `path`
+    fn get_xpub(&self, path: &DerivationPath) -> Result<Xpub, Error>;
+
+    /// Sign the inputs of `tx` having a key path in `paths`, spending `prevouts`,
+    /// with their `sighash_types`
+    ///
+    /// Inputs without a sighash type are signed with the default one,
+    /// SIGHASH_ALL for ECDSA and SIGHASH_DEFAULT for taproot key spends.
+    /// Returns the signature of each input, if signed: DER encoded with the
+    /// sighash byte for ECDSA, 64 bytes for taproot key spends, 65 with a
+    /// sighash type other than SIGHASH_DEFAULT.
+    fn sign_tx(
+        &self,
+        tx: &BETransaction,
+        prevouts: &[Option<BETxOut>],
+        paths: &[Option<DerivationPath>],
+        sighash_types: &[Option<u32>],
+    ) -> Result<Vec<Option<Vec<u8>>>, Error>;
+
+    /// Sign the inputs of `tx` spending multisig outputs with the key at
+    /// their path in `paths`, as [`Signer::sign_tx`], the sighashes committing
+    /// to the `witness_scripts` of the outputs
+    ///
+    /// Returns the DER encoded ECDSA signature of each input signed, with the
+    /// sighash byte.
+    fn sign_multisig_tx(
+        &self,
+        _tx: &BETransaction,
+        _prevouts: &[Option<BETxOut>],
+        _paths: &[Option<DerivationPath>],
+        _sighash_types: &[Option<u32>],
+        _witness_scripts: &[Option<ScriptBuf>],
+    ) -> Result<Vec<Option<Vec<u8>>>, Error> {
+        Err(Error::Generic("the signer does not support multisig".into()))
+    }
+
+    /// Sign `message` with the key at `path` of an address of `script_type`,
+    /// returning the base64 encoded signature
+    fn sign_message(
+        &self,
+        path: &DerivationPath,
+        script_type: ScriptType,
+        message: &str,
+        format: MessageFormat,
+    ) -> Result<String, Error>;
+
+    /// The SLIP77 master blinding key, Liquid only
+    fn get_master_blinding_key(&self) -> Result<MasterBlindingKey, Error>;
+
+    /// Show the user the address of `script_type` of the key at `path` on
+    /// `network`, returning it
+    ///
+    /// Devices compute the address on their own, if it differs from the one
+    /// shown by the session the host is compromised.
+    fn display_address(
+        &self,
+        path: &DerivationPath,
+        script_type: ScriptType,
+        network: NetworkId,
+    ) -> Result<String, Error>;
+
+    /// Whether the signer takes part in the anti-exfil protocol, see
+    /// [`crate::anti_exfil`], letting the session check the signature nonces
+    /// don't leak the keys
+    fn anti_exfil(&self) -> bool {
+        false
+    }
+
+    /// First round of the anti-exfil protocol: commit to the nonces of the
+    /// ECDSA signatures of the inputs of `tx` having a host commitment
+    ///
+    /// Returns the nonce point of each of those signatures, before being
+    /// tweaked with the host entropy.
+    fn anti_exfil_commit(
+        &self,
+        _tx: &BETransaction,
+        _prevouts: &[Option<BETxOut>],
+        _paths: &[Option<DerivationPath>],
+        _sighash_types: &[Option<u32>],
+        _host_commitments: &[Option<[u8; 32]>],
+    ) -> Result<Vec<Option<PublicKey>>, Error> {
+        Err(Error::Generic("the signer does not support anti-exfil".into()))
+    }
+
+    /// Second round of the anti-exfil protocol: like [`Signer::sign_tx`],
+    /// with the ECDSA signatures of the inputs having a host entropy made
+    /// with the committed nonces tweaked with it
+    fn anti_exfil_sign_tx(
+        &self,
+        _tx: &BETransaction,
+        _prevouts: &[Option<BETxOut>],
+        _paths: &[Option<DerivationPath>],
+        _sighash_types: &[Option<u32>],
+        _host_entropy: &[Option<[u8; 32]>],
+    ) -> Result<Vec<Option<Vec<u8>>>, Error> {
+        Err(Error::Generic("the signer does not support anti-exfil".into()))
+    }
+}
+
+/// A signer keeping an extended private key in memory
+pub struct SoftwareSigner {
+    xprv: Xpriv,
+
+    /// The path of `xprv` from the master key
+    path: DerivationPath,
+
+    master_blinding_key: Option<MasterBlindingKey>,
+}
+
+impl SoftwareSigner {
+    /// The signer of the wallet of the BIP39 `seed`
+    pub fn from_seed(seed: &[u8], network: NetworkKind) -> Result<Self, Error> {
+        Ok(SoftwareSigner {
+            xprv: Xpriv::new_master(network, seed)?,
+            path: DerivationPath::master(),
+            master_blinding_key: Some(asset_blinding_key_from_seed(seed)),
+        })
+    }
+
+    /// The signer of the keys of an account, whose extended private key at `path` is `xprv`
+    pub fn from_account_xprv(xprv: Xpriv, path: DerivationPath) -> Self {
+        SoftwareSigner {
+            xprv,
+            path,
+            master_blinding_key: None,
+        }
+    }
+
+    fn derive(&self, path: &DerivationPath) -> Result<Xpriv, Error> {
+        let child_path = path
+            .as_ref()
+            .strip_prefix(self.path.as_ref())
+            .ok_or_else(|| Error::Generic(format!("key {} not held by the signer", path)))?;
+        Ok(self.xprv.derive_priv(&EC, &DerivationPath::from(child_path))?)
+    }
+}
+
+impl Signer for SoftwareSigner {
+    fn get_xpub(&self, path: &DerivationPath) -> Result<Xpub, Error> {
+        Ok(Xpub::from_priv(&EC, &self.derive(path)?))
+    }
+
+    fn sign_tx(
+        &self,
+        tx: &BETransaction,
+        prevouts: &[Option<BETxOut>],
+        paths: &[Option<DerivationPath>],
+        sighash_types: &[Option<u32>],
+    ) -> Result<Vec<Option<Vec<u8>>>, Error> {
+        let mut signatures = vec![];
+        for (i, path) in paths.iter().enumerate() {
+            let path = match path {
+                Some(path) => path,
+                None => {
+                    signatures.push(None);
+                    continue;
+                }
+            };
+            let private_key = self.derive(path)?.private_key;
+            let public_key = private_key.public_key(&EC);
+            let sighash_type = sighash_types.get(i).copied().flatten();
+            let signature = match input_message(tx, prevouts, i, &public_key, sighash_type)? {
+                (message, SignatureType::Schnorr(sighash_type)) => {
+                    let keypair = Keypair::from_secret_key(&EC, &private_key).tap_tweak(&EC, None);
+                    taproot::Signature {
+                        signature: EC.sign_schnorr(&message, &keypair.into()),
+                        sighash_type,
+                    }
+                    .to_vec()
+                }
+                (message, SignatureType::Ecdsa(sighash_byte)) => {
+                    let mut signature =
+                        EC.sign_ecdsa(&message, &private_key).serialize_der().to_vec();
+                    signature.push(sighash_byte);
+                    signature
+                }
+            };
+            signatures.push(Some(signature));
+        }
+        Ok(signatures)
+    }
+
+    fn sign_multisig_tx(
+        &self,
+        tx: &BETransaction,
+        prevouts: &[Option<BETxOut>],
+        paths: &[Option<DerivationPath>],
+        sighash_types: &[Option<u32>],
+        witness_scripts: &[Option<ScriptBuf>],
+    ) -> Result<Vec<Option<Vec<u8>>>, Error> {
+        let mut signatures = vec![];
+        for (i, path) in paths.iter().enumerate() {
+            let path = match path {
+                Some(path) => path,
+                None => {
+                    signatures.push(None);
+                    continue;
+                }
+            };
+            let witness_script =
+                witness_scripts.get(i).and_then(Option::as_ref).ok_or_else(|| {
+                    Error::Generic(format!("cannot sign input {}: missing witness script", i))
+                })?;
+            let private_key = self.derive(path)?.private_key;
+            let sighash_type = sighash_types.get(i).copied().flatten();
+            let (message, sighash_byte) =
+                multisig_input_message(tx, prevouts, i, witness_script, sighash_type)?;
+            let mut signature = EC.sign_ecdsa(&message, &private_key).serialize_der().to_vec();
+            signature.push(sighash_byte);
+            signatures.push(Some(signature));
+        }
+        Ok(signatures)
+    }
+
+    fn sign_message(
+        &self,
+        path: &DerivationPath,
+        script_type: ScriptType,
+        message: &str,
+        format: MessageFormat,
+    ) -> Result<String, Error> {
+        let private_key = self.derive(path)?.private_key;
+        message::sign_message(&private_key, script_type, message, format)
+    }
+
+    fn get_master_blinding_key(&self) -> Result<MasterBlindingKey, Error> {
+        self.master_blinding_key.clone().ok_or(Error::MissingMasterBlindingKey)
+    }
+
+    fn display_address(
+        &self,
+        path: &DerivationPath,
+        script_type: ScriptType,
+        network: NetworkId,
+    ) -> Result<String, Error> {
+        // Nothing to display, check the address computed from the keys instead
+        let public_key = CompressedPublicKey(self.derive(path)?.private_key.public_key(&EC));
+        let master_blinding_key = match network {
+            NetworkId::Bitcoin(_) => None,
+            NetworkId::Elements(_) => Some(self.get_master_blinding_key()?),
+        };
+        Ok(key_address(&public_key, script_type, network, master_blinding_key.as_ref())?
+            .to_string())
+    }
+}
+
+/// The signature of an input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SignatureType {
+    /// ECDSA, with the sighash byte appended
+    Ecdsa(u8),
+
+    /// Schnorr of a taproot key spend
+    Schnorr(TapSighashType),
+}
+
+/// The message signed by `public_key` for input `index` of `tx` spending
+/// `prevouts`, with `sighash_type` or the default one, and the signature it
+/// takes
+pub(crate) fn input_message(
+    tx: &BETransaction,
+    prevouts: &[Option<BETxOut>],
+    index: usize,
+    public_key: &PublicKey,
+    sighash_type: Option<u32>,
+) -> Result<(Message, SignatureType), Error> {
+    let missing_prevout =
+        |i| Error::Generic(format!("cannot sign input {}: missing previous output", i));
+    let invalid_sighash = |sighash_type: u32| {
+        Error::Generic(format!("invalid sighash type {:#x} of input {}", sighash_type, index))
+    };
+    let public_key = CompressedPublicKey(*public_key);
+    match tx {
+        BETransaction::Bitcoin(tx) => {
+            let mut cache = SighashCache::new(tx);
+            let prevout = match prevouts.get(index) {
+                Some(Some(BETxOut::Bitcoin(prevout))) => prevout,
+                _ => return Err(missing_prevout(index)),
+            };
+            let script_pubkey = &prevout.script_pubkey;
+            if script_pubkey.is_p2tr() {
+                let sighash_type = match sighash_type {
+                    Some(t) => u8::try_from(t)
+                        .ok()
+                        .and_then(|t| TapSighashType::from_consensus_u8(t).ok())
+                        .ok_or_else(|| invalid_sighash(t))?,
+                    None => TapSighashType::Default,
+                };
+                let all_prevouts;
+                let prevouts = match sighash_type {
+                    TapSighashType::AllPlusAnyoneCanPay
+                    | TapSighashType::NonePlusAnyoneCanPay
+                    | TapSighashType::SinglePlusAnyoneCanPay => Prevouts::One(index, prevout),
+                    _ => {
+                        all_prevouts = prevouts
+                            .iter()
+                            .enumerate()
+                            .map(|(i, prevout)| match prevout {
+                                Some(BETxOut::Bitcoin(prevout)) => Ok(prevout),
+                                _ => Err(missing_prevout(i)),
+                            })
+                            .collect::<Result<Vec<_>, _>>()?;
+                        Prevouts::All(&all_prevouts)
+                    }
+                };
+                let sighash = cache
+                    .taproot_key_spend_signature_hash(index, &prevouts, sighash_type)
+                    .map_err(|e| Error::Generic(e.to_string()))?;
+                let message = Message::from_digest(sighash.to_byte_array());
+                return Ok((message, SignatureType::Schnorr(sighash_type)));
+            }
+            let sighash_type = match sighash_type {
+                Some(t) => EcdsaSighashType::from_standard(t).map_err(|_| invalid_sighash(t))?,
+                None => EcdsaSighashType::All,
+            };
+            let sighash = if script_pubkey.is_p2pkh() {
+                cache
+                    .legacy_signature_hash(index, script_pubkey, sighash_type.to_u32())
+                    .map_err(|e| Error::Generic(e.to_string()))?
+                    .to_byte_array()
+            } else {
+                // p2wpkh, also nested in p2sh
+                let wpkh_script = ScriptBuf::new_p2wpkh(&public_key.wpubkey_hash());
+                cache
+                    .p2wpkh_signature_hash(index, &wpkh_script, prevout.value, sighash_type)
+                    .map_err(|e| Error::Generic(e.to_string()))?
+                    .to_byte_array()
+            };
+            Ok((Message::from_digest(sighash), SignatureType::Ecdsa(sighash_type.to_u32() as u8)))
+        }
+        BETransaction::Elements(tx) => {
+            let mut cache = ElementsSighashCache::new(tx);
+            let prevout = match prevouts.get(index) {
+                Some(Some(BETxOut::Elements(prevout))) => prevout,
+                _ => return Err(missing_prevout(index)),
+            };
+            let sighash_type = match sighash_type {
+                Some(t) => {
+                    elements::EcdsaSighashType::from_standard(t).map_err(|_| invalid_sighash(t))?
+                }
+                None => elements::EcdsaSighashType::All,
+            };
+            let sighash = if prevout.script_pubkey.is_p2pkh() {
+                cache.legacy_sighash(index, &prevout.script_pubkey, sighash_type)
+            } else {
+                let script_code = p2pkh_script(public_key).into_elements();
+                cache.segwitv0_sighash(index, &script_code, prevout.value, sighash_type)
+            };
+            let message = Message::from_digest(sighash.to_byte_array());
+            Ok((message, SignatureType::Ecdsa(sighash_type.as_u32() as u8)))
+        }
+    }
+}
+
+/// The message signed for input `index` of `tx`, spending the p2wsh output
+/// of `witness_script` in `prevouts`, with `sighash_type` or SIGHASH_ALL,
+/// and the sighash byte to append to the signature
+pub(crate) fn multisig_input_message(
+    tx: &BETransaction,
+    prevouts: &[Option<BETxOut>],
+    index: usize,
+    witness_script: &ScriptBuf,
+    sighash_type: Option<u32>,
+) -> Result<(Message, u8), Error> {
+    let (tx, prevout) = match (tx, prevouts.get(index)) {
+        (BETransaction::Bitcoin(tx), Some(Some(BETxOut::Bitcoin(prevout)))) => (tx, prevout),
+        _ => {
+            return Err(Error::Generic(format!(
+                "cannot sign input {}: missing previous output",
+                index
+            )))
+        }
+    };
+    if prevout.script_pubkey != ScriptBuf::new_p2wsh(&witness_script.wscript_hash()) {
+        return Err(Error::Generic(format!(
+            "cannot sign input {}: witness script not matching the previous output",
+            index
+        )));
+    }
+    let sighash_type = match sighash_type {
+        Some(t) => EcdsaSighashType::from_standard(t).map_err(|_| {
+            Error::Generic(format!("invalid sighash type {:#x} of input {}", t, index))
+        })?,
+        None => EcdsaSighashType::All,
+    };
+    let sighash = SighashCache::new(tx)
+        .p2wsh_signature_hash(index, witness_script, prevout.value, sighash_type)
+        .map_err(|e| Error::Generic(e.to_string()))?;
+    Ok((Message::from_digest(sighash.to_byte_array()), sighash_type.to_u32() as u8))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gdk_common::bitcoin;
+    use gdk_common::bitcoin::ecdsa;
+    use gdk_common::bitcoin::secp256k1::schnorr;
+    use gdk_common::bitcoin::{Amount, OutPoint, Transaction, TxIn, TxOut};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_software_signer() {
+        let signer = SoftwareSigner::from_seed(&[1; 32], NetworkKind::Test).unwrap();
+        let account_path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        let path = DerivationPath::from_str("m/84'/1'/0'/0/3").unwrap();
+        let account_xprv =
+            Xpriv::new_master(NetworkKind::Test, &[1; 32]).unwrap().derive_priv(&EC, &account_path);
+        let account_signer =
+            SoftwareSigner::from_account_xprv(account_xprv.unwrap(), account_path.clone());
+        let xpub = signer.get_xpub(&path).unwrap();
+        assert_eq!(account_signer.get_xpub(&path).unwrap(), xpub);
+        assert_eq!(
+            signer.get_xpub(&account_path).unwrap(),
+            account_signer.get_xpub(&account_path).unwrap()
+        );
+        assert!(account_signer
+            .get_xpub(&DerivationPath::from_str("m/44'/1'/0'").unwrap())
+            .is_err());
+        assert!(account_signer.get_master_blinding_key().is_err());
+        signer.get_master_blinding_key().unwrap();
+
+        let public_key = CompressedPublicKey(xpub.public_key);
+        let (output_key, _) = xpub.public_key.x_only_public_key().0.tap_tweak(&EC, None);
+        let prevouts = vec![
+            TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: ScriptBuf::new_p2wpkh(&public_key.wpubkey_hash()),
+            },
+            TxOut {
+                value: Amount::from_sat(20_000),
+                script_pubkey: ScriptBuf::new_p2tr_tweaked(output_key),
+            },
+            TxOut {
+                value: Amount::from_sat(30_000),
+                script_pubkey: ScriptBuf::new_op_return([]),
+            },
+        ];
+        let tx = Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: (0..3)
+                .map(|vout| TxIn {
+                    previous_output: OutPoint::new(bitcoin::Txid::all_zeros(), vout),
+                    ..Default::default()
+                })
+                .collect(),
+            output: vec![prevouts[0].clone()],
+        };
+        let be_tx = BETransaction::Bitcoin(tx.clone());
+        let be_prevouts: Vec<_> =
+            prevouts.iter().cloned().map(BETxOut::Bitcoin).map(Some).collect();
+        let paths = vec![Some(path.clone()), Some(path.clone()), None];
+        let signatures = signer.sign_tx(&be_tx, &be_prevouts, &paths, &[]).unwrap();
+        assert!(signatures[2].is_none());
+
+        let mut cache = SighashCache::new(&tx);
+        let sighash = cache
+            .p2wpkh_signature_hash(
+                0,
+                &prevouts[0].script_pubkey,
+                prevouts[0].value,
+                EcdsaSighashType::All,
+            )
+            .unwrap();
+        let signature = ecdsa::Signature::from_slice(signatures[0].as_ref().unwrap()).unwrap();
+        let message = Message::from_digest(sighash.to_byte_array());
+        EC.verify_ecdsa(&message, &signature.signature, &xpub.public_key).unwrap();
+
+        let sighash = cache
+            .taproot_key_spend_signature_hash(1, &Prevouts::All(&prevouts), TapSighashType::Default)
+            .unwrap();
+        let signature = schnorr::Signature::from_slice(signatures[1].as_ref().unwrap()).unwrap();
+        let message = Message::from_digest(sighash.to_byte_array());
+        EC.verify_schnorr(&signature, &message, &output_key.to_inner()).unwrap();
+
+        // The taproot sighash commits to all the previous outputs
+        let mut missing = be_prevouts.clone();
+        missing[2] = None;
+        assert!(signer.sign_tx(&be_tx, &missing, &paths, &[]).is_err());
+        assert!(signer.sign_tx(&be_tx, &missing, &[Some(path), None, None], &[]).is_ok());
+    }
+}
@@ -0,0 +1,901 @@
+//! A simulated chain, to exercise the wallet flows without a server or funds.
+//!
+//! The sessions of the `"simulation"` server type don't connect anywhere:
+//! their chain, the addresses of their wallet and the outputs funding it are
+//! fabricated from the `simulation_seed` network parameter, so that two
+//! sessions with the same seed see the same wallet. The keys of the addresses
+//! are random keys derived from the seed, not the keys of the wallet, and the
+//! transactions sent are accepted without checking their signatures, as long
+//! as they spend unspent simulated outputs and pay at least the minimum fee.
+//!
+//! Blocks are mined every `simulation_block_interval` seconds if set, and on
+//! each `simulate_mine` call, confirming all the transactions sent.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use gdk_common::amount::{AmountFormatter, Unit};
+use gdk_common::be::{BEBlockHeader, BETxid};
+use gdk_common::bitcoin::bip32::ChildNumber;
+use gdk_common::bitcoin::block::{Header, Version};
+use gdk_common::bitcoin::consensus::encode::{deserialize, serialize_hex};
+use gdk_common::bitcoin::hashes::hex::FromHex;
+use gdk_common::bitcoin::hashes::{sha256, Hash, HashEngine};
+use gdk_common::bitcoin::secp256k1::{PublicKey, SecretKey};
+use gdk_common::bitcoin::{
+    absolute, transaction, Address, Amount, BlockHash, CompactTarget, CompressedPublicKey, Network,
+    OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxMerkleNode, TxOut, Txid, Witness,
+};
+use gdk_common::exchange_rates::{ExchangeRatesCache, ExchangeRatesCacher};
+use gdk_common::model::*;
+use gdk_common::notification::{NativeNotif, TransactionNotification};
+use gdk_common::scripts::ScriptType;
+use gdk_common::session::{JsonError, Session};
+use gdk_common::{log, ureq, NetworkParameters, State, EC};
+use serde_json::Value;
+
+use crate::error::Error;
+use crate::session::{fee_estimate_values, ToJson};
+
+/// The only subaccount of simulated wallets
+const SUBACCOUNT: u32 = 0;
+
+/// The height of the tip of a new simulated chain
+const START_HEIGHT: u32 = 100;
+
+/// The number of outputs funding a new simulated wallet, confirmed in the
+/// blocks before the tip
+const FUNDING_OUTPUTS: u32 = 3;
+
+/// The timestamp of the simulated genesis block, the next ones are 10 minutes apart
+const GENESIS_TIME: u32 = 1_700_000_000;
+
+/// A session on a simulated chain, see the [module documentation](self)
+pub struct SimulationSession {
+    network: NetworkParameters,
+    pub notify: NativeNotif,
+    xr_cache: ExchangeRatesCache,
+    chain: Arc<Mutex<SimulatedChain>>,
+    settings: Settings,
+
+    /// Set to stop the thread mining blocks on a timer
+    stop_miner: Arc<AtomicBool>,
+    miner: Option<thread::JoinHandle<()>>,
+}
+
+/// A wallet output, spent or not
+struct SimulatedOutput {
+    satoshi: u64,
+    is_internal: bool,
+    pointer: u32,
+    script_pubkey: ScriptBuf,
+    spent: bool,
+}
+
+struct SimulatedTx {
+    tx: Transaction,
+    /// None while unconfirmed
+    height: Option<u32>,
+    /// In microseconds
+    created_at: u64,
+}
+
+/// The chain and the wallet of a simulated session
+struct SimulatedChain {
+    seed: sha256::Hash,
+    network: Network,
+    headers: Vec<Header>,
+    txs: HashMap<Txid, SimulatedTx>,
+    outputs: HashMap<OutPoint, SimulatedOutput>,
+    /// The addresses given out, by script
+    scripts: HashMap<ScriptBuf, (bool, u32)>,
+    /// The next pointer of the external and the internal chain
+    next_pointer: [u32; 2],
+}
+
+impl SimulatedChain {
+    fn new(seed: &str, network: Network) -> Self {
+        let mut chain = SimulatedChain {
+            seed: sha256::Hash::hash(seed.as_bytes()),
+            network,
+            headers: vec![],
+            txs: HashMap::new(),
+            outputs: HashMap::new(),
+            scripts: HashMap::new(),
+            next_pointer: [0, 0],
+        };
+        for height in 0..=START_HEIGHT {
+            chain.push_header(height);
+        }
+        for i in 0..FUNDING_OUTPUTS {
+            let pointer = chain.next_address(false);
+            let satoshi = 100_000 * (1 + chain.derive(b"amount", i)[0] as u64 % 10);
+            let previous_output = OutPoint {
+                txid: Txid::from_byte_array(chain.derive(b"funding", i)),
+                vout: 0,
+            };
+            let tx = Transaction {
+                version: transaction::Version::TWO,
+                lock_time: absolute::LockTime::ZERO,
+                input: vec![TxIn {
+                    previous_output,
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::new(),
+                }],
+                output: vec![TxOut {
+                    value: Amount::from_sat(satoshi),
+                    script_pubkey: chain.script_pubkey(false, pointer),
+                }],
+            };
+            let height = START_HEIGHT - FUNDING_OUTPUTS + i;
+            let created_at = block_time(height) as u64 * 1_000_000;
+            chain.add_tx(tx, Some(height), created_at);
+        }
+        chain
+    }
+
+    /// 32 bytes derived from the seed, for the `index` of `purpose`
+    fn derive(&self, purpose: &[u8], index: u32) -> [u8; 32] {
+        let mut engine = sha256::Hash::engine();
+        engine.input(self.seed.as_byte_array());
+        engine.input(purpose);
+        engine.input(&index.to_be_bytes());
+        sha256::Hash::from_engine(engine).to_byte_array()
+    }
+
+    fn public_key(&self, is_internal: bool, pointer: u32) -> CompressedPublicKey {
+        let purpose = if is_internal {
+            b"internal"
+        } else {
+            b"external"
+        };
+        let secret_key = SecretKey::from_slice(&self.derive(purpose, pointer))
+            .expect("a sha256 digest is a valid secret key with overwhelming probability");
+        CompressedPublicKey(PublicKey::from_secret_key(&EC, &secret_key))
+    }
+
+    fn address(&self, is_internal: bool, pointer: u32) -> Address {
+        Address::p2wpkh(&self.public_key(is_internal, pointer), self.network)
+    }
+
+    fn script_pubkey(&self, is_internal: bool, pointer: u32) -> ScriptBuf {
+        self.address(is_internal, pointer).script_pubkey()
+    }
+
+    /// Give out the next address of a chain, returning its pointer
+    fn next_address(&mut self, is_internal: bool) -> u32 {
+        let pointer = self.next_pointer[is_internal as usize];
+        self.next_pointer[is_internal as usize] += 1;
+        self.scripts.insert(self.script_pubkey(is_internal, pointer), (is_internal, pointer));
+        pointer
+    }
+
+    fn push_header(&mut self, height: u32) {
+        let prev_blockhash = self.headers.last().map_or(BlockHash::all_zeros(), Header::block_hash);
+        self.headers.push(Header {
+            version: Version::ONE,
+            prev_blockhash,
+            merkle_root: TxMerkleNode::from_byte_array(self.derive(b"block", height)),
+            time: block_time(height),
+            bits: CompactTarget::from_consensus(0x207fffff),
+            nonce: 0,
+        });
+    }
+
+    fn tip_height(&self) -> u32 {
+        self.headers.len() as u32 - 1
+    }
+
+    fn confirmations(&self, height: Option<u32>) -> u32 {
+        height.map_or(0, |height| self.tip_height() + 1 - height)
+    }
+
+    fn add_tx(&mut self, tx: Transaction, height: Option<u32>, created_at: u64) -> Txid {
+        let txid = tx.compute_txid();
+        for input in tx.input.iter() {
+            if let Some(output) = self.outputs.get_mut(&input.previous_output) {
+                output.spent = true;
+            }
+        }
+        for (vout, output) in tx.output.iter().enumerate() {
+            if let Some(&(is_internal, pointer)) = self.scripts.get(&output.script_pubkey) {
+                let outpoint = OutPoint::new(txid, vout as u32);
+                self.outputs.insert(
+                    outpoint,
+                    SimulatedOutput {
+                        satoshi: output.value.to_sat(),
+                        is_internal,
+                        pointer,
+                        script_pubkey: output.script_pubkey.clone(),
+                        spent: false,
+                    },
+                );
+                // Like a wallet syncing, don't give out an address already paid
+                let next = &mut self.next_pointer[is_internal as usize];
+                *next = (*next).max(pointer + 1);
+            }
+        }
+        self.txs.insert(
+            txid,
+            SimulatedTx {
+                tx,
+                height,
+                created_at,
+            },
+        );
+        txid
+    }
+
+    /// Accept a transaction spending simulated outputs, returning its txid
+    fn send(&mut self, tx: Transaction, min_fee_rate: u64) -> Result<Txid, Error> {
+        let txid = tx.compute_txid();
+        if self.txs.contains_key(&txid) {
+            return Ok(txid);
+        }
+        let mut spent = BTreeSet::new();
+        let mut satoshi_in = 0;
+        for input in tx.input.iter() {
+            match self.outputs.get(&input.previous_output) {
+                Some(output) if !output.spent && spent.insert(input.previous_output) => {
+                    satoshi_in += output.satoshi
+                }
+                _ => {
+                    return Err(Error::TransactionRejected {
+                        reason: RejectReason::MissingInputs,
+                        message: format!("{} is not unspent", input.previous_output),
+                    })
+                }
+            }
+        }
+        let satoshi_out: u64 = tx.output.iter().map(|output| output.value.to_sat()).sum();
+        ensure!(satoshi_out <= satoshi_in, Error::InsufficientFunds);
+        let fee_rate = (satoshi_in - satoshi_out) * 1000 / tx.vsize() as u64;
+        if fee_rate < min_fee_rate {
+            return Err(Error::TransactionRejected {
+                reason: RejectReason::MinRelayFeeNotMet,
+                message: format!("fee rate {} is below {}", fee_rate, min_fee_rate),
+            });
+        }
+        Ok(self.add_tx(tx, None, now_micros()))
+    }
+
+    /// Mine a block confirming the unconfirmed transactions
+    fn mine(&mut self) -> Header {
+        let height = self.tip_height() + 1;
+        self.push_header(height);
+        for tx in self.txs.values_mut().filter(|tx| tx.height.is_none()) {
+            tx.height = Some(height);
+        }
+        *self.headers.last().expect("just pushed")
+    }
+
+    fn unspent(&self, num_confs: u32) -> impl Iterator<Item = (&OutPoint, &SimulatedOutput)> {
+        self.outputs.iter().filter(move |(outpoint, output)| {
+            !output.spent && self.confirmations(self.txs[&outpoint.txid].height) >= num_confs
+        })
+    }
+
+    /// The satoshi received and sent by the wallet in `tx`
+    fn wallet_amounts(&self, tx: &Transaction) -> (u64, u64) {
+        let received = tx
+            .output
+            .iter()
+            .filter(|output| self.scripts.contains_key(&output.script_pubkey))
+            .map(|output| output.value.to_sat())
+            .sum();
+        let sent = tx
+            .input
+            .iter()
+            .filter_map(|input| self.outputs.get(&input.previous_output))
+            .map(|output| output.satoshi)
+            .sum();
+        (received, sent)
+    }
+
+    fn tx_type(&self, tx: &Transaction, sent: u64) -> TransactionType {
+        if sent == 0 {
+            TransactionType::Incoming
+        } else if tx.output.iter().all(|output| self.scripts.contains_key(&output.script_pubkey)) {
+            TransactionType::Redeposit
+        } else {
+            TransactionType::Outgoing
+        }
+    }
+
+    fn in_out(&self, is_output: bool, pt_idx: u32, satoshi: u64, script: &ScriptBuf) -> GetTxInOut {
+        let relevant = self.scripts.get(script);
+        GetTxInOut {
+            address: Address::from_script(script, self.network)
+                .map(|address| address.to_string())
+                .unwrap_or_default(),
+            address_type: relevant.map(|_| ScriptType::P2wpkh.to_string()).unwrap_or_default(),
+            is_output,
+            is_relevant: relevant.is_some(),
+            is_spent: false,
+            subaccount: SUBACCOUNT,
+            is_internal: relevant.is_some_and(|(is_internal, _)| *is_internal),
+            pointer: relevant.map_or(0, |(_, pointer)| *pointer),
+            pt_idx,
+            satoshi,
+            subtype: 0,
+            asset_id: None,
+            asset_blinder: None,
+            amount_blinder: None,
+            is_blinded: None,
+            is_confidential: None,
+            blinding_key: None,
+            unconfidential_address: None,
+            script_pubkey: script.to_hex_string(),
+            label: None,
+        }
+    }
+
+    fn tx_list_item(&self, txid: &Txid, formatter: &AmountFormatter) -> TxListItem {
+        let SimulatedTx {
+            tx,
+            height,
+            created_at,
+        } = &self.txs[txid];
+        let (received, sent) = self.wallet_amounts(tx);
+        let satoshi_out: u64 = tx.output.iter().map(|output| output.value.to_sat()).sum();
+        let fee = sent.saturating_sub(satoshi_out);
+        let satoshi: Balances = [("btc".to_string(), received as i64 - sent as i64)].into();
+        let inputs = tx
+            .input
+            .iter()
+            .enumerate()
+            .map(|(vin, input)| match self.outputs.get(&input.previous_output) {
+                Some(output) => {
+                    self.in_out(false, vin as u32, output.satoshi, &output.script_pubkey)
+                }
+                None => self.in_out(false, vin as u32, 0, &ScriptBuf::new()),
+            })
+            .collect();
+        let outputs = tx
+            .output
+            .iter()
+            .enumerate()
+            .map(|(vout, output)| {
+                self.in_out(true, vout as u32, output.value.to_sat(), &output.script_pubkey)
+            })
+            .collect();
+        TxListItem {
+            block_height: height.unwrap_or(0),
+            created_at_ts: *created_at,
+            type_: self.tx_type(tx, sent),
+            txhash: txid.to_string(),
+            amounts: formatter.format_balances(&satoshi),
+            satoshi,
+            spv_verified: "disabled".to_string(),
+            fee,
+            fee_amount: formatter.format(fee as i64, "btc"),
+            fee_rate: fee * 1000 / tx.vsize() as u64,
+            inputs,
+            outputs,
+            transaction_size: tx.total_size(),
+            transaction_vsize: tx.vsize(),
+            transaction_weight: tx.weight().to_wu() as usize,
+            block_hash: height.map(|h| self.headers[h as usize].block_hash().to_string()),
+            block_time: height.map(block_time),
+            ..Default::default()
+        }
+    }
+}
+
+fn block_time(height: u32) -> u32 {
+    GENESIS_TIME + height * 600
+}
+
+fn now_micros() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_micros() as u64)
+}
+
+impl ExchangeRatesCacher for SimulationSession {
+    fn xr_cache(&self) -> ExchangeRatesCache {
+        Arc::clone(&self.xr_cache)
+    }
+}
+
+impl Session for SimulationSession {
+    fn new(network_parameters: NetworkParameters) -> Result<Self, JsonError> {
+        network_parameters.validate().map_err(Error::from)?;
+        let network = match network_parameters.id().get_bitcoin_network() {
+            Some(network) if network != Network::Bitcoin => network,
+            _ => {
+                return Err(
+                    Error::Generic("only bitcoin test networks can be simulated".into()).into()
+                )
+            }
+        };
+        let seed = network_parameters.simulation_seed.as_deref().unwrap_or_default();
+        let mut notify = NativeNotif::new();
+        if let Some(queue_size) = network_parameters.notification_queue_size {
+            notify.set_queue_size(queue_size);
+        }
+        Ok(Self {
+            chain: Arc::new(Mutex::new(SimulatedChain::new(seed, network))),
+            network: network_parameters,
+            notify,
+            xr_cache: ExchangeRatesCache::default(),
+            settings: Settings::default(),
+            stop_miner: Arc::new(AtomicBool::new(false)),
+            miner: None,
+        })
+    }
+
+    fn native_notification(&mut self) -> &mut NativeNotif {
+        &mut self.notify
+    }
+
+    fn network_parameters(&self) -> &NetworkParameters {
+        &self.network
+    }
+
+    fn build_request_agent(&self) -> Result<ureq::Agent, ureq::Error> {
+        gdk_common::network::build_request_agent(None)
+    }
+
+    fn handle_call(&mut self, method: &str, input: Value) -> Result<Value, JsonError> {
+        match method {
+            "connect" => self.connect().to_json(),
+            "disconnect" => self.disconnect().to_json(),
+
+            // There is nothing to load nor to sync
+            "load_store" | "set_fingerprint" | "start_threads" | "remove_account" => {
+                Ok(Value::Null)
+            }
+
+            "get_block_height" => self.get_block_height().to_json(),
+            "simulate_mine" => self.simulate_mine().to_json(),
+
+            "get_subaccount_nums" => Ok(json!([SUBACCOUNT])),
+            "get_subaccounts" => self.get_subaccounts().to_json(),
+            "create_subaccount" => {
+                let opt: CreateAccountOpt = serde_json::from_value(input)?;
+                self.create_subaccount(&opt).to_json()
+            }
+
+            "get_receive_address" => {
+                self.get_receive_address(&serde_json::from_value(input)?).to_json()
+            }
+            "get_transactions" => self.get_transactions(&serde_json::from_value(input)?).to_json(),
+            "get_transaction_hex" => self
+                .get_transaction_hex(input.as_str().ok_or_else(|| {
+                    Error::Generic("get_transaction_hex: input is not a string".into())
+                })?)
+                .to_json(),
+            "get_unspent_outputs" => {
+                self.get_unspent_outputs(&serde_json::from_value(input)?).to_json()
+            }
+            "get_balance" => self.get_balance(&serde_json::from_value(input)?).to_json(),
+
+            "broadcast_transaction" => self
+                .broadcast_transaction(input.as_str().ok_or_else(|| {
+                    Error::Generic("broadcast_transaction: input not a string".into())
+                })?)
+                .to_json(),
+            "send_transaction" => self.send_transaction(&serde_json::from_value(input)?).to_json(),
+
+            "get_fee_estimates" => fee_estimate_values(&self.get_fee_estimates()),
+            "get_min_fee_rate" => Ok(json!(self.network.policy().min_fee_rate)),
+
+            "get_settings" => Ok(serde_json::to_value(&self.settings)?),
+            "change_settings" => self.change_settings(&input).to_json(),
+
+            _ => Err(Error::MethodNotFound {
+                method: method.to_string(),
+                in_session: true,
+            })
+            .map_err(Into::into),
+        }
+    }
+}
+
+impl SimulationSession {
+    /// Start mining blocks every `simulation_block_interval` seconds, if set
+    pub fn connect(&mut self) -> Result<(), Error> {
+        self.notify.network(State::Connected, State::Connected);
+        let interval = match self.network.simulation_block_interval {
+            Some(seconds) if seconds > 0 && self.miner.is_none() => {
+                Duration::from_secs(seconds as u64)
+            }
+            _ => return Ok(()),
+        };
+        self.stop_miner.store(false, Ordering::Relaxed);
+        let chain = self.chain.clone();
+        let notify = self.notify.clone();
+        let stop = self.stop_miner.clone();
+        self.miner = Some(thread::spawn(move || {
+            let mut last_block = Instant::now();
+            while !stop.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(100));
+                if last_block.elapsed() >= interval {
+                    last_block = Instant::now();
+                    let (height, header) = match chain.lock() {
+                        Ok(mut chain) => (chain.tip_height() + 1, chain.mine()),
+                        Err(_) => return,
+                    };
+                    log::info!("simulated block {} mined", height);
+                    notify.block(height, &BEBlockHeader::Bitcoin(header), false, None);
+                }
+            }
+        }));
+        Ok(())
+    }
+
+    pub fn disconnect(&mut self) -> Result<(), Error> {
+        self.stop_miner.store(true, Ordering::Relaxed);
+        if let Some(miner) = self.miner.take() {
+            miner.join().map_err(|_| Error::Generic("the simulated miner panicked".into()))?;
+        }
+        self.notify.network(State::Disconnected, State::Disconnected);
+        Ok(())
+    }
+
+    fn chain(&self) -> Result<std::sync::MutexGuard<SimulatedChain>, Error> {
+        self.chain.lock().map_err(|_| Error::Generic("simulated chain poisoned".into()))
+    }
+
+    pub fn get_block_height(&self) -> Result<u32, Error> {
+        Ok(self.chain()?.tip_height())
+    }
+
+    /// Mine a block confirming all the unconfirmed transactions, returning its height
+    pub fn simulate_mine(&self) -> Result<u32, Error> {
+        let (height, header) = {
+            let mut chain = self.chain()?;
+            let header = chain.mine();
+            (chain.tip_height(), header)
+        };
+        self.notify.block(height, &BEBlockHeader::Bitcoin(header), false, None);
+        Ok(height)
+    }
+
+    fn account_info(&self) -> AccountInfo {
+        AccountInfo {
+            account_num: SUBACCOUNT,
+            script_type: ScriptType::P2wpkh,
+            settings: AccountSettings {
+                name: "Simulated".to_string(),
+                ..Default::default()
+            },
+            required_ca: 0,
+            receiving_id: "".to_string(),
+            bip44_discovered: true,
+            user_path: account_path(),
+            core_descriptors: vec![],
+            slip132_extended_pubkey: None,
+        }
+    }
+
+    pub fn get_subaccounts(&self) -> Result<Vec<AccountInfo>, Error> {
+        Ok(vec![self.account_info()])
+    }
+
+    /// The simulated subaccount, the only one that can be created
+    pub fn create_subaccount(&self, opt: &CreateAccountOpt) -> Result<AccountInfo, Error> {
+        ensure!(
+            opt.subaccount == SUBACCOUNT,
+            Error::Generic("simulated wallets have a single subaccount".into())
+        );
+        Ok(self.account_info())
+    }
+
+    fn check_subaccount(&self, subaccount: u32) -> Result<(), Error> {
+        ensure!(subaccount == SUBACCOUNT, Error::InvalidSubaccount(subaccount));
+        Ok(())
+    }
+
+    pub fn get_receive_address(&self, opt: &GetAddressOpt) -> Result<AddressPointer, Error> {
+        self.check_subaccount(opt.subaccount)?;
+        let is_internal = opt.is_internal.unwrap_or(false);
+        let mut chain = self.chain()?;
+        let pointer = chain.next_address(is_internal);
+        let address = chain.address(is_internal, pointer);
+        Ok(AddressPointer {
+            subaccount: SUBACCOUNT,
+            address_type: ScriptType::P2wpkh.to_string(),
+            script_pubkey: address.script_pubkey().to_hex_string(),
+            address: address.to_string(),
+            blinding_key: None,
+            pointer,
+            user_path: user_path(is_internal, pointer),
+            is_internal,
+            is_confidential: None,
+            unconfidential_address: None,
+            device_confirmed: false,
+        })
+    }
+
+    fn amount_formatter(&self) -> AmountFormatter {
+        let unit = self.settings.unit.parse().unwrap_or(Unit::Btc);
+        AmountFormatter::new(unit, "btc", HashMap::new())
+    }
+
+    /// The transactions of the wallet, most recent first
+    pub fn get_transactions(&self, opt: &GetTransactionsOpt) -> Result<TxsResult, Error> {
+        self.check_subaccount(opt.subaccount)?;
+        let chain = self.chain()?;
+        let mut txids: Vec<_> = chain.txs.keys().collect();
+        txids.sort_by_key(|txid| {
+            let tx = &chain.txs[*txid];
+            std::cmp::Reverse((tx.height.unwrap_or(u32::MAX), tx.created_at))
+        });
+        let formatter = self.amount_formatter();
+        let transactions = txids
+            .iter()
+            .skip(opt.first)
+            .take(opt.count)
+            .map(|txid| chain.tx_list_item(txid, &formatter))
+            .collect();
+        Ok(TxsResult {
+            transactions,
+            total: txids.len(),
+            sync_generation: chain.tip_height() as u64,
+        })
+    }
+
+    pub fn get_transaction_hex(&self, txid: &str) -> Result<String, Error> {
+        let txid = BETxid::from_hex(txid, self.network.id())?;
+        let chain = self.chain()?;
+        let tx = chain.txs.get(&txid.into_bitcoin()).ok_or_else(|| Error::TxNotFound(txid))?;
+        Ok(serialize_hex(&tx.tx))
+    }
+
+    pub fn get_unspent_outputs(&self, opt: &GetUnspentOpt) -> Result<GetUnspentOutputs, Error> {
+        self.check_subaccount(opt.subaccount)?;
+        let chain = self.chain()?;
+        let formatter = self.amount_formatter();
+        let mut utxos: Vec<_> = chain
+            .unspent(opt.num_confs.unwrap_or(0))
+            .map(|(outpoint, output)| UnspentOutput {
+                address_type: ScriptType::P2wpkh.to_string(),
+                block_height: chain.txs[&outpoint.txid].height.unwrap_or(0),
+                pointer: output.pointer,
+                pt_idx: outpoint.vout,
+                satoshi: output.satoshi,
+                amount: formatter.format(output.satoshi as i64, "btc"),
+                subaccount: SUBACCOUNT,
+                txhash: outpoint.txid.to_string(),
+                is_internal: output.is_internal,
+                user_path: user_path(output.is_internal, output.pointer),
+                script_code: output.script_pubkey.to_hex_string(),
+                public_key: chain.public_key(output.is_internal, output.pointer).to_string(),
+                ..Default::default()
+            })
+            .collect();
+        utxos.sort_by(|a, b| (&a.txhash, a.pt_idx).cmp(&(&b.txhash, b.pt_idx)));
+        Ok(GetUnspentOutputs([("btc".to_string(), utxos)].into()))
+    }
+
+    pub fn get_balance(&self, opt: &GetBalanceOpt) -> Result<Balances, Error> {
+        self.check_subaccount(opt.subaccount)?;
+        let chain = self.chain()?;
+        let num_confs = opt.num_confs.unwrap_or(0);
+        let satoshi: u64 = chain.unspent(num_confs).map(|(_, output)| output.satoshi).sum();
+        Ok([("btc".to_string(), satoshi as i64)].into())
+    }
+
+    /// Append the transaction to the simulated mempool, without checking its
+    /// signatures, returning its txid
+    pub fn broadcast_transaction(&self, tx_hex: &str) -> Result<String, Error> {
+        let bytes = Vec::<u8>::from_hex(tx_hex)
+            .map_err(|_| Error::Generic("the transaction is not valid hex".into()))?;
+        let tx: Transaction = deserialize(&bytes)
+            .map_err(|e| Error::Generic(format!("invalid transaction: {}", e)))?;
+        let (txid, received, sent, type_) = {
+            let mut chain = self.chain()?;
+            let txid = chain.send(tx, self.network.policy().min_fee_rate)?;
+            let tx = &chain.txs[&txid].tx;
+            let (received, sent) = chain.wallet_amounts(tx);
+            (txid, received, sent, chain.tx_type(tx, sent))
+        };
+        let delta = received as i64 - sent as i64;
+        self.notify.updated_txs(&TransactionNotification {
+            subaccounts: vec![SUBACCOUNT],
+            txid,
+            satoshi: Some(delta.unsigned_abs()),
+            type_: Some(type_),
+            block_height: None,
+            satoshi_delta: Some([("btc".to_string(), delta)].into()),
+            removed: false,
+        });
+        Ok(txid.to_string())
+    }
+
+    /// Broadcast the transaction, the payjoin and the spend limits are not
+    /// simulated
+    pub fn send_transaction(
+        &self,
+        opt: &SendTransactionOpt,
+    ) -> Result<SendTransactionResult, Error> {
+        Ok(SendTransactionResult {
+            txhash: self.broadcast_transaction(&opt.transaction)?,
+            payjoin: false,
+            payjoin_error: None,
+            broadcast: Some(BroadcastDetails {
+                transaction: opt.transaction.clone(),
+                broadcast_at: now_micros() / 1_000_000,
+                server: "simulation".to_string(),
+                in_mempool: Some(true),
+            }),
+        })
+    }
+
+    /// Fee rates decreasing with the blocks to confirm, the first one being
+    /// the minimum fee rate
+    pub fn get_fee_estimates(&self) -> FeeEstimates {
+        let min_fee_rate = self.network.policy().min_fee_rate;
+        let fees = (0..25u64)
+            .map(|blocks| match blocks {
+                0 => FeeEstimate(min_fee_rate),
+                _ => FeeEstimate((min_fee_rate * 20 / blocks).max(min_fee_rate)),
+            })
+            .collect();
+        FeeEstimates {
+            fees,
+            stale: false,
+            fetched_at: Some(now_micros() / 1_000_000),
+        }
+    }
+
+    pub fn change_settings(&mut self, value: &Value) -> Result<(), Error> {
+        self.settings.update(value)?;
+        self.settings.revision = Some(self.settings.revision() + 1);
+        self.notify.settings(&self.settings);
+        Ok(())
+    }
+}
+
+impl Drop for SimulationSession {
+    fn drop(&mut self) {
+        self.stop_miner.store(true, Ordering::Relaxed);
+        if let Some(miner) = self.miner.take() {
+            let _ = miner.join();
+        }
+    }
+}
+
+fn account_path() -> Vec<ChildNumber> {
+    vec![
+        ChildNumber::Hardened {
+            index: 84,
+        },
+        ChildNumber::Hardened {
+            index: 1,
+        },
+        ChildNumber::Hardened {
+            index: SUBACCOUNT,
+        },
+    ]
+}
+
+fn user_path(is_internal: bool, pointer: u32) -> Vec<ChildNumber> {
+    let mut path = account_path();
+    path.push(ChildNumber::Normal {
+        index: is_internal as u32,
+    });
+    path.push(ChildNumber::Normal {
+        index: pointer,
+    });
+    path
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gdk_common::bitcoin::WScriptHash;
+
+    fn session(seed: &str) -> SimulationSession {
+        let network = NetworkParameters {
+            development: true,
+            simulation_seed: Some(seed.to_string()),
+            ..Default::default()
+        };
+        SimulationSession::new(network).unwrap()
+    }
+
+    fn balance(session: &mut SimulationSession, num_confs: u32) -> i64 {
+        let opt = json!({"subaccount": 0, "num_confs": num_confs});
+        session.handle_call("get_balance", opt).unwrap()["btc"].as_i64().unwrap()
+    }
+
+    #[test]
+    fn test_simulation() {
+        let mut session = session("ui");
+        assert_eq!(session.handle_call("get_block_height", Value::Null).unwrap(), START_HEIGHT);
+        let initial = balance(&mut session, 1);
+        assert!(initial > 0);
+        assert_eq!(balance(&mut self::session("ui"), 1), initial);
+
+        // Spend all the funding outputs, with change back to the wallet
+        let utxos =
+            session.get_unspent_outputs(&GetUnspentOpt::default()).unwrap().0["btc"].clone();
+        assert_eq!(utxos.len(), FUNDING_OUTPUTS as usize);
+        let opt = json!({"subaccount": 0, "is_internal": true});
+        let change = session.handle_call("get_receive_address", opt).unwrap();
+        let change = ScriptBuf::from_hex(change["scriptpubkey"].as_str().unwrap()).unwrap();
+        let (sent, fee) = (50_000, 2_000);
+        let tx = |fee: u64| Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: utxos
+                .iter()
+                .map(|utxo| TxIn {
+                    previous_output: OutPoint::new(utxo.txhash.parse().unwrap(), utxo.pt_idx),
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: vec![
+                TxOut {
+                    value: Amount::from_sat(sent),
+                    script_pubkey: ScriptBuf::new_p2wsh(&WScriptHash::all_zeros()),
+                },
+                TxOut {
+                    value: Amount::from_sat(initial as u64 - sent - fee),
+                    script_pubkey: change.clone(),
+                },
+            ],
+        };
+        let err =
+            session.handle_call("send_transaction", json!({"transaction": serialize_hex(&tx(0))}));
+        assert_eq!(err.unwrap_err().error, "id_fee_rate_is_below_minimum");
+        let opt = json!({ "transaction": serialize_hex(&tx(fee)) });
+        let result = session.handle_call("send_transaction", opt).unwrap();
+        let txid = result["txhash"].as_str().unwrap().to_string();
+        assert_eq!(result["broadcast"]["transaction"], serialize_hex(&tx(fee)));
+
+        // The change is unconfirmed, the funding outputs can't be spent again
+        assert_eq!(balance(&mut session, 0), initial - (sent + fee) as i64);
+        assert_eq!(balance(&mut session, 1), 0);
+        let opt = json!({ "transaction": serialize_hex(&tx(fee + 1)) });
+        let err = session.handle_call("broadcast_transaction", opt["transaction"].clone());
+        assert_eq!(err.unwrap_err().error, "id_transaction_rejected");
+
+        let height = session.handle_call("simulate_mine", Value::Null).unwrap();
+        assert_eq!(height, START_HEIGHT + 1);
+        assert_eq!(balance(&mut session, 1), initial - (sent + fee) as i64);
+
+        let opt = json!({"subaccount": 0, "first": 0, "count": 10});
+        let txs: TxsResult =
+            serde_json::from_value(session.handle_call("get_transactions", opt).unwrap()).unwrap();
+        assert_eq!(txs.total, FUNDING_OUTPUTS as usize + 1);
+        let last = &txs.transactions[0];
+        assert_eq!(last.txhash, txid);
+        assert_eq!(last.type_, TransactionType::Outgoing);
+        assert_eq!(last.satoshi["btc"], -((sent + fee) as i64));
+        assert_eq!((last.fee, last.block_height), (fee, START_HEIGHT + 1));
+        assert!(txs.transactions[1..].iter().all(|tx| tx.type_ == TransactionType::Incoming));
+        let hex = session.handle_call("get_transaction_hex", json!(txid)).unwrap();
+        assert_eq!(hex, serialize_hex(&tx(fee)));
+    }
+
+    #[test]
+    fn test_simulation_timer() {
+        let network = NetworkParameters {
+            development: true,
+            simulation_block_interval: Some(1),
+            ..Default::default()
+        };
+        let mut session = SimulationSession::new(network).unwrap();
+        session.handle_call("connect", json!({})).unwrap();
+        thread::sleep(Duration::from_millis(2_500));
+        session.handle_call("disconnect", Value::Null).unwrap();
+        let height = session.get_block_height().unwrap();
+        assert!(height >= START_HEIGHT + 1, "no block mined");
+        thread::sleep(Duration::from_millis(1_500));
+        assert_eq!(session.get_block_height().unwrap(), height);
+
+        let network = NetworkParameters {
+            mainnet: true,
+            ..Default::default()
+        };
+        assert!(SimulationSession::new(network).is_err());
+    }
+}
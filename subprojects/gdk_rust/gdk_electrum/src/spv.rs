@@ -1,4 +1,3 @@
-use bitcoin_29::blockdata::constants::max_target;
 use bitcoin_29::util::uint::Uint256;
 use bitcoin_29::util::BitArray;
 use gdk_common::log::warn;
@@ -17,6 +16,7 @@ use gdk_common::network::NetworkParameters;
 use crate::error::Error;
 use crate::headers::bitcoin::HeadersChain;
 use crate::interface::ElectrumUrl;
+use crate::resolver::Resolver;
 use crate::session::determine_electrum_url;
 
 const INIT_CHUNK_SIZE: u32 = 5;
@@ -28,6 +28,7 @@ const SERVERS_PER_ROUND: usize = 3;
 pub struct SpvCrossValidator {
     servers: Vec<ElectrumUrl>,
     proxy: Option<String>,
+    resolver: Resolver,
     last_result: CrossValidationResult,
     timeout: Option<u8>,
 }
@@ -104,6 +105,7 @@ impl SpvCrossValidator {
                 server_url,
                 self.timeout,
                 &self.proxy,
+                &self.resolver,
             ) {
                 Ok(r) => r,
                 Err(e) => {
@@ -138,6 +140,7 @@ impl SpvCrossValidator {
                 servers: get_cross_servers(network)?,
                 last_result: CrossValidationResult::Valid,
                 proxy: proxy.clone(),
+                resolver: Resolver::new(network),
                 timeout,
             })
         } else {
@@ -158,8 +161,9 @@ pub fn spv_cross_validate(
     server_url: &ElectrumUrl,
     timeout: Option<u8>,
     proxy: &Option<String>,
+    resolver: &Resolver,
 ) -> Result<CrossValidationResult, CrossValidationError> {
-    let client = server_url.build_client(proxy.as_deref(), timeout)?;
+    let client = server_url.build_client(proxy.as_deref(), timeout, resolver)?;
     let remote_tip = client.block_headers_subscribe()?;
     let remote_tip_hash = remote_tip.header.block_hash();
     let remote_tip_height = remote_tip.height as u32;
@@ -324,7 +328,13 @@ fn get_fork_branch(
                     retarget.2 = Some(header.clone());
                 } else if is_period_first {
                     let (_, retarget_block, period_last) = retarget;
-                    verify_retarget(&retarget_block, &header, &period_last.unwrap())?; // period_last must exists if we got here
+                    // period_last must exists if we got here
+                    verify_retarget(
+                        &retarget_block,
+                        &header,
+                        &period_last.unwrap(),
+                        chain.network,
+                    )?;
                     curr_retarget = None;
                 }
             }
@@ -347,7 +357,7 @@ fn get_fork_branch(
     if let Some((retarget_height, retarget_block, period_last)) = curr_retarget {
         let period_first = chain.get(retarget_height - DIFFCHANGE_INTERVAL)?;
         let period_last = period_last.map_or_else(|| chain.get(retarget_height - 1), Ok)?;
-        verify_retarget(&retarget_block, &period_first, &period_last)?;
+        verify_retarget(&retarget_block, &period_first, &period_last, chain.network)?;
     }
 
     Ok(ForkBranch {
@@ -361,8 +371,9 @@ fn verify_retarget(
     retarget_block: &block::Header,
     period_first: &block::Header,
     period_last: &block::Header,
+    network: bitcoin::Network,
 ) -> Result<(), CrossValidationError> {
-    let expected_target = calc_difficulty_retarget(period_first, period_last);
+    let expected_target = calc_difficulty_retarget(period_first, period_last, network);
     ensure!(
         retarget_block.bits
             == CompactTarget::from_consensus(bitcoin_29::BlockHeader::compact_target_from_u256(
@@ -373,7 +384,11 @@ fn verify_retarget(
     Ok(())
 }
 
-pub fn calc_difficulty_retarget(first: &block::Header, last: &block::Header) -> Uint256 {
+pub fn calc_difficulty_retarget(
+    first: &block::Header,
+    last: &block::Header,
+    network: bitcoin::Network,
+) -> Uint256 {
     let timespan = last.time - first.time;
     let timespan = timespan.min(DIFFCHANGE_TIMESPAN * 4);
     let timespan = timespan.max(DIFFCHANGE_TIMESPAN / 4);
@@ -381,7 +396,8 @@ pub fn calc_difficulty_retarget(first: &block::Header, last: &block::Header) ->
     let new_target = last.target().as_uint256() * Uint256::from_u64(timespan as u64).unwrap()
         / Uint256::from_u64(DIFFCHANGE_TIMESPAN as u64).unwrap();
 
-    new_target.min(max_target(bitcoin_29::Network::Bitcoin))
+    // signet has an easier proof-of-work limit than mainnet and testnet
+    new_target.min(network.params().max_attainable_target.as_uint256())
 }
 
 impl CrossValidationInvalid {
@@ -427,6 +443,9 @@ static SERVER_LIST_MAINNET: Lazy<Vec<ElectrumUrl>> =
 static SERVER_LIST_TESTNET: Lazy<Vec<ElectrumUrl>> =
     Lazy::new(|| parse_server_file(include_str!("servers-testnet.txt")));
 
+static SERVER_LIST_SIGNET: Lazy<Vec<ElectrumUrl>> =
+    Lazy::new(|| parse_server_file(include_str!("servers-signet.txt")));
+
 fn parse_server_file(sl: &str) -> Vec<ElectrumUrl> {
     sl.lines().map(FromStr::from_str).collect::<Result<_, _>>().unwrap()
 }
@@ -443,7 +462,8 @@ pub fn get_cross_servers(network: &NetworkParameters) -> Result<Vec<ElectrumUrl>
             let mut servers = match net {
                 bitcoin::Network::Bitcoin => SERVER_LIST_MAINNET.clone(),
                 bitcoin::Network::Testnet => SERVER_LIST_TESTNET.clone(),
-                bitcoin::Network::Regtest | bitcoin::Network::Signet => vec![],
+                bitcoin::Network::Signet => SERVER_LIST_SIGNET.clone(),
+                bitcoin::Network::Regtest => vec![],
                 _ => panic!("unknown network"),
             };
             // Filter the default cross validation servers list.
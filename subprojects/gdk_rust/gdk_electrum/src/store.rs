@@ -1,10 +1,11 @@
 use crate::account::xpubs_equivalent;
 use crate::spv::CrossValidationResult;
-use crate::{Error, ScriptStatuses};
+use crate::{unblind_outputs, Error, ScriptStatuses};
 use gdk_common::aes::Aes256GcmSiv;
 use gdk_common::be::BETxidConvert;
 use gdk_common::be::{
-    BEBlockHash, BEBlockHeader, BEScript, BETransactionEntry, BETransactions, BETxid,
+    BEBlockHash, BEBlockHeader, BEOutPoint, BEScript, BETransaction, BETransactionEntry,
+    BETransactions, BETxid,
 };
 use gdk_common::bitcoin::bip32::{DerivationPath, Xpub};
 use gdk_common::bitcoin::hashes::{sha256, Hash};
@@ -12,21 +13,25 @@ use gdk_common::bitcoin::Txid;
 use gdk_common::elements;
 use gdk_common::elements::TxOutSecrets;
 use gdk_common::log::{info, log, Level};
-use gdk_common::model::{AccountSettings, FeeEstimate, SPVVerifyTxResult, Settings};
+use gdk_common::model::{
+    AccountSettings, BroadcastDetails, ElectrumServer, ExpectedPayment, FeeEstimate,
+    FiatAtConfirmation, PaymentStatus, PendingBroadcast, SPVVerifyTxResult, Settings, TxRemoval,
+    TxReorgs, WalletBirthday, WalletMigrationPlan,
+};
 use gdk_common::serde_cbor;
 use gdk_common::store::{Decryptable, Encryptable};
 use gdk_common::util::MasterBlindingKey;
-use gdk_common::NetworkId;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
 use std::fmt::{Display, Formatter};
 use std::fs::File;
 use std::io::Write;
-use std::ops::{Index, IndexMut};
+use std::ops::{Deref, DerefMut, Index, IndexMut};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, RwLock};
-use std::time::Instant;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Instant, SystemTime};
 
 pub type Store = Arc<RwLock<StoreMeta>>;
 
@@ -71,12 +76,31 @@ pub struct RawCache {
 
     /// The master blinding key, available only in liquid
     pub master_blinding: Option<MasterBlindingKey>,
+
+    /// The version of the script statuses of the accounts, see
+    /// [`SCRIPT_STATUSES_VERSION`]
+    pub script_statuses_version: Option<u32>,
+
+    /// The version of the unblinded outputs of the accounts, see
+    /// [`UNBLINDED_VERSION`]
+    ///
+    /// NOTE: this is Option to keep cache backwards-compatibility, remove if breaking cache
+    pub unblinded_version: Option<u32>,
+
+    /// Unix time in seconds of when `fee_estimates` were got from the server
+    pub fee_estimates_at: Option<u64>,
+
+    /// Transactions not of the wallet fetched from the server and kept at the
+    /// request of the caller, never part of the wallet history
+    ///
+    /// NOTE: is Option to keep cache backwards-compatibility, remove if breaking cache
+    pub foreign_txs: Option<BETransactions>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct RawAccountCache {
-    /// contains all my tx and all prevouts
-    pub all_txs: BETransactions,
+    /// contains all my tx and all prevouts, loaded on first access
+    pub all_txs: LazyTxs,
 
     /// contains all my script up to an empty batch of BATCHSIZE
     pub paths: HashMap<BEScript, DerivationPath>,
@@ -116,6 +140,206 @@ pub struct RawAccountCache {
     ///
     /// NOTE: this is Option to keep cache backwards-compatibility, remove if breaking cache
     pub count_given: Option<Indexes>,
+
+    /// Sync generations of the account transactions, see [`TxGenerations`]
+    ///
+    /// NOTE: this is Option to keep cache backwards-compatibility, remove if breaking cache
+    pub tx_generations: Option<TxGenerations>,
+
+    /// Running balances of the account, updated by the syncs, see [`UtxoBalances`]
+    ///
+    /// NOTE: this is Option to keep cache backwards-compatibility, remove if breaking cache
+    pub utxo_balances: Option<UtxoBalances>,
+
+    /// Transactions no longer returned by the server, kept in `all_txs` for the history
+    ///
+    /// NOTE: this is Option to keep cache backwards-compatibility, remove if breaking cache
+    pub removed: Option<HashMap<BETxid, TxRemoval>>,
+
+    /// The reorganizations that removed the block confirming the transactions
+    ///
+    /// NOTE: this is Option to keep cache backwards-compatibility, remove if breaking cache
+    pub reorgs: Option<HashMap<BETxid, TxReorgs>>,
+
+    /// The provenance of the transactions spending outputs of the account,
+    /// see [`RawAccountCache::record_provenance`]
+    ///
+    /// NOTE: this is Option to keep cache backwards-compatibility, remove if breaking cache
+    pub provenance: Option<HashMap<BETxid, Vec<BETxid>>>,
+}
+
+/// The maximum number of hops recorded in the provenance of a transaction
+pub const MAX_PROVENANCE_HOPS: usize = 8;
+
+/// The maximum number of accounts whose transactions are kept in memory, the
+/// least recently used are unloaded when the store is flushed
+const MAX_LOADED_TXS: usize = 8;
+
+/// Where the transactions of the accounts of a store are loaded from
+struct TxsSource {
+    path: PathBuf,
+    cipher: Aes256GcmSiv,
+
+    /// how many times transactions were loaded
+    loads: AtomicU64,
+
+    /// incremented on every access to the transactions, to find the least
+    /// recently used
+    clock: AtomicU64,
+}
+
+/// The transactions of an account, the cold part of the cache.
+///
+/// They are kept in their own file, loaded on first access, and written only
+/// when changed. In the cache file they are serialized empty: caches written
+/// before they had their own file have them inline, they move to their file at
+/// the next flush.
+#[derive(Default)]
+pub struct LazyTxs {
+    txs: OnceLock<BETransactions>,
+    source: Option<(u32, Arc<TxsSource>)>,
+    dirty: bool,
+    last_access: AtomicU64,
+}
+
+impl LazyTxs {
+    /// The transactions of a new account, written at the next flush
+    fn new() -> Self {
+        LazyTxs {
+            txs: OnceLock::from(BETransactions::default()),
+            dirty: true,
+            ..Default::default()
+        }
+    }
+
+    fn attach(&mut self, account_num: u32, source: Arc<TxsSource>) {
+        self.source = Some((account_num, source));
+    }
+
+    fn is_loaded(&self) -> bool {
+        self.txs.get().is_some()
+    }
+
+    /// The transactions to write, if changed since loaded
+    fn dirty(&self) -> Option<&BETransactions> {
+        self.txs.get().filter(|_| self.dirty)
+    }
+
+    /// Whether the transactions can be unloaded, and when last accessed
+    fn unloadable(&self) -> Option<u64> {
+        match self.source {
+            Some(_) if self.is_loaded() && !self.dirty => {
+                Some(self.last_access.load(Ordering::Relaxed))
+            }
+            _ => None,
+        }
+    }
+
+    fn unload(&mut self) {
+        self.txs = OnceLock::new();
+    }
+
+    fn load(&self) -> BETransactions {
+        let (account_num, source) = match &self.source {
+            Some(source) => source,
+            None => return BETransactions::default(),
+        };
+        source.loads.fetch_add(1, Ordering::Relaxed);
+        let loaded = load_decrypt(Kind::Txs(*account_num), &source.path, &source.cipher)
+            .and_then(|decrypted| Ok(serde_cbor::from_reader(&decrypted[..])?));
+        loaded.unwrap_or_else(|e| {
+            log_initialization(e, &source.path);
+            BETransactions::default()
+        })
+    }
+}
+
+impl Deref for LazyTxs {
+    type Target = BETransactions;
+
+    fn deref(&self) -> &BETransactions {
+        if let Some((_, source)) = &self.source {
+            let now = source.clock.fetch_add(1, Ordering::Relaxed);
+            self.last_access.store(now, Ordering::Relaxed);
+        }
+        self.txs.get_or_init(|| self.load())
+    }
+}
+
+impl DerefMut for LazyTxs {
+    fn deref_mut(&mut self) -> &mut BETransactions {
+        self.deref();
+        self.dirty = true;
+        self.txs.get_mut().expect("loaded by deref")
+    }
+}
+
+impl Serialize for LazyTxs {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        BETransactions::default().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for LazyTxs {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let txs = BETransactions::deserialize(deserializer)?;
+        let mut lazy = LazyTxs::default();
+        if !txs.is_empty() {
+            lazy.txs = OnceLock::from(txs);
+            lazy.dirty = true;
+        }
+        Ok(lazy)
+    }
+}
+
+/// The unspent outputs of an account and their sum per asset, updated
+/// incrementally as the transactions of the account change.
+///
+/// Outputs of liquid transactions that cannot be unblinded are not included.
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct UtxoBalances {
+    /// asset and value of the unspent outputs
+    utxos: HashMap<BEOutPoint, (String, u64)>,
+
+    /// the account transactions spending each output
+    spent: HashMap<BEOutPoint, HashSet<BETxid>>,
+
+    /// sum of the unspent outputs per asset, "btc" on bitcoin
+    balances: HashMap<String, u64>,
+}
+
+impl UtxoBalances {
+    /// The balance per asset, assets without unspent outputs are not included
+    pub fn balances(&self) -> &HashMap<String, u64> {
+        &self.balances
+    }
+
+    fn insert_utxo(&mut self, outpoint: BEOutPoint, asset: String, value: u64) {
+        self.remove_utxo(&outpoint);
+        *self.balances.entry(asset.clone()).or_default() += value;
+        self.utxos.insert(outpoint, (asset, value));
+    }
+
+    fn remove_utxo(&mut self, outpoint: &BEOutPoint) {
+        if let Some((asset, value)) = self.utxos.remove(outpoint) {
+            if let Entry::Occupied(mut entry) = self.balances.entry(asset) {
+                *entry.get_mut() -= value;
+                if *entry.get() == 0 {
+                    entry.remove();
+                }
+            }
+        }
+    }
+}
+
+/// The sync generation is incremented every time the transactions of an account
+/// change, each transaction records the generation in which it was first seen.
+///
+/// Transactions stored before generations were introduced have generation 0.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct TxGenerations {
+    pub current: u64,
+    pub first_seen: HashMap<BETxid, u64>,
 }
 
 #[derive(Default, Clone, Serialize, Deserialize)]
@@ -141,22 +365,165 @@ pub struct RawStore {
     accounts_settings: Option<HashMap<u32, AccountSettings>>,
 
     pub client_blob: Option<ClientBlob>,
+
+    /// transactions sent in the last [`SPEND_WINDOW_SECS`], for the spend limits
+    spends: Option<Vec<Spend>>,
+
+    /// the username of the watch-only credentials of the wallet, if set
+    pub watch_only_username: Option<String>,
+
+    /// the exchange rates of the policy asset when the transactions got confirmed
+    confirmation_rates: Option<HashMap<Txid, ConfirmationRate>>,
+
+    /// the transactions sent by client request id
+    client_requests: Option<HashMap<String, ClientRequest>>,
+
+    /// the wallet migration being executed
+    wallet_migration: Option<WalletMigration>,
+
+    /// the transactions whose broadcast failed, oldest first
+    pending_broadcasts: Option<Vec<QueuedBroadcast>>,
+
+    /// when the wallet was created, if given at restore
+    wallet_birthday: Option<WalletBirthday>,
+
+    /// the payments expected to receive addresses, oldest first
+    expected_payments: Option<Vec<ExpectedPayment>>,
+
+    /// transaction output labels (txid -> vout -> label)
+    output_labels: Option<HashMap<Txid, HashMap<u32, String>>>,
+
+    /// how the transactions sent by the wallet were broadcast
+    broadcasts: Option<HashMap<Txid, BroadcastDetails>>,
+
+    /// the electrum server chosen with `set_electrum_server`
+    electrum_server: Option<ElectrumServer>,
+
+    /// the version of the format of the store, see [`STORE_VERSION`]
+    version: Option<u32>,
+
+    /// whether the mnemonic of the wallet has a BIP39 passphrase, known once
+    /// logged in with it
+    bip39_passphrase: Option<bool>,
+}
+
+/// A transaction whose broadcast failed because the server could not be
+/// reached
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QueuedBroadcast {
+    pub pending: PendingBroadcast,
+
+    /// The satoshi sent out of the wallet, accounted in the spend limits
+    /// once broadcast
+    pub spent: u64,
+}
+
+/// The execution of a wallet migration plan
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WalletMigration {
+    pub plan: WalletMigrationPlan,
+
+    /// The signed transactions of the steps, once signed
+    pub signed: Vec<Option<String>>,
+
+    /// Whether the steps have been broadcast
+    pub broadcast: Vec<bool>,
+}
+
+impl WalletMigration {
+    pub fn new(plan: WalletMigrationPlan) -> Self {
+        let steps = plan.steps.len();
+        WalletMigration {
+            plan,
+            signed: vec![None; steps],
+            broadcast: vec![false; steps],
+        }
+    }
+}
+
+/// A transaction sent with a client request id
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ClientRequest {
+    pub txid: Txid,
+
+    /// Whether it is the payjoin of the transaction given
+    pub payjoin: bool,
+
+    /// unix time in seconds of when the transaction was sent
+    pub timestamp: u64,
+}
+
+/// The exchange rate of the policy asset when a transaction got confirmed
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConfirmationRate {
+    pub currency: String,
+    pub rate: f64,
+}
+
+impl ConfirmationRate {
+    /// `satoshi` of the policy asset valued at this rate
+    pub fn fiat_at_confirmation(&self, satoshi: i64) -> FiatAtConfirmation {
+        FiatAtConfirmation {
+            fiat: format!("{:.2}", satoshi as f64 * self.rate / 100_000_000.0),
+            fiat_currency: self.currency.clone(),
+            fiat_rate: format!("{:.8}", self.rate),
+        }
+    }
+}
+
+/// The length of the rolling window of the daily spend limit
+pub const SPEND_WINDOW_SECS: u64 = 24 * 60 * 60;
+
+/// The version of the script statuses cached, to bump when they are computed
+/// differently: the histories of all the scripts are then fetched again
+pub const SCRIPT_STATUSES_VERSION: u32 = 1;
+
+/// The version of the format of the store, to bump when it changes in a way
+/// older versions can't read. The stores written before it was recorded are
+/// of the first version.
+pub const STORE_VERSION: u32 = 1;
+
+/// The version of the unblinded outputs cached, to bump when unblinding
+/// changes: the outputs of the transactions in the store are then unblinded
+/// again when it's opened
+pub const UNBLINDED_VERSION: u32 = 1;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Spend {
+    pub txid: Txid,
+
+    /// unix time in seconds of when the transaction was sent
+    pub timestamp: u64,
+
+    /// policy asset amount sent out of the wallet, fee included
+    pub satoshi: u64,
 }
 
 pub struct StoreMeta {
     pub cache: RawCache,
     pub store: RawStore,
-    id: NetworkId,
+
+    /// The minimum fee rate of the network, used until the estimates are fetched
+    min_fee_rate: u64,
     path: PathBuf,
     cipher: Aes256GcmSiv,
+    txs_source: Arc<TxsSource>,
     last: HashMap<Kind, sha256::Hash>,
     to_remove: bool,
+
+    /// Modification time of the store file when last loaded or written by us
+    store_modified: Option<SystemTime>,
+
+    /// When the store was last flushed, see [`Self::flushed_at`]
+    flushed_at: Option<SystemTime>,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Kind {
     Cache,
     Store,
+    /// the transactions of an account
+    Txs(u32),
 }
 
 impl Display for Kind {
@@ -164,6 +531,7 @@ impl Display for Kind {
         match self {
             Kind::Store => write!(f, "store"),
             Kind::Cache => write!(f, "cache"),
+            Kind::Txs(account_num) => write!(f, "txs_{}", account_num),
         }
     }
 }
@@ -173,6 +541,11 @@ impl Drop for StoreMeta {
         if self.to_remove && self.path.exists() {
             self.remove_file(Kind::Store);
             self.remove_file(Kind::Cache);
+            for account_num in self.cache.accounts.keys().copied().collect::<Vec<_>>() {
+                self.remove_file(Kind::Txs(account_num));
+            }
+            // The credentials kept for quick login are of the removed wallet
+            crate::quick_login::remove(&crate::quick_login::quick_login_path(&self.path)).unwrap();
             std::fs::remove_dir(&self.path).unwrap();
         } else {
             self.flush().unwrap();
@@ -224,6 +597,46 @@ impl RawCache {
         Ok(store)
     }
 
+    /// Forget the script statuses of another version, so that the next sync
+    /// refreshes the history of every script
+    fn check_script_statuses_version(&mut self) {
+        if self.script_statuses_version == Some(SCRIPT_STATUSES_VERSION) {
+            return;
+        }
+        if !self.accounts.is_empty() {
+            info!(
+                "script statuses version {:?} is not {}, refreshing all the histories",
+                self.script_statuses_version, SCRIPT_STATUSES_VERSION
+            );
+        }
+        for account in self.accounts.values_mut() {
+            account.script_statuses = None;
+        }
+        self.script_statuses_version = Some(SCRIPT_STATUSES_VERSION);
+    }
+
+    /// Unblind again the outputs of the accounts unblinded by another
+    /// version, their transactions are loaded for it
+    ///
+    /// Outputs unblinded by the current version are never unblinded again.
+    fn check_unblinded_version(&mut self) {
+        if self.unblinded_version == Some(UNBLINDED_VERSION) {
+            return;
+        }
+        if let Some(master_blinding) = self.master_blinding.as_ref() {
+            info!(
+                "unblinded version {:?} is not {}, unblinding all the outputs again",
+                self.unblinded_version, UNBLINDED_VERSION
+            );
+            for account in self.accounts.values_mut() {
+                let outputs = account.wallet_outputs();
+                account.unblinded = unblind_outputs(outputs, master_blinding).into_iter().collect();
+                account.utxo_balances = Some(account.compute_utxo_balances());
+            }
+        }
+        self.unblinded_version = Some(UNBLINDED_VERSION);
+    }
+
     // The following 3 functions are needed to handle the missing `tip_`.
     // This should be happening at most once when upgrading the cache.
     #[allow(deprecated)]
@@ -298,9 +711,28 @@ impl StoreMeta {
     pub fn new<P: AsRef<Path>>(
         path: P,
         cipher: &Aes256GcmSiv,
-        id: NetworkId,
+        min_fee_rate: u64,
     ) -> Result<StoreMeta, Error> {
-        let cache = RawCache::new(path.as_ref(), &cipher);
+        let mut cache = RawCache::new(path.as_ref(), &cipher);
+        cache.check_script_statuses_version();
+        let txs_source = Arc::new(TxsSource {
+            path: path.as_ref().to_path_buf(),
+            cipher: cipher.clone(),
+            loads: AtomicU64::new(0),
+            clock: AtomicU64::new(0),
+        });
+        for (account_num, account) in cache.accounts.iter_mut() {
+            let txs_path = path.as_ref().join(Kind::Txs(*account_num).to_string());
+            if !account.all_txs.is_loaded() && !account.heights.is_empty() && !txs_path.exists() {
+                info!("transactions of account {} missing, syncing them again", account_num);
+                *account = RawAccountCache::new(account.xpub, account.bip44_discovered);
+            }
+            account.all_txs.attach(*account_num, txs_source.clone());
+            if account.utxo_balances.is_none() {
+                account.utxo_balances = Some(account.compute_utxo_balances());
+            }
+        }
+        cache.check_unblinded_version();
 
         let mut store = RawStore::new(path.as_ref(), &cipher);
         let path = path.as_ref().to_path_buf();
@@ -308,16 +740,21 @@ impl StoreMeta {
         std::fs::create_dir_all(&path)?; // does nothing if path exists
 
         store.accounts_settings.get_or_insert_with(|| Default::default());
+        store.version.get_or_insert(STORE_VERSION);
 
-        let store = StoreMeta {
+        let mut store = StoreMeta {
             cache,
             store,
-            id,
+            min_fee_rate,
             cipher: cipher.clone(),
+            txs_source,
             path,
             last: HashMap::new(),
             to_remove: false,
+            store_modified: None,
+            flushed_at: None,
         };
+        store.store_modified = store.store_file_modified();
         Ok(store)
     }
 
@@ -333,8 +770,11 @@ impl StoreMeta {
 
     fn remove_file(&mut self, kind: Kind) {
         let path = self.file_path(kind);
-        if path.exists() {
-            std::fs::remove_file(&path).unwrap();
+        // Including the one left by a flush interrupted before its rename
+        for path in [path.with_extension("tmp"), path] {
+            if path.exists() {
+                std::fs::remove_file(&path).unwrap();
+            }
         }
     }
 
@@ -344,6 +784,10 @@ impl StoreMeta {
         let plaintext = match kind {
             Kind::Store => serde_cbor::to_vec(&self.store),
             Kind::Cache => serde_cbor::to_vec(&self.cache),
+            Kind::Txs(account_num) => match self.account_cache(account_num)?.all_txs.dirty() {
+                Some(txs) => serde_cbor::to_vec(txs),
+                None => return Ok(()),
+            },
         }?;
 
         let hash = sha256::Hash::hash(&plaintext);
@@ -355,16 +799,20 @@ impl StoreMeta {
             }
         }
 
-        self.last.insert(kind, hash);
-
         let (nonce_bytes, ciphertext) = plaintext.encrypt(&self.cipher)?;
 
         let store_path = self.file_path(kind);
         //TODO should avoid rewriting if not changed? it involves saving plaintext (or struct hash)
         // in the front of the file
-        let mut file = File::create(&store_path)?;
+        // Written aside and then renamed, so a process killed while flushing leaves the
+        // previous file intact
+        let tmp_path = store_path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
         file.write_all(&nonce_bytes)?;
         file.write_all(&ciphertext)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, &store_path)?;
+        self.last.insert(kind, hash);
         info!(
             "flushing {} bytes on {:?} took {}ms",
             ciphertext.len() + 16,
@@ -376,20 +824,85 @@ impl StoreMeta {
 
     fn flush_store(&mut self) -> Result<(), Error> {
         self.flush_serializable(Kind::Store)?;
+        self.store_modified = self.store_file_modified();
         Ok(())
     }
 
+    fn store_file_modified(&mut self) -> Option<SystemTime> {
+        std::fs::metadata(self.file_path(Kind::Store)).and_then(|m| m.modified()).ok()
+    }
+
+    /// If another session of the same wallet wrote settings with a higher revision
+    /// to the store file, load and return them
+    pub fn reload_settings_if_changed(&mut self) -> Result<Option<Settings>, Error> {
+        let modified = self.store_file_modified();
+        if modified.is_none() || modified == self.store_modified {
+            return Ok(None);
+        }
+        self.store_modified = modified;
+        let on_disk = RawStore::try_new(&self.path, &self.cipher)?;
+        let current_revision = self.store.settings.as_ref().map_or(0, Settings::revision);
+        match on_disk.settings {
+            Some(settings) if settings.revision() > current_revision => {
+                info!("settings changed by another session, revision {}", settings.revision());
+                self.store.settings = Some(settings.clone());
+                Ok(Some(settings))
+            }
+            _ => Ok(None),
+        }
+    }
+
     fn flush_cache(&mut self) -> Result<(), Error> {
+        // The transactions first, the cache refers to them
+        let account_nums: Vec<u32> = self.cache.accounts.keys().copied().collect();
+        for account_num in account_nums {
+            self.flush_serializable(Kind::Txs(account_num))?;
+            self.account_cache_mut(account_num)?.all_txs.dirty = false;
+        }
         self.flush_serializable(Kind::Cache)?;
+        self.unload_txs();
         Ok(())
     }
 
+    /// Unload the transactions of the least recently used accounts, keeping at
+    /// most [`MAX_LOADED_TXS`] in memory
+    fn unload_txs(&mut self) {
+        let mut loaded: Vec<(u64, u32)> = self
+            .cache
+            .accounts
+            .iter()
+            .filter_map(|(account_num, account)| {
+                Some((account.all_txs.unloadable()?, *account_num))
+            })
+            .collect();
+        if loaded.len() <= MAX_LOADED_TXS {
+            return;
+        }
+        loaded.sort();
+        for (_, account_num) in loaded.iter().take(loaded.len() - MAX_LOADED_TXS) {
+            if let Some(account) = self.cache.accounts.get_mut(account_num) {
+                account.all_txs.unload();
+            }
+        }
+    }
+
+    /// How many times account transactions were loaded from their files
+    pub fn txs_loads(&self) -> u64 {
+        self.txs_source.loads.load(Ordering::Relaxed)
+    }
+
     pub fn flush(&mut self) -> Result<(), Error> {
         self.flush_store()?;
         self.flush_cache()?;
+        self.flushed_at = Some(SystemTime::now());
         Ok(())
     }
 
+    /// When the store was last flushed, if it was since opened
+    pub fn flushed_at(&self) -> Option<SystemTime> {
+        self.flushed_at
+    }
+
     pub fn account_cache(&self, account_num: u32) -> Result<&RawAccountCache, Error> {
         self.cache.accounts.get(&account_num).ok_or_else(|| Error::InvalidSubaccount(account_num))
     }
@@ -417,7 +930,8 @@ impl StoreMeta {
 
         match self.cache.accounts.entry(account_num) {
             Entry::Vacant(entry) => {
-                let account = RawAccountCache::new(account_xpub, discovered);
+                let mut account = RawAccountCache::new(account_xpub, discovered);
+                account.all_txs.attach(account_num, self.txs_source.clone());
                 entry.insert(account);
             }
             Entry::Occupied(entry) => {
@@ -440,18 +954,30 @@ impl StoreMeta {
     }
 
     pub fn min_fee_rate(&self) -> u64 {
-        self.cache.fee_estimates.get(0).map_or_else(|| self.id.default_min_fee_rate(), |f| f.0)
+        self.cache.fee_estimates.get(0).map_or(self.min_fee_rate, |f| f.0)
     }
 
     pub fn fee_estimates(&self) -> Vec<FeeEstimate> {
         if self.cache.fee_estimates.is_empty() {
-            let min_fee = self.id.default_min_fee_rate();
+            let min_fee = self.min_fee_rate;
             vec![FeeEstimate(min_fee); 25]
         } else {
             self.cache.fee_estimates.clone()
         }
     }
 
+    /// Replace the fee estimates with the ones got from the server at `now`
+    pub fn set_fee_estimates(&mut self, fee_estimates: Vec<FeeEstimate>, now: u64) {
+        self.cache.fee_estimates = fee_estimates;
+        self.cache.fee_estimates_at = Some(now);
+    }
+
+    /// Unix time in seconds of when the fee estimates were got from the
+    /// server, None if they never were
+    pub fn fee_estimates_at(&self) -> Option<u64> {
+        self.cache.fee_estimates_at
+    }
+
     pub fn insert_memo(&mut self, txid: BETxid, memo: &str) -> Result<(), Error> {
         // Coerced into a bitcoin::Txid to retain database compatibility
         let txid = txid.into_bitcoin();
@@ -464,6 +990,84 @@ impl StoreMeta {
         self.store.memos.get(&txid.into_bitcoin())
     }
 
+    /// Set the label of an output, removing it if `label` is empty
+    pub fn insert_output_label(&mut self, outpoint: &BEOutPoint, label: &str) -> Result<(), Error> {
+        let labels = self.store.output_labels.get_or_insert_with(HashMap::new);
+        let txid = outpoint.txid().into_bitcoin();
+        if label.is_empty() {
+            if let Entry::Occupied(mut entry) = labels.entry(txid) {
+                entry.get_mut().remove(&outpoint.vout());
+                if entry.get().is_empty() {
+                    entry.remove();
+                }
+            }
+        } else {
+            labels.entry(txid).or_default().insert(outpoint.vout(), label.to_string());
+        }
+        self.flush_store()?;
+        Ok(())
+    }
+
+    pub fn get_output_label(&self, outpoint: &BEOutPoint) -> Option<&String> {
+        let labels = self.store.output_labels.as_ref()?;
+        labels.get(&outpoint.txid().into_bitcoin())?.get(&outpoint.vout())
+    }
+
+    /// The labels of the outputs of `txid`, by vout
+    pub fn get_output_labels(&self, txid: &BETxid) -> Option<&HashMap<u32, String>> {
+        self.store.output_labels.as_ref()?.get(&txid.into_bitcoin())
+    }
+
+    /// All the output labels, by txid and vout
+    pub fn output_labels(&self) -> impl Iterator<Item = (&Txid, &HashMap<u32, String>)> {
+        self.store.output_labels.iter().flatten()
+    }
+
+    pub fn insert_broadcast(
+        &mut self,
+        txid: &BETxid,
+        details: BroadcastDetails,
+    ) -> Result<(), Error> {
+        self.store.broadcasts.get_or_insert_with(HashMap::new).insert(txid.into_bitcoin(), details);
+        self.flush_store()?;
+        Ok(())
+    }
+
+    /// How the transaction `txid` was broadcast, if sent by the wallet
+    pub fn get_broadcast(&self, txid: &BETxid) -> Option<&BroadcastDetails> {
+        self.store.broadcasts.as_ref()?.get(&txid.into_bitcoin())
+    }
+
+    pub fn set_electrum_server(&mut self, server: ElectrumServer) -> Result<(), Error> {
+        self.store.electrum_server = Some(server);
+        self.flush_store()?;
+        Ok(())
+    }
+
+    /// The electrum server chosen by the user, overriding the network one
+    pub fn get_electrum_server(&self) -> Option<&ElectrumServer> {
+        self.store.electrum_server.as_ref()
+    }
+
+    /// The version of the format of the store, see [`STORE_VERSION`]
+    pub fn version(&self) -> u32 {
+        self.store.version.unwrap_or(STORE_VERSION)
+    }
+
+    pub fn set_bip39_passphrase(&mut self, used: bool) -> Result<(), Error> {
+        if self.store.bip39_passphrase != Some(used) {
+            self.store.bip39_passphrase = Some(used);
+            self.flush_store()?;
+        }
+        Ok(())
+    }
+
+    /// Whether the mnemonic of the wallet has a BIP39 passphrase, None if
+    /// never logged in with the mnemonic
+    pub fn bip39_passphrase(&self) -> Option<bool> {
+        self.store.bip39_passphrase
+    }
+
     pub fn insert_settings(&mut self, settings: Option<Settings>) -> Result<(), Error> {
         self.store.settings = settings;
         self.flush_store()?;
@@ -474,6 +1078,203 @@ impl StoreMeta {
         self.store.settings.clone()
     }
 
+    /// Record that `txid` sent `satoshi` out of the wallet at `now`, forgetting
+    /// the spends out of the window
+    pub fn insert_spend(&mut self, txid: BETxid, satoshi: u64, now: u64) -> Result<(), Error> {
+        // Coerced into a bitcoin::Txid as the memos
+        let txid = txid.into_bitcoin();
+        let spends = self.store.spends.get_or_insert_with(Vec::new);
+        spends.retain(|spend| spend.timestamp + SPEND_WINDOW_SECS > now);
+        // Broadcasting again the same transaction doesn't spend more
+        if !spends.iter().any(|spend| spend.txid == txid) {
+            spends.push(Spend {
+                txid,
+                timestamp: now,
+                satoshi,
+            });
+        }
+        self.flush_store()?;
+        Ok(())
+    }
+
+    /// Record the exchange rate at which `txids` got confirmed, keeping the
+    /// first one recorded for each
+    pub fn insert_confirmation_rates(
+        &mut self,
+        txids: &[BETxid],
+        rate: &ConfirmationRate,
+    ) -> Result<(), Error> {
+        let rates = self.store.confirmation_rates.get_or_insert_with(HashMap::new);
+        for txid in txids {
+            // Coerced into a bitcoin::Txid as the memos
+            rates.entry(txid.into_bitcoin()).or_insert_with(|| rate.clone());
+        }
+        self.flush_store()?;
+        Ok(())
+    }
+
+    pub fn confirmation_rate(&self, txid: &BETxid) -> Option<&ConfirmationRate> {
+        self.store.confirmation_rates.as_ref()?.get(&txid.into_bitcoin())
+    }
+
+    /// Record the transaction sent with the client request `id`, replacing
+    /// the previous one and removing the requests older than `expiry` seconds
+    pub fn insert_client_request(
+        &mut self,
+        id: &str,
+        request: ClientRequest,
+        expiry: u64,
+    ) -> Result<(), Error> {
+        let now = request.timestamp;
+        let requests = self.store.client_requests.get_or_insert_with(HashMap::new);
+        requests.retain(|_, request| request.timestamp.saturating_add(expiry) > now);
+        requests.insert(id.to_string(), request);
+        self.flush_store()?;
+        Ok(())
+    }
+
+    /// The transaction sent with the client request `id`, if not older than
+    /// `expiry` seconds at `now`
+    pub fn client_request(&self, id: &str, now: u64, expiry: u64) -> Option<&ClientRequest> {
+        let request = self.store.client_requests.as_ref()?.get(id)?;
+        Some(request).filter(|request| request.timestamp.saturating_add(expiry) > now)
+    }
+
+    /// The wallet migration being executed, if any
+    pub fn wallet_migration(&self) -> Option<&WalletMigration> {
+        self.store.wallet_migration.as_ref()
+    }
+
+    /// Record the progress of a wallet migration, replacing the previous one
+    pub fn set_wallet_migration(&mut self, migration: WalletMigration) -> Result<(), Error> {
+        self.store.wallet_migration = Some(migration);
+        self.flush_store()?;
+        Ok(())
+    }
+
+    /// Queue a transaction whose broadcast failed, if not already queued
+    pub fn queue_broadcast(&mut self, queued: QueuedBroadcast) -> Result<(), Error> {
+        let pending = self.store.pending_broadcasts.get_or_insert_with(Vec::new);
+        if !pending.iter().any(|q| q.pending.txhash == queued.pending.txhash) {
+            pending.push(queued);
+            self.flush_store()?;
+        }
+        Ok(())
+    }
+
+    /// The transactions whose broadcast failed, oldest first
+    pub fn pending_broadcasts(&self) -> impl Iterator<Item = &QueuedBroadcast> {
+        self.store.pending_broadcasts.iter().flatten()
+    }
+
+    /// The queued transactions to broadcast again at `now`, marking as
+    /// expired the ones queued more than `max_age` seconds before
+    pub fn broadcasts_to_retry(
+        &mut self,
+        now: u64,
+        max_age: u64,
+    ) -> Result<Vec<QueuedBroadcast>, Error> {
+        let pending = match self.store.pending_broadcasts.as_mut() {
+            Some(pending) => pending,
+            None => return Ok(vec![]),
+        };
+        let mut changed = false;
+        for queued in pending.iter_mut().filter(|q| !q.pending.expired) {
+            if queued.pending.created_at.saturating_add(max_age) <= now {
+                queued.pending.expired = true;
+                changed = true;
+            }
+        }
+        let retry = pending.iter().filter(|q| !q.pending.expired).cloned().collect();
+        if changed {
+            self.flush_store()?;
+        }
+        Ok(retry)
+    }
+
+    /// Mark the queued transaction `txhash` as expired, not to broadcast it
+    /// again
+    pub fn expire_broadcast(&mut self, txhash: &str) -> Result<(), Error> {
+        let pending = self.store.pending_broadcasts.iter_mut().flatten();
+        if let Some(queued) = pending.find(|q| q.pending.txhash == txhash) {
+            queued.pending.expired = true;
+            self.flush_store()?;
+        }
+        Ok(())
+    }
+
+    /// Remove the queued transaction `txhash`, returning whether it was queued
+    pub fn remove_pending_broadcast(&mut self, txhash: &str) -> Result<bool, Error> {
+        let pending = self.store.pending_broadcasts.get_or_insert_with(Vec::new);
+        let len = pending.len();
+        pending.retain(|q| q.pending.txhash != txhash);
+        let removed = pending.len() != len;
+        if removed {
+            self.flush_store()?;
+        }
+        Ok(removed)
+    }
+
+    /// When the wallet was created, if given at restore
+    pub fn wallet_birthday(&self) -> Option<&WalletBirthday> {
+        self.store.wallet_birthday.as_ref()
+    }
+
+    /// Record when the wallet was created, replacing the previous birthday
+    pub fn set_wallet_birthday(&mut self, birthday: WalletBirthday) -> Result<(), Error> {
+        self.store.wallet_birthday = Some(birthday);
+        self.flush_store()?;
+        Ok(())
+    }
+
+    /// Record a payment expected to a receive address, replacing the pending
+    /// one expected to the same address
+    pub fn insert_expected_payment(&mut self, payment: ExpectedPayment) -> Result<(), Error> {
+        let payments = self.store.expected_payments.get_or_insert_with(Vec::new);
+        payments.retain(|p| {
+            (p.subaccount, p.pointer, p.status)
+                != (payment.subaccount, payment.pointer, PaymentStatus::Pending)
+        });
+        payments.push(payment);
+        self.flush_store()?;
+        Ok(())
+    }
+
+    /// The payments expected to receive addresses, oldest first
+    pub fn expected_payments(&self) -> impl Iterator<Item = &ExpectedPayment> {
+        self.store.expected_payments.iter().flatten()
+    }
+
+    /// Settle the pending payment expected to the receive address `pointer`
+    /// of `subaccount` with `received_satoshi` paid by `txid` at `now`,
+    /// returning it if there is one
+    pub fn settle_expected_payment(
+        &mut self,
+        subaccount: u32,
+        pointer: u32,
+        txid: &BETxid,
+        received_satoshi: u64,
+        now: u64,
+    ) -> Result<Option<ExpectedPayment>, Error> {
+        let mut payments = self.store.expected_payments.iter_mut().flatten();
+        let payment = match payments.find(|p| {
+            (p.subaccount, p.pointer, p.status) == (subaccount, pointer, PaymentStatus::Pending)
+        }) {
+            Some(payment) => payment,
+            None => return Ok(None),
+        };
+        payment.settle(txid.to_string(), received_satoshi, now);
+        let payment = payment.clone();
+        self.flush_store()?;
+        Ok(Some(payment))
+    }
+
+    /// The satoshi sent out of the wallet in the window ending at `now`
+    pub fn spent_in_window(&self, now: u64) -> u64 {
+        let spends = self.store.spends.iter().flatten();
+        spends.filter(|spend| spend.timestamp + SPEND_WINDOW_SECS > now).map(|s| s.satoshi).sum()
+    }
+
     pub fn get_accounts_settings(&self) -> &HashMap<u32, AccountSettings> {
         // This field is an Option to retain backwards compatibility with the db serialization,
         // but is guaranteed to be initialized as a Some (via StoreMeta::new).
@@ -524,6 +1325,37 @@ impl StoreMeta {
         Err(Error::TxNotFound(txid.clone()))
     }
 
+    /// Put back `tx` in the transactions of the accounts whose history has
+    /// it, if it went missing from them, returning whether it's a wallet
+    /// transaction
+    pub fn restore_wallet_tx(&mut self, tx: &BETransaction) -> Result<bool, Error> {
+        let txid = tx.txid();
+        let mut restored = false;
+        for acc_store in self.cache.accounts.values_mut() {
+            if acc_store.heights.contains_key(&txid) {
+                acc_store.all_txs.insert(txid, tx.clone().into());
+                restored = true;
+            }
+        }
+        if restored {
+            self.flush_cache()?;
+        }
+        Ok(restored)
+    }
+
+    /// The transaction `txid` kept by [`Self::insert_foreign_tx`]
+    pub fn foreign_tx(&self, txid: &BETxid) -> Option<&BETransactionEntry> {
+        self.cache.foreign_txs.as_ref()?.get(txid)
+    }
+
+    /// Keep `tx`, which is not of the wallet, apart from the wallet
+    /// transactions
+    pub fn insert_foreign_tx(&mut self, tx: BETransaction) -> Result<(), Error> {
+        let foreign_txs = self.cache.foreign_txs.get_or_insert_with(BETransactions::default);
+        foreign_txs.insert(tx.txid(), tx.into());
+        self.flush_cache()
+    }
+
     pub fn update_tip(&mut self, new_height: u32, new_header: BEBlockHeader) -> Result<(), Error> {
         self.cache.tip_ = Some((new_height, new_header));
         self.flush_cache()?;
@@ -534,7 +1366,7 @@ impl StoreMeta {
 impl RawAccountCache {
     pub fn new(xpub: Xpub, bip44_discovered: bool) -> Self {
         RawAccountCache {
-            all_txs: Default::default(),
+            all_txs: LazyTxs::new(),
             paths: Default::default(),
             scripts: Default::default(),
             heights: Default::default(),
@@ -542,10 +1374,272 @@ impl RawAccountCache {
             unblinded: Default::default(),
             last_used: Default::default(),
             count_given: Some(Default::default()),
+            tx_generations: Some(Default::default()),
+            utxo_balances: Some(Default::default()),
+            removed: Some(Default::default()),
+            reorgs: Some(Default::default()),
+            provenance: Some(Default::default()),
             xpub,
             bip44_discovered,
         }
     }
+
+    /// Compute the running balances from all the transactions of the account
+    pub fn compute_utxo_balances(&self) -> UtxoBalances {
+        let mut utxo_balances = UtxoBalances::default();
+        for txid in self.heights.keys() {
+            self.add_tx_balances(&mut utxo_balances, txid);
+        }
+        utxo_balances
+    }
+
+    /// Update the running balances after the transactions `txids` were added,
+    /// removed or confirmed
+    ///
+    /// Must be called after `heights`, `paths` and `unblinded` are updated.
+    pub fn update_utxo_balances<'a>(&mut self, txids: impl Iterator<Item = &'a BETxid>) {
+        let mut utxo_balances = match self.utxo_balances.take() {
+            Some(utxo_balances) => utxo_balances,
+            None => self.compute_utxo_balances(),
+        };
+        for txid in txids {
+            self.remove_tx_balances(&mut utxo_balances, txid);
+            if self.heights.contains_key(txid) {
+                self.add_tx_balances(&mut utxo_balances, txid);
+            }
+        }
+        self.utxo_balances = Some(utxo_balances);
+    }
+
+    fn add_tx_balances(&self, utxo_balances: &mut UtxoBalances, txid: &BETxid) {
+        let tx = match self.all_txs.get(txid) {
+            Some(txe) => &txe.tx,
+            None => return,
+        };
+        for outpoint in tx.previous_outputs() {
+            utxo_balances.remove_utxo(&outpoint);
+            utxo_balances.spent.entry(outpoint).or_default().insert(*txid);
+        }
+        for vout in 0..(tx.output_len() as u32) {
+            let outpoint = tx.outpoint(vout);
+            if utxo_balances.spent.contains_key(&outpoint) {
+                continue;
+            }
+            if let Some((asset, value)) = self.owned_output(tx, vout) {
+                utxo_balances.insert_utxo(outpoint, asset, value);
+            }
+        }
+    }
+
+    fn remove_tx_balances(&self, utxo_balances: &mut UtxoBalances, txid: &BETxid) {
+        let tx = match self.all_txs.get(txid) {
+            Some(txe) => &txe.tx,
+            None => return,
+        };
+        for vout in 0..(tx.output_len() as u32) {
+            utxo_balances.remove_utxo(&tx.outpoint(vout));
+        }
+        for outpoint in tx.previous_outputs() {
+            let spenders = match utxo_balances.spent.get_mut(&outpoint) {
+                Some(spenders) if spenders.remove(txid) => spenders,
+                _ => continue,
+            };
+            if !spenders.is_empty() {
+                continue;
+            }
+            utxo_balances.spent.remove(&outpoint);
+            // the output is unspent again if its transaction is still in the account
+            let previous_txid = outpoint.txid();
+            if !self.heights.contains_key(&previous_txid) {
+                continue;
+            }
+            if let Some(previous_tx) = self.all_txs.get(&previous_txid) {
+                if let Some((asset, value)) = self.owned_output(&previous_tx.tx, outpoint.vout()) {
+                    utxo_balances.insert_utxo(outpoint, asset, value);
+                }
+            }
+        }
+    }
+
+    /// Asset and value of the output `vout` of `tx` if it belongs to the account
+    /// The outputs of the account liquid transactions paying its scripts
+    fn wallet_outputs(&self) -> Vec<(elements::OutPoint, elements::TxOut)> {
+        let mut outputs = vec![];
+        for entry in self.all_txs.values() {
+            if let BETransaction::Elements(tx) = &entry.tx {
+                let txid = tx.txid();
+                for (vout, output) in tx.output.iter().enumerate() {
+                    if self.paths.contains_key(&output.script_pubkey.clone().into()) {
+                        let outpoint = elements::OutPoint {
+                            txid,
+                            vout: vout as u32,
+                        };
+                        outputs.push((outpoint, output.clone()));
+                    }
+                }
+            }
+        }
+        outputs
+    }
+
+    fn owned_output(&self, tx: &BETransaction, vout: u32) -> Option<(String, u64)> {
+        let script_pubkey = tx.output_script(vout);
+        if script_pubkey.is_empty() || !self.paths.contains_key(&script_pubkey) {
+            return None;
+        }
+        match tx.outpoint(vout) {
+            BEOutPoint::Bitcoin(_) => {
+                Some(("btc".to_string(), tx.output_value(vout, &self.unblinded)?))
+            }
+            BEOutPoint::Elements(outpoint) => {
+                let secrets = self.unblinded.get(&outpoint)?;
+                Some((secrets.asset.to_string(), secrets.value))
+            }
+        }
+    }
+
+    /// Whether `tx` spends an output of the account, so that its outputs
+    /// paying the account, such as the change, are created by ourselves
+    pub fn spends_own_outputs(&self, tx: &BETransaction) -> bool {
+        tx.previous_outputs().iter().any(|outpoint| match self.all_txs.get(&outpoint.txid()) {
+            Some(previous_tx) => {
+                self.paths.contains_key(&previous_tx.tx.output_script(outpoint.vout()))
+            }
+            None => false,
+        })
+    }
+
+    /// Record the transactions `txids`, removed from `heights`, as replaced if
+    /// a transaction of the account spends some of their inputs, as dropped
+    /// otherwise
+    ///
+    /// Must be called after the running balances are updated.
+    pub fn mark_removed<'a>(&mut self, txids: impl Iterator<Item = &'a BETxid>) {
+        let mut marked = vec![];
+        for txid in txids {
+            let tx = match self.all_txs.get(txid) {
+                Some(txe) => &txe.tx,
+                None => continue,
+            };
+            let spent = self.utxo_balances.as_ref().map(|b| &b.spent);
+            let replaced = tx.previous_outputs().iter().any(|outpoint| {
+                spent.and_then(|spent| spent.get(outpoint)).is_some_and(|s| !s.is_empty())
+            });
+            let removal = if replaced {
+                TxRemoval::Replaced
+            } else {
+                TxRemoval::Dropped
+            };
+            marked.push((*txid, removal));
+        }
+        self.removed.get_or_insert_with(Default::default).extend(marked);
+    }
+
+    /// Forget the removal of the transactions `txids` returned again by the server
+    pub fn unmark_removed<'a>(&mut self, txids: impl Iterator<Item = &'a BETxid>) {
+        if let Some(removed) = self.removed.as_mut() {
+            for txid in txids {
+                removed.remove(txid);
+            }
+        }
+    }
+
+    /// Why `txid` is no longer in the account history, None if it is
+    pub fn removal(&self, txid: &BETxid) -> Option<TxRemoval> {
+        self.removed.as_ref().and_then(|removed| removed.get(txid)).copied()
+    }
+
+    /// The transactions replaced or dropped
+    pub fn removed_txids(&self) -> impl Iterator<Item = &BETxid> {
+        self.removed.iter().flat_map(|removed| removed.keys())
+    }
+
+    /// Count a reorganization removing the block at `height` that confirmed
+    /// `txid`, returning the updated count
+    pub fn record_reorg(&mut self, txid: BETxid, height: u32) -> TxReorgs {
+        let reorgs = self.reorgs.get_or_insert_with(Default::default).entry(txid).or_default();
+        reorgs.count += 1;
+        reorgs.last_height = height;
+        *reorgs
+    }
+
+    /// The reorganizations that removed the block confirming `txid`, None if none
+    pub fn reorgs(&self, txid: &BETxid) -> Option<TxReorgs> {
+        self.reorgs.as_ref().and_then(|reorgs| reorgs.get(txid)).copied()
+    }
+
+    /// Record the provenance of the `txids` spending outputs of the account:
+    /// the transaction that created the output spent by the first input
+    /// spending from the account, then the provenance of that transaction, up
+    /// to [`MAX_PROVENANCE_HOPS`] hops
+    ///
+    /// The walk stops at the first transaction whose provenance is already
+    /// recorded, so the transactions can be recorded in any order.
+    pub fn record_provenance<'a>(&mut self, txids: impl Iterator<Item = &'a BETxid>) {
+        let mut recorded = vec![];
+        for txid in txids {
+            let mut hops = vec![];
+            let mut current = *txid;
+            while hops.len() < MAX_PROVENANCE_HOPS {
+                if current != *txid {
+                    if let Some(known) = self.provenance(&current) {
+                        hops.extend(known.iter().copied());
+                        break;
+                    }
+                }
+                match self.funding_txid(&current) {
+                    Some(funding) => {
+                        hops.push(funding);
+                        current = funding;
+                    }
+                    None => break,
+                }
+            }
+            if !hops.is_empty() {
+                hops.truncate(MAX_PROVENANCE_HOPS);
+                recorded.push((*txid, hops));
+            }
+        }
+        self.provenance.get_or_insert_with(Default::default).extend(recorded);
+    }
+
+    /// The transaction creating the output of the account spent by the first
+    /// input of `txid` that spends from the account
+    fn funding_txid(&self, txid: &BETxid) -> Option<BETxid> {
+        let tx = &self.all_txs.get(txid)?.tx;
+        let outpoint = tx.previous_outputs().into_iter().find(|outpoint| {
+            self.all_txs.get(&outpoint.txid()).is_some_and(|previous_tx| {
+                self.paths.contains_key(&previous_tx.tx.output_script(outpoint.vout()))
+            })
+        })?;
+        Some(outpoint.txid())
+    }
+
+    /// The provenance of `txid`, nearest transaction first, None if it
+    /// doesn't spend outputs of the account
+    pub fn provenance(&self, txid: &BETxid) -> Option<&Vec<BETxid>> {
+        self.provenance.as_ref().and_then(|provenance| provenance.get(txid))
+    }
+
+    /// The current sync generation of the account
+    pub fn sync_generation(&self) -> u64 {
+        self.tx_generations.as_ref().map_or(0, |g| g.current)
+    }
+
+    /// The sync generation in which `txid` was first seen
+    pub fn tx_generation(&self, txid: &BETxid) -> u64 {
+        self.tx_generations.as_ref().and_then(|g| g.first_seen.get(txid)).copied().unwrap_or(0)
+    }
+
+    /// Start a new sync generation, in which the `txids` not seen before are first seen
+    pub fn new_sync_generation<'a>(&mut self, txids: impl Iterator<Item = &'a BETxid>) {
+        let generations = self.tx_generations.get_or_insert_with(Default::default);
+        generations.current += 1;
+        for txid in txids {
+            generations.first_seen.entry(*txid).or_insert(generations.current);
+        }
+    }
+
     pub fn get_path(&self, script_pubkey: &BEScript) -> Result<&DerivationPath, Error> {
         self.paths.get(script_pubkey).ok_or_else(|| Error::ScriptPubkeyNotFound)
     }
@@ -614,12 +1708,205 @@ impl RawAccountCache {
 mod tests {
     use super::*;
     use gdk_common::bitcoin::bip32::Xpub;
-    use gdk_common::bitcoin::{Network, Txid};
+    use gdk_common::bitcoin::{Network, ScriptBuf, Txid};
+    use gdk_common::electrum_client::ScriptStatus;
+    use gdk_common::model::WalletMigrationStep;
     use gdk_common::store::ToCipher;
     use gdk_common::{be::BETxid, NetworkId};
     use std::str::FromStr;
     use tempfile::TempDir;
 
+    #[test]
+    fn test_sync_generations() {
+        let xpub = Xpub::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let txid = |byte: u8| BETxid::Bitcoin(Txid::from_byte_array([byte; 32]));
+        let mut cache = RawAccountCache::new(xpub, true);
+        assert_eq!(cache.sync_generation(), 0);
+
+        cache.new_sync_generation([txid(1), txid(2)].iter());
+        cache.new_sync_generation([txid(1), txid(2), txid(3)].iter());
+        assert_eq!(cache.sync_generation(), 2);
+        assert_eq!(cache.tx_generation(&txid(1)), 1);
+        assert_eq!(cache.tx_generation(&txid(3)), 2);
+
+        // Caches created before generations were introduced
+        cache.tx_generations = None;
+        assert_eq!(cache.tx_generation(&txid(1)), 0);
+        cache.new_sync_generation([txid(4)].iter());
+        assert_eq!(cache.sync_generation(), 1);
+        assert_eq!(cache.tx_generation(&txid(1)), 0);
+        assert_eq!(cache.tx_generation(&txid(4)), 1);
+    }
+
+    #[test]
+    fn test_provenance() {
+        use gdk_common::bitcoin::{self, Amount};
+
+        let xpub = Xpub::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let mine = ScriptBuf::from_bytes(vec![0x51]);
+        let mut cache = RawAccountCache::new(xpub, false);
+        cache.paths.insert(BEScript::Bitcoin(mine.clone()), DerivationPath::master());
+        let mut previous_output = bitcoin::OutPoint::null();
+        let mut txids = vec![];
+        for value in 0..MAX_PROVENANCE_HOPS as u64 + 3 {
+            let tx = BETransaction::Bitcoin(bitcoin::Transaction {
+                version: bitcoin::transaction::Version::TWO,
+                lock_time: bitcoin::absolute::LockTime::ZERO,
+                input: vec![bitcoin::TxIn {
+                    previous_output,
+                    ..Default::default()
+                }],
+                output: vec![bitcoin::TxOut {
+                    value: Amount::from_sat(100_000 - value),
+                    script_pubkey: mine.clone(),
+                }],
+            });
+            let txid = tx.txid();
+            previous_output = bitcoin::OutPoint::new(*txid.ref_bitcoin().unwrap(), 0);
+            cache.all_txs.insert(txid, tx.into());
+            cache.heights.insert(txid, None);
+            txids.push(txid);
+        }
+
+        // Recorded in any order, the funding transaction has no provenance
+        cache.record_provenance(txids.iter().rev());
+        assert_eq!(cache.provenance(&txids[0]), None);
+        assert_eq!(cache.provenance(&txids[1]), Some(&vec![txids[0]]));
+        assert_eq!(cache.provenance(&txids[2]), Some(&vec![txids[1], txids[0]]));
+        let last = cache.provenance(txids.last().unwrap()).unwrap();
+        assert_eq!(last.len(), MAX_PROVENANCE_HOPS);
+        assert_eq!(last[0], txids[txids.len() - 2]);
+    }
+
+    #[test]
+    fn test_utxo_balances() {
+        use gdk_common::bitcoin::{self, Amount};
+
+        let xpub = Xpub::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let mine = ScriptBuf::from_bytes(vec![0x51]);
+        let other = ScriptBuf::from_bytes(vec![0x52]);
+        let mut cache = RawAccountCache::new(xpub, false);
+        cache.paths.insert(BEScript::Bitcoin(mine.clone()), DerivationPath::master());
+        let add_tx = |cache: &mut RawAccountCache, input: bitcoin::OutPoint, outputs: Vec<_>| {
+            let tx = BETransaction::Bitcoin(bitcoin::Transaction {
+                version: bitcoin::transaction::Version::TWO,
+                lock_time: bitcoin::absolute::LockTime::ZERO,
+                input: vec![bitcoin::TxIn {
+                    previous_output: input,
+                    ..Default::default()
+                }],
+                output: outputs
+                    .into_iter()
+                    .map(|(value, script_pubkey): (u64, ScriptBuf)| bitcoin::TxOut {
+                        value: Amount::from_sat(value),
+                        script_pubkey,
+                    })
+                    .collect(),
+            });
+            let txid = tx.txid();
+            cache.all_txs.insert(txid, tx.into());
+            cache.heights.insert(txid, None);
+            cache.update_utxo_balances([txid].iter());
+            txid
+        };
+        let balance = |cache: &RawAccountCache| {
+            cache.utxo_balances.as_ref().unwrap().balances().get("btc").copied()
+        };
+
+        let funding = add_tx(
+            &mut cache,
+            bitcoin::OutPoint::null(),
+            vec![(10_000, mine.clone()), (1, other.clone())],
+        );
+        assert_eq!(balance(&cache), Some(10_000));
+        let outpoint = bitcoin::OutPoint::new(*funding.ref_bitcoin().unwrap(), 0);
+        let spending = add_tx(&mut cache, outpoint, vec![(4_000, mine.clone()), (5_000, other)]);
+        assert_eq!(balance(&cache), Some(4_000));
+        assert_eq!(cache.utxo_balances, Some(cache.compute_utxo_balances()));
+
+        // The spent output is unspent again once the spending tx is dropped
+        cache.heights.remove(&spending);
+        cache.update_utxo_balances([spending].iter());
+        cache.mark_removed([spending].iter());
+        assert_eq!(balance(&cache), Some(10_000));
+        assert_eq!(cache.utxo_balances, Some(cache.compute_utxo_balances()));
+        assert_eq!(cache.removal(&spending), Some(TxRemoval::Dropped));
+
+        // A conflicting spend of the same output replaces it
+        let conflict = add_tx(&mut cache, outpoint, vec![(3_000, mine.clone())]);
+        cache.mark_removed([spending].iter());
+        assert_eq!(balance(&cache), Some(3_000));
+        assert_eq!(cache.removal(&spending), Some(TxRemoval::Replaced));
+        assert_eq!(cache.removed_txids().collect::<Vec<_>>(), vec![&spending]);
+        cache.heights.remove(&conflict);
+        cache.update_utxo_balances([conflict].iter());
+        cache.unmark_removed([spending].iter());
+        assert_eq!(cache.removal(&spending), None);
+
+        assert_eq!(cache.reorgs(&funding), None);
+        cache.record_reorg(funding, 102);
+        let reorgs = cache.record_reorg(funding, 103);
+        assert_eq!(reorgs.count, 2);
+        assert_eq!(cache.reorgs(&funding), Some(reorgs));
+        assert_eq!(reorgs.last_height, 103);
+
+        cache.heights.remove(&funding);
+        cache.update_utxo_balances([funding].iter());
+        assert_eq!(balance(&cache), None);
+        assert_eq!(cache.utxo_balances, Some(UtxoBalances::default()));
+    }
+
+    #[test]
+    fn test_lazy_txs() {
+        use gdk_common::bitcoin::{self, absolute::LockTime, Amount};
+
+        let dir = TempDir::new().unwrap();
+        let xpub = Xpub::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let cipher = xpub.to_cipher().unwrap();
+        let mine = ScriptBuf::from_bytes(vec![0x51]);
+        {
+            let mut store = StoreMeta::new(dir.path(), &cipher, 1000).unwrap();
+            store.make_account(0, xpub, false).unwrap();
+            let acc_store = store.account_cache_mut(0).unwrap();
+            acc_store.paths.insert(BEScript::Bitcoin(mine.clone()), DerivationPath::master());
+            for i in 0..1000 {
+                let tx = BETransaction::Bitcoin(bitcoin::Transaction {
+                    version: bitcoin::transaction::Version::TWO,
+                    lock_time: LockTime::from_consensus(i),
+                    input: vec![],
+                    output: vec![bitcoin::TxOut {
+                        value: Amount::from_sat(1_000),
+                        script_pubkey: mine.clone(),
+                    }],
+                });
+                let txid = tx.txid();
+                acc_store.all_txs.insert(txid, tx.into());
+                acc_store.heights.insert(txid, Some(i));
+                acc_store.update_utxo_balances([txid].iter());
+            }
+        }
+
+        // Logging in loads balances and heights, not the transactions
+        let mut store = StoreMeta::new(dir.path(), &cipher, 1000).unwrap();
+        let acc_store = store.account_cache(0).unwrap();
+        assert_eq!(acc_store.heights.len(), 1000);
+        let balances = acc_store.utxo_balances.as_ref().unwrap().balances();
+        assert_eq!(balances.get("btc"), Some(&1_000_000));
+        assert_eq!(store.txs_loads(), 0);
+
+        // Loaded on first access, once
+        assert_eq!(acc_store.all_txs.len(), 1000);
+        assert_eq!(acc_store.all_txs.len(), 1000);
+        assert_eq!(store.txs_loads(), 1);
+
+        // Unchanged transactions are not written again
+        let txs_path = dir.path().join(Kind::Txs(0).to_string());
+        let modified = std::fs::metadata(&txs_path).unwrap().modified().unwrap();
+        store.store.memos.insert(Txid::from_byte_array([1; 32]), "memo".to_string());
+        store.flush().unwrap();
+        assert_eq!(std::fs::metadata(&txs_path).unwrap().modified().unwrap(), modified);
+    }
+
     #[test]
     fn test_db_roundtrip() {
         let id = NetworkId::Bitcoin(Network::Testnet);
@@ -636,18 +1923,114 @@ mod tests {
 
         let cipher = xpub.to_cipher().unwrap();
         {
-            let mut store = StoreMeta::new(&dir, &cipher, id).unwrap();
+            let mut store = StoreMeta::new(&dir, &cipher, 1000).unwrap();
             store.make_account(0, xpub, true).unwrap(); // The xpub here is incorrect, but that's irrelevant for the sake of the test
             store.account_cache_mut(0).unwrap().heights.insert(txid, Some(1));
             store.store.memos.insert(*txid_btc, "memo".to_string());
         }
 
-        let store = StoreMeta::new(&dir, &cipher, id).unwrap();
+        let store = StoreMeta::new(&dir, &cipher, 1000).unwrap();
 
         assert_eq!(store.account_cache(0).unwrap().heights.get(&txid), Some(&Some(1)));
         assert_eq!(store.store.memos.get(txid_btc), Some(&"memo".to_string()));
     }
 
+    #[test]
+    fn test_script_statuses_version() {
+        let dir = TempDir::new().unwrap();
+        let xpub = Xpub::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let cipher = xpub.to_cipher().unwrap();
+        let statuses: ScriptStatuses = [(ScriptBuf::new(), ScriptStatus::from([1; 32]))].into();
+        {
+            let mut store = StoreMeta::new(dir.path(), &cipher, 1000).unwrap();
+            assert_eq!(store.cache.script_statuses_version, Some(SCRIPT_STATUSES_VERSION));
+            store.make_account(0, xpub, false).unwrap();
+            store.account_cache_mut(0).unwrap().script_statuses = Some(statuses.clone());
+        }
+
+        // Kept by the same version
+        let mut store = StoreMeta::new(dir.path(), &cipher, 1000).unwrap();
+        assert_eq!(store.account_cache(0).unwrap().script_statuses, Some(statuses));
+
+        // Refreshed after a version change
+        store.cache.script_statuses_version = Some(SCRIPT_STATUSES_VERSION - 1);
+        drop(store);
+        let store = StoreMeta::new(dir.path(), &cipher, 1000).unwrap();
+        assert_eq!(store.account_cache(0).unwrap().script_statuses, None);
+        assert_eq!(store.cache.script_statuses_version, Some(SCRIPT_STATUSES_VERSION));
+    }
+
+    #[test]
+    fn test_interrupted_flush() {
+        let dir = TempDir::new().unwrap();
+        let xpub = Xpub::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let cipher = xpub.to_cipher().unwrap();
+        let txid = Txid::from_byte_array([1; 32]);
+        {
+            let mut store = StoreMeta::new(dir.path(), &cipher, 1000).unwrap();
+            store.store.memos.insert(txid, "memo".to_string());
+        }
+
+        // A process killed while flushing leaves a partial file aside
+        let tmp = dir.path().join("store.tmp");
+        std::fs::write(&tmp, b"partial").unwrap();
+        let mut store = StoreMeta::new(dir.path(), &cipher, 1000).unwrap();
+        assert_eq!(store.store.memos.get(&txid), Some(&"memo".to_string()));
+
+        // Removed with the credentials kept for quick login
+        std::fs::write(crate::quick_login::quick_login_path(dir.path()), b"{}").unwrap();
+        store.to_remove();
+        drop(store);
+        assert!(!dir.path().exists());
+    }
+
+    #[test]
+    fn test_reload_settings() {
+        let dir = TempDir::new().unwrap();
+        let xpub = Xpub::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let cipher = xpub.to_cipher().unwrap();
+
+        // Two sessions of the same wallet
+        let mut store1 = StoreMeta::new(dir.path(), &cipher, 1000).unwrap();
+        let mut store2 = StoreMeta::new(dir.path(), &cipher, 1000).unwrap();
+        assert!(store1.reload_settings_if_changed().unwrap().is_none());
+
+        let mut settings = Settings::default();
+        settings.unit = "sats".to_string();
+        settings.revision = Some(1);
+        store2.insert_settings(Some(settings.clone())).unwrap();
+        assert!(store2.reload_settings_if_changed().unwrap().is_none());
+
+        assert_eq!(store1.reload_settings_if_changed().unwrap(), Some(settings.clone()));
+        assert_eq!(store1.get_settings(), Some(settings));
+        assert!(store1.reload_settings_if_changed().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_spends() {
+        let dir = TempDir::new().unwrap();
+        let xpub = Xpub::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let cipher = xpub.to_cipher().unwrap();
+        let txid = |byte: u8| BETxid::Bitcoin(Txid::from_byte_array([byte; 32]));
+        let now = 1_700_000_000;
+
+        {
+            let mut store = StoreMeta::new(dir.path(), &cipher, 1000).unwrap();
+            assert_eq!(store.spent_in_window(now), 0);
+            store.insert_spend(txid(1), 10_000, now - SPEND_WINDOW_SECS).unwrap();
+            store.insert_spend(txid(2), 20_000, now - 60).unwrap();
+            store.insert_spend(txid(2), 20_000, now - 30).unwrap();
+            store.insert_spend(txid(3), 30_000, now).unwrap();
+        }
+
+        // Persisted, and spends out of the window are not counted
+        let store = StoreMeta::new(dir.path(), &cipher, 1000).unwrap();
+        assert_eq!(store.spent_in_window(now), 50_000);
+        assert_eq!(store.spent_in_window(now + SPEND_WINDOW_SECS - 45), 30_000);
+        assert_eq!(store.spent_in_window(now + SPEND_WINDOW_SECS), 0);
+        assert_eq!(store.store.spends.as_ref().unwrap().len(), 2);
+    }
+
     #[test]
     fn test_db_load_static() {
         let id = NetworkId::Bitcoin(Network::Testnet);
@@ -671,7 +2054,7 @@ mod tests {
         }
 
         let cipher = xpub.to_cipher().unwrap();
-        let store = StoreMeta::new(temp_dir, &cipher, id).unwrap();
+        let store = StoreMeta::new(temp_dir, &cipher, 1000).unwrap();
 
         assert_eq!(store.account_cache(0).unwrap().heights.get(&txid), Some(&Some(1)));
         assert_eq!(store.store.memos.get(txid_btc), Some(&"memo".to_string()));
@@ -740,4 +2123,180 @@ mod tests {
 
         assert_eq!(cache_v0.xpub, cache_v1.xpub);
     }
+
+    #[test]
+    fn test_fiat_at_confirmation() {
+        let rate = ConfirmationRate {
+            currency: "EUR".into(),
+            rate: 25_000.5,
+        };
+        let fiat = rate.fiat_at_confirmation(-1_234_567);
+        assert_eq!(fiat.fiat, "-308.65");
+        assert_eq!(fiat.fiat_currency, "EUR");
+        assert_eq!(fiat.fiat_rate, "25000.50000000");
+    }
+
+    #[test]
+    fn test_client_requests() {
+        let dir = TempDir::new().unwrap();
+        let xpub = Xpub::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let cipher = xpub.to_cipher().unwrap();
+        let request = |byte: u8, timestamp: u64| ClientRequest {
+            txid: Txid::from_byte_array([byte; 32]),
+            payjoin: false,
+            timestamp,
+        };
+        let expiry = 100;
+
+        let mut store = StoreMeta::new(dir.path(), &cipher, 1000).unwrap();
+        store.insert_client_request("a", request(1, 1_000), expiry).unwrap();
+        store.insert_client_request("b", request(2, 1_050), expiry).unwrap();
+        assert_eq!(store.client_request("a", 1_099, expiry), Some(&request(1, 1_000)));
+        assert_eq!(store.client_request("a", 1_100, expiry), None);
+        assert_eq!(store.client_request("c", 1_050, expiry), None);
+        drop(store);
+
+        // Persisted, and expired requests are removed when inserting
+        let mut store = StoreMeta::new(dir.path(), &cipher, 1000).unwrap();
+        assert_eq!(store.client_request("b", 1_060, expiry), Some(&request(2, 1_050)));
+        store.insert_client_request("c", request(3, 1_120), expiry).unwrap();
+        assert_eq!(store.store.client_requests.as_ref().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_pending_broadcasts() {
+        let dir = TempDir::new().unwrap();
+        let xpub = Xpub::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let cipher = xpub.to_cipher().unwrap();
+        let queued = |txhash: &str, created_at: u64| QueuedBroadcast {
+            pending: PendingBroadcast {
+                txhash: txhash.into(),
+                transaction: "00".into(),
+                created_at,
+                expired: false,
+            },
+            spent: 1_000,
+        };
+        let max_age = 100;
+
+        let mut store = StoreMeta::new(dir.path(), &cipher, 1000).unwrap();
+        store.queue_broadcast(queued("a", 1_000)).unwrap();
+        store.queue_broadcast(queued("b", 1_050)).unwrap();
+        store.queue_broadcast(queued("a", 1_060)).unwrap();
+        assert_eq!(store.broadcasts_to_retry(1_099, max_age).unwrap().len(), 2);
+        drop(store);
+
+        // Persisted, the old ones expire and are kept until removed
+        let mut store = StoreMeta::new(dir.path(), &cipher, 1000).unwrap();
+        let retry = store.broadcasts_to_retry(1_100, max_age).unwrap();
+        assert_eq!(retry, vec![queued("b", 1_050)]);
+        let pending: Vec<_> = store.pending_broadcasts().map(|q| &q.pending).collect();
+        assert_eq!(pending.len(), 2);
+        assert_eq!((pending[0].txhash.as_str(), pending[0].expired), ("a", true));
+
+        store.expire_broadcast("b").unwrap();
+        assert!(store.broadcasts_to_retry(1_100, max_age).unwrap().is_empty());
+        assert!(store.remove_pending_broadcast("a").unwrap());
+        assert!(!store.remove_pending_broadcast("a").unwrap());
+        assert_eq!(store.pending_broadcasts().count(), 1);
+    }
+
+    #[test]
+    fn test_output_labels() {
+        let dir = TempDir::new().unwrap();
+        let xpub = Xpub::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let cipher = xpub.to_cipher().unwrap();
+        let txid = Txid::from_byte_array([1; 32]);
+        let outpoint = |vout| BEOutPoint::new_bitcoin(txid, vout);
+
+        let mut store = StoreMeta::new(dir.path(), &cipher, 1000).unwrap();
+        store.insert_output_label(&outpoint(0), "savings").unwrap();
+        store.insert_output_label(&outpoint(1), "change").unwrap();
+        drop(store);
+
+        let mut store = StoreMeta::new(dir.path(), &cipher, 1000).unwrap();
+        assert_eq!(store.get_output_label(&outpoint(0)), Some(&"savings".to_string()));
+        assert_eq!(store.get_output_labels(&txid.into()).unwrap().len(), 2);
+
+        // The empty label removes it, and the transaction entry once empty
+        store.insert_output_label(&outpoint(0), "").unwrap();
+        assert_eq!(store.get_output_label(&outpoint(0)), None);
+        store.insert_output_label(&outpoint(1), "").unwrap();
+        assert_eq!(store.output_labels().count(), 0);
+    }
+
+    #[test]
+    fn test_broadcasts() {
+        let dir = TempDir::new().unwrap();
+        let xpub = Xpub::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let cipher = xpub.to_cipher().unwrap();
+        let txid: BETxid = Txid::from_byte_array([1; 32]).into();
+        let details = BroadcastDetails {
+            transaction: "00".into(),
+            broadcast_at: 1_700_000_000,
+            server: "localhost:50001".into(),
+            in_mempool: Some(true),
+        };
+
+        let mut store = StoreMeta::new(dir.path(), &cipher, 1000).unwrap();
+        assert_eq!(store.get_broadcast(&txid), None);
+        store.insert_broadcast(&txid, details.clone()).unwrap();
+        drop(store);
+
+        let store = StoreMeta::new(dir.path(), &cipher, 1000).unwrap();
+        assert_eq!(store.get_broadcast(&txid), Some(&details));
+    }
+
+    #[test]
+    fn test_wallet_migration() {
+        let dir = TempDir::new().unwrap();
+        let xpub = Xpub::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let cipher = xpub.to_cipher().unwrap();
+        let step = WalletMigrationStep {
+            transaction: "00".into(),
+            fee: 100,
+            transaction_vsize: 100,
+            transaction_inputs: vec![],
+            outputs: vec![],
+        };
+        let plan = WalletMigrationPlan {
+            id: "id".into(),
+            fee_rate: 1000,
+            fee: 200,
+            steps: vec![step.clone(), step],
+        };
+
+        let mut store = StoreMeta::new(dir.path(), &cipher, 1000).unwrap();
+        assert_eq!(store.wallet_migration(), None);
+        let mut migration = WalletMigration::new(plan);
+        assert_eq!(migration.signed, [None, None]);
+        assert_eq!(migration.broadcast, [false, false]);
+        migration.signed[0] = Some("01".into());
+        migration.broadcast[0] = true;
+        store.set_wallet_migration(migration.clone()).unwrap();
+        drop(store);
+
+        let store = StoreMeta::new(dir.path(), &cipher, 1000).unwrap();
+        assert_eq!(store.wallet_migration(), Some(&migration));
+    }
+
+    #[test]
+    fn test_wallet_birthday() {
+        let dir = TempDir::new().unwrap();
+        let xpub = Xpub::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let cipher = xpub.to_cipher().unwrap();
+        let birthday = WalletBirthday {
+            height: Some(800_000),
+            timestamp: None,
+            asserted: true,
+        };
+
+        let mut store = StoreMeta::new(dir.path(), &cipher, 1000).unwrap();
+        assert_eq!(store.wallet_birthday(), None);
+        store.set_wallet_birthday(birthday.clone()).unwrap();
+        drop(store);
+
+        let store = StoreMeta::new(dir.path(), &cipher, 1000).unwrap();
+        assert_eq!(store.wallet_birthday(), Some(&birthday));
+    }
 }
@@ -1,4 +1,5 @@
 use crate::error::Error;
+use crate::resolver::Resolver;
 use crate::session::determine_electrum_url;
 use gdk_common::be::{BEScript, BEScriptConvert};
 use gdk_common::bitcoin::{Address, CompressedPublicKey, Network, PublicKey};
@@ -32,7 +33,7 @@ impl SweepOpt {
     /// Build the Electrum client
     pub fn build_client(&self) -> Result<Client, Error> {
         let url = determine_electrum_url(&self.network)?;
-        url.build_client(self.network.proxy.as_deref(), self.timeout)
+        url.build_client(self.network.proxy.as_deref(), self.timeout, &Resolver::new(&self.network))
     }
 
     /// Compute the script_pubkey and script_code
@@ -64,6 +65,7 @@ pub fn get_unspent_outputs_for_private_key(opt: &SweepOpt) -> Result<Vec<Unspent
             pointer: 0,
             pt_idx: unspent.tx_pos as u32,
             satoshi: unspent.value,
+            amount: String::new(),
             subaccount: 0,
             txhash: unspent.tx_hash.to_string(),
             is_internal: false,
@@ -81,6 +83,8 @@ pub fn get_unspent_outputs_for_private_key(opt: &SweepOpt) -> Result<Vec<Unspent
             asset_commitment: None,
             value_commitment: None,
             nonce_commitment: None,
+            label: None,
+            provenance: None,
         })
         .collect();
     Ok(utxos)
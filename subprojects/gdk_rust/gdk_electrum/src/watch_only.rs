@@ -0,0 +1,148 @@
+//! Watch-only access to a singlesig wallet with a username and password.
+//!
+//! The full session stores a bundle with the extended public keys of the
+//! wallet, and the master blinding key on Liquid, in the state directory,
+//! encrypted with a key derived from the password. A watch-only session
+//! decrypts it to log in without the mnemonic.
+//!
+//! The file is named after the network and the username, and starts with the
+//! random salt of the key derivation, followed by the nonce and the
+//! ciphertext as the store files.
+
+use crate::error::Error;
+use gdk_common::aes::aead::NewAead;
+use gdk_common::aes::{Aes256GcmSiv, Key};
+use gdk_common::bitcoin::bip32::Xpub;
+use gdk_common::bitcoin::hashes::{sha256, Hash, HashEngine};
+use gdk_common::bitcoin::hex::DisplayHex;
+use gdk_common::model::AccountXpub;
+use gdk_common::rand::{thread_rng, Rng};
+use gdk_common::store::{Decryptable, Encryptable};
+use gdk_common::util::{pbkdf2_hmac_sha512_256, MasterBlindingKey};
+use gdk_common::{serde_cbor, NetworkParameters};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// The iterations of the derivation of the key from the password
+const KEY_DERIVATION_COST: u32 = 100_000;
+
+const SALT_LEN: usize = 16;
+
+/// The keys needed by a watch-only session of the wallet
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WatchOnlyBundle {
+    /// Identifies the wallet and its store, shared with full sessions
+    pub master_xpub: Xpub,
+
+    pub xpubs: Vec<AccountXpub>,
+
+    pub master_blinding_key: Option<MasterBlindingKey>,
+}
+
+/// The path of the bundle of `username` on `network`
+fn bundle_path(network: &NetworkParameters, username: &str) -> PathBuf {
+    let mut engine = sha256::Hash::engine();
+    engine.input(network.name.as_bytes());
+    engine.input(&[0]);
+    engine.input(username.as_bytes());
+    let id = sha256::Hash::from_engine(engine);
+    let mut path = PathBuf::from(network.state_dir.as_str());
+    path.push(format!("watch_only_{}", id.as_byte_array().to_lower_hex_string()));
+    path
+}
+
+fn cipher(password: &str, salt: &[u8]) -> Aes256GcmSiv {
+    let key = pbkdf2_hmac_sha512_256(password.into(), salt.to_vec(), KEY_DERIVATION_COST);
+    Aes256GcmSiv::new(Key::from_slice(&key))
+}
+
+/// Store `bundle` encrypted with `password`, replacing the one of `username`
+/// if any
+pub(crate) fn save(
+    network: &NetworkParameters,
+    username: &str,
+    password: &str,
+    bundle: &WatchOnlyBundle,
+) -> Result<(), Error> {
+    let mut salt = [0u8; SALT_LEN];
+    thread_rng().fill(&mut salt);
+    let plaintext = serde_cbor::to_vec(bundle)?;
+    let (nonce, ciphertext) = plaintext.encrypt(&cipher(password, &salt))?;
+    std::fs::create_dir_all(&network.state_dir)?;
+    std::fs::write(bundle_path(network, username), [&salt[..], &nonce, &ciphertext].concat())?;
+    Ok(())
+}
+
+/// The bundle of `username`, failing in the same way if it doesn't exist or
+/// the password is wrong
+pub(crate) fn load(
+    network: &NetworkParameters,
+    username: &str,
+    password: &str,
+) -> Result<WatchOnlyBundle, Error> {
+    let data =
+        std::fs::read(bundle_path(network, username)).map_err(|_| Error::UserNotFoundOrInvalid)?;
+    if data.len() < SALT_LEN {
+        return Err(Error::UserNotFoundOrInvalid);
+    }
+    let (salt, encrypted) = data.split_at(SALT_LEN);
+    let plaintext = encrypted
+        .to_vec()
+        .decrypt(&cipher(password, salt))
+        .map_err(|_| Error::UserNotFoundOrInvalid)?;
+    Ok(serde_cbor::from_slice(&plaintext)?)
+}
+
+/// Remove the bundle of `username`, if any
+pub(crate) fn remove(network: &NetworkParameters, username: &str) -> Result<(), Error> {
+    let path = bundle_path(network, username);
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gdk_common::bitcoin::bip32::Xpriv;
+    use gdk_common::bitcoin::NetworkKind;
+    use gdk_common::EC;
+
+    #[test]
+    fn test_bundle() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut network = NetworkParameters::default();
+        network.state_dir = format!("{}", dir.path().display());
+        let xprv = Xpriv::new_master(NetworkKind::Test, &[1u8; 32]).unwrap();
+        let bundle = WatchOnlyBundle {
+            master_xpub: Xpub::from_priv(&EC, &xprv),
+            xpubs: vec![],
+            master_blinding_key: None,
+        };
+
+        save(&network, "alice", "first", &bundle).unwrap();
+        assert_eq!(load(&network, "alice", "first").unwrap().master_xpub, bundle.master_xpub);
+        let wrong_password = load(&network, "alice", "second").unwrap_err();
+        assert!(matches!(wrong_password, Error::UserNotFoundOrInvalid));
+        assert!(matches!(load(&network, "bob", "first"), Err(Error::UserNotFoundOrInvalid)));
+
+        // A new password re-encrypts the bundle, with a new salt
+        let data = std::fs::read(bundle_path(&network, "alice")).unwrap();
+        save(&network, "alice", "second", &bundle).unwrap();
+        assert_ne!(
+            std::fs::read(bundle_path(&network, "alice")).unwrap()[..SALT_LEN],
+            data[..SALT_LEN]
+        );
+        assert!(load(&network, "alice", "first").is_err());
+        assert!(load(&network, "alice", "second").is_ok());
+
+        // Bundles are per network
+        network.name = "other".into();
+        assert!(load(&network, "alice", "second").is_err());
+        network.name = NetworkParameters::default().name;
+
+        remove(&network, "alice").unwrap();
+        assert!(load(&network, "alice", "second").is_err());
+    }
+}
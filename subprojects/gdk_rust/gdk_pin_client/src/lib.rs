@@ -4,10 +4,15 @@ mod pin;
 mod pin_client;
 mod pin_data;
 mod pin_request;
+mod server;
+
+#[cfg(test)]
+mod mock_server;
 
 pub use error::Error;
 pub use pin::Pin;
 pub use pin_client::PinClient;
 pub use pin_data::PinData;
+pub use server::{HttpPinServer, PinServer};
 
 pub(crate) type Result<T> = std::result::Result<T, Error>;
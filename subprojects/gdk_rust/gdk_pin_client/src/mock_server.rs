@@ -0,0 +1,162 @@
+//! An in-memory PIN server implementing the server side of the protocol, for
+//! testing.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::hex::DisplayHex;
+use bitcoin::secp256k1::ecdh::SharedSecret;
+use bitcoin::secp256k1::ecdsa::{RecoverableSignature, RecoveryId};
+use bitcoin::secp256k1::hashes::{sha256, Hash, HashEngine, Hmac, HmacEngine};
+use bitcoin::secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+use block_modes::BlockMode;
+use rand::Rng;
+use serde_json::{json, Value};
+
+use crate::crypto::Aes256Cbc;
+use crate::{PinServer, Result};
+
+/// The wrong PINs after which the server forgets the key of a PIN
+pub(crate) const MAX_ATTEMPTS: u32 = 3;
+
+struct Record {
+    pin_hash: Vec<u8>,
+    key: [u8; 32],
+    failed_attempts: u32,
+}
+
+#[derive(Default)]
+struct State {
+    /// The ephemeral keys of the handshakes, by their public key
+    handshakes: HashMap<PublicKey, SecretKey>,
+
+    /// The PINs, by the public key of their client key
+    records: HashMap<PublicKey, Record>,
+}
+
+pub(crate) struct MockPinServer {
+    key: SecretKey,
+    state: Mutex<State>,
+}
+
+impl MockPinServer {
+    pub(crate) fn new() -> Self {
+        Self {
+            key: SecretKey::new(&mut rand::thread_rng()),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    pub(crate) fn public_key(&self) -> bitcoin::PublicKey {
+        bitcoin::PublicKey::new(PublicKey::from_secret_key(&Secp256k1::new(), &self.key))
+    }
+
+    fn start_handshake(&self) -> Value {
+        let secp = Secp256k1::new();
+        let ephemeral = SecretKey::new(&mut rand::thread_rng());
+        let ske = PublicKey::from_secret_key(&secp, &ephemeral);
+        let message = Message::from_digest(sha256::Hash::hash(&ske.serialize()).to_byte_array());
+        let sig = secp.sign_ecdsa(&message, &self.key);
+        self.state.lock().unwrap().handshakes.insert(ske, ephemeral);
+        json!({
+            "ske": ske.serialize().to_lower_hex_string(),
+            "sig": sig.serialize_compact().to_lower_hex_string(),
+        })
+    }
+
+    fn pin_op(&self, body: &Value, set: bool) -> Result<Value> {
+        let field = |name: &str| -> Result<Vec<u8>> {
+            let hex = body[name].as_str().ok_or(crate::Error::InvalidResponse)?;
+            Ok(Vec::<u8>::from_hex(hex)?)
+        };
+        let cke = field("cke")?;
+        let ske = PublicKey::from_slice(&field("ske")?)?;
+        let encrypted_data = field("encrypted_data")?;
+
+        let mut state = self.state.lock().unwrap();
+        let ephemeral = state.handshakes.remove(&ske).ok_or(crate::Error::HandshakeFailed)?;
+        let shared = SharedSecret::new(&PublicKey::from_slice(&cke)?, &ephemeral);
+
+        let mut engine = HmacEngine::<sha256::Hash>::new(&derive(1, &shared)[..]);
+        engine.input(&cke);
+        engine.input(&encrypted_data);
+        if Hmac::from_engine(engine)[..] != field("hmac_encrypted_data")?[..] {
+            return Err(crate::Error::InvalidHmac);
+        }
+
+        let (iv, encrypted) = encrypted_data.split_at(16);
+        let payload = Aes256Cbc::new_from_slices(&derive(0, &shared)[..], iv)
+            .expect("right lengths")
+            .decrypt_vec(encrypted)?;
+        let (pin_hash, salt) = (&payload[..32], &payload[32..64]);
+        let recovery_id = RecoveryId::from_i32(payload[64] as i32 - 31)?;
+        let signature = RecoverableSignature::from_compact(&payload[65..], recovery_id)?;
+        let message = Message::from_digest(
+            sha256::Hash::hash(&[&cke[..], pin_hash, salt].concat()).to_byte_array(),
+        );
+        let client = Secp256k1::new().recover_ecdsa(&message, &signature)?;
+
+        let random_key = rand::thread_rng().gen::<[u8; 32]>();
+        let key = if set {
+            state.records.insert(
+                client,
+                Record {
+                    pin_hash: pin_hash.to_vec(),
+                    key: random_key,
+                    failed_attempts: 0,
+                },
+            );
+            random_key
+        } else {
+            // Wrong and forgotten PINs get a random key, like the real server
+            match state.records.get_mut(&client) {
+                Some(record) if record.pin_hash == pin_hash => {
+                    record.failed_attempts = 0;
+                    record.key
+                }
+                Some(record) => {
+                    record.failed_attempts += 1;
+                    if record.failed_attempts >= MAX_ATTEMPTS {
+                        state.records.remove(&client);
+                    }
+                    random_key
+                }
+                None => random_key,
+            }
+        };
+
+        let iv = rand::thread_rng().gen::<[u8; 16]>();
+        let encrypted_key = [
+            &iv[..],
+            &Aes256Cbc::new_from_slices(&derive(2, &shared)[..], &iv)
+                .expect("right lengths")
+                .encrypt_vec(&key),
+        ]
+        .concat();
+        let mut engine = HmacEngine::<sha256::Hash>::new(&derive(3, &shared)[..]);
+        engine.input(&encrypted_key);
+        let hmac = Hmac::<sha256::Hash>::from_engine(engine);
+        Ok(json!({
+            "encrypted_key": encrypted_key.to_lower_hex_string(),
+            "hmac": hmac.as_byte_array().to_lower_hex_string(),
+        }))
+    }
+}
+
+impl PinServer for MockPinServer {
+    fn post(&self, endpoint: &str, body: Option<&Value>) -> Result<Value> {
+        match (endpoint, body) {
+            ("start_handshake", None) => Ok(self.start_handshake()),
+            ("set_pin", Some(body)) => self.pin_op(body, true),
+            ("get_pin", Some(body)) => self.pin_op(body, false),
+            _ => Err(crate::Error::ServerCallFailed),
+        }
+    }
+}
+
+fn derive(value: u8, key: &SharedSecret) -> Hmac<sha256::Hash> {
+    let mut engine = HmacEngine::<sha256::Hash>::new(&key.secret_bytes()[..]);
+    engine.input(&[value]);
+    Hmac::from_engine(engine)
+}
@@ -1,4 +1,5 @@
 use std::str::FromStr;
+use std::sync::Arc;
 
 use bitcoin::secp256k1::hashes::{sha256, Hash, HashEngine, Hmac, HmacEngine};
 use bitcoin::PublicKey;
@@ -7,26 +8,35 @@ use url::Url;
 use crate::crypto;
 use crate::crypto::{Aes256Cbc, ClientKey, ServerKey};
 use crate::pin_request::{HandShake, PinServerRequest, ResponseDecryptionKey};
+use crate::server::{HttpPinServer, PinServer};
 use crate::{Pin, PinData, Result};
 
 /// The PIN client used to manage all interactions with the PIN server.
 pub struct PinClient {
-    agent: ureq::Agent,
+    server: Arc<dyn PinServer>,
     pin_server_public_key: PublicKey,
-    pin_server_url: Url,
 }
 
 impl PinClient {
     /*
-     `PinClient::new`, `PinClient::encrypt` and `PinClient::decrypt` are the
-      only methods exposed publicly.
+     `PinClient::new`, `PinClient::with_server`, `PinClient::encrypt` and
+      `PinClient::decrypt` are the only methods exposed publicly.
     */
 
     #[inline]
     pub fn new(agent: ureq::Agent, pin_server_url: Url, pin_server_public_key: PublicKey) -> Self {
+        Self::with_server(
+            Arc::new(HttpPinServer::new(agent, pin_server_url)),
+            pin_server_public_key,
+        )
+    }
+
+    /// A client of `server`, whose handshakes are signed by
+    /// `pin_server_public_key`.
+    #[inline]
+    pub fn with_server(server: Arc<dyn PinServer>, pin_server_public_key: PublicKey) -> Self {
         Self {
-            agent,
-            pin_server_url,
+            server,
             pin_server_public_key,
         }
     }
@@ -86,14 +96,10 @@ impl PinClient {
     }
 
     fn handshake_server(&self) -> Result<HandShake> {
-        let response = self
-            .agent
-            .post(&format!("{}/start_handshake", self.pin_server_url))
-            .set("content-length", "0")
-            .call()
-            .map_err(|_| crate::Error::HandshakeFailed)?;
+        let response =
+            self.server.post("start_handshake", None).map_err(|_| crate::Error::HandshakeFailed)?;
 
-        serde_json::from_reader(response.into_reader()).map_err(Into::into)
+        serde_json::from_value(response).map_err(Into::into)
     }
 
     fn call_server(&self, request: &PinServerRequest, op: ServerOp) -> Result<PinServerResponse> {
@@ -103,12 +109,11 @@ impl PinClient {
         };
 
         let response = self
-            .agent
-            .post(&format!("{}/{endpoint}", self.pin_server_url))
-            .send_json(request)
+            .server
+            .post(endpoint, Some(&serde_json::to_value(request)?))
             .map_err(|_| crate::Error::ServerCallFailed)?;
 
-        serde_json::from_reader(response.into_reader()).map_err(Into::into)
+        serde_json::from_value(response).map_err(Into::into)
     }
 }
 
@@ -176,6 +181,7 @@ mod tests {
     use serde_json::json;
 
     use super::*;
+    use crate::mock_server::{MockPinServer, MAX_ATTEMPTS};
 
     /// Tests that a PIN server response deserializes correctly from a JSON,
     /// that it verifies against its Hmac key and that its server key can be
@@ -210,4 +216,42 @@ mod tests {
 
         assert_eq!(expected, response.decrypt_server_key(&decryption_key).unwrap());
     }
+
+    #[test]
+    fn encrypt_decrypt() {
+        let server = Arc::new(MockPinServer::new());
+        let client = PinClient::with_server(server.clone(), server.public_key());
+        let pin_data = client.encrypt(b"secret", &"123456".into()).unwrap();
+
+        assert_eq!(client.decrypt(&pin_data, &"123456".into()).unwrap(), b"secret");
+
+        // A right PIN resets the failed attempts
+        for _ in 1..MAX_ATTEMPTS {
+            assert_eq!(client.decrypt(&pin_data, &"654321".into()), Err(crate::Error::InvalidPin));
+        }
+        assert!(client.decrypt(&pin_data, &"123456".into()).is_ok());
+
+        // Too many wrong PINs invalidate the data, even for the right PIN
+        for _ in 0..MAX_ATTEMPTS {
+            assert_eq!(client.decrypt(&pin_data, &"654321".into()), Err(crate::Error::InvalidPin));
+        }
+        assert_eq!(client.decrypt(&pin_data, &"123456".into()), Err(crate::Error::InvalidPin));
+
+        // The handshake must be signed by the server key
+        let other = MockPinServer::new();
+        let client = PinClient::with_server(server, other.public_key());
+        assert!(client.encrypt(b"secret", &"123456".into()).is_err());
+    }
+
+    #[test]
+    fn server_unreachable() {
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        drop(listener);
+
+        let key = MockPinServer::new().public_key();
+        let client = PinClient::new(ureq::Agent::new(), url, key);
+        let err = client.encrypt(b"secret", &"123456".into()).unwrap_err();
+        assert_eq!(err, crate::Error::HandshakeFailed);
+    }
 }
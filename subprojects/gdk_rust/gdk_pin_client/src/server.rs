@@ -0,0 +1,46 @@
+use serde_json::Value;
+use url::Url;
+
+use crate::Result;
+
+/// The transport of the requests of a [`PinClient`](crate::PinClient) to a
+/// PIN server, by default an [`HttpPinServer`].
+///
+/// The server counts the failed attempts of each PIN and forgets its key
+/// after too many of them, so the encrypted data can't be decrypted anymore.
+pub trait PinServer: Send + Sync {
+    /// Post `body` to `endpoint` (`start_handshake`, `get_pin` or `set_pin`)
+    /// and return the JSON response.
+    fn post(&self, endpoint: &str, body: Option<&Value>) -> Result<Value>;
+}
+
+/// A PIN server reached over HTTP.
+pub struct HttpPinServer {
+    agent: ureq::Agent,
+    url: Url,
+}
+
+impl HttpPinServer {
+    /// `agent` should be built with the session proxy.
+    #[inline]
+    pub fn new(agent: ureq::Agent, url: Url) -> Self {
+        Self {
+            agent,
+            url,
+        }
+    }
+}
+
+impl PinServer for HttpPinServer {
+    fn post(&self, endpoint: &str, body: Option<&Value>) -> Result<Value> {
+        let request = self.agent.post(&format!("{}/{endpoint}", self.url));
+
+        let response = match body {
+            Some(body) => request.send_json(body),
+            None => request.set("content-length", "0").call(),
+        }
+        .map_err(|_| crate::Error::ServerCallFailed)?;
+
+        serde_json::from_reader(response.into_reader()).map_err(Into::into)
+    }
+}
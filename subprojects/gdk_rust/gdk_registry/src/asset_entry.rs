@@ -54,6 +54,44 @@ pub struct AssetEntry {
     pub version: u8,
 }
 
+/// A summary of an [`AssetEntry`] returned by
+/// [`get_asset_metadata`](crate::get_asset_metadata).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AssetMetadata {
+    /// The identifier of the asset.
+    pub asset_id: AssetId,
+
+    /// Name of the asset.
+    pub name: String,
+
+    /// Ticker of the asset.
+    pub ticker: Option<String>,
+
+    /// Precision of the asset as the number of digits after the decimal
+    /// separator.
+    pub precision: u8,
+
+    /// The internet domain of the asset issuer, if any.
+    pub domain: Option<String>,
+
+    /// Whether the contract, and so the issuer domain, is committed in the
+    /// asset id. See [`AssetEntry::verifies`].
+    pub verified: bool,
+}
+
+impl From<&AssetEntry> for AssetMetadata {
+    fn from(entry: &AssetEntry) -> Self {
+        Self {
+            asset_id: entry.asset_id,
+            name: entry.name.clone(),
+            ticker: entry.ticker.clone(),
+            precision: entry.precision,
+            domain: entry.domain().map(ToOwned::to_owned),
+            verified: entry.verifies().unwrap_or(false),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Prevout {
     txid: Txid,
@@ -89,6 +127,11 @@ impl AssetEntry {
         serde_json::to_string(&self.contract).map_err(Into::into)
     }
 
+    /// The internet domain of the asset issuer, if any.
+    pub fn domain(&self) -> Option<&str> {
+        self.entity["domain"].as_str()
+    }
+
     pub(crate) fn issuance_prevout(&self) -> OutPoint {
         OutPoint::new(self.issuance_prevout.txid, self.issuance_prevout.vout)
     }
@@ -118,3 +161,30 @@ impl AssetEntry {
             && self.entity == self.contract["entity"])
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TETHER_ENTRY: &str = r#"{"asset_id":"ce091c998b83c78bb71a632313ba3760f1763d9cfcffae02258ffa9865a37bd2","contract":{"entity":{"domain":"tether.to"},"issuer_pubkey":"0337cceec0beea0232ebe14cba0197a9fbd45fcf2ec946749de920e71434c2b904","name":"Tether USD","precision":8,"ticker":"USDt","version":0},"issuance_txin":{"txid":"abb4080d91849e933ee2ed65da6b436f7c385cf363fb4aa08399f1e27c58ff3d","vin":0},"issuance_prevout":{"txid":"9596d259270ef5bac0020435e6d859aea633409483ba64e232b8ba04ce288668","vout":0},"name":"Tether USD","ticker":"USDt","precision":8,"entity":{"domain":"tether.to"},"version":0,"issuer_pubkey":"0337cceec0beea0232ebe14cba0197a9fbd45fcf2ec946749de920e71434c2b904"}"#;
+
+    #[test]
+    fn test_metadata() {
+        let entry: AssetEntry = serde_json::from_str(TETHER_ENTRY).unwrap();
+        let metadata = AssetMetadata::from(&entry);
+        assert_eq!(metadata.asset_id, entry.asset_id);
+        assert_eq!(metadata.name, "Tether USD");
+        assert_eq!(metadata.ticker.as_deref(), Some("USDt"));
+        assert_eq!(metadata.precision, 8);
+        assert_eq!(metadata.domain.as_deref(), Some("tether.to"));
+        assert!(metadata.verified);
+
+        // A domain not committed in the issuance must not validate
+        let mut forged = entry.clone();
+        forged.contract["entity"]["domain"] = "tether.example".into();
+        forged.entity["domain"] = "tether.example".into();
+        let metadata = AssetMetadata::from(&forged);
+        assert_eq!(metadata.domain.as_deref(), Some("tether.example"));
+        assert!(!metadata.verified);
+    }
+}
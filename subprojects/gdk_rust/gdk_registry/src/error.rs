@@ -40,6 +40,11 @@ pub enum Error {
     #[error("InvalidNetwork({0})")]
     InvalidNetwork(String),
 
+    /// A registry file doesn't match its signature, or the key to check it
+    /// is invalid.
+    #[error("Invalid signature: {0}")]
+    InvalidSignature(String),
+
     /// Wraps IO errors.
     #[error(transparent)]
     Io(#[from] std::io::Error),
@@ -1,20 +1,28 @@
 use std::collections::HashMap;
-use std::io::BufReader;
+use std::io::{self, BufReader, Read};
 use std::time::{Duration, Instant};
 
 use gdk_common::log::info;
 use gdk_common::ureq;
 
 use crate::Result;
-use serde_json::Value;
 
-/// Returns `None` if the response status is `304 Not Modified`.
+/// Minimum number of bytes read between two progress reports.
+const PROGRESS_STEP: u64 = 64 * 1024;
+
+/// Called with the bytes of the body read so far and the total announced by
+/// the `Content-Length` header.
+pub(crate) type Progress<'a> = &'a dyn Fn(u64, Option<u64>);
+
+/// Returns the body and its last modification date, `None` if the response
+/// status is `304 Not Modified`.
 pub(crate) fn call(
     url: &str,
     agent: &ureq::Agent,
     last_modified: &str,
     custom_params: &HashMap<String, String>,
-) -> Result<Option<(Value, String)>> {
+    progress: Progress,
+) -> Result<Option<(Vec<u8>, String)>> {
     let start = Instant::now();
 
     let mut request =
@@ -38,14 +46,64 @@ pub(crate) fn call(
         .unwrap_or_default()
         .to_string();
 
-    // `respone.into_json()` is slow because of many syscalls. See:
-    // https://github.com/algesten/ureq/pull/506.
-    let buffered_reader = BufReader::new(response.into_reader());
-    let value = serde_json::from_reader(buffered_reader)?;
+    // The length of a compressed body doesn't match the decoded bytes we count.
+    let total = match response.header("Content-Encoding") {
+        None | Some("identity") => {
+            response.header("Content-Length").and_then(|len| len.parse().ok())
+        }
+        Some(_) => None,
+    };
+
+    let reader = ProgressReader {
+        inner: response.into_reader(),
+        read: 0,
+        reported: 0,
+        total,
+        progress,
+    };
+
+    // The body is kept whole to check its signature before parsing it.
+    let mut body = vec![];
+    BufReader::new(reader).read_to_end(&mut body)?;
 
     info!("END call {} {} took: {:?}", &url, status, start.elapsed());
 
-    Ok(Some((value, last_modified)))
+    Ok(Some((body, last_modified)))
+}
+
+/// Returns the detached signature at `url`.
+pub(crate) fn get_signature(
+    url: &str,
+    agent: &ureq::Agent,
+    custom_params: &HashMap<String, String>,
+) -> Result<String> {
+    let mut request = agent.get(url).timeout(Duration::from_secs(30));
+    for param in custom_params {
+        request = request.set(param.0, param.1);
+    }
+    Ok(request.call()?.into_string()?)
+}
+
+/// Reports the bytes read from `inner` every [`PROGRESS_STEP`] and at the end
+/// of the body.
+struct ProgressReader<'a, R> {
+    inner: R,
+    read: u64,
+    reported: u64,
+    total: Option<u64>,
+    progress: Progress<'a>,
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        if (n == 0 && self.read != self.reported) || self.read - self.reported >= PROGRESS_STEP {
+            self.reported = self.read;
+            (self.progress)(self.read, self.total);
+        }
+        Ok(n)
+    }
 }
 
 #[cfg(test)]
@@ -76,12 +134,16 @@ mod test {
                 ),
             );
 
-            let (_, last_modified) =
-                call(&server.url_str(what.endpoint()), &agent, "", &HashMap::new())
+            let reports = std::cell::RefCell::new(vec![]);
+            let progress = |read, total| reports.borrow_mut().push((read, total));
+            let (body, last_modified) =
+                call(&server.url_str(what.endpoint()), &agent, "", &HashMap::new(), &progress)
                     .unwrap()
                     .unwrap();
 
+            assert_eq!(body, b"{}");
             assert_eq!(expected_last_modified, last_modified);
+            assert_eq!(reports.into_inner(), vec![(2, Some(2))]);
         }
     }
 }
@@ -6,10 +6,12 @@
 //! preserving privacy. It also provides asset icons.
 //!
 //! A small number of assets information are hard-coded within this library,
-//! others are fetched from a default "asset registry" or a user-defined one.
+//! others are fetched from a default "asset registry" or a user-defined one,
+//! like a mirror whose files can be required to be signed by a given key.
 //!
 //! The main methods are [`get_assets`] and [`refresh_assets`], but the library
-//! must first be initialized by calling [`init`].
+//! must first be initialized by calling [`init`]. Refreshes can also run in the
+//! background with [`start_refresh_assets`], reporting their progress.
 //!
 //! Assets metadata are informations like the name of an asset, the ticker, and
 //! the precision (decimal places of amounts) which define how wallets show
@@ -32,12 +34,12 @@ mod hard_coded;
 mod http;
 mod last_modified;
 mod params;
+mod refresh;
 mod registry;
 mod registry_infos;
+mod signature;
 
 use std::path::Path;
-use std::sync::Arc;
-use std::thread;
 
 use assets_or_icons::AssetsOrIcons;
 use cache::Cache;
@@ -46,12 +48,14 @@ use last_modified::LastModified;
 use params::GetAssetsQuery;
 use registry_infos::RegistrySource;
 
-pub use asset_entry::AssetEntry;
+pub use asset_entry::{AssetEntry, AssetMetadata};
 pub use error::{Error, Result};
 pub use hard_coded::policy_asset_id;
 pub use params::{
-    AssetCategory, Config, ElementsNetwork, GetAssetsBuilder, GetAssetsParams, RefreshAssetsParams,
+    AssetCategory, Config, ElementsNetwork, GetAssetMetadataParams, GetAssetsBuilder,
+    GetAssetsParams, RefreshAssetsParams,
 };
+pub use refresh::{start_refresh_assets, RefreshEvent, RefreshHandle, RefreshId, RefreshListener};
 pub use registry_infos::RegistryInfos;
 
 /// Initialize the library by specifying the root directory where the cached
@@ -151,6 +155,17 @@ pub fn get_assets(params: GetAssetsParams) -> Result<RegistryInfos> {
     Ok(cache.to_registry(from_cache))
 }
 
+/// Returns the metadata of a single asset, or `None` if the asset is not in
+/// the local copy of the registry.
+///
+/// Unlike [`get_assets`], this function never reaches the asset registry and
+/// returns whether the asset contract commits to the asset id, so that callers
+/// know if the issuer domain can be trusted.
+pub fn get_asset_metadata(params: GetAssetMetadataParams) -> Result<Option<AssetMetadata>> {
+    let registry = registry::get_full(params.config.network)?;
+    Ok(registry.assets.get(&params.asset_id).map(AssetMetadata::from))
+}
+
 /// Returns informations about a set of assets and related icons.
 ///
 /// Results could come from the persisted cached value when `params.refresh`
@@ -158,34 +173,11 @@ pub fn get_assets(params: GetAssetsParams) -> Result<RegistryInfos> {
 /// default, the Liquid mainnet network is used and the asset registry used is
 /// managed by Blockstream and no proxy is used to access it. This default
 /// configuration can be overridden by providing the `params.config` parameter.
+///
+/// Blocks until the refresh completes, see [`start_refresh_assets`] to run it
+/// in the background.
 pub fn refresh_assets(params: RefreshAssetsParams) -> Result<RegistrySource> {
-    if !params.wants_something() {
-        return Err(Error::BothAssetsIconsFalse);
-    }
-
-    let params = Arc::new(params);
-
-    let assets_handle = {
-        let params = Arc::clone(&params);
-        thread::spawn(move || {
-            params
-                .wants_assets()
-                .then(|| registry::refresh_assets(&params))
-                .transpose()
-                .map(Option::unwrap_or_default)
-        })
-    };
-
-    let icons_source = params
-        .wants_icons()
-        // forces multiline formatting
-        .then(|| registry::refresh_icons(&params))
-        .transpose()?
-        .unwrap_or_default();
-
-    let assets_source = assets_handle.join().unwrap()?;
-
-    Ok(RegistrySource::merge(assets_source, icons_source))
+    start_refresh_assets(params, None)?.wait()
 }
 
 #[cfg(test)]
@@ -196,14 +188,18 @@ mod tests {
     use crate::params::GetAssetsBuilder;
     use crate::params::{AssetCategory, ElementsNetwork};
     use gdk_common::bitcoin::bip32::Xpub;
+    use gdk_common::bitcoin::secp256k1::{PublicKey, SecretKey};
     use gdk_common::elements::AssetId;
     use gdk_common::log::info;
+    use gdk_common::EC;
     use httptest::{matchers::*, responders::*, Expectation, Server};
     use rusty_fork::rusty_fork_test;
     use serde_json::Value;
     use std::collections::{HashMap, HashSet};
     use std::path::Path;
     use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
 
     use tempfile::TempDir;
 
@@ -293,6 +289,16 @@ mod tests {
         super::refresh_assets(params)
     }
 
+    /// A listener recording the refresh events.
+    fn recorder() -> (RefreshListener, Arc<Mutex<Vec<RefreshEvent>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let listener: RefreshListener = {
+            let events = Arc::clone(&events);
+            Arc::new(move |event: &RefreshEvent| events.lock().unwrap().push(event.clone()))
+        };
+        (listener, events)
+    }
+
     fn get_full_registry() -> RegistryInfos {
         registry::get_full(ElementsNetwork::Liquid).unwrap()
     }
@@ -377,6 +383,183 @@ mod tests {
             }
         }
 
+        #[test]
+        fn test_refresh_assets_in_background() {
+            let _ = env_logger::try_init();
+
+            let temp_dir = TempDir::new().unwrap();
+            info!("{:?}", temp_dir);
+            init(&temp_dir).unwrap();
+
+            // A slow registry answering once, the second refresh joins the
+            // first one.
+            let server = Server::run();
+            let (body, last_modified) = AssetsOrIcons::Assets.liquid_data();
+            let len = body.len() as u64;
+            server.expect(
+                Expectation::matching(request::method_path("GET", "/index.json"))
+                    .times(1)
+                    .respond_with(delay_and_then(
+                        Duration::from_millis(500),
+                        status_code(200).body(body).append_header("last-modified", last_modified),
+                    )),
+            );
+            let config = Config {
+                url: format!("http://localhost:{}", server.addr().port()),
+                ..Default::default()
+            };
+
+            let (listener, events) = recorder();
+            let params = RefreshAssetsParams::new(true, false, config.clone(), None);
+            let first = start_refresh_assets(params, Some(listener)).unwrap();
+            let params = RefreshAssetsParams::new(true, false, config, None);
+            let second = start_refresh_assets(params, None).unwrap();
+            let refresh_id = first.id();
+            assert_eq!(second.id(), refresh_id);
+            assert_eq!(second.wait().unwrap(), RegistrySource::Downloaded);
+            assert_eq!(first.wait().unwrap(), RegistrySource::Downloaded);
+
+            let mut events = events.lock().unwrap().clone();
+            let completed = events.pop().unwrap();
+            let expected = RefreshEvent::Completed {
+                refresh_id,
+                not_modified: false,
+                error: None,
+            };
+            assert_eq!(completed, expected);
+            assert!(!events.is_empty());
+            for event in &events {
+                let progress = matches!(
+                    event,
+                    RefreshEvent::Progress { what, total: Some(total), .. }
+                        if what == "assets" && *total == len
+                );
+                assert!(progress, "{:?}", event);
+            }
+            let done = matches!(
+                events.last(),
+                Some(RefreshEvent::Progress { downloaded, .. }) if *downloaded == len
+            );
+            assert!(done, "{:?}", events.last());
+
+            // Once completed a new refresh is started, here not modified.
+            let joined_id = refresh_id;
+            test_refresh_assets(true, true, false).unwrap();
+            let server = Server::run();
+            let config = local_server_config(&server, true, true);
+            let xpub = Xpub::from_str(DEFAULT_XPUB).unwrap();
+            let params = RefreshAssetsParams::new(true, true, config, Some(xpub));
+            let (listener, events) = recorder();
+            let now = Instant::now();
+            let handle = start_refresh_assets(params, Some(listener)).unwrap();
+            let refresh_id = handle.id();
+            assert_eq!(handle.wait().unwrap(), RegistrySource::NotModified);
+            assert!(now.elapsed() < Duration::from_secs(5), "not modified took {:?}", now.elapsed());
+            assert_ne!(refresh_id, joined_id);
+            let expected = RefreshEvent::Completed {
+                refresh_id,
+                not_modified: true,
+                error: None,
+            };
+            assert_eq!(*events.lock().unwrap(), vec![expected]);
+        }
+
+        #[test]
+        fn test_signed_registry() {
+            let _ = env_logger::try_init();
+
+            let temp_dir = TempDir::new().unwrap();
+            info!("{:?}", temp_dir);
+            init(&temp_dir).unwrap();
+
+            let secret_key = SecretKey::from_slice(&[7; 32]).unwrap();
+            let public_key = PublicKey::from_secret_key(&EC, &secret_key).to_string();
+            let test_asset =
+                AssetId::from_str("123465c803ae336c62180e52d94ee80d80828db54df9bedbb9860060f49de2eb")
+                    .unwrap();
+
+            // The files signed by the key, and the assets tampered by a mirror
+            let signed = |what: AssetsOrIcons| {
+                let (body, last_modified) = what.liquid_data();
+                let signature = signature::test::sign(body.as_bytes(), &secret_key);
+                (body, signature, last_modified)
+            };
+            let (assets, assets_signature, last_modified) = signed(AssetsOrIcons::Assets);
+            let mut tampered: serde_json::Map<String, Value> =
+                serde_json::from_str(&assets).unwrap();
+            tampered.remove(&test_asset.to_string());
+            let tampered = serde_json::to_string(&tampered).unwrap();
+
+            let serve = |server: &Server, what: AssetsOrIcons, body: &str, signature: &str| {
+                server.expect(
+                    Expectation::matching(request::method_path("GET", what.endpoint()))
+                        .respond_with(
+                            status_code(200)
+                                .body(body.to_string())
+                                .append_header("last-modified", last_modified.clone()),
+                        ),
+                );
+                let signature_path = format!("{}.sig", what.endpoint());
+                server.expect(
+                    Expectation::matching(request::method_path("GET", signature_path))
+                        .respond_with(status_code(200).body(signature.to_string())),
+                );
+            };
+            let refresh = |config: Config, icons: bool| {
+                let (listener, events) = recorder();
+                let params = RefreshAssetsParams::new(true, icons, config, None);
+                let source = start_refresh_assets(params, Some(listener)).unwrap().wait().unwrap();
+                let warnings = events
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .filter(|event| matches!(event, RefreshEvent::Warning { .. }))
+                    .count();
+                (source, warnings)
+            };
+
+            // A tampered registry is rejected, the hard coded assets are kept
+            let before = get_full_registry();
+            let tampering = Server::run();
+            serve(&tampering, AssetsOrIcons::Assets, &tampered, &assets_signature);
+            let tampering_config = Config {
+                url: format!("http://localhost:{}", tampering.addr().port()),
+                public_key: Some(public_key.clone()),
+                ..Default::default()
+            };
+            assert_eq!(refresh(tampering_config.clone(), false), (RegistrySource::NotModified, 1));
+            assert_eq!(get_full_registry(), before);
+
+            // The signed registry is used, with the icons from another server
+            let mirror = Server::run();
+            serve(&mirror, AssetsOrIcons::Assets, &assets, &assets_signature);
+            let icons_server = Server::run();
+            let (icons, icons_signature, _) = signed(AssetsOrIcons::Icons);
+            serve(&icons_server, AssetsOrIcons::Icons, &icons, &icons_signature);
+            let config = Config {
+                url: format!("http://localhost:{}", mirror.addr().port()),
+                icons_url: Some(format!("http://localhost:{}", icons_server.addr().port())),
+                public_key: Some(public_key.clone()),
+                ..Default::default()
+            };
+            assert_eq!(refresh(config, true), (RegistrySource::Downloaded, 0));
+            let downloaded = get_full_registry();
+            assert!(downloaded.assets.contains_key(&test_asset));
+            assert!(downloaded.icons.len() > before.icons.len());
+
+            // Tampered again, the downloaded copy is kept
+            assert_eq!(refresh(tampering_config.clone(), false), (RegistrySource::NotModified, 1));
+            assert_eq!(get_full_registry(), downloaded);
+
+            // An invalid key fails the refresh
+            let invalid_key = Config {
+                public_key: Some("02".into()),
+                ..tampering_config
+            };
+            let params = RefreshAssetsParams::new(true, false, invalid_key, None);
+            assert!(start_refresh_assets(params, None).is_err());
+        }
+
         #[test]
         fn test_get_assets() {
             let _ = env_logger::try_init();
@@ -426,6 +609,27 @@ mod tests {
             assert_eq!(res.source, Some(RegistrySource::Cache));
         }
 
+        #[test]
+        fn test_get_asset_metadata() {
+            let _ = env_logger::try_init();
+
+            let temp_dir = TempDir::new().unwrap();
+            info!("{:?}", temp_dir);
+            init(&temp_dir).unwrap();
+
+            let get = |id: AssetId| {
+                let params = GetAssetMetadataParams::new(id, Config::default());
+                super::get_asset_metadata(params).unwrap()
+            };
+
+            let policy_asset = policy_asset_id(ElementsNetwork::Liquid);
+            let metadata = get(policy_asset).unwrap();
+            assert_eq!(metadata.ticker.as_deref(), Some("L-BTC"));
+
+            // unknown assets are not an error
+            assert!(get(AssetId::default()).is_none());
+        }
+
         #[test]
         fn test_corrupted_registry() {
             let _ = env_logger::try_init();
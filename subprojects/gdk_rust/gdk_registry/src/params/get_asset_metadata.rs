@@ -0,0 +1,25 @@
+use gdk_common::elements::AssetId;
+use serde::{Deserialize, Serialize};
+
+use super::Config;
+
+/// Parameters passed to [`crate::get_asset_metadata`].
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GetAssetMetadataParams {
+    /// The asset to look up.
+    pub(crate) asset_id: AssetId,
+
+    /// Options to configure network used and registry connection.
+    #[serde(default)]
+    pub(crate) config: Config,
+}
+
+impl GetAssetMetadataParams {
+    /// Creates a new [`crate::GetAssetMetadataParams`].
+    pub fn new(asset_id: AssetId, config: Config) -> Self {
+        Self {
+            asset_id,
+            config,
+        }
+    }
+}
@@ -1,5 +1,7 @@
+mod get_asset_metadata;
 mod get_assets;
 mod refresh_assets;
 
+pub use get_asset_metadata::*;
 pub use get_assets::*;
 pub use refresh_assets::*;
@@ -2,11 +2,12 @@ use std::collections::HashMap;
 use std::fmt;
 
 use gdk_common::bitcoin::bip32::Xpub;
-use gdk_common::ureq;
+use gdk_common::bitcoin::secp256k1::PublicKey;
+use gdk_common::{ureq, NetworkId, NetworkParameters};
 use serde::{Deserialize, Serialize};
 
 use crate::assets_or_icons::AssetsOrIcons;
-use crate::Result;
+use crate::{signature, Error, Result};
 use gdk_common::network;
 
 const BASE_URL: &str = "http://assets.blockstream.info";
@@ -53,7 +54,24 @@ impl RefreshAssetsParams {
     }
 
     pub(crate) fn url(&self, what: AssetsOrIcons) -> String {
-        format!("{}{}", self.config.url, what.endpoint())
+        let base_url = match (what, &self.config.icons_url) {
+            (AssetsOrIcons::Icons, Some(icons_url)) if !icons_url.is_empty() => icons_url,
+            _ => &self.config.url,
+        };
+        format!("{}{}", base_url, what.endpoint())
+    }
+
+    /// The url of the detached signature of the file at [`Self::url`].
+    pub(crate) fn signature_url(&self, what: AssetsOrIcons) -> String {
+        format!("{}.sig", self.url(what))
+    }
+
+    /// The key signing the registry files, if their signatures are checked.
+    pub(crate) fn public_key(&self) -> Result<Option<PublicKey>> {
+        match self.config.public_key.as_deref().filter(|key| !key.is_empty()) {
+            Some(key) => signature::parse_public_key(key).map(Some),
+            None => Ok(None),
+        }
     }
 
     pub(crate) const fn wants_something(&self) -> bool {
@@ -84,16 +102,54 @@ pub struct Config {
 
     pub(crate) url: String,
 
+    /// The base url of the icons, `url` if not set.
+    #[serde(default)]
+    pub(crate) icons_url: Option<String>,
+
+    /// The hex public key signing the registry files. If set the files are
+    /// updated only if their detached signature, served at their url with
+    /// the `.sig` extension, is valid.
+    #[serde(default)]
+    pub(crate) public_key: Option<String>,
+
     #[serde(default)]
     pub(crate) custom_headers: HashMap<String, String>,
 }
 
+impl Config {
+    /// The configuration of the registry of the Liquid network `network`.
+    pub fn from_network_parameters(network: &NetworkParameters) -> Result<Self> {
+        let elements_network = match network.id() {
+            NetworkId::Elements(gdk_common::ElementsNetwork::Liquid) => ElementsNetwork::Liquid,
+            NetworkId::Elements(gdk_common::ElementsNetwork::LiquidTestnet) => {
+                ElementsNetwork::LiquidTestnet
+            }
+            NetworkId::Elements(gdk_common::ElementsNetwork::ElementsRegtest) => {
+                ElementsNetwork::ElementsRegtest
+            }
+            NetworkId::Bitcoin(_) => {
+                return Err(Error::InvalidNetwork("not a Liquid network".into()))
+            }
+        };
+        Ok(Self {
+            network: elements_network,
+            proxy: network.proxy.clone(),
+            url: network.registry_base_url()?,
+            icons_url: network.registry_icons_url().map(String::from),
+            public_key: network.registry_public_key().map(String::from),
+            custom_headers: HashMap::new(),
+        })
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             network: ElementsNetwork::Liquid,
             proxy: None,
             url: BASE_URL.to_owned(),
+            icons_url: None,
+            public_key: None,
             custom_headers: HashMap::new(),
         }
     }
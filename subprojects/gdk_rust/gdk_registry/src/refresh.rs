@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use gdk_common::bitcoin::bip32::Xpub;
+use gdk_common::log::{info, warn};
+use gdk_common::once_cell::sync::Lazy;
+use serde::Serialize;
+
+use crate::assets_or_icons::AssetsOrIcons;
+use crate::params::{ElementsNetwork, RefreshAssetsParams};
+use crate::registry_infos::RegistrySource;
+use crate::{registry, Error, Result};
+
+/// Identifies a refresh started with [`start_refresh_assets`].
+pub type RefreshId = u64;
+
+/// Called with the events of a refresh, from the thread running it.
+pub type RefreshListener = Arc<dyn Fn(&RefreshEvent) + Send + Sync>;
+
+/// Events emitted while refreshing the registry.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RefreshEvent {
+    /// Some bytes of the assets or the icons have been downloaded.
+    Progress {
+        /// The refresh downloading.
+        refresh_id: RefreshId,
+
+        /// Either `"assets"` or `"icons"`.
+        what: String,
+
+        /// Number of bytes downloaded so far.
+        downloaded: u64,
+
+        /// Size of the download, if announced by the registry.
+        total: Option<u64>,
+    },
+
+    /// A downloaded file was not used, the local one is kept.
+    Warning {
+        /// The refresh that downloaded the file.
+        refresh_id: RefreshId,
+
+        /// Why the file was not used.
+        message: String,
+    },
+
+    /// The refresh has completed, successfully if `error` is `None`.
+    Completed {
+        /// The refresh completed.
+        refresh_id: RefreshId,
+
+        /// Whether the local registry was already up to date.
+        not_modified: bool,
+
+        /// The reason of the failure.
+        error: Option<String>,
+    },
+}
+
+/// Refreshes asking for the same data from the same registry share the
+/// in-flight download: the network, the urls of the assets and of the icons,
+/// the key signing them, which of them are wanted and the xpub.
+type RefreshKey = (ElementsNetwork, String, String, Option<String>, bool, bool, Option<Xpub>);
+
+static IN_FLIGHT: Lazy<Mutex<HashMap<RefreshKey, Arc<Shared>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The state of a refresh shared by its handles and the thread running it.
+struct Shared {
+    id: RefreshId,
+    state: Mutex<State>,
+    completed: Condvar,
+}
+
+struct State {
+    listeners: Vec<RefreshListener>,
+    outcome: Option<std::result::Result<RegistrySource, String>>,
+}
+
+impl Shared {
+    fn emit(&self, event: &RefreshEvent) {
+        // Listeners are called without holding the lock so that they can
+        // start other refreshes.
+        let listeners = match self.state.lock() {
+            Ok(state) => state.listeners.clone(),
+            Err(_) => return,
+        };
+        for listener in listeners {
+            listener(event);
+        }
+    }
+
+    fn progress(&self, what: AssetsOrIcons, downloaded: u64, total: Option<u64>) {
+        self.emit(&RefreshEvent::Progress {
+            refresh_id: self.id,
+            what: what.to_string(),
+            downloaded,
+            total,
+        });
+    }
+
+    fn warning(&self, message: String) {
+        self.emit(&RefreshEvent::Warning {
+            refresh_id: self.id,
+            message,
+        });
+    }
+}
+
+/// A refresh running in the background, returned by
+/// [`start_refresh_assets`].
+pub struct RefreshHandle {
+    shared: Arc<Shared>,
+}
+
+impl RefreshHandle {
+    /// The id of the refresh, the same for the handles of joined refreshes.
+    pub fn id(&self) -> RefreshId {
+        self.shared.id
+    }
+
+    /// Blocks until the refresh completes.
+    pub fn wait(self) -> Result<RegistrySource> {
+        let mut state = self.shared.state.lock()?;
+        loop {
+            if let Some(outcome) = &state.outcome {
+                return outcome.clone().map_err(Error::Generic);
+            }
+            state = self.shared.completed.wait(state)?;
+        }
+    }
+}
+
+/// Starts refreshing the registry on a background thread, calling `listener`
+/// with its progress and completion.
+///
+/// If a refresh of the same data from the same registry is already running,
+/// `listener` is added to it instead and the returned handle waits for it.
+pub fn start_refresh_assets(
+    params: RefreshAssetsParams,
+    listener: Option<RefreshListener>,
+) -> Result<RefreshHandle> {
+    if !params.wants_something() {
+        return Err(Error::BothAssetsIconsFalse);
+    }
+
+    let key = (
+        params.network(),
+        params.url(AssetsOrIcons::Assets),
+        params.url(AssetsOrIcons::Icons),
+        params.public_key()?.map(|key| key.to_string()),
+        params.wants_assets(),
+        params.wants_icons(),
+        params.xpub,
+    );
+
+    let mut in_flight = IN_FLIGHT.lock()?;
+
+    if let Some(shared) = in_flight.get(&key) {
+        info!("joining registry refresh {}", shared.id);
+        shared.state.lock()?.listeners.extend(listener);
+        return Ok(RefreshHandle {
+            shared: Arc::clone(shared),
+        });
+    }
+
+    let shared = Arc::new(Shared {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        state: Mutex::new(State {
+            listeners: listener.into_iter().collect(),
+            outcome: None,
+        }),
+        completed: Condvar::new(),
+    });
+    in_flight.insert(key.clone(), Arc::clone(&shared));
+    drop(in_flight);
+
+    {
+        let shared = Arc::clone(&shared);
+        thread::spawn(move || {
+            let outcome = run(params, &shared).map_err(|err| err.to_string());
+            if let Err(err) = &outcome {
+                warn!("registry refresh {} failed: {}", shared.id, err);
+            }
+
+            // Refreshes started from now on download again.
+            if let Ok(mut in_flight) = IN_FLIGHT.lock() {
+                in_flight.remove(&key);
+            }
+
+            // Listeners know about the completion before the waiters return.
+            shared.emit(&RefreshEvent::Completed {
+                refresh_id: shared.id,
+                not_modified: outcome == Ok(RegistrySource::NotModified),
+                error: outcome.clone().err(),
+            });
+
+            if let Ok(mut state) = shared.state.lock() {
+                state.outcome = Some(outcome);
+            }
+            shared.completed.notify_all();
+        });
+    }
+
+    Ok(RefreshHandle {
+        shared,
+    })
+}
+
+/// Downloads the assets and the icons concurrently.
+fn run(params: RefreshAssetsParams, shared: &Arc<Shared>) -> Result<RegistrySource> {
+    let params = Arc::new(params);
+
+    let assets_handle = {
+        let params = Arc::clone(&params);
+        let shared = Arc::clone(shared);
+        thread::spawn(move || {
+            let progress =
+                |downloaded, total| shared.progress(AssetsOrIcons::Assets, downloaded, total);
+            let warning = |message| shared.warning(message);
+            params
+                .wants_assets()
+                .then(|| registry::refresh_assets(&params, &progress, &warning))
+                .transpose()
+                .map(Option::unwrap_or_default)
+        })
+    };
+
+    let progress = |downloaded, total| shared.progress(AssetsOrIcons::Icons, downloaded, total);
+    let warning = |message| shared.warning(message);
+    let icons_source = params
+        .wants_icons()
+        // forces multiline formatting
+        .then(|| registry::refresh_icons(&params, &progress, &warning))
+        .transpose()?
+        .unwrap_or_default();
+
+    let assets_source = assets_handle.join().unwrap()?;
+
+    Ok(RegistrySource::merge(assets_source, icons_source))
+}
@@ -7,11 +7,15 @@ use gdk_common::log::{debug, warn};
 use gdk_common::once_cell::sync::OnceCell;
 use serde::{de::DeserializeOwned, Serialize};
 
+use crate::http::Progress;
 use crate::params::{ElementsNetwork, RefreshAssetsParams};
 use crate::registry_infos::{RegistryAssets, RegistryIcons, RegistrySource};
-use crate::{cache, file, hard_coded, http};
+use crate::{cache, file, hard_coded, http, signature};
 use crate::{AssetEntry, AssetsOrIcons, Error, LastModified, RegistryInfos, Result};
 
+/// Called with the reason a downloaded file is not used.
+pub(crate) type Warning<'a> = &'a dyn Fn(String);
+
 type LastModifiedFiles = HashMap<ElementsNetwork, Mutex<File>>;
 type RegistryFiles = HashMap<(ElementsNetwork, AssetsOrIcons), Mutex<File>>;
 
@@ -74,8 +78,12 @@ pub(crate) fn init(registry_dir: impl AsRef<Path>) -> Result<()> {
     Ok(())
 }
 
-pub(crate) fn refresh_assets(params: &RefreshAssetsParams) -> Result<RegistrySource> {
-    match refresh::<RegistryAssets>(AssetsOrIcons::Assets, params)? {
+pub(crate) fn refresh_assets(
+    params: &RefreshAssetsParams,
+    progress: Progress,
+    warning: Warning,
+) -> Result<RegistrySource> {
+    match refresh::<RegistryAssets>(AssetsOrIcons::Assets, params, progress, warning)? {
         Some(mut assets) => {
             let len = assets.len();
             debug!("downloaded {} assets", assets.len());
@@ -93,8 +101,12 @@ pub(crate) fn refresh_assets(params: &RefreshAssetsParams) -> Result<RegistrySou
     }
 }
 
-pub(crate) fn refresh_icons(params: &RefreshAssetsParams) -> Result<RegistrySource> {
-    match refresh::<RegistryIcons>(AssetsOrIcons::Icons, params)? {
+pub(crate) fn refresh_icons(
+    params: &RefreshAssetsParams,
+    progress: Progress,
+    warning: Warning,
+) -> Result<RegistrySource> {
+    match refresh::<RegistryIcons>(AssetsOrIcons::Icons, params, progress, warning)? {
         Some(icons) => {
             debug!("downloaded {} icons", icons.len());
             if let Some(xpub) = params.xpub {
@@ -176,10 +188,16 @@ fn fetch<T: Default + Serialize + DeserializeOwned>(
     }
 }
 
+/// Returns `None` if the local file is up to date, or if the downloaded one
+/// doesn't match its signature and the local one is kept.
 fn refresh<T: Serialize + DeserializeOwned>(
     what: AssetsOrIcons,
     params: &RefreshAssetsParams,
+    progress: Progress,
+    warning: Warning,
 ) -> Result<Option<T>> {
+    let public_key = params.public_key()?;
+
     let file = &mut *get_registry_file(params.network(), what)?;
 
     let last_modified = if file::read::<T>(file).is_ok() {
@@ -188,11 +206,27 @@ fn refresh<T: Serialize + DeserializeOwned>(
         String::new()
     };
 
-    match http::call(&params.url(what), &params.agent()?, &last_modified, &params.custom_headers())?
+    let agent = params.agent()?;
+    match http::call(&params.url(what), &agent, &last_modified, params.custom_headers(), progress)?
     {
-        Some((value, new_modified)) => {
+        Some((body, new_modified)) => {
             debug!("fetched {} were last modified {}", what, new_modified);
-            let downloaded = serde_json::from_value::<T>(value)?;
+            if let Some(public_key) = public_key {
+                let verified = http::get_signature(
+                    &params.signature_url(what),
+                    &agent,
+                    params.custom_headers(),
+                )
+                .and_then(|signature| signature::verify(&body, &signature, &public_key));
+                if let Err(err) = verified {
+                    // Not updating the last modified date, to download them again next time
+                    let reason = format!("the downloaded {} are not used: {}", what, err);
+                    warn!("{}", reason);
+                    warning(reason);
+                    return Ok(None);
+                }
+            }
+            let downloaded = serde_json::from_slice::<T>(&body)?;
             file::write(&downloaded, file)?;
             set_last_modified(new_modified, params.network(), what)?;
             Ok(Some(downloaded))
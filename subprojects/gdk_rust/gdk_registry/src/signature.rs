@@ -0,0 +1,57 @@
+//! Detached signatures of the registry files, so that a mirror of the
+//! registry can't serve assets metadata of its own.
+//!
+//! The signature of a file is served next to it with the `.sig` extension, as
+//! the hex of the DER encoded ECDSA signature of the SHA256 of the file.
+
+use gdk_common::bitcoin::hashes::{sha256, Hash};
+use gdk_common::bitcoin::hex::FromHex;
+use gdk_common::bitcoin::secp256k1::{ecdsa, Message, PublicKey};
+use gdk_common::EC;
+
+use crate::{Error, Result};
+
+/// Checks that `signature` is the signature of `body` by `public_key`.
+pub(crate) fn verify(body: &[u8], signature: &str, public_key: &PublicKey) -> Result<()> {
+    let invalid = |reason: &str| Error::InvalidSignature(reason.to_string());
+    let der = Vec::<u8>::from_hex(signature.trim()).map_err(|_| invalid("not hex"))?;
+    let signature = ecdsa::Signature::from_der(&der).map_err(|_| invalid("not DER"))?;
+    let message = Message::from_digest(sha256::Hash::hash(body).to_byte_array());
+    EC.verify_ecdsa(&message, &signature, public_key).map_err(|_| invalid("not signed by the key"))
+}
+
+/// The public key signing the registry files, given in hex.
+pub(crate) fn parse_public_key(hex: &str) -> Result<PublicKey> {
+    hex.parse().map_err(|_| Error::InvalidSignature(format!("invalid public key {}", hex)))
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use gdk_common::bitcoin::hex::DisplayHex;
+    use gdk_common::bitcoin::secp256k1::SecretKey;
+
+    /// The hex signature of `body` by `secret_key`.
+    pub(crate) fn sign(body: &[u8], secret_key: &SecretKey) -> String {
+        let message = Message::from_digest(sha256::Hash::hash(body).to_byte_array());
+        EC.sign_ecdsa(&message, secret_key).serialize_der().to_lower_hex_string()
+    }
+
+    #[test]
+    fn test_verify() {
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&EC, &secret_key);
+        let body = br#"{"some":"assets"}"#;
+        let signature = sign(body, &secret_key);
+
+        verify(body, &signature, &public_key).unwrap();
+        verify(body, &format!("{}\n", signature), &public_key).unwrap();
+        assert!(verify(br#"{"some":"other assets"}"#, &signature, &public_key).is_err());
+        assert!(verify(body, "not a signature", &public_key).is_err());
+        let other_key = PublicKey::from_secret_key(&EC, &SecretKey::from_slice(&[2; 32]).unwrap());
+        assert!(verify(body, &signature, &other_key).is_err());
+
+        assert_eq!(parse_public_key(&public_key.to_string()).unwrap(), public_key);
+        assert!(parse_public_key("02").is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use gdk_common::error::Error as CommonError;
+use gdk_common::error::{Error as CommonError, ErrorCode};
 use gdk_common::exchange_rates;
 use gdk_common::model::ExchangeRateError;
 use gdk_common::ureq;
@@ -44,13 +44,21 @@ pub enum Error {
 }
 
 impl Error {
-    /// Convert the error to a GDK-compatible code.
-    pub fn to_gdk_code(&self) -> String {
+    /// Classify the error, see [`ErrorCode`].
+    pub fn code(&self) -> ErrorCode {
         match self {
-            Error::Electrum(err) => err.to_gdk_code(),
-            _ => "id_unknown".to_string(),
+            Error::Electrum(err) => err.code(),
+            Error::Common(err) => err.code(),
+            Error::Io(err) => ErrorCode::from_io(err),
+            Error::Ureq(err) => ErrorCode::from_ureq(err),
+            _ => ErrorCode::Unknown,
         }
     }
+
+    /// Convert the error to a GDK-compatible code.
+    pub fn to_gdk_code(&self) -> String {
+        self.code().gdk_id().to_string()
+    }
 }
 
 impl From<String> for Error {
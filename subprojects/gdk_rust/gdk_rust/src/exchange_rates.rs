@@ -5,6 +5,7 @@ use gdk_common::exchange_rates::{Currency, Pair, Ticker};
 use gdk_common::log::{debug, info};
 use gdk_common::session::Session;
 use gdk_common::ureq;
+use gdk_electrum::price::{HttpPriceSource, PriceSource};
 use serde::{de::Deserializer, Deserialize};
 
 use crate::Error;
@@ -60,28 +61,9 @@ pub(crate) fn fetch(
     url: &str,
     exchange: &str,
 ) -> Result<Ticker, Error> {
-    #[derive(serde::Deserialize)]
-    struct ExchangeRateResponse {
-        // TODO: this should be returned as a number by the server.
-        // rate: f64,
-        rate: String,
-    }
-
-    let endpoint = format!(
-        "{}/v0/venues/{}/pairs/{}/{}",
-        url,
-        exchange.to_ascii_uppercase(),
-        Currency::BTC.endpoint_name(),
-        currency.endpoint_name()
-    );
-
     let pair = Pair::new(Currency::BTC, currency);
 
-    info!("fetching {} price data from {}", pair, endpoint);
-
-    let response = agent.get(&endpoint).call()?.into_json::<ExchangeRateResponse>()?;
-
-    let ticker = Ticker::new(pair, response.rate.parse::<f64>().unwrap());
+    let ticker = HttpPriceSource::new(agent.clone(), url).fetch(pair, exchange)?;
 
     info!("got exchange rate {:?}", ticker);
     Ok(ticker)
@@ -11,15 +11,18 @@ use std::ffi::CString;
 use std::io::Write;
 use std::os::raw::c_char;
 use std::str::FromStr;
-use std::sync::Once;
+use std::sync::{Arc, Once};
 use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-use gdk_common::model::{InitParam, SPVDownloadHeadersParams, SPVVerifyTxParams};
+use gdk_common::model::{
+    GetWalletIdentifierOpt, InitParam, SPVDownloadHeadersParams, SPVVerifyTxParams,
+};
 
 use crate::error::Error;
 use gdk_common::log::{self, debug, info, LevelFilter, Metadata, Record};
 use gdk_common::session::{JsonError, Session};
-use gdk_electrum::{headers, sweep, ElectrumSession};
+use gdk_electrum::simulation::SimulationSession;
+use gdk_electrum::{address, headers, sweep, ElectrumSession};
 use serde::Serialize;
 
 pub const GA_OK: i32 = 0;
@@ -33,6 +36,7 @@ pub struct GdkSession {
 pub enum GdkBackend {
     // Rpc(RpcSession),
     Electrum(ElectrumSession),
+    Simulation(SimulationSession),
 }
 
 impl From<Error> for JsonError {
@@ -40,6 +44,7 @@ impl From<Error> for JsonError {
         JsonError {
             message: e.to_string(),
             error: e.to_gdk_code(),
+            code: e.code(),
         }
     }
 }
@@ -83,15 +88,16 @@ pub extern "C" fn GDKRUST_create_session(
 fn init_logging(level: LevelFilter) {
     #[cfg(target_os = "android")]
     INIT_LOGGER.call_once(|| {
-        android_logger::init_once(
-            android_logger::Config::default()
-                .with_min_level(level.to_level().unwrap_or(log::Level::Error))
-                .with_filter(
-                    android_logger::FilterBuilder::new()
-                        .parse("warn,gdk_rust=debug,gdk_electrum=debug")
-                        .build(),
-                ),
-        )
+        let min_level = level.to_level().unwrap_or(log::Level::Error);
+        let config = android_logger::Config::default().with_min_level(min_level).with_filter(
+            android_logger::FilterBuilder::new()
+                .parse("warn,gdk_rust=debug,gdk_electrum=debug")
+                .build(),
+        );
+        let logger = Box::leak(Box::new(AndroidLogger(android_logger::AndroidLogger::new(config))));
+        log::set_logger(logger)
+            .map(|()| log::set_max_level(min_level.to_level_filter()))
+            .expect("cannot initialize logging");
     });
 
     #[cfg(not(target_os = "android"))]
@@ -123,6 +129,10 @@ fn create_session(network: &Value) -> Result<GdkSession, Value> {
             let session = ElectrumSession::new(parsed_network)?;
             GdkBackend::Electrum(session)
         }
+        Some("simulation") => {
+            let session = SimulationSession::new(parsed_network)?;
+            GdkBackend::Simulation(session)
+        }
         _ => return Err(json!("server_type invalid")),
     };
     let gdk_session = GdkSession {
@@ -179,6 +189,7 @@ fn call_session(sess: &mut GdkSession, method: &str, input: &str) -> Result<Valu
 
         let ticker = match sess.backend {
             GdkBackend::Electrum(ref mut s) => exchange_rates::fetch_cached(s, &params),
+            GdkBackend::Simulation(ref mut s) => exchange_rates::fetch_cached(s, &params),
         }?;
 
         let rate = ticker.map(|t| format!("{:.8}", t.rate)).unwrap_or_default();
@@ -186,6 +197,38 @@ fn call_session(sess: &mut GdkSession, method: &str, input: &str) -> Result<Valu
         return Ok(json!({ "currencies": { params.currency.to_string(): rate } }));
     }
 
+    if method == "start_refresh_assets" {
+        let params: gdk_registry::RefreshAssetsParams = serde_json::from_value(input)?;
+
+        let notify = match sess.backend {
+            GdkBackend::Electrum(ref s) => s.notify.clone(),
+            GdkBackend::Simulation(ref s) => s.notify.clone(),
+        };
+        let listener: gdk_registry::RefreshListener =
+            Arc::new(move |event: &gdk_registry::RefreshEvent| match event {
+                gdk_registry::RefreshEvent::Progress {
+                    refresh_id,
+                    what,
+                    downloaded,
+                    total,
+                } => notify.assets_refresh_progress(*refresh_id, what.clone(), *downloaded, *total),
+                gdk_registry::RefreshEvent::Warning {
+                    refresh_id,
+                    message,
+                } => notify.assets_refresh_warning(*refresh_id, message.clone()),
+                gdk_registry::RefreshEvent::Completed {
+                    refresh_id,
+                    not_modified,
+                    error,
+                } => notify.assets_refresh_completed(*refresh_id, *not_modified, error.clone()),
+            });
+
+        let handle =
+            gdk_registry::start_refresh_assets(params, Some(listener)).map_err(Error::from)?;
+
+        return Ok(json!({ "refresh_id": handle.id() }));
+    }
+
     // Redact inputs containing private data
     let methods_to_redact_in = vec![
         "login",
@@ -193,8 +236,11 @@ fn call_session(sess: &mut GdkSession, method: &str, input: &str) -> Result<Valu
         "encrypt_with_pin",
         "decrypt_with_pin",
         "create_subaccount",
+        "create_green_multisig_subaccount",
         "credentials_from_pin_data",
         "set_master_blinding_key",
+        "set_watch_only_credentials",
+        "login_watch_only",
     ];
     let input_str = format!("{:?}", &input);
     let input_redacted = if methods_to_redact_in.contains(&method)
@@ -211,6 +257,7 @@ fn call_session(sess: &mut GdkSession, method: &str, input: &str) -> Result<Valu
 
     let res = match sess.backend {
         GdkBackend::Electrum(ref mut s) => s.handle_call(&method, input),
+        GdkBackend::Simulation(ref mut s) => s.handle_call(&method, input),
     };
 
     let methods_to_redact_out =
@@ -240,6 +287,7 @@ pub extern "C" fn GDKRUST_set_notification_handler(
 
     match backend {
         GdkBackend::Electrum(ref mut s) => s.notify.set_native((handler, self_context)),
+        GdkBackend::Simulation(ref mut s) => s.notify.set_native((handler, self_context)),
     };
 
     info!("set notification handler");
@@ -264,11 +312,10 @@ pub extern "C" fn GDKRUST_destroy_session(ptr: *mut libc::c_void) {
 }
 
 fn build_error(_method: &str, error: &Error) -> String {
-    let message = error.to_string();
-    let error = error.to_gdk_code();
     let json_error = JsonError {
-        message,
-        error,
+        message: error.to_string(),
+        error: error.to_gdk_code(),
+        code: error.code(),
     };
     to_string(&json_error)
 }
@@ -327,6 +374,25 @@ fn handle_call(method: &str, input: &str) -> Result<String, Error> {
             let params: gdk_registry::GetAssetsParams = serde_json::from_str(input)?;
             to_string(&gdk_registry::get_assets(params)?)
         }
+        "get_asset_metadata" => {
+            let params: gdk_registry::GetAssetMetadataParams = serde_json::from_str(input)?;
+            to_string(&gdk_registry::get_asset_metadata(params)?)
+        }
+        "get_wallet_identifier" => {
+            let param: GetWalletIdentifierOpt = serde_json::from_str(input)?;
+            to_string(&gdk_electrum::get_wallet_identifier(&param)?)
+        }
+        "validate_address" => {
+            let param: address::ValidateAddressOpt = serde_json::from_str(input)?;
+            to_string(&address::validate_address(&param))
+        }
+        "check_send_amount" => {
+            let mut param: address::CheckSendAmountOpt = serde_json::from_str(input)?;
+            if param.amount.is_some() && param.precision.is_none() {
+                param.precision = registry_precision(&param)?;
+            }
+            to_string(&address::check_send_amount(&param))
+        }
         "get_unspent_outputs_for_private_key" => {
             let param: sweep::SweepOpt = serde_json::from_str(input)?;
             to_string(&sweep::get_unspent_outputs_for_private_key(&param)?)
@@ -345,6 +411,53 @@ fn handle_call(method: &str, input: &str) -> Result<String, Error> {
     Ok(res)
 }
 
+/// The precision of the asset of `opt` in the local copy of the registry, if
+/// it's a Liquid asset known to it
+fn registry_precision(opt: &address::CheckSendAmountOpt) -> Result<Option<u8>, Error> {
+    let asset_id = match &opt.asset_id {
+        Some(asset_id) if opt.network.liquid && opt.asset_id != opt.network.policy_asset => {
+            asset_id
+        }
+        _ => return Ok(None),
+    };
+    let asset_id = gdk_common::elements::AssetId::from_str(asset_id)
+        .map_err(|_| gdk_electrum::error::Error::InvalidAssetId)?;
+    let config = gdk_registry::Config::from_network_parameters(&opt.network)?;
+    let params = gdk_registry::GetAssetMetadataParams::new(asset_id, config);
+    Ok(gdk_registry::get_asset_metadata(params)?.map(|metadata| metadata.precision))
+}
+
+/// Wraps the android logger to redact messages when privacy mode is enabled
+#[cfg(target_os = "android")]
+struct AndroidLogger(android_logger::AndroidLogger);
+
+#[cfg(target_os = "android")]
+impl log::Log for AndroidLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !gdk_common::redact::privacy_mode() {
+            return self.0.log(record);
+        }
+        let msg = gdk_common::redact::log_message(record.args());
+        self.0.log(
+            &Record::builder()
+                .args(format_args!("{}", msg))
+                .metadata(record.metadata().clone())
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .build(),
+        );
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
 #[cfg(not(target_os = "android"))]
 static LOGGER: SimpleLogger = SimpleLogger;
 
@@ -365,13 +478,14 @@ impl log::Log for SimpleLogger {
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
             let ts = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards");
+            let msg = gdk_common::redact::log_message(record.args());
             let _ = writeln!(
                 std::io::stdout(),
                 "{:02}.{:03} {} - {}",
                 ts.as_secs() % 60,
                 ts.subsec_millis(),
                 record.level(),
-                record.args()
+                msg
             );
         }
     }
@@ -1,21 +1,34 @@
 use std::net::TcpListener;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
 
+use electrsd::bitcoind::bitcoincore_rpc::RpcApi;
 use electrsd::electrum_client::ElectrumApi;
+use gdk_common::exchange_rates::{Currency, Pair, Ticker};
 use gdk_common::log::info;
 use serde_json::Value;
 use tempfile::TempDir;
 
 use gdk_common::model::*;
+use gdk_common::scripts::ScriptType;
 use gdk_common::session::Session;
 use gdk_common::{NetworkParameters, State};
 use gdk_electrum::headers::bitcoin::HeadersChain;
 use gdk_electrum::interface::ElectrumUrl;
+use gdk_electrum::manager::SessionManager;
+use gdk_electrum::multi::MultiSession;
+use gdk_electrum::price::{AvailableCurrencies, PriceSource};
+use gdk_electrum::quick_login::CredentialProtector;
+use gdk_electrum::resolver::Resolver;
+use gdk_electrum::signer::SoftwareSigner;
 use gdk_electrum::{headers, spv, ElectrumSession};
 use gdk_test::utils;
+use gdk_test::LogRecorder;
 use gdk_test::RpcNodeExt;
-use gdk_test::TestSession;
+use gdk_test::SpanRecorder;
+use gdk_test::{Chain, TestSession};
 
 #[test]
 fn test_electrum_disconnect() {
@@ -108,6 +121,7 @@ fn spv_cross_validate() {
             &session2_electrum_url,
             None,
             &None,
+            &Resolver::default(),
         )
         .unwrap();
 
@@ -125,6 +139,24 @@ fn spv_cross_validate() {
         test_session2.node_generate(12);
         test_session2.wait_blockheight(133);
 
+        // The server tip is known at once, the headers catch up at the next
+        // round of the headers thread
+        let timeout = Duration::from_secs(60);
+        let status = test_session2.session.get_block_status().unwrap();
+        let best_hash = test_session2.node.client.get_best_block_hash().unwrap();
+        assert_eq!(status.server_tip_height, 133);
+        assert_eq!(status.server_tip_hash, best_hash.to_string());
+        assert!(status.local_header_height.is_some_and(|height| height <= 133));
+        assert_eq!(status.in_sync, status.local_header_height == Some(133));
+        test_session2.wait_for_headers(133, timeout);
+        assert!(test_session2.session.get_block_status().unwrap().in_sync);
+
+        // session1 is in sync with its own server, unaware of the longer chain
+        test_session1.wait_for_headers(121, timeout);
+        let status = test_session1.session.get_block_status().unwrap();
+        assert_eq!(status.server_tip_height, 121);
+        assert!(status.in_sync);
+
         // Grab direct access to session1's HeadersChain
         let session1_chain = get_chain(&mut test_session1);
         assert_eq!(session1_chain.height(), 121);
@@ -138,6 +170,7 @@ fn spv_cross_validate() {
             &session2_electrum_url,
             None,
             &None,
+            &Resolver::default(),
         )
         .unwrap();
 
@@ -147,6 +180,192 @@ fn spv_cross_validate() {
     }
 }
 
+#[test]
+fn test_reorg_own_tx() {
+    let mut test_session = TestSession::new(|_| ());
+    let timeout = Duration::from_secs(60);
+    test_session.fund(100_000);
+    let address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let address = address.parse::<gdk_common::bitcoin::Address<_>>().unwrap().assume_checked();
+    let txid = test_session.send_all(&address, 1_000);
+    test_session.wait_for_tx(&txid, 0, timeout);
+
+    // Confirmed and verified at height 102
+    let hash = test_session.mine_block();
+    test_session.wait_for_tx(&txid, 1, timeout);
+    test_session.wait_for_spv(&txid, "verified", timeout);
+    assert_eq!(test_session.get_tx_from_list(0, &txid).block_height, 102);
+
+    // Its block is reorged out, the transaction is back in the mempool
+    test_session.invalidate_block(&hash);
+    test_session.wait_for_height(101, timeout);
+    test_session.wait_for_spv(&txid, "unconfirmed", timeout);
+    assert_eq!(test_session.get_tx_from_list(0, &txid).block_height, 0);
+
+    // An empty block takes its place, the transaction confirms in the next one
+    let empty: Vec<String> = vec![];
+    let mining_address = test_session.node.client.getnewaddress(None, None).unwrap();
+    test_session
+        .node
+        .client
+        .call::<Value>("generateblock", &[mining_address.into(), empty.into()])
+        .unwrap();
+    test_session.node_generate(1);
+    test_session.wait_for_height(103, timeout);
+    test_session.wait_for_tx(&txid, 1, timeout);
+    assert_eq!(test_session.get_tx_from_list(0, &txid).block_height, 103);
+    test_session.wait_for_spv(&txid, "verified", timeout);
+
+    test_session.stop();
+}
+
+#[test]
+fn test_reorg_counter() {
+    let mut test_session = TestSession::new(|_| ());
+    let timeout = Duration::from_secs(60);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let sender = std::sync::Mutex::new(sender);
+    test_session.session.subscribe(&[NotificationKind::TransactionReorged], move |ntf| {
+        sender.lock().unwrap().send(ntf.clone()).unwrap();
+    });
+    let txid = test_session.fund(100_000);
+
+    let hash = test_session.mine_block();
+    test_session.wait_for_tx(&txid, 1, timeout);
+    let item = test_session.get_tx_from_list(0, &txid);
+    assert_eq!(item.reorg_count, 0);
+    assert_eq!(item.last_reorg_height, None);
+
+    // Its block is reorged out, the transaction is back in the mempool
+    test_session.invalidate_block(&hash);
+    test_session.wait_for_height(101, timeout);
+    test_session.wait_for_spv(&txid, "unconfirmed", timeout);
+    match receiver.recv_timeout(timeout).unwrap() {
+        Notification::TransactionReorged {
+            subaccount,
+            txid: reorged_txid,
+            height,
+            reorg_count,
+        } => {
+            assert_eq!(subaccount, 0);
+            assert_eq!(reorged_txid.to_string(), txid);
+            assert_eq!(height, 102);
+            assert_eq!(reorg_count, 1);
+        }
+        ntf => panic!("unexpected notification {:?}", ntf),
+    }
+
+    // It confirms again in another block, which is not another reorg
+    test_session.mine_block();
+    test_session.wait_for_tx(&txid, 1, timeout);
+    let item = test_session.get_tx_from_list(0, &txid);
+    assert_eq!(item.block_height, 102);
+    assert_eq!(item.reorg_count, 1);
+    assert_eq!(item.last_reorg_height, Some(102));
+    assert!(receiver.try_recv().is_err());
+
+    test_session.stop();
+}
+
+#[test]
+fn test_tx_block_hash_time() {
+    let mut test_session = TestSession::new(|_| ());
+    let timeout = Duration::from_secs(60);
+    let txid = test_session.fund(100_000);
+    let item = test_session.get_tx_from_list(0, &txid);
+    assert_eq!(item.block_hash, None);
+    assert_eq!(item.block_time, None);
+
+    let block_of = |test_session: &TestSession, hash: &str| {
+        let block = test_session.node.client.call::<Value>("getblock", &[hash.into()]).unwrap();
+        (block["hash"].as_str().unwrap().to_string(), block["time"].as_u64().unwrap() as u32)
+    };
+
+    let hash = test_session.mine_block();
+    test_session.wait_for_tx(&txid, 1, timeout);
+    let item = test_session.get_tx_from_list(0, &txid);
+    let (block_hash, block_time) = block_of(&test_session, &hash);
+    assert_eq!(item.block_hash.as_ref(), Some(&block_hash));
+    assert_eq!(item.block_time, Some(block_time));
+    assert_eq!(item.created_at_ts, block_time as u64 * 1_000_000);
+
+    // Confirmed again at the same height in another block, the fields follow
+    test_session.invalidate_block(&hash);
+    test_session.wait_for_height(101, timeout);
+    test_session.wait_for_spv(&txid, "unconfirmed", timeout);
+    let new_hash = test_session.mine_block();
+    assert_ne!(new_hash, hash);
+    test_session.wait_for_tx(&txid, 1, timeout);
+    let (block_hash, block_time) = block_of(&test_session, &new_hash);
+    for i in 0.. {
+        assert!(i < 60, "timeout waiting for the hash of the new block");
+        let item = test_session.get_tx_from_list(0, &txid);
+        if item.block_hash.as_ref() == Some(&block_hash) {
+            assert_eq!(item.block_height, 102);
+            assert_eq!(item.block_time, Some(block_time));
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    test_session.stop();
+}
+
+#[test]
+fn test_replaced_own_tx() {
+    let mut test_session = TestSession::new(|_| ());
+    let timeout = Duration::from_secs(60);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let sender = std::sync::Mutex::new(sender);
+    test_session.session.subscribe(&[NotificationKind::Transaction], move |ntf| {
+        sender.lock().unwrap().send(ntf.clone()).unwrap();
+    });
+    test_session.fund(100_000);
+    // generateblock only mines the transactions given, the coins must be confirmed
+    test_session.mine_block();
+    let address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let address = address.parse::<gdk_common::bitcoin::Address<_>>().unwrap().assume_checked();
+
+    // Two transactions spending the same coins, as if signed by two instances of the wallet
+    let sent_hex = test_session.create_tx(&address, 10_000, 1_000);
+    let conflict_hex = test_session.create_tx(&address, 20_000, 2_000);
+    let sent = test_session.session.broadcast_transaction(&sent_hex).unwrap();
+    test_session.wait_for_tx(&sent, 0, timeout);
+
+    // The conflicting one is mined, the first one is evicted from the mempool
+    let conflict: gdk_common::bitcoin::Transaction =
+        gdk_common::bitcoin::consensus::encode::deserialize_hex(&conflict_hex).unwrap();
+    let conflict = conflict.compute_txid().to_string();
+    let mining_address = test_session.node.client.getnewaddress(None, None).unwrap();
+    test_session
+        .node
+        .client
+        .call::<Value>("generateblock", &[mining_address.into(), vec![conflict_hex].into()])
+        .unwrap();
+    test_session.electrs.trigger().unwrap();
+    test_session.wait_for_tx(&conflict, 1, timeout);
+    loop {
+        match receiver.recv_timeout(timeout).unwrap() {
+            Notification::Transaction {
+                txid,
+                removed: true,
+                ..
+            } if txid.to_string() == sent => break,
+            _ => continue,
+        }
+    }
+
+    // Kept in the list, no longer counted in the balance
+    let item = test_session.get_tx_from_list(0, &sent);
+    assert_eq!(item.removed, Some(TxRemoval::Replaced));
+    assert_eq!(item.block_height, 0);
+    assert!(!item.can_rbf);
+    let balance = test_session.session.get_balance(&GetBalanceOpt::default()).unwrap();
+    assert_eq!(balance["btc"], 100_000 - 20_000 - 2_000);
+
+    test_session.stop();
+}
+
 // Test high-level session management, background validation and transaction status
 #[test]
 fn spv_cross_validation_session() {
@@ -163,16 +382,17 @@ fn spv_cross_validation_session() {
     info!("sent mempool tx");
 
     // Confirm it, wait for it to SPV-validate
+    let timeout = Duration::from_secs(60);
     test_session1.node_generate(1);
-    test_session1.wait_blockheight(122);
-    test_session1.wait_tx_spv_change(&txid, "verified");
+    test_session1.wait_for_tx(&txid, 1, timeout);
+    test_session1.wait_for_spv(&txid, "verified", timeout);
     assert_eq!(test_session1.get_tx_from_list(0, &txid).block_height, 122);
     info!("tx confirmed and spv validated");
 
     // Extend session2, putting session1 on a minority fork
     test_session2.node_generate(10);
-    test_session2.wait_blockheight(131);
-    test_session1.wait_blockheight(122);
+    test_session2.wait_for_height(131, timeout);
+    test_session1.wait_for_height(122, timeout);
     let cross_result = test_session1.wait_spv_cross_validation_change(false);
     let inv = assert_unwrap_invalid(cross_result);
     assert_eq!(inv.common_ancestor, 121);
@@ -182,11 +402,11 @@ fn spv_cross_validation_session() {
 
     // Extend session1, making it the best chain
     test_session1.node_generate(11);
-    test_session1.wait_blockheight(133);
+    test_session1.wait_for_height(133, timeout);
     let cross_result = test_session1.wait_spv_cross_validation_change(true);
     assert!(cross_result.is_valid());
     assert_eq!(test_session1.get_tx_from_list(0, &txid).spv_verified, "verified");
-    assert_eq!(test_session1.session.block_status().unwrap().0, 133);
+    assert_eq!(test_session1.session.get_block_status().unwrap().server_tip_height, 133);
     info!("extended session1, making session1 the majority");
 
     // Make session1 the minority again
@@ -203,6 +423,10 @@ fn spv_cross_validation_session() {
     test_session1.node_connect(test_session2.p2p_port);
     let cross_result = test_session1.wait_spv_cross_validation_change(true);
     assert!(cross_result.is_valid());
+    test_session1.wait_for_height(134, timeout);
+    let block_ntf = test_session1.wait_block_ntf(134);
+    assert!(block_ntf.reorg);
+    assert!(block_ntf.common_ancestor_height.map_or(true, |h| h <= 121));
     let txitem = test_session1.get_tx_from_list(0, &txid);
     assert_eq!(txitem.block_height, 0);
     assert_eq!(txitem.spv_verified, "unconfirmed");
@@ -211,7 +435,8 @@ fn spv_cross_validation_session() {
     // Re-confirm the tx and then re-fork the chain, such that the tx is confirmed before the forking point
     // Cross-validation should fail, but the tx should still appear as SPV-validated
     test_session1.node_generate(1);
-    test_session1.wait_tx_spv_change(&txid, "verified");
+    test_session1.wait_for_tx(&txid, 1, timeout);
+    test_session1.wait_for_spv(&txid, "verified", timeout);
     assert_eq!(test_session1.get_tx_from_list(0, &txid).block_height, 135);
     test_session1.node_disconnect_all();
     test_session1.node_generate(5);
@@ -228,9 +453,43 @@ fn spv_cross_validation_session() {
     test_session2.stop();
 }
 
+#[test]
+fn test_spv_disabled() {
+    let mut test_session = TestSession::new(|n| n.spv_enabled = Some(false));
+    let timeout = Duration::from_secs(60);
+    let txid = test_session.fund(100_000);
+    test_session.node_generate(5);
+    test_session.wait_for_tx(&txid, 5, timeout);
+    let headers_thread = |test_session: &TestSession| {
+        test_session.session.handles.iter().any(|h| h.thread().name() == Some("headers"))
+    };
+
+    // The server is trusted, no headers are downloaded
+    assert!(!headers_thread(&test_session));
+    assert_eq!(test_session.get_tx_from_list(0, &txid).spv_verified, "disabled");
+    let status = test_session.session.get_block_status().unwrap();
+    assert_eq!(status.local_header_height, None);
+    assert!(status.in_sync);
+
+    // Enabled on the live session, the old transaction gets verified
+    let enable = |enabled| serde_json::json!({ "enabled": enabled });
+    test_session.session.handle_call("set_spv_enabled", enable(true)).unwrap();
+    assert!(headers_thread(&test_session));
+    test_session.wait_for_spv(&txid, "verified", timeout);
+    assert!(test_session.session.get_block_status().unwrap().local_header_height.is_some());
+
+    // And disabled again
+    test_session.session.handle_call("set_spv_enabled", enable(false)).unwrap();
+    assert!(!headers_thread(&test_session));
+    assert_eq!(test_session.get_tx_from_list(0, &txid).spv_verified, "disabled");
+    assert!(test_session.session.get_block_status().unwrap().in_sync);
+
+    test_session.stop();
+}
+
 #[test]
 fn test_spv_timeout() {
-    let _ = env_logger::try_init();
+    gdk_test::init_logger();
 
     let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap(); // 0 means the OS choose a free port
     let mut network = NetworkParameters::default();
@@ -333,6 +592,4146 @@ fn test_spv_external_concurrent(spv_enabled: bool) {
     }
 }
 
+#[test]
+fn test_asset_filter() {
+    let mut test_session = TestSession::new(|_| ());
+    let txid = test_session.fund(100_000);
+
+    let mut opt = GetTransactionsOpt::default();
+    opt.count = 100;
+    opt.asset_id = Some("btc".into());
+    let txs = test_session.session.get_transactions(&opt).unwrap().transactions;
+    assert!(txs.iter().any(|tx| tx.txhash == txid));
+
+    // On Bitcoin any other asset filter is an error
+    let asset_id = "5ac9f65c0efcc4775e0baec4ec03abdde22473cd3cf33c0419ca290e0751b225";
+    opt.asset_id = Some(asset_id.into());
+    assert!(test_session.session.get_transactions(&opt).is_err());
+
+    let mut opt = GetBalanceOpt::default();
+    opt.asset_id = Some("btc".into());
+    let balance = test_session.session.get_balance(&opt).unwrap();
+    assert_eq!(balance, vec![("btc".to_string(), 100_000)].into_iter().collect());
+    opt.asset_id = Some(asset_id.into());
+    assert!(test_session.session.get_balance(&opt).is_err());
+
+    test_session.stop();
+}
+
+#[test]
+fn test_transactions_pagination() {
+    let mut test_session = TestSession::new(|_| ());
+    let mut txids = vec![];
+    for i in 0..33 {
+        txids.push(test_session.fund(10_000 + i * 1_000));
+        if i % 10 == 9 {
+            test_session.mine_block();
+        }
+    }
+
+    let mut opt = GetTransactionsOpt::default();
+    opt.count = 10;
+    let first_page = test_session.session.get_transactions(&opt).unwrap();
+    assert_eq!(first_page.total, 33);
+    assert_eq!(first_page.transactions.len(), 10);
+
+    // A transaction arriving while paging doesn't shift the following pages
+    let new_txid = test_session.fund(5_000);
+    opt.sync_generation = Some(first_page.sync_generation);
+    let mut listed: Vec<String> =
+        first_page.transactions.iter().map(|tx| tx.txhash.clone()).collect();
+    for first in [10, 20, 30] {
+        opt.first = first;
+        let page = test_session.session.get_transactions(&opt).unwrap();
+        assert_eq!(page.total, 33);
+        assert_eq!(page.sync_generation, first_page.sync_generation);
+        listed.extend(page.transactions.into_iter().map(|tx| tx.txhash));
+    }
+    assert!(!listed.contains(&new_txid));
+    listed.sort();
+    listed.dedup();
+    txids.sort();
+    assert_eq!(listed, txids);
+
+    // Without a sync generation the latest transactions are listed
+    let mut opt = GetTransactionsOpt::default();
+    opt.count = 100;
+    let all = test_session.session.get_transactions(&opt).unwrap();
+    assert_eq!(all.total, 34);
+    assert_eq!(all.transactions[0].txhash, new_txid);
+    assert!(all.sync_generation > first_page.sync_generation);
+
+    opt.min_satoshi = Some(40_000);
+    let txs = test_session.session.get_transactions(&opt).unwrap();
+    assert_eq!(txs.total, 3);
+    assert!(txs.transactions.iter().all(|tx| tx.satoshi["btc"] >= 40_000));
+    opt.min_satoshi = None;
+
+    opt.type_ = Some(TransactionType::Outgoing);
+    assert_eq!(test_session.session.get_transactions(&opt).unwrap().total, 0);
+    opt.type_ = Some(TransactionType::Incoming);
+    assert_eq!(test_session.session.get_transactions(&opt).unwrap().total, 34);
+    opt.type_ = None;
+
+    // Confirmed transactions are timestamped with their block time
+    let confirmed_at = all.transactions.iter().map(|tx| tx.created_at_ts).min().unwrap();
+    let expected = all.transactions.iter().filter(|tx| tx.created_at_ts <= confirmed_at).count();
+    assert!(expected >= 10);
+    opt.created_at_to = Some(confirmed_at);
+    let older = test_session.session.get_transactions(&opt).unwrap();
+    assert_eq!(older.total, expected);
+    assert!(older.transactions.iter().all(|tx| tx.created_at_ts <= confirmed_at));
+    opt.created_at_to = None;
+    opt.created_at_from = Some(confirmed_at + 1);
+    let newer = test_session.session.get_transactions(&opt).unwrap();
+    assert_eq!(newer.total, 34 - expected);
+
+    test_session.stop();
+}
+
+#[test]
+fn test_transaction_details() {
+    let mut test_session = TestSession::new(|_| ());
+    let txid = test_session.fund(100_000);
+    test_session.session.set_transaction_memo(&txid, "payment").unwrap();
+
+    let details = test_session.session.get_transaction_details(&txid).unwrap();
+    assert!(!details.not_wallet_tx);
+    assert_eq!(details.txhash, txid);
+    assert_eq!(details.block_height, 0);
+    assert_eq!(details.confirmations, 0);
+    assert_eq!(details.memo, "payment");
+    assert_eq!(details.subaccounts.len(), 1);
+    assert_eq!(details.subaccounts[&0]["btc"], 100_000);
+    let ours: Vec<_> = details.outputs.iter().filter(|o| o.inout.is_relevant).collect();
+    assert_eq!(ours.len(), 1);
+    assert_eq!(ours[0].inout.satoshi, 100_000);
+    assert!(details.outputs.iter().all(|o| o.script_type.is_some() && o.op_return_data.is_none()));
+    assert!(details.inputs.iter().all(|i| !i.inout.is_relevant && i.prevout_txhash.is_some()));
+    assert_eq!(details.transaction, test_session.session.get_transaction_hex(&txid).unwrap());
+
+    test_session.mine_block();
+    let details = test_session.session.get_transaction_details(&txid).unwrap();
+    assert_eq!(details.block_height, 102);
+    assert_eq!(details.confirmations, 1);
+
+    // Spending our output the input carries the spent prevout details
+    let address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let node_address = address.parse::<gdk_common::bitcoin::Address<_>>().unwrap().assume_checked();
+    let spend_txid = test_session.send_all(&node_address, 1_000);
+    for i in 0.. {
+        assert!(i < 60, "timeout waiting for tx {}", spend_txid);
+        if test_session.get_tx_list(0).iter().any(|tx| tx.txhash == spend_txid) {
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+    let details = test_session.session.get_transaction_details(&spend_txid).unwrap();
+    assert_eq!(details.fee, Some(1_000));
+    assert_eq!(details.subaccounts[&0]["btc"], -100_000);
+    assert_eq!(details.inputs.len(), 1);
+    assert!(details.inputs[0].inout.is_relevant);
+    assert_eq!(details.inputs[0].inout.satoshi, 100_000);
+    assert_eq!(details.inputs[0].prevout_txhash.as_ref(), Some(&txid));
+
+    // A transaction not belonging to the wallet is fetched from the server
+    let foreign_txid = test_session.node.client.sendtoaddress(&address, 50_000, None).unwrap();
+    test_session.mine_block();
+    let details = test_session.session.get_transaction_details(&foreign_txid).unwrap();
+    assert!(details.not_wallet_tx);
+    assert_eq!(details.txhash, foreign_txid);
+    assert_eq!(details.confirmations, 1);
+    assert!(details.subaccounts.is_empty());
+    assert!(details.fee.is_some());
+    assert!(details.outputs.iter().all(|o| !o.inout.is_relevant));
+    assert!(details
+        .outputs
+        .iter()
+        .any(|o| o.inout.address == address && o.inout.satoshi == 50_000));
+    assert!(details.inputs.iter().all(|i| i.prevout_txhash.is_some() && i.inout.satoshi > 0));
+
+    let txid = "0000000000000000000000000000000000000000000000000000000000000000";
+    assert!(test_session.session.get_transaction_details(txid).is_err());
+
+    test_session.stop();
+}
+
+#[test]
+fn test_get_raw_transaction() {
+    let mut test_session = TestSession::new(|_| ());
+    let txid = test_session.fund(100_000);
+    let opt = |txid: &str, cache| GetRawTransactionOpt {
+        txid: txid.to_string(),
+        cache,
+    };
+    let node_hex = |test_session: &TestSession, txid: &str| {
+        test_session.node.client.call::<String>("getrawtransaction", &[txid.into()]).unwrap()
+    };
+
+    // A wallet transaction is served from the cache
+    let raw = test_session.session.get_raw_transaction(&opt(&txid, false)).unwrap();
+    assert_eq!(raw.source, RawTransactionSource::Wallet);
+    assert_eq!(raw.txhash, txid);
+    assert_eq!(raw.transaction, node_hex(&test_session, &txid));
+
+    // Missing from the cache it's fetched and put back with the wallet ones
+    let be_txid = gdk_common::be::BETxid::from_hex(&txid, test_session.network.id()).unwrap();
+    let store = test_session.session.store().unwrap();
+    store.write().unwrap().account_cache_mut(0).unwrap().all_txs.remove(&be_txid);
+    let raw = test_session.session.get_raw_transaction(&opt(&txid, false)).unwrap();
+    assert_eq!(raw.source, RawTransactionSource::Server);
+    assert_eq!(raw.transaction, node_hex(&test_session, &txid));
+    let raw = test_session.session.get_raw_transaction(&opt(&txid, false)).unwrap();
+    assert_eq!(raw.source, RawTransactionSource::Wallet);
+
+    // A transaction of the node only, kept apart when asked
+    let address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let foreign_txid = test_session.node.client.sendtoaddress(&address, 50_000, None).unwrap();
+    let raw = test_session.session.get_raw_transaction(&opt(&foreign_txid, false)).unwrap();
+    assert_eq!(raw.source, RawTransactionSource::Server);
+    assert_eq!(raw.transaction, node_hex(&test_session, &foreign_txid));
+    let raw = test_session.session.get_raw_transaction(&opt(&foreign_txid, true)).unwrap();
+    assert_eq!(raw.source, RawTransactionSource::Server);
+    let raw = test_session.session.get_raw_transaction(&opt(&foreign_txid, false)).unwrap();
+    assert_eq!(raw.source, RawTransactionSource::Foreign);
+    assert_eq!(raw.transaction, node_hex(&test_session, &foreign_txid));
+    assert!(test_session.get_tx_list(0).iter().all(|tx| tx.txhash != foreign_txid));
+
+    // Unknown to the server, or the server unreachable
+    let unknown = "0000000000000000000000000000000000000000000000000000000000000000";
+    let err = test_session.session.get_raw_transaction(&opt(unknown, true)).unwrap_err();
+    assert_eq!(err.code(), gdk_common::ErrorCode::TransactionNotFound);
+    assert_eq!(err.to_gdk_code(), "id_transaction_not_found");
+    test_session.electrs.kill().unwrap();
+    let err = test_session.session.get_raw_transaction(&opt(unknown, false)).unwrap_err();
+    assert!(err.is_connection_error(), "{:?}", err);
+    let raw = test_session.session.get_raw_transaction(&opt(&foreign_txid, false)).unwrap();
+    assert_eq!(raw.source, RawTransactionSource::Foreign);
+
+    // On Liquid the transactions keep their confidential data
+    let mut test_session = TestSession::builder().chain(Chain::Liquid).spawn();
+    let txid = test_session.fund(100_000);
+    let raw = test_session.session.get_raw_transaction(&opt(&txid, false)).unwrap();
+    assert_eq!(raw.transaction, node_hex(&test_session, &txid));
+    let bytes =
+        <Vec<u8> as gdk_common::bitcoin::hashes::hex::FromHex>::from_hex(&raw.transaction).unwrap();
+    let tx: gdk_common::elements::Transaction =
+        gdk_common::elements::encode::deserialize(&bytes).unwrap();
+    assert!(tx.output.iter().any(|o| o.value.is_confidential() && o.witness.rangeproof.is_some()));
+    test_session.stop();
+}
+
+#[test]
+fn test_sign_message() {
+    let mut test_session = TestSession::new(|_| ());
+    let message = "proof of funds";
+
+    // Legacy signatures of p2pkh addresses are verified by bitcoind
+    let xprv = test_session.create_subaccount(2);
+    let address = test_session.get_receive_address(2).address;
+    let opt = SignMessageOpt {
+        subaccount: 2,
+        address: Some(address.clone()),
+        path: None,
+        message: message.to_string(),
+        format: None,
+        xprv: Some(xprv),
+    };
+    let signed = test_session.session.sign_message(&opt).unwrap();
+    assert_eq!(signed.format, MessageFormat::Legacy);
+    assert_eq!(signed.address, address);
+    let valid: bool = test_session
+        .node
+        .client
+        .call(
+            "verifymessage",
+            &[address.clone().into(), signed.signature.clone().into(), message.into()],
+        )
+        .unwrap();
+    assert!(valid);
+    let mut verify = VerifyMessageOpt {
+        address: address.clone(),
+        message: message.to_string(),
+        signature: signed.signature,
+    };
+    assert!(test_session.session.verify_message(&verify).unwrap());
+
+    // Signatures made by bitcoind are verified by gdk
+    let node_address = test_session.node.client.getnewaddress(None, Some("legacy")).unwrap();
+    let signature: String = test_session
+        .node
+        .client
+        .call("signmessage", &[node_address.clone().into(), message.into()])
+        .unwrap();
+    verify.address = node_address;
+    verify.signature = signature;
+    assert!(test_session.session.verify_message(&verify).unwrap());
+    verify.message = "tampered".to_string();
+    assert!(!test_session.session.verify_message(&verify).unwrap());
+
+    // BIP322 signatures of bech32 addresses round-trip
+    let xprv = test_session.create_subaccount(17);
+    let opt = SignMessageOpt {
+        subaccount: 17,
+        address: None,
+        path: Some(vec![0.into(), 7.into()]),
+        message: message.to_string(),
+        format: Some(MessageFormat::Bip322),
+        xprv: Some(xprv),
+    };
+    let signed = test_session.session.sign_message(&opt).unwrap();
+    assert!(signed.address.starts_with("bcrt1q"));
+    let verify = VerifyMessageOpt {
+        address: signed.address.clone(),
+        message: message.to_string(),
+        signature: signed.signature,
+    };
+    assert!(test_session.session.verify_message(&verify).unwrap());
+
+    // The key must belong to the subaccount
+    let mut opt = opt;
+    opt.subaccount = 0;
+    assert!(test_session.session.sign_message(&opt).is_err());
+
+    test_session.stop();
+}
+
+#[test]
+fn test_ownership_proof() {
+    let mut test_session = TestSession::new(|_| ());
+    let funding_1 = test_session.fund(1_000_000);
+    let funding_2 = test_session.fund(500_000);
+    let message = "proof of reserves 42";
+    let mut opt = CreateOwnershipProofOpt {
+        subaccount: 0,
+        message: message.to_string(),
+        utxos: vec![],
+        xprv: Some(test_session.subaccount_xprv(0)),
+    };
+
+    // Every utxo of the subaccount
+    let proof = test_session.session.create_ownership_proof(&opt).unwrap();
+    assert_eq!(proof.satoshi, 1_500_000);
+    assert_eq!(proof.utxos.len(), 2);
+    let txids: Vec<_> = proof.utxos.iter().map(|u| u.txhash.clone()).collect();
+    assert!(txids.contains(&funding_1) && txids.contains(&funding_2));
+
+    // Verified without the wallet keys, only for the same message
+    let mut verify = VerifyOwnershipProofOpt {
+        proof: proof.proof.clone(),
+        message: message.to_string(),
+    };
+    let verified = test_session.session.verify_ownership_proof(&verify).unwrap();
+    assert_eq!(verified.satoshi, 1_500_000);
+    assert_eq!(verified.utxos, proof.utxos);
+    verify.message = "proof of reserves 43".to_string();
+    assert!(test_session.session.verify_ownership_proof(&verify).is_err());
+
+    // A selection of the utxos
+    let utxo = proof.utxos.iter().find(|u| u.txhash == funding_2).unwrap();
+    opt.utxos = vec![UtxoRef {
+        txhash: utxo.txhash.clone(),
+        pt_idx: utxo.pt_idx,
+    }];
+    let proof = test_session.session.create_ownership_proof(&opt).unwrap();
+    assert_eq!(proof.satoshi, 500_000);
+    opt.utxos[0].pt_idx += 2;
+    assert!(test_session.session.create_ownership_proof(&opt).is_err());
+
+    test_session.stop();
+}
+
+#[test]
+fn test_subscribe_transactions() {
+    let mut test_session = TestSession::new(|_| ());
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let sender = std::sync::Mutex::new(sender);
+    test_session.session.subscribe(&[NotificationKind::Transaction], move |ntf| {
+        sender.lock().unwrap().send(ntf.clone()).unwrap();
+    });
+    let wait_tx = |txid: &str| loop {
+        match receiver.recv_timeout(Duration::from_secs(60)).unwrap() {
+            Notification::Transaction {
+                txid: got,
+                ..
+            } if got.to_string() == txid => break,
+            Notification::Transaction {
+                ..
+            } => continue,
+            other => panic!("unexpected notification {:?}", other),
+        }
+    };
+
+    // incoming payment from the node
+    let txid = test_session.fund(100_000);
+    wait_tx(&txid);
+
+    let address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let address = address.parse::<gdk_common::bitcoin::Address<_>>().unwrap().assume_checked();
+    let txid = test_session.send_all(&address, 1_000);
+    wait_tx(&txid);
+
+    test_session.stop();
+}
+
+#[test]
+fn test_notification_latency() {
+    // Without the server notifications the wallet would sync every 10 minutes
+    let mut test_session = TestSession::new(|network| network.sync_interval = Some(600));
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let sender = std::sync::Mutex::new(sender);
+    let kinds = [NotificationKind::Transaction, NotificationKind::Block];
+    test_session.session.subscribe(&kinds, move |ntf| {
+        sender.lock().unwrap().send(ntf.clone()).unwrap();
+    });
+    // Generous bounds, it takes less than a second
+    let max_latency = Duration::from_secs(5);
+
+    let ap = test_session.get_receive_address(0);
+    let txid = test_session.node.client.sendtoaddress(&ap.address, 10_000, None).unwrap();
+    test_session.electrs.trigger().unwrap();
+    let start = Instant::now();
+    loop {
+        match receiver.recv_timeout(Duration::from_secs(60)).unwrap() {
+            Notification::Transaction {
+                txid: got,
+                ..
+            } if got.to_string() == txid => break,
+            _ => continue,
+        }
+    }
+    let latency = start.elapsed();
+    assert!(latency < max_latency, "transaction notified after {:?}", latency);
+
+    test_session.node_generate(1);
+    let start = Instant::now();
+    loop {
+        match receiver.recv_timeout(Duration::from_secs(60)).unwrap() {
+            Notification::Block {
+                height: 102,
+                ..
+            } => break,
+            _ => continue,
+        }
+    }
+    let latency = start.elapsed();
+    assert!(latency < max_latency, "block notified after {:?}", latency);
+
+    let metrics = test_session.session.get_session_metrics().unwrap();
+    assert!(metrics.syncs >= 3);
+    let now = gdk_common::util::now() / 1_000_000;
+    assert!(metrics.last_sync_timestamp.unwrap() + 60 > now);
+
+    test_session.stop();
+}
+
+#[test]
+fn test_sync_skips_unchanged_histories() {
+    let mut test_session = TestSession::new(|_| ());
+    let metrics = |test_session: &TestSession| test_session.session.get_session_metrics().unwrap();
+    let wait_syncs = |test_session: &TestSession, syncs: u64| {
+        for i in 0.. {
+            assert!(i < 60, "timeout waiting for {} syncs", syncs);
+            if metrics(test_session).syncs >= syncs {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    };
+    for _ in 0..3 {
+        test_session.fund(10_000);
+    }
+
+    // Nothing changed, nothing is fetched
+    let before = metrics(&test_session);
+    wait_syncs(&test_session, before.syncs + 3);
+    assert_eq!(metrics(&test_session).histories_fetched, before.histories_fetched);
+
+    // A script used for the first time, its status was null
+    let before = metrics(&test_session);
+    test_session.fund(10_000);
+    let after = metrics(&test_session);
+    assert_eq!(after.histories_fetched, before.histories_fetched + 1);
+
+    test_session.stop();
+}
+
+#[test]
+fn test_health() {
+    let mut test_session = TestSession::new(|_| ());
+    let wait_health = |test_session: &TestSession, status: HealthStatus| {
+        for i in 0.. {
+            let health = test_session.session.get_health().unwrap();
+            if health.status == status {
+                return health;
+            }
+            assert!(i < 100, "timeout waiting for {:?}, got {:?}", status, health);
+            thread::sleep(Duration::from_millis(100));
+        }
+        unreachable!()
+    };
+
+    let health = wait_health(&test_session, HealthStatus::Healthy);
+    assert!(health.reasons.is_empty());
+    assert!(health.connected);
+    assert_eq!(health.server_tip_height, Some(101));
+    assert!(health.last_message_age.unwrap() < 10);
+
+    // The server goes away
+    test_session.electrs.kill().unwrap();
+    let health = wait_health(&test_session, HealthStatus::Degraded);
+    assert!(health.reasons.contains(&HealthReason::ServerUnreachable), "{:?}", health);
+    assert!(!health.connected);
+
+    test_session.session.disconnect().unwrap();
+    let health = test_session.session.get_health().unwrap();
+    assert_eq!(health.status, HealthStatus::Unhealthy);
+    assert_eq!(health.reasons, vec![HealthReason::NotSyncing]);
+}
+
+#[test]
+fn test_shared_tx_fetched_once() {
+    let mut test_session = TestSession::new(|_| ());
+    test_session.create_subaccount(1);
+
+    // One transaction paying five addresses of two subaccounts, synced concurrently
+    let mut amounts = serde_json::Map::new();
+    for subaccount in [0, 0, 0, 1, 1] {
+        let address = test_session.get_receive_address(subaccount).address;
+        amounts.insert(address, "0.0001".into());
+    }
+    let before = test_session.session.get_session_metrics().unwrap();
+    let txid: String =
+        test_session.node.client.call("sendmany", &["".into(), amounts.into()]).unwrap();
+    test_session.wait_tx(vec![0, 1], &txid, None, Some(TransactionType::Incoming));
+    let after = test_session.session.get_session_metrics().unwrap();
+
+    // The transaction and the previous ones of its inputs are fetched once each
+    let tx: Value = test_session
+        .node
+        .client
+        .call("getrawtransaction", &[txid.clone().into(), true.into()])
+        .unwrap();
+    let previous_txids: std::collections::HashSet<&str> =
+        tx["vin"].as_array().unwrap().iter().map(|input| input["txid"].as_str().unwrap()).collect();
+    assert_eq!(after.txs_fetched - before.txs_fetched, 1 + previous_txids.len() as u64);
+
+    test_session.stop();
+}
+
+#[test]
+fn test_incremental_balance() {
+    let mut test_session = TestSession::new(|_| ());
+    let opt = GetBalanceOpt::default();
+    let time_balances = |test_session: &TestSession| {
+        let start = Instant::now();
+        for _ in 0..100 {
+            test_session.session.get_balance(&opt).unwrap();
+        }
+        start.elapsed()
+    };
+    test_session.fund(1_000);
+    let initial = time_balances(&test_session);
+
+    // A few hundred incoming transactions, mined in batches to stay within the
+    // mempool chain limits of the node
+    let address = test_session.get_receive_address(0).address;
+    let mut expected = 1_000;
+    for i in 0..300 {
+        test_session.node.client.sendtoaddress(&address, 1_000 + i, None).unwrap();
+        expected += 1_000 + i;
+        if i % 25 == 24 {
+            test_session.mine_block();
+        }
+    }
+    test_session.mine_block();
+    for i in 0.. {
+        assert!(i < 60, "timeout waiting for balance {}", expected);
+        if test_session.session.get_balance(&opt).unwrap()["btc"] == expected as i64 {
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    // Equal to the balance computed from the unspent outputs
+    let confirmed = GetBalanceOpt {
+        num_confs: Some(1),
+        ..opt.clone()
+    };
+    let balance = test_session.session.get_balance(&confirmed).unwrap();
+    assert_eq!(balance["btc"], expected as i64);
+    test_session.session.verify_store().unwrap();
+
+    let after = time_balances(&test_session);
+    assert!(
+        after < initial * 10 + Duration::from_millis(50),
+        "get_balance took {:?} with 300 txs, {:?} with 1",
+        after,
+        initial
+    );
+
+    test_session.stop();
+}
+
+#[test]
+fn test_parallel_restore() {
+    let mut test_session = TestSession::new(|_| ());
+    let xprv = test_session.create_subaccount(1);
+    test_session.fund(10_000);
+    let address = test_session.get_receive_address(1).address;
+    let txid = test_session.node.client.sendtoaddress(&address, 20_000, None).unwrap();
+    test_session.wait_tx(vec![1], &txid, Some(20_000), Some(TransactionType::Incoming));
+    test_session.mine_block();
+
+    // Restore the wallet with both subaccounts in an empty state dir
+    let state_dir = TempDir::new().unwrap();
+    let mut network = test_session.network.clone();
+    network.state_dir = state_dir.path().display().to_string();
+    let mut session = ElectrumSession::new(network.clone()).unwrap();
+    session.login_with_signer(test_session.software_signer()).unwrap();
+    let opt = CreateAccountOpt {
+        subaccount: 1,
+        name: String::new(),
+        xpub: gdk_common::bitcoin::bip32::Xpub::from_priv(&gdk_common::EC, &xprv),
+        discovered: true,
+        is_already_created: true,
+        allow_gaps: true,
+    };
+    session.create_subaccount(opt).unwrap();
+    session.connect(&serde_json::to_value(&network).unwrap()).unwrap();
+
+    let balance = |subaccount| {
+        let opt = GetBalanceOpt {
+            subaccount,
+            ..Default::default()
+        };
+        session.get_balance(&opt).unwrap().get("btc").copied()
+    };
+    for i in 0.. {
+        assert!(i < 60, "timeout waiting for the restore");
+        let metrics = session.get_session_metrics().unwrap();
+        if balance(0) == Some(10_000) && balance(1) == Some(20_000) && metrics.syncs > 0 {
+            // The subaccounts were synced at once
+            assert!(metrics.max_parallel_syncs >= 2);
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    session.disconnect().unwrap();
+    test_session.stop();
+}
+
+#[test]
+fn test_quick_login() {
+    let mut test_session = TestSession::new(|_| ());
+    let network = test_session.network.clone();
+    let credentials = test_session.credentials.clone();
+    let master_xpub = credentials.master_xpub(&network).unwrap();
+    let opt = QuickLoginOpt {
+        wallet_hash_id: network.wallet_hash_id(&master_xpub),
+    };
+
+    // Only the credentials of the wallet logged in are kept
+    let other = Credentials {
+        mnemonic: credentials.mnemonic.clone(),
+        bip39_passphrase: "other".into(),
+    };
+    assert!(test_session.session.enable_quick_login(&other).is_err());
+    test_session.session.enable_quick_login(&credentials).unwrap();
+    test_session.stop();
+
+    let mut session = ElectrumSession::new(network.clone()).unwrap();
+    let login_data = session.quick_login(&opt).unwrap();
+    assert_eq!(login_data.wallet_hash_id, opt.wallet_hash_id);
+    session.stop().unwrap();
+
+    // The keystore can't unwrap the key any longer, eg. the biometrics changed
+    struct InvalidatedProtector;
+    impl CredentialProtector for InvalidatedProtector {
+        fn wrap_key(&self, key: &[u8]) -> Result<Vec<u8>, gdk_electrum::error::Error> {
+            Ok(key.to_vec())
+        }
+        fn unwrap_key(&self, _wrapped: &[u8]) -> Result<Vec<u8>, gdk_electrum::error::Error> {
+            Err(gdk_electrum::error::Error::Generic("key permanently invalidated".into()))
+        }
+    }
+    let mut session = ElectrumSession::new(network.clone()).unwrap();
+    session.set_credential_protector(Arc::new(InvalidatedProtector));
+    let err = session.quick_login(&opt).unwrap_err();
+    assert_eq!(err.code(), gdk_common::ErrorCode::QuickLoginUnavailable);
+
+    // The mnemonic login still works
+    let signer = SoftwareSigner::from_seed(&credentials.seed().unwrap(), network.bip32_network());
+    session.login_with_signer(Arc::new(signer.unwrap())).unwrap();
+    session.disable_quick_login(&opt).unwrap();
+    session.stop().unwrap();
+    let err = ElectrumSession::new(network).unwrap().quick_login(&opt).unwrap_err();
+    assert_eq!(err.code(), gdk_common::ErrorCode::QuickLoginUnavailable);
+}
+
+#[test]
+fn test_wallet_info() {
+    let mut test_session = TestSession::new(|_| ());
+    test_session.fund(100_000);
+    test_session.create_subaccount(1);
+    let address = test_session.get_receive_address(1).address;
+    let txid = test_session.node.client.sendtoaddress(&address, 20_000, None).unwrap();
+    test_session.wait_tx(vec![1], &txid, Some(20_000), Some(TransactionType::Incoming));
+    test_session.mine_block();
+
+    let info = test_session.session.get_wallet_info().unwrap();
+    let master_xpub = test_session.credentials.master_xpub(&test_session.network).unwrap();
+    assert_eq!(info.wallet_hash_id, Some(test_session.network.wallet_hash_id(&master_xpub)));
+    assert_eq!(info.network, test_session.network.name);
+    assert!(!info.watch_only);
+    let types: Vec<_> = info.subaccounts.iter().map(|s| (s.pointer, s.script_type)).collect();
+    assert_eq!(types, vec![(0, ScriptType::P2shP2wpkh), (1, ScriptType::P2wpkh)]);
+    assert_eq!(info.balances.get("btc"), Some(&120_000));
+    assert_eq!(info.wallet_birthday, None);
+    assert_eq!(info.store_version, 1);
+    // Logged in with a signer, the mnemonic was never seen
+    assert_eq!(info.bip39_passphrase, None);
+
+    let value = test_session.session.handle_call("get_wallet_info", Value::Null).unwrap();
+    assert_eq!(value["subaccounts"][1]["type"], "p2wpkh");
+    test_session.stop();
+
+    // Logged in with the mnemonic, offline
+    let mut session = ElectrumSession::new(test_session.network.clone()).unwrap();
+    session.login(&test_session.credentials).unwrap();
+    let info = session.get_wallet_info().unwrap();
+    assert_eq!(info.bip39_passphrase, Some(false));
+    assert_eq!(info.balances.get("btc"), Some(&120_000));
+    session.stop().unwrap();
+}
+
+#[test]
+fn test_wallet_birthday() {
+    let mut test_session = TestSession::new(|_| ());
+    test_session.fund(10_000);
+    test_session.mine_block();
+    test_session.node_generate(10);
+    test_session.wait_blockheight(112);
+    let checkpoint = test_session.node.client.get_block_hash(110).unwrap();
+
+    // Restores in empty state dirs, on a network with a checkpoint at 110
+    let restore = |wallet_birthday: Option<WalletBirthday>| {
+        let state_dir = TempDir::new().unwrap();
+        let mut network = test_session.network.clone();
+        network.state_dir = state_dir.path().display().to_string();
+        network.headers_checkpoints.insert(110, checkpoint.to_string().parse().unwrap());
+        let mut session = ElectrumSession::new(network.clone()).unwrap();
+        session.restore_with_signer(test_session.software_signer(), wallet_birthday).unwrap();
+        session.connect(&serde_json::to_value(&network).unwrap()).unwrap();
+        (session, state_dir)
+    };
+    let wait_restored = |session: &ElectrumSession, headers: u64| -> i64 {
+        for i in 0.. {
+            assert!(i < 60, "timeout waiting for the restore");
+            let metrics = session.get_session_metrics().unwrap();
+            if metrics.syncs > 0 && metrics.headers_downloaded >= headers {
+                assert_eq!(metrics.headers_downloaded, headers);
+                return session.get_balance(&GetBalanceOpt::default()).unwrap()["btc"];
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+        unreachable!()
+    };
+
+    // Without a birthday the headers are downloaded from genesis
+    let (mut session, _state_dir) = restore(None);
+    assert_eq!(wait_restored(&session, 112), 10_000);
+    assert_eq!(session.get_wallet_info().unwrap().wallet_birthday, None);
+    session.disconnect().unwrap();
+
+    // From the checkpoint below the birthday, the older transactions are still synced
+    let birthday = WalletBirthday {
+        height: Some(111),
+        timestamp: None,
+        asserted: false,
+    };
+    let (mut session, _state_dir) = restore(Some(birthday.clone()));
+    assert_eq!(wait_restored(&session, 2), 10_000);
+    assert_eq!(session.get_wallet_info().unwrap().wallet_birthday, Some(birthday.clone()));
+    session.disconnect().unwrap();
+
+    // Unless the user asserts the wallet has none
+    let asserted = WalletBirthday {
+        asserted: true,
+        ..birthday
+    };
+    let (mut session, _state_dir) = restore(Some(asserted));
+    assert_eq!(wait_restored(&session, 2), 0);
+    session.disconnect().unwrap();
+
+    // A timestamp is resolved to a height with the server
+    let time = test_session.node.client.get_block_header_info(&checkpoint).unwrap().time as u64;
+    let by_time = WalletBirthday {
+        height: None,
+        timestamp: Some(time + 3 * 60 * 60),
+        asserted: false,
+    };
+    let (mut session, _state_dir) = restore(Some(by_time));
+    assert_eq!(wait_restored(&session, 2), 10_000);
+    let resolved = session.get_wallet_info().unwrap().wallet_birthday.unwrap();
+    assert_eq!(resolved.timestamp, Some(time + 3 * 60 * 60));
+    // The regtest blocks are all mined before the timestamp, less two hours
+    assert_eq!(resolved.height, Some(112));
+    session.disconnect().unwrap();
+
+    test_session.stop();
+}
+
+#[test]
+fn test_trust_own_unconfirmed() {
+    let mut test_session = TestSession::new(|_| ());
+
+    let txid = test_session.fund(100_000);
+    test_session.mine_block();
+    test_session.wait_for_tx(&txid, 1, Duration::from_secs(60));
+
+    test_session
+        .session
+        .change_settings(&serde_json::json!({"trust_own_unconfirmed": false}))
+        .unwrap();
+
+    let address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let address = address.parse::<gdk_common::bitcoin::Address<_>>().unwrap().assume_checked();
+    let tx = test_session.create_tx(&address, 10_000, 1_000);
+    let txid = test_session.session.broadcast_transaction(&tx).unwrap();
+    test_session.wait_for_tx(&txid, 0, Duration::from_secs(60));
+
+    let balance = |test_session: &TestSession, trust_own_unconfirmed| {
+        let opt = GetBalanceOpt {
+            subaccount: 0,
+            trust_own_unconfirmed,
+            ..Default::default()
+        };
+        test_session.session.get_balance(&opt).unwrap()["btc"]
+    };
+    let spendable = |test_session: &TestSession| {
+        let opt = GetUnspentOpt {
+            subaccount: 0,
+            ..Default::default()
+        };
+        let mut utxos = test_session.session.get_unspent_outputs(&opt).unwrap().0;
+        utxos.remove("btc").unwrap_or_default()
+    };
+
+    // The change is not spendable until mined
+    assert_eq!(balance(&test_session, None), 0);
+    assert!(spendable(&test_session).is_empty());
+    assert_eq!(balance(&test_session, Some(true)), 89_000);
+
+    // The subaccount settings override the wallet ones
+    let opt = UpdateAccountOpt {
+        subaccount: 0,
+        trust_own_unconfirmed: Some(true),
+        ..Default::default()
+    };
+    test_session.session.update_subaccount(opt).unwrap();
+    assert_eq!(balance(&test_session, None), 89_000);
+    assert_eq!(spendable(&test_session).len(), 1);
+    let opt = UpdateAccountOpt {
+        subaccount: 0,
+        trust_own_unconfirmed: Some(false),
+        ..Default::default()
+    };
+    test_session.session.update_subaccount(opt).unwrap();
+    assert_eq!(balance(&test_session, None), 0);
+
+    test_session.mine_block();
+    test_session.wait_for_tx(&txid, 1, Duration::from_secs(60));
+    assert_eq!(balance(&test_session, None), 89_000);
+    assert_eq!(spendable(&test_session).len(), 1);
+
+    test_session.stop();
+}
+
+#[test]
+fn test_fee_estimates_offline() {
+    let mut test_session = TestSession::new(|_| ());
+
+    let estimates = test_session.session.get_fee_estimates().unwrap();
+    assert!(!estimates.stale);
+    assert!(estimates.fetched_at.is_some());
+
+    // Logging in again with the server down serves the persisted estimates
+    test_session.session.disconnect().unwrap();
+    test_session.electrs.kill().unwrap();
+    let session = test_session.new_session_same_wallet();
+    let offline = session.get_fee_estimates().unwrap();
+    assert!(offline.stale);
+    assert_eq!(offline.fetched_at, estimates.fetched_at);
+    let fees = |e: &FeeEstimates| e.fees.iter().map(|f| f.0).collect::<Vec<_>>();
+    assert_eq!(fees(&offline), fees(&estimates));
+
+    // The failed fetch is remembered, the server is not asked again right away
+    let start = Instant::now();
+    let again = session.get_fee_estimates().unwrap();
+    assert!(again.stale);
+    assert_eq!(again.fetched_at, estimates.fetched_at);
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    // Once too old, they're not used to pick the fee rate of transactions
+    let mut session = session;
+    session.change_settings(&serde_json::json!({"max_fee_estimates_age": 0})).unwrap();
+    thread::sleep(Duration::from_millis(1100));
+    let address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let mut opt = CreateWalletMigrationOpt {
+        destination_xpub: None,
+        destination_master_blinding_key: None,
+        destination_addresses: vec![address],
+        fee_rate: None,
+    };
+    let err = session.create_wallet_migration(&opt).unwrap_err();
+    assert_eq!(err.to_gdk_code(), "id_fee_estimates_are_stale");
+    opt.fee_rate = Some(1000);
+    let err = session.create_wallet_migration(&opt).unwrap_err();
+    assert_eq!(err.to_gdk_code(), "id_insufficient_funds");
+
+    test_session.stop();
+}
+
+#[test]
+fn test_tx_confirmation_notifications() {
+    let mut test_session = TestSession::new(|_| ());
+    let txid = test_session.fund(100_000);
+    let tx_events = |test_session: &TestSession| -> Vec<Value> {
+        test_session
+            .session
+            .filter_events("transaction")
+            .into_iter()
+            .filter(|e| e["transaction"]["txhash"] == txid.as_str())
+            .collect()
+    };
+
+    // The mempool arrival is notified before the tx is mined
+    let events = tx_events(&test_session);
+    assert_eq!(events.len(), 1);
+    assert!(events[0]["transaction"].get("block_height").is_none());
+    assert_eq!(events[0]["transaction"]["satoshi_delta"]["btc"], 100_000);
+
+    test_session.mine_block();
+    let mut events = tx_events(&test_session);
+    for _ in 0..60 {
+        if events.len() > 1 {
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+        events = tx_events(&test_session);
+    }
+    assert_eq!(events.len(), 2, "missing confirmation notification");
+    assert_eq!(events[1]["transaction"]["block_height"], 102);
+    assert_eq!(events[1]["transaction"]["subaccounts"], serde_json::json!([0]));
+    assert!(events[1]["transaction"].get("removed").is_none());
+
+    test_session.stop();
+}
+
+#[test]
+fn test_pending_notifications() {
+    let mut test_session = TestSession::new(|_| ());
+    test_session.mine_block();
+    let last = test_session
+        .session
+        .get_last_notification_of_type(&GetLastNotificationOpt {
+            kind: NotificationKind::Block,
+        })
+        .unwrap()
+        .unwrap();
+    assert_eq!(last["block"]["block_height"], 102);
+
+    // The block notified before registering is delivered on registration
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let sender = std::sync::Mutex::new(sender);
+    test_session.session.subscribe(&[NotificationKind::Block], move |ntf| {
+        sender.lock().unwrap().send(ntf.clone()).unwrap();
+    });
+    loop {
+        match receiver.recv_timeout(Duration::from_secs(10)).unwrap() {
+            Notification::Block {
+                height: 102,
+                ..
+            } => break,
+            _ => continue,
+        }
+    }
+
+    test_session.stop();
+}
+
+#[test]
+fn test_settings_concurrent_sessions() {
+    let mut test_session = TestSession::new(|_| ());
+    let revision = test_session.session.get_settings().unwrap().revision();
+
+    // Another session of the same wallet changes the settings
+    let mut session2 = test_session.new_session_same_wallet();
+    session2.change_settings(&serde_json::json!({"unit": "sats", "revision": revision})).unwrap();
+
+    // The running session picks up the change and notifies it
+    for i in 0.. {
+        assert!(i < 20, "timeout waiting for settings change");
+        let settings = test_session.session.get_settings().unwrap();
+        if settings.revision() == revision + 1 {
+            assert_eq!(settings.unit, "sats");
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+    let last = test_session.session.filter_events("settings").last().cloned().unwrap();
+    assert_eq!(last["settings"]["unit"], "sats");
+    assert_eq!(last["settings"]["revision"], revision + 1);
+
+    // A change based on a stale revision is rejected
+    let err = test_session
+        .session
+        .change_settings(&serde_json::json!({"unit": "BTC", "revision": revision}))
+        .unwrap_err();
+    assert_eq!(err.to_gdk_code(), "id_settings_conflict");
+    assert_eq!(test_session.session.get_settings().unwrap().unit, "sats");
+
+    test_session.stop();
+}
+
+#[test]
+fn test_amounts_in_unit() {
+    let mut test_session = TestSession::new(|_| ());
+    let txid = test_session.fund(1_234_567);
+
+    let listed = |test_session: &TestSession| {
+        let mut opt = GetTransactionsOpt::default();
+        opt.count = 10;
+        let txs = test_session.session.get_transactions(&opt).unwrap().transactions;
+        let tx = txs.into_iter().find(|tx| tx.txhash == txid).unwrap();
+        let opt = GetUnspentOpt {
+            num_confs: Some(0),
+            ..Default::default()
+        };
+        let mut utxos = test_session.session.get_unspent_outputs(&opt).unwrap().0;
+        (tx, utxos.remove("btc").unwrap().remove(0))
+    };
+
+    let (tx, utxo) = listed(&test_session);
+    assert_eq!(tx.amounts["btc"], "0.01234567");
+    assert_eq!(utxo.amount, "0.01234567");
+
+    // The unit change applies to the next calls
+    for (unit, formatted) in [("bits", "12345.67"), ("mbtc", "12.34567"), ("sats", "1234567")] {
+        test_session.session.change_settings(&serde_json::json!({ "unit": unit })).unwrap();
+        let (tx, utxo) = listed(&test_session);
+        assert_eq!(tx.satoshi["btc"], 1_234_567);
+        assert_eq!(tx.amounts["btc"], formatted);
+        assert_eq!(utxo.satoshi, 1_234_567);
+        assert_eq!(utxo.amount, formatted);
+    }
+    let (tx, _) = listed(&test_session);
+    assert_eq!(tx.fee_amount, tx.fee.to_string());
+
+    // Unknown units are rejected
+    let value = serde_json::json!({ "unit": "satoshi" });
+    assert!(test_session.session.change_settings(&value).is_err());
+    assert_eq!(test_session.session.get_settings().unwrap().unit, "sats");
+
+    test_session.stop();
+}
+
+#[test]
+fn test_convert_amount() {
+    struct FixedRates;
+
+    impl PriceSource for FixedRates {
+        fn fetch(&self, pair: Pair, _exchange: &str) -> Result<Ticker, gdk_electrum::error::Error> {
+            let rate = match pair.second().to_string().as_str() {
+                "USD" => 20_000.0,
+                "EUR" => 18_000.0,
+                _ => return Err(gdk_electrum::error::Error::Generic("unknown pair".into())),
+            };
+            Ok(Ticker::new(pair, rate))
+        }
+
+        fn available_currencies(&self) -> Result<AvailableCurrencies, gdk_electrum::error::Error> {
+            let currencies = ["USD", "EUR", "JPY"].iter().map(|c| c.parse().unwrap()).collect();
+            Ok(AvailableCurrencies::from([("BITFINEX".to_string(), currencies)]))
+        }
+    }
+
+    let mut test_session = TestSession::new(|_| ());
+
+    // Without a price server the fiat values are not available
+    let input = serde_json::json!({"satoshi": 150_000_000});
+    let converted = test_session.session.handle_call("convert_amount", input.clone()).unwrap();
+    assert_eq!(converted["btc"], "1.50000000");
+    assert_eq!(converted["fiat"], Value::Null);
+    assert_eq!(converted["fiat_currency"], "USD");
+    assert_eq!(converted["fiat_rate_stale"], true);
+
+    test_session.session.set_price_source(Arc::new(FixedRates));
+    let converted = test_session.session.handle_call("convert_amount", input.clone()).unwrap();
+    assert_eq!(converted["fiat"], "30000.00");
+    assert_eq!(converted["fiat_rate"], "20000.00000000");
+    assert_eq!(converted["fiat_rate_stale"], false);
+    assert!(converted["fiat_rate_timestamp"].as_u64().unwrap() > 0);
+
+    // Changing the currency in the settings changes the conversion
+    test_session
+        .session
+        .change_settings(&serde_json::json!({"pricing": {"currency": "EUR"}}))
+        .unwrap();
+    let converted = test_session.session.handle_call("convert_amount", input).unwrap();
+    assert_eq!(converted["fiat"], "27000.00");
+    assert_eq!(converted["fiat_currency"], "EUR");
+    let converted = test_session
+        .session
+        .handle_call("convert_amount", serde_json::json!({"fiat": "9000"}))
+        .unwrap();
+    assert_eq!(converted["satoshi"], 50_000_000);
+
+    // Currencies the source has no rate for
+    test_session
+        .session
+        .change_settings(&serde_json::json!({"pricing": {"currency": "JPY"}}))
+        .unwrap();
+    let converted = test_session
+        .session
+        .handle_call("convert_amount", serde_json::json!({"bits": "1.5"}))
+        .unwrap();
+    assert_eq!(converted["satoshi"], 150);
+    assert_eq!(converted["fiat"], Value::Null);
+    assert_eq!(converted["fiat_rate_stale"], true);
+
+    test_session.stop();
+}
+
+#[test]
+fn test_available_currencies() {
+    struct TwoCurrencies {
+        offline: AtomicBool,
+    }
+
+    impl PriceSource for TwoCurrencies {
+        fn fetch(&self, pair: Pair, _exchange: &str) -> Result<Ticker, gdk_electrum::error::Error> {
+            Ok(Ticker::new(pair, 20_000.0))
+        }
+
+        fn available_currencies(&self) -> Result<AvailableCurrencies, gdk_electrum::error::Error> {
+            if self.offline.load(Ordering::Relaxed) {
+                return Err(gdk_electrum::error::Error::Generic("offline".into()));
+            }
+            let currencies = vec![Currency::USD, Currency::EUR];
+            Ok(AvailableCurrencies::from([("BITFINEX".to_string(), currencies)]))
+        }
+    }
+
+    let mut test_session = TestSession::new(|_| ());
+
+    // Without a price server there are no currencies and the pricing is not validated
+    let input = serde_json::json!({});
+    assert!(test_session.session.handle_call("get_available_currencies", input.clone()).is_err());
+    let pricing = serde_json::json!({"pricing": {"currency": "JPY"}});
+    test_session.session.change_settings(&pricing).unwrap();
+
+    let source = Arc::new(TwoCurrencies {
+        offline: AtomicBool::new(false),
+    });
+    test_session.session.set_price_source(source.clone());
+    let currencies =
+        test_session.session.handle_call("get_available_currencies", input.clone()).unwrap();
+    assert_eq!(currencies["all"], serde_json::json!(["USD", "EUR"]));
+    assert_eq!(currencies["per_exchange"]["BITFINEX"], serde_json::json!(["USD", "EUR"]));
+
+    // Offline the cached currencies are served and used for validation
+    source.offline.store(true, Ordering::Relaxed);
+    let cached =
+        test_session.session.handle_call("get_available_currencies", input.clone()).unwrap();
+    assert_eq!(cached, currencies);
+
+    let pricing = serde_json::json!({"pricing": {"currency": "EUR"}});
+    test_session.session.change_settings(&pricing).unwrap();
+    assert_eq!(test_session.session.get_settings().unwrap().pricing.currency(), "EUR");
+
+    for pricing in [
+        serde_json::json!({"pricing": {"currency": "JPY"}}),
+        serde_json::json!({"pricing": {"exchange": "KRAKEN"}}),
+    ] {
+        let err = test_session.session.change_settings(&pricing).unwrap_err();
+        assert_eq!(err.to_gdk_code(), "id_unsupported_currency");
+    }
+    let settings = test_session.session.get_settings().unwrap();
+    assert_eq!(settings.pricing.currency(), "EUR");
+    assert_eq!(settings.pricing.exchange(), "BITFINEX");
+
+    test_session.stop();
+}
+
+#[test]
+fn test_spend_limits() {
+    let mut test_session = TestSession::new(|_| ());
+    test_session.fund(1_000_000);
+    let limits = serde_json::json!({"spend_limits": {"per_transaction": 50_000}});
+    test_session.session.change_settings(&limits).unwrap();
+
+    let wait_tx = |test_session: &TestSession, txid: &str| {
+        for _ in 0..60 {
+            if test_session.get_tx_list(0).iter().any(|e| e.txhash == txid) {
+                return;
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+        panic!("timeout waiting for tx {}", txid);
+    };
+
+    let address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let address = address.parse::<gdk_common::bitcoin::Address<_>>().unwrap().assume_checked();
+
+    // Below the limit the transaction is sent without confirmation
+    let tx = test_session.create_tx(&address, 10_000, 1_000);
+    let check = test_session.session.check_spend_limits(&tx).unwrap();
+    assert_eq!(check.satoshi, 11_000);
+    assert!(!check.requires_confirmation);
+    let opt = SendTransactionOpt {
+        transaction: tx,
+        confirmation_token: None,
+        payjoin: None,
+        client_request_id: None,
+        check_acceptance: false,
+    };
+    let txid = test_session.session.send_transaction(&opt).unwrap().txhash;
+    wait_tx(&test_session, &txid);
+
+    // Above the limit a one-time token from approve_transaction is required
+    let tx = test_session.create_tx(&address, 100_000, 1_000);
+    let check = test_session.session.check_spend_limits(&tx).unwrap();
+    assert_eq!(check.spent_last_day, 11_000);
+    assert!(check.requires_confirmation);
+    for confirmation_token in [None, Some("00".repeat(32))] {
+        let opt = SendTransactionOpt {
+            transaction: tx.clone(),
+            confirmation_token,
+            payjoin: None,
+            client_request_id: None,
+            check_acceptance: false,
+        };
+        let err = test_session.session.send_transaction(&opt).unwrap_err();
+        assert_eq!(err.to_gdk_code(), "id_confirmation_required");
+    }
+    // Broadcasting doesn't skip the approval
+    let err = test_session.session.broadcast_transaction(&tx).unwrap_err();
+    assert_eq!(err.to_gdk_code(), "id_confirmation_required");
+    let token = test_session.session.approve_transaction(&tx).unwrap();
+    let opt = SendTransactionOpt {
+        transaction: tx,
+        confirmation_token: Some(token),
+        payjoin: None,
+        client_request_id: None,
+        check_acceptance: false,
+    };
+    let txid = test_session.session.send_transaction(&opt).unwrap().txhash;
+    wait_tx(&test_session, &txid);
+    let err = test_session.session.send_transaction(&opt).unwrap_err();
+    assert_eq!(err.to_gdk_code(), "id_confirmation_required");
+
+    // The accounting survives in the store
+    let tx = test_session.create_tx(&address, 1_000, 1_000);
+    let check = test_session.session.check_spend_limits(&tx).unwrap();
+    assert_eq!(check.spent_last_day, 112_000);
+
+    test_session.stop();
+}
+
+#[test]
+fn test_spend_limits_p2pkh() {
+    let mut test_session = TestSession::new(|_| ());
+    let limits = serde_json::json!({"spend_limits": {"per_transaction": 50_000}});
+    test_session.session.change_settings(&limits).unwrap();
+
+    // Signing p2pkh inputs changes the txid, the approval of the unsigned
+    // transaction still holds for the signed one
+    let xprv = test_session.create_subaccount(2);
+    let funding_address = test_session.get_receive_address(2).address;
+    let funding_txid =
+        test_session.node.client.sendtoaddress(&funding_address, 1_000_000, None).unwrap();
+    test_session.wait_tx(vec![2], &funding_txid, Some(1_000_000), Some(TransactionType::Incoming));
+    let address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let address = address.parse::<gdk_common::bitcoin::Address<_>>().unwrap().assume_checked();
+    let (unsigned, signed) = test_session.create_subaccount_tx(2, xprv, &address, 100_000, 1_000);
+    assert!(test_session.session.check_spend_limits(&signed).unwrap().requires_confirmation);
+    let token = test_session.session.approve_transaction(&unsigned).unwrap();
+    let opt = SendTransactionOpt {
+        transaction: signed,
+        confirmation_token: Some(token),
+        payjoin: None,
+        client_request_id: None,
+        check_acceptance: false,
+    };
+    let txid = test_session.session.send_transaction(&opt).unwrap().txhash;
+    test_session.wait_tx(vec![2], &txid, Some(101_000), Some(TransactionType::Outgoing));
+    let err = test_session.session.send_transaction(&opt).unwrap_err();
+    assert_eq!(err.to_gdk_code(), "id_confirmation_required");
+
+    test_session.stop();
+}
+
+#[test]
+fn test_privacy_mode_logs() {
+    let mut test_session = TestSession::new(|network| network.privacy_mode = Some(true));
+    let recorder = LogRecorder::start();
+
+    let funding_address = test_session.get_receive_address(0).address;
+    let funding_txid =
+        test_session.node.client.sendtoaddress(&funding_address, 1_234_567, None).unwrap();
+    test_session.wait_tx(vec![0], &funding_txid, Some(1_234_567), Some(TransactionType::Incoming));
+    let address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let parsed = address.parse::<gdk_common::bitcoin::Address<_>>().unwrap().assume_checked();
+    let tx = test_session.create_tx(&parsed, 76_543, 1_000);
+    let txid = test_session.session.broadcast_transaction(&tx).unwrap();
+    test_session.wait_tx(vec![0], &txid, Some(77_543), Some(TransactionType::Outgoing));
+
+    // The session logged the flow without the amounts and the addresses
+    let messages = recorder.messages();
+    assert!(!messages.is_empty());
+    for known in ["1234567", "76543", "77543", funding_address.as_str(), address.as_str()] {
+        assert!(!messages.iter().any(|msg| msg.contains(known)), "{} logged", known);
+    }
+
+    // Redaction stops with the session
+    test_session.stop();
+    drop(test_session);
+    assert!(!gdk_common::redact::privacy_mode());
+}
+
+#[test]
+fn test_redeposit() {
+    let mut test_session = TestSession::new(|_| ());
+    test_session.fund(1_000_000);
+
+    // Sending to an address of the wallet only costs the fee
+    let address = test_session.get_receive_address(0).address;
+    let parsed = address.parse::<gdk_common::bitcoin::Address<_>>().unwrap().assume_checked();
+    let tx = test_session.create_tx(&parsed, 10_000, 1_000);
+    let txid = test_session.session.broadcast_transaction(&tx).unwrap();
+    test_session.wait_tx(vec![0], &txid, Some(1_000), Some(TransactionType::Redeposit));
+
+    let item = test_session.get_tx_from_list(0, &txid);
+    assert_eq!(item.type_, TransactionType::Redeposit);
+    assert_eq!(item.satoshi.len(), 1);
+    assert_eq!(item.satoshi["btc"], -1_000);
+    assert_eq!(item.fee, 1_000);
+    assert_eq!(item.internal_destinations.len(), 2);
+    assert!(item.internal_destinations.contains(&address));
+
+    // Sending out of the wallet is not
+    let address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let address = address.parse::<gdk_common::bitcoin::Address<_>>().unwrap().assume_checked();
+    let tx = test_session.create_tx(&address, 10_000, 1_000);
+    let txid = test_session.session.broadcast_transaction(&tx).unwrap();
+    test_session.wait_tx(vec![0], &txid, Some(11_000), Some(TransactionType::Outgoing));
+    let item = test_session.get_tx_from_list(0, &txid);
+    assert_eq!(item.satoshi["btc"], -11_000);
+    assert!(item.internal_destinations.is_empty());
+
+    test_session.stop();
+}
+
+#[test]
+fn test_pending_broadcast_replay() {
+    let mut test_session = TestSession::new(|_| ());
+    test_session.fund(1_000_000);
+    let address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let address = address.parse::<gdk_common::bitcoin::Address<_>>().unwrap().assume_checked();
+    let tx = test_session.create_tx(&address, 10_000, 1_000);
+
+    // The server goes away, the transaction failing to be sent is queued
+    test_session.electrs.kill().unwrap();
+    let opt = SendTransactionOpt {
+        transaction: tx.clone(),
+        confirmation_token: None,
+        payjoin: None,
+        client_request_id: None,
+        check_acceptance: false,
+    };
+    let err = test_session.session.send_transaction(&opt).unwrap_err();
+    assert_eq!(err.to_gdk_code(), "id_connection_failed");
+    let pending = test_session.session.get_pending_broadcasts().unwrap().pending_broadcasts;
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].transaction, tx);
+    assert!(!pending[0].expired);
+    let txid = pending[0].txhash.clone();
+
+    let opt = CancelPendingBroadcastOpt {
+        txhash: "00".repeat(32),
+    };
+    assert!(test_session.session.cancel_pending_broadcast(&opt).is_err());
+
+    // Once the server is back, the transaction is broadcast and notified
+    test_session.session.disconnect().unwrap();
+    test_session.restart_electrs();
+    test_session.session.connect(&Value::Null).unwrap();
+    test_session.wait_tx(vec![0], &txid, Some(11_000), Some(TransactionType::Outgoing));
+    let txhash = txid.parse().unwrap();
+    assert!(test_session.node.client.get_mempool_entry(&txhash).is_ok());
+    assert!(test_session.session.get_pending_broadcasts().unwrap().pending_broadcasts.is_empty());
+
+    test_session.stop();
+}
+
+#[test]
+fn test_set_electrum_server() {
+    let mut test_session = TestSession::new(|_| ());
+    let other = test_session.spawn_electrs();
+    let server = |url: &str| serde_json::json!({"url": url, "tls": false});
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let sender = std::sync::Mutex::new(sender);
+    test_session.session.subscribe(&[NotificationKind::Network], move |ntf| {
+        if let Notification::Network {
+            server: Some(server),
+            ..
+        } = ntf
+        {
+            sender.lock().unwrap().send(server.clone()).unwrap();
+        }
+    });
+
+    // A failed switch leaves the session on its server
+    let current = test_session.electrs.electrum_url.clone();
+    assert!(test_session
+        .session
+        .handle_call("set_electrum_server", server("127.0.0.1:1"))
+        .is_err());
+    assert_eq!(test_session.session.url.url(), current);
+    test_session.fund(10_000);
+
+    // Mid-session the wallet is synced and notified by the new server
+    test_session.session.handle_call("set_electrum_server", server(&other.electrum_url)).unwrap();
+    assert_eq!(test_session.session.url.url(), other.electrum_url);
+    let switched = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(switched, other.electrum_url);
+    let _ = test_session.electrs.kill();
+    test_session.fund(20_000);
+
+    // The choice is kept for the next login
+    let session = test_session.new_session_same_wallet();
+    assert_eq!(session.url.url(), other.electrum_url);
+
+    test_session.stop();
+}
+
+#[test]
+fn test_transaction_policy() {
+    use gdk_electrum::policy::{PolicyDecision, PolicyTransaction};
+    use std::sync::Mutex;
+
+    let mut test_session = TestSession::new(|_| ());
+    test_session.fund(1_000_000);
+    let blocked = test_session.node.client.getnewaddress(None, None).unwrap();
+    let blocked = blocked.parse::<gdk_common::bitcoin::Address<_>>().unwrap().assume_checked();
+
+    let checked: Arc<Mutex<Option<PolicyTransaction>>> = Arc::default();
+    let blocked_address = blocked.to_string();
+    let last_checked = Arc::clone(&checked);
+    test_session.session.set_transaction_policy(Box::new(move |tx| {
+        *last_checked.lock().unwrap() = Some(tx.clone());
+        match tx.outputs.iter().any(|o| o.address.as_ref() == Some(&blocked_address)) {
+            true => PolicyDecision::Reject("blacklisted address".into()),
+            false => PolicyDecision::Allow,
+        }
+    }));
+
+    // Sending to the blocked address fails
+    let tx = test_session.create_tx(&blocked, 10_000, 1_000);
+    let err = test_session.session.broadcast_transaction(&tx).unwrap_err();
+    assert_eq!(err.to_gdk_code(), "id_transaction_rejected_by_policy");
+    assert!(err.to_string().contains("blacklisted address"));
+
+    // The policy sees the change and the fee
+    let policy_tx = checked.lock().unwrap().take().unwrap();
+    assert_eq!(policy_tx.outputs.len(), 2);
+    assert!(policy_tx.outputs.iter().any(|o| o.subaccount == Some(0)));
+    assert_eq!(policy_tx.fee, Some(1_000));
+
+    // Other addresses pass
+    let address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let address = address.parse::<gdk_common::bitcoin::Address<_>>().unwrap().assume_checked();
+    let tx = test_session.create_tx(&address, 10_000, 1_000);
+    let txid = test_session.session.broadcast_transaction(&tx).unwrap();
+    test_session.wait_tx(vec![0], &txid, Some(11_000), Some(TransactionType::Outgoing));
+    assert!(checked.lock().unwrap().is_some());
+
+    test_session.stop();
+}
+
+#[test]
+fn test_transaction_policy_confirmation() {
+    use gdk_electrum::policy::PolicyDecision;
+
+    let mut test_session = TestSession::new(|_| ());
+    test_session.fund(1_000_000);
+    test_session.session.set_transaction_policy(Box::new(|_| {
+        PolicyDecision::RequireConfirmation("every transaction".into())
+    }));
+
+    let address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let address = address.parse::<gdk_common::bitcoin::Address<_>>().unwrap().assume_checked();
+    let tx = test_session.create_tx(&address, 10_000, 1_000);
+    let err = test_session.session.broadcast_transaction(&tx).unwrap_err();
+    assert_eq!(err.to_gdk_code(), "id_confirmation_required");
+
+    // An approval lets the transaction through once
+    test_session.session.approve_transaction(&tx).unwrap();
+    let txid = test_session.session.broadcast_transaction(&tx).unwrap();
+    test_session.wait_tx(vec![0], &txid, Some(11_000), Some(TransactionType::Outgoing));
+    let err = test_session.session.broadcast_transaction(&tx).unwrap_err();
+    assert_eq!(err.to_gdk_code(), "id_confirmation_required");
+
+    test_session.stop();
+}
+
+#[test]
+fn test_balance_at_height() {
+    let mut test_session = TestSession::new(|_| ());
+    let timeout = Duration::from_secs(60);
+    let opt_at = |height| GetAtHeightOpt {
+        subaccount: 0,
+        height,
+        asset_id: None,
+        asset_precisions: Default::default(),
+    };
+
+    // Funded at height 102
+    let funding_txid = test_session.fund(1_000_000);
+    test_session.mine_block();
+    test_session.wait_for_tx(&funding_txid, 1, timeout);
+
+    // Spending it at height 103
+    let address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let address = address.parse::<gdk_common::bitcoin::Address<_>>().unwrap().assume_checked();
+    let tx = test_session.create_tx(&address, 10_000, 1_000);
+    let txid = test_session.session.broadcast_transaction(&tx).unwrap();
+    test_session.wait_tx(vec![0], &txid, Some(11_000), Some(TransactionType::Outgoing));
+    test_session.mine_block();
+    test_session.wait_for_tx(&txid, 1, timeout);
+
+    for (height, expected) in [(0, 0), (101, 0), (102, 1_000_000), (103, 989_000)] {
+        let balance = test_session.session.get_balance_at(&opt_at(height)).unwrap();
+        assert_eq!(balance["btc"], expected, "balance at height {}", height);
+    }
+
+    let utxos = test_session.session.get_unspent_outputs_at(&opt_at(101)).unwrap().0;
+    assert!(utxos.is_empty());
+    let utxos = test_session.session.get_unspent_outputs_at(&opt_at(102)).unwrap().0;
+    assert_eq!(utxos["btc"].len(), 1);
+    assert_eq!(utxos["btc"][0].txhash, funding_txid);
+    let utxos = test_session.session.get_unspent_outputs_at(&opt_at(103)).unwrap().0;
+    assert_eq!(utxos["btc"].len(), 1);
+    assert_eq!(utxos["btc"][0].txhash, txid);
+
+    // Unconfirmed transactions are not accounted
+    let tx = test_session.create_tx(&address, 10_000, 1_000);
+    let unconfirmed_txid = test_session.session.broadcast_transaction(&tx).unwrap();
+    test_session.wait_tx(vec![0], &unconfirmed_txid, Some(11_000), Some(TransactionType::Outgoing));
+    let balance = test_session.session.get_balance_at(&opt_at(103)).unwrap();
+    assert_eq!(balance["btc"], 989_000);
+
+    let err = test_session.session.get_balance_at(&opt_at(104)).unwrap_err();
+    assert!(matches!(err, gdk_electrum::error::Error::HeightAboveTip { .. }));
+
+    test_session.stop();
+}
+
+#[test]
+fn test_psbt() {
+    use gdk_common::bitcoin;
+
+    let mut test_session = TestSession::new(|_| ());
+    test_session.fund(100_000);
+
+    // Spend the wallet utxo together with a node utxo, the node gets its
+    // amount back and the wallet change pays the fee
+    let opt = GetUnspentOpt {
+        subaccount: 0,
+        num_confs: Some(0),
+        confidential_utxos_only: None,
+        all_coins: None,
+        asset_id: None,
+        trust_own_unconfirmed: None,
+        asset_precisions: Default::default(),
+        include_provenance: false,
+    };
+    let utxos = test_session.session.get_unspent_outputs(&opt).unwrap().0.remove("btc").unwrap();
+    assert_eq!(utxos.len(), 1);
+    let unspent: Value = test_session.node.client.call("listunspent", &[0.into()]).unwrap();
+    let node_utxo = &unspent.as_array().unwrap()[0];
+    let node_satoshi = (node_utxo["amount"].as_f64().unwrap() * 100_000_000.0).round() as u64;
+    let node_address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let node_address = node_address.parse::<bitcoin::Address<_>>().unwrap().assume_checked();
+    let change = test_session.get_receive_address(0).address;
+    let change = change.parse::<bitcoin::Address<_>>().unwrap().assume_checked();
+    let outpoint = |txid: &str, vout: u32| bitcoin::TxIn {
+        previous_output: bitcoin::OutPoint::new(txid.parse().unwrap(), vout),
+        ..Default::default()
+    };
+    let tx = bitcoin::Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: bitcoin::absolute::LockTime::ZERO,
+        input: vec![
+            outpoint(&utxos[0].txhash, utxos[0].pt_idx),
+            outpoint(
+                node_utxo["txid"].as_str().unwrap(),
+                node_utxo["vout"].as_u64().unwrap() as u32,
+            ),
+        ],
+        output: vec![
+            bitcoin::TxOut {
+                value: bitcoin::Amount::from_sat(node_satoshi),
+                script_pubkey: node_address.script_pubkey(),
+            },
+            bitcoin::TxOut {
+                value: bitcoin::Amount::from_sat(100_000 - 1_000),
+                script_pubkey: change.script_pubkey(),
+            },
+        ],
+    };
+
+    // Other elements of the create transaction result are ignored
+    let create_result = serde_json::json!({
+        "transaction": bitcoin::consensus::encode::serialize_hex(&tx),
+        "fee": 1_000,
+    });
+    let exported =
+        test_session.session.handle_call("psbt_from_create_transaction", create_result).unwrap();
+    let psbt = bitcoin::psbt::Psbt::deserialize(
+        &base64::decode(exported["psbt"].as_str().unwrap()).unwrap(),
+    )
+    .unwrap();
+    let (_, path) = psbt.inputs[0].bip32_derivation.values().next().unwrap();
+    let account_path: bitcoin::bip32::DerivationPath = "49'/1'/0'".parse().unwrap();
+    assert_eq!(path[..3], account_path[..]);
+    assert!(psbt.inputs[0].redeem_script.is_some());
+    assert!(psbt.inputs[0].witness_utxo.is_some());
+    assert!(psbt.inputs[1].bip32_derivation.is_empty());
+    assert!(psbt.outputs[0].bip32_derivation.is_empty());
+    assert_eq!(psbt.outputs[1].bip32_derivation.len(), 1);
+
+    let opt = SignPsbtOpt {
+        psbt: exported["psbt"].as_str().unwrap().to_string(),
+        subaccount: 0,
+        xprv: None,
+        inputs: None,
+        expert: false,
+    };
+    let signed = test_session.session.sign_psbt(&opt).unwrap();
+    assert_eq!(signed.signed_inputs, vec![0]);
+    assert_eq!(signed.complete_inputs, vec![true, false]);
+    assert!(!signed.is_complete);
+
+    // The node input is not signed yet
+    let opt = FinalizePsbtOpt {
+        psbt: signed.psbt.clone(),
+    };
+    assert!(test_session.session.finalize_and_extract(&opt).is_err());
+    let unsigned = bitcoin::consensus::encode::serialize_hex(&tx);
+    assert!(matches!(
+        test_session.session.broadcast_transaction(&unsigned),
+        Err(gdk_electrum::error::Error::UnsignedInput(0))
+    ));
+
+    let processed: Value = test_session
+        .node
+        .client
+        .call(
+            "walletprocesspsbt",
+            &[signed.psbt.into(), true.into(), "ALL".into(), true.into(), false.into()],
+        )
+        .unwrap();
+    let opt = FinalizePsbtOpt {
+        psbt: processed["psbt"].as_str().unwrap().to_string(),
+    };
+    let finalized = test_session.session.finalize_and_extract(&opt).unwrap();
+    let txid = test_session.session.broadcast_transaction(&finalized.transaction).unwrap();
+    assert_eq!(txid, finalized.txhash);
+    for i in 0.. {
+        assert!(i < 60, "timeout waiting for tx {}", txid);
+        if test_session.get_tx_list(0).iter().any(|e| e.txhash == txid) {
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    test_session.stop();
+}
+
+#[test]
+fn test_psbt_cosign() {
+    use gdk_common::bitcoin;
+
+    // Two wallets on the same chain, each with a utxo
+    let mut test_session1 = TestSession::new(|_| ());
+    let mut test_session2 = TestSession::new(|_| ());
+    test_session2.node_connect(test_session1.p2p_port);
+    test_session1.node_generate(20);
+    test_session1.wait_blockheight(121);
+    test_session2.wait_blockheight(121);
+    test_session1.fund(100_000);
+    let address2 = test_session2.get_receive_address(0).address;
+    let funding_tx = test_session1.node.client.sendtoaddress(&address2, 50_000, None).unwrap();
+    test_session1.node_generate(1);
+    test_session2.wait_tx(vec![0], &funding_tx, Some(50_000), Some(TransactionType::Incoming));
+
+    // Wallet 1 proposes a transaction spending a utxo of each wallet, wallet 2
+    // gets 10_000 more than it spends and wallet 1 pays the fee
+    let utxo = |test_session: &TestSession| {
+        let opt = GetUnspentOpt {
+            subaccount: 0,
+            num_confs: Some(0),
+            confidential_utxos_only: None,
+            all_coins: None,
+            asset_id: None,
+            trust_own_unconfirmed: None,
+            asset_precisions: Default::default(),
+            include_provenance: false,
+        };
+        let mut utxos = test_session.session.get_unspent_outputs(&opt).unwrap().0;
+        let utxo = utxos.remove("btc").unwrap().remove(0);
+        bitcoin::TxIn {
+            previous_output: bitcoin::OutPoint::new(utxo.txhash.parse().unwrap(), utxo.pt_idx),
+            ..Default::default()
+        }
+    };
+    let output = |test_session: &TestSession, satoshi: u64| {
+        let address = test_session.get_receive_address(0).address;
+        let address = address.parse::<bitcoin::Address<_>>().unwrap().assume_checked();
+        bitcoin::TxOut {
+            value: bitcoin::Amount::from_sat(satoshi),
+            script_pubkey: address.script_pubkey(),
+        }
+    };
+    let tx = bitcoin::Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: bitcoin::absolute::LockTime::ZERO,
+        input: vec![utxo(&test_session1), utxo(&test_session2)],
+        output: vec![output(&test_session1, 89_000), output(&test_session2, 60_000)],
+    };
+    let opt = PsbtFromTransactionOpt {
+        transaction: bitcoin::consensus::encode::serialize_hex(&tx),
+        transaction_inputs: vec![],
+    };
+    let psbt = test_session1.session.psbt_from_create_transaction(&opt).unwrap().psbt;
+
+    let opt = PsbtGetDetailsOpt {
+        psbt: psbt.clone(),
+        max_fee_rate: None,
+    };
+    let details = test_session2.session.psbt_get_details(&opt).unwrap();
+    assert_eq!(details.txhash, tx.compute_txid().to_string());
+    assert_eq!(details.inputs[0].subaccount, None);
+    assert_eq!(details.inputs[0].satoshi, Some(100_000));
+    assert_eq!(details.inputs[1].subaccount, Some(0));
+    assert_eq!(details.inputs[1].satoshi, Some(50_000));
+    assert_eq!(details.outputs[0].subaccount, None);
+    assert_eq!(details.outputs[1].subaccount, Some(0));
+    assert_eq!(details.satoshi.get("btc"), Some(&10_000));
+    assert_eq!(details.subaccounts[&0].get("btc"), Some(&10_000));
+    assert_eq!(details.fee, Some(1_000));
+    assert!(details.fee_rate.unwrap() > 1_000);
+    assert!(details.warnings.is_empty());
+
+    let opt = PsbtGetDetailsOpt {
+        psbt: psbt.clone(),
+        max_fee_rate: Some(1_000),
+    };
+    let details = test_session2.session.psbt_get_details(&opt).unwrap();
+    assert!(matches!(details.warnings[..], [PsbtWarning::HighFeeRate { .. }]));
+
+    // An output of wallet 1 claiming a derivation path of wallet 2
+    let opt = PsbtFromTransactionOpt {
+        transaction: bitcoin::consensus::encode::serialize_hex(&tx),
+        transaction_inputs: vec![],
+    };
+    let psbt2 = test_session2.session.psbt_from_create_transaction(&opt).unwrap().psbt;
+    let psbt2 = bitcoin::psbt::Psbt::deserialize(&base64::decode(psbt2).unwrap()).unwrap();
+    let mut forged = bitcoin::psbt::Psbt::deserialize(&base64::decode(&psbt).unwrap()).unwrap();
+    forged.outputs[0].bip32_derivation = psbt2.outputs[1].bip32_derivation.clone();
+    let opt = PsbtGetDetailsOpt {
+        psbt: base64::encode(forged.serialize()),
+        max_fee_rate: None,
+    };
+    let details = test_session2.session.psbt_get_details(&opt).unwrap();
+    assert_eq!(
+        details.warnings,
+        vec![PsbtWarning::UnknownChangePath {
+            output: 0
+        }]
+    );
+
+    // Wallet 2 signs only the transaction it acknowledged
+    let mut opt = PsbtSignOpt {
+        psbt,
+        txhash: funding_tx,
+        subaccount: 0,
+        xprv: Some(test_session2.subaccount_xprv(0)),
+    };
+    assert!(test_session2.session.psbt_sign(&opt).is_err());
+    opt.txhash = tx.compute_txid().to_string();
+    let signed = test_session2.session.psbt_sign(&opt).unwrap();
+    assert_eq!(signed.signed_inputs, vec![1]);
+
+    let opt = SignPsbtOpt {
+        psbt: signed.psbt,
+        subaccount: 0,
+        xprv: None,
+        inputs: None,
+        expert: false,
+    };
+    let signed = test_session1.session.sign_psbt(&opt).unwrap();
+    assert_eq!(signed.signed_inputs, vec![0]);
+    let opt = FinalizePsbtOpt {
+        psbt: signed.psbt,
+    };
+    let finalized = test_session1.session.finalize_and_extract(&opt).unwrap();
+    let txid = test_session1.session.broadcast_transaction(&finalized.transaction).unwrap();
+    for i in 0.. {
+        assert!(i < 60, "timeout waiting for tx {}", txid);
+        if test_session2.get_tx_list(0).iter().any(|e| e.txhash == txid) {
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    test_session1.stop();
+    test_session2.stop();
+}
+
+#[test]
+fn test_multisig_subaccount() {
+    use gdk_common::bitcoin;
+
+    // Two wallets on the same chain sharing their keys for a 2-of-2
+    let mut test_session1 = TestSession::new(|_| ());
+    let mut test_session2 = TestSession::new(|_| ());
+    test_session2.node_connect(test_session1.p2p_port);
+    test_session1.node_generate(20);
+    test_session1.wait_blockheight(121);
+    test_session2.wait_blockheight(121);
+    let key1 = test_session1.session.get_multisig_key().unwrap();
+    let key2 = test_session2.session.get_multisig_key().unwrap();
+    assert_eq!(key1.subaccount, 4);
+    assert_eq!(key2.subaccount, 4);
+
+    // A descriptor without a key of the wallet is rejected
+    let opt = CreateMultisigAccountOpt {
+        name: "".into(),
+        descriptor: format!("wsh(sortedmulti(1,{}))", key2.key),
+    };
+    let err = test_session1.session.create_multisig_subaccount(opt).unwrap_err();
+    assert!(matches!(err, gdk_electrum::error::Error::MultisigKeyNotFound));
+
+    let descriptor = format!("wsh(sortedmulti(2,{},{}/<0;1>/*))", key1.key, key2.key);
+    let opt = CreateMultisigAccountOpt {
+        name: "2of2".into(),
+        descriptor: descriptor.clone(),
+    };
+    let info1 = test_session1.session.create_multisig_subaccount(opt.clone()).unwrap();
+    let info2 = test_session2.session.create_multisig_subaccount(opt).unwrap();
+    assert_eq!(info1.account_num, 4);
+    assert_eq!(info1.script_type.to_string(), "p2wsh");
+    assert_eq!(info1.settings.name, "2of2");
+    assert_eq!(info1.core_descriptors, info2.core_descriptors);
+
+    // Both wallets derive the same addresses and see the funds
+    let address = test_session1.get_receive_address(4);
+    assert_eq!(address.address, test_session2.get_receive_address(4).address);
+    assert_eq!(address.address_type, "p2wsh");
+    let txid = test_session1.node.client.sendtoaddress(&address.address, 50_000, None).unwrap();
+    test_session1.node_generate(1);
+    test_session1.wait_tx(vec![4], &txid, Some(50_000), Some(TransactionType::Incoming));
+    test_session2.wait_tx(vec![4], &txid, Some(50_000), Some(TransactionType::Incoming));
+
+    // Wallet 1 spends the multisig utxo to the node
+    let opt = GetUnspentOpt {
+        subaccount: 4,
+        num_confs: Some(0),
+        confidential_utxos_only: None,
+        all_coins: None,
+        asset_id: None,
+        trust_own_unconfirmed: None,
+        asset_precisions: Default::default(),
+        include_provenance: false,
+    };
+    let utxos = test_session1.session.get_unspent_outputs(&opt).unwrap().0.remove("btc").unwrap();
+    assert_eq!(utxos.len(), 1);
+    let node_address = test_session1.node.client.getnewaddress(None, None).unwrap();
+    let node_address = node_address.parse::<bitcoin::Address<_>>().unwrap().assume_checked();
+    let tx = bitcoin::Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: bitcoin::absolute::LockTime::ZERO,
+        input: vec![bitcoin::TxIn {
+            previous_output: bitcoin::OutPoint::new(
+                utxos[0].txhash.parse().unwrap(),
+                utxos[0].pt_idx,
+            ),
+            ..Default::default()
+        }],
+        output: vec![bitcoin::TxOut {
+            value: bitcoin::Amount::from_sat(49_000),
+            script_pubkey: node_address.script_pubkey(),
+        }],
+    };
+    let opt = PsbtFromTransactionOpt {
+        transaction: bitcoin::consensus::encode::serialize_hex(&tx),
+        transaction_inputs: vec![],
+    };
+    let psbt = test_session1.session.psbt_from_create_transaction(&opt).unwrap().psbt;
+    let parsed = bitcoin::psbt::Psbt::deserialize(&base64::decode(&psbt).unwrap()).unwrap();
+    assert!(parsed.inputs[0].witness_script.is_some());
+    assert_eq!(parsed.inputs[0].bip32_derivation.len(), 2);
+
+    // Its signature alone leaves the transaction incomplete
+    let opt = SignPsbtOpt {
+        psbt,
+        subaccount: 4,
+        xprv: None,
+        inputs: None,
+        expert: false,
+    };
+    let signed = test_session1.session.sign_psbt(&opt).unwrap();
+    assert_eq!(signed.signed_inputs, vec![0]);
+    assert_eq!(signed.complete_inputs, vec![false]);
+    assert!(!signed.is_complete);
+    let opt = FinalizePsbtOpt {
+        psbt: signed.psbt.clone(),
+    };
+    assert!(test_session1.session.finalize_and_extract(&opt).is_err());
+
+    // Wallet 2 co-signs, completing it
+    let opt = SignPsbtOpt {
+        psbt: signed.psbt,
+        subaccount: 4,
+        xprv: None,
+        inputs: None,
+        expert: false,
+    };
+    let signed = test_session2.session.sign_psbt(&opt).unwrap();
+    assert_eq!(signed.signed_inputs, vec![0]);
+    assert!(signed.is_complete);
+    let opt = FinalizePsbtOpt {
+        psbt: signed.psbt,
+    };
+    let finalized = test_session2.session.finalize_and_extract(&opt).unwrap();
+    let txid = test_session2.session.broadcast_transaction(&finalized.transaction).unwrap();
+    for i in 0.. {
+        assert!(i < 60, "timeout waiting for tx {}", txid);
+        if test_session1.get_tx_list(4).iter().any(|e| e.txhash == txid) {
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    // Logging in again restores the subaccount from its descriptor
+    test_session1.session.disconnect().unwrap();
+    let mut session = test_session1.new_session_same_wallet();
+    session.restore_with_signer(test_session1.software_signer(), None).unwrap();
+    let subaccounts = session.get_subaccounts().unwrap();
+    let restored = subaccounts.iter().find(|info| info.account_num == 4).unwrap();
+    assert_eq!(restored.core_descriptors, info1.core_descriptors);
+    assert_eq!(restored.settings.multisig_descriptor, Some(descriptor));
+
+    test_session1.stop();
+    test_session2.stop();
+}
+
+#[test]
+fn test_green_multisig_subaccount() {
+    use gdk_common::bitcoin;
+    use gdk_common::bitcoin::bip32::{DerivationPath, Xpriv, Xpub};
+    use gdk_common::bitcoin::opcodes::all::*;
+    use gdk_common::bitcoin::script::Builder;
+    use gdk_common::EC;
+
+    let mut test_session = TestSession::new(|_| ());
+    let xpub = |seed| {
+        let master = Xpriv::new_master(bitcoin::NetworkKind::Test, &[seed; 32]).unwrap();
+        Xpub::from_priv(&EC, &master)
+    };
+    let (service, user) = (xpub(1), xpub(2));
+    let key = |xpub: &Xpub, path: &str| {
+        let path: DerivationPath = path.parse().unwrap();
+        bitcoin::PublicKey::new(xpub.derive_pub(&EC, &path).unwrap().public_key)
+    };
+
+    let opt = CreateGreenMultisigAccountOpt {
+        name: "green".into(),
+        template: GreenMultisigTemplate {
+            service_xpub: service,
+            user_xpub: user,
+            recovery_xpub: None,
+            csv_blocks: Some(144),
+        },
+    };
+    let info = test_session.session.create_green_multisig_subaccount(opt).unwrap();
+    assert_eq!(info.account_num, 5);
+    assert_eq!(info.script_type.to_string(), "green-multisig");
+    assert_eq!(info.settings.name, "green");
+
+    // The 2of2 and the CSV addresses of Green at pointer 1
+    let multisig = Builder::new()
+        .push_int(2)
+        .push_key(&key(&service, "m/1"))
+        .push_key(&key(&user, "m/1/1"))
+        .push_int(2)
+        .push_opcode(OP_CHECKMULTISIG)
+        .into_script();
+    let csv = Builder::new()
+        .push_key(&key(&user, "m/1/1"))
+        .push_opcode(OP_CHECKSIGVERIFY)
+        .push_key(&key(&service, "m/1"))
+        .push_opcode(OP_CHECKSIG)
+        .push_opcode(OP_IFDUP)
+        .push_opcode(OP_NOTIF)
+        .push_int(144)
+        .push_opcode(OP_CSV)
+        .push_opcode(OP_ENDIF)
+        .into_script();
+    let mut total = 0;
+    for (script, satoshi) in [(multisig, 30_000), (csv, 20_000)] {
+        let address = bitcoin::Address::p2shwsh(&script, bitcoin::Network::Regtest);
+        let txid =
+            test_session.node.client.sendtoaddress(&address.to_string(), satoshi, None).unwrap();
+        test_session.wait_tx(vec![5], &txid, Some(satoshi), Some(TransactionType::Incoming));
+        total += satoshi;
+    }
+    let opt = GetBalanceOpt {
+        subaccount: 5,
+        num_confs: Some(0),
+        ..Default::default()
+    };
+    assert_eq!(test_session.session.get_balance(&opt).unwrap()["btc"], total as i64);
+    assert_eq!(test_session.get_tx_list(5).len(), 2);
+
+    // Nothing can be signed
+    let opt = SignMessageOpt {
+        subaccount: 5,
+        address: None,
+        path: Some(vec![0.into(), 1.into()]),
+        message: "message".into(),
+        format: None,
+        xprv: None,
+    };
+    let err = test_session.session.sign_message(&opt).unwrap_err();
+    assert_eq!(err.to_gdk_code(), "id_watch_only");
+
+    // The watch-only access exports the keys of the subaccount
+    let credentials = serde_json::json!({"username": "green", "password": "password"});
+    test_session.session.handle_call("set_watch_only_credentials", credentials.clone()).unwrap();
+
+    // Logging in again restores the subaccount from its keys
+    test_session.session.disconnect().unwrap();
+    let mut session = test_session.new_session_same_wallet();
+    session.restore_with_signer(test_session.software_signer(), None).unwrap();
+    let subaccounts = session.get_subaccounts().unwrap();
+    let restored = subaccounts.iter().find(|info| info.account_num == 5).unwrap();
+    assert_eq!(restored.core_descriptors, info.core_descriptors);
+
+    let mut session = ElectrumSession::new(test_session.network.clone()).unwrap();
+    let login_data = session.handle_call("login_watch_only", credentials).unwrap();
+    let xpubs = login_data["xpubs"].as_array().unwrap();
+    assert!(xpubs.iter().any(|x| x["pointer"] == 5 && x["xpub"] == user.to_string()));
+    let subaccounts = session.get_subaccounts().unwrap();
+    let watched = subaccounts.iter().find(|info| info.account_num == 5).unwrap();
+    assert_eq!(watched.core_descriptors, info.core_descriptors);
+
+    test_session.stop();
+}
+
+#[test]
+fn test_recovery_transactions() {
+    use gdk_common::bitcoin;
+    use gdk_common::bitcoin::bip32::{Xpriv, Xpub};
+    use gdk_common::EC;
+
+    let mut test_session = TestSession::new(|_| ());
+    let xprv = |seed| Xpriv::new_master(bitcoin::NetworkKind::Test, &[seed; 32]).unwrap();
+    let user_xprv = xprv(2);
+    let opt = CreateGreenMultisigAccountOpt {
+        name: "".into(),
+        template: GreenMultisigTemplate {
+            service_xpub: Xpub::from_priv(&EC, &xprv(1)),
+            user_xpub: Xpub::from_priv(&EC, &user_xprv),
+            recovery_xpub: None,
+            csv_blocks: Some(10),
+        },
+    };
+    test_session.session.create_green_multisig_subaccount(opt).unwrap();
+
+    // Fund a CSV address, on the internal chain, and a 2of2 one
+    let subaccounts = test_session.session.get_subaccounts().unwrap();
+    let info = subaccounts.iter().find(|info| info.account_num == 5).unwrap();
+    let csv: gdk_common::miniscript::Descriptor<gdk_common::miniscript::DescriptorPublicKey> =
+        info.core_descriptors[1].parse().unwrap();
+    let csv_address = csv.at_derivation_index(1).unwrap().address(bitcoin::Network::Regtest);
+    let csv_txid = test_session
+        .node
+        .client
+        .sendtoaddress(&csv_address.unwrap().to_string(), 50_000, None)
+        .unwrap();
+    let multisig_address = test_session.get_receive_address(5).address;
+    test_session.node.client.sendtoaddress(&multisig_address, 20_000, None).unwrap();
+    test_session.node_generate(1);
+    test_session.wait_tx(vec![5], &csv_txid, Some(50_000), Some(TransactionType::Incoming));
+    let funded_height = test_session.node.client.get_block_count().unwrap() as u32;
+    test_session.wait_blockheight(funded_height);
+    let opt = GetBalanceOpt {
+        subaccount: 5,
+        num_confs: Some(1),
+        ..Default::default()
+    };
+    for i in 0.. {
+        assert!(i < 60, "timeout waiting for the utxos");
+        if test_session.session.get_balance(&opt).unwrap()["btc"] == 70_000 {
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    // Only the CSV utxo has a recovery transaction, not yet expired
+    let node_address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let opt = CreateRecoveryTransactionsOpt {
+        subaccount: 5,
+        address: node_address,
+        xprv: user_xprv,
+        fee_rate: Some(1000),
+    };
+    let recovery = test_session.session.create_recovery_transactions(&opt).unwrap();
+    assert_eq!(recovery.transactions.len(), 1);
+    let tx = &recovery.transactions[0];
+    assert_eq!(tx.txhash, csv_txid);
+    assert_eq!(tx.satoshi, 50_000);
+    assert_eq!(tx.expiry_height, Some(funded_height + 10));
+    assert!(!tx.is_expired);
+    assert!(test_session.session.broadcast_transaction(&tx.transaction).is_err());
+
+    // It can be broadcast once the CSV blocks have passed
+    test_session.node_generate(9);
+    test_session.wait_blockheight(funded_height + 9);
+    let recovery = test_session.session.create_recovery_transactions(&opt).unwrap();
+    let tx = &recovery.transactions[0];
+    assert!(tx.is_expired);
+    let txid = test_session.session.broadcast_transaction(&tx.transaction).unwrap();
+    for i in 0.. {
+        assert!(i < 60, "timeout waiting for tx {}", txid);
+        if test_session.get_tx_list(5).iter().any(|e| e.txhash == txid) {
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    // A key other than the user one can't sign them
+    let opt = CreateRecoveryTransactionsOpt {
+        xprv: xprv(1),
+        ..opt
+    };
+    assert!(test_session.session.create_recovery_transactions(&opt).is_err());
+
+    test_session.stop();
+}
+
+#[test]
+fn test_payjoin() {
+    use electrsd::bitcoind::bitcoincore_rpc::Client;
+    use gdk_common::bitcoin;
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    /// A minimal payjoin receiver for a single request: adds `node_utxo` to
+    /// the `payee` output and takes `fee` from the change, plus `stolen` it
+    /// moves to the payee output
+    fn serve(
+        listener: &TcpListener,
+        node: &Client,
+        payee: &bitcoin::Script,
+        node_utxo: &(bitcoin::OutPoint, bitcoin::TxOut),
+        fee: u64,
+        stolen: u64,
+    ) {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+        assert!(request_line.starts_with("POST /pj?v=1&disableoutputsubstitution=true"));
+        let change_index = request_line
+            .split(|c| c == '&' || c == ' ')
+            .find_map(|param| param.strip_prefix("additionalfeeoutputindex="))
+            .unwrap()
+            .parse::<usize>()
+            .unwrap();
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            if header.trim().is_empty() {
+                break;
+            }
+            let (name, value) = header.split_once(':').unwrap();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+
+        let original = bitcoin::psbt::Psbt::deserialize(&base64::decode(&body).unwrap()).unwrap();
+        let mut proposal = original.clone();
+        for input in proposal.inputs.iter_mut() {
+            input.final_script_sig = None;
+            input.final_script_witness = None;
+        }
+        let (outpoint, txout) = node_utxo.clone();
+        proposal.unsigned_tx.input.push(bitcoin::TxIn {
+            previous_output: outpoint,
+            sequence: original.unsigned_tx.input[0].sequence,
+            ..Default::default()
+        });
+        proposal.inputs.push(bitcoin::psbt::Input {
+            witness_utxo: Some(txout.clone()),
+            ..Default::default()
+        });
+        let outputs = &mut proposal.unsigned_tx.output;
+        let payee = outputs.iter_mut().find(|o| *o.script_pubkey == *payee).unwrap();
+        payee.value += txout.value + bitcoin::Amount::from_sat(stolen);
+        outputs[change_index].value -= bitcoin::Amount::from_sat(fee + stolen);
+        let processed: Value = node
+            .call(
+                "walletprocesspsbt",
+                &[
+                    base64::encode(proposal.serialize()).into(),
+                    true.into(),
+                    "ALL".into(),
+                    true.into(),
+                    true.into(),
+                ],
+            )
+            .unwrap();
+        let response = processed["psbt"].as_str().unwrap();
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\
+             Connection: close\r\n\r\n{}",
+            response.len(),
+            response
+        )
+        .unwrap();
+    }
+
+    let mut test_session = TestSession::new(|_| ());
+
+    // The receiver contributes inputs of the sender script type, p2sh-p2wpkh for subaccount 0
+    let mut node_utxos = vec![];
+    for _ in 0..2 {
+        let address = test_session.node.client.getnewaddress(None, Some("p2sh-segwit")).unwrap();
+        let txid = test_session.node.client.sendtoaddress(&address, 200_000, None).unwrap();
+        let txid = txid.parse().unwrap();
+        let tx = test_session.node.client.get_raw_transaction(&txid, None).unwrap();
+        let address = address.parse::<bitcoin::Address<_>>().unwrap().assume_checked();
+        let vout =
+            tx.output.iter().position(|o| o.script_pubkey == address.script_pubkey()).unwrap();
+        node_utxos.push((bitcoin::OutPoint::new(txid, vout as u32), tx.output[vout].clone()));
+    }
+    test_session.node_generate(1);
+    test_session.fund(100_000);
+
+    let payee = test_session.node.client.getnewaddress(None, None).unwrap();
+    let payee = payee.parse::<bitcoin::Address<_>>().unwrap().assume_checked();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let payjoin = PayjoinOpt {
+        uri: format!(
+            "bitcoin:{}?amount=0.0006&pj=http://127.0.0.1:{}/pj",
+            payee,
+            listener.local_addr().unwrap().port()
+        ),
+        subaccount: 0,
+        xprv: Some(test_session.subaccount_xprv(0)),
+        max_additional_fee: Some(500),
+    };
+    let txid = |tx: &str| {
+        let tx: bitcoin::Transaction = bitcoin::consensus::encode::deserialize_hex(tx).unwrap();
+        tx.compute_txid().to_string()
+    };
+    let wait_tx = |test_session: &TestSession, txid: &str| {
+        for i in 0.. {
+            assert!(i < 60, "timeout waiting for tx {}", txid);
+            if test_session.get_tx_list(0).iter().any(|e| e.txhash == txid) {
+                break;
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+    };
+
+    // The receiver adds an input and the wallet pays a bit more fee
+    let opt = SendTransactionOpt {
+        transaction: test_session.create_tx(&payee, 60_000, 1_000),
+        confirmation_token: None,
+        payjoin: Some(payjoin.clone()),
+        client_request_id: None,
+        check_acceptance: false,
+    };
+    let (session, node) = (&mut test_session.session, &test_session.node.client);
+    let result = thread::scope(|s| {
+        s.spawn(|| serve(&listener, node, &payee.script_pubkey(), &node_utxos[0], 300, 0));
+        session.send_transaction(&opt).unwrap()
+    });
+    assert!(result.payjoin, "{:?}", result.payjoin_error);
+    assert_ne!(result.txhash, txid(&opt.transaction));
+    wait_tx(&test_session, &result.txhash);
+    let txhash = result.txhash.parse().unwrap();
+    let sent = test_session.node.client.get_raw_transaction(&txhash, None).unwrap();
+    assert_eq!(sent.input.len(), 2);
+    assert_eq!(sent.input[1].previous_output, node_utxos[0].0);
+
+    // A receiver taking part of the change for itself is ignored
+    let opt = SendTransactionOpt {
+        transaction: test_session.create_tx(&payee, 60_000, 1_000),
+        confirmation_token: None,
+        payjoin: Some(payjoin.clone()),
+        client_request_id: None,
+        check_acceptance: false,
+    };
+    let (session, node) = (&mut test_session.session, &test_session.node.client);
+    let result = thread::scope(|s| {
+        s.spawn(|| serve(&listener, node, &payee.script_pubkey(), &node_utxos[1], 100, 100));
+        session.send_transaction(&opt).unwrap()
+    });
+    assert!(!result.payjoin);
+    assert_eq!(result.txhash, txid(&opt.transaction));
+    assert_eq!(result.payjoin_error.as_deref(), Some("invalid payjoin proposal: fee decreased"));
+    wait_tx(&test_session, &result.txhash);
+
+    // Without a payjoin endpoint the transaction is sent as is
+    let opt = SendTransactionOpt {
+        transaction: test_session.create_tx(&payee, 10_000, 1_000),
+        confirmation_token: None,
+        payjoin: Some(PayjoinOpt {
+            uri: format!("bitcoin:{}", payee),
+            ..payjoin
+        }),
+        client_request_id: None,
+        check_acceptance: false,
+    };
+    let result = test_session.session.send_transaction(&opt).unwrap();
+    assert!(!result.payjoin);
+    assert_eq!(result.payjoin_error, None);
+    assert_eq!(result.txhash, txid(&opt.transaction));
+}
+
+#[test]
+fn test_custom_network() {
+    use gdk_common::bitcoin;
+
+    let regtest_genesis =
+        bitcoin::blockdata::constants::genesis_block(bitcoin::Network::Regtest).block_hash();
+    let custom = |network: &mut NetworkParameters, genesis_hash: String| {
+        let mut value = serde_json::to_value(&*network).unwrap();
+        value["network"] = "custom-regtest".into();
+        value["bech32_prefix"] = "bcrt".into();
+        value["p2pkh_version"] = 111.into();
+        value["p2sh_version"] = 196.into();
+        value["genesis_hash"] = genesis_hash.into();
+        *network = serde_json::from_value(value).unwrap();
+    };
+
+    // A registered regtest with its own state directory
+    let mut test_session = TestSession::new(|network| custom(network, regtest_genesis.to_string()));
+    test_session.fund(100_000);
+    let address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let address = address.parse::<bitcoin::Address<_>>().unwrap().assume_checked();
+    let tx = test_session.create_tx(&address, 10_000, 1_000);
+    let txid = test_session.session.broadcast_transaction(&tx).unwrap();
+    for i in 0.. {
+        assert!(i < 60, "timeout waiting for tx {}", txid);
+        if test_session.get_tx_list(0).iter().any(|e| e.txhash == txid) {
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    // The genesis of the registered network is checked against the server
+    let mut network = test_session.network.clone();
+    custom(&mut network, "00".repeat(32));
+    let mut session = ElectrumSession::new(network.clone()).unwrap();
+    let err: Value =
+        session.handle_call("connect", serde_json::to_value(network).unwrap()).unwrap_err().into();
+    assert_eq!(err["code"], "server_genesis_mismatch");
+
+    // Inconsistent parameters are refused
+    let mut value = serde_json::to_value(&test_session.network).unwrap();
+    value["bech32_prefix"] = "tb".into();
+    let network: NetworkParameters = serde_json::from_value(value).unwrap();
+    assert!(ElectrumSession::new(network).is_err());
+
+    test_session.stop();
+}
+
+#[test]
+fn test_signet() {
+    use gdk_common::bitcoin;
+
+    let mut test_session = TestSession::new(|network| {
+        let mut value = serde_json::to_value(&*network).unwrap();
+        value["signet"] = true.into();
+        value["bech32_prefix"] = "tb".into();
+        *network = serde_json::from_value(value).unwrap();
+    });
+    assert_eq!(test_session.network.id(), gdk_common::NetworkId::Bitcoin(bitcoin::Network::Signet));
+
+    let ap = test_session.get_receive_address(0);
+    assert!(ap.address.starts_with('2'));
+    let txid = test_session.fund(100_000);
+    let address = test_session.node.client.getnewaddress(None, Some("bech32")).unwrap();
+    assert!(address.starts_with("tb1"));
+    let address = address.parse::<bitcoin::Address<_>>().unwrap().assume_checked();
+    let tx = test_session.create_tx(&address, 10_000, 1_000);
+    let sent_txid = test_session.session.broadcast_transaction(&tx).unwrap();
+    for i in 0.. {
+        assert!(i < 60, "timeout waiting for tx {}", sent_txid);
+        if test_session.get_tx_list(0).iter().any(|e| e.txhash == sent_txid) {
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    // The signet headers are validated and the transactions verified against them
+    test_session.mine_block();
+    test_session.wait_tx_spv_change(&txid, "verified");
+    test_session.spv_verify_tx(&sent_txid, 102, Some(100));
+
+    test_session.stop();
+}
+
+#[test]
+fn signer_process() {
+    gdk_test::serve_signer_process();
+}
+
+#[test]
+fn test_process_signer() {
+    use gdk_common::bitcoin;
+
+    // The keys are only held by the child process, the session asks it for
+    // the xpubs at login and for every signature
+    let mut test_session = TestSession::new_with_process_signer(|_| ());
+    let balance = |test_session: &TestSession, subaccount| {
+        let opt = GetBalanceOpt {
+            subaccount,
+            num_confs: Some(0),
+            ..Default::default()
+        };
+        test_session.session.get_balance(&opt).unwrap()["btc"]
+    };
+    let node_address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let node_address = node_address.parse::<bitcoin::Address<_>>().unwrap().assume_checked();
+
+    // Receive and confirm
+    let txid = test_session.fund(100_000);
+    test_session.mine_block();
+    test_session.wait_for_tx(&txid, 1, Duration::from_secs(60));
+    assert_eq!(balance(&test_session, 0), 100_000);
+
+    // Send with change, signed by the signer process
+    let tx = test_session.create_tx(&node_address, 10_000, 1_000);
+    let txid = test_session.session.broadcast_transaction(&tx).unwrap();
+    test_session.wait_tx(vec![0], &txid, Some(11_000), Some(TransactionType::Outgoing));
+    assert_eq!(balance(&test_session, 0), 89_000);
+
+    // Send to an address of the wallet
+    let address = test_session.get_receive_address(0).address;
+    let address = address.parse::<bitcoin::Address<_>>().unwrap().assume_checked();
+    let tx = test_session.create_tx(&address, 20_000, 1_000);
+    let txid = test_session.session.broadcast_transaction(&tx).unwrap();
+    test_session.wait_tx(vec![0], &txid, Some(1_000), Some(TransactionType::Redeposit));
+    test_session.mine_block();
+    test_session.wait_for_tx(&txid, 1, Duration::from_secs(60));
+    assert_eq!(balance(&test_session, 0), 88_000);
+
+    // Receive on another subaccount
+    test_session.create_subaccount(1);
+    let address = test_session.get_receive_address(1).address;
+    let txid = test_session.node.client.sendtoaddress(&address, 50_000, None).unwrap();
+    test_session.wait_tx(vec![1], &txid, Some(50_000), Some(TransactionType::Incoming));
+    assert_eq!(balance(&test_session, 1), 50_000);
+
+    // Send all
+    let txid = test_session.send_all(&node_address, 1_000);
+    test_session.wait_tx(vec![0], &txid, Some(88_000), Some(TransactionType::Outgoing));
+    test_session.mine_block();
+    test_session.wait_for_tx(&txid, 1, Duration::from_secs(60));
+    assert_eq!(balance(&test_session, 0), 0);
+
+    let address = test_session.get_receive_address(0).address;
+    let message = "Hello world";
+    let opt = SignMessageOpt {
+        subaccount: 0,
+        address: Some(address.clone()),
+        path: None,
+        message: message.to_string(),
+        format: None,
+        xprv: None,
+    };
+    let signed = test_session.session.sign_message(&opt).unwrap();
+    let valid: bool = test_session
+        .node
+        .client
+        .call("verifymessage", &[address.into(), signed.signature.into(), message.into()])
+        .unwrap();
+    assert!(valid);
+
+    test_session.stop();
+}
+
+#[test]
+fn test_fast_login() {
+    let mut test_session = TestSession::new(|_| ());
+    test_session.fund(100_000);
+    let balance = test_session.session.get_balance(&GetBalanceOpt::default()).unwrap();
+    assert!(!test_session.session.get_sync_status().syncing);
+    test_session.stop();
+
+    // The identifiers are computed without a session
+    let mut network = test_session.network.clone();
+    let opt = GetWalletIdentifierOpt {
+        network: network.clone(),
+        credentials: WalletIdentifierCredentials::Mnemonic(test_session.credentials.clone()),
+    };
+    let identifier = gdk_electrum::get_wallet_identifier(&opt).unwrap();
+    let master_xpub = test_session.credentials.master_xpub(&network).unwrap();
+    let opt = serde_json::json!({"network": network, "master_xpub": master_xpub.to_string()});
+    let opt: GetWalletIdentifierOpt = serde_json::from_value(opt).unwrap();
+    assert_eq!(gdk_electrum::get_wallet_identifier(&opt).unwrap(), identifier);
+
+    // A server accepting connections but never replying, as a paused electrs
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    network.electrum_url = Some(listener.local_addr().unwrap().to_string());
+    let mut session = ElectrumSession::new(network.clone()).unwrap();
+    let start = Instant::now();
+    let login_data = session.login_with_signer(test_session.software_signer()).unwrap();
+    session.connect(&serde_json::to_value(&network).unwrap()).unwrap();
+    assert_eq!(session.get_balance(&GetBalanceOpt::default()).unwrap(), balance);
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert!(login_data.syncing);
+    assert!(session.get_sync_status().syncing);
+    assert_eq!(login_data.wallet_hash_id, identifier.wallet_hash_id);
+    assert_eq!(login_data.xpub_hash_id, identifier.xpub_hash_id);
+
+    // Closing the listener unblocks the threads waiting for the server
+    drop(listener);
+    session.disconnect().unwrap();
+}
+
+#[test]
+fn test_watch_only_credentials() {
+    let mut test_session = TestSession::new(|_| ());
+    test_session.fund(100_000);
+    let balance = test_session.session.get_balance(&GetBalanceOpt::default()).unwrap();
+    let credentials = serde_json::json!({"username": "alice", "password": "first"});
+    test_session.session.handle_call("set_watch_only_credentials", credentials.clone()).unwrap();
+    let username = test_session.session.handle_call("get_watch_only_username", Value::Null);
+    assert_eq!(username.unwrap(), "alice");
+    // Flush the store shared with the watch-only session
+    test_session.session.disconnect().unwrap();
+
+    let network = test_session.network.clone();
+    let mut session = ElectrumSession::new(network.clone()).unwrap();
+    let err: Value = session
+        .handle_call("login_watch_only", serde_json::json!({"username": "alice", "password": "x"}))
+        .unwrap_err()
+        .into();
+    assert_eq!(err["code"], "user_not_found_or_invalid");
+    let err: Value = session
+        .handle_call(
+            "login_watch_only",
+            serde_json::json!({"username": "bob", "password": "first"}),
+        )
+        .unwrap_err()
+        .into();
+    assert_eq!(err["code"], "user_not_found_or_invalid");
+
+    session.connect(&serde_json::to_value(&network).unwrap()).unwrap();
+    let login_data = session.handle_call("login_watch_only", credentials.clone()).unwrap();
+    assert_eq!(login_data["xpubs"].as_array().unwrap().len(), 1);
+    assert_eq!(session.get_balance(&GetBalanceOpt::default()).unwrap(), balance);
+    for i in 0.. {
+        assert!(i < 60, "timeout waiting for the watch-only sync");
+        if !session.get_sync_status().syncing {
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+    assert_eq!(session.get_balance(&GetBalanceOpt::default()).unwrap(), balance);
+    session.disconnect().unwrap();
+
+    // A new password re-encrypts the bundle, the old one doesn't work anymore
+    let credentials = serde_json::json!({"username": "alice", "password": "second"});
+    test_session.session.handle_call("set_watch_only_credentials", credentials.clone()).unwrap();
+    let mut session = ElectrumSession::new(network.clone()).unwrap();
+    let old = serde_json::json!({"username": "alice", "password": "first"});
+    assert!(session.handle_call("login_watch_only", old).is_err());
+    session.handle_call("login_watch_only", credentials.clone()).unwrap();
+    assert_eq!(session.get_balance(&GetBalanceOpt::default()).unwrap(), balance);
+
+    // A new username replaces the previous one once saved
+    let renamed = serde_json::json!({"username": "carol", "password": "second"});
+    test_session.session.handle_call("set_watch_only_credentials", renamed.clone()).unwrap();
+    let mut session = ElectrumSession::new(network.clone()).unwrap();
+    assert!(session.handle_call("login_watch_only", credentials).is_err());
+    session.handle_call("login_watch_only", renamed.clone()).unwrap();
+
+    // Empty credentials remove the access
+    let empty = serde_json::json!({"username": "", "password": ""});
+    test_session.session.handle_call("set_watch_only_credentials", empty).unwrap();
+    let username = test_session.session.handle_call("get_watch_only_username", Value::Null);
+    assert_eq!(username.unwrap(), "");
+    let mut session = ElectrumSession::new(network).unwrap();
+    assert!(session.handle_call("login_watch_only", renamed).is_err());
+
+    test_session.stop();
+}
+
+#[test]
+fn test_pause_resume() {
+    let mut test_session = TestSession::new(|_| ());
+    test_session.fund(100_000);
+    let opt = serde_json::to_value(GetBalanceOpt::default()).unwrap();
+    let balance = test_session.session.handle_call("get_balance", opt.clone()).unwrap();
+
+    // Paused sessions fail all calls, or only those needing the server
+    test_session.session.handle_call("pause", serde_json::json!({})).unwrap();
+    let err: Value =
+        test_session.session.handle_call("get_balance", opt.clone()).unwrap_err().into();
+    assert_eq!(err["code"], "session_paused");
+    let serve_cache = serde_json::json!({"serve_cache": true});
+    test_session.session.handle_call("pause", serve_cache).unwrap();
+    assert_eq!(test_session.session.handle_call("get_balance", opt.clone()).unwrap(), balance);
+    let txid = "0000000000000000000000000000000000000000000000000000000000000001";
+    let err = test_session.session.get_transaction_hex(txid).unwrap_err();
+    assert_eq!(err.to_gdk_code(), "id_session_paused");
+    let network = serde_json::to_value(&test_session.network).unwrap();
+    let err: Value = test_session.session.handle_call("connect", network).unwrap_err().into();
+    assert_eq!(err["code"], "session_paused");
+
+    // Transactions received while paused are synced on resume
+    let address = test_session.get_receive_address(0).address;
+    let txid = test_session.node.client.sendtoaddress(&address, 50_000, None).unwrap();
+    thread::sleep(Duration::from_secs(3));
+    assert_eq!(test_session.session.handle_call("get_balance", opt).unwrap(), balance);
+    test_session.session.handle_call("resume", Value::Null).unwrap();
+    test_session.wait_tx(vec![0], &txid, Some(50_000), Some(TransactionType::Incoming));
+
+    test_session.reconnect();
+    test_session.fund(10_000);
+
+    test_session.stop();
+}
+
+#[test]
+fn test_pause_during_sync() {
+    let mut test_session = TestSession::new(|_| ());
+    let mut txid = String::new();
+    for _ in 0..10 {
+        let address = test_session.get_receive_address(0).address;
+        txid = test_session.node.client.sendtoaddress(&address, 10_000, None).unwrap();
+    }
+    test_session.wait_tx(vec![0], &txid, Some(10_000), Some(TransactionType::Incoming));
+    let balance = test_session.session.get_balance(&GetBalanceOpt::default()).unwrap();
+
+    // A session with an empty store has the whole wallet to sync
+    let state_dir = TempDir::new().unwrap();
+    let mut network = test_session.network.clone();
+    network.state_dir = format!("{}", state_dir.path().display());
+    let mut session = ElectrumSession::new(network.clone()).unwrap();
+    session.login_with_signer(test_session.software_signer()).unwrap();
+    session.set_transaction_memo(&txid, "paused").unwrap();
+    session.connect(&serde_json::to_value(&network).unwrap()).unwrap();
+    session.pause(&PauseOpt::default()).unwrap();
+
+    // The store flushed on pause is intact
+    let mut other = ElectrumSession::new(network.clone()).unwrap();
+    other.login_with_signer(test_session.software_signer()).unwrap();
+    let memos = other.get_memos().unwrap();
+    assert_eq!(memos.values().collect::<Vec<_>>(), vec!["paused"]);
+    drop(other);
+
+    session.resume().unwrap();
+    for i in 0.. {
+        assert!(i < 60, "timeout waiting for the sync after resume");
+        if session.get_balance(&GetBalanceOpt::default()).unwrap() == balance {
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+    session.disconnect().unwrap();
+
+    test_session.stop();
+}
+
+#[test]
+fn test_session_peers() {
+    let mut test_session = TestSession::builder().nodes(2).spawn();
+    assert_eq!(test_session.peers().len(), 1);
+    let hash = test_session.mine_block();
+
+    // The peer follows the chain of the node indexed by the server
+    let peer = &test_session.peers()[0].client;
+    for i in 0.. {
+        assert!(i < 60, "timeout waiting for the peer to get block {}", hash);
+        if peer.get_best_block_hash().unwrap().to_string() == hash {
+            break;
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+    assert_eq!(test_session.node_client().get_block_count().unwrap(), 102);
+
+    test_session.stop();
+}
+
+#[test]
+fn test_call_spans() {
+    let mut test_session = TestSession::new(|_| ());
+    test_session.fund(100_000);
+    let address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let address = address.parse::<gdk_common::bitcoin::Address<_>>().unwrap().assume_checked();
+    let opt = SendTransactionOpt {
+        transaction: test_session.create_tx(&address, 10_000, 1_000),
+        confirmation_token: None,
+        payjoin: None,
+        client_request_id: None,
+        check_acceptance: false,
+    };
+
+    let recorder = SpanRecorder::default();
+    gdk_common::tracing::subscriber::with_default(recorder.clone(), || {
+        let opt = serde_json::to_value(&opt).unwrap();
+        test_session.session.handle_call("send_transaction", opt).unwrap();
+    });
+    let spans = recorder.spans("call");
+    assert_eq!(spans.len(), 1);
+    let fields = &spans[0].fields;
+    assert_eq!(fields["method"], "send_transaction");
+    assert!(!fields["wallet_hash_id"].is_empty());
+    assert!(fields["elapsed_ms"].parse::<u64>().is_ok());
+
+    test_session.stop();
+}
+
+#[test]
+fn test_stop_during_sync() {
+    let mut test_session = TestSession::new(|_| ());
+    let mut txid = String::new();
+    for _ in 0..10 {
+        let address = test_session.get_receive_address(0).address;
+        txid = test_session.node.client.sendtoaddress(&address, 10_000, None).unwrap();
+    }
+    test_session.wait_tx(vec![0], &txid, Some(10_000), Some(TransactionType::Incoming));
+    let balance = test_session.session.get_balance(&GetBalanceOpt::default()).unwrap();
+
+    // Stop a session with an empty store during its initial sync
+    let state_dir = TempDir::new().unwrap();
+    let mut network = test_session.network.clone();
+    network.state_dir = format!("{}", state_dir.path().display());
+    let mut session = ElectrumSession::new(network.clone()).unwrap();
+    session.login_with_signer(test_session.software_signer()).unwrap();
+    session.handle_call("connect", serde_json::to_value(&network).unwrap()).unwrap();
+    session.handle_call("stop", Value::Null).unwrap();
+
+    // Every call fails afterwards
+    let opt = serde_json::to_value(GetBalanceOpt::default()).unwrap();
+    let err: Value = session.handle_call("get_balance", opt).unwrap_err().into();
+    assert_eq!(err["code"], "session_closed");
+    let err: Value = session.handle_call("stop", Value::Null).unwrap_err().into();
+    assert_eq!(err["code"], "session_closed");
+    drop(session);
+
+    // The store is consistent and the sync completes on login again
+    let mut session = ElectrumSession::new(network.clone()).unwrap();
+    session.login_with_signer(test_session.software_signer()).unwrap();
+    session.verify_store().unwrap();
+    session.connect(&serde_json::to_value(&network).unwrap()).unwrap();
+    for i in 0.. {
+        assert!(i < 60, "timeout waiting for the sync after stop");
+        if session.get_balance(&GetBalanceOpt::default()).unwrap() == balance {
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+    session.stop().unwrap();
+
+    test_session.stop();
+}
+
+#[test]
+fn test_session_manager() {
+    let mut test_session = TestSession::new(|_| ());
+    let electrs_port = test_session.electrs.electrum_url.rsplit(':').next().unwrap().to_string();
+    let connections_before = connections_to(&electrs_port);
+
+    let manager = SessionManager::new(2);
+    let network = test_session.network.clone();
+    let mut sessions: Vec<_> = (0..100u8)
+        .map(|i| {
+            let mut session = ElectrumSession::new(network.clone()).unwrap();
+            session.set_session_manager(&manager);
+            let signer = SoftwareSigner::from_seed(&[i; 64], network.bip32_network()).unwrap();
+            session.login_with_signer(Arc::new(signer)).unwrap();
+            session.connect(&serde_json::to_value(&network).unwrap()).unwrap();
+            session
+        })
+        .collect();
+    assert_eq!(manager.sessions(), 100);
+    assert_eq!(manager.connections(), 1);
+
+    // Funds sent to some wallets are seen by them only
+    let opt = GetAddressOpt {
+        subaccount: 0,
+        address_type: None,
+        is_internal: None,
+        ignore_gap_limit: None,
+        confirm_on_device: None,
+        expected_satoshi: None,
+        expected_asset: None,
+        expiry_seconds: None,
+    };
+    for session in sessions.iter().step_by(25) {
+        let address = session.get_receive_address(&opt).unwrap().address;
+        test_session.node.client.sendtoaddress(&address, 10_000, None).unwrap();
+    }
+    let mut opt = GetBalanceOpt::default();
+    opt.asset_id = Some("btc".into());
+    for i in 0.. {
+        assert!(i < 60, "timeout waiting for the managed sessions to sync");
+        let synced = sessions.iter().all(|s| !s.get_sync_status().syncing);
+        let funded = sessions.iter().step_by(25).all(|s| s.get_balance(&opt).unwrap()["btc"] > 0);
+        if synced && funded {
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+    for (i, session) in sessions.iter().enumerate() {
+        let expected = if i % 25 == 0 {
+            10_000
+        } else {
+            0
+        };
+        assert_eq!(session.get_balance(&opt).unwrap()["btc"], expected);
+        assert!(session.handles.is_empty());
+    }
+
+    // All the wallets share a single connection to the server
+    assert!(connections_to(&electrs_port) <= connections_before + 1);
+
+    for session in sessions.iter_mut() {
+        session.disconnect().unwrap();
+    }
+    assert_eq!(manager.sessions(), 0);
+
+    test_session.stop();
+}
+
+/// The established TCP connections to the local `port`
+fn connections_to(port: &str) -> usize {
+    let port = format!("{:04X}", port.parse::<u16>().unwrap());
+    ["/proc/net/tcp", "/proc/net/tcp6"]
+        .iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .flat_map(|table| {
+            table
+                .lines()
+                .skip(1)
+                .map(|line| line.split_whitespace().map(String::from).collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        })
+        .filter(|fields| fields[3] == "01" && fields[2].ends_with(&format!(":{port}")))
+        .count()
+}
+
+#[test]
+fn test_network_info() {
+    let mut test_session = TestSession::new(|_| ());
+    let info = test_session.session.handle_call("get_network_info", Value::Null).unwrap();
+    assert_eq!(info["liquid"], false);
+    assert_eq!(info["dust_limits"]["p2wpkh"], 294);
+    assert_eq!(info["max_standard_tx_weight"], 400_000);
+    // The minimum fee rate reported is the one required when sending
+    let min_fee_rate = test_session.session.get_min_fee_rate().unwrap();
+    assert_eq!(info["min_fee_rate"], min_fee_rate);
+
+    // The skew of the local clock is estimated from the tips just found,
+    // mined by the node with the same clock
+    test_session.mine_block();
+    let height = test_session.session.get_block_status().unwrap().server_tip_height;
+    test_session.wait_block_ntf(height);
+    let info = test_session.session.handle_call("get_network_info", Value::Null).unwrap();
+    let skew = info["clock_skew_seconds"].as_i64().unwrap();
+    assert!(skew.abs() < 60, "{}", skew);
+    assert!(test_session.session.filter_events("clock_skew").is_empty());
+
+    test_session.stop();
+}
+
+#[test]
+fn test_pegout() {
+    let mut test_session = TestSession::builder().chain(Chain::Liquid).spawn();
+    test_session.fund(1_000_000);
+
+    let mainchain_address = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+    let opt = CreatePegoutOpt {
+        subaccount: 0,
+        mainchain_address: mainchain_address.to_string(),
+        satoshi: 100_000,
+        fee_rate: Some(test_session.session.get_min_fee_rate().unwrap()),
+        split: false,
+    };
+    let pegout = test_session.session.create_pegout_transaction(&opt).unwrap();
+    assert_eq!(pegout.mainchain_address, mainchain_address);
+    assert!(pegout.consolidation_steps.is_empty());
+
+    let opt = PsbtFromTransactionOpt {
+        transaction: pegout.transaction.clone(),
+        transaction_inputs: vec![],
+    };
+    let psbt = test_session.session.psbt_from_create_transaction(&opt).unwrap().psbt;
+    let opt = SignPsbtOpt {
+        psbt,
+        subaccount: 0,
+        xprv: None,
+        inputs: None,
+        expert: false,
+    };
+    let signed = test_session.session.sign_psbt(&opt).unwrap();
+    assert!(signed.is_complete);
+    let opt = FinalizePsbtOpt {
+        psbt: signed.psbt,
+    };
+    let finalized = test_session.session.finalize_and_extract(&opt).unwrap();
+
+    // The node accepts the peg-out and mines it
+    let txid = test_session.session.broadcast_transaction(&finalized.transaction).unwrap();
+    assert_eq!(txid, finalized.txhash);
+    let sent = pegout.satoshi + pegout.fee;
+    test_session.wait_tx(vec![0], &txid, Some(sent), Some(TransactionType::Outgoing));
+    test_session.mine_block();
+    test_session.wait_for_tx(&txid, 1, Duration::from_secs(60));
+
+    let tx: Value = test_session
+        .node
+        .client
+        .call("getrawtransaction", &[txid.clone().into(), true.into()])
+        .unwrap();
+    let pegout_output = tx["vout"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|output| output["scriptPubKey"]["type"] == "nulldata")
+        .unwrap();
+    assert_eq!(pegout_output["value"], serde_json::json!(0.001));
+    assert!(tx["confirmations"].as_u64().unwrap() >= 1);
+
+    let policy_asset = test_session.network.policy_asset.clone().unwrap();
+    let item = test_session.get_tx_from_list(0, &txid);
+    assert_eq!(item.fee, pegout.fee);
+    assert_eq!(item.satoshi[&policy_asset], -(sent as i64));
+
+    test_session.stop();
+}
+
+#[test]
+fn test_signblock_validation() {
+    // The regtest blocks are signed by the default OP_TRUE signblockscript
+    let mut test_session = TestSession::builder().chain(Chain::Liquid).spawn();
+    test_session.mine_block();
+    let height = test_session.session.get_block_status().unwrap().server_tip_height;
+    test_session.wait_block_ntf(height);
+    let signblock = test_session.session.get_network_info().signblock.unwrap();
+    assert_eq!(signblock.status, SignblockStatus::Valid);
+    assert_eq!(signblock.height, height);
+    let health = test_session.session.get_health().unwrap();
+    assert!(!health.reasons.contains(&HealthReason::SignblockInvalid), "{:?}", health);
+    test_session.stop();
+
+    // A session expecting the blocks signed by a federation finds them invalid
+    let pubkey = "026a2a106ec32c8a1e8052e5d02a7b0a150423dbd9b116fc48d46630ff6e6a05b9";
+    let mut test_session = TestSession::builder()
+        .chain(Chain::Liquid)
+        .network(|network| network.federation_pubkeys = Some(vec![pubkey.to_string()]))
+        .spawn();
+    test_session.mine_block();
+    let height = test_session.session.get_block_status().unwrap().server_tip_height;
+    test_session.wait_block_ntf(height);
+    let info = test_session.session.handle_call("get_network_info", Value::Null).unwrap();
+    assert_eq!(info["signblock"]["status"], "invalid");
+    assert_eq!(info["signblock"]["height"], height);
+    let health = test_session.session.get_health().unwrap();
+    assert!(health.reasons.contains(&HealthReason::SignblockInvalid), "{:?}", health);
+    assert_ne!(health.status, HealthStatus::Healthy);
+    test_session.stop();
+}
+
+#[test]
+fn test_search_transactions() {
+    let mut test_session = TestSession::new(|_| ());
+    let txid1 = test_session.fund(100_000);
+    test_session.mine_block();
+    let txid2 = test_session.fund(200_000);
+    let txid3 = test_session.fund(300_000);
+    test_session.session.set_transaction_memo(&txid1, "MEMO1 invoice 1234").unwrap();
+    test_session.session.set_transaction_memo(&txid2, "memo2 Café").unwrap();
+
+    let search = |query: &str| {
+        let opt = SearchTransactionsOpt {
+            query: query.into(),
+        };
+        let result = test_session.session.search_transactions(&opt).unwrap();
+        result.transactions.into_iter().map(|found| found.transaction.txhash).collect::<Vec<_>>()
+    };
+    assert_eq!(search("memo1"), vec![txid1.clone()]);
+    assert_eq!(search("Invoice 1234"), vec![txid1.clone()]);
+    assert_eq!(search("CAFÉ"), vec![txid2.clone()]);
+    assert!(search("memo3").is_empty());
+    // Most recent first, transactions without memo are never listed
+    assert_eq!(search("MEMO"), vec![txid2.clone(), txid1.clone()]);
+    assert_eq!(search(""), vec![txid2, txid1]);
+    assert!(!search("").contains(&txid3));
+
+    test_session.stop();
+}
+
+#[test]
+fn test_output_labels() {
+    let mut test_session = TestSession::new(|_| ());
+    let txid = test_session.fund(100_000);
+    let details = test_session.session.get_transaction_details(&txid).unwrap();
+    let vout = details.outputs.iter().position(|o| o.inout.is_relevant).unwrap() as u32;
+
+    let set_label = |test_session: &TestSession, txhash: &str, pt_idx: u32, label: &str| {
+        let opt = OutputLabel {
+            txhash: txhash.into(),
+            pt_idx,
+            label: label.into(),
+        };
+        test_session.session.set_output_label(&opt)
+    };
+    let utxo_labels = |test_session: &TestSession| {
+        let opt = GetUnspentOpt {
+            subaccount: 0,
+            num_confs: Some(0),
+            confidential_utxos_only: None,
+            all_coins: None,
+            asset_id: None,
+            trust_own_unconfirmed: None,
+            asset_precisions: Default::default(),
+            include_provenance: false,
+        };
+        let utxos = test_session.session.get_unspent_outputs(&opt).unwrap();
+        utxos.0["btc"]
+            .iter()
+            .map(|u| (u.txhash.clone(), u.pt_idx, u.label.clone()))
+            .collect::<Vec<_>>()
+    };
+
+    set_label(&test_session, &txid, vout, "cold storage").unwrap();
+    assert!(set_label(&test_session, &txid, 99, "no such output").is_err());
+    let unknown = "0000000000000000000000000000000000000000000000000000000000000000";
+    assert!(set_label(&test_session, unknown, 0, "no such tx").is_err());
+    assert!(set_label(&test_session, &txid, vout, &"a".repeat(1025)).is_err());
+
+    assert_eq!(utxo_labels(&test_session), vec![(txid.clone(), vout, Some("cold storage".into()))]);
+    let details = test_session.session.get_transaction_details(&txid).unwrap();
+    assert_eq!(details.outputs[vout as usize].inout.label.as_deref(), Some("cold storage"));
+    let opt = SearchTransactionsOpt {
+        query: "COLD".into(),
+    };
+    let found = test_session.session.search_transactions(&opt).unwrap().transactions;
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].transaction.txhash, txid);
+
+    // The spending input shows the label, the label of the change shows in the utxos
+    let address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let address = address.parse::<gdk_common::bitcoin::Address<_>>().unwrap().assume_checked();
+    let tx = test_session.create_tx(&address, 10_000, 1_000);
+    let spend_txid = test_session.session.broadcast_transaction(&tx).unwrap();
+    test_session.wait_for_tx(&spend_txid, 0, Duration::from_secs(60));
+    let details = test_session.session.get_transaction_details(&spend_txid).unwrap();
+    assert_eq!(details.inputs[0].inout.label.as_deref(), Some("cold storage"));
+    set_label(&test_session, &spend_txid, 1, "change").unwrap();
+    assert_eq!(utxo_labels(&test_session), vec![(spend_txid.clone(), 1, Some("change".into()))]);
+
+    // Labels persist across sessions
+    test_session.reconnect();
+    assert_eq!(utxo_labels(&test_session)[0].2.as_deref(), Some("change"));
+
+    // Export, clear and import again
+    test_session.session.set_transaction_memo(&spend_txid, "rent").unwrap();
+    let exported = test_session.session.export_labels().unwrap();
+    assert_eq!(exported.memos[&spend_txid], "rent");
+    assert_eq!(exported.output_labels.len(), 2);
+    let exported: WalletLabels =
+        serde_json::from_value(serde_json::to_value(&exported).unwrap()).unwrap();
+    set_label(&test_session, &spend_txid, 1, "").unwrap();
+    set_label(&test_session, &txid, vout, "").unwrap();
+    assert_eq!(utxo_labels(&test_session)[0].2, None);
+    assert_eq!(test_session.session.export_labels().unwrap().output_labels, vec![]);
+
+    test_session.session.import_labels(&exported).unwrap();
+    assert_eq!(test_session.session.export_labels().unwrap(), exported);
+    assert_eq!(utxo_labels(&test_session)[0].2.as_deref(), Some("change"));
+
+    test_session.stop();
+}
+
+#[test]
+fn test_change_provenance() {
+    let mut test_session = TestSession::new(|_| ());
+    let funding = test_session.fund(100_000);
+    let address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let address = address.parse::<gdk_common::bitcoin::Address<_>>().unwrap().assume_checked();
+    let mut spends = vec![];
+    for _ in 0..3 {
+        let tx = test_session.create_tx(&address, 10_000, 1_000);
+        let txid = test_session.session.broadcast_transaction(&tx).unwrap();
+        test_session.wait_for_tx(&txid, 0, Duration::from_secs(60));
+        spends.push(txid);
+    }
+
+    let utxos = |test_session: &TestSession, include_provenance| {
+        let opt = GetUnspentOpt {
+            subaccount: 0,
+            num_confs: Some(0),
+            include_provenance,
+            ..Default::default()
+        };
+        test_session.session.get_unspent_outputs(&opt).unwrap().0.remove("btc").unwrap()
+    };
+    let utxos_with_provenance = utxos(&test_session, true);
+    assert_eq!(utxos_with_provenance.len(), 1);
+    let change = &utxos_with_provenance[0];
+    assert_eq!(change.txhash, spends[2]);
+    let expected = vec![spends[1].clone(), spends[0].clone(), funding];
+    assert_eq!(change.provenance, Some(expected.clone()));
+    assert_eq!(utxos(&test_session, false)[0].provenance, None);
+
+    // Recorded in the cache as the transactions are synced
+    test_session.reconnect();
+    assert_eq!(utxos(&test_session, true)[0].provenance, Some(expected));
+
+    test_session.stop();
+}
+
+#[test]
+fn send_fails() {
+    use gdk_common::bitcoin;
+    use gdk_common::bitcoin::hashes::Hash;
+    use gdk_common::bitcoin::hex::DisplayHex;
+    use gdk_common::ErrorCode;
+    use gdk_electrum::address::{check_send_amount, CheckSendAmountOpt};
+    use gdk_electrum::error::Error;
+    use std::str::FromStr;
+
+    let mut test_session = TestSession::new(|_| ());
+    test_session.fund(100_000);
+    test_session.mine_block();
+    let address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let address = address.parse::<bitcoin::Address<_>>().unwrap().assume_checked();
+    let valid = test_session.create_tx(&address, 10_000, 1_000);
+    let broken = |edit: &dyn Fn(&mut bitcoin::Transaction)| {
+        let mut tx: bitcoin::Transaction =
+            bitcoin::consensus::encode::deserialize_hex(&valid).unwrap();
+        edit(&mut tx);
+        bitcoin::consensus::encode::serialize_hex(&tx)
+    };
+    let check = |test_session: &TestSession, tx: &str| {
+        let opt = CheckMempoolAcceptanceOpt {
+            transaction: tx.to_string(),
+        };
+        test_session.session.check_mempool_acceptance(&opt).unwrap().reason
+    };
+    let broadcast_reason = |test_session: &mut TestSession, tx: &str| match test_session
+        .session
+        .broadcast_transaction(tx)
+    {
+        Err(Error::TransactionRejected {
+            reason,
+            ..
+        }) => reason,
+        other => panic!("expected a typed rejection, got {:?}", other),
+    };
+
+    let cases = [
+        (
+            broken(&|tx| tx.input[0].previous_output.txid = bitcoin::Txid::all_zeros()),
+            RejectReason::MissingInputs,
+        ),
+        (test_session.create_tx(&address, 10_000, 0), RejectReason::MinRelayFeeNotMet),
+        (
+            broken(&|tx| {
+                tx.lock_time = bitcoin::absolute::LockTime::from_height(10_000).unwrap();
+                tx.input[0].sequence = bitcoin::Sequence::ENABLE_LOCKTIME_NO_RBF;
+            }),
+            RejectReason::NonFinal,
+        ),
+    ];
+    for (tx, reason) in cases.iter() {
+        assert_eq!(check(&test_session, tx), Some(*reason));
+        assert_eq!(broadcast_reason(&mut test_session, tx), *reason);
+    }
+
+    // Unsigned transactions are refused before broadcasting
+    let unsigned = broken(&|tx| {
+        tx.input[0].witness.clear();
+        tx.input[0].script_sig = bitcoin::ScriptBuf::new();
+    });
+    assert_eq!(check(&test_session, &unsigned), Some(RejectReason::MissingWitness));
+
+    // Below the relay fee, the error code is the one of the low fee rates
+    let zero_fee = test_session.create_tx(&address, 10_000, 0);
+    let err = test_session.session.broadcast_transaction(&zero_fee).unwrap_err();
+    assert_eq!(err.to_gdk_code(), "id_fee_rate_is_below_minimum");
+
+    // Checked before sending, the valid one is sent
+    assert_eq!(check(&test_session, &valid), None);
+    let mut opt = SendTransactionOpt {
+        transaction: cases[0].0.clone(),
+        confirmation_token: None,
+        payjoin: None,
+        client_request_id: None,
+        check_acceptance: true,
+    };
+    let err = test_session.session.send_transaction(&opt).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::TransactionRejected {
+            reason: RejectReason::MissingInputs,
+            ..
+        }
+    ));
+    opt.transaction = valid;
+    let txid = test_session.session.send_transaction(&opt).unwrap().txhash;
+    test_session.wait_for_tx(&txid, 0, Duration::from_secs(60));
+
+    // Amounts that can't be sent are refused with the code of the reason
+    let dust = test_session.network.policy().dust_limits.for_script(&address.script_pubkey());
+    let check_amount = |asset_id: Option<&str>, satoshi: Option<i64>, amount: Option<&str>| {
+        let opt = CheckSendAmountOpt {
+            network: test_session.network.clone(),
+            scriptpubkey: address.script_pubkey().as_bytes().to_lower_hex_string(),
+            asset_id: asset_id.map(String::from),
+            satoshi,
+            amount: amount.map(String::from),
+            precision: Some(2),
+        };
+        check_send_amount(&opt)
+    };
+    let cases = [
+        (Some(0), None, ErrorCode::AmountZero),
+        (Some(-1), None, ErrorCode::AmountNegative),
+        (Some(dust as i64 - 1), None, ErrorCode::AmountBelowDust),
+        (Some(2_100_000_000_000_001), None, ErrorCode::AmountOverflow),
+        (None, Some("21000000.00000001"), ErrorCode::AmountOverflow),
+        (None, Some("0.000100001"), ErrorCode::AmountPrecisionLoss),
+        (Some(10_000), Some("0.0001"), ErrorCode::InvalidAmount),
+    ];
+    for (satoshi, amount, code) in cases {
+        let result = check_amount(None, satoshi, amount);
+        assert_eq!(result.error_code, Some(code), "{:?} {:?}", satoshi, amount);
+        assert_eq!(result.satoshi, None);
+    }
+    assert_eq!(check_amount(None, Some(dust as i64), None).satoshi, Some(dust));
+    assert_eq!(check_amount(None, None, Some("0.0001")).satoshi, Some(10_000));
+
+    test_session.stop();
+
+    // Other Liquid assets have no dust limit, their amounts have the
+    // precision of the asset in the registry
+    let mut test_session = TestSession::builder().chain(Chain::Liquid).spawn();
+    let address = test_session.get_receive_address(0).address;
+    let address = gdk_common::elements::Address::from_str(&address).unwrap();
+    let asset_id = "0".repeat(63) + "1";
+    let check_amount = |satoshi: Option<i64>, amount: Option<&str>| {
+        let opt = CheckSendAmountOpt {
+            network: test_session.network.clone(),
+            scriptpubkey: address.script_pubkey().as_bytes().to_lower_hex_string(),
+            asset_id: Some(asset_id.clone()),
+            satoshi,
+            amount: amount.map(String::from),
+            precision: Some(2),
+        };
+        check_send_amount(&opt)
+    };
+    assert_eq!(check_amount(Some(1), None).satoshi, Some(1));
+    assert_eq!(check_amount(None, Some("1.5")).satoshi, Some(150));
+    assert_eq!(check_amount(Some(0), None).error_code, Some(ErrorCode::AmountZero));
+    let result = check_amount(None, Some("1.505"));
+    assert_eq!(result.error_code, Some(ErrorCode::AmountPrecisionLoss));
+    assert_eq!(result.error, "id_invalid_amount");
+    test_session.stop();
+}
+
+#[test]
+fn test_export_transactions() {
+    struct FixedRate;
+
+    impl PriceSource for FixedRate {
+        fn fetch(&self, pair: Pair, _exchange: &str) -> Result<Ticker, gdk_electrum::error::Error> {
+            Ok(Ticker::new(pair, 20_000.0))
+        }
+
+        fn available_currencies(&self) -> Result<AvailableCurrencies, gdk_electrum::error::Error> {
+            let currencies = vec!["USD".parse().unwrap()];
+            Ok(AvailableCurrencies::from([("BITFINEX".to_string(), currencies)]))
+        }
+    }
+
+    let mut test_session = TestSession::new(|_| ());
+
+    // Confirmed before the rates are recorded
+    test_session.fund(50_000);
+    test_session.mine_block();
+
+    test_session.session.set_price_source(Arc::new(FixedRate));
+    test_session.reconnect();
+    let txid = test_session.fund(100_000);
+    test_session.session.set_transaction_memo(&txid, "invoice 1234, \"paid\"\nthanks").unwrap();
+    test_session.mine_block();
+    let address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let address = address.parse::<gdk_common::bitcoin::Address<_>>().unwrap().assume_checked();
+    let tx = test_session.create_tx(&address, 30_000, 1_000);
+    let txid = test_session.session.broadcast_transaction(&tx).unwrap();
+    for i in 0.. {
+        assert!(i < 60, "timeout waiting for the sent transaction");
+        if test_session.get_tx_list(0).iter().any(|e| e.txhash == txid) {
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+    test_session.mine_block();
+
+    let export = |format: ExportFormat| {
+        let opt = ExportTransactionsOpt {
+            format,
+            subaccount: 0,
+            created_at_from: None,
+            created_at_to: None,
+        };
+        let mut data = vec![];
+        test_session.session.export_transactions(&opt, &mut data).unwrap();
+        String::from_utf8(data).unwrap()
+    };
+    let mut rows = vec![];
+    for i in 0.. {
+        assert!(i < 60, "timeout waiting for the confirmation rates");
+        let csv = export(ExportFormat::Csv);
+        rows = parse_csv(&csv);
+        if rows.len() == 4 && rows[1..].iter().filter(|row| row[9] == "USD").count() == 2 {
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    let header = &rows[0];
+    assert_eq!(header[..4], ["timestamp", "txid", "direction", "btc"]);
+    assert_eq!(header[4..], ["fee", "memo", "address", "height", "fiat_value", "fiat_currency"]);
+    let sum: i64 = rows[1..].iter().map(|row| row[3].parse::<i64>().unwrap()).sum();
+    assert_eq!(sum, 50_000 + 100_000 - 30_000 - 1_000);
+    let directions: Vec<_> = rows[1..].iter().map(|row| row[2].as_str()).collect();
+    assert_eq!(directions, ["incoming", "incoming", "outgoing"]);
+    assert_eq!(rows[2][5], "invoice 1234, \"paid\"\nthanks");
+    assert_eq!((rows[1][8].as_str(), rows[1][9].as_str()), ("", ""));
+    assert_eq!((rows[2][8].as_str(), rows[2][9].as_str()), ("20.00", "USD"));
+    assert_eq!(rows[3][8], "-6.20");
+    assert_eq!(rows[3][6], address.to_string());
+
+    let json: Vec<ExportedTransaction> = serde_json::from_str(&export(ExportFormat::Json)).unwrap();
+    assert_eq!(json.len(), 3);
+    assert_eq!(json[2].amounts["btc"], -31_000);
+    assert_eq!(json[2].fee, 1_000);
+
+    test_session.stop();
+}
+
+/// The fields of the rows of `csv`, quoted as in RFC 4180
+fn parse_csv(csv: &str) -> Vec<Vec<String>> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = csv.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => row.push(std::mem::take(&mut field)),
+            ('\r', false) => (),
+            ('\n', false) => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            (c, _) => field.push(c),
+        }
+    }
+    rows
+}
+
+#[test]
+fn test_fiat_at_confirmation() {
+    struct FixedRate;
+
+    impl PriceSource for FixedRate {
+        fn fetch(&self, pair: Pair, _exchange: &str) -> Result<Ticker, gdk_electrum::error::Error> {
+            Ok(Ticker::new(pair, 20_000.0))
+        }
+
+        fn available_currencies(&self) -> Result<AvailableCurrencies, gdk_electrum::error::Error> {
+            let currencies = vec!["USD".parse().unwrap()];
+            Ok(AvailableCurrencies::from([("BITFINEX".to_string(), currencies)]))
+        }
+
+        fn fetch_historical(
+            &self,
+            pair: Pair,
+            _exchange: &str,
+            _timestamp: u64,
+        ) -> Result<Option<Ticker>, gdk_electrum::error::Error> {
+            Ok(Some(Ticker::new(pair, 10_000.0)))
+        }
+    }
+
+    let mut test_session = TestSession::new(|_| ());
+
+    // Confirmed before the rates are recorded
+    let old_txid = test_session.fund(50_000);
+    test_session.mine_block();
+
+    test_session.session.set_price_source(Arc::new(FixedRate));
+    test_session.reconnect();
+    let txid = test_session.fund(100_000);
+    test_session.mine_block();
+
+    let fiat_of = |test_session: &TestSession, txid: &str| {
+        let txs = test_session.get_tx_list(0);
+        txs.into_iter().find(|tx| tx.txhash == txid).unwrap().fiat_at_confirmation
+    };
+    for i in 0.. {
+        assert!(i < 60, "timeout waiting for the confirmation rate");
+        if fiat_of(&test_session, &txid).is_some() {
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+    assert_eq!(fiat_of(&test_session, &old_txid), None);
+
+    test_session.reconnect();
+    let expected = FiatAtConfirmation {
+        fiat: "20.00".into(),
+        fiat_currency: "USD".into(),
+        fiat_rate: "20000.00000000".into(),
+    };
+    assert_eq!(fiat_of(&test_session, &txid), Some(expected.clone()));
+    assert_eq!(fiat_of(&test_session, &old_txid), None);
+
+    let result = test_session.session.backfill_fiat_at_confirmation().unwrap();
+    assert_eq!((result.backfilled, result.missing), (1, 0));
+    assert_eq!(fiat_of(&test_session, &old_txid).unwrap().fiat, "5.00");
+    assert_eq!(fiat_of(&test_session, &txid), Some(expected));
+
+    test_session.stop();
+}
+
+#[test]
+fn test_send_transaction_idempotent() {
+    let mut test_session = TestSession::new(|_| ());
+    test_session.fund(100_000);
+    test_session.mine_block();
+
+    let address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let address = address.parse::<gdk_common::bitcoin::Address<_>>().unwrap().assume_checked();
+    let send = |test_session: &mut TestSession, tx: String, id: &str| {
+        let opt = SendTransactionOpt {
+            transaction: tx,
+            confirmation_token: None,
+            payjoin: None,
+            client_request_id: Some(id.to_string()),
+            check_acceptance: false,
+        };
+        test_session.session.send_transaction(&opt).unwrap().txhash
+    };
+
+    let tx = test_session.create_tx(&address, 30_000, 1_000);
+    let txid = send(&mut test_session, tx, "payment-1");
+    for i in 0.. {
+        assert!(i < 60, "timeout waiting for the sent transaction");
+        if test_session.get_tx_list(0).iter().any(|e| e.txhash == txid) {
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    // A retry rebuilding the transaction, spending the change of the first
+    let tx = test_session.create_tx(&address, 30_000, 1_000);
+    assert_eq!(send(&mut test_session, tx, "payment-1"), txid);
+    let mempool = test_session.node.client.get_raw_mempool().unwrap();
+    assert_eq!(mempool.iter().map(|txid| txid.to_string()).collect::<Vec<_>>(), [txid.clone()]);
+
+    // Also after a reconnection, while another id sends a new transaction
+    test_session.reconnect();
+    let tx = test_session.create_tx(&address, 30_000, 1_000);
+    assert_eq!(send(&mut test_session, tx.clone(), "payment-1"), txid);
+    assert_ne!(send(&mut test_session, tx, "payment-2"), txid);
+    assert_eq!(test_session.node.client.get_raw_mempool().unwrap().len(), 2);
+
+    test_session.stop();
+}
+
+#[test]
+fn test_send_transaction_broadcast_details() {
+    let mut test_session = TestSession::new(|_| ());
+    test_session.fund(100_000);
+    test_session.mine_block();
+
+    let address = test_session.node.client.getnewaddress(None, None).unwrap();
+    let address = address.parse::<gdk_common::bitcoin::Address<_>>().unwrap().assume_checked();
+    let tx = test_session.create_tx(&address, 30_000, 1_000);
+    let opt = SendTransactionOpt {
+        transaction: tx.clone(),
+        confirmation_token: None,
+        payjoin: None,
+        client_request_id: None,
+        check_acceptance: false,
+    };
+    let result = test_session.session.send_transaction(&opt).unwrap();
+    let broadcast = result.broadcast.clone().unwrap();
+    assert_eq!(broadcast.transaction, tx);
+    assert!(!broadcast.server.is_empty());
+    assert!(broadcast.broadcast_at > 0);
+    assert_eq!(broadcast.in_mempool, Some(true));
+
+    // Kept with the transaction, also after a reconnection
+    for i in 0.. {
+        assert!(i < 60, "timeout waiting for the sent transaction");
+        if test_session.get_tx_list(0).iter().any(|e| e.txhash == result.txhash) {
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+    let details = test_session.session.get_transaction_details(&result.txhash).unwrap();
+    assert_eq!(details.broadcast.as_ref(), Some(&broadcast));
+    test_session.reconnect();
+    let details = test_session.session.get_transaction_details(&result.txhash).unwrap();
+    assert_eq!(details.broadcast, Some(broadcast));
+
+    test_session.stop();
+}
+
+#[test]
+fn test_address_payments() {
+    let mut test_session = TestSession::new(|_| ());
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let sender = std::sync::Mutex::new(sender);
+    test_session.session.subscribe(&[NotificationKind::AddressReuse], move |ntf| {
+        sender.lock().unwrap().send(ntf.clone()).unwrap();
+    });
+
+    let ap = test_session.get_receive_address(0);
+    let pay = |test_session: &TestSession, satoshi: u64| {
+        let txid = test_session.node.client.sendtoaddress(&ap.address, satoshi, None).unwrap();
+        test_session.wait_tx(vec![0], &txid, Some(satoshi), Some(TransactionType::Incoming));
+        txid
+    };
+    let first = pay(&test_session, 20_000);
+    test_session.mine_block();
+    let second = pay(&test_session, 30_000);
+
+    match receiver.recv_timeout(Duration::from_secs(60)).unwrap() {
+        Notification::AddressReuse {
+            subaccount,
+            address,
+            pointer,
+            txid,
+            previous_txids,
+        } => {
+            assert_eq!((subaccount, pointer), (0, ap.pointer));
+            assert_eq!(address, ap.address);
+            assert_eq!(txid.to_string(), second);
+            assert_eq!(previous_txids.len(), 1);
+            assert_eq!(previous_txids[0].to_string(), first);
+        }
+        other => panic!("unexpected notification {:?}", other),
+    }
+
+    let by_address = GetAddressPaymentsOpt {
+        subaccount: 0,
+        address: Some(ap.address.clone()),
+        pointer: None,
+        is_internal: false,
+    };
+    let payments = test_session.session.get_address_payments(&by_address).unwrap();
+    assert_eq!((payments.pointer, payments.is_internal), (ap.pointer, false));
+    let summary: Vec<_> =
+        payments.payments.iter().map(|p| (p.txhash.as_str(), p.satoshi, p.confirmations)).collect();
+    assert_eq!(summary, [(first.as_str(), 20_000, 1), (second.as_str(), 30_000, 0)]);
+    assert_eq!(payments.payments[1].block_height, 0);
+
+    let by_pointer = GetAddressPaymentsOpt {
+        subaccount: 0,
+        address: None,
+        pointer: Some(ap.pointer),
+        is_internal: false,
+    };
+    let by_pointer = test_session.session.get_address_payments(&by_pointer).unwrap();
+    assert_eq!(by_pointer.address, ap.address);
+    assert_eq!(by_pointer.payments, payments.payments);
+
+    test_session.mine_block();
+    for i in 0.. {
+        assert!(i < 60, "timeout waiting for the confirmation");
+        let payments = test_session.session.get_address_payments(&by_address).unwrap().payments;
+        let confirmations: Vec<_> = payments.iter().map(|p| p.confirmations).collect();
+        if confirmations == [2, 1] {
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    // A single payment is not a reuse
+    test_session.fund(10_000);
+    assert!(receiver.recv_timeout(Duration::from_secs(2)).is_err());
+
+    test_session.stop();
+}
+
+#[test]
+fn test_expected_payments() {
+    let mut test_session = TestSession::new(|_| ());
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let sender = std::sync::Mutex::new(sender);
+    test_session.session.subscribe(&[NotificationKind::ExpectedPayment], move |ntf| {
+        sender.lock().unwrap().send(ntf.clone()).unwrap();
+    });
+
+    let expect = |satoshi: u64, expiry_seconds: Option<u64>| GetAddressOpt {
+        subaccount: 0,
+        expected_satoshi: Some(satoshi),
+        expiry_seconds,
+        ..Default::default()
+    };
+    let ap = test_session.session.get_receive_address(&expect(12_345, Some(900))).unwrap();
+    let expired = test_session.session.get_receive_address(&expect(1_000, Some(0))).unwrap();
+    let list = |test_session: &TestSession, include_expired: bool, include_paid: bool| {
+        let opt = GetExpectedPaymentsOpt {
+            subaccount: Some(0),
+            include_expired,
+            include_paid,
+        };
+        test_session.session.get_expected_payments(&opt).unwrap().payments
+    };
+
+    let pending = list(&test_session, false, false);
+    assert_eq!(pending.len(), 1);
+    assert_eq!(
+        (pending[0].address.as_str(), pending[0].pointer),
+        (ap.address.as_str(), ap.pointer)
+    );
+    assert_eq!((pending[0].satoshi, pending[0].asset_id.as_str()), (Some(12_345), "btc"));
+    assert_eq!(pending[0].status, PaymentStatus::Pending);
+    let with_expired = list(&test_session, true, false);
+    assert_eq!(with_expired.len(), 2);
+    assert_eq!(with_expired[1].address, expired.address);
+    assert_eq!(with_expired[1].status, PaymentStatus::Expired);
+
+    // The node pays less than expected
+    let txid = test_session.node.client.sendtoaddress(&ap.address, 10_000, None).unwrap();
+    test_session.wait_tx(vec![0], &txid, Some(10_000), Some(TransactionType::Incoming));
+    match receiver.recv_timeout(Duration::from_secs(60)).unwrap() {
+        Notification::ExpectedPayment(payment) => {
+            assert_eq!(payment.address, ap.address);
+            assert_eq!(payment.status, PaymentStatus::Underpaid);
+            assert_eq!((payment.satoshi, payment.received_satoshi), (Some(12_345), Some(10_000)));
+            assert_eq!(payment.txid.as_deref(), Some(txid.as_str()));
+        }
+        other => panic!("unexpected notification {:?}", other),
+    }
+    assert!(list(&test_session, false, false).is_empty());
+    let paid = list(&test_session, false, true);
+    assert_eq!(paid.len(), 1);
+    assert_eq!(paid[0].status, PaymentStatus::Underpaid);
+
+    // An expectation needs an amount and a receive address
+    let opt = GetAddressOpt {
+        subaccount: 0,
+        expiry_seconds: Some(60),
+        ..Default::default()
+    };
+    assert!(test_session.session.get_receive_address(&opt).is_err());
+    let opt = GetAddressOpt {
+        is_internal: Some(true),
+        ..expect(1_000, None)
+    };
+    assert!(test_session.session.get_receive_address(&opt).is_err());
+    assert!(test_session.session.get_receive_address(&expect(0, None)).is_err());
+
+    test_session.stop();
+}
+
+#[test]
+fn test_verify_receive_address() {
+    let mut test_session = TestSession::new(|_| ());
+    let other = test_session.spawn_electrs();
+    let ap = test_session.get_receive_address(0);
+    let opt = |pointer, address: &str| VerifyReceiveAddressOpt {
+        subaccount: 0,
+        pointer,
+        is_internal: false,
+        address: Some(address.to_string()),
+    };
+
+    // Without a verification server only the derivation is checked
+    let result =
+        test_session.session.verify_receive_address(&opt(ap.pointer, &ap.address)).unwrap();
+    assert!(result.verified);
+    assert_eq!(result.address, ap.address);
+    assert_eq!(result.derivation, AddressCheck::passed());
+    assert_eq!(result.address_match, AddressCheck::passed());
+    assert_eq!(result.server_history.status, AddressCheckStatus::Skipped);
+
+    test_session.session.network.address_verification_server =
+        Some(format!("{}:t", other.electrum_url));
+    let result =
+        test_session.session.verify_receive_address(&opt(ap.pointer, &ap.address)).unwrap();
+    assert!(result.verified);
+    assert_eq!(result.server_history, AddressCheck::passed());
+
+    // A used address has the history known by the wallet
+    let txid = test_session.node.client.sendtoaddress(&ap.address, 20_000, None).unwrap();
+    test_session.wait_tx(vec![0], &txid, Some(20_000), Some(TransactionType::Incoming));
+    let result =
+        test_session.session.verify_receive_address(&opt(ap.pointer, &ap.address)).unwrap();
+    assert!(result.verified);
+
+    // An address shown for another pointer is caught
+    let result =
+        test_session.session.verify_receive_address(&opt(ap.pointer + 1, &ap.address)).unwrap();
+    assert!(!result.verified);
+    assert_eq!(result.address_match.status, AddressCheckStatus::Failed);
+    assert_eq!(result.derivation, AddressCheck::passed());
+
+    // As is a history differing from the one known by the wallet
+    test_session.session.forget_tx(0, &txid).unwrap();
+    let result =
+        test_session.session.verify_receive_address(&opt(ap.pointer, &ap.address)).unwrap();
+    assert!(!result.verified);
+    assert_eq!(result.server_history.status, AddressCheckStatus::Failed);
+
+    test_session.stop();
+}
+
+#[test]
+fn test_diagnose_script() {
+    let mut test_session = TestSession::new(|_| ());
+
+    let ap = test_session.get_receive_address(0);
+    let txid = test_session.node.client.sendtoaddress(&ap.address, 20_000, None).unwrap();
+    test_session.wait_tx(vec![0], &txid, Some(20_000), Some(TransactionType::Incoming));
+    test_session.mine_block();
+
+    let opt = DiagnoseScriptOpt {
+        subaccount: 0,
+        address: Some(ap.address.clone()),
+        pointer: None,
+        is_internal: false,
+    };
+    // Until the wallet syncs the confirmation
+    let diagnosis = (0..)
+        .map(|i| {
+            assert!(i < 60, "timeout waiting for the confirmation");
+            thread::sleep(Duration::from_millis(if i == 0 {
+                0
+            } else {
+                1000
+            }));
+            test_session.session.diagnose_script(&opt).unwrap()
+        })
+        .find(ScriptDiagnosis::is_consistent)
+        .unwrap();
+    assert_eq!((diagnosis.pointer, diagnosis.is_internal), (ap.pointer, false));
+    assert_eq!(diagnosis.script_hash.len(), 64);
+    assert!(diagnosis.server_status.is_some());
+    assert_eq!(diagnosis.server_status, diagnosis.store_status);
+
+    // The statuses still match, so the sync doesn't notice the missing tx
+    test_session.session.forget_tx(0, &txid).unwrap();
+    let by_pointer = serde_json::json!({"subaccount": 0, "pointer": ap.pointer});
+    let diagnosis: ScriptDiagnosis = serde_json::from_value(
+        test_session.session.handle_call("diagnose_script", by_pointer).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(diagnosis.address, ap.address);
+    assert_eq!(diagnosis.only_on_server, [txid.clone()]);
+    assert!(diagnosis.only_in_store.is_empty());
+    assert!(diagnosis.height_mismatches.is_empty());
+    assert_eq!(diagnosis.utxos_only_on_server.len(), 1);
+    assert!(diagnosis.utxos_only_on_server[0].starts_with(&format!("{}:", txid)));
+    assert!(diagnosis.utxos_only_in_store.is_empty());
+    assert_eq!(diagnosis.server_status, diagnosis.store_status);
+
+    test_session.stop();
+}
+
+#[test]
+fn test_wallet_migration() {
+    // Two wallets on the same chain
+    let mut test_session1 = TestSession::new(|_| ());
+    let mut test_session2 = TestSession::new(|_| ());
+    test_session2.node_connect(test_session1.p2p_port);
+    test_session1.node_generate(20);
+    test_session1.wait_blockheight(121);
+    test_session2.wait_blockheight(121);
+
+    // 35 utxos of wallet 1, across two subaccounts, paid 5 at a time
+    test_session1.create_subaccount(1);
+    let mut total = 0;
+    for (i, &subaccount) in [0, 0, 0, 0, 0, 0, 1].iter().enumerate() {
+        let mut amounts = serde_json::Map::new();
+        for j in 0..5 {
+            let satoshi = 10_000 + (i * 5 + j) as u64 * 100;
+            let address = test_session1.get_receive_address(subaccount).address;
+            amounts.insert(address, format!("{:.8}", satoshi as f64 / 100_000_000.0).into());
+            total += satoshi;
+        }
+        let txid: String =
+            test_session1.node.client.call("sendmany", &["".into(), amounts.into()]).unwrap();
+        test_session1.mine_block();
+        test_session1.wait_tx(vec![subaccount], &txid, None, Some(TransactionType::Incoming));
+    }
+
+    let xpub = test_session2.session.get_subaccounts().unwrap()[0].slip132_extended_pubkey.clone();
+    let opt = CreateWalletMigrationOpt {
+        destination_xpub: xpub,
+        destination_master_blinding_key: None,
+        destination_addresses: vec![],
+        fee_rate: Some(2_000),
+    };
+    let plan = test_session1.session.create_wallet_migration(&opt).unwrap();
+    assert_eq!(plan.steps.len(), 1);
+    let step = &plan.steps[0];
+    assert_eq!(step.transaction_inputs.len(), 35);
+    let subaccounts: std::collections::BTreeSet<_> =
+        step.transaction_inputs.iter().map(|u| u.subaccount).collect();
+    assert_eq!(subaccounts.into_iter().collect::<Vec<_>>(), [0, 1]);
+    assert_eq!(plan.fee, step.fee);
+    assert_eq!(step.fee, step.transaction_vsize as u64 * 2);
+    assert_eq!(step.outputs.len(), 1);
+    assert_eq!(step.outputs[0].satoshi, total - step.fee);
+    assert_eq!(step.outputs[0].address, test_session2.get_receive_address(0).address);
+
+    // A modified plan is refused
+    let mut modified = plan.clone();
+    modified.steps[0].transaction = plan.steps[0].transaction.replacen("02", "01", 1);
+    assert!(test_session1.session.execute_wallet_migration(&modified).is_err());
+
+    let result = test_session1.session.execute_wallet_migration(&plan).unwrap();
+    assert_eq!(result.skipped, 0);
+    let txid = result.txhashes[0].clone();
+    test_session2.wait_tx(vec![0], &txid, Some(total - step.fee), Some(TransactionType::Incoming));
+
+    // Executing again, as after an interruption, broadcasts nothing new
+    let again = test_session1.session.execute_wallet_migration(&plan).unwrap();
+    assert_eq!(again.txhashes, result.txhashes);
+    assert_eq!(again.skipped, 1);
+    let mempool = test_session1.node.client.get_raw_mempool().unwrap();
+    assert_eq!(mempool.iter().map(|txid| txid.to_string()).collect::<Vec<_>>(), [txid.clone()]);
+
+    // Nothing left to migrate once wallet 1 sees the transaction
+    for i in 0.. {
+        assert!(i < 60, "timeout waiting for the migration to be synced");
+        match test_session1.session.create_wallet_migration(&opt) {
+            Err(gdk_electrum::error::Error::InsufficientFunds) => break,
+            _ => thread::sleep(Duration::from_secs(1)),
+        }
+    }
+
+    test_session1.stop();
+    test_session2.stop();
+}
+
+#[test]
+fn test_error_codes() {
+    let mut test_session = TestSession::new(|_| ());
+
+    // Calls requiring the store fail before load_store
+    let mut session = ElectrumSession::new(test_session.network.clone()).unwrap();
+    let err: Value = session.handle_call("get_memos", Value::Null).unwrap_err().into();
+    assert_eq!(err["code"], "store_locked");
+    assert_eq!(err["error"], "id_store_locked");
+
+    // A server on another chain is rejected
+    let mut network = test_session.network.clone();
+    network.development = false;
+    let mut session = ElectrumSession::new(network.clone()).unwrap();
+    let err: Value =
+        session.handle_call("connect", serde_json::to_value(network).unwrap()).unwrap_err().into();
+    assert_eq!(err["code"], "server_genesis_mismatch");
+
+    // Each kind of malformed address has its own code
+    let address = test_session.node.client.getnewaddress(None, Some("bech32")).unwrap();
+    let last = if address.ends_with('q') {
+        'p'
+    } else {
+        'q'
+    };
+    let cases = [
+        (address.replacen("bcrt1q", "bcrt1Q", 1), "invalid_address_case"),
+        (format!("{}{}", &address[..address.len() - 1], last), "invalid_address_checksum"),
+        (address.replacen("bcrt1q", "bcrt1l", 1), "unknown_witness_version"),
+        (address[..30].to_string(), "invalid_witness_program_length"),
+        ("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4".to_string(), "invalid_address"),
+        ("not an address".to_string(), "invalid_address"),
+    ];
+    for (bad_address, code) in cases {
+        let input = serde_json::json!({ "address": bad_address });
+        let err: Value =
+            test_session.session.handle_call("get_address_data", input).unwrap_err().into();
+        assert_eq!(err["code"], code, "{}", bad_address);
+        assert_eq!(err["error"], "id_invalid_address");
+    }
+    let input = serde_json::json!({ "address": format!(" {} ", address.to_uppercase()) });
+    let err: Value =
+        test_session.session.handle_call("get_address_data", input).unwrap_err().into();
+    assert_eq!(err["code"], "unknown", "not a wallet address, but a valid one");
+
+    // And each kind of invalid amount
+    let cases = [
+        (serde_json::json!({ "satoshi": 2_100_000_000_000_001u64 }), "amount_overflow"),
+        (serde_json::json!({ "btc": "21000000.1" }), "amount_overflow"),
+        (serde_json::json!({ "btc": "1.123456789" }), "amount_precision_loss"),
+        (serde_json::json!({ "bits": "1e3" }), "invalid_amount"),
+        (serde_json::json!({ "btc": "1", "mbtc": "1000" }), "invalid_amount"),
+    ];
+    for (input, code) in cases {
+        let err: Value =
+            test_session.session.handle_call("convert_amount", input.clone()).unwrap_err().into();
+        assert_eq!(err["code"], code, "{}", input);
+        assert_eq!(err["error"], "id_invalid_amount");
+    }
+
+    test_session.stop();
+}
+
+#[test]
+fn test_parse_uri_send_to_self() {
+    let mut test_session = TestSession::new(|_| ());
+
+    let parse_uri = |session: &mut ElectrumSession, address: &str| {
+        let input = serde_json::json!({ "uri": format!("bitcoin:{}", address) });
+        session.handle_call("parse_uri", input).map_err(Value::from)
+    };
+    let own = test_session.get_receive_address(0).address;
+    let parsed = parse_uri(&mut test_session.session, &own).unwrap();
+    assert_eq!(parsed["send_to_self"], true);
+    let other = test_session.node.client.getnewaddress(None, None).unwrap();
+    let parsed = parse_uri(&mut test_session.session, &other).unwrap();
+    assert_eq!(parsed["send_to_self"], false);
+
+    // Addresses of other networks are rejected as such
+    for address in
+        ["bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4", "1BvBMSEYstWetqTFn5Au4m5GFg7xJaNVN2"]
+    {
+        let err = parse_uri(&mut test_session.session, address).unwrap_err();
+        assert_eq!(err["code"], "invalid_address", "{}", address);
+        assert_eq!(err["network_mismatch"], true, "{}", address);
+    }
+
+    test_session.stop();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_rpc_server() {
+    use gdk_electrum::rpc::{RpcEndpoint, RpcServer};
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+    use std::sync::{Arc, Mutex};
+
+    let mut test_session = TestSession::new(|_| ());
+    test_session.fund(100_000);
+
+    // The server needs a shared session, put it back in test_session at the end
+    let placeholder = ElectrumSession::new(test_session.network.clone()).unwrap();
+    let session = Arc::new(Mutex::new(std::mem::replace(&mut test_session.session, placeholder)));
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("gdk.sock");
+    let mut server =
+        RpcServer::start(session.clone(), RpcEndpoint::Unix(path.clone()), "secret").unwrap();
+
+    let stream = UnixStream::connect(&path).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(60))).unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut read = || {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        serde_json::from_str::<Value>(&line).unwrap()
+    };
+    let mut send = |id: u32, method: &str, params: Value| {
+        let request =
+            serde_json::json!({"jsonrpc": "2.0", "method": method, "params": params, "id": id});
+        writer.write_all(format!("{}\n", request).as_bytes()).unwrap();
+    };
+
+    let balance_opt = serde_json::json!({"subaccount": 0, "num_confs": 0});
+    send(1, "get_balance", balance_opt.clone());
+    assert_eq!(read()["error"]["code"], -32001);
+    send(2, "auth", serde_json::json!({"token": "wrong"}));
+    assert_eq!(read()["error"]["code"], -32001);
+    send(3, "auth", serde_json::json!({"token": "secret"}));
+    assert_eq!(read(), serde_json::json!({"jsonrpc": "2.0", "result": true, "id": 3}));
+
+    send(4, "get_balance", balance_opt);
+    let response = read();
+    assert_eq!(response["id"], 4);
+    assert_eq!(response["result"]["btc"], 100_000);
+
+    send(5, "get_balance", serde_json::json!({"subaccount": 99, "num_confs": 0}));
+    let response = read();
+    assert_eq!(response["error"]["code"], -32000);
+    assert_eq!(response["error"]["data"]["code"], "unknown");
+
+    send(6, "subscribe", serde_json::json!({"events": ["block"]}));
+    let mut subscribed = false;
+    let height = test_session.electrs_tip() as u32 + 1;
+    test_session.node_generate(1);
+    loop {
+        let message = read();
+        if message["id"] == 6 {
+            assert!(message["result"].is_u64());
+            subscribed = true;
+            continue;
+        }
+        assert_eq!(message["method"], "notification");
+        assert_eq!(message["params"]["event"], "block");
+        if message["params"]["block"]["block_height"] == height {
+            break;
+        }
+    }
+    assert!(subscribed);
+
+    server.stop();
+    test_session.session = Arc::try_unwrap(session).ok().unwrap().into_inner().unwrap();
+    test_session.stop();
+}
+
+#[test]
+fn test_multi_session() {
+    let mut bitcoin = TestSession::new(|_| ());
+    let mut liquid = TestSession::builder().chain(Chain::Liquid).spawn();
+    let policy_asset = liquid.network.policy_asset.clone().unwrap();
+
+    // Another wallet on both chains, its stores next to the ones of the harnesses
+    let mut multi = MultiSession::new([
+        ("bitcoin".to_string(), bitcoin.network.clone()),
+        ("liquid".to_string(), liquid.network.clone()),
+    ])
+    .unwrap();
+    assert_eq!(multi.networks().collect::<Vec<_>>(), vec!["bitcoin", "liquid"]);
+
+    // The notifications of both sessions reach the shared queue
+    let networks = Arc::new(AtomicUsize::new(0));
+    let received = Arc::clone(&networks);
+    multi.subscribe(&[NotificationKind::Network], move |_| {
+        received.fetch_add(1, Ordering::Relaxed);
+    });
+    multi.connect().unwrap();
+    assert_eq!(networks.load(Ordering::Relaxed), 2);
+
+    let credentials = Credentials {
+        mnemonic: "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about".to_string(),
+        bip39_passphrase: "".to_string(),
+    };
+    let logins = multi.login(&credentials).unwrap();
+    assert_eq!(logins["bitcoin"].master_xpub_fingerprint, logins["liquid"].master_xpub_fingerprint);
+    assert!(logins["bitcoin"].master_blinding_key.is_none());
+    assert!(logins["liquid"].master_blinding_key.is_some());
+
+    let address = |network: &str| {
+        let opt = GetAddressOpt::default();
+        multi.session(network).unwrap().get_receive_address(&opt).unwrap().address
+    };
+    let btc_address = address("bitcoin");
+    bitcoin.node.client.sendtoaddress(&btc_address, 100_000, None).unwrap();
+    liquid.node.client.sendtoaddress(&address("liquid"), 200_000, None).unwrap();
+
+    let wait_balances = |multi: &MultiSession, btc: i64, lbtc: i64| {
+        for i in 0.. {
+            assert!(i < 60, "timeout waiting for the balances {} and {}", btc, lbtc);
+            let balances = multi.get_balance(&GetBalanceOpt::default()).unwrap();
+            if balances["bitcoin"]["btc"] == btc && balances["liquid"][&policy_asset] == lbtc {
+                break;
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+    };
+    wait_balances(&multi, 100_000, 200_000);
+
+    let mut txs_opt = GetTransactionsOpt::default();
+    txs_opt.count = 10;
+    let txs = multi.get_transactions(&txs_opt).unwrap();
+    assert_eq!(txs.total, 2);
+    let mut received: Vec<_> = txs
+        .transactions
+        .iter()
+        .map(|item| (item.network.as_str(), item.tx.satoshi.values().sum::<i64>()))
+        .collect();
+    received.sort();
+    assert_eq!(received, vec![("bitcoin", 100_000), ("liquid", 200_000)]);
+    let json = serde_json::to_value(&txs.transactions[0]).unwrap();
+    assert!(json["network"].is_string() && json["txhash"].is_string());
+
+    txs_opt.first = 1;
+    txs_opt.count = 1;
+    let page = multi.get_transactions(&txs_opt).unwrap();
+    assert_eq!(page.total, 2);
+    assert_eq!(page.transactions.len(), 1);
+    assert_eq!(page.transactions[0].tx.txhash, txs.transactions[1].tx.txhash);
+
+    // Calls are routed to the session of the network
+    let balance = multi.handle_call("liquid", "get_balance", serde_json::json!({})).unwrap();
+    assert_eq!(balance[&policy_asset], 200_000);
+    let err: Value =
+        multi.handle_call("testnet", "get_balance", serde_json::json!({})).unwrap_err().into();
+    assert_eq!(err["code"], "unknown");
+
+    // A bitcoin transaction paying the wallet, only the bitcoin session sends it
+    bitcoin.fund(100_000);
+    let btc_address =
+        btc_address.parse::<gdk_common::bitcoin::Address<_>>().unwrap().assume_checked();
+    let opt = SendTransactionOpt {
+        transaction: bitcoin.create_tx(&btc_address, 50_000, 1_000),
+        confirmation_token: None,
+        payjoin: None,
+        client_request_id: None,
+        check_acceptance: false,
+    };
+    assert!(multi.send_transaction("liquid", &opt).is_err());
+    multi.send_transaction("bitcoin", &opt).unwrap();
+    wait_balances(&multi, 150_000, 200_000);
+
+    // Both sessions are logged out together
+    multi.logout().unwrap();
+    assert!(matches!(
+        multi.session_mut("liquid").unwrap().stop(),
+        Err(gdk_electrum::error::Error::SessionClosed)
+    ));
+
+    bitcoin.stop();
+    liquid.stop();
+}
+
 fn setup_forking_sessions(enable_session_cross: bool) -> (TestSession, TestSession) {
     let test_session2 = TestSession::new(|_| ());
 
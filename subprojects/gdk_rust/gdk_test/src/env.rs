@@ -9,3 +9,10 @@ pub(crate) const BITCOIND_EXEC: Lazy<String> = Lazy::new(|| env::var("BITCOIND_E
 
 /// Environment variable pointing to the `electrs` executable.
 pub(crate) const ELECTRS_EXEC: Lazy<String> = Lazy::new(|| env::var("ELECTRS_EXEC").unwrap());
+
+/// Environment variable pointing to the `elementsd` executable.
+pub(crate) const ELEMENTSD_EXEC: Lazy<String> = Lazy::new(|| env::var("ELEMENTSD_EXEC").unwrap());
+
+/// Environment variable pointing to the Liquid build of the `electrs` executable.
+pub(crate) const ELECTRS_LIQUID_EXEC: Lazy<String> =
+    Lazy::new(|| env::var("ELECTRS_LIQUID_EXEC").unwrap());
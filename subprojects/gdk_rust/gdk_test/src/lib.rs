@@ -1,9 +1,20 @@
+//! A harness to test against gdk: [`TestSession`] spawns nodes and an
+//! electrum server from the executables in the environment, with a wallet
+//! connected to them, and has helpers to fund the wallet, mine blocks and
+//! wait for the session to see them.
+
 mod env;
 mod error;
+mod log_recorder;
+mod process_signer;
 mod rpc_node_ext;
+mod span_recorder;
 mod test_session;
 pub mod utils;
 
 pub use error::{Error, Result};
+pub use log_recorder::{init_logger, LogRecorder};
+pub use process_signer::{serve_signer_process, ProcessSigner, SIGNER_PROCESS_TEST};
 pub use rpc_node_ext::RpcNodeExt;
-pub use test_session::TestSession;
+pub use span_recorder::{RecordedSpan, SpanRecorder};
+pub use test_session::{Chain, TestSession, TestSessionBuilder};
@@ -0,0 +1,75 @@
+//! A logger recording the messages logged, as the library loggers write
+//! them, to check what the session logs.
+
+use std::sync::{Arc, Mutex, Once};
+
+use gdk_common::log::{self, LevelFilter, Log, Metadata, Record};
+use gdk_common::redact;
+
+static INIT: Once = Once::new();
+
+/// The recorders started and not dropped yet
+static RECORDERS: Mutex<Vec<Arc<Mutex<Vec<String>>>>> = Mutex::new(Vec::new());
+
+struct Logger(env_logger::Logger);
+
+impl Log for Logger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        if self.0.matches(record) {
+            self.0.log(record);
+        }
+        let recorders = RECORDERS.lock().unwrap();
+        if !recorders.is_empty() {
+            let msg = redact::log_message(record.args());
+            for recorder in recorders.iter() {
+                recorder.lock().unwrap().push(msg.clone());
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
+/// Install the logger of the tests, printing the messages as configured by
+/// `RUST_LOG` and passing them to the started [`LogRecorder`]s
+pub fn init_logger() {
+    INIT.call_once(|| {
+        let logger = env_logger::Builder::from_default_env().build();
+        log::set_boxed_logger(Box::new(Logger(logger)))
+            .map(|()| log::set_max_level(LevelFilter::Debug))
+            .expect("cannot initialize logging");
+    });
+}
+
+/// Records the messages logged by any thread until dropped
+pub struct LogRecorder {
+    messages: Arc<Mutex<Vec<String>>>,
+}
+
+impl LogRecorder {
+    pub fn start() -> Self {
+        init_logger();
+        let messages = Arc::new(Mutex::new(vec![]));
+        RECORDERS.lock().unwrap().push(messages.clone());
+        LogRecorder {
+            messages,
+        }
+    }
+
+    /// The messages recorded so far, in order
+    pub fn messages(&self) -> Vec<String> {
+        self.messages.lock().unwrap().clone()
+    }
+}
+
+impl Drop for LogRecorder {
+    fn drop(&mut self) {
+        RECORDERS.lock().unwrap().retain(|recorder| !Arc::ptr_eq(recorder, &self.messages));
+    }
+}
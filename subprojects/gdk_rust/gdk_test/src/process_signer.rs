@@ -0,0 +1,221 @@
+//! A [`Signer`] running in another process, talking over its stdin and
+//! stdout, showing the session gets everything it needs from the signer.
+//!
+//! The child process is the test binary itself running the test named
+//! [`SIGNER_PROCESS_TEST`], which must call [`serve_signer_process`].
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::Mutex;
+
+use bip39::Mnemonic;
+use gdk_common::be::{BETransaction, BETxOut};
+use gdk_common::bitcoin::bip32::{DerivationPath, Xpub};
+use gdk_common::bitcoin::NetworkKind;
+use gdk_common::model::MessageFormat;
+use gdk_common::scripts::ScriptType;
+use gdk_common::util::MasterBlindingKey;
+use gdk_common::NetworkId;
+use gdk_electrum::error::Error;
+use gdk_electrum::signer::{Signer, SoftwareSigner};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// The name of the test serving the signer in the child process
+pub const SIGNER_PROCESS_TEST: &str = "signer_process";
+
+/// Environment variable with the mnemonic of the child process signer
+const MNEMONIC_ENV: &str = "GDK_TEST_SIGNER_MNEMONIC";
+
+/// Environment variable set to use mainnet keys in the child process signer
+const MAINNET_ENV: &str = "GDK_TEST_SIGNER_MAINNET";
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Request {
+    GetXpub {
+        path: DerivationPath,
+    },
+    SignTx {
+        tx: BETransaction,
+        prevouts: Vec<Option<BETxOut>>,
+        paths: Vec<Option<DerivationPath>>,
+        sighash_types: Vec<Option<u32>>,
+    },
+    SignMessage {
+        path: DerivationPath,
+        script_type: ScriptType,
+        message: String,
+        format: MessageFormat,
+    },
+    GetMasterBlindingKey,
+    DisplayAddress {
+        path: DerivationPath,
+        script_type: ScriptType,
+        network: NetworkId,
+    },
+}
+
+pub struct ProcessSigner {
+    child: Mutex<(Child, ChildStdin, BufReader<ChildStdout>)>,
+}
+
+impl ProcessSigner {
+    /// Spawn the signer of the wallet of `mnemonic` in a child process
+    pub fn spawn(mnemonic: &str, network: NetworkKind) -> Self {
+        let mut command = Command::new(std::env::current_exe().unwrap());
+        command
+            .args([SIGNER_PROCESS_TEST, "--nocapture", "--test-threads=1", "-q"])
+            .env(MNEMONIC_ENV, mnemonic)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped());
+        if network == NetworkKind::Main {
+            command.env(MAINNET_ENV, "1");
+        }
+        let mut child = command.spawn().unwrap();
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        ProcessSigner {
+            child: Mutex::new((child, stdin, stdout)),
+        }
+    }
+
+    fn call<T: DeserializeOwned>(&self, request: Request) -> Result<T, Error> {
+        let mut child = self.child.lock().unwrap();
+        let (_, stdin, stdout) = &mut *child;
+        writeln!(stdin, "{}", serde_json::to_string(&request)?)?;
+        stdin.flush()?;
+        loop {
+            let mut line = String::new();
+            if stdout.read_line(&mut line)? == 0 {
+                return Err(Error::Generic("signer process exited".into()));
+            }
+            // Skip the output of the test harness of the child
+            if !line.starts_with('{') {
+                continue;
+            }
+            let response: std::result::Result<Value, String> = serde_json::from_str(&line)?;
+            return Ok(serde_json::from_value(response.map_err(Error::Generic)?)?);
+        }
+    }
+}
+
+impl Drop for ProcessSigner {
+    fn drop(&mut self) {
+        if let Ok(child) = self.child.get_mut() {
+            let _ = child.0.kill();
+            let _ = child.0.wait();
+        }
+    }
+}
+
+impl Signer for ProcessSigner {
+    fn get_xpub(&self, path: &DerivationPath) -> Result<Xpub, Error> {
+        self.call(Request::GetXpub {
+            path: path.clone(),
+        })
+    }
+
+    fn sign_tx(
+        &self,
+        tx: &BETransaction,
+        prevouts: &[Option<BETxOut>],
+        paths: &[Option<DerivationPath>],
+        sighash_types: &[Option<u32>],
+    ) -> Result<Vec<Option<Vec<u8>>>, Error> {
+        self.call(Request::SignTx {
+            tx: tx.clone(),
+            prevouts: prevouts.to_vec(),
+            paths: paths.to_vec(),
+            sighash_types: sighash_types.to_vec(),
+        })
+    }
+
+    fn sign_message(
+        &self,
+        path: &DerivationPath,
+        script_type: ScriptType,
+        message: &str,
+        format: MessageFormat,
+    ) -> Result<String, Error> {
+        self.call(Request::SignMessage {
+            path: path.clone(),
+            script_type,
+            message: message.to_string(),
+            format,
+        })
+    }
+
+    fn get_master_blinding_key(&self) -> Result<MasterBlindingKey, Error> {
+        self.call(Request::GetMasterBlindingKey)
+    }
+
+    fn display_address(
+        &self,
+        path: &DerivationPath,
+        script_type: ScriptType,
+        network: NetworkId,
+    ) -> Result<String, Error> {
+        self.call(Request::DisplayAddress {
+            path: path.clone(),
+            script_type,
+            network,
+        })
+    }
+}
+
+/// Serve the requests of a [`ProcessSigner`] if running as its child
+/// process, do nothing otherwise
+pub fn serve_signer_process() {
+    let mnemonic = match std::env::var(MNEMONIC_ENV) {
+        Ok(mnemonic) => mnemonic,
+        Err(_) => return,
+    };
+    let network = match std::env::var(MAINNET_ENV) {
+        Ok(_) => NetworkKind::Main,
+        Err(_) => NetworkKind::Test,
+    };
+    let seed = Mnemonic::parse(&mnemonic).unwrap().to_seed("");
+    let signer = SoftwareSigner::from_seed(&seed, network).unwrap();
+
+    let stdout = std::io::stdout();
+    for line in std::io::stdin().lock().lines() {
+        let request: Request = serde_json::from_str(&line.unwrap()).unwrap();
+        let response = match request {
+            Request::GetXpub {
+                path,
+            } => signer.get_xpub(&path).map(|xpub| serde_json::to_value(xpub).unwrap()),
+            Request::SignTx {
+                tx,
+                prevouts,
+                paths,
+                sighash_types,
+            } => signer
+                .sign_tx(&tx, &prevouts, &paths, &sighash_types)
+                .map(|signatures| serde_json::to_value(signatures).unwrap()),
+            Request::SignMessage {
+                path,
+                script_type,
+                message,
+                format,
+            } => signer
+                .sign_message(&path, script_type, &message, format)
+                .map(|signature| serde_json::to_value(signature).unwrap()),
+            Request::GetMasterBlindingKey => {
+                signer.get_master_blinding_key().map(|key| serde_json::to_value(key).unwrap())
+            }
+            Request::DisplayAddress {
+                path,
+                script_type,
+                network,
+            } => signer
+                .display_address(&path, script_type, network)
+                .map(|address| serde_json::to_value(address).unwrap()),
+        };
+        let response = response.map_err(|e| e.to_string());
+        let mut stdout = stdout.lock();
+        writeln!(stdout, "{}", serde_json::to_string(&response).unwrap()).unwrap();
+        stdout.flush().unwrap();
+    }
+}
@@ -6,6 +6,10 @@ use serde_json::{Map, Value};
 
 use crate::Result;
 
+/// Nonces tried by [`RpcNodeExt::generate`] over all the blocks of a call, a signet block at the
+/// minimum difficulty takes around 5 million tries while the node default is 1 million.
+const GENERATE_MAX_TRIES: u32 = 100_000_000;
+
 /// Extension trait for [`bitcoin_rpc::client::Client`] providing a more
 /// ergonomic API.
 pub trait RpcNodeExt {
@@ -15,7 +19,8 @@ pub trait RpcNodeExt {
     ///
     /// * `nblocks` - How many blocks are generated immediately
     /// * `address` - The address to send the newly generated bitcoin to (default = `RpcNodeExt::getnewaddress`)
-    // /// * `maxtries` - How many iterations to try (default = `1000000`)
+    ///
+    /// The node tries up to 100 million nonces, enough for a few signet blocks.
     fn generate(&self, nblocks: u32, address: Option<&str>) -> Result<Vec<String>>;
 
     /// Returns a new Bitcoin address for receiving payments.
@@ -50,7 +55,7 @@ macro_rules! values {
 impl RpcNodeExt for Client {
     fn generate(&self, nblocks: u32, address: Option<&str>) -> Result<Vec<String>> {
         let address = address.unwrap_or(&*self.getnewaddress(None, None)?).to_owned();
-        let params = values!(nblocks, address);
+        let params = values!(nblocks, address, GENERATE_MAX_TRIES);
         let block_hashes = self.call("generatetoaddress", &params)?;
         if nblocks < 10 {
             log::info!("generate result {:?}", block_hashes);
@@ -0,0 +1,77 @@
+//! A tracing subscriber recording the spans created, to check what the
+//! session traces.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use gdk_common::tracing::field::{Field, Visit};
+use gdk_common::tracing::span::{Attributes, Id, Record};
+use gdk_common::tracing::{Event, Metadata, Subscriber};
+
+/// A span recorded by a [`SpanRecorder`]
+#[derive(Clone, Debug)]
+pub struct RecordedSpan {
+    pub name: &'static str,
+
+    /// The fields recorded so far, strings as they are and other values
+    /// formatted with `Debug`
+    pub fields: HashMap<&'static str, String>,
+}
+
+/// Records the spans created while it's the subscriber, usually set for the
+/// calling thread with `tracing::subscriber::with_default`
+#[derive(Clone, Default)]
+pub struct SpanRecorder {
+    spans: Arc<Mutex<Vec<RecordedSpan>>>,
+}
+
+impl SpanRecorder {
+    /// The spans recorded named `name`, in order of creation
+    pub fn spans(&self, name: &str) -> Vec<RecordedSpan> {
+        self.spans.lock().unwrap().iter().filter(|span| span.name == name).cloned().collect()
+    }
+}
+
+struct FieldsVisitor<'a>(&'a mut HashMap<&'static str, String>);
+
+impl Visit for FieldsVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name(), format!("{:?}", value));
+    }
+}
+
+impl Subscriber for SpanRecorder {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let mut fields = HashMap::new();
+        span.record(&mut FieldsVisitor(&mut fields));
+        let mut spans = self.spans.lock().unwrap();
+        spans.push(RecordedSpan {
+            name: span.metadata().name(),
+            fields,
+        });
+        Id::from_u64(spans.len() as u64)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut spans = self.spans.lock().unwrap();
+        let span = &mut spans[span.into_u64() as usize - 1];
+        values.record(&mut FieldsVisitor(&mut span.fields));
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, _event: &Event<'_>) {}
+
+    fn enter(&self, _span: &Id) {}
+
+    fn exit(&self, _span: &Id) {}
+}
@@ -1,84 +1,288 @@
 use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use bip39::Mnemonic;
 use electrsd::bitcoind::bitcoincore_rpc::RpcApi;
 use electrsd::electrum_client::ElectrumApi;
-use gdk_common::bitcoin::bip32::{ChildNumber, DerivationPath, Xpriv, Xpub};
+use gdk_common::bitcoin::bip32::{Xpriv, Xpub};
+use gdk_common::bitcoin::hashes::Hash;
 use gdk_common::log::{info, warn};
 use gdk_common::rand::Rng;
 use gdk_common::util;
 use gdk_common::{bitcoin, rand};
-use serde_json::{json, Value};
+use serde_json::Value;
 use tempfile::TempDir;
 
 use gdk_common::be::*;
 use gdk_common::model::*;
 use gdk_common::session::Session;
 use gdk_common::{NetworkId, NetworkParameters, State};
+use gdk_electrum::account::get_account_derivation;
+use gdk_electrum::interface::ElectrumUrl;
+use gdk_electrum::signer::{Signer, SoftwareSigner};
 use gdk_electrum::spv;
-use gdk_electrum::{ElectrumSession, TransactionNotification};
+use gdk_electrum::{BlockNotification, ElectrumSession, TransactionNotification};
 
-use crate::RpcNodeExt;
 use crate::{env, utils};
+use crate::{ProcessSigner, RpcNodeExt};
+
+/// How long the waits of the helpers not taking a timeout last
+const WAIT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The policy asset of the Elements regtest chain spawned for [`Chain::Liquid`]
+const LIQUID_REGTEST_POLICY_ASSET: &str =
+    "5ac9f65c0efcc4775e0baec4ec03abdde22473cd3cf33c0419ca290e0751b225";
+
+/// The chain of the nodes spawned by a [`TestSessionBuilder`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chain {
+    /// Bitcoin regtest, or a custom signet if the network parameters set
+    /// signet, with `bitcoind` and `electrs` from `BITCOIND_EXEC` and
+    /// `ELECTRS_EXEC`
+    Bitcoin,
+
+    /// Elements regtest, with `elementsd` and the Liquid build of `electrs`
+    /// from `ELEMENTSD_EXEC` and `ELECTRS_LIQUID_EXEC`
+    Liquid,
+}
+
+/// The notifications of a session, received through a subscription
+#[derive(Default)]
+struct Notifications {
+    /// As JSON, in the format of `filter_events`
+    received: Mutex<Vec<Value>>,
+    cond: Condvar,
+}
+
+impl Notifications {
+    fn push(&self, ntf: &gdk_common::model::Notification) {
+        self.received.lock().unwrap().push(gdk_common::notification::to_json(ntf));
+        self.cond.notify_all();
+    }
+
+    /// The notifications received with `event`
+    fn filter_events(&self, event: &str) -> Vec<Value> {
+        let received = self.received.lock().unwrap();
+        received.iter().filter(|ntf| ntf["event"] == event).cloned().collect()
+    }
+
+    /// Return as soon as `done`, checked now and again at every notification,
+    /// panicking with `what` after `timeout`
+    fn wait_until<F: FnMut() -> bool>(&self, what: &str, timeout: Duration, mut done: F) {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let seen = self.received.lock().unwrap().len();
+            if done() {
+                return;
+            }
+            let mut received = self.received.lock().unwrap();
+            while received.len() == seen {
+                let now = Instant::now();
+                assert!(now < deadline, "timeout after {:?} waiting for {}", timeout, what);
+                received = self.cond.wait_timeout(received, deadline - now).unwrap().0;
+            }
+        }
+    }
+}
+
+/// Configures and spawns a [`TestSession`]
+pub struct TestSessionBuilder<'a> {
+    chain: Chain,
+    nodes: usize,
+    process_signer: bool,
+    network_confs: Vec<Box<dyn FnOnce(&mut NetworkParameters) + 'a>>,
+}
+
+impl<'a> TestSessionBuilder<'a> {
+    /// The chain of the nodes, bitcoin by default
+    pub fn chain(mut self, chain: Chain) -> Self {
+        self.chain = chain;
+        self
+    }
+
+    /// The number of nodes, one by default, the others are peers of the one
+    /// the server indexes, see [`TestSession::peers`]
+    pub fn nodes(mut self, nodes: usize) -> Self {
+        assert!(nodes > 0, "at least one node is needed");
+        self.nodes = nodes;
+        self
+    }
 
+    /// Hold the keys of the wallet in a [`ProcessSigner`] instead of the test
+    /// process
+    pub fn process_signer(mut self, process_signer: bool) -> Self {
+        self.process_signer = process_signer;
+        self
+    }
+
+    /// Override the network parameters of the session, after the defaults of
+    /// the chain are set, the overrides are applied in order
+    pub fn network<F>(mut self, network_conf: F) -> Self
+    where
+        F: FnOnce(&mut NetworkParameters) + 'a,
+    {
+        self.network_confs.push(Box::new(network_conf));
+        self
+    }
+
+    /// Spawn the nodes and the server, mine 101 blocks and connect a new
+    /// wallet to them
+    pub fn spawn(self) -> TestSession {
+        TestSession::spawn(self)
+    }
+}
+
+/// Nodes, an electrum server and a wallet connected to them, to test against
+/// gdk
+///
+/// ```no_run
+/// use gdk_common::model::GetBalanceOpt;
+/// use gdk_test::{Chain, TestSession};
+///
+/// let mut test_session = TestSession::builder()
+///     .chain(Chain::Bitcoin)
+///     .network(|network| network.gap_limit = Some(10))
+///     .spawn();
+/// test_session.fund(100_000);
+/// let balance = test_session.session.get_balance(&GetBalanceOpt::default()).unwrap();
+/// assert_eq!(balance["btc"], 100_000);
+/// test_session.stop();
+/// ```
 #[allow(unused)]
 pub struct TestSession {
     pub node: electrsd::bitcoind::BitcoinD,
     pub electrs: electrsd::ElectrsD,
+
+    /// The other nodes spawned, peers of `node`
+    pub peers: Vec<electrsd::bitcoind::BitcoinD>,
+    pub chain: Chain,
     pub session: ElectrumSession,
     pub credentials: Credentials,
     tx_status: u64,
-    block_status: (u32, BEBlockHash),
+    block_status: BlockStatus,
     state_dir: TempDir,
     network_id: NetworkId,
     pub network: NetworkParameters,
     pub p2p_port: u16,
+    notifications: Arc<Notifications>,
+
+    /// The keys are held by a [`ProcessSigner`], the transactions are signed
+    /// through the session
+    process_signer: bool,
 }
 
 impl TestSession {
+    /// Spawn a node and an electrs server and connect a new wallet to them
+    ///
+    /// The node runs a custom signet if `network_conf` sets a signet network, regtest otherwise
     pub fn new<F>(network_conf: F) -> Self
     where
         F: FnOnce(&mut NetworkParameters),
     {
-        let node = env::BITCOIND_EXEC;
-        let electrs = env::ELECTRS_EXEC;
+        Self::builder().network(network_conf).spawn()
+    }
+
+    /// Like [`Self::new`], with the keys of the wallet held by a [`ProcessSigner`]
+    pub fn new_with_process_signer<F>(network_conf: F) -> Self
+    where
+        F: FnOnce(&mut NetworkParameters),
+    {
+        Self::builder().network(network_conf).process_signer(true).spawn()
+    }
+
+    /// A builder of a test session with a single bitcoin regtest node
+    pub fn builder<'a>() -> TestSessionBuilder<'a> {
+        TestSessionBuilder {
+            chain: Chain::Bitcoin,
+            nodes: 1,
+            process_signer: false,
+            network_confs: vec![],
+        }
+    }
+
+    fn spawn(builder: TestSessionBuilder) -> Self {
+        let (node_exec, electrs_exec) = match builder.chain {
+            Chain::Bitcoin => (env::BITCOIND_EXEC, env::ELECTRS_EXEC),
+            Chain::Liquid => (env::ELEMENTSD_EXEC, env::ELECTRS_LIQUID_EXEC),
+        };
 
         let is_debug = std::env::var("DEBUG").is_ok();
 
-        let _ = env_logger::try_init();
+        crate::init_logger();
 
-        let args = vec!["-fallbackfee=0.0001", "-dustrelayfee=0.00000001", "-regtest"];
-        let network = "regtest";
+        let mut network = NetworkParameters::default();
+        network.sync_interval = Some(1);
+        network.development = true;
+        network.spv_enabled = Some(true);
+        network.set_asset_registry_url("https://assets.blockstream.info".to_string());
+        if builder.chain == Chain::Liquid {
+            network.liquid = true;
+            network.policy_asset = Some(LIQUID_REGTEST_POLICY_ASSET.to_string());
+        }
+
+        for network_conf in builder.network_confs {
+            network_conf(&mut network);
+        }
+
+        let network_id = network.id();
+        let mut args = vec!["-fallbackfee=0.0001", "-dustrelayfee=0.00000001"];
+        let chain = match network_id {
+            NetworkId::Bitcoin(bitcoin::Network::Signet) => {
+                // a custom signet whose blocks anyone can mine, with the signet proof of work
+                args.extend(["-signet", "-signetchallenge=51"]);
+                "signet"
+            }
+            NetworkId::Elements(_) => {
+                // the initial free coins fund the node wallet, no peg-in to validate
+                args.extend([
+                    "-chain=liquidregtest",
+                    "-initialfreecoins=2100000000000000",
+                    "-validatepegin=0",
+                ]);
+                "liquidregtest"
+            }
+            _ => {
+                args.push("-regtest");
+                "regtest"
+            }
+        };
         let mut conf = electrsd::bitcoind::Conf::default();
         conf.args = args;
         conf.view_stdout = is_debug;
         conf.p2p = electrsd::bitcoind::P2P::Yes;
-        conf.network = network;
+        conf.network = chain;
 
-        let node = electrsd::bitcoind::BitcoinD::with_conf(&*node, &conf).unwrap();
+        let node = electrsd::bitcoind::BitcoinD::with_conf(&*node_exec, &conf).unwrap();
         info!("node spawned");
 
-        RpcNodeExt::generate(&node.client, 1, None).unwrap();
+        if builder.chain == Chain::Liquid {
+            // the wallet sees the initial free coins after a rescan
+            node.client.call::<Value>("rescanblockchain", &[]).unwrap();
+        }
 
-        let p2p_port = node.params.p2p_socket.unwrap().port();
+        let peers: Vec<_> = (1..builder.nodes)
+            .map(|_| {
+                let mut conf = conf.clone();
+                conf.p2p = node.p2p_connect(true).unwrap();
+                electrsd::bitcoind::BitcoinD::with_conf(&*node_exec, &conf).unwrap()
+            })
+            .collect();
+        info!("{} peers spawned", peers.len());
 
-        let mut args = vec![];
-        if is_debug {
-            args.push("-v");
-        }
+        RpcNodeExt::generate(&node.client, 1, None).unwrap();
 
-        let mut conf = electrsd::Conf::default();
-        conf.args = args;
-        conf.view_stderr = is_debug;
-        conf.http_enabled = false;
-        conf.network = network;
+        let p2p_port = node.params.p2p_socket.unwrap().port();
 
-        let electrs = electrsd::ElectrsD::with_conf(&*electrs, &node, &conf).unwrap();
+        let electrs = spawn_electrs(&electrs_exec, &node, chain, is_debug);
         info!("Electrs spawned");
 
-        let mut hashes = RpcNodeExt::generate(&node.client, 100, None).unwrap();
+        // mine one block per call, signet blocks take a while and the rpc could time out
+        let mut hashes = vec![];
+        for _ in 0..100 {
+            hashes.extend(RpcNodeExt::generate(&node.client, 1, None).unwrap());
+        }
         electrs.trigger().unwrap();
 
         let mut i = 60;
@@ -95,14 +299,7 @@ impl TestSession {
         }
         info!("Electrs synced with node");
 
-        let mut network = NetworkParameters::default();
         network.electrum_url = Some(electrs.electrum_url.clone());
-        network.sync_interval = Some(1);
-        network.development = true;
-        network.spv_enabled = Some(true);
-        network.set_asset_registry_url("https://assets.blockstream.info".to_string());
-
-        network_conf(&mut network);
 
         let state_dir = TempDir::new().unwrap();
 
@@ -119,6 +316,10 @@ impl TestSession {
         );
         assert_eq!(session.filter_events("network").len(), ntf_len + 1);
 
+        let notifications = Arc::new(Notifications::default());
+        let received = notifications.clone();
+        session.subscribe(&[], move |ntf| received.push(ntf));
+
         let mut entropy = [0u8; 32];
         rand::thread_rng().fill(&mut entropy);
         let mnemonic_str = Mnemonic::from_entropy(&entropy).unwrap().to_string();
@@ -128,68 +329,34 @@ impl TestSession {
             bip39_passphrase: "".to_string(),
         };
         info!("logging in gdk session");
-        let (master_xprv, master_xpub, _master_blinding_key) =
-            keys_from_credentials(&credentials, network.bip32_network());
-
-        let opt = LoadStoreOpt {
-            master_xpub: Some(master_xpub),
-            master_xpub_fingerprint: Some(master_xpub.fingerprint()),
-            filename: None,
-            encryption_key_hex: None,
+        let signer: Arc<dyn Signer> = if builder.process_signer {
+            Arc::new(ProcessSigner::spawn(&credentials.mnemonic, network.bip32_network()))
+        } else {
+            let seed = Mnemonic::parse(&credentials.mnemonic).unwrap().to_seed("");
+            Arc::new(SoftwareSigner::from_seed(&seed, network.bip32_network()).unwrap())
         };
-        session.load_store(&opt).unwrap();
-
-        let account_nums = session.get_subaccount_nums().unwrap();
+        let login_data = session.login_with_signer(signer).unwrap();
+        let account_nums: Vec<_> = login_data.xpubs.iter().map(|x| x.account_num).collect();
         assert_eq!(account_nums, vec![0]);
 
-        // Create subaccount 0
-        let path: DerivationPath = "84'/1'/0'".parse().unwrap();
-        let path: Vec<ChildNumber> = path.into();
-        let xprv = master_xprv.derive_priv(&gdk_common::EC, &path).unwrap();
-        let xpub = Xpub::from_priv(&gdk_common::EC, &xprv);
-        let opt = CreateAccountOpt {
-            subaccount: 0,
-            name: "".to_string(),
-            xpub: xpub,
-            discovered: false,
-            is_already_created: true,
-            allow_gaps: false,
-        };
-        session.create_subaccount(opt).unwrap();
-
-        session.start_threads().unwrap();
-
         assert!(session.filter_events("settings").last().is_some());
 
         assert_eq!(network.name, ""); // network name contributes to wallet hash id
 
         let tx_status = session.tx_status().unwrap();
         assert_eq!(tx_status, 15130871412783076140);
-        let mut i = 60;
-        let block_status = loop {
-            assert!(i > 0, "timeout waiting for updates");
-            i -= 1;
-            let block_status = session.block_status().unwrap();
-            if block_status.0 == 101 {
-                break block_status;
-            } else {
-                thread::sleep(Duration::from_secs(1));
-            }
+        let block_ntf = || {
+            let blocks = notifications.filter_events("block");
+            blocks.last().map(utils::parse_block_ntf).filter(|ntf| ntf.block_height == 101)
         };
-        assert_eq!(block_status.0, 101);
-        let hash = hashes.pop().unwrap();
-        let prev_hash = hashes.pop().unwrap();
-        let expected = json!({"block":{"block_height":101u32,"block_hash":hash,"previous_hash":prev_hash},"event":"block"});
-        for i in 0.. {
-            assert!(i < 10);
-            if session.filter_events("block").last() == Some(&expected) {
-                break;
-            } else {
-                std::thread::sleep(Duration::from_millis(100));
-            }
-        }
-
-        let network_id = NetworkId::Bitcoin(bitcoin::Network::Regtest);
+        notifications.wait_until("the block at height 101", WAIT_TIMEOUT, || {
+            block_ntf().is_some() && session.get_block_status().unwrap().server_tip_height == 101
+        });
+        let block_status = session.get_block_status().unwrap();
+        let ntf = block_ntf().unwrap();
+        assert_eq!(ntf.block_hash.to_string(), hashes.pop().unwrap());
+        assert_eq!(ntf.previous_hash.to_string(), hashes.pop().unwrap());
+        assert!(!ntf.reorg);
 
         info!("returning TestSession");
 
@@ -198,12 +365,16 @@ impl TestSession {
             block_status,
             node,
             electrs,
+            peers,
+            chain: builder.chain,
             session,
             credentials,
             state_dir,
             network_id,
             network,
             p2p_port,
+            notifications,
+            process_signer: builder.process_signer,
         }
     }
 
@@ -215,11 +386,232 @@ impl TestSession {
         funding_tx
     }
 
+    /// send all the funds of the gdk session (account #0) to `address` paying `fee`
+    pub fn send_all(&mut self, address: &bitcoin::Address, fee: u64) -> String {
+        let utxos = self.utxos(0);
+        let total: u64 = utxos.iter().map(|u| u.satoshi).sum();
+        let output = bitcoin::TxOut {
+            value: bitcoin::Amount::from_sat(total - fee),
+            script_pubkey: address.script_pubkey(),
+        };
+        let tx_hex = self.sign_tx(&utxos, vec![output]);
+        self.session.broadcast_transaction(&tx_hex).unwrap()
+    }
+
+    /// create and sign a transaction spending all the funds of account #0 to
+    /// send `satoshi` to `address` paying `fee`, with the change back to the wallet
+    pub fn create_tx(&mut self, address: &bitcoin::Address, satoshi: u64, fee: u64) -> String {
+        let utxos = self.utxos(0);
+        let total: u64 = utxos.iter().map(|u| u.satoshi).sum();
+        let change = bitcoin::Address::from_str(&self.get_receive_address(0).address)
+            .unwrap()
+            .assume_checked();
+        let outputs = vec![
+            bitcoin::TxOut {
+                value: bitcoin::Amount::from_sat(satoshi),
+                script_pubkey: address.script_pubkey(),
+            },
+            bitcoin::TxOut {
+                value: bitcoin::Amount::from_sat(total - satoshi - fee),
+                script_pubkey: change.script_pubkey(),
+            },
+        ];
+        self.sign_tx(&utxos, outputs)
+    }
+
+    /// create a transaction spending all the funds of `subaccount` to send
+    /// `satoshi` to `address` paying `fee`, with the change back to the
+    /// subaccount, returning it unsigned and signed with `xprv`
+    pub fn create_subaccount_tx(
+        &mut self,
+        subaccount: u32,
+        xprv: Xpriv,
+        address: &bitcoin::Address,
+        satoshi: u64,
+        fee: u64,
+    ) -> (String, String) {
+        let utxos = self.utxos(subaccount);
+        let total: u64 = utxos.iter().map(|u| u.satoshi).sum();
+        let change = bitcoin::Address::from_str(&self.get_receive_address(subaccount).address)
+            .unwrap()
+            .assume_checked();
+        let tx = bitcoin::Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: utxos
+                .iter()
+                .map(|u| bitcoin::TxIn {
+                    previous_output: bitcoin::OutPoint::new(u.txhash.parse().unwrap(), u.pt_idx),
+                    ..Default::default()
+                })
+                .collect(),
+            output: vec![
+                bitcoin::TxOut {
+                    value: bitcoin::Amount::from_sat(satoshi),
+                    script_pubkey: address.script_pubkey(),
+                },
+                bitcoin::TxOut {
+                    value: bitcoin::Amount::from_sat(total - satoshi - fee),
+                    script_pubkey: change.script_pubkey(),
+                },
+            ],
+        };
+        let signed = self.sign_tx_with_session(&tx, subaccount, Some(xprv));
+        (bitcoin::consensus::encode::serialize_hex(&tx), signed)
+    }
+
+    fn utxos(&self, subaccount: u32) -> Vec<UnspentOutput> {
+        let opt = GetUnspentOpt {
+            subaccount,
+            num_confs: Some(0),
+            confidential_utxos_only: None,
+            all_coins: None,
+            asset_id: None,
+            trust_own_unconfirmed: None,
+            asset_precisions: Default::default(),
+            include_provenance: false,
+        };
+        self.session.get_unspent_outputs(&opt).unwrap().0.remove("btc").unwrap_or_default()
+    }
+
+    /// sign a transaction spending p2sh-p2wpkh or p2wpkh `utxos` to `outputs`,
+    /// with the signer of the session if it's a process signer, with the
+    /// keys of the credentials otherwise
+    fn sign_tx(&mut self, utxos: &[UnspentOutput], outputs: Vec<bitcoin::TxOut>) -> String {
+        let tx = bitcoin::Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: utxos
+                .iter()
+                .map(|u| bitcoin::TxIn {
+                    previous_output: bitcoin::OutPoint::new(u.txhash.parse().unwrap(), u.pt_idx),
+                    ..Default::default()
+                })
+                .collect(),
+            output: outputs,
+        };
+        if self.process_signer {
+            return self.sign_tx_with_session(&tx, 0, None);
+        }
+        let (master_xprv, _, _) =
+            keys_from_credentials(&self.credentials, self.network.bip32_network());
+        let mut cache = bitcoin::sighash::SighashCache::new(tx);
+        let mut script_sigs = vec![];
+        for (i, utxo) in utxos.iter().enumerate() {
+            let xprv = master_xprv.derive_priv(&gdk_common::EC, &utxo.user_path).unwrap();
+            let public_key = xprv.private_key.public_key(&gdk_common::EC);
+            let wpkh_script = bitcoin::ScriptBuf::new_p2wpkh(
+                &bitcoin::CompressedPublicKey(public_key).wpubkey_hash(),
+            );
+            let sighash = cache
+                .p2wpkh_signature_hash(
+                    i,
+                    &wpkh_script,
+                    bitcoin::Amount::from_sat(utxo.satoshi),
+                    bitcoin::EcdsaSighashType::All,
+                )
+                .unwrap();
+            let message = bitcoin::secp256k1::Message::from_digest(sighash.to_byte_array());
+            let signature = gdk_common::EC.sign_ecdsa(&message, &xprv.private_key);
+            *cache.witness_mut(i).unwrap() = bitcoin::Witness::p2wpkh(
+                &bitcoin::ecdsa::Signature::sighash_all(signature),
+                &public_key,
+            );
+            if utxo.address_type == "p2sh-p2wpkh" {
+                let redeem_script = <&bitcoin::script::PushBytes>::try_from(wpkh_script.as_bytes());
+                let script_sig = bitcoin::script::Builder::new()
+                    .push_slice(redeem_script.unwrap())
+                    .into_script();
+                script_sigs.push((i, script_sig));
+            }
+        }
+        let mut tx = cache.into_transaction();
+        for (i, script_sig) in script_sigs {
+            tx.input[i].script_sig = script_sig;
+        }
+        bitcoin::consensus::encode::serialize_hex(&tx)
+    }
+
+    /// sign the `subaccount` inputs of `tx` with `xprv` if given, with the
+    /// signer of the session otherwise
+    fn sign_tx_with_session(
+        &mut self,
+        tx: &bitcoin::Transaction,
+        subaccount: u32,
+        xprv: Option<Xpriv>,
+    ) -> String {
+        let opt = PsbtFromTransactionOpt {
+            transaction: bitcoin::consensus::encode::serialize_hex(tx),
+            transaction_inputs: vec![],
+        };
+        let psbt = self.session.psbt_from_create_transaction(&opt).unwrap().psbt;
+        let opt = SignPsbtOpt {
+            psbt,
+            subaccount,
+            xprv,
+            inputs: None,
+            expert: false,
+        };
+        let signed = self.session.sign_psbt(&opt).unwrap();
+        assert!(signed.is_complete);
+        let opt = FinalizePsbtOpt {
+            psbt: signed.psbt,
+        };
+        self.session.finalize_and_extract(&opt).unwrap().transaction
+    }
+
+    /// The extended private key of `subaccount`
+    pub fn subaccount_xprv(&self, subaccount: u32) -> Xpriv {
+        let (master_xprv, _, _) =
+            keys_from_credentials(&self.credentials, self.network.bip32_network());
+        let (_, path) = get_account_derivation(subaccount, self.network_id).unwrap();
+        master_xprv.derive_priv(&gdk_common::EC, &path).unwrap()
+    }
+
+    /// Create `subaccount` and return its extended private key
+    pub fn create_subaccount(&mut self, subaccount: u32) -> Xpriv {
+        let xprv = self.subaccount_xprv(subaccount);
+        let opt = CreateAccountOpt {
+            subaccount,
+            name: format!("subaccount {}", subaccount),
+            xpub: Xpub::from_priv(&gdk_common::EC, &xprv),
+            discovered: false,
+            is_already_created: true,
+            allow_gaps: true,
+        };
+        self.session.create_subaccount(opt).unwrap();
+        xprv
+    }
+
+    /// A software signer of the wallet, to log in other sessions
+    pub fn software_signer(&self) -> Arc<dyn Signer> {
+        let seed = Mnemonic::parse(&self.credentials.mnemonic)
+            .unwrap()
+            .to_seed(&self.credentials.bip39_passphrase);
+        Arc::new(SoftwareSigner::from_seed(&seed, self.network.bip32_network()).unwrap())
+    }
+
+    /// Another session of the same wallet, sharing the store in `state_dir`
+    pub fn new_session_same_wallet(&self) -> ElectrumSession {
+        let mut session = ElectrumSession::new(self.network.clone()).unwrap();
+        let (_, master_xpub, _) =
+            keys_from_credentials(&self.credentials, self.network.bip32_network());
+        let opt = LoadStoreOpt {
+            master_xpub: Some(master_xpub),
+            master_xpub_fingerprint: Some(master_xpub.fingerprint()),
+            filename: None,
+            encryption_key_hex: None,
+            wallet_birthday: None,
+        };
+        session.load_store(&opt).unwrap();
+        session
+    }
+
     pub fn get_tx_list(&self, subaccount: u32) -> Vec<TxListItem> {
         let mut opt = GetTransactionsOpt::default();
         opt.subaccount = subaccount;
         opt.count = 100;
-        self.session.get_transactions(&opt).unwrap().0
+        self.session.get_transactions(&opt).unwrap().transactions
     }
 
     pub fn get_tx_from_list(&self, subaccount: u32, txid: &str) -> TxListItem {
@@ -236,34 +628,43 @@ impl TestSession {
             address_type: None,
             is_internal: None,
             ignore_gap_limit: None,
+            confirm_on_device: Some(true),
+            expected_satoshi: None,
+            expected_asset: None,
+            expiry_seconds: None,
         };
-        self.session.get_receive_address(&addr_opt).unwrap()
+        let address = self.session.get_receive_address(&addr_opt).unwrap();
+        assert!(address.device_confirmed);
+        address
     }
 
     /// mine a block with the node and check if gdk session see the change
     pub fn mine_block(&mut self) -> String {
         let initial_height_electrs = self.electrs_tip() as u32;
-        let initial_height_wallet = self.session.block_status().unwrap().0;
+        let initial_height_wallet = self.session.get_block_status().unwrap().server_tip_height;
         assert_eq!(initial_height_electrs, initial_height_wallet);
         let block = self.node_generate(1);
-        let height = initial_height_electrs + 1;
-        // Wait until electrs has updated
-        let mut i = 60;
-        loop {
-            assert!(i > 0, "timeout waiting for electrs block height {}", height);
-            i -= 1;
-            if height == self.electrs_tip() as u32 {
-                break;
-            }
-            thread::sleep(Duration::from_secs(1));
-        }
-
-        // Wait until wallet has updated
-        self.wait_blockheight(height);
+        // The wallet sees the block once electrs has it
+        self.wait_for_height(initial_height_electrs + 1, WAIT_TIMEOUT);
 
         block[0].to_string()
     }
 
+    /// The RPC client of the node indexed by the server
+    pub fn node_client(&self) -> &electrsd::bitcoind::bitcoincore_rpc::Client {
+        &self.node.client
+    }
+
+    /// The electrum client of the server
+    pub fn electrum_client(&self) -> &electrsd::electrum_client::Client {
+        &self.electrs.client
+    }
+
+    /// The nodes other than the one indexed by the server
+    pub fn peers(&self) -> &[electrsd::bitcoind::BitcoinD] {
+        &self.peers
+    }
+
     pub fn node_generate(&self, block_num: u32) -> Vec<String> {
         let client = &self.node.client;
         let hashes = RpcNodeExt::generate(client, block_num, None).unwrap();
@@ -271,6 +672,20 @@ impl TestSession {
         hashes
     }
 
+    /// Invalidate the block `hash` and its descendants in the node, its
+    /// transactions go back to the mempool
+    pub fn invalidate_block(&self, hash: &str) {
+        self.node.client.call::<Value>("invalidateblock", &[hash.into()]).unwrap();
+        self.electrs.trigger().unwrap();
+    }
+
+    /// Undo [`Self::invalidate_block`], the node switches back to the chain
+    /// of `hash` if it's the one with the most work
+    pub fn reconsider_block(&self, hash: &str) {
+        self.node.client.call::<Value>("reconsiderblock", &[hash.into()]).unwrap();
+        self.electrs.trigger().unwrap();
+    }
+
     pub fn node_connect(&self, port: u16) {
         self.node.client.call::<Value>("clearbanned", &[]).unwrap();
         self.node
@@ -305,9 +720,42 @@ impl TestSession {
     }
 
     /// stop the bitcoin node in the test session
+    /// Close the connections to the server and open new ones, as an app
+    /// suspended and resumed
+    pub fn reconnect(&mut self) {
+        self.session.pause(&PauseOpt::default()).unwrap();
+        self.session.resume().unwrap();
+    }
+
+    /// Kill the electrs server and spawn a new one, indexing the same node,
+    /// for the session to reconnect to
+    ///
+    /// The new server listens on another port, the session must be connected
+    /// again to use it.
+    pub fn restart_electrs(&mut self) {
+        let _ = self.electrs.kill();
+        self.electrs = self.spawn_electrs();
+        self.network.electrum_url = Some(self.electrs.electrum_url.clone());
+        self.session.url = ElectrumUrl::Plaintext(self.electrs.electrum_url.clone());
+    }
+
+    /// Spawn another electrs server indexing the same node
+    pub fn spawn_electrs(&self) -> electrsd::ElectrsD {
+        let (electrs_exec, chain) = match self.network_id {
+            NetworkId::Bitcoin(bitcoin::Network::Signet) => (env::ELECTRS_EXEC.clone(), "signet"),
+            NetworkId::Bitcoin(_) => (env::ELECTRS_EXEC.clone(), "regtest"),
+            NetworkId::Elements(_) => (env::ELECTRS_LIQUID_EXEC.clone(), "liquidregtest"),
+        };
+        let is_debug = std::env::var("DEBUG").is_ok();
+        spawn_electrs(&electrs_exec, &self.node, chain, is_debug)
+    }
+
     pub fn stop(&mut self) {
         self.session.disconnect().unwrap();
         self.node.stop().unwrap();
+        for peer in self.peers.iter_mut() {
+            peer.stop().unwrap();
+        }
     }
 
     pub fn get_spv_cross_validation(&self) -> Option<spv::CrossValidationResult> {
@@ -316,28 +764,83 @@ impl TestSession {
         store.cache.cross_validation_result.clone()
     }
 
-    /// wait for the spv cross validation status to change
+    /// wait for the spv cross validation status to change, notified with a block
     pub fn wait_spv_cross_validation_change(&self, wait_for: bool) -> spv::CrossValidationResult {
-        for _ in 0..60 {
-            if let Some(result) = self.get_spv_cross_validation() {
-                if result.is_valid() == wait_for {
-                    return result;
-                }
-            }
-            thread::sleep(Duration::from_secs(1));
-        }
-        panic!("timeout waiting for spv cross-validation change");
+        let what = format!("the spv cross-validation to be valid: {}", wait_for);
+        let valid = || self.get_spv_cross_validation().filter(|r| r.is_valid() == wait_for);
+        self.notifications.wait_until(&what, WAIT_TIMEOUT, || valid().is_some());
+        valid().unwrap()
     }
 
     /// wait for the spv validation status of a transaction to change
     pub fn wait_tx_spv_change(&self, txid: &str, wait_for: &str) {
-        for _ in 0..60 {
-            if self.get_tx_from_list(0, txid).spv_verified == wait_for {
+        self.wait_for_spv(txid, wait_for, WAIT_TIMEOUT);
+    }
+
+    /// The transaction `txid` of the first subaccount listing it
+    fn find_tx(&self, txid: &str) -> Option<TxListItem> {
+        let subaccounts = self.session.get_subaccount_nums().unwrap();
+        subaccounts.into_iter().find_map(|subaccount| {
+            self.get_tx_list(subaccount).into_iter().find(|tx| tx.txhash == txid)
+        })
+    }
+
+    /// Wait for the transaction `txid` to be in the wallet with `min_confs`
+    /// confirmations or more, 0 to just have it
+    ///
+    /// Like the other `wait_for_*` helpers, the check is repeated at every
+    /// notification of the session instead of polling, so they return as soon
+    /// as the session is updated, not up to a second later.
+    pub fn wait_for_tx(&self, txid: &str, min_confs: u32, timeout: Duration) {
+        let what = format!("tx {} with {} confirmations", txid, min_confs);
+        self.notifications.wait_until(&what, timeout, || match self.find_tx(txid) {
+            Some(_) if min_confs == 0 => true,
+            Some(tx) if tx.block_height == 0 => false,
+            Some(tx) => {
+                let tip = self.session.get_block_status().unwrap().server_tip_height;
+                (tip + 1).saturating_sub(tx.block_height) >= min_confs
+            }
+            None => false,
+        });
+    }
+
+    /// Wait for the tip of the wallet to be at `height`
+    pub fn wait_for_height(&self, height: u32, timeout: Duration) {
+        let what = format!("wallet block height {}", height);
+        self.notifications.wait_until(&what, timeout, || {
+            self.session.get_block_status().unwrap().server_tip_height == height
+        });
+    }
+
+    /// Wait for the SPV headers chain of the wallet to be at `height`
+    ///
+    /// The progress of the headers is not notified, so unlike the other
+    /// `wait_for_*` helpers this one polls.
+    pub fn wait_for_headers(&self, height: u32, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let status = self.session.get_block_status().unwrap();
+            if status.local_header_height == Some(height) {
                 return;
             }
-            thread::sleep(Duration::from_secs(1));
+            assert!(
+                Instant::now() < deadline,
+                "timeout after {:?} waiting for headers height {}, {:?}",
+                timeout,
+                height,
+                status
+            );
+            thread::sleep(Duration::from_millis(100));
         }
-        panic!("timeout waiting for tx spv change");
+    }
+
+    /// Wait for the spv validation status of the transaction `txid` to be
+    /// `state`, as in [`TxListItem::spv_verified`]
+    pub fn wait_for_spv(&self, txid: &str, state: &str, timeout: Duration) {
+        let what = format!("tx {} spv status {}", txid, state);
+        self.notifications.wait_until(&what, timeout, || {
+            self.find_tx(txid).is_some_and(|tx| tx.spv_verified == state)
+        });
     }
 
     fn wait_tx_ntf(
@@ -352,48 +855,57 @@ impl TestSession {
             txid: bitcoin::Txid::from_str(&txid).unwrap(),
             satoshi,
             type_,
+            block_height: None,
+            satoshi_delta: None,
+            removed: false,
         });
-        for _ in 0..10 {
-            let events = self.session.filter_events("transaction");
-            if events.iter().any(|e| e["transaction"]["txhash"].as_str().unwrap() == txid) {
-                if events.contains(&ntf) {
-                    return;
-                }
-                let got = events
-                    .iter()
-                    .filter(|e| e["transaction"]["txhash"].as_str().unwrap() == txid)
-                    .last()
-                    .unwrap();
-                let got_subaccounts: Vec<u32> =
-                    serde_json::from_value(got["transaction"]["subaccounts"].clone()).unwrap();
-                if subaccounts.len() > 1 && got_subaccounts.iter().all(|i| subaccounts.contains(i))
-                {
-                    // FIXME: make multi subaccount notification less flaky
-                    // Sometimes notification with more than one subaccount miss one subaccount,
-                    // this might cause the satoshi and type fields to be incorrect. For now we
-                    // relax the test here.
-                    return;
-                }
-                panic!(
-                    "notification does not match the expected one: expected {:?} got {:?}",
-                    ntf, got
-                );
+        let what = format!("the notification of tx {}", txid);
+        self.notifications.wait_until(&what, Duration::from_secs(10), || {
+            // The block height and per asset amounts are not checked here
+            let events: Vec<Value> = self
+                .notifications
+                .filter_events("transaction")
+                .into_iter()
+                .map(|mut e| {
+                    let ntf = e["transaction"].as_object_mut().unwrap();
+                    ntf.remove("block_height");
+                    ntf.remove("satoshi_delta");
+                    e
+                })
+                .collect();
+            if !events.iter().any(|e| e["transaction"]["txhash"].as_str().unwrap() == txid) {
+                return false;
             }
-            thread::sleep(Duration::from_secs(1));
-        }
-        panic!("timeout waiting for notification for tx {}", txid);
+            if events.contains(&ntf) {
+                return true;
+            }
+            let got = events
+                .iter()
+                .filter(|e| e["transaction"]["txhash"].as_str().unwrap() == txid)
+                .last()
+                .unwrap();
+            let got_subaccounts: Vec<u32> =
+                serde_json::from_value(got["transaction"]["subaccounts"].clone()).unwrap();
+            if subaccounts.len() > 1 && got_subaccounts.iter().all(|i| subaccounts.contains(i)) {
+                // FIXME: make multi subaccount notification less flaky
+                // Sometimes notification with more than one subaccount miss one subaccount,
+                // this might cause the satoshi and type fields to be incorrect. For now we
+                // relax the test here.
+                return true;
+            }
+            panic!(
+                "notification does not match the expected one: expected {:?} got {:?}",
+                ntf, got
+            );
+        });
     }
 
     /// wait for the txid to show up in the given account
     fn wait_account_tx(&self, subaccount: u32, txid: &str) {
-        for _ in 0..60 {
-            let txs = self.get_tx_list(subaccount);
-            if txs.iter().any(|tx| tx.txhash == txid) {
-                return;
-            }
-            thread::sleep(Duration::from_secs(1));
-        }
-        panic!("timeout waiting for tx {} to show up in account {}", txid, subaccount);
+        let what = format!("tx {} in account {}", txid, subaccount);
+        self.notifications.wait_until(&what, WAIT_TIMEOUT, || {
+            self.get_tx_list(subaccount).iter().any(|tx| tx.txhash == txid)
+        });
     }
 
     pub fn wait_tx(
@@ -409,16 +921,23 @@ impl TestSession {
         self.wait_tx_ntf(subaccounts, txid, satoshi, type_);
     }
 
+    /// wait for the block notification of the tip at `height`
+    pub fn wait_block_ntf(&self, height: u32) -> BlockNotification {
+        let block_ntf = || {
+            let blocks = self.notifications.filter_events("block");
+            blocks
+                .iter()
+                .map(utils::parse_block_ntf)
+                .filter(|ntf| ntf.block_height == height)
+                .last()
+        };
+        let what = format!("the block notification at height {}", height);
+        self.notifications.wait_until(&what, WAIT_TIMEOUT, || block_ntf().is_some());
+        block_ntf().unwrap()
+    }
+
     pub fn wait_blockheight(&self, height: u32) {
-        let mut i = 60;
-        loop {
-            assert!(i > 0, "timeout waiting for wallet block height {}", height);
-            i -= 1;
-            if height == self.session.block_status().unwrap().0 {
-                return;
-            }
-            thread::sleep(Duration::from_secs(1));
-        }
+        self.wait_for_height(height, WAIT_TIMEOUT);
     }
 }
 
@@ -433,3 +952,28 @@ fn keys_from_credentials(
     let master_blinding = util::asset_blinding_key_from_seed(&seed);
     (master_xprv, master_xpub, master_blinding)
 }
+
+/// Spawn an electrs server indexing `node` of `chain`
+fn spawn_electrs(
+    electrs_exec: &str,
+    node: &electrsd::bitcoind::BitcoinD,
+    chain: &'static str,
+    is_debug: bool,
+) -> electrsd::ElectrsD {
+    let mut args = vec![];
+    if is_debug {
+        args.push("-v");
+    }
+    if chain == "signet" {
+        // blk files of a custom signet don't have the default signet magic
+        args.push("--jsonrpc-import");
+    }
+
+    let mut conf = electrsd::Conf::default();
+    conf.args = args;
+    conf.view_stderr = is_debug;
+    conf.http_enabled = false;
+    conf.network = chain;
+
+    electrsd::ElectrsD::with_conf(electrs_exec, node, &conf).unwrap()
+}
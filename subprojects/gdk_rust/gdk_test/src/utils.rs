@@ -6,13 +6,18 @@ use serde_json::Value;
 use gdk_common::model::*;
 use gdk_common::{NetworkId, NetworkParameters, State};
 use gdk_electrum::headers;
-use gdk_electrum::{Notification, TransactionNotification};
+use gdk_electrum::{BlockNotification, Notification, TransactionNotification};
 
 /// Json of network notification
 pub fn ntf_network(current: State, desired: State) -> Value {
     serde_json::to_value(&Notification::new_network(current, desired)).unwrap()
 }
 
+/// Parse the json of a block notification
+pub fn parse_block_ntf(ntf: &Value) -> BlockNotification {
+    serde_json::from_value(ntf["block"].clone()).unwrap()
+}
+
 /// Json of transaction notification
 pub fn ntf_transaction(ntf: &TransactionNotification) -> Value {
     serde_json::to_value(&Notification::new_transaction(ntf)).unwrap()
@@ -1,9 +1,13 @@
 // A simple test to call session methods in a valgrindable environment
 #include "src/ga_auth_handlers.hpp"
+#include "src/ga_wally.hpp"
 #include "src/network_parameters.hpp"
 #include "src/session.hpp"
 #include "src/session_impl.hpp"
+#include "src/transaction_utils.hpp"
+#include <algorithm>
 #include <assert.h>
+#include <cctype>
 #include <cstdlib>
 #include <iostream>
 #include <nlohmann/json.hpp>
@@ -113,6 +117,255 @@ int main()
     }
 #endif
 
+#if 1
+    // Validate a transaction with several errors, all reported at once
+    {
+        const nlohmann::json utxo_details({ { "subaccount", 0 }, { "num_confs", 0 } });
+        auto_auth_handler utxos_call(new get_unspent_outputs_call(session, utxo_details));
+        const auto utxos = process_auth(utxos_call).at("unspent_outputs");
+
+        auto_auth_handler address_call(new get_receive_address_call(session, { { "subaccount", 0 } }));
+        const std::string address = process_auth(address_call).at("address");
+
+        nlohmann::json details({ { "subaccount", 0 }, { "utxos", utxos }, { "validate", true } });
+        details["addressees"] = { { { "address", "not an address" }, { "satoshi", 10000 } },
+            { { "address", address }, { "satoshi", 1 } } };
+        auto_auth_handler call(new create_transaction_call(session, details));
+        const auto result = process_auth(call);
+        std::cout << result << std::endl;
+        const auto& addressee_errors = result.at("addressee_errors");
+        assert(addressee_errors.size() == 2);
+        assert(addressee_errors.at(0) == "id_invalid_address");
+        assert(addressee_errors.at(1) == "id_amount_below_the_dust_threshold");
+        assert(result.at("error") == "id_invalid_address");
+        assert(!result.contains("transaction"));
+    }
+#endif
+
+#if 1
+    // Invalid addresses are reported with the reason they are invalid
+    if (const auto& net_params = session.get_network_parameters(); !net_params.is_liquid()) {
+        const auto p2wpkh = h2b("0014751e76e8199196d454941c45d1b3a323f1433bd6");
+        const auto address = get_address_from_scriptpubkey(net_params, p2wpkh);
+        const auto separator = address.rfind('1');
+        auto mixed_case = address;
+        auto letter = std::find_if(mixed_case.begin() + separator + 2, mixed_case.end(), ::isalpha);
+        *letter = static_cast<char>(::toupper(*letter));
+        auto typo = address;
+        typo.back() = typo.back() == 'q' ? 'p' : 'q';
+        auto unknown_version = address;
+        unknown_version[separator + 1] = 'l';
+        auto short_program = address;
+        short_program.erase(separator + 2, 5);
+        const std::string other_network = net_params.bech32_prefix() == "bc"
+            ? "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx"
+            : "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+
+        const std::vector<std::pair<std::string, nlohmann::json>> table = {
+            { mixed_case, { { "code", "invalid_address_case" } } },
+            { typo, { { "code", "invalid_address_checksum" } } },
+            { unknown_version, { { "code", "unknown_witness_version" } } },
+            { short_program, { { "code", "invalid_witness_program_length" } } },
+            { other_network, { { "code", "invalid_address" }, { "network_mismatch", true } } },
+            { "not an address", { { "code", "invalid_address" }, { "network_mismatch", false } } },
+        };
+        nlohmann::json details({ { "subaccount", 0 }, { "utxos", nlohmann::json::object() }, { "validate", true } });
+        details["addressees"] = { { { "address", " " + address + "\n" }, { "satoshi", 10000 } } };
+        for (const auto& [malformed, code] : table) {
+            details["addressees"].push_back({ { "address", malformed }, { "satoshi", 10000 } });
+        }
+        auto_auth_handler call(new create_transaction_call(session, details));
+        const auto result = process_auth(call);
+        std::cout << result << std::endl;
+        const auto& addressees = result.at("addressees");
+        const auto& addressee_errors = result.at("addressee_errors");
+        // Surrounding whitespace is trimmed
+        assert(addressee_errors.at(0).empty());
+        assert(addressees.at(0).at("address") == address);
+        assert(!addressees.at(0).contains("error_code"));
+        for (size_t i = 0; i < table.size(); ++i) {
+            assert(addressee_errors.at(i + 1) == "id_invalid_address");
+            assert(addressees.at(i + 1).at("error_code") == table[i].second);
+        }
+    }
+#endif
+
+#if 1
+    // The change outputs are listed by their position in the transaction
+    {
+        const nlohmann::json utxo_details({ { "subaccount", 0 }, { "num_confs", 0 } });
+        auto_auth_handler utxos_call(new get_unspent_outputs_call(session, utxo_details));
+        const auto utxos = process_auth(utxos_call).at("unspent_outputs");
+
+        auto_auth_handler address_call(new get_receive_address_call(session, { { "subaccount", 0 } }));
+        const std::string address = process_auth(address_call).at("address");
+
+        nlohmann::json details({ { "subaccount", 0 }, { "utxos", utxos } });
+        details["addressees"] = { { { "address", address }, { "satoshi", 1000 } } };
+        auto_auth_handler call(new create_transaction_call(session, details));
+        const auto result = process_auth(call);
+        std::cout << result << std::endl;
+        assert(result.at("error").empty());
+        const auto& change_outputs = result.at("change_outputs");
+        assert(change_outputs.size() == 1);
+        const auto& change = change_outputs.at(0);
+        const auto& output = result.at("transaction_outputs").at(change.at("vout").get<size_t>());
+        assert(output.at("scriptpubkey") == change.at("scriptpubkey"));
+        assert(output.at("is_change") == true);
+        // The change is on the internal chain of the wallet
+        assert(change.at("is_internal") == true);
+        auto_auth_handler previous_call(new get_previous_addresses_call(
+            session, { { "subaccount", 0 }, { "is_internal", true } }));
+        const auto internal = process_auth(previous_call).at("list");
+        auto&& is_change = [&change](const auto& a) { return a.at("address") == change.at("address"); };
+        assert(std::any_of(internal.begin(), internal.end(), is_change));
+
+        auto_auth_handler sign_call(new sign_transaction_call(session, result));
+        const auto signed_tx = process_auth(sign_call);
+        assert(signed_tx.at("change_outputs") == change_outputs);
+    }
+#endif
+
+#if 1
+    // Sending to an address of the wallet is flagged, even given bare
+    {
+        const nlohmann::json utxo_details({ { "subaccount", 0 }, { "num_confs", 0 } });
+        auto_auth_handler utxos_call(new get_unspent_outputs_call(session, utxo_details));
+        const auto utxos = process_auth(utxos_call).at("unspent_outputs");
+
+        auto_auth_handler address_call(new get_receive_address_call(session, { { "subaccount", 0 } }));
+        const std::string address = process_auth(address_call).at("address");
+
+        nlohmann::json details({ { "subaccount", 0 }, { "utxos", utxos } });
+        details["addressees"] = { { { "address", address }, { "satoshi", 1000 } } };
+        auto_auth_handler call(new create_transaction_call(session, details));
+        const auto result = process_auth(call);
+        std::cout << result << std::endl;
+        assert(result.at("error").empty());
+        assert(result.at("send_to_self") == true);
+
+        if (const auto& net_params = session.get_network_parameters(); !net_params.is_liquid()) {
+            const auto p2wpkh = h2b("0014751e76e8199196d454941c45d1b3a323f1433bd6");
+            details["addressees"].at(0)["address"] = get_address_from_scriptpubkey(net_params, p2wpkh);
+            auto_auth_handler external_call(new create_transaction_call(session, details));
+            const auto external = process_auth(external_call);
+            std::cout << external << std::endl;
+            assert(external.at("error").empty());
+            assert(external.at("send_to_self") == false);
+        }
+    }
+#endif
+
+#if 1
+    // Transactions heavier than nodes relay are refused before being signed
+    if (session.get_network_parameters().is_development()) {
+        green::session light_session;
+        light_session.connect({ { "name", net_params["name"] }, { "max_standard_tx_weight", 600 } });
+        auto_auth_handler light_login(new login_user_call(light_session, nlohmann::json(), details));
+        process_auth(light_login);
+
+        const nlohmann::json utxo_details({ { "subaccount", 0 }, { "num_confs", 0 } });
+        auto_auth_handler utxos_call(new get_unspent_outputs_call(light_session, utxo_details));
+        const auto utxos = process_auth(utxos_call).at("unspent_outputs");
+
+        auto_auth_handler address_call(new get_receive_address_call(light_session, { { "subaccount", 0 } }));
+        const std::string address = process_auth(address_call).at("address");
+
+        nlohmann::json tx_details({ { "subaccount", 0 }, { "utxos", utxos } });
+        tx_details["addressees"] = { { { "address", address }, { "satoshi", 1000 } } };
+        auto_auth_handler call(new create_transaction_call(light_session, tx_details));
+        const auto result = process_auth(call);
+        std::cout << result << std::endl;
+        if (result.at("error") == "id_transaction_too_large") {
+            assert(!result.contains("transaction"));
+            assert(result.at("inputs_needed").get<size_t>() > result.at("max_inputs").get<size_t>());
+        } else {
+            assert(result.at("error") == "id_insufficient_funds");
+        }
+    }
+#endif
+
+#if 1
+    // Change below the floor is raised with another input or added to the fee, whichever is cheaper
+    {
+        const auto policy_asset = session.get_network_parameters().get_policy_asset();
+        const bool is_liquid = session.get_network_parameters().is_liquid();
+
+        const nlohmann::json utxo_details({ { "subaccount", 0 }, { "num_confs", 0 } });
+        auto_auth_handler utxos_call(new get_unspent_outputs_call(session, utxo_details));
+        auto utxos = process_auth(utxos_call).at("unspent_outputs").at(policy_asset);
+        assert(utxos.size() >= 2);
+        auto&& by_value = [](const auto& l, const auto& r) { return l.at("satoshi") > r.at("satoshi"); };
+        std::sort(utxos.begin(), utxos.end(), by_value);
+        // The two largest utxos, spent in this order
+        const nlohmann::json two_utxos({ { policy_asset, { utxos.at(0), utxos.at(1) } } });
+        const uint64_t first = utxos.at(0).at("satoshi");
+        const uint64_t second = utxos.at(1).at("satoshi");
+        assert(first >= 100000);
+
+        auto_auth_handler address_call(new get_receive_address_call(session, { { "subaccount", 0 } }));
+        const std::string address = process_auth(address_call).at("address");
+
+        auto&& create_tx = [&](uint32_t min_change, const nlohmann::json& pool, const nlohmann::json& addressee) {
+            green::session floor_session;
+            floor_session.connect({ { "name", net_params["name"] }, { "min_change_satoshi", min_change } });
+            auto_auth_handler floor_login(new login_user_call(floor_session, nlohmann::json(), details));
+            process_auth(floor_login);
+            nlohmann::json tx_details({ { "subaccount", 0 }, { "utxos", pool }, { "fee_rate", 1000 } });
+            tx_details["addressees"] = { addressee };
+            if (is_liquid) {
+                tx_details["addressees"].at(0)["asset_id"] = policy_asset;
+            }
+            auto_auth_handler call(new create_transaction_call(floor_session, tx_details));
+            auto result = process_auth(call);
+            std::cout << result << std::endl;
+            assert(result.at("error").empty());
+            return result;
+        };
+
+        // The fee of a transaction spending the first utxo with change, which is kept
+        const nlohmann::json one_utxo({ { policy_asset, { utxos.at(0) } } });
+        const auto probe = create_tx(1, one_utxo, { { "address", address }, { "satoshi", first / 2 } });
+        assert(probe.at("change_decision") == "kept");
+        assert(probe.at("transaction_inputs").size() == 1);
+        const uint64_t probe_fee = probe.at("fee");
+
+        // Sending so that the first utxo leaves `change`, well above the fee of another input
+        const uint64_t change = 10000;
+        const nlohmann::json addressee({ { "address", address }, { "satoshi", first - probe_fee - change } });
+
+        // Adding the second utxo raises the change above the floor for less than adding it to the fee
+        const auto added = create_tx(change + 1, two_utxos, addressee);
+        assert(added.at("change_decision") == "added_input");
+        assert(added.at("transaction_inputs").size() == 2);
+        assert(added.at("change_outputs").size() == 1);
+        assert(added.at("change_amount").at(policy_asset).get<uint64_t>() > change);
+
+        // No input raises the change above the floor: it is added to the fee, or kept on Liquid
+        const auto folded = create_tx(2100000000, two_utxos, addressee);
+        assert(folded.at("transaction_inputs").size() == 1);
+        if (is_liquid) {
+            assert(folded.at("change_decision") == "below_floor");
+            assert(folded.at("change_outputs").size() == 1);
+        } else {
+            assert(folded.at("change_decision") == "folded_into_fee");
+            assert(folded.at("change_outputs").empty());
+            assert(folded.at("fee").get<uint64_t>() == probe_fee + change);
+        }
+
+        // Sending everything takes the fee from the amount sent, there is never change
+        const nlohmann::json send_all({ { "address", address }, { "is_greedy", true } });
+        for (const uint32_t min_change : { 1u, 2100000000u }) {
+            const auto all = create_tx(min_change, two_utxos, send_all);
+            assert(all.at("change_decision") == "no_change");
+            assert(all.at("change_outputs").empty());
+            assert(all.at("transaction_inputs").size() == 2);
+            const uint64_t sent = all.at("addressees").at(0).at("satoshi");
+            assert(sent + all.at("fee").get<uint64_t>() == first + second);
+        }
+    }
+#endif
+
 #if 0
     // Test disconnecting a session while an auth handler is in progress
     // Create a thread fetching transactions on the session in a loop